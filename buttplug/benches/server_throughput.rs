@@ -0,0 +1,173 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! End to end message throughput, from messages going into the server (directly or via an
+//! in-process client connector) to commands coming out at a test device.
+
+// Share the test device manager with the integration tests. Only some of the utilities are used
+// here, and the tests already get linted on their own.
+#[path = "../tests/util/mod.rs"]
+#[allow(unused, clippy::all)]
+mod util;
+
+use buttplug::{
+  client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, ScalarValueCommand},
+  core::{
+    connector::ButtplugInProcessClientConnectorBuilder,
+    message::{
+      ActuatorType,
+      ButtplugServerMessage,
+      RequestServerInfo,
+      ScalarCmd,
+      ScalarSubcommand,
+      StartScanning,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  server::ButtplugServer,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::{future, pin_mut, StreamExt};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use util::{test_server_with_device, TestDeviceChannelHost};
+
+/// Messages sent per benchmark iteration, in parallel, to approximate a client streaming commands.
+const MESSAGES_PER_ITERATION: u64 = 100;
+
+fn runtime() -> Runtime {
+  tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()
+    .expect("Benchmark runtime should always build")
+}
+
+/// Throw away everything the test device is sent, so the device never backs up.
+fn drain_device(runtime: &Runtime, device: TestDeviceChannelHost) {
+  let mut receiver = device.receiver;
+  runtime.spawn(async move {
+    // Hold the sender, otherwise the test device sees its event channel close.
+    let _sender = device.sender;
+    while receiver.recv().await.is_some() {}
+  });
+}
+
+async fn connected_server() -> (ButtplugServer, TestDeviceChannelHost) {
+  let (server, device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      RequestServerInfo::new("Benchmark Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Benchmark, assuming infallible.");
+  server
+    .parse_message(StartScanning::default().into())
+    .await
+    .expect("Benchmark, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = msg {
+      break;
+    }
+  }
+  (server, device)
+}
+
+async fn connected_client_device(
+  server: ButtplugServer,
+) -> (ButtplugClient, Arc<ButtplugClientDevice>) {
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server)
+    .finish();
+  let client = ButtplugClient::new("Benchmark Client");
+  client
+    .connect(connector)
+    .await
+    .expect("Benchmark, assuming infallible.");
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Benchmark, assuming infallible.");
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(device) = msg {
+      return (client, device);
+    }
+  }
+  panic!("Client event stream ended before device was added.");
+}
+
+/// Speeds alternate between messages, so duplicate suppression never skips a command.
+fn speed(message_index: u64) -> f64 {
+  [0.25, 0.75][(message_index % 2) as usize]
+}
+
+fn server_throughput(c: &mut Criterion) {
+  let runtime = runtime();
+  let (server, device) = runtime.block_on(connected_server());
+  drain_device(&runtime, device);
+
+  let mut group = c.benchmark_group("server");
+  group.throughput(Throughput::Elements(MESSAGES_PER_ITERATION));
+  group.bench_function("scalar_cmd", |b| {
+    b.to_async(&runtime).iter(|| {
+      future::join_all((0..MESSAGES_PER_ITERATION).map(|i| {
+        server.parse_message(
+          ScalarCmd::new(
+            0,
+            vec![ScalarSubcommand::new(0, speed(i), ActuatorType::Vibrate)],
+          )
+          .into(),
+        )
+      }))
+    })
+  });
+  // Parallel messages to one device get coalesced by its command queue, so also time messages
+  // sent one after another, which each have to make it all the way to the device.
+  group.bench_function("scalar_cmd_sequential", |b| {
+    b.to_async(&runtime).iter(|| async {
+      for i in 0..MESSAGES_PER_ITERATION {
+        server
+          .parse_message(
+            ScalarCmd::new(
+              0,
+              vec![ScalarSubcommand::new(0, speed(i), ActuatorType::Vibrate)],
+            )
+            .into(),
+          )
+          .await
+          .expect("Benchmark, assuming infallible.");
+      }
+    })
+  });
+  group.finish();
+}
+
+fn client_throughput(c: &mut Criterion) {
+  let runtime = runtime();
+  let (_client, client_device) = runtime.block_on(async {
+    let (server, device) = test_server_with_device("Massage Demo", false).await;
+    drain_device(&runtime, device);
+    connected_client_device(server).await
+  });
+
+  let mut group = c.benchmark_group("client");
+  group.throughput(Throughput::Elements(MESSAGES_PER_ITERATION));
+  group.bench_function("vibrate", |b| {
+    b.to_async(&runtime).iter(|| {
+      future::join_all(
+        (0..MESSAGES_PER_ITERATION)
+          .map(|i| client_device.vibrate(&ScalarValueCommand::ScalarValue(speed(i)))),
+      )
+    })
+  });
+  group.finish();
+}
+
+criterion_group!(benches, server_throughput, client_throughput);
+criterion_main!(benches);
@@ -0,0 +1,366 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Synchronous client API, for applications that don't run an async runtime.
+//!
+//! GUI frameworks and scripting hosts usually own the main thread, and have no good place to drive
+//! Rust futures from. [ButtplugBlockingClient] wraps a [ButtplugClient] along with a tokio runtime
+//! that it manages itself, and exposes the client's methods as calls that block until the server
+//! replies. Events are delivered to a callback instead of a stream:
+//!
+//! ```no_run
+//! # use buttplug::{
+//! #   client::{blocking::{ButtplugBlockingClient, ButtplugBlockingClientEvent}, ScalarValueCommand},
+//! #   core::connector::new_json_ws_client_connector,
+//! # };
+//! let client = ButtplugBlockingClient::new("My App").unwrap();
+//! client.set_event_callback(|event| {
+//!   if let ButtplugBlockingClientEvent::DeviceAdded(device) = event {
+//!     println!("Found {}", device.name());
+//!     device.vibrate(&ScalarValueCommand::ScalarValue(0.5)).unwrap();
+//!   }
+//! });
+//! client
+//!   .connect(new_json_ws_client_connector("ws://127.0.0.1:12345"))
+//!   .unwrap();
+//! client.start_scanning().unwrap();
+//! ```
+//!
+//! Callbacks run on a thread owned by the client, one event at a time, so they can call back into
+//! the client, but shouldn't touch UI state directly. Most GUI frameworks have a way to post work to
+//! their own thread for that.
+//!
+//! The blocking methods panic if they're called from inside an async runtime, as blocking there
+//! would stall the runtime. Async applications should use [ButtplugClient] directly.
+
+// ButtplugClientError is large due to the connector errors it can contain, and is returned as is so
+// errors match the async client's.
+#![allow(clippy::result_large_err)]
+
+use super::{
+  ButtplugClient,
+  ButtplugClientDevice,
+  ButtplugClientEvent,
+  ButtplugClientResult,
+  LinearCommand,
+  RotateCommand,
+  ScalarCommand,
+  ScalarValueCommand,
+};
+use crate::core::{
+  connector::ButtplugConnector,
+  errors::ButtplugError,
+  message::{
+    ButtplugCurrentSpecClientMessage,
+    ButtplugCurrentSpecServerMessage,
+    ClientDeviceMessageAttributes,
+  },
+};
+use futures::{Future, StreamExt};
+use std::{
+  sync::{mpsc, Arc, Mutex},
+  thread,
+  time::Duration,
+};
+use tokio::{
+  runtime::{Handle, Runtime},
+  task::JoinHandle,
+};
+
+/// Events emitted by a [ButtplugBlockingClient], matching [ButtplugClientEvent] with devices
+/// wrapped for blocking use.
+#[derive(Clone, Debug)]
+pub enum ButtplugBlockingClientEvent {
+  /// A scanning session has finished.
+  ScanningFinished,
+  /// A device has been added to the server.
+  DeviceAdded(ButtplugBlockingClientDevice),
+  /// A device has been removed from the server.
+  DeviceRemoved(ButtplugBlockingClientDevice),
+  /// The client has not pinged the server in a sufficient amount of time.
+  PingTimeout,
+  /// The client has connected to a server.
+  ServerConnect,
+  /// The server has disconnected.
+  ServerDisconnect,
+  /// An error that cannot be matched to a request was received from the server.
+  Error(ButtplugError),
+}
+
+impl ButtplugBlockingClientEvent {
+  fn new(event: ButtplugClientEvent, handle: &Handle) -> Self {
+    match event {
+      ButtplugClientEvent::ScanningFinished => Self::ScanningFinished,
+      ButtplugClientEvent::DeviceAdded(device) => {
+        Self::DeviceAdded(ButtplugBlockingClientDevice::new(device, handle))
+      }
+      ButtplugClientEvent::DeviceRemoved(device) => {
+        Self::DeviceRemoved(ButtplugBlockingClientDevice::new(device, handle))
+      }
+      ButtplugClientEvent::PingTimeout => Self::PingTimeout,
+      ButtplugClientEvent::ServerConnect => Self::ServerConnect,
+      ButtplugClientEvent::ServerDisconnect => Self::ServerDisconnect,
+      ButtplugClientEvent::Error(err) => Self::Error(err),
+    }
+  }
+}
+
+/// [ButtplugClientDevice] with blocking versions of its common commands. Anything else can be run
+/// through [device](Self::device) and [ButtplugBlockingClient::block_on].
+#[derive(Clone)]
+pub struct ButtplugBlockingClientDevice {
+  device: Arc<ButtplugClientDevice>,
+  handle: Handle,
+}
+
+impl std::fmt::Debug for ButtplugBlockingClientDevice {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ButtplugBlockingClientDevice")
+      .field("name", self.device.name())
+      .field("index", &self.device.index())
+      .finish()
+  }
+}
+
+impl ButtplugBlockingClientDevice {
+  fn new(device: Arc<ButtplugClientDevice>, handle: &Handle) -> Self {
+    Self {
+      device,
+      handle: handle.clone(),
+    }
+  }
+
+  /// Creates a future inside the runtime context, in case it spawns anything, and blocks on it.
+  fn run<F: Future>(&self, make_future: impl FnOnce() -> F) -> F::Output {
+    let _guard = self.handle.enter();
+    self.handle.block_on(make_future())
+  }
+
+  /// The async device this wraps.
+  pub fn device(&self) -> &Arc<ButtplugClientDevice> {
+    &self.device
+  }
+
+  pub fn name(&self) -> &String {
+    self.device.name()
+  }
+
+  pub fn display_name(&self) -> &Option<String> {
+    self.device.display_name()
+  }
+
+  pub fn index(&self) -> u32 {
+    self.device.index()
+  }
+
+  pub fn message_attributes(&self) -> &ClientDeviceMessageAttributes {
+    self.device.message_attributes()
+  }
+
+  pub fn connected(&self) -> bool {
+    self.device.connected()
+  }
+
+  /// See [ButtplugClientDevice::vibrate].
+  pub fn vibrate(&self, speed_cmd: &ScalarValueCommand) -> ButtplugClientResult {
+    self.run(|| self.device.vibrate(speed_cmd))
+  }
+
+  /// See [ButtplugClientDevice::oscillate].
+  pub fn oscillate(&self, speed_cmd: &ScalarValueCommand) -> ButtplugClientResult {
+    self.run(|| self.device.oscillate(speed_cmd))
+  }
+
+  /// See [ButtplugClientDevice::scalar].
+  pub fn scalar(&self, scalar_cmd: &ScalarCommand) -> ButtplugClientResult {
+    self.run(|| self.device.scalar(scalar_cmd))
+  }
+
+  /// See [ButtplugClientDevice::linear].
+  pub fn linear(&self, linear_cmd: &LinearCommand) -> ButtplugClientResult {
+    self.run(|| self.device.linear(linear_cmd))
+  }
+
+  /// See [ButtplugClientDevice::rotate].
+  pub fn rotate(&self, rotate_cmd: &RotateCommand) -> ButtplugClientResult {
+    self.run(|| self.device.rotate(rotate_cmd))
+  }
+
+  /// See [ButtplugClientDevice::battery_level].
+  pub fn battery_level(&self) -> ButtplugClientResult<f64> {
+    self.run(|| self.device.battery_level())
+  }
+
+  /// See [ButtplugClientDevice::rssi_level].
+  pub fn rssi_level(&self) -> ButtplugClientResult<i32> {
+    self.run(|| self.device.rssi_level())
+  }
+
+  /// See [ButtplugClientDevice::stop].
+  pub fn stop(&self) -> ButtplugClientResult {
+    self.run(|| self.device.stop())
+  }
+}
+
+/// [ButtplugClient] with its own runtime and blocking methods. See the [module
+/// documentation](self) for how to use it.
+pub struct ButtplugBlockingClient {
+  client: ButtplugClient,
+  /// Task forwarding client events to the callback thread, if a callback is set.
+  event_forwarder: Mutex<Option<JoinHandle<()>>>,
+  runtime: Runtime,
+}
+
+impl ButtplugBlockingClient {
+  /// Creates a client with default settings, and a runtime to run it on. Fails if the runtime
+  /// can't be created.
+  pub fn new(name: &str) -> std::io::Result<Self> {
+    Self::with_client(ButtplugClient::new(name))
+  }
+
+  /// Wraps a client that's already been configured, i.e. with
+  /// [ButtplugClient::request_capabilities]. The client should not be connected yet, as it has to
+  /// run on the runtime created here.
+  pub fn with_client(client: ButtplugClient) -> std::io::Result<Self> {
+    Ok(Self {
+      client,
+      event_forwarder: Mutex::new(None),
+      runtime: Runtime::new()?,
+    })
+  }
+
+  /// The async client this wraps.
+  pub fn client(&self) -> &ButtplugClient {
+    &self.client
+  }
+
+  /// Handle to the client's runtime. Connectors that spawn tasks when they're built (like the
+  /// in-process connector, which builds a server) need to be built inside it, via
+  /// [Handle::enter].
+  pub fn handle(&self) -> &Handle {
+    self.runtime.handle()
+  }
+
+  /// Runs a future on the client's runtime, blocking until it's done. For async client methods
+  /// that don't have a blocking version here.
+  pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+    self.runtime.block_on(future)
+  }
+
+  /// Creates a future inside the runtime context, in case it spawns anything, and blocks on it.
+  fn run<F: Future>(&self, make_future: impl FnOnce() -> F) -> F::Output {
+    let _guard = self.runtime.enter();
+    self.runtime.block_on(make_future())
+  }
+
+  /// See [ButtplugClient::connect].
+  pub fn connect<ConnectorType>(&self, connector: ConnectorType) -> ButtplugClientResult
+  where
+    ConnectorType: ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage>
+      + 'static,
+  {
+    self.run(|| self.client.connect(connector))
+  }
+
+  /// See [ButtplugClient::disconnect].
+  pub fn disconnect(&self) -> ButtplugClientResult {
+    self.run(|| self.client.disconnect())
+  }
+
+  pub fn connected(&self) -> bool {
+    self.client.connected()
+  }
+
+  /// See [ButtplugClient::start_scanning].
+  pub fn start_scanning(&self) -> ButtplugClientResult {
+    self.run(|| self.client.start_scanning())
+  }
+
+  /// See [ButtplugClient::start_scanning_with_timeout].
+  pub fn start_scanning_with_timeout(&self, timeout: Duration) -> ButtplugClientResult {
+    self.run(|| self.client.start_scanning_with_timeout(timeout))
+  }
+
+  /// See [ButtplugClient::stop_scanning].
+  pub fn stop_scanning(&self) -> ButtplugClientResult {
+    self.run(|| self.client.stop_scanning())
+  }
+
+  /// See [ButtplugClient::stop_all_devices].
+  pub fn stop_all_devices(&self) -> ButtplugClientResult {
+    self.run(|| self.client.stop_all_devices())
+  }
+
+  /// See [ButtplugClient::ping].
+  pub fn ping(&self) -> ButtplugClientResult {
+    self.run(|| self.client.ping())
+  }
+
+  pub fn server_name(&self) -> Option<String> {
+    self.client.server_name()
+  }
+
+  /// Currently connected devices.
+  pub fn devices(&self) -> Vec<ButtplugBlockingClientDevice> {
+    self
+      .client
+      .devices()
+      .into_iter()
+      .map(|device| ButtplugBlockingClientDevice::new(device, self.handle()))
+      .collect()
+  }
+
+  /// Calls `callback` with every event the client emits from now on, replacing any callback set
+  /// before. Callbacks run on a thread of their own, in the order events were emitted.
+  pub fn set_event_callback<F>(&self, mut callback: F)
+  where
+    F: FnMut(ButtplugBlockingClientEvent) + Send + 'static,
+  {
+    let (event_sender, event_receiver) = mpsc::channel();
+    // Subscribe before returning, so no events are missed between this call and the forwarder
+    // starting up.
+    let events = self.client.event_stream();
+    let handle = self.handle().clone();
+    let forwarder = self.runtime.spawn(async move {
+      futures::pin_mut!(events);
+      while let Some(event) = events.next().await {
+        if event_sender
+          .send(ButtplugBlockingClientEvent::new(event, &handle))
+          .is_err()
+        {
+          break;
+        }
+      }
+    });
+    // The thread exits once the forwarder is stopped and it's handled everything already sent.
+    thread::spawn(move || {
+      for event in event_receiver {
+        callback(event);
+      }
+    });
+    if let Some(previous) = self
+      .event_forwarder
+      .lock()
+      .expect("Never poisoned, no panics while held")
+      .replace(forwarder)
+    {
+      previous.abort();
+    }
+  }
+
+  /// Stops calling the event callback. Events already on their way to it are still delivered.
+  pub fn clear_event_callback(&self) {
+    if let Some(forwarder) = self
+      .event_forwarder
+      .lock()
+      .expect("Never poisoned, no panics while held")
+      .take()
+    {
+      forwarder.abort();
+    }
+  }
+}
@@ -13,21 +13,24 @@ use super::{
   ButtplugClientEvent,
   ButtplugClientMessageFuturePair,
   ButtplugClientMessageSender,
+  ButtplugServerMessageStateShared,
 };
 use crate::core::{
   connector::{ButtplugConnector, ButtplugConnectorStateShared},
   errors::{ButtplugDeviceError, ButtplugError},
   message::{
+    ButtplugCapability,
     ButtplugCurrentSpecClientMessage,
     ButtplugCurrentSpecServerMessage,
     ButtplugDeviceMessage,
     ButtplugMessageValidator,
+    CancelCmd,
     DeviceList,
     DeviceMessageInfo,
   },
 };
 use dashmap::DashMap;
-use futures::FutureExt;
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
 use std::sync::{
   atomic::{AtomicBool, Ordering},
   Arc,
@@ -47,6 +50,11 @@ pub enum ButtplugClientRequest {
   /// Bundled future should have reply set and waker called when this is
   /// finished.
   Message(ButtplugClientMessageFuturePair),
+  /// The future for a message sent earlier was dropped before its reply arrived. Cancel the
+  /// message on the server, if it's a device command that hasn't run yet.
+  CancelMessage(ButtplugServerMessageStateShared),
+  /// Cancel every device command for a device that's still waiting on a reply.
+  CancelDeviceCommands(u32),
 }
 
 /// Event loop for running [ButtplugClient] connections.
@@ -77,6 +85,27 @@ pub enum ButtplugClientRequest {
 ///
 /// Note that no async call here should block. Any .await should only be on
 /// async channels, and those channels should never have backpressure. We hope.
+///
+/// # Sends in flight
+///
+/// Depending on the connector, sending a message can take as long as the
+/// server takes to answer it (the in-process connector hands the message
+/// straight to the server, and only finishes once the reply is out). Sends are
+/// therefore kept in a set of futures that runs alongside the loop instead of
+/// being awaited one at a time, so a slow request doesn't keep later requests
+/// from being sent, or their replies and server events from being delivered.
+/// Messages are still handed to the connector in the order the loop receives
+/// them.
+///
+/// # Cancellation
+///
+/// If the server agreed to the
+/// [Cancellation](crate::core::message::ButtplugCapability::Cancellation)
+/// capability, device commands whose futures are dropped before their reply
+/// arrives are cancelled on the server via [CancelCmd], so they don't run late
+/// on a device that's fallen behind. Their futures stay registered with the
+/// sorter until the server answers, so the late reply isn't mistaken for an
+/// event.
 pub(super) struct ButtplugClientEventLoop<ConnectorType>
 where
   ConnectorType:
@@ -97,7 +126,9 @@ where
   from_client_sender: Arc<ButtplugClientMessageSender>,
   /// Receives incoming messages from client instances.
   from_client_receiver: broadcast::Receiver<ButtplugClientRequest>,
-  sorter: ClientMessageSorter,
+  sorter: Arc<ClientMessageSorter>,
+  /// Connector sends that haven't finished yet.
+  pending_sends: FuturesUnordered<BoxFuture<'static, ()>>,
 }
 
 impl<ConnectorType> ButtplugClientEventLoop<ConnectorType>
@@ -127,7 +158,8 @@ where
       to_client_sender,
       from_connector_receiver,
       connector,
-      sorter: ClientMessageSorter::default(),
+      sorter: Arc::new(ClientMessageSorter::default()),
+      pending_sends: FuturesUnordered::new(),
     }
   }
 
@@ -263,6 +295,14 @@ where
             ));
         }
       }
+      ButtplugCurrentSpecServerMessage::LowBatteryWarning(msg) => {
+        let device_idx = msg.device_index();
+        if let Some(device) = self.device_map.get(&device_idx) {
+          device
+            .value()
+            .queue_event(ButtplugClientDeviceEvent::LowBattery(msg.battery_level()));
+        }
+      }
       ButtplugCurrentSpecServerMessage::Error(e) => {
         self.send_client_event(ButtplugClientEvent::Error(e.into()));
       }
@@ -281,9 +321,37 @@ where
     }
 
     trace!("Sending message to connector: {:?}", msg_fut.msg);
-    self.sorter.register_future(&mut msg_fut);
-    if self.connector.send(msg_fut.msg).await.is_err() {
-      error!("Sending message failed, connector most likely no longer connected.");
+    let id = self.sorter.register_future(&mut msg_fut);
+    let send_fut = self.connector.send(msg_fut.msg);
+    let sorter = self.sorter.clone();
+    self.pending_sends.push(
+      async move {
+        if let Err(err) = send_fut.await {
+          error!("Sending message failed, connector most likely no longer connected.");
+          sorter.fail_future(id, err);
+        }
+      }
+      .boxed(),
+    );
+  }
+
+  /// Sends [CancelCmd]s for device commands, if the server agreed to cancellation. Nothing waits
+  /// on their replies, the futures for the cancelled commands get an error from the server instead.
+  async fn cancel_messages(&mut self, ids: Vec<u32>) {
+    if !self
+      .from_client_sender
+      .has_capability(ButtplugCapability::Cancellation)
+    {
+      return;
+    }
+    for id in ids {
+      debug!("Cancelling message {}", id);
+      self
+        .send_message(ButtplugClientMessageFuturePair::new(
+          CancelCmd::new(id).into(),
+          ButtplugServerMessageStateShared::default(),
+        ))
+        .await;
     }
   }
 
@@ -302,6 +370,16 @@ where
         self.send_message(msg_fut).await;
         true
       }
+      ButtplugClientRequest::CancelMessage(state) => {
+        let ids = self.sorter.cancellable_id(&state).into_iter().collect();
+        self.cancel_messages(ids).await;
+        true
+      }
+      ButtplugClientRequest::CancelDeviceCommands(device_index) => {
+        let ids = self.sorter.cancellable_ids(device_index);
+        self.cancel_messages(ids).await;
+        true
+      }
       ButtplugClientRequest::Disconnect(state) => {
         trace!("Client requested disconnect");
         state.set_reply(self.connector.disconnect().await);
@@ -335,6 +413,7 @@ where
             self.parse_connector_message(msg).await;
           }
         },
+        _ = self.pending_sends.select_next_some() => {},
         client = self.from_client_receiver.recv().fuse() => match client {
           Err(_) => {
             info!("Client disconnected, exiting loop.");
@@ -358,6 +437,8 @@ where
       .iter()
       .for_each(|k| self.disconnect_device(*k));
     self.connected_status.store(false, Ordering::SeqCst);
+    // Nothing will answer requests still in flight now, so don't leave them hanging.
+    self.sorter.fail_all();
     self.send_client_event(ButtplugClientEvent::ServerDisconnect);
 
     debug!("Exiting client event loop.");
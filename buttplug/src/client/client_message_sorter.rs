@@ -13,14 +13,46 @@ use crate::{
     ButtplugClientMessageFuturePair,
     ButtplugServerMessageStateShared,
   },
-  core::message::{ButtplugCurrentSpecServerMessage, ButtplugMessage, ButtplugMessageValidator},
+  core::{
+    connector::ButtplugConnectorError,
+    message::{
+      ButtplugClientMessage,
+      ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage,
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugMessageValidator,
+    },
+  },
 };
 use dashmap::DashMap;
-use std::sync::{
-  atomic::{AtomicU32, Ordering},
-  Arc,
+use std::{
+  convert::TryFrom,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+  },
 };
 
+/// Future waiting on a response, along with what's needed to cancel its message.
+struct PendingFuture {
+  state: ButtplugServerMessageStateShared,
+  /// Device the message commands, if the server can cancel it.
+  cancellable_device_index: Option<u32>,
+}
+
+/// Device index for device commands that can be cancelled via
+/// [CancelCmd](crate::core::message::CancelCmd). Stops can't be.
+fn cancellable_device_index(msg: &ButtplugCurrentSpecClientMessage) -> Option<u32> {
+  if matches!(msg, ButtplugCurrentSpecClientMessage::StopDeviceCmd(_)) {
+    return None;
+  }
+  ButtplugDeviceCommandMessageUnion::try_from(ButtplugClientMessage::from(msg.clone()))
+    .ok()
+    .map(|device_msg| device_msg.device_index())
+}
+
 /// Message sorting and pairing for remote client connectors.
 ///
 /// In order to create reliable connections to remote systems, we need a way to maintain message
@@ -52,13 +84,16 @@ use std::sync::{
 /// - If the message `id` is not zero but there is no future waiting, the message is dropped and an
 ///   error is emitted.
 ///
+/// Futures are resolved in whatever order their responses arrive, which isn't necessarily the order
+/// their messages were sent in. A slow request (a raw read waiting on hardware, say) stays in the
+/// map while responses to later requests resolve around it.
 pub struct ClientMessageSorter {
   /// Map of message `id`s to their related future.
   ///
   /// This is where we store message `id`s that are waiting for a return from the server. Once we
   /// get back a response with a matching `id`, we remove the entry from this map, and use the waker
   /// to complete the future with the received response message.
-  future_map: DashMap<u32, ButtplugServerMessageStateShared>,
+  future_map: DashMap<u32, PendingFuture>,
 
   /// Message `id` counter
   ///
@@ -73,12 +108,58 @@ impl ClientMessageSorter {
   ///
   /// Given a message and its related future, set the message's `id`, and match that id with the
   /// future to be resolved when we get a response back.
-  pub fn register_future(&self, msg_fut: &mut ButtplugClientMessageFuturePair) {
-    let id = self.current_id.load(Ordering::SeqCst);
+  pub fn register_future(&self, msg_fut: &mut ButtplugClientMessageFuturePair) -> u32 {
+    let mut id = self.current_id.fetch_add(1, Ordering::SeqCst);
+    // 0 is reserved for server events, so skip it if we ever wrap around.
+    if id == 0 {
+      id = self.current_id.fetch_add(1, Ordering::SeqCst);
+    }
     trace!("Setting message id to {}", id);
     msg_fut.msg.set_id(id);
-    self.future_map.insert(id, msg_fut.waker.clone());
-    self.current_id.store(id + 1, Ordering::SeqCst);
+    self.future_map.insert(
+      id,
+      PendingFuture {
+        state: msg_fut.waker.clone(),
+        cancellable_device_index: cancellable_device_index(&msg_fut.msg),
+      },
+    );
+    id
+  }
+
+  /// Id of the message a future is waiting on, if it's a device command that can be cancelled.
+  /// The future stays registered, as the server still answers cancelled commands.
+  pub fn cancellable_id(&self, state: &ButtplugServerMessageStateShared) -> Option<u32> {
+    self
+      .future_map
+      .iter()
+      .find(|x| x.cancellable_device_index.is_some() && x.state.ptr_eq(state))
+      .map(|x| *x.key())
+  }
+
+  /// Ids of the device commands still waiting on a response for a device, that can be cancelled.
+  pub fn cancellable_ids(&self, device_index: u32) -> Vec<u32> {
+    self
+      .future_map
+      .iter()
+      .filter(|x| x.cancellable_device_index == Some(device_index))
+      .map(|x| *x.key())
+      .collect()
+  }
+
+  /// Resolves the future for a message that never made it to the server with an error.
+  pub fn fail_future(&self, id: u32, err: ButtplugConnectorError) {
+    if let Some((_, pending)) = self.future_map.remove(&id) {
+      pending.state.set_reply(Err(err.into()));
+    }
+  }
+
+  /// Resolves every future still waiting on a response with an error, for when the connection is
+  /// gone and no more responses will arrive.
+  pub fn fail_all(&self) {
+    let ids: Vec<u32> = self.future_map.iter().map(|x| *x.key()).collect();
+    for id in ids {
+      self.fail_future(id, ButtplugConnectorError::ConnectorNotConnected);
+    }
   }
 
   /// Given a response message from the server, resolve related future if we have one.
@@ -89,7 +170,7 @@ impl ClientMessageSorter {
     let id = msg.id();
     trace!("Trying to resolve message future for id {}.", id);
     match self.future_map.remove(&id) {
-      Some((_, state)) => {
+      Some((_, PendingFuture { state, .. })) => {
         trace!("Resolved id {} to a future.", id);
         if let Err(e) = msg.is_valid() {
           error!("Message not valid: {:?} - Error: {}", msg, e);
@@ -8,7 +8,11 @@
 //! Representation and management of devices connected to the server.
 
 use super::{
+  client_event_loop::ButtplugClientRequest,
   create_boxed_future_client_error,
+  feature::{device_actuators, device_sensors, Actuator, Sensor},
+  ramp::{Easing, Ramp},
+  raw_stream::{RawEndpointStream, RawSubscriptionCounts},
   ButtplugClientMessageSender,
   ButtplugClientResultFuture,
 };
@@ -17,19 +21,33 @@ use crate::{
     errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
     message::{
       ActuatorType,
+      ButtplugCapability,
       ButtplugCurrentSpecClientMessage,
       ButtplugCurrentSpecServerMessage,
       ButtplugDeviceMessageType,
       ClientDeviceMessageAttributes,
       ClientGenericDeviceMessageAttributes,
+      DeviceDisplayNameCmd,
+      DeviceFirmwareUpdateCmd,
+      DeviceLockCmd,
       DeviceMessageInfo,
+      DeviceSelfTestCmd,
+      DeviceSelfTestReport,
+      DeviceTransport,
+      DeviceUnlockCmd,
+      DeviceVersion,
       Endpoint,
       LinearCmd,
+      PatternLoadCmd,
+      PatternPlayCmd,
+      PatternStopCmd,
       RawReadCmd,
       RawSubscribeCmd,
       RawUnsubscribeCmd,
       RawWriteCmd,
       RotateCmd,
+      RotatePositionCmd,
+      RotationMode,
       RotationSubcommand,
       ScalarCmd,
       ScalarSubcommand,
@@ -41,10 +59,11 @@ use crate::{
       VectorSubcommand,
     },
   },
-  util::stream::convert_broadcast_receiver_to_stream,
+  util::{pattern::Pattern, sleep, stream::convert_broadcast_receiver_to_stream},
 };
-use futures::{FutureExt, Stream};
+use futures::{future, FutureExt, Stream};
 use getset::{CopyGetters, Getters};
+use instant::Instant;
 use std::{
   collections::HashMap,
   fmt,
@@ -52,6 +71,7 @@ use std::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
+  time::Duration,
 };
 use tokio::sync::broadcast;
 
@@ -68,12 +88,16 @@ pub enum ButtplugClientDeviceEvent {
   ClientDisconnect,
   /// Message was received from server for that specific device.
   Message(ButtplugCurrentSpecServerMessage),
+  /// Device battery dropped below the server's low battery threshold. Carries the battery level
+  /// (0.0-1.0).
+  LowBattery(f64),
 }
 
 /// Convenience enum for forming [VibrateCmd] commands.
 ///
 /// Allows users to easily specify speeds across different vibration features in
 /// a device. Units are in absolute speed values (0.0-1.0).
+#[derive(Debug, Clone, PartialEq)]
 pub enum ScalarCommand {
   /// Sets all vibration features of a device to the same speed.
   Scalar((f64, ActuatorType)),
@@ -91,6 +115,7 @@ pub enum ScalarCommand {
 ///
 /// Allows users to easily specify speeds across different vibration features in
 /// a device. Units are in absolute speed values (0.0-1.0).
+#[derive(Debug, Clone, PartialEq)]
 pub enum ScalarValueCommand {
   /// Sets all vibration features of a device to the same speed.
   ScalarValue(f64),
@@ -109,6 +134,7 @@ pub enum ScalarValueCommand {
 /// Allows users to easily specify speeds/directions across different rotation
 /// features in a device. Units are in absolute speed (0.0-1.0), and clockwise
 /// direction (clockwise if true, counterclockwise if false)
+#[derive(Debug, Clone, PartialEq)]
 pub enum RotateCommand {
   /// Sets all rotation features of a device to the same speed/direction.
   Rotate(f64, bool),
@@ -127,6 +153,7 @@ pub enum RotateCommand {
 /// Allows users to easily specify position/durations across different rotation
 /// features in a device. Units are in absolute position (0.0-1.0) and
 /// millliseconds of movement duration.
+#[derive(Debug, Clone, PartialEq)]
 pub enum LinearCommand {
   /// Sets all linear features of a device to the same position/duration.
   Linear(u32, f64),
@@ -154,6 +181,18 @@ pub struct ButtplugClientDevice {
   /// Display name of the device
   #[getset(get = "pub")]
   display_name: Option<String>,
+  /// Type of connection the device is using on the server, and its address on that connection.
+  /// Will be None if the server didn't send transport information.
+  #[getset(get = "pub")]
+  transport: Option<DeviceTransport>,
+  /// Firmware and hardware revisions the device reported to the server. Will be None if the server
+  /// didn't send version information, or the device didn't report any.
+  #[getset(get = "pub")]
+  version: Option<DeviceVersion>,
+  /// User metadata (icon, color, notes, etc...) stored for the device in the server's user device
+  /// configuration. Will be None if no metadata is set.
+  #[getset(get = "pub")]
+  metadata: Option<HashMap<String, String>>,
   /// Index of the device, matching the index in the
   /// [ButtplugServer][crate::server::ButtplugServer]'s
   /// [DeviceManager][crate::server::device_manager::DeviceManager].
@@ -169,6 +208,8 @@ pub struct ButtplugClientDevice {
   /// through the connector.
   event_loop_sender: Arc<ButtplugClientMessageSender>,
   internal_event_sender: broadcast::Sender<ButtplugClientDeviceEvent>,
+  /// Live [RawEndpointStream]s per endpoint.
+  raw_subscriptions: RawSubscriptionCounts,
   /// True if this [ButtplugClientDevice] is currently connected to the
   /// [ButtplugServer][crate::server::ButtplugServer].
   device_connected: Arc<AtomicBool>,
@@ -192,9 +233,13 @@ impl ButtplugClientDevice {
   /// [ButtplugClientDevice]. A [ButtplugClientDevice] is mostly a shim around
   /// the [ButtplugClient] that generated it, with some added convenience
   /// functions for forming device control messages.
+  #[allow(clippy::too_many_arguments)]
   pub(super) fn new(
     name: &str,
     display_name: &Option<String>,
+    transport: &Option<DeviceTransport>,
+    version: &Option<DeviceVersion>,
+    metadata: &Option<HashMap<String, String>>,
     index: u32,
     message_attributes: &ClientDeviceMessageAttributes,
     message_sender: &Arc<ButtplugClientMessageSender>,
@@ -210,10 +255,14 @@ impl ButtplugClientDevice {
     Self {
       name: name.to_owned(),
       display_name: display_name.clone(),
+      transport: transport.clone(),
+      version: version.clone(),
+      metadata: metadata.clone(),
       index,
       message_attributes: message_attributes.clone(),
       event_loop_sender: message_sender.clone(),
       internal_event_sender: event_sender,
+      raw_subscriptions: RawSubscriptionCounts::default(),
       device_connected,
       client_connected,
     }
@@ -226,6 +275,9 @@ impl ButtplugClientDevice {
     ButtplugClientDevice::new(
       info.device_name(),
       info.device_display_name(),
+      info.device_transport(),
+      info.device_version(),
+      info.device_metadata(),
       info.device_index(),
       info.device_messages(),
       sender,
@@ -257,6 +309,27 @@ impl ButtplugClientDevice {
     }
   }
 
+  /// Output features of the device, in ScalarCmd, RotateCmd, LinearCmd order. Each [Actuator] can
+  /// be commanded on its own, without working out its index in the message attributes.
+  pub fn actuators(&self) -> Vec<Actuator> {
+    device_actuators(
+      self.index,
+      &self.message_attributes,
+      &self.event_loop_sender,
+    )
+  }
+
+  /// Sensors of the device, which can be read from or subscribed to through the [Sensor] handle.
+  pub fn sensors(&self) -> Vec<Sensor> {
+    device_sensors(
+      self.index,
+      &self.message_attributes,
+      &self.event_loop_sender,
+      &self.internal_event_sender,
+    )
+  }
+
+  #[deprecated(note = "Use actuators() instead")]
   pub fn scalar_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
     if let Some(attrs) = self.message_attributes.scalar_cmd() {
       attrs.clone()
@@ -339,6 +412,7 @@ impl ButtplugClientDevice {
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  #[deprecated(note = "Use actuators() instead")]
   pub fn vibrate_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
     self.scalar_value_attributes(&ActuatorType::Vibrate)
   }
@@ -348,20 +422,97 @@ impl ButtplugClientDevice {
     self.scalar_from_value_command(
       speed_cmd,
       &ActuatorType::Vibrate,
-      &self.vibrate_attributes(),
+      &self.scalar_value_attributes(&ActuatorType::Vibrate),
     )
   }
 
+  #[deprecated(note = "Use actuators() instead")]
   pub fn oscillate_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
     self.scalar_value_attributes(&ActuatorType::Oscillate)
   }
 
-  /// Commands device to vibrate, assuming it has the features to do so.
+  /// Commands device to oscillate (thrust, stroke, etc), assuming it has the features to do so.
   pub fn oscillate(&self, speed_cmd: &ScalarValueCommand) -> ButtplugClientResultFuture {
     self.scalar_from_value_command(
       speed_cmd,
       &ActuatorType::Oscillate,
-      &self.oscillate_attributes(),
+      &self.scalar_value_attributes(&ActuatorType::Oscillate),
+    )
+  }
+
+  /// Ramps all features of an actuator type from one value to another over time.
+  ///
+  /// The returned future runs the update loop, sending a new value every
+  /// [Ramp::update_interval], and resolves once the final value has been sent. Dropping the future
+  /// cancels the ramp, leaving the device at whatever value was last sent. The ramp will also end
+  /// with an error if the device disconnects before it finishes.
+  pub fn scalar_ramp(&self, actuator: &ActuatorType, ramp: &Ramp) -> ButtplugClientResultFuture {
+    let attrs = self.scalar_value_attributes(actuator);
+    if attrs.is_empty() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::UnhandledCommand(format!(
+          "ScalarCmd with {actuator} is not handled by this device"
+        ))
+        .into(),
+      );
+    }
+    let index = self.index;
+    let actuator = *actuator;
+    let ramp = *ramp;
+    let event_loop_sender = self.event_loop_sender.clone();
+    let device_connected = self.device_connected.clone();
+    let name = self.name.clone();
+    async move {
+      let start = Instant::now();
+      loop {
+        if !device_connected.load(Ordering::SeqCst) {
+          return Err(ButtplugError::from(ButtplugDeviceError::DeviceNotConnected(name)).into());
+        }
+        let elapsed = start.elapsed();
+        let value = ramp.value_at(elapsed);
+        let scalar_vec = attrs
+          .iter()
+          .map(|attr| ScalarSubcommand::new(*attr.index(), value, actuator))
+          .collect();
+        event_loop_sender
+          .send_message_expect_ok(ScalarCmd::new(index, scalar_vec).into())
+          .await?;
+        if elapsed >= ramp.duration() {
+          return Ok(());
+        }
+        sleep(ramp.update_interval().min(ramp.duration() - elapsed)).await;
+      }
+    }
+    .boxed()
+  }
+
+  /// Ramps all vibration features of a device from one speed to another over the given duration.
+  /// See [ButtplugClientDevice::scalar_ramp] for cancellation behavior.
+  pub fn vibrate_ramp(
+    &self,
+    from: f64,
+    to: f64,
+    duration: Duration,
+    easing: Easing,
+  ) -> ButtplugClientResultFuture {
+    self.scalar_ramp(
+      &ActuatorType::Vibrate,
+      &Ramp::new(from, to, duration, easing),
+    )
+  }
+
+  /// Ramps all oscillation features of a device from one speed to another over the given duration.
+  /// See [ButtplugClientDevice::scalar_ramp] for cancellation behavior.
+  pub fn oscillate_ramp(
+    &self,
+    from: f64,
+    to: f64,
+    duration: Duration,
+    easing: Easing,
+  ) -> ButtplugClientResultFuture {
+    self.scalar_ramp(
+      &ActuatorType::Oscillate,
+      &Ramp::new(from, to, duration, easing),
     )
   }
 
@@ -419,6 +570,31 @@ impl ButtplugClientDevice {
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  /// Loads a pattern into the server's pattern engine, replacing any pattern already loaded for
+  /// the device. Tracks are matched to the device's scalar actuators by type and index, and tracks
+  /// with no matching actuator are ignored. Use [play_pattern](Self::play_pattern) to start it.
+  pub fn load_pattern(&self, pattern: &Pattern) -> ButtplugClientResultFuture {
+    self
+      .event_loop_sender
+      .send_message_expect_ok(PatternLoadCmd::new(self.index, &pattern.to_json()).into())
+  }
+
+  /// Starts playing the loaded pattern on the server, from the section with the given label, or
+  /// from the beginning if None is passed. Resolves once playback has started.
+  pub fn play_pattern(&self, section: Option<&str>) -> ButtplugClientResultFuture {
+    self
+      .event_loop_sender
+      .send_message_expect_ok(PatternPlayCmd::new(self.index, section).into())
+  }
+
+  /// Stops server side pattern playback, zeroing the actuators the pattern drives.
+  pub fn stop_pattern(&self) -> ButtplugClientResultFuture {
+    self
+      .event_loop_sender
+      .send_message_expect_ok(PatternStopCmd::new(self.index).into())
+  }
+
+  #[deprecated(note = "Use actuators() instead")]
   pub fn linear_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
     if let Some(attrs) = self.message_attributes.linear_cmd() {
       attrs.clone()
@@ -477,8 +653,9 @@ impl ButtplugClientDevice {
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  #[deprecated(note = "Use actuators() instead")]
   pub fn rotate_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
-    if let Some(attrs) = self.message_attributes.linear_cmd() {
+    if let Some(attrs) = self.message_attributes.rotate_cmd() {
       attrs.clone()
     } else {
       vec![]
@@ -535,6 +712,41 @@ impl ButtplugClientDevice {
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  /// Commands positional rotators (see
+  /// [ClientGenericDeviceMessageAttributes::is_positional_rotator]) to turn to an absolute
+  /// position. Map entries are rotator index to (duration in milliseconds, position 0.0-1.0).
+  pub fn rotate_position(
+    &self,
+    positions: &HashMap<u32, (u32, f64)>,
+  ) -> ButtplugClientResultFuture {
+    let rotate_attrs = self
+      .message_attributes
+      .rotate_cmd()
+      .clone()
+      .unwrap_or_default();
+    let mut vectors = Vec::with_capacity(positions.len());
+    for (idx, (dur, pos)) in positions {
+      let Some(attr) = rotate_attrs.get(*idx as usize) else {
+        return create_boxed_future_client_error(
+          ButtplugDeviceError::DeviceFeatureIndexError(rotate_attrs.len() as u32, *idx).into(),
+        );
+      };
+      if !attr.is_positional_rotator() {
+        return create_boxed_future_client_error(
+          ButtplugDeviceError::DeviceRotationModeMismatch(
+            *idx,
+            RotationMode::Positional,
+            attr.rotation_mode().unwrap_or_default(),
+          )
+          .into(),
+        );
+      }
+      vectors.push(VectorSubcommand::new(*idx, *dur, *pos));
+    }
+    let msg = RotatePositionCmd::new(self.index, vectors).into();
+    self.event_loop_sender.send_message_expect_ok(msg)
+  }
+
   pub fn subscribe_sensor(
     &self,
     sensor_index: u32,
@@ -713,6 +925,59 @@ impl ButtplugClientDevice {
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  /// Subscribes to an endpoint, returning a stream of the data it sends.
+  ///
+  /// Multiple streams can be opened for the same endpoint, and the endpoint is unsubscribed once
+  /// the last of them is dropped. Mixing this with [raw_subscribe](Self::raw_subscribe) and
+  /// [raw_unsubscribe](Self::raw_unsubscribe) on the same endpoint will end streams early.
+  pub fn subscribe_endpoint(
+    &self,
+    endpoint: Endpoint,
+  ) -> ButtplugClientResultFuture<RawEndpointStream> {
+    if self.message_attributes.raw_subscribe_cmd().is_none() {
+      // Streams aren't Sync, so create_boxed_future_client_error can't be used here.
+      return future::ready(Err(
+        ButtplugError::from(ButtplugDeviceError::MessageNotSupported(
+          ButtplugDeviceMessageType::RawSubscribeCmd,
+        ))
+        .into(),
+      ))
+      .boxed();
+    }
+    // Listen before subscribing, so readings sent right after the subscription aren't missed.
+    let receiver = self.internal_event_sender.subscribe();
+    let first = {
+      let mut subscriptions = self
+        .raw_subscriptions
+        .lock()
+        .expect("Never poisoned, no panics while held");
+      let count = subscriptions.entry(endpoint).or_insert(0);
+      *count += 1;
+      *count == 1
+    };
+    let mut stream = RawEndpointStream::new(
+      self.index,
+      endpoint,
+      receiver,
+      &self.event_loop_sender,
+      &self.raw_subscriptions,
+    );
+    if !first {
+      return future::ready(Ok(stream)).boxed();
+    }
+    let subscribe = self.raw_subscribe(endpoint);
+    async move {
+      match subscribe.await {
+        Ok(()) => Ok(stream),
+        Err(err) => {
+          stream.forget_subscription();
+          Err(err)
+        }
+      }
+    }
+    .boxed()
+  }
+
   /// Commands device to stop all movement.
   pub fn stop(&self) -> ButtplugClientResultFuture {
     // All devices accept StopDeviceCmd
@@ -721,6 +986,98 @@ impl ButtplugClientDevice {
       .send_message_expect_ok(StopDeviceCmd::new(self.index).into())
   }
 
+  /// Cancels commands sent to the device that the server hasn't answered yet. Commands still
+  /// waiting for the device are dropped, and their futures fail with
+  /// [ButtplugDeviceError::DeviceCommandCancelled]. Commands already being written to the hardware
+  /// finish, but still fail the same way. Stops aren't cancelled.
+  ///
+  /// Resolves once the cancellations are on their way, not once the commands have been answered.
+  /// Requires the server to agree to the [Cancellation](ButtplugCapability::Cancellation)
+  /// capability. Dropping a command's future cancels it the same way, so this is mostly useful for
+  /// commands whose futures are held elsewhere.
+  pub fn cancel_commands(&self) -> ButtplugClientResultFuture {
+    if !self
+      .event_loop_sender
+      .has_capability(ButtplugCapability::Cancellation)
+    {
+      return create_boxed_future_client_error(
+        ButtplugMessageError::UnhandledMessage(format!(
+          "Cancelling commands requires the {} capability",
+          ButtplugCapability::Cancellation
+        ))
+        .into(),
+      );
+    }
+    self
+      .event_loop_sender
+      .send_message_to_event_loop(ButtplugClientRequest::CancelDeviceCommands(self.index))
+  }
+
+  /// Requests exclusive control of the device.
+  ///
+  /// While locked, output commands for the device from other clients connected to the same server
+  /// are refused. Stop commands are still accepted from any client. Fails with
+  /// [ButtplugDeviceError::DeviceLocked] if another client already holds the lock. Locks are
+  /// released on [unlock](Self::unlock), or when the client disconnects.
+  pub fn lock(&self) -> ButtplugClientResultFuture {
+    self
+      .event_loop_sender
+      .send_message_expect_ok(DeviceLockCmd::new(self.index).into())
+  }
+
+  /// Releases exclusive control of the device, previously requested with [lock](Self::lock).
+  pub fn unlock(&self) -> ButtplugClientResultFuture {
+    self
+      .event_loop_sender
+      .send_message_expect_ok(DeviceUnlockCmd::new(self.index).into())
+  }
+
+  /// Sets the display name of the device, i.e. "Left Toy", or clears it if None is passed.
+  ///
+  /// The server keeps display names in its user device configuration, so they survive server
+  /// restarts. The [display_name](Self::display_name) of this instance isn't updated, the new name
+  /// shows up the next time the device is added.
+  pub fn set_display_name(&self, display_name: Option<&str>) -> ButtplugClientResultFuture {
+    self
+      .event_loop_sender
+      .send_message_expect_ok(DeviceDisplayNameCmd::new(self.index, display_name).into())
+  }
+
+  /// Updates the firmware of the device through its bootloader, with the init packet (.dat) and
+  /// firmware image (.bin) from the vendor's DFU package.
+  ///
+  /// Resolves once the update is done, at which point the device restarts into the new firmware and
+  /// disconnects. Needs an admin connection to a server that allows firmware updates. The device
+  /// refuses other commands while updating, and a bad image can leave it unusable, so only use
+  /// firmware from the device's vendor.
+  pub fn update_firmware(&self, init_packet: &[u8], firmware: &[u8]) -> ButtplugClientResultFuture {
+    self.event_loop_sender.send_message_expect_ok(
+      DeviceFirmwareUpdateCmd::new(self.index, init_packet, firmware).into(),
+    )
+  }
+
+  /// Runs a short self-test on the device, pulsing each actuator in turn and reading each sensor,
+  /// then stopping the device. The report has the result and timing for every actuator and sensor,
+  /// which is useful for checking a repaired toy or for support requests.
+  pub fn self_test(&self) -> ButtplugClientResultFuture<DeviceSelfTestReport> {
+    let reply = self
+      .event_loop_sender
+      .send_message(DeviceSelfTestCmd::new(self.index).into());
+    async move {
+      if let ButtplugCurrentSpecServerMessage::DeviceSelfTestReport(report) = reply.await? {
+        Ok(report)
+      } else {
+        Err(
+          ButtplugError::ButtplugMessageError(ButtplugMessageError::UnexpectedMessageType(
+            "DeviceSelfTestReport".to_owned(),
+          ))
+          .into(),
+        )
+      }
+    }
+    .boxed()
+  }
+
   pub(super) fn set_device_connected(&self, connected: bool) {
     self.device_connected.store(connected, Ordering::SeqCst);
   }
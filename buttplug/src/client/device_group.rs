@@ -0,0 +1,162 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Drive multiple devices from a single intensity value.
+//!
+//! Applications with more than one device connected usually want to treat them as one output ("set
+//! the room to 60%"), while still toning down devices that are too strong, or favoring devices that
+//! feel weak at low speeds. A [DeviceGroup] holds devices with a weight and a response curve each,
+//! and [DeviceGroup::set_intensity] fans a single value out to all of them:
+//!
+//! `level = min(curve(intensity) * weight, 1.0)`
+//!
+//! The level is sent to every scalar actuator (vibrators, oscillators, constrictors, etc) and
+//! continuous rotator of each device. Linear and positional features have no sensible intensity,
+//! and are left alone.
+
+use super::{ramp::Easing, ButtplugClientDevice, ButtplugClientResultFuture, ScalarCommand};
+use crate::core::message::ButtplugDeviceMessageType;
+use futures::{future, FutureExt};
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+struct DeviceGroupMember {
+  device: Arc<ButtplugClientDevice>,
+  weight: f64,
+  curve: Easing,
+}
+
+impl DeviceGroupMember {
+  fn level(&self, intensity: f64) -> f64 {
+    (self.curve.apply(intensity) * self.weight).clamp(0.0, 1.0)
+  }
+
+  fn send(&self, intensity: f64) -> Vec<ButtplugClientResultFuture> {
+    let level = self.level(intensity);
+    let actuators = self.device.actuators();
+    let scalars: HashMap<u32, (f64, _)> = actuators
+      .iter()
+      .filter(|x| x.message_type() == ButtplugDeviceMessageType::ScalarCmd)
+      .map(|x| (x.index(), (level, *x.actuator_type())))
+      .collect();
+    let mut sends = vec![];
+    if !scalars.is_empty() {
+      sends.push(self.device.scalar(&ScalarCommand::ScalarMap(scalars)));
+    }
+    sends.extend(
+      actuators
+        .iter()
+        .filter(|x| {
+          x.message_type() == ButtplugDeviceMessageType::RotateCmd
+            && !x.attributes().is_positional_rotator()
+        })
+        .map(|x| x.rotate(level, true)),
+    );
+    sends
+  }
+}
+
+/// Set of devices driven by a single intensity value. See the [module documentation](self) for
+/// how levels are calculated.
+#[derive(Default)]
+pub struct DeviceGroup {
+  members: Mutex<Vec<DeviceGroupMember>>,
+  intensity: Mutex<f64>,
+}
+
+impl DeviceGroup {
+  /// Adds a device to the group, or updates its weight and curve if it's already a member. Weights
+  /// above 1.0 boost the device, but levels are capped at 1.0. New members are brought to the
+  /// group's current intensity the next time [set_intensity](Self::set_intensity) is called.
+  pub fn add(&self, device: &Arc<ButtplugClientDevice>, weight: f64, curve: Easing) {
+    let mut members = self
+      .members
+      .lock()
+      .expect("Never poisoned, no panics while held");
+    let member = DeviceGroupMember {
+      device: device.clone(),
+      weight: weight.max(0.0),
+      curve,
+    };
+    match members
+      .iter_mut()
+      .find(|x| x.device.index() == device.index())
+    {
+      Some(existing) => *existing = member,
+      None => members.push(member),
+    }
+  }
+
+  /// Removes a device from the group. Returns false if the device wasn't a member. The device is
+  /// left at whatever level it was last sent.
+  pub fn remove(&self, device_index: u32) -> bool {
+    let mut members = self
+      .members
+      .lock()
+      .expect("Never poisoned, no panics while held");
+    let count = members.len();
+    members.retain(|x| x.device.index() != device_index);
+    members.len() != count
+  }
+
+  /// Indexes of the devices in the group.
+  pub fn device_indexes(&self) -> Vec<u32> {
+    self
+      .members
+      .lock()
+      .expect("Never poisoned, no panics while held")
+      .iter()
+      .map(|x| x.device.index())
+      .collect()
+  }
+
+  /// Last intensity set on the group.
+  pub fn intensity(&self) -> f64 {
+    *self
+      .intensity
+      .lock()
+      .expect("Never poisoned, no panics while held")
+  }
+
+  /// Sets every device in the group to its level for the given intensity (0.0-1.0). Devices that
+  /// have disconnected are dropped from the group. The returned future resolves once all devices
+  /// have been updated, failing with the first error any of them returned.
+  pub fn set_intensity(&self, intensity: f64) -> ButtplugClientResultFuture {
+    let intensity = intensity.clamp(0.0, 1.0);
+    *self
+      .intensity
+      .lock()
+      .expect("Never poisoned, no panics while held") = intensity;
+    let mut members = self
+      .members
+      .lock()
+      .expect("Never poisoned, no panics while held");
+    members.retain(|x| x.device.connected());
+    let sends: Vec<ButtplugClientResultFuture> =
+      members.iter().flat_map(|x| x.send(intensity)).collect();
+    async move { future::try_join_all(sends).await.map(|_| ()) }.boxed()
+  }
+
+  /// Stops every device in the group, and resets the group intensity to 0.
+  pub fn stop(&self) -> ButtplugClientResultFuture {
+    *self
+      .intensity
+      .lock()
+      .expect("Never poisoned, no panics while held") = 0.0;
+    let sends: Vec<ButtplugClientResultFuture> = self
+      .members
+      .lock()
+      .expect("Never poisoned, no panics while held")
+      .iter()
+      .filter(|x| x.device.connected())
+      .map(|x| x.device.stop())
+      .collect();
+    async move { future::try_join_all(sends).await.map(|_| ()) }.boxed()
+  }
+}
@@ -0,0 +1,423 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-feature handles for client devices.
+//!
+//! Message Spec v3 describes device features as arrays in the device's message attributes, and
+//! commands address features by their position in those arrays. A device like [Vibrate, Oscillate,
+//! Vibrate] means remembering that the vibrators are scalar features 0 and 2, that rotators and
+//! linear features are counted separately, and that sensors have different indexes for reading and
+//! subscribing. [Actuator] and [Sensor] do that bookkeeping once, when the device is added, so each
+//! feature can be driven on its own:
+//!
+//! ```no_run
+//! # use buttplug::client::{ButtplugClientDevice, ButtplugClientError};
+//! # use buttplug::core::message::ActuatorType;
+//! # async fn run(device: &ButtplugClientDevice) -> Result<(), ButtplugClientError> {
+//! for actuator in device.actuators() {
+//!   if *actuator.actuator_type() == ActuatorType::Vibrate {
+//!     actuator.command(0.5).await?;
+//!   }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{
+  create_boxed_future_client_error,
+  device::ButtplugClientDeviceEvent,
+  ButtplugClientMessageSender,
+  ButtplugClientResultFuture,
+};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
+    message::{
+      ActuatorType,
+      ButtplugCurrentSpecServerMessage,
+      ButtplugDeviceMessageType,
+      ClientDeviceMessageAttributes,
+      ClientGenericDeviceMessageAttributes,
+      LinearCmd,
+      RotateCmd,
+      RotatePositionCmd,
+      RotationMode,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+      SensorDeviceMessageAttributes,
+      SensorReadCmd,
+      SensorSubscribeCmd,
+      SensorType,
+      SensorUnsubscribeCmd,
+      VectorSubcommand,
+    },
+  },
+  util::stream::convert_broadcast_receiver_to_stream,
+};
+use futures::{FutureExt, Stream, StreamExt};
+use getset::{CopyGetters, Getters};
+use std::{ops::RangeInclusive, sync::Arc};
+use tokio::sync::broadcast;
+
+/// A single output feature of a [ButtplugClientDevice](super::ButtplugClientDevice).
+///
+/// Obtained from [ButtplugClientDevice::actuators](super::ButtplugClientDevice::actuators). Which
+/// command methods work depends on the [message_type](Self::message_type) the feature is driven
+/// by. The others fail with [ButtplugDeviceError::MessageNotSupported].
+#[derive(Clone, Getters, CopyGetters)]
+pub struct Actuator {
+  /// Index of the device the feature belongs to.
+  #[getset(get_copy = "pub")]
+  device_index: u32,
+  /// Index of the feature in the attributes of its [message_type](Self::message_type).
+  #[getset(get_copy = "pub")]
+  index: u32,
+  /// Message used to drive the feature, one of ScalarCmd, RotateCmd or LinearCmd.
+  #[getset(get_copy = "pub")]
+  message_type: ButtplugDeviceMessageType,
+  #[getset(get = "pub")]
+  attributes: ClientGenericDeviceMessageAttributes,
+  event_loop_sender: Arc<ButtplugClientMessageSender>,
+}
+
+impl Actuator {
+  fn new(
+    device_index: u32,
+    index: u32,
+    message_type: ButtplugDeviceMessageType,
+    attributes: &ClientGenericDeviceMessageAttributes,
+    event_loop_sender: &Arc<ButtplugClientMessageSender>,
+  ) -> Self {
+    Self {
+      device_index,
+      index,
+      message_type,
+      attributes: attributes.clone(),
+      event_loop_sender: event_loop_sender.clone(),
+    }
+  }
+
+  pub fn actuator_type(&self) -> &ActuatorType {
+    self.attributes.actuator_type()
+  }
+
+  pub fn feature_descriptor(&self) -> &String {
+    self.attributes.feature_descriptor()
+  }
+
+  pub fn step_count(&self) -> u32 {
+    *self.attributes.step_count()
+  }
+
+  fn unsupported(&self, message_type: ButtplugDeviceMessageType) -> ButtplugClientResultFuture {
+    create_boxed_future_client_error(ButtplugDeviceError::MessageNotSupported(message_type).into())
+  }
+
+  /// Sets a scalar feature (vibrator, oscillator, constrictor, etc) to a level between 0.0 and
+  /// 1.0. Other features of the device are left alone.
+  pub fn command(&self, level: f64) -> ButtplugClientResultFuture {
+    if self.message_type != ButtplugDeviceMessageType::ScalarCmd {
+      return self.unsupported(ButtplugDeviceMessageType::ScalarCmd);
+    }
+    let msg = ScalarCmd::new(
+      self.device_index,
+      vec![ScalarSubcommand::new(
+        self.index,
+        level,
+        *self.attributes.actuator_type(),
+      )],
+    );
+    self.event_loop_sender.send_message_expect_ok(msg.into())
+  }
+
+  /// Spins a rotation feature at a speed between 0.0 and 1.0, clockwise if `clockwise` is true.
+  pub fn rotate(&self, speed: f64, clockwise: bool) -> ButtplugClientResultFuture {
+    if self.message_type != ButtplugDeviceMessageType::RotateCmd {
+      return self.unsupported(ButtplugDeviceMessageType::RotateCmd);
+    }
+    if self.attributes.is_positional_rotator() {
+      return self.rotation_mode_mismatch(RotationMode::Continuous);
+    }
+    let msg = RotateCmd::new(
+      self.device_index,
+      vec![RotationSubcommand::new(self.index, speed, clockwise)],
+    );
+    self.event_loop_sender.send_message_expect_ok(msg.into())
+  }
+
+  /// Turns a positional rotation feature to a position between 0.0 and 1.0, over `duration`
+  /// milliseconds.
+  pub fn rotate_to(&self, duration: u32, position: f64) -> ButtplugClientResultFuture {
+    if self.message_type != ButtplugDeviceMessageType::RotateCmd {
+      return self.unsupported(ButtplugDeviceMessageType::RotateCmd);
+    }
+    if !self.attributes.is_positional_rotator() {
+      return self.rotation_mode_mismatch(RotationMode::Positional);
+    }
+    let msg = RotatePositionCmd::new(
+      self.device_index,
+      vec![VectorSubcommand::new(self.index, duration, position)],
+    );
+    self.event_loop_sender.send_message_expect_ok(msg.into())
+  }
+
+  fn rotation_mode_mismatch(&self, requested: RotationMode) -> ButtplugClientResultFuture {
+    create_boxed_future_client_error(
+      ButtplugDeviceError::DeviceRotationModeMismatch(
+        self.index,
+        requested,
+        self.attributes.rotation_mode().unwrap_or_default(),
+      )
+      .into(),
+    )
+  }
+
+  /// Moves a linear feature to a position between 0.0 and 1.0, over `duration` milliseconds.
+  pub fn linear(&self, duration: u32, position: f64) -> ButtplugClientResultFuture {
+    if self.message_type != ButtplugDeviceMessageType::LinearCmd {
+      return self.unsupported(ButtplugDeviceMessageType::LinearCmd);
+    }
+    let msg = LinearCmd::new(
+      self.device_index,
+      vec![VectorSubcommand::new(self.index, duration, position)],
+    );
+    self.event_loop_sender.send_message_expect_ok(msg.into())
+  }
+
+  /// Stops a scalar or continuous rotation feature. Features moved to positions have nothing to
+  /// stop, and fail with [ButtplugDeviceError::UnhandledCommand]. Use
+  /// [ButtplugClientDevice::stop](super::ButtplugClientDevice::stop) to stop the whole device.
+  pub fn stop(&self) -> ButtplugClientResultFuture {
+    match self.message_type {
+      ButtplugDeviceMessageType::ScalarCmd => self.command(0.0),
+      ButtplugDeviceMessageType::RotateCmd if !self.attributes.is_positional_rotator() => {
+        self.rotate(0.0, true)
+      }
+      _ => create_boxed_future_client_error(
+        ButtplugDeviceError::UnhandledCommand(format!(
+          "{} feature {} has no stop command",
+          self.attributes.actuator_type(),
+          self.index
+        ))
+        .into(),
+      ),
+    }
+  }
+}
+
+/// A single sensor of a [ButtplugClientDevice](super::ButtplugClientDevice).
+///
+/// Obtained from [ButtplugClientDevice::sensors](super::ButtplugClientDevice::sensors). A sensor
+/// may be readable, subscribable, or both. Methods for the missing capability fail with
+/// [ButtplugDeviceError::MessageNotSupported].
+#[derive(Clone, Getters, CopyGetters)]
+pub struct Sensor {
+  /// Index of the device the sensor belongs to.
+  #[getset(get_copy = "pub")]
+  device_index: u32,
+  #[getset(get_copy = "pub")]
+  sensor_type: SensorType,
+  #[getset(get = "pub")]
+  feature_descriptor: String,
+  /// Ranges of the values in each reading.
+  #[getset(get = "pub")]
+  sensor_range: Vec<RangeInclusive<u32>>,
+  /// Index of the sensor in the device's SensorReadCmd attributes, if it can be read.
+  #[getset(get_copy = "pub")]
+  read_index: Option<u32>,
+  /// Index of the sensor in the device's SensorSubscribeCmd attributes, if it can be subscribed
+  /// to.
+  #[getset(get_copy = "pub")]
+  subscribe_index: Option<u32>,
+  event_loop_sender: Arc<ButtplugClientMessageSender>,
+  device_event_sender: broadcast::Sender<ButtplugClientDeviceEvent>,
+}
+
+impl Sensor {
+  fn new(
+    device_index: u32,
+    attributes: &SensorDeviceMessageAttributes,
+    event_loop_sender: &Arc<ButtplugClientMessageSender>,
+    device_event_sender: &broadcast::Sender<ButtplugClientDeviceEvent>,
+  ) -> Self {
+    Self {
+      device_index,
+      sensor_type: *attributes.sensor_type(),
+      feature_descriptor: attributes.feature_descriptor().clone(),
+      sensor_range: attributes.sensor_range().clone(),
+      read_index: None,
+      subscribe_index: None,
+      event_loop_sender: event_loop_sender.clone(),
+      device_event_sender: device_event_sender.clone(),
+    }
+  }
+
+  pub fn readable(&self) -> bool {
+    self.read_index.is_some()
+  }
+
+  pub fn subscribable(&self) -> bool {
+    self.subscribe_index.is_some()
+  }
+
+  /// Reads the current value of the sensor.
+  pub fn read(&self) -> ButtplugClientResultFuture<Vec<i32>> {
+    let Some(index) = self.read_index else {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::SensorReadCmd).into(),
+      );
+    };
+    let reply = self
+      .event_loop_sender
+      .send_message(SensorReadCmd::new(self.device_index, index, self.sensor_type).into());
+    async move {
+      if let ButtplugCurrentSpecServerMessage::SensorReading(data) = reply.await? {
+        Ok(data.data().clone())
+      } else {
+        Err(
+          ButtplugError::ButtplugMessageError(ButtplugMessageError::UnexpectedMessageType(
+            "SensorReading".to_owned(),
+          ))
+          .into(),
+        )
+      }
+    }
+    .boxed()
+  }
+
+  /// Asks the server to send readings as the sensor updates. Readings are delivered through
+  /// [reading_stream](Self::reading_stream).
+  pub fn subscribe(&self) -> ButtplugClientResultFuture {
+    let Some(index) = self.subscribe_index else {
+      return self.subscribe_unsupported();
+    };
+    self.event_loop_sender.send_message_expect_ok(
+      SensorSubscribeCmd::new(self.device_index, index, self.sensor_type).into(),
+    )
+  }
+
+  pub fn unsubscribe(&self) -> ButtplugClientResultFuture {
+    let Some(index) = self.subscribe_index else {
+      return self.subscribe_unsupported();
+    };
+    self.event_loop_sender.send_message_expect_ok(
+      SensorUnsubscribeCmd::new(self.device_index, index, self.sensor_type).into(),
+    )
+  }
+
+  fn subscribe_unsupported(&self) -> ButtplugClientResultFuture {
+    create_boxed_future_client_error(
+      ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::SensorSubscribeCmd)
+        .into(),
+    )
+  }
+
+  /// Stream of readings for this sensor received while subscribed. Ends when the device or client
+  /// disconnects.
+  pub fn reading_stream(&self) -> impl Stream<Item = Vec<i32>> + Send + Unpin {
+    let subscribe_index = self.subscribe_index;
+    let sensor_type = self.sensor_type;
+    Box::pin(
+      convert_broadcast_receiver_to_stream(self.device_event_sender.subscribe())
+        .take_while(|event| {
+          futures::future::ready(!matches!(
+            event,
+            ButtplugClientDeviceEvent::DeviceRemoved | ButtplugClientDeviceEvent::ClientDisconnect
+          ))
+        })
+        .filter_map(move |event| {
+          futures::future::ready(match event {
+            ButtplugClientDeviceEvent::Message(
+              ButtplugCurrentSpecServerMessage::SensorReading(reading),
+            ) if Some(reading.sensor_index()) == subscribe_index
+              && reading.sensor_type() == sensor_type =>
+            {
+              Some(reading.data().clone())
+            }
+            _ => None,
+          })
+        }),
+    )
+  }
+}
+
+/// Builds the [Actuator] handles for a device, in ScalarCmd, RotateCmd, LinearCmd order.
+pub(super) fn device_actuators(
+  device_index: u32,
+  message_attributes: &ClientDeviceMessageAttributes,
+  event_loop_sender: &Arc<ButtplugClientMessageSender>,
+) -> Vec<Actuator> {
+  let mut actuators = vec![];
+  for (message_type, attrs) in [
+    (
+      ButtplugDeviceMessageType::ScalarCmd,
+      message_attributes.scalar_cmd(),
+    ),
+    (
+      ButtplugDeviceMessageType::RotateCmd,
+      message_attributes.rotate_cmd(),
+    ),
+    (
+      ButtplugDeviceMessageType::LinearCmd,
+      message_attributes.linear_cmd(),
+    ),
+  ] {
+    for (index, attr) in attrs.iter().flatten().enumerate() {
+      actuators.push(Actuator::new(
+        device_index,
+        index as u32,
+        message_type,
+        attr,
+        event_loop_sender,
+      ));
+    }
+  }
+  actuators
+}
+
+/// Builds the [Sensor] handles for a device. Sensors are listed separately for reading and
+/// subscribing, so entries with the same type and descriptor are merged into a single handle.
+pub(super) fn device_sensors(
+  device_index: u32,
+  message_attributes: &ClientDeviceMessageAttributes,
+  event_loop_sender: &Arc<ButtplugClientMessageSender>,
+  device_event_sender: &broadcast::Sender<ButtplugClientDeviceEvent>,
+) -> Vec<Sensor> {
+  let mut sensors: Vec<Sensor> = vec![];
+  for (index, attr) in message_attributes
+    .sensor_read_cmd()
+    .iter()
+    .flatten()
+    .enumerate()
+  {
+    let mut sensor = Sensor::new(device_index, attr, event_loop_sender, device_event_sender);
+    sensor.read_index = Some(index as u32);
+    sensors.push(sensor);
+  }
+  for (index, attr) in message_attributes
+    .sensor_subscribe_cmd()
+    .iter()
+    .flatten()
+    .enumerate()
+  {
+    let existing = sensors.iter_mut().find(|sensor| {
+      sensor.subscribe_index.is_none()
+        && sensor.sensor_type == *attr.sensor_type()
+        && sensor.feature_descriptor == *attr.feature_descriptor()
+    });
+    match existing {
+      Some(sensor) => sensor.subscribe_index = Some(index as u32),
+      None => {
+        let mut sensor = Sensor::new(device_index, attr, event_loop_sender, device_event_sender);
+        sensor.subscribe_index = Some(index as u32);
+        sensors.push(sensor);
+      }
+    }
+  }
+  sensors
+}
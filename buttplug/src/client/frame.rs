@@ -0,0 +1,195 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Frame synced command submission, for game engines and other fixed tick loops.
+//!
+//! Game loops tend to recompute device output every frame, which at 60+ frames per second means
+//! sending the same command over and over, or sending commands faster than hardware can take them.
+//! A [FrameScheduler] collects commands submitted during a frame, and sends what's left once per
+//! frame when [FrameScheduler::flush] is called:
+//!
+//! - Only the last command of each [FrameCommand] kind submitted for a device is kept.
+//! - Commands identical to the last one sent to a device are dropped.
+//! - Devices are sent commands at most once per [FrameScheduler::min_interval]. Commands for a
+//!   device that was sent commands too recently stay queued (and can still be replaced) until a
+//!   later flush. Stop commands are never delayed.
+//! - Commands tagged with a tick older than the newest tick submitted are dropped, so commands
+//!   computed late (i.e. on a worker thread) can't overwrite newer ones.
+
+use super::{
+  ButtplugClientDevice,
+  ButtplugClientResultFuture,
+  LinearCommand,
+  RotateCommand,
+  ScalarCommand,
+  ScalarValueCommand,
+};
+use futures::{future, FutureExt};
+use instant::Instant;
+use std::{
+  collections::HashMap,
+  mem::{discriminant, Discriminant},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+/// Default minimum time between commands sent to a device by a [FrameScheduler].
+///
+/// Matches [DEFAULT_RAMP_UPDATE_INTERVAL](super::ramp::DEFAULT_RAMP_UPDATE_INTERVAL), for the same
+/// reasons.
+pub const DEFAULT_FRAME_MIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A command submitted to a [FrameScheduler]. Each variant is sent using the
+/// [ButtplugClientDevice] method of the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameCommand {
+  Vibrate(ScalarValueCommand),
+  Oscillate(ScalarValueCommand),
+  Scalar(ScalarCommand),
+  Linear(LinearCommand),
+  Rotate(RotateCommand),
+  /// Stops the device, replacing any other commands queued for it.
+  Stop,
+}
+
+impl FrameCommand {
+  fn send(&self, device: &ButtplugClientDevice) -> ButtplugClientResultFuture {
+    match self {
+      FrameCommand::Vibrate(cmd) => device.vibrate(cmd),
+      FrameCommand::Oscillate(cmd) => device.oscillate(cmd),
+      FrameCommand::Scalar(cmd) => device.scalar(cmd),
+      FrameCommand::Linear(cmd) => device.linear(cmd),
+      FrameCommand::Rotate(cmd) => device.rotate(cmd),
+      FrameCommand::Stop => device.stop(),
+    }
+  }
+}
+
+/// Replaces the command of the same kind in the list, or adds the command if there isn't one.
+fn set_command(commands: &mut Vec<FrameCommand>, command: FrameCommand) {
+  let kind: Discriminant<FrameCommand> = discriminant(&command);
+  commands.retain(|existing| discriminant(existing) != kind);
+  commands.push(command);
+}
+
+struct DeviceFrameState {
+  device: Arc<ButtplugClientDevice>,
+  pending: Vec<FrameCommand>,
+  sent: Vec<FrameCommand>,
+  last_sent: Option<Instant>,
+}
+
+#[derive(Default)]
+struct FrameSchedulerState {
+  newest_tick: Option<u64>,
+  devices: HashMap<u32, DeviceFrameState>,
+}
+
+/// Coalesces and rate limits device commands submitted by a game loop. See the
+/// [module documentation](self) for details.
+pub struct FrameScheduler {
+  min_interval: Duration,
+  state: Mutex<FrameSchedulerState>,
+}
+
+impl Default for FrameScheduler {
+  fn default() -> Self {
+    Self::new(DEFAULT_FRAME_MIN_INTERVAL)
+  }
+}
+
+impl FrameScheduler {
+  pub fn new(min_interval: Duration) -> Self {
+    Self {
+      min_interval,
+      state: Mutex::new(FrameSchedulerState::default()),
+    }
+  }
+
+  /// Minimum time between commands sent to a device.
+  pub fn min_interval(&self) -> Duration {
+    self.min_interval
+  }
+
+  /// Queues a command for a device, for the frame with the given tick. Ticks can be any increasing
+  /// number (frame count, game time in milliseconds, etc). Returns false if the command was
+  /// dropped because a newer tick has already been submitted.
+  pub fn submit(
+    &self,
+    tick: u64,
+    device: &Arc<ButtplugClientDevice>,
+    command: FrameCommand,
+  ) -> bool {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Never poisoned, no panics while held");
+    if state.newest_tick.is_some_and(|newest| tick < newest) {
+      return false;
+    }
+    state.newest_tick = Some(tick);
+    let device_state = state
+      .devices
+      .entry(device.index())
+      .or_insert_with(|| DeviceFrameState {
+        device: device.clone(),
+        pending: vec![],
+        sent: vec![],
+        last_sent: None,
+      });
+    if command == FrameCommand::Stop {
+      device_state.pending.clear();
+    }
+    set_command(&mut device_state.pending, command);
+    true
+  }
+
+  /// Sends the queued commands that are due. Call once per frame, after submitting the frame's
+  /// commands. Commands are sent when the returned future is polled, which resolves once the
+  /// server has answered all of them, so game loops that can't wait on it should hand it off to
+  /// their runtime.
+  pub fn flush(&self) -> ButtplugClientResultFuture {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Never poisoned, no panics while held");
+    // Forget devices that went away, the client will hand out a new device object if they return.
+    state
+      .devices
+      .retain(|_, device_state| device_state.device.connected());
+    let now = Instant::now();
+    let mut sends = vec![];
+    for device_state in state.devices.values_mut() {
+      if device_state.pending.is_empty() {
+        continue;
+      }
+      let rate_limited = device_state
+        .last_sent
+        .is_some_and(|last_sent| now.duration_since(last_sent) < self.min_interval);
+      if rate_limited && !device_state.pending.contains(&FrameCommand::Stop) {
+        continue;
+      }
+      let mut sent_any = false;
+      for command in device_state.pending.drain(..) {
+        if command == FrameCommand::Stop {
+          // Stopping resets every output, so nothing sent before counts as redundant anymore.
+          device_state.sent.clear();
+        } else if device_state.sent.contains(&command) {
+          continue;
+        } else {
+          set_command(&mut device_state.sent, command.clone());
+        }
+        sends.push(command.send(&device_state.device));
+        sent_any = true;
+      }
+      if sent_any {
+        device_state.last_sent = Some(now);
+      }
+    }
+    async move { future::try_join_all(sends).await.map(|_| ()) }.boxed()
+  }
+}
@@ -6,30 +6,84 @@
 // for full license information.
 
 //! Communications API for accessing Buttplug Servers
+//!
+//! # Message Ordering
+//!
+//! Messages go out to the server in the order their futures are first polled, usually the order
+//! they're awaited in. Replies come back as soon as the server has them, which isn't necessarily
+//! in that order: a [RawReadCmd](crate::core::message::RawReadCmd) waiting on slow hardware, or a
+//! [StartScanning](crate::core::message::StartScanning) waiting on device managers, doesn't hold up
+//! the replies to anything sent after it. What the server guarantees beyond that depends on the
+//! message:
+//!
+//! - Output commands to the same device ([ScalarCmd](crate::core::message::ScalarCmd),
+//!   [LinearCmd](crate::core::message::LinearCmd), [RotateCmd](crate::core::message::RotateCmd),
+//!   etc) run on the hardware in the order they were sent.
+//! - [StopDeviceCmd](crate::core::message::StopDeviceCmd) and
+//!   [StopAllDevices](crate::core::message::StopAllDevices) run ahead of any output commands still
+//!   queued for their devices. Commands sent after the stop run after it.
+//! - Raw commands, sensor reads and subscriptions go straight to the hardware, and aren't ordered
+//!   against output commands or each other.
+//! - Commands to different devices aren't ordered against each other.
+//! - Other messages (Ping, RequestDeviceList, scanning, etc) are answered independently of device
+//!   commands.
+//! - Events ([ButtplugClientEvent]s and device events) arrive in the order the server sends them,
+//!   but aren't ordered against replies.
+//!
+//! If the connection drops, everything still waiting on a reply fails with
+//! [ButtplugConnectorError::ConnectorNotConnected].
+//!
+//! # Cancellation
+//!
+//! If the server agrees to the
+//! [Cancellation](crate::core::message::ButtplugCapability::Cancellation) capability (requested by
+//! default), dropping the future for a device command before its reply arrives cancels the command
+//! on the server. If it's still queued behind slower commands, i.e. while a Bluetooth connection
+//! stalls, it never reaches the device, instead of running seconds after the application stopped
+//! caring about it. Commands that were already being written still run.
+//! [ButtplugClientDevice::cancel_commands] does the same for every command waiting on a device.
+//! Stop commands are never cancelled.
+#[cfg(feature = "blocking-client")]
+pub mod blocking;
 pub mod client_event_loop;
 pub mod client_message_sorter;
 pub mod device;
+pub mod device_group;
+pub mod feature;
+pub mod frame;
+pub mod ramp;
+pub mod raw_stream;
+pub mod vibration_pattern;
 
 use crate::{
   core::{
     connector::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorFuture},
     errors::{ButtplugError, ButtplugHandshakeError},
     message::{
+      ButtplugCapability,
       ButtplugCurrentSpecClientMessage,
       ButtplugCurrentSpecServerMessage,
+      EndSessionCmd,
+      ForceStopAllDevicesCmd,
+      IntensityCeilingCmd,
+      KickClientCmd,
       Ping,
       RequestDeviceList,
       RequestServerInfo,
+      ScanningEnabledCmd,
+      ServerShutdownCmd,
       StartScanning,
       StopAllDevices,
       StopScanning,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+      BUTTPLUG_DEFAULT_CAPABILITIES,
     },
   },
   util::{
     async_manager,
     future::{ButtplugFuture, ButtplugFutureStateShared},
     stream::convert_broadcast_receiver_to_stream,
+    unix_time_millis,
   },
 };
 use client_event_loop::{ButtplugClientEventLoop, ButtplugClientRequest};
@@ -42,17 +96,26 @@ pub use device::{
   ScalarCommand,
   ScalarValueCommand,
 };
+pub use device_group::DeviceGroup;
+pub use feature::{Actuator, Sensor};
+pub use frame::{FrameCommand, FrameScheduler};
 use futures::{
   future::{self, BoxFuture, FutureExt},
   Stream,
 };
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
+pub use ramp::{Easing, Ramp};
+pub use raw_stream::RawEndpointStream;
+use std::{
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
 };
 use thiserror::Error;
 use tokio::sync::{broadcast, mpsc, Mutex};
 use tracing_futures::Instrument;
+pub use vibration_pattern::{VibrationPattern, VibrationPatternBuilder};
 
 /// Result type used for public APIs.
 ///
@@ -156,22 +219,59 @@ where
   future::ready(Err(ButtplugClientError::ButtplugError(err))).boxed()
 }
 
+/// Asks the event loop to cancel a message if the future waiting on it is dropped before the reply
+/// arrives.
+struct CancelOnDrop {
+  message_sender: broadcast::Sender<ButtplugClientRequest>,
+  state: Option<ButtplugServerMessageStateShared>,
+}
+
+impl CancelOnDrop {
+  /// Call once the reply has arrived, as there's nothing left to cancel.
+  fn disarm(mut self) {
+    self.state = None;
+  }
+}
+
+impl Drop for CancelOnDrop {
+  fn drop(&mut self) {
+    if let Some(state) = self.state.take() {
+      // If the event loop is gone, so is the message.
+      let _ = self
+        .message_sender
+        .send(ButtplugClientRequest::CancelMessage(state));
+    }
+  }
+}
+
 pub(super) struct ButtplugClientMessageSender {
   message_sender: broadcast::Sender<ButtplugClientRequest>,
   connected: Arc<AtomicBool>,
+  capabilities: Arc<std::sync::Mutex<Vec<ButtplugCapability>>>,
 }
 
 impl ButtplugClientMessageSender {
   fn new(
     message_sender: &broadcast::Sender<ButtplugClientRequest>,
     connected: &Arc<AtomicBool>,
+    capabilities: &Arc<std::sync::Mutex<Vec<ButtplugCapability>>>,
   ) -> Self {
     Self {
       message_sender: message_sender.clone(),
       connected: connected.clone(),
+      capabilities: capabilities.clone(),
     }
   }
 
+  /// True if the server agreed to the capability during the last handshake.
+  pub fn has_capability(&self, capability: ButtplugCapability) -> bool {
+    self
+      .capabilities
+      .lock()
+      .expect("Lock is never poisoned")
+      .contains(&capability)
+  }
+
   /// Send message to the internal event loop.
   ///
   /// Mostly for handling boilerplate around possible send errors.
@@ -212,22 +312,31 @@ impl ButtplugClientMessageSender {
 
   /// Sends a ButtplugMessage from client to server. Expects to receive a
   /// ButtplugMessage back from the server.
+  ///
+  /// Dropping the returned future after the message has been sent cancels it, if it's a device
+  /// command and the server supports cancellation.
   pub fn send_message_ignore_connect_status(
     &self,
     msg: ButtplugCurrentSpecClientMessage,
   ) -> ButtplugServerMessageResultFuture {
     // Create a future to pair with the message being resolved.
     let fut = ButtplugServerMessageFuture::default();
-    let internal_msg = ButtplugClientRequest::Message(ButtplugClientMessageFuturePair::new(
-      msg,
-      fut.get_state_clone(),
-    ));
+    let state = fut.get_state_clone();
+    let internal_msg =
+      ButtplugClientRequest::Message(ButtplugClientMessageFuturePair::new(msg, state.clone()));
 
     // Send message to internal loop and wait for return.
     let send_fut = self.send_message_to_event_loop(internal_msg);
+    let message_sender = self.message_sender.clone();
     async move {
       send_fut.await?;
-      fut.await
+      let cancel_on_drop = CancelOnDrop {
+        message_sender,
+        state: Some(state),
+      };
+      let reply = fut.await;
+      cancel_on_drop.disarm();
+      reply
     }
     .boxed()
   }
@@ -265,6 +374,21 @@ pub struct ButtplugClient {
   client_name: String,
   /// The server name that we're current connected to.
   server_name: Arc<Mutex<Option<String>>>,
+  /// Ping time to request from the server during the handshake. If None, the server default is
+  /// used.
+  requested_max_ping_time: Option<u32>,
+  /// Ping time the server is using for the current connection, 0 if there's no ping timeout.
+  max_ping_time: Arc<AtomicU32>,
+  /// Token from the last handshake, sent when reconnecting so the server resumes our session
+  /// instead of starting a new one.
+  resumption_token: Arc<Mutex<Option<String>>>,
+  /// Capabilities to request from the server during the handshake.
+  requested_capabilities: Vec<ButtplugCapability>,
+  /// Capabilities the server agreed to during the handshake.
+  capabilities: Arc<std::sync::Mutex<Vec<ButtplugCapability>>>,
+  /// Difference between the server clock and ours in milliseconds, estimated during the
+  /// handshake. None if the server didn't send its time.
+  server_time_offset: Arc<std::sync::Mutex<Option<i64>>>,
   event_stream: broadcast::Sender<ButtplugClientEvent>,
   // Sender to relay messages to the internal client loop
   message_sender: Arc<ButtplugClientMessageSender>,
@@ -274,22 +398,48 @@ pub struct ButtplugClient {
 
 impl ButtplugClient {
   pub fn new(name: &str) -> Self {
+    Self::new_with_options(name, None)
+  }
+
+  /// Creates a client that requests the given ping time, in milliseconds, from the server when
+  /// connecting. 0 requests no ping timeout. The server may limit the ping time, see
+  /// [ButtplugClient::max_ping_time] for the ping time actually used once connected.
+  pub fn new_with_max_ping_time(name: &str, max_ping_time: u32) -> Self {
+    Self::new_with_options(name, Some(max_ping_time))
+  }
+
+  fn new_with_options(name: &str, requested_max_ping_time: Option<u32>) -> Self {
     let (message_sender, _) = broadcast::channel(256);
     let (event_stream, _) = broadcast::channel(256);
     let connected = Arc::new(AtomicBool::new(false));
+    let capabilities = Arc::new(std::sync::Mutex::new(vec![]));
     Self {
       client_name: name.to_owned(),
       server_name: Arc::new(Mutex::new(None)),
+      requested_max_ping_time,
+      max_ping_time: Arc::new(AtomicU32::new(0)),
+      resumption_token: Arc::new(Mutex::new(None)),
+      requested_capabilities: BUTTPLUG_DEFAULT_CAPABILITIES.to_vec(),
+      server_time_offset: Arc::new(std::sync::Mutex::new(None)),
       event_stream,
       message_sender: Arc::new(ButtplugClientMessageSender::new(
         &message_sender,
         &connected,
+        &capabilities,
       )),
+      capabilities,
       connected,
       device_map: Arc::new(DashMap::new()),
     }
   }
 
+  /// Set the capabilities to request from the server when connecting, replacing the
+  /// [defaults](crate::core::message::BUTTPLUG_DEFAULT_CAPABILITIES). See
+  /// [ButtplugClient::capabilities] for the ones the server agreed to once connected.
+  pub fn request_capabilities(&mut self, capabilities: &[ButtplugCapability]) {
+    self.requested_capabilities = capabilities.to_vec();
+  }
+
   pub async fn connect<ConnectorType>(
     &self,
     mut connector: ConnectorType,
@@ -342,17 +492,42 @@ impl ButtplugClient {
   async fn run_handshake(&self) -> ButtplugClientResult {
     // Run our handshake
     info!("Running handshake with server.");
+    let mut request = if let Some(max_ping_time) = self.requested_max_ping_time {
+      RequestServerInfo::new_with_max_ping_time(
+        &self.client_name,
+        BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+        max_ping_time,
+      )
+    } else {
+      RequestServerInfo::new(&self.client_name, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+    };
+    // If we got dropped without disconnecting, try to pick our session back up.
+    request.set_resumption_token(self.resumption_token.lock().await.clone());
+    request.set_capabilities(self.requested_capabilities.clone());
+    let request_time = unix_time_millis();
     let msg = self
       .message_sender
-      .send_message_ignore_connect_status(
-        RequestServerInfo::new(&self.client_name, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
-      )
+      .send_message_ignore_connect_status(request.into())
       .await?;
+    let response_time = unix_time_millis();
 
     debug!("Got ServerInfo return.");
     if let ButtplugCurrentSpecServerMessage::ServerInfo(server_info) = msg {
       info!("Connected to {}", server_info.server_name());
       *self.server_name.lock().await = Some(server_info.server_name().clone());
+      self
+        .max_ping_time
+        .store(server_info.max_ping_time(), Ordering::SeqCst);
+      *self.resumption_token.lock().await = server_info.resumption_token().clone();
+      *self.capabilities.lock().expect("Lock is never poisoned") =
+        server_info.capabilities().clone();
+      // Assume the server read its clock halfway through the round trip.
+      *self
+        .server_time_offset
+        .lock()
+        .expect("Lock is never poisoned") = server_info.server_time().map(|server_time| {
+        server_time as i64 - (request_time + (response_time - request_time) / 2) as i64
+      });
       // Don't set ourselves as connected until after ServerInfo has been
       // received. This means we avoid possible races with the RequestServerInfo
       // handshake.
@@ -400,7 +575,17 @@ impl ButtplugClient {
     let msg = ButtplugClientRequest::Disconnect(fut.get_state_clone());
     let send_fut = self.message_sender.send_message_to_event_loop(msg);
     let connected = self.connected.clone();
+    let resumption_token = self.resumption_token.clone();
+    let message_sender = self.message_sender.clone();
     async move {
+      // A server that gave us a resumption token will hold our session for a while after we go
+      // away, which isn't what anyone disconnecting on purpose wants. Ending the session has the
+      // server clean it up as soon as the connection closes, without touching other sessions.
+      if resumption_token.lock().await.take().is_some() {
+        let _ = message_sender
+          .send_message(EndSessionCmd::default().into())
+          .await;
+      }
       connected.store(false, Ordering::SeqCst);
       send_fut.await?;
       Ok(())
@@ -418,6 +603,21 @@ impl ButtplugClient {
       .send_message_expect_ok(StartScanning::default().into())
   }
 
+  /// Tells server to scan for devices, stopping automatically after `timeout`. A
+  /// [ButtplugClientEvent::ScanningFinished] event is emitted when scanning stops.
+  ///
+  /// Returns Err([ButtplugClientError]) if request fails due to issues with
+  /// DeviceManagers on the server, disconnection, etc.
+  pub fn start_scanning_with_timeout(&self, timeout: Duration) -> ButtplugClientResultFuture {
+    // Timeouts are sent in milliseconds, where 0 means no timeout, so round up to at least 1ms.
+    let timeout = u32::try_from(timeout.as_millis())
+      .unwrap_or(u32::MAX)
+      .max(1);
+    self
+      .message_sender
+      .send_message_expect_ok(StartScanning::new_with_timeout(timeout).into())
+  }
+
   /// Tells server to stop scanning for devices.
   ///
   /// Returns Err([ButtplugClientError]) if request fails due to issues with
@@ -438,6 +638,50 @@ impl ButtplugClient {
       .send_message_expect_ok(StopAllDevices::default().into())
   }
 
+  /// Caps the intensity of every actuator on the server to `ceiling` (0.0-1.0), for commands from
+  /// all clients, until the server shuts down or this is called again. 1.0 removes the cap. Needs
+  /// an admin connection, so a partner connected with less access can't raise it, and raising it
+  /// also needs the server to allow server management.
+  pub fn set_intensity_ceiling(&self, ceiling: f64) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(IntensityCeilingCmd::new(ceiling).into())
+  }
+
+  /// Stops every device on the server, along with scheduled commands and playback started by any
+  /// client, unlike [ButtplugClient::stop_all_devices], which leaves other clients' playback
+  /// running. Needs an admin connection to a server that allows server management.
+  pub fn force_stop_all_devices(&self) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(ForceStopAllDevicesCmd::default().into())
+  }
+
+  /// Disconnects the client of another server session. Needs an admin connection to a server that
+  /// allows server management.
+  pub fn kick_client(&self, session_id: u32) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(KickClientCmd::new(session_id).into())
+  }
+
+  /// Allows or refuses device scanning for every client. Disabling scanning stops any scan in
+  /// progress. Needs an admin connection to a server that allows server management.
+  pub fn set_scanning_enabled(&self, enabled: bool) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(ScanningEnabledCmd::new(enabled).into())
+  }
+
+  /// Asks the server to shut down. Devices are stopped and other clients are disconnected right
+  /// away, the rest is up to the process hosting the server, which should close this connection
+  /// too. Needs an admin connection to a server that allows server management.
+  pub fn request_server_shutdown(&self) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(ServerShutdownCmd::default().into())
+  }
+
   pub fn event_stream(&self) -> impl Stream<Item = ButtplugClientEvent> {
     let stream = convert_broadcast_receiver_to_stream(self.event_stream.subscribe());
     // We can either Box::pin here or force the user to pin_mut!() on their
@@ -464,6 +708,42 @@ impl ButtplugClient {
     async move { ping_fut.await }.boxed()
   }
 
+  /// Ping time the server is using for this connection, in milliseconds. If this is not 0, the
+  /// client needs to call [ButtplugClient::ping] at least this often to stay connected.
+  pub fn max_ping_time(&self) -> u32 {
+    self.max_ping_time.load(Ordering::SeqCst)
+  }
+
+  /// Current time on the server clock, in milliseconds since the Unix epoch, based on the clock
+  /// offset estimated during the last handshake. Use this to work out timestamps for scheduled
+  /// device commands. None if the server didn't send its time.
+  pub fn server_time(&self) -> Option<u64> {
+    self
+      .server_time_offset
+      .lock()
+      .expect("Lock is never poisoned")
+      .map(|offset| (unix_time_millis() as i64 + offset).max(0) as u64)
+  }
+
+  /// Capabilities the server agreed to during the last handshake. Servers that don't know about
+  /// capabilities never agree to any.
+  pub fn capabilities(&self) -> Vec<ButtplugCapability> {
+    self
+      .capabilities
+      .lock()
+      .expect("Lock is never poisoned")
+      .clone()
+  }
+
+  /// True if the server agreed to the capability during the last handshake.
+  pub fn has_capability(&self, capability: ButtplugCapability) -> bool {
+    self
+      .capabilities
+      .lock()
+      .expect("Lock is never poisoned")
+      .contains(&capability)
+  }
+
   pub fn server_name(&self) -> Option<String> {
     // We'd have to be calling server_name in an extremely tight, asynchronous
     // loop for this to return None, so we'll treat this as lockless.
@@ -0,0 +1,149 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Easing curves and timing for ramped client device commands.
+//!
+//! Applications commonly want to move a device from one value to another over a period of time
+//! (fading a vibrator up, slowly winding down an oscillator, etc...), which requires running a timed
+//! update loop on the client side. The types in this module describe those ramps, and are consumed
+//! by the ramp methods on [ButtplugClientDevice](super::ButtplugClientDevice).
+
+use std::time::Duration;
+
+/// Default time between value updates sent during a ramp.
+///
+/// Most hardware can't take updates much faster than this, and BLE connection intervals mean we'd
+/// just be filling queues anyways.
+pub const DEFAULT_RAMP_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Curve used to interpolate between the start and end values of a ramp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+  /// Constant rate of change.
+  #[default]
+  Linear,
+  /// Starts slow, speeds up toward the end (quadratic).
+  EaseIn,
+  /// Starts fast, slows down toward the end (quadratic).
+  EaseOut,
+  /// Starts slow, speeds up through the middle, slows down toward the end (quadratic).
+  EaseInOut,
+}
+
+impl Easing {
+  /// Map a linear progress value (0.0-1.0) to an eased progress value (0.0-1.0). Values outside of
+  /// the 0.0-1.0 range are clamped.
+  pub fn apply(&self, progress: f64) -> f64 {
+    let t = progress.clamp(0.0, 1.0);
+    match self {
+      Easing::Linear => t,
+      Easing::EaseIn => t * t,
+      Easing::EaseOut => t * (2.0 - t),
+      Easing::EaseInOut => {
+        if t < 0.5 {
+          2.0 * t * t
+        } else {
+          -1.0 + (4.0 - 2.0 * t) * t
+        }
+      }
+    }
+  }
+}
+
+/// Description of a value change over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ramp {
+  from: f64,
+  to: f64,
+  duration: Duration,
+  easing: Easing,
+  update_interval: Duration,
+}
+
+impl Ramp {
+  pub fn new(from: f64, to: f64, duration: Duration, easing: Easing) -> Self {
+    Self {
+      from,
+      to,
+      duration,
+      easing,
+      update_interval: DEFAULT_RAMP_UPDATE_INTERVAL,
+    }
+  }
+
+  /// Set the time between value updates. Zero durations will be treated as the default interval.
+  pub fn with_update_interval(mut self, interval: Duration) -> Self {
+    self.update_interval = if interval.is_zero() {
+      DEFAULT_RAMP_UPDATE_INTERVAL
+    } else {
+      interval
+    };
+    self
+  }
+
+  pub fn from(&self) -> f64 {
+    self.from
+  }
+
+  pub fn to(&self) -> f64 {
+    self.to
+  }
+
+  pub fn duration(&self) -> Duration {
+    self.duration
+  }
+
+  pub fn easing(&self) -> Easing {
+    self.easing
+  }
+
+  pub fn update_interval(&self) -> Duration {
+    self.update_interval
+  }
+
+  /// Value of the ramp at a certain amount of time after it has started.
+  pub fn value_at(&self, elapsed: Duration) -> f64 {
+    if self.duration.is_zero() || elapsed >= self.duration {
+      return self.to;
+    }
+    let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+    self.from + (self.to - self.from) * self.easing.apply(progress)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_easing_endpoints() {
+    for easing in [
+      Easing::Linear,
+      Easing::EaseIn,
+      Easing::EaseOut,
+      Easing::EaseInOut,
+    ] {
+      assert_eq!(easing.apply(0.0), 0.0);
+      assert_eq!(easing.apply(1.0), 1.0);
+      assert_eq!(easing.apply(-1.0), 0.0);
+      assert_eq!(easing.apply(2.0), 1.0);
+    }
+    assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    assert!(Easing::EaseIn.apply(0.5) < 0.5);
+    assert!(Easing::EaseOut.apply(0.5) > 0.5);
+  }
+
+  #[test]
+  fn test_ramp_values() {
+    let ramp = Ramp::new(1.0, 0.0, Duration::from_secs(1), Easing::Linear);
+    assert_eq!(ramp.value_at(Duration::ZERO), 1.0);
+    assert_eq!(ramp.value_at(Duration::from_millis(500)), 0.5);
+    assert_eq!(ramp.value_at(Duration::from_secs(5)), 0.0);
+    let instant = Ramp::new(0.0, 1.0, Duration::ZERO, Easing::EaseIn);
+    assert_eq!(instant.value_at(Duration::ZERO), 1.0);
+  }
+}
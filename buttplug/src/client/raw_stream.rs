@@ -0,0 +1,127 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Streams of raw endpoint readings, for experimenting with custom hardware.
+
+use super::{device::ButtplugClientDeviceEvent, ButtplugClientMessageSender};
+use crate::{
+  core::message::{ButtplugCurrentSpecServerMessage, Endpoint, RawUnsubscribeCmd},
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+};
+use futures::{future, Stream, StreamExt};
+use std::{
+  collections::HashMap,
+  pin::Pin,
+  sync::{Arc, Mutex},
+  task::{Context, Poll},
+};
+use tokio::sync::broadcast;
+
+/// Number of live [RawEndpointStream]s per endpoint of a device. The endpoint is only unsubscribed
+/// once the last stream for it is dropped.
+pub(super) type RawSubscriptionCounts = Arc<Mutex<HashMap<Endpoint, u32>>>;
+
+/// Data received from a subscribed device endpoint, created by
+/// [ButtplugClientDevice::subscribe_endpoint](super::ButtplugClientDevice::subscribe_endpoint).
+///
+/// Yields the data of each notification the endpoint sends. The stream ends when the device or
+/// client disconnects. Dropping the last stream for an endpoint unsubscribes from it.
+pub struct RawEndpointStream {
+  device_index: u32,
+  endpoint: Endpoint,
+  stream: Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>,
+  event_loop_sender: Arc<ButtplugClientMessageSender>,
+  subscriptions: RawSubscriptionCounts,
+  unsubscribe_on_drop: bool,
+}
+
+impl RawEndpointStream {
+  pub(super) fn new(
+    device_index: u32,
+    endpoint: Endpoint,
+    receiver: broadcast::Receiver<ButtplugClientDeviceEvent>,
+    event_loop_sender: &Arc<ButtplugClientMessageSender>,
+    subscriptions: &RawSubscriptionCounts,
+  ) -> Self {
+    let stream = convert_broadcast_receiver_to_stream(receiver)
+      .take_while(|event| {
+        future::ready(!matches!(
+          event,
+          ButtplugClientDeviceEvent::DeviceRemoved | ButtplugClientDeviceEvent::ClientDisconnect
+        ))
+      })
+      .filter_map(move |event| {
+        future::ready(match event {
+          ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::RawReading(
+            reading,
+          )) if reading.endpoint() == endpoint => Some(reading.data().clone()),
+          _ => None,
+        })
+      });
+    Self {
+      device_index,
+      endpoint,
+      stream: Box::pin(stream),
+      event_loop_sender: event_loop_sender.clone(),
+      subscriptions: subscriptions.clone(),
+      unsubscribe_on_drop: true,
+    }
+  }
+
+  pub fn endpoint(&self) -> Endpoint {
+    self.endpoint
+  }
+
+  /// Keeps the stream from unsubscribing when dropped, for streams whose subscription failed.
+  pub(super) fn forget_subscription(&mut self) {
+    self.unsubscribe_on_drop = false;
+  }
+}
+
+impl Stream for RawEndpointStream {
+  type Item = Vec<u8>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.stream.as_mut().poll_next(cx)
+  }
+}
+
+impl Drop for RawEndpointStream {
+  fn drop(&mut self) {
+    let last = {
+      let mut subscriptions = self
+        .subscriptions
+        .lock()
+        .expect("Never poisoned, no panics while held");
+      let count = subscriptions.entry(self.endpoint).or_insert(1);
+      *count -= 1;
+      if *count == 0 {
+        subscriptions.remove(&self.endpoint);
+        true
+      } else {
+        false
+      }
+    };
+    if !last || !self.unsubscribe_on_drop {
+      return;
+    }
+    let unsubscribe = self
+      .event_loop_sender
+      .send_message_expect_ok(RawUnsubscribeCmd::new(self.device_index, self.endpoint).into());
+    let device_index = self.device_index;
+    let endpoint = self.endpoint;
+    async_manager::spawn(async move {
+      if let Err(err) = unsubscribe.await {
+        // Usually means the device or client is already gone, so there's nothing to unsubscribe.
+        debug!(
+          "Could not unsubscribe from endpoint {} on device {}: {:?}",
+          endpoint, device_index, err
+        );
+      }
+    });
+  }
+}
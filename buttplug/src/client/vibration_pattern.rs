@@ -0,0 +1,461 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Builder for timed multi-actuator patterns.
+//!
+//! Writing [pattern files](crate::util::pattern) by hand means working out keyframe times for every
+//! actuator. [VibrationPatternBuilder] keeps a time cursor instead: levels are set at the cursor,
+//! and [hold](VibrationPatternBuilder::hold) and [ramp](VibrationPatternBuilder::ramp) move it
+//! forward.
+//!
+//! ```no_run
+//! # use buttplug::client::{ButtplugClientDevice, ButtplugClientError, Easing, Ramp, VibrationPatternBuilder};
+//! # use buttplug::core::message::ActuatorType;
+//! # use std::{sync::Arc, time::Duration};
+//! # async fn run(device: &Arc<ButtplugClientDevice>) -> Result<(), ButtplugClientError> {
+//! let pattern = VibrationPatternBuilder::default()
+//!   .section("beat")
+//!   .set(ActuatorType::Vibrate, 0, 1.0)
+//!   .hold(Duration::from_millis(150))
+//!   .set(ActuatorType::Vibrate, 0, 0.0)
+//!   .hold(Duration::from_millis(350))
+//!   .repeat(3)
+//!   .section("fade")
+//!   .ramp(
+//!     ActuatorType::Vibrate,
+//!     0,
+//!     &Ramp::new(1.0, 0.0, Duration::from_secs(2), Easing::EaseOut),
+//!   )
+//!   .build()
+//!   .expect("Pattern has sections and valid levels");
+//! pattern.play(device).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Built patterns are [Pattern]s, so they can be played by the server's pattern engine, or streamed
+//! from the client as [ScalarCmd](crate::core::message::ScalarCmd) commands for servers that don't
+//! have one.
+
+use super::{
+  create_boxed_future_client_error,
+  ramp::Ramp,
+  ButtplugClientDevice,
+  ButtplugClientError,
+  ButtplugClientResultFuture,
+  ScalarCommand,
+};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{ActuatorType, ButtplugDeviceMessageType},
+  },
+  util::{
+    pattern::{Pattern, PatternError, PatternKeyframe, PatternSection, PatternTrack},
+    sleep,
+  },
+};
+use futures::FutureExt;
+use instant::Instant;
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::Arc,
+  time::Duration,
+};
+
+fn duration_ms(duration: Duration) -> u32 {
+  duration.as_millis().min(u32::MAX as u128) as u32
+}
+
+#[derive(Default)]
+struct SectionDraft {
+  label: Option<String>,
+  repeat: u32,
+  cursor: u32,
+  tracks: Vec<((ActuatorType, u32), Vec<PatternKeyframe>)>,
+}
+
+impl SectionDraft {
+  fn new(label: Option<&str>) -> Self {
+    Self {
+      label: label.map(|x| x.to_owned()),
+      repeat: 1,
+      ..Default::default()
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.cursor == 0 && self.tracks.is_empty()
+  }
+
+  fn set_at(&mut self, actuator: ActuatorType, index: u32, at: u32, value: f64) {
+    let keyframes = match self
+      .tracks
+      .iter_mut()
+      .find(|(key, _)| *key == (actuator, index))
+    {
+      Some((_, keyframes)) => keyframes,
+      None => {
+        self.tracks.push(((actuator, index), vec![]));
+        &mut self.tracks.last_mut().expect("Just pushed").1
+      }
+    };
+    match keyframes.last_mut() {
+      Some(last) if last.at == at => last.value = value,
+      _ => keyframes.push(PatternKeyframe { at, value }),
+    }
+  }
+
+  fn finish(&self, position: usize) -> PatternSection {
+    let label = self
+      .label
+      .clone()
+      .unwrap_or_else(|| format!("section-{}", position));
+    let tracks: Vec<PatternTrack> = self
+      .tracks
+      .iter()
+      .map(|((actuator, index), keyframes)| PatternTrack::new(*actuator, *index, keyframes))
+      .collect();
+    let section = PatternSection::new(&label, &tracks).with_repeat(self.repeat);
+    if self.cursor > 0 {
+      section.with_duration(self.cursor)
+    } else {
+      section
+    }
+  }
+}
+
+/// Fluent builder for [VibrationPattern]s. See the [module documentation](self) for an example.
+///
+/// Actuators are addressed the same way as pattern file tracks, by [ActuatorType] and the index of
+/// the actuator among actuators of that type on the device.
+pub struct VibrationPatternBuilder {
+  name: Option<String>,
+  looping: bool,
+  sections: Vec<SectionDraft>,
+}
+
+impl Default for VibrationPatternBuilder {
+  fn default() -> Self {
+    Self {
+      name: None,
+      looping: false,
+      sections: vec![SectionDraft::new(None)],
+    }
+  }
+}
+
+impl VibrationPatternBuilder {
+  fn current(&mut self) -> &mut SectionDraft {
+    self
+      .sections
+      .last_mut()
+      .expect("Always have at least one section")
+  }
+
+  pub fn name(&mut self, name: &str) -> &mut Self {
+    self.name = Some(name.to_owned());
+    self
+  }
+
+  /// If true, playback restarts from the first section after the last section finishes.
+  pub fn looping(&mut self, looping: bool) -> &mut Self {
+    self.looping = looping;
+    self
+  }
+
+  /// Starts a new labeled section, with its time cursor at 0. Steps added before the first call
+  /// to this go into an unlabeled section.
+  pub fn section(&mut self, label: &str) -> &mut Self {
+    if self.current().is_empty() {
+      self.current().label = Some(label.to_owned());
+    } else {
+      self.sections.push(SectionDraft::new(Some(label)));
+    }
+    self
+  }
+
+  /// Sets how many times the current section plays before moving on to the next one.
+  pub fn repeat(&mut self, repeat: u32) -> &mut Self {
+    self.current().repeat = repeat;
+    self
+  }
+
+  /// Sets an actuator to a level (0.0-1.0) at the current time. Levels are held until they're set
+  /// again.
+  pub fn set(&mut self, actuator: ActuatorType, index: u32, level: f64) -> &mut Self {
+    let section = self.current();
+    let at = section.cursor;
+    section.set_at(actuator, index, at, level);
+    self
+  }
+
+  /// Moves the time cursor forward, holding all current levels.
+  pub fn hold(&mut self, duration: Duration) -> &mut Self {
+    let section = self.current();
+    section.cursor = section.cursor.saturating_add(duration_ms(duration));
+    self
+  }
+
+  /// Sets levels for multiple actuators, then holds them for the duration.
+  pub fn step(&mut self, duration: Duration, levels: &[(ActuatorType, u32, f64)]) -> &mut Self {
+    for (actuator, index, level) in levels {
+      self.set(*actuator, *index, *level);
+    }
+    self.hold(duration)
+  }
+
+  /// Ramps an actuator over the duration of the ramp, sampled every
+  /// [Ramp::update_interval], and moves the time cursor to the end of the ramp. Other actuators
+  /// hold their levels.
+  pub fn ramp(&mut self, actuator: ActuatorType, index: u32, ramp: &Ramp) -> &mut Self {
+    let section = self.current();
+    let start = section.cursor;
+    let duration = duration_ms(ramp.duration());
+    let interval = duration_ms(ramp.update_interval()).max(1);
+    let mut offset = 0;
+    while offset < duration {
+      let value = ramp.value_at(Duration::from_millis(offset as u64));
+      section.set_at(actuator, index, start.saturating_add(offset), value);
+      offset = offset.saturating_add(interval);
+    }
+    section.set_at(actuator, index, start.saturating_add(duration), ramp.to());
+    section.cursor = start.saturating_add(duration);
+    self
+  }
+
+  /// Builds the pattern, failing if it has no steps or has levels outside of 0.0-1.0.
+  pub fn build(&self) -> Result<VibrationPattern, PatternError> {
+    let sections: Vec<PatternSection> = self
+      .sections
+      .iter()
+      .filter(|x| !x.is_empty())
+      .enumerate()
+      .map(|(position, section)| section.finish(position))
+      .collect();
+    let pattern = Pattern::new(self.name.as_deref(), self.looping, &sections);
+    pattern.validate()?;
+    Ok(VibrationPattern { pattern })
+  }
+}
+
+/// Levels for a device's scalar features, in [ScalarCommand::ScalarMap] form.
+type ScalarLevels = HashMap<u32, (f64, ActuatorType)>;
+
+/// Section with tracks mapped to a device's scalar features, for streaming from the client.
+struct StreamedSection {
+  duration: u32,
+  repeat: u32,
+  steps: Vec<(u32, ScalarLevels)>,
+}
+
+/// Timed multi-actuator pattern, built with [VibrationPatternBuilder].
+#[derive(Debug, Clone, PartialEq)]
+pub struct VibrationPattern {
+  pattern: Pattern,
+}
+
+impl VibrationPattern {
+  /// The pattern in [pattern file](crate::util::pattern) form, as sent to the server's pattern
+  /// engine.
+  pub fn pattern(&self) -> &Pattern {
+    &self.pattern
+  }
+
+  /// Plays the pattern on a device, using the server's pattern engine if it has one, and
+  /// [streaming](Self::stream) it otherwise.
+  ///
+  /// When the server plays the pattern, the returned future resolves once playback has started,
+  /// and [ButtplugClientDevice::stop_pattern] or any new command for the device stops it. When
+  /// streamed, it behaves like [stream](Self::stream).
+  pub fn play(&self, device: &Arc<ButtplugClientDevice>) -> ButtplugClientResultFuture {
+    let device = device.clone();
+    let pattern = self.clone();
+    async move {
+      match device.load_pattern(&pattern.pattern).await {
+        Ok(()) => device.play_pattern(None).await,
+        // Servers without a pattern engine won't know the message.
+        Err(ButtplugClientError::ButtplugError(ButtplugError::ButtplugMessageError(err))) => {
+          debug!(
+            "Server could not load pattern, streaming instead: {:?}",
+            err
+          );
+          pattern.stream(&device).await
+        }
+        Err(err) => Err(err),
+      }
+    }
+    .boxed()
+  }
+
+  /// Plays the pattern by sending commands from the client, as the pattern calls for them.
+  ///
+  /// The returned future runs playback and resolves once the pattern finishes, zeroing the
+  /// actuators it drove. Looping patterns never finish. Dropping the future stops playback, leaving
+  /// the device at whatever levels were last sent.
+  pub fn stream(&self, device: &Arc<ButtplugClientDevice>) -> ButtplugClientResultFuture {
+    let features: Vec<(ActuatorType, u32)> = device
+      .message_attributes()
+      .scalar_cmd()
+      .iter()
+      .flatten()
+      .enumerate()
+      .map(|(i, attr)| (*attr.actuator_type(), i as u32))
+      .collect();
+    let resolve = |actuator: ActuatorType, index: u32| {
+      features
+        .iter()
+        .filter(|(actuator_type, _)| *actuator_type == actuator)
+        .nth(index as usize)
+        .map(|(_, feature_index)| *feature_index)
+    };
+    let mut used_features = HashMap::new();
+    let mut sections = vec![];
+    for section in self.pattern.sections() {
+      let duration = section.duration();
+      let mut steps: BTreeMap<u32, ScalarLevels> = BTreeMap::new();
+      for track in section.tracks() {
+        let Some(feature_index) = resolve(track.actuator(), track.index()) else {
+          continue;
+        };
+        used_features.insert(feature_index, (0.0, track.actuator()));
+        for keyframe in track.keyframes().iter().filter(|x| x.at <= duration) {
+          steps
+            .entry(keyframe.at)
+            .or_default()
+            .insert(feature_index, (keyframe.value, track.actuator()));
+        }
+      }
+      sections.push(StreamedSection {
+        duration,
+        repeat: section.repeat(),
+        steps: steps.into_iter().collect(),
+      });
+    }
+    if used_features.is_empty() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::ScalarCmd).into(),
+      );
+    }
+    let device = device.clone();
+    let looping = self.pattern.looping();
+    async move {
+      // Same as the server's pattern engine, step times come from an anchor that advances by
+      // exact section durations, so command round trips don't make the pattern drift.
+      let mut anchor = Instant::now();
+      loop {
+        for section in &sections {
+          for _ in 0..section.repeat {
+            for (at, scalars) in &section.steps {
+              sleep(
+                (anchor + Duration::from_millis(*at as u64))
+                  .saturating_duration_since(Instant::now()),
+              )
+              .await;
+              if !device.connected() {
+                return Err(
+                  ButtplugError::from(ButtplugDeviceError::DeviceNotConnected(
+                    device.name().clone(),
+                  ))
+                  .into(),
+                );
+              }
+              device
+                .scalar(&ScalarCommand::ScalarMap(scalars.clone()))
+                .await?;
+            }
+            anchor += Duration::from_millis(section.duration as u64);
+          }
+        }
+        if !looping {
+          break;
+        }
+      }
+      // Hold the final levels until the end of the last section.
+      sleep(anchor.saturating_duration_since(Instant::now())).await;
+      device
+        .scalar(&ScalarCommand::ScalarMap(used_features))
+        .await
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::client::Easing;
+
+  #[test]
+  fn test_vibration_pattern_builder() {
+    let pattern = VibrationPatternBuilder::default()
+      .name("Test")
+      .step(
+        Duration::from_millis(100),
+        &[
+          (ActuatorType::Vibrate, 0, 1.0),
+          (ActuatorType::Vibrate, 1, 0.5),
+        ],
+      )
+      .set(ActuatorType::Vibrate, 0, 0.0)
+      .hold(Duration::from_millis(100))
+      .repeat(2)
+      .section("fade")
+      .ramp(
+        ActuatorType::Vibrate,
+        0,
+        &Ramp::new(1.0, 0.0, Duration::from_millis(100), Easing::Linear)
+          .with_update_interval(Duration::from_millis(50)),
+      )
+      .build()
+      .expect("Test, assuming infallible.");
+    let pattern = pattern.pattern();
+    assert_eq!(pattern.name(), &Some("Test".to_owned()));
+    assert_eq!(pattern.sections().len(), 2);
+    let first = &pattern.sections()[0];
+    assert_eq!(first.label(), "section-0");
+    assert_eq!(first.repeat(), 2);
+    assert_eq!(first.duration(), 200);
+    assert_eq!(
+      first.tracks()[0].keyframes(),
+      &[
+        PatternKeyframe { at: 0, value: 1.0 },
+        PatternKeyframe {
+          at: 100,
+          value: 0.0
+        }
+      ]
+    );
+    assert_eq!(first.tracks()[1].index(), 1);
+    let fade = &pattern.sections()[1];
+    assert_eq!(fade.label(), "fade");
+    assert_eq!(fade.duration(), 100);
+    let values: Vec<f64> = fade.tracks()[0]
+      .keyframes()
+      .iter()
+      .map(|x| x.value)
+      .collect();
+    assert_eq!(values, vec![1.0, 0.5, 0.0]);
+  }
+
+  #[test]
+  fn test_vibration_pattern_builder_invalid() {
+    assert_eq!(
+      VibrationPatternBuilder::default().build(),
+      Err(PatternError::NoSections)
+    );
+    assert_eq!(
+      VibrationPatternBuilder::default()
+        .step(
+          Duration::from_millis(100),
+          &[(ActuatorType::Vibrate, 0, 2.0)]
+        )
+        .build(),
+      Err(PatternError::InvalidValue(2.0))
+    );
+  }
+}
@@ -7,6 +7,8 @@
 
 //! In-process communication between clients and servers
 
+#[cfg(feature = "simulation-manager")]
+use crate::server::device::hardware::communication::simulated::SimulationSettings;
 use crate::{
   core::{
     connector::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorResultFuture},
@@ -16,7 +18,7 @@ use crate::{
   util::async_manager,
 };
 use futures::{
-  future::{self, BoxFuture, FutureExt},
+  future::{BoxFuture, FutureExt},
   StreamExt,
 };
 use std::{
@@ -32,6 +34,8 @@ use tracing_futures::Instrument;
 #[derive(Default)]
 pub struct ButtplugInProcessClientConnectorBuilder {
   server: Option<ButtplugServer>,
+  #[cfg(feature = "simulation-manager")]
+  simulation: Option<SimulationSettings>,
 }
 
 impl ButtplugInProcessClientConnectorBuilder {
@@ -40,7 +44,29 @@ impl ButtplugInProcessClientConnectorBuilder {
     self
   }
 
+  /// Connect to a server running only simulated devices, so apps can run full integration flows
+  /// headlessly. Ignored if a server is also given, in which case use
+  /// [ButtplugServerBuilder::simulation] when building it.
+  #[cfg(feature = "simulation-manager")]
+  pub fn simulation(&mut self, settings: SimulationSettings) -> &mut Self {
+    self.simulation = Some(settings);
+    self
+  }
+
   pub fn finish(&mut self) -> ButtplugInProcessClientConnector {
+    #[cfg(feature = "simulation-manager")]
+    if let Some(settings) = self.simulation.take() {
+      if self.server.is_none() {
+        self.server = Some(
+          ButtplugServerBuilder::default()
+            .simulation(settings)
+            .finish()
+            .expect("Default server builder should always work."),
+        );
+      } else {
+        warn!("Server given to in-process connector, ignoring simulation settings.");
+      }
+    }
     ButtplugInProcessClientConnector::new(self.server.take())
   }
 }
@@ -150,7 +176,15 @@ impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServ
   fn disconnect(&self) -> ButtplugConnectorResultFuture {
     if self.connected.load(Ordering::SeqCst) {
       self.connected.store(false, Ordering::SeqCst);
-      future::ready(Ok(())).boxed()
+      // Disconnect the server too, so devices get stopped even if the client didn't do it first.
+      let server = self.server.clone();
+      async move {
+        if let Err(err) = server.disconnect().await {
+          error!("Error disconnecting in-process server: {:?}", err);
+        }
+        Ok(())
+      }
+      .boxed()
     } else {
       ButtplugConnectorError::ConnectorNotConnected.into()
     }
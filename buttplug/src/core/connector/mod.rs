@@ -63,7 +63,7 @@
 //! There are slightly more useful situations like device forwarders where this work comes in also,
 //! but that Windows 7/Android example is where the idea originally came from.
 
-#[cfg(all(feature = "server", feature = "client", not(feature = "wasm")))]
+#[cfg(all(feature = "server", feature = "client"))]
 mod in_process_connector;
 pub mod remote_connector;
 pub mod transport;
@@ -74,7 +74,7 @@ use crate::{
 };
 use displaydoc::Display;
 use futures::future::{self, BoxFuture, FutureExt};
-#[cfg(all(feature = "server", feature = "client", not(feature = "wasm")))]
+#[cfg(all(feature = "server", feature = "client"))]
 pub use in_process_connector::{
   ButtplugInProcessClientConnector,
   ButtplugInProcessClientConnectorBuilder,
@@ -86,9 +86,17 @@ pub use remote_connector::{
 };
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
+#[cfg(all(feature = "browser-websockets", target_arch = "wasm32"))]
+pub use transport::ButtplugBrowserWebsocketClientTransport;
 #[cfg(feature = "websockets")]
 pub use transport::ButtplugWebsocketClientTransport;
+#[cfg(feature = "encrypted-transport")]
+pub use transport::{ButtplugEncryptedTransport, ButtplugEncryptionKeypair};
 
+#[cfg(feature = "websocket-mdns")]
+pub use transport::BUTTPLUG_MDNS_SERVICE_TYPE;
+#[cfg(feature = "websocket-ssdp")]
+pub use transport::BUTTPLUG_SSDP_SEARCH_TARGET;
 #[cfg(feature = "websockets")]
 pub use transport::{ButtplugWebsocketServerTransport, ButtplugWebsocketServerTransportBuilder};
 
@@ -176,6 +184,11 @@ where
   fn disconnect(&self) -> ButtplugConnectorResultFuture;
   /// Sends a message of outbound message type `O` to the other connector.
   ///
+  /// Messages have to go out in the order send is called. Callers may keep several returned
+  /// futures running at once, polling them for the first time in the order they were created, so
+  /// the message needs to be on its way either by the time send returns or once its future is
+  /// first polled. Futures don't have to finish in order.
+  ///
   /// # Errors
   ///
   /// If the connector is not currently connected, or an error happens during
@@ -202,3 +215,34 @@ pub fn new_json_ws_client_connector(
     address,
   ))
 }
+
+/// Convenience method for creating a new Buttplug Client Websocket connector that uses the JSON
+/// serializer, and end to end encrypts everything sent through the websocket. Meant for connecting
+/// to a remote server through a relay, where `server_public_key` is the hex encoded public key of
+/// the server on the other side of the relay. See [ButtplugEncryptedTransport] for more info.
+#[cfg(all(
+  feature = "websockets",
+  feature = "serialize-json",
+  feature = "encrypted-transport"
+))]
+#[allow(clippy::result_large_err)]
+pub fn new_encrypted_json_ws_client_connector(
+  address: &str,
+  keypair: &ButtplugEncryptionKeypair,
+  server_public_key: &str,
+) -> Result<
+  impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage>,
+  ButtplugConnectorError,
+> {
+  use crate::core::message::serializer::ButtplugClientJSONSerializer;
+
+  let transport = ButtplugEncryptedTransport::new_initiator(
+    ButtplugWebsocketClientTransport::new_insecure_connector(address),
+    keypair,
+    server_public_key,
+  )?;
+  Ok(ButtplugRemoteClientConnector::<
+    ButtplugEncryptedTransport<ButtplugWebsocketClientTransport>,
+    ButtplugClientJSONSerializer,
+  >::new(transport))
+}
@@ -103,8 +103,17 @@ async fn remote_connector_event_loop<
       StreamValue::Incoming(remote_msg) => {
         match remote_msg {
           ButtplugTransportIncomingMessage::Message(serialized_msg) => {
-            match serializer.deserialize(&serialized_msg) {
-              Ok(array) => {
+            match serializer.deserialize_with_responses(&serialized_msg) {
+              Ok((array, responses)) => {
+                if !responses.is_empty()
+                  && transport_outgoing_sender
+                    .send(serializer.serialize(&responses))
+                    .await
+                    .is_err()
+                {
+                  error!("Transport has disconnected, exiting remote connector loop.");
+                  return;
+                }
                 for smsg in array {
                   // TODO Test validity here.
                   if connector_incoming_sender.send(smsg).await.is_err() {
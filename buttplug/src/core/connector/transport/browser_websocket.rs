@@ -0,0 +1,242 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Websocket client transport for browsers, using the WebSocket API via [web_sys]
+//!
+//! tungstenite can't open sockets in a browser, so WASM clients need to go through the browser's
+//! own WebSocket implementation. TLS is also handled by the browser, so the same transport works
+//! for both "ws://" and "wss://" addresses.
+
+use crate::core::{
+  connector::{
+    transport::{
+      ButtplugConnectorTransport,
+      ButtplugConnectorTransportSpecificError,
+      ButtplugTransportIncomingMessage,
+    },
+    ButtplugConnectorError,
+    ButtplugConnectorResultFuture,
+  },
+  message::serializer::ButtplugSerializedMessage,
+};
+use futures::{future::BoxFuture, FutureExt};
+use js_sys::{ArrayBuffer, Uint8Array};
+use std::sync::Arc;
+use tokio::sync::{
+  mpsc::{self, Receiver, Sender},
+  oneshot,
+  Notify,
+};
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{BinaryType, Event, MessageEvent, WebSocket};
+
+/// Events from the WebSocket JS callbacks, forwarded into the transport loop.
+enum BrowserWebsocketEvent {
+  Open,
+  Message(ButtplugSerializedMessage),
+  Error,
+  Close,
+}
+
+/// Websocket connector for ButtplugClients running in a browser.
+pub struct ButtplugBrowserWebsocketClientTransport {
+  /// Address of the server we'll connect to.
+  address: String,
+  /// Internally held sender, used for when disconnect is called.
+  disconnect_notifier: Arc<Notify>,
+}
+
+impl ButtplugBrowserWebsocketClientTransport {
+  /// Creates a new connector for "ws://" or "wss://" addresses
+  ///
+  /// Address should be the full URL of the server, i.e. "ws://127.0.0.1:12345". Certificate
+  /// verification for secure connections is handled by the browser, so servers with self-signed
+  /// certs will need to be trusted by the browser first.
+  pub fn new(address: &str) -> Self {
+    Self {
+      address: address.to_owned(),
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+}
+
+impl ButtplugConnectorTransport for ButtplugBrowserWebsocketClientTransport {
+  fn connect(
+    &self,
+    outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let (connected_sender, connected_receiver) = oneshot::channel();
+    // JS objects aren't Send, so the socket lives entirely in a local task, and we only wait on the
+    // result of the connection here.
+    spawn_local(run_browser_websocket_loop(
+      self.address.clone(),
+      outgoing_receiver,
+      incoming_sender,
+      self.disconnect_notifier.clone(),
+      connected_sender,
+    ));
+    async move {
+      connected_receiver
+        .await
+        .unwrap_or(Err(ButtplugConnectorError::ConnectorChannelClosed))
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let disconnect_notifier = self.disconnect_notifier;
+    async move {
+      // If we can't send the message, we have no loop, so we're not connected.
+      disconnect_notifier.notify_waiters();
+      Ok(())
+    }
+    .boxed()
+  }
+}
+
+async fn run_browser_websocket_loop(
+  address: String,
+  mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+  incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  disconnect_notifier: Arc<Notify>,
+  connected_sender: oneshot::Sender<Result<(), ButtplugConnectorError>>,
+) {
+  let ws = match WebSocket::new(&address) {
+    Ok(ws) => ws,
+    Err(err) => {
+      let _ = connected_sender.send(Err(ButtplugConnectorError::TransportSpecificError(
+        ButtplugConnectorTransportSpecificError::GenericNetworkError(format!("{:?}", err)),
+      )));
+      return;
+    }
+  };
+  ws.set_binary_type(BinaryType::Arraybuffer);
+
+  // The callbacks only forward events, so that everything else can happen in the loop below.
+  // Closures are held until the end of this function, and handlers are removed before they're
+  // dropped.
+  let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
+  let open_sender = event_sender.clone();
+  let onopen = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+    let _ = open_sender.send(BrowserWebsocketEvent::Open);
+  });
+  let message_sender = event_sender.clone();
+  let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(move |e: MessageEvent| {
+    let data = e.data();
+    let msg = if let Some(text) = data.as_string() {
+      ButtplugSerializedMessage::Text(text)
+    } else if let Ok(buffer) = data.dyn_into::<ArrayBuffer>() {
+      ButtplugSerializedMessage::Binary(Uint8Array::new(&buffer).to_vec())
+    } else {
+      warn!("Browser websocket received message of unknown type, ignoring.");
+      return;
+    };
+    let _ = message_sender.send(BrowserWebsocketEvent::Message(msg));
+  });
+  let error_sender = event_sender.clone();
+  let onerror = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+    let _ = error_sender.send(BrowserWebsocketEvent::Error);
+  });
+  let onclose = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+    let _ = event_sender.send(BrowserWebsocketEvent::Close);
+  });
+  ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+  ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+  ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+  ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+
+  let clear_handlers = |ws: &WebSocket| {
+    ws.set_onopen(None);
+    ws.set_onmessage(None);
+    ws.set_onerror(None);
+    ws.set_onclose(None);
+  };
+
+  // Browsers report connection failures as an error event followed by a close event, and won't
+  // tell us anything more specific than that.
+  if !matches!(
+    event_receiver.recv().await,
+    Some(BrowserWebsocketEvent::Open)
+  ) {
+    clear_handlers(&ws);
+    let _ = connected_sender.send(Err(ButtplugConnectorError::TransportSpecificError(
+      ButtplugConnectorTransportSpecificError::GenericNetworkError(format!(
+        "Could not connect to {}",
+        address
+      )),
+    )));
+    return;
+  }
+  if connected_sender.send(Ok(())).is_err() {
+    clear_handlers(&ws);
+    let _ = ws.close();
+    return;
+  }
+
+  let close_reason = loop {
+    select! {
+      msg = outgoing_receiver.recv().fuse() => {
+        let Some(msg) = msg else {
+          info!("Connector holding websocket dropped, returning");
+          break "Server closed connection";
+        };
+        let result = match msg {
+          ButtplugSerializedMessage::Text(text) => ws.send_with_str(&text),
+          ButtplugSerializedMessage::Binary(bin) => ws.send_with_u8_array(&bin),
+        };
+        if let Err(err) = result {
+          error!("Error sending on browser websocket (assuming disconnect): {:?}", err);
+          break "Websocket send failed, closed connection";
+        }
+      },
+      event = event_receiver.recv().fuse() => {
+        match event {
+          Some(BrowserWebsocketEvent::Message(msg)) => {
+            if incoming_sender
+              .send(ButtplugTransportIncomingMessage::Message(msg))
+              .await
+              .is_err()
+            {
+              warn!("Websocket holder has closed, exiting websocket loop.");
+              clear_handlers(&ws);
+              let _ = ws.close();
+              return;
+            }
+          }
+          Some(BrowserWebsocketEvent::Error) => {
+            error!("Error in browser websocket client loop (assuming disconnect)");
+            break "Websocket error, closed connection";
+          }
+          Some(BrowserWebsocketEvent::Close) | None => {
+            info!("Websocket has requested close.");
+            break "Server closed connection";
+          }
+          Some(BrowserWebsocketEvent::Open) => {}
+        }
+      },
+      _ = disconnect_notifier.notified().fuse() => {
+        info!("Websocket requested to disconnect.");
+        break "Disconnect notifier triggered, closed connection";
+      }
+    }
+  };
+  clear_handlers(&ws);
+  if let Err(err) = ws.close() {
+    error!("{:?}", err);
+  }
+  if incoming_sender
+    .send(ButtplugTransportIncomingMessage::Close(
+      close_reason.to_owned(),
+    ))
+    .await
+    .is_err()
+  {
+    warn!("Websocket holder has closed, exiting websocket loop.");
+  }
+}
@@ -0,0 +1,678 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! End to end encrypted transport, for connecting through relays that shouldn't see messages.
+//!
+//! [ButtplugEncryptedTransport] wraps another transport (usually a websocket client connected to a
+//! relay server) and runs a [Noise protocol](https://noiseprotocol.org) session over it. Both sides
+//! need to know each other's public key before connecting, which they can exchange however they
+//! like (chat, QR code, etc). Anything in between the two sides, including the relay, only ever
+//! sees ciphertext, and connections using the wrong keys fail during the handshake.
+//!
+//! Exactly one side of a connection must be the initiator, which is usually the client side.
+
+// ButtplugConnectorError is large due to the tungstenite error it can contain.
+#![allow(clippy::result_large_err)]
+
+use super::{
+  ButtplugConnectorTransport,
+  ButtplugConnectorTransportSpecificError,
+  ButtplugTransportIncomingMessage,
+};
+use crate::{
+  core::connector::{
+    ButtplugConnectorError,
+    ButtplugConnectorResultFuture,
+    ButtplugSerializedMessage,
+  },
+  util::{async_manager, sleep},
+};
+use futures::{
+  future::{BoxFuture, FutureExt},
+  select,
+};
+use snow::{
+  params::{DHChoice, NoiseParams},
+  resolvers::{CryptoResolver, DefaultResolver},
+  Builder,
+  HandshakeState,
+  TransportState,
+};
+use std::{fmt, time::Duration};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// Noise handshake pattern used. KK, since both sides know each other's static key in advance.
+const NOISE_PARAMS: &str = "Noise_KK_25519_ChaChaPoly_BLAKE2s";
+/// Mixed into the handshake, so sessions from other applications can't be replayed here.
+const NOISE_PROLOGUE: &[u8] = b"buttplug-e2e-v1";
+/// Largest message a Noise session can send, including the auth tag.
+const NOISE_MAX_MESSAGE_LEN: usize = 65535;
+const NOISE_TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+// Each encrypted frame starts with a flag byte, followed by a chunk of the message. Messages too
+// large for a single Noise message are split over multiple frames.
+const FRAME_TEXT: u8 = 0x01;
+const FRAME_MORE: u8 = 0x02;
+const FRAME_MAX_PAYLOAD: usize = NOISE_MAX_MESSAGE_LEN - NOISE_TAG_LEN - 1;
+/// Largest message we'll reassemble from frames. Much bigger than any Buttplug message, but stops
+/// the other side from making us buffer frames forever.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+/// How long the other side has to complete the handshake. Relays may hold the connection open
+/// without anything on the other end, which would otherwise leave connect waiting forever.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn encryption_error(msg: impl ToString) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    ButtplugConnectorTransportSpecificError::EncryptionError(msg.to_string()),
+  )
+}
+
+fn noise_params() -> NoiseParams {
+  NOISE_PARAMS.parse().expect("Noise params are constant")
+}
+
+fn encode_key(key: &[u8]) -> String {
+  key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, ButtplugConnectorError> {
+  let key = key.trim();
+  if key.len() != KEY_LEN * 2 || !key.is_ascii() {
+    return Err(encryption_error(format!(
+      "Keys must be {} hex characters",
+      KEY_LEN * 2
+    )));
+  }
+  (0..key.len())
+    .step_by(2)
+    .map(|i| {
+      u8::from_str_radix(&key[i..i + 2], 16)
+        .map_err(|_| encryption_error("Keys must be hex encoded"))
+    })
+    .collect()
+}
+
+/// Static keypair identifying one side of an encrypted connection.
+///
+/// Keys are passed around as hex strings. The public key is what gets shared with the other side,
+/// the private key should be stored somewhere safe and reused, so the other side doesn't need a new
+/// public key every session.
+#[derive(Clone)]
+pub struct ButtplugEncryptionKeypair {
+  private_key: Vec<u8>,
+  public_key: Vec<u8>,
+}
+
+impl ButtplugEncryptionKeypair {
+  /// Generate a new random keypair.
+  pub fn generate() -> Result<Self, ButtplugConnectorError> {
+    let keypair = Builder::new(noise_params())
+      .generate_keypair()
+      .map_err(encryption_error)?;
+    Ok(Self {
+      private_key: keypair.private,
+      public_key: keypair.public,
+    })
+  }
+
+  /// Load a keypair from a previously stored (hex encoded) private key.
+  pub fn from_private_key(private_key: &str) -> Result<Self, ButtplugConnectorError> {
+    let private_key = decode_key(private_key)?;
+    let mut dh = DefaultResolver
+      .resolve_dh(&DHChoice::Curve25519)
+      .expect("Default resolver always supports 25519");
+    dh.set(&private_key);
+    let public_key = dh.pubkey().to_vec();
+    Ok(Self {
+      private_key,
+      public_key,
+    })
+  }
+
+  /// Hex encoded private key, for storage. Never share this.
+  pub fn private_key(&self) -> String {
+    encode_key(&self.private_key)
+  }
+
+  /// Hex encoded public key, to give to the other side of the connection.
+  pub fn public_key(&self) -> String {
+    encode_key(&self.public_key)
+  }
+}
+
+impl fmt::Debug for ButtplugEncryptionKeypair {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ButtplugEncryptionKeypair")
+      .field("public_key", &self.public_key())
+      .finish_non_exhaustive()
+  }
+}
+
+/// Transport wrapper that end to end encrypts everything sent over another transport.
+pub struct ButtplugEncryptedTransport<T: ButtplugConnectorTransport> {
+  inner: T,
+  keypair: ButtplugEncryptionKeypair,
+  remote_public_key: Vec<u8>,
+  initiator: bool,
+}
+
+impl<T: ButtplugConnectorTransport> ButtplugEncryptedTransport<T> {
+  /// Create the initiating side of an encrypted connection over `inner`. `remote_public_key` is the
+  /// hex encoded public key of the other side.
+  pub fn new_initiator(
+    inner: T,
+    keypair: &ButtplugEncryptionKeypair,
+    remote_public_key: &str,
+  ) -> Result<Self, ButtplugConnectorError> {
+    Self::new(inner, keypair, remote_public_key, true)
+  }
+
+  /// Create the responding side of an encrypted connection over `inner`. `remote_public_key` is the
+  /// hex encoded public key of the other side.
+  pub fn new_responder(
+    inner: T,
+    keypair: &ButtplugEncryptionKeypair,
+    remote_public_key: &str,
+  ) -> Result<Self, ButtplugConnectorError> {
+    Self::new(inner, keypair, remote_public_key, false)
+  }
+
+  fn new(
+    inner: T,
+    keypair: &ButtplugEncryptionKeypair,
+    remote_public_key: &str,
+    initiator: bool,
+  ) -> Result<Self, ButtplugConnectorError> {
+    Ok(Self {
+      inner,
+      keypair: keypair.clone(),
+      remote_public_key: decode_key(remote_public_key)?,
+      initiator,
+    })
+  }
+
+  fn build_handshake(&self) -> Result<HandshakeState, ButtplugConnectorError> {
+    let builder = Builder::new(noise_params())
+      .local_private_key(&self.keypair.private_key)
+      .and_then(|builder| builder.remote_public_key(&self.remote_public_key))
+      .and_then(|builder| builder.prologue(NOISE_PROLOGUE))
+      .map_err(encryption_error)?;
+    if self.initiator {
+      builder.build_initiator()
+    } else {
+      builder.build_responder()
+    }
+    .map_err(encryption_error)
+  }
+}
+
+impl<T: ButtplugConnectorTransport> ButtplugConnectorTransport for ButtplugEncryptedTransport<T> {
+  fn connect(
+    &self,
+    outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let handshake = self.build_handshake();
+    let (inner_outgoing_sender, inner_outgoing_receiver) = channel(256);
+    let (inner_incoming_sender, mut inner_incoming_receiver) = channel(256);
+    let inner_connect = self
+      .inner
+      .connect(inner_outgoing_receiver, inner_incoming_sender);
+    async move {
+      let handshake = handshake?;
+      inner_connect.await?;
+      let session = run_handshake(
+        handshake,
+        &inner_outgoing_sender,
+        &mut inner_incoming_receiver,
+        HANDSHAKE_TIMEOUT,
+      )
+      .await?;
+      info!("Encrypted session established.");
+      async_manager::spawn(run_encrypted_session(
+        session,
+        outgoing_receiver,
+        incoming_sender,
+        inner_outgoing_sender,
+        inner_incoming_receiver,
+      ));
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    self.inner.disconnect()
+  }
+}
+
+async fn next_handshake_message(
+  receiver: &mut Receiver<ButtplugTransportIncomingMessage>,
+) -> Result<Vec<u8>, ButtplugConnectorError> {
+  loop {
+    match receiver.recv().await {
+      Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(data))) => {
+        return Ok(data)
+      }
+      Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Text(_))) => {
+        return Err(encryption_error(
+          "Remote sent unencrypted message during handshake",
+        ))
+      }
+      Some(ButtplugTransportIncomingMessage::Connected) => continue,
+      Some(ButtplugTransportIncomingMessage::Error(err))
+      | Some(ButtplugTransportIncomingMessage::Close(err)) => {
+        return Err(ButtplugConnectorError::ConnectorGenericError(err))
+      }
+      None => return Err(ButtplugConnectorError::ConnectorChannelClosed),
+    }
+  }
+}
+
+async fn run_handshake(
+  handshake: HandshakeState,
+  sender: &Sender<ButtplugSerializedMessage>,
+  receiver: &mut Receiver<ButtplugTransportIncomingMessage>,
+  timeout: Duration,
+) -> Result<TransportState, ButtplugConnectorError> {
+  select! {
+    session = exchange_handshake_messages(handshake, sender, receiver).fuse() => session,
+    _ = sleep(timeout).fuse() => Err(encryption_error("Handshake timed out")),
+  }
+}
+
+async fn exchange_handshake_messages(
+  mut handshake: HandshakeState,
+  sender: &Sender<ButtplugSerializedMessage>,
+  receiver: &mut Receiver<ButtplugTransportIncomingMessage>,
+) -> Result<TransportState, ButtplugConnectorError> {
+  let mut buffer = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+  while !handshake.is_handshake_finished() {
+    if handshake.is_my_turn() {
+      let len = handshake
+        .write_message(&[], &mut buffer)
+        .map_err(encryption_error)?;
+      sender
+        .send(ButtplugSerializedMessage::Binary(buffer[..len].to_vec()))
+        .await
+        .map_err(|_| ButtplugConnectorError::ConnectorChannelClosed)?;
+    } else {
+      let message = next_handshake_message(receiver).await?;
+      handshake
+        .read_message(&message, &mut buffer)
+        .map_err(|_| encryption_error("Handshake failed, check both sides' keys"))?;
+    }
+  }
+  handshake.into_transport_mode().map_err(encryption_error)
+}
+
+fn encrypt_message(
+  session: &mut TransportState,
+  msg: ButtplugSerializedMessage,
+) -> Result<Vec<Vec<u8>>, snow::Error> {
+  let (kind, data) = match msg {
+    ButtplugSerializedMessage::Text(text) => (FRAME_TEXT, text.into_bytes()),
+    ButtplugSerializedMessage::Binary(data) => (0, data),
+  };
+  let chunk_count = data.len().div_ceil(FRAME_MAX_PAYLOAD).max(1);
+  let mut frames = Vec::with_capacity(chunk_count);
+  let mut plaintext = Vec::with_capacity(FRAME_MAX_PAYLOAD + 1);
+  let mut buffer = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+  for i in 0..chunk_count {
+    let chunk = &data[i * FRAME_MAX_PAYLOAD..((i + 1) * FRAME_MAX_PAYLOAD).min(data.len())];
+    plaintext.clear();
+    plaintext.push(if i + 1 < chunk_count {
+      kind | FRAME_MORE
+    } else {
+      kind
+    });
+    plaintext.extend_from_slice(chunk);
+    let len = session.write_message(&plaintext, &mut buffer)?;
+    frames.push(buffer[..len].to_vec());
+  }
+  Ok(frames)
+}
+
+/// Reassembles decrypted frames into messages.
+#[derive(Default)]
+struct FrameAssembler {
+  partial: Vec<u8>,
+}
+
+impl FrameAssembler {
+  fn add_frame(
+    &mut self,
+    frame: &[u8],
+  ) -> Result<Option<ButtplugSerializedMessage>, ButtplugConnectorError> {
+    let (flags, payload) = frame
+      .split_first()
+      .ok_or_else(|| encryption_error("Received empty frame"))?;
+    if self.partial.len() + payload.len() > MAX_MESSAGE_LEN {
+      return Err(encryption_error(format!(
+        "Received message larger than {} bytes",
+        MAX_MESSAGE_LEN
+      )));
+    }
+    self.partial.extend_from_slice(payload);
+    if flags & FRAME_MORE != 0 {
+      return Ok(None);
+    }
+    let data = std::mem::take(&mut self.partial);
+    if flags & FRAME_TEXT != 0 {
+      String::from_utf8(data)
+        .map(|text| Some(ButtplugSerializedMessage::Text(text)))
+        .map_err(|_| encryption_error("Received invalid text message"))
+    } else {
+      Ok(Some(ButtplugSerializedMessage::Binary(data)))
+    }
+  }
+}
+
+async fn run_encrypted_session(
+  mut session: TransportState,
+  mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+  incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  inner_sender: Sender<ButtplugSerializedMessage>,
+  mut inner_receiver: Receiver<ButtplugTransportIncomingMessage>,
+) {
+  let mut assembler = FrameAssembler::default();
+  let mut buffer = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+  // Dropping the inner sender on return tells the wrapped transport to close too.
+  loop {
+    select! {
+      outgoing = outgoing_receiver.recv().fuse() => {
+        let Some(msg) = outgoing else {
+          return;
+        };
+        let frames = match encrypt_message(&mut session, msg) {
+          Ok(frames) => frames,
+          Err(err) => {
+            error!("Cannot encrypt message, closing connection: {}", err);
+            let _ = incoming_sender.send(ButtplugTransportIncomingMessage::Close(err.to_string())).await;
+            return;
+          }
+        };
+        for frame in frames {
+          if inner_sender.send(ButtplugSerializedMessage::Binary(frame)).await.is_err() {
+            return;
+          }
+        }
+      }
+      incoming = inner_receiver.recv().fuse() => {
+        let msg = match incoming {
+          Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(data))) => {
+            session
+              .read_message(&data, &mut buffer)
+              .map_err(|_| encryption_error("Cannot decrypt message"))
+              .and_then(|len| assembler.add_frame(&buffer[..len]))
+          }
+          Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Text(_))) => {
+            Err(encryption_error("Received unencrypted message"))
+          }
+          Some(other) => {
+            if incoming_sender.send(other).await.is_err() {
+              return;
+            }
+            continue;
+          }
+          None => return,
+        };
+        match msg {
+          Ok(Some(msg)) => {
+            if incoming_sender.send(ButtplugTransportIncomingMessage::Message(msg)).await.is_err() {
+              return;
+            }
+          }
+          Ok(None) => {}
+          // Anything that fails to decrypt may have been tampered with, and the session can't
+          // recover from a missing message anyways, so drop the connection.
+          Err(err) => {
+            error!("{}, closing connection.", err);
+            let _ = incoming_sender.send(ButtplugTransportIncomingMessage::Close(err.to_string())).await;
+            return;
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::Mutex;
+
+  /// Stands in for an untrusted relay, passing messages between two transports and keeping a copy
+  /// of everything it sees.
+  struct RelayTransport {
+    sender: Mutex<Option<Sender<ButtplugSerializedMessage>>>,
+    receiver: Mutex<Option<Receiver<ButtplugSerializedMessage>>>,
+    observed: Sender<ButtplugSerializedMessage>,
+  }
+
+  impl ButtplugConnectorTransport for RelayTransport {
+    fn connect(
+      &self,
+      mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+      incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+    ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+      let sender = self
+        .sender
+        .lock()
+        .expect("Test, assuming infallible.")
+        .take()
+        .expect("Test, assuming infallible.");
+      let mut receiver = self
+        .receiver
+        .lock()
+        .expect("Test, assuming infallible.")
+        .take()
+        .expect("Test, assuming infallible.");
+      let observed = self.observed.clone();
+      async_manager::spawn(async move {
+        while let Some(msg) = outgoing_receiver.recv().await {
+          let _ = observed.send(msg.clone()).await;
+          if sender.send(msg).await.is_err() {
+            break;
+          }
+        }
+      });
+      async_manager::spawn(async move {
+        while let Some(msg) = receiver.recv().await {
+          if incoming_sender
+            .send(ButtplugTransportIncomingMessage::Message(msg))
+            .await
+            .is_err()
+          {
+            break;
+          }
+        }
+      });
+      async { Ok(()) }.boxed()
+    }
+
+    fn disconnect(self) -> ButtplugConnectorResultFuture {
+      async { Ok(()) }.boxed()
+    }
+  }
+
+  fn relay_pair() -> (
+    RelayTransport,
+    RelayTransport,
+    Receiver<ButtplugSerializedMessage>,
+  ) {
+    let (a_sender, b_receiver) = channel(256);
+    let (b_sender, a_receiver) = channel(256);
+    let (observed, observed_receiver) = channel(256);
+    (
+      RelayTransport {
+        sender: Mutex::new(Some(a_sender)),
+        receiver: Mutex::new(Some(a_receiver)),
+        observed: observed.clone(),
+      },
+      RelayTransport {
+        sender: Mutex::new(Some(b_sender)),
+        receiver: Mutex::new(Some(b_receiver)),
+        observed,
+      },
+      observed_receiver,
+    )
+  }
+
+  struct ConnectedSide {
+    sender: Sender<ButtplugSerializedMessage>,
+    receiver: Receiver<ButtplugTransportIncomingMessage>,
+  }
+
+  async fn connect_side<T: ButtplugConnectorTransport>(
+    transport: &T,
+  ) -> Result<ConnectedSide, ButtplugConnectorError> {
+    let (sender, outgoing_receiver) = channel(256);
+    let (incoming_sender, receiver) = channel(256);
+    transport
+      .connect(outgoing_receiver, incoming_sender)
+      .await?;
+    Ok(ConnectedSide { sender, receiver })
+  }
+
+  async fn next_message(side: &mut ConnectedSide) -> ButtplugSerializedMessage {
+    match side.receiver.recv().await {
+      Some(ButtplugTransportIncomingMessage::Message(msg)) => msg,
+      other => panic!("Expected message, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_keypair_round_trip() {
+    let keypair = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    assert_eq!(keypair.public_key().len(), 64);
+    let loaded = ButtplugEncryptionKeypair::from_private_key(&keypair.private_key())
+      .expect("Test, assuming infallible.");
+    assert_eq!(keypair.public_key(), loaded.public_key());
+    assert!(!format!("{:?}", keypair).contains(&keypair.private_key()));
+    assert!(ButtplugEncryptionKeypair::from_private_key("not a key").is_err());
+    assert!(ButtplugEncryptionKeypair::from_private_key(&"zz".repeat(32)).is_err());
+  }
+
+  #[tokio::test]
+  async fn test_encrypted_round_trip() {
+    let client_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let server_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let (client_relay, server_relay, mut observed) = relay_pair();
+    let client = ButtplugEncryptedTransport::new_initiator(
+      client_relay,
+      &client_keys,
+      &server_keys.public_key(),
+    )
+    .expect("Test, assuming infallible.");
+    let server = ButtplugEncryptedTransport::new_responder(
+      server_relay,
+      &server_keys,
+      &client_keys.public_key(),
+    )
+    .expect("Test, assuming infallible.");
+    let (client_side, server_side) = futures::join!(connect_side(&client), connect_side(&server));
+    let (client_side, mut server_side) = (
+      client_side.expect("Test, assuming infallible."),
+      server_side.expect("Test, assuming infallible."),
+    );
+
+    let text = r#"[{"RequestServerInfo":{"Id":1,"ClientName":"Test","MessageVersion":3}}]"#;
+    client_side
+      .sender
+      .send(ButtplugSerializedMessage::Text(text.to_owned()))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      next_message(&mut server_side).await,
+      ButtplugSerializedMessage::Text(text.to_owned())
+    );
+
+    // Larger than a single Noise message, so has to be split up.
+    let large: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    client_side
+      .sender
+      .send(ButtplugSerializedMessage::Binary(large.clone()))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      next_message(&mut server_side).await,
+      ButtplugSerializedMessage::Binary(large)
+    );
+
+    // The relay should only have seen binary ciphertext.
+    while let Ok(msg) = observed.try_recv() {
+      match msg {
+        ButtplugSerializedMessage::Binary(data) => {
+          assert!(!data
+            .windows(b"RequestServerInfo".len())
+            .any(|window| window == b"RequestServerInfo"));
+        }
+        ButtplugSerializedMessage::Text(_) => panic!("Relay saw a text message"),
+      }
+    }
+  }
+
+  #[test]
+  fn test_frame_assembler_message_limit() {
+    let mut assembler = FrameAssembler::default();
+    let mut frame = vec![FRAME_MORE];
+    frame.extend_from_slice(&[0u8; FRAME_MAX_PAYLOAD]);
+    let frame_count = MAX_MESSAGE_LEN / FRAME_MAX_PAYLOAD;
+    for _ in 0..frame_count {
+      assert!(matches!(assembler.add_frame(&frame), Ok(None)));
+    }
+    assert!(assembler.add_frame(&frame).is_err());
+  }
+
+  #[tokio::test]
+  async fn test_encrypted_handshake_timeout() {
+    let client_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let server_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let client = ButtplugEncryptedTransport::new_initiator(
+      relay_pair().0,
+      &client_keys,
+      &server_keys.public_key(),
+    )
+    .expect("Test, assuming infallible.");
+    // Nothing ever answers, like a relay with nobody connected on the other end.
+    let (sender, _remote_receiver) = channel(256);
+    let (_remote_sender, mut receiver) = channel(256);
+    let handshake = client
+      .build_handshake()
+      .expect("Test, assuming infallible.");
+    assert!(
+      run_handshake(handshake, &sender, &mut receiver, Duration::from_millis(50))
+        .await
+        .is_err()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_encrypted_wrong_key() {
+    let client_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let server_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let other_keys = ButtplugEncryptionKeypair::generate().expect("Test, assuming infallible.");
+    let (client_relay, server_relay, _observed) = relay_pair();
+    let client = ButtplugEncryptedTransport::new_initiator(
+      client_relay,
+      &client_keys,
+      &other_keys.public_key(),
+    )
+    .expect("Test, assuming infallible.");
+    let server = ButtplugEncryptedTransport::new_responder(
+      server_relay,
+      &server_keys,
+      &client_keys.public_key(),
+    )
+    .expect("Test, assuming infallible.");
+    let (client_side, server_side) = futures::join!(connect_side(&client), connect_side(&server));
+    assert!(client_side.is_err());
+    assert!(server_side.is_err());
+  }
+}
@@ -7,6 +7,12 @@
 
 //! Transports for remote (IPC/network/etc) communication between clients and servers
 
+#[cfg(all(feature = "browser-websockets", target_arch = "wasm32"))]
+mod browser_websocket;
+#[cfg(feature = "encrypted-transport")]
+mod encrypted;
+#[cfg(feature = "relay-transport")]
+mod relay;
 #[cfg(feature = "websockets")]
 mod websocket;
 use crate::core::connector::{
@@ -14,12 +20,30 @@ use crate::core::connector::{
   ButtplugConnectorResultFuture,
   ButtplugSerializedMessage,
 };
+#[cfg(all(feature = "browser-websockets", target_arch = "wasm32"))]
+pub use browser_websocket::ButtplugBrowserWebsocketClientTransport;
+#[cfg(feature = "encrypted-transport")]
+pub use encrypted::{ButtplugEncryptedTransport, ButtplugEncryptionKeypair};
 use futures::future::BoxFuture;
+#[cfg(feature = "relay-transport")]
+pub use relay::{
+  ButtplugRelayChannelTransport,
+  ButtplugRelayKey,
+  ButtplugRelayLink,
+  ButtplugRelayRole,
+};
 use thiserror::Error;
 use tokio::sync::mpsc::{Receiver, Sender};
 #[cfg(feature = "websockets")]
+pub(crate) use websocket::WebsocketServerAcceptor;
+#[cfg(feature = "websocket-mdns")]
+pub use websocket::BUTTPLUG_MDNS_SERVICE_TYPE;
+#[cfg(feature = "websocket-ssdp")]
+pub use websocket::BUTTPLUG_SSDP_SEARCH_TARGET;
+#[cfg(feature = "websockets")]
 pub use websocket::{
   ButtplugWebsocketClientTransport,
+  ButtplugWebsocketServerStream,
   ButtplugWebsocketServerTransport,
   ButtplugWebsocketServerTransportBuilder,
   TungsteniteError,
@@ -55,4 +79,10 @@ pub enum ButtplugConnectorTransportSpecificError {
   TungsteniteError(#[from] TungsteniteError),
   #[error("Network error: {0}")]
   GenericNetworkError(String),
+  #[cfg(feature = "encrypted-transport")]
+  #[error("Encryption error: {0}")]
+  EncryptionError(String),
+  #[cfg(feature = "relay-transport")]
+  #[error("Relay error: {0}")]
+  RelayError(String),
 }
@@ -0,0 +1,849 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Transport for connecting through a dumb relay, i.e. a TCP or websocket server that passes
+//! messages between whoever connects to it, so neither side needs port forwarding.
+//!
+//! Both sides share a secret ([ButtplugRelayKey]), which they use to authenticate each other when
+//! the link comes up. Every frame after that carries a sequence number and a MAC, so anything the
+//! relay injects, replays, drops, reorders or modifies is detected, and the link is closed. Frames
+//! are not encrypted, so if the relay shouldn't be able to read messages, wrap each channel in an
+//! encrypted transport (see the `encrypted-transport` feature).
+//!
+//! One relay connection can carry several Buttplug connections. [ButtplugRelayLink] wraps the
+//! transport connected to the relay, and hands out a [ButtplugRelayChannelTransport] per channel
+//! id. Both sides need to agree on channel ids, and one side must use
+//! [ButtplugRelayRole::Client] while the other uses [ButtplugRelayRole::Server].
+
+// ButtplugConnectorError is large due to the tungstenite error it can contain.
+#![allow(clippy::result_large_err)]
+
+use super::{
+  ButtplugConnectorTransport,
+  ButtplugConnectorTransportSpecificError,
+  ButtplugTransportIncomingMessage,
+};
+use crate::{
+  core::connector::{
+    ButtplugConnectorError,
+    ButtplugConnectorResultFuture,
+    ButtplugSerializedMessage,
+  },
+  util::{async_manager, sleep},
+};
+use blake2::{
+  digest::{KeyInit, Mac},
+  Blake2s256,
+  Blake2sMac256,
+  Digest,
+};
+use futures::{
+  future::{BoxFuture, FutureExt},
+  select,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+  collections::HashMap,
+  fmt,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::sync::{
+  mpsc::{channel, Receiver, Sender},
+  OnceCell,
+};
+
+/// Mixed into key derivation, so secrets used elsewhere don't produce the same keys.
+const RELAY_KEY_DOMAIN: &[u8] = b"buttplug-relay-v1";
+const KEY_LEN: usize = 32;
+const MAC_LEN: usize = 32;
+const NONCE_LEN: usize = 16;
+const MIN_SECRET_LEN: usize = 16;
+const GENERATED_SECRET_LEN: usize = 32;
+/// How often the client repeats its hello until the server answers, as relays usually drop
+/// anything sent before the other side connects.
+const HELLO_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+const FRAME_HELLO: u8 = 0x01;
+const FRAME_CONFIRM: u8 = 0x02;
+const FRAME_DATA: u8 = 0x03;
+const PAYLOAD_BINARY: u8 = 0x00;
+const PAYLOAD_TEXT: u8 = 0x01;
+const HELLO_LEN: usize = 2 + NONCE_LEN;
+// Data frames are frame type, channel id (u16), sequence number (u64), payload type, then the
+// payload and MAC.
+const DATA_HEADER_LEN: usize = 1 + 2 + 8 + 1;
+
+fn relay_error(msg: impl ToString) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    ButtplugConnectorTransportSpecificError::RelayError(msg.to_string()),
+  )
+}
+
+fn mac(key: &[u8; KEY_LEN], parts: &[&[u8]]) -> [u8; MAC_LEN] {
+  let mut mac =
+    <Blake2sMac256 as KeyInit>::new_from_slice(key).expect("Keys are always a valid length");
+  for part in parts {
+    mac.update(part);
+  }
+  mac.finalize().into_bytes().into()
+}
+
+fn verify_mac(key: &[u8; KEY_LEN], parts: &[&[u8]], tag: &[u8]) -> bool {
+  let mut mac =
+    <Blake2sMac256 as KeyInit>::new_from_slice(key).expect("Keys are always a valid length");
+  for part in parts {
+    mac.update(part);
+  }
+  mac.verify_slice(tag).is_ok()
+}
+
+/// Pre-shared key both sides of a relay link authenticate with.
+#[derive(Clone)]
+pub struct ButtplugRelayKey {
+  key: [u8; KEY_LEN],
+}
+
+impl ButtplugRelayKey {
+  /// Create a key from a shared secret. Both sides must use the same secret, which should be long
+  /// and random, like the ones from [ButtplugRelayKey::generate_secret].
+  pub fn new(secret: &str) -> Result<Self, ButtplugConnectorError> {
+    if secret.len() < MIN_SECRET_LEN {
+      return Err(relay_error(format!(
+        "Relay secrets must be at least {} characters",
+        MIN_SECRET_LEN
+      )));
+    }
+    Ok(Self {
+      key: Blake2s256::new()
+        .chain_update(RELAY_KEY_DOMAIN)
+        .chain_update(secret.as_bytes())
+        .finalize()
+        .into(),
+    })
+  }
+
+  /// Generate a random secret, to share with the other side however is convenient.
+  pub fn generate_secret() -> String {
+    thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(GENERATED_SECRET_LEN)
+      .map(char::from)
+      .collect()
+  }
+}
+
+impl fmt::Debug for ButtplugRelayKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ButtplugRelayKey").finish_non_exhaustive()
+  }
+}
+
+/// Which end of a relay link this is. The client starts the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtplugRelayRole {
+  Client,
+  Server,
+}
+
+impl ButtplugRelayRole {
+  fn id(self) -> u8 {
+    match self {
+      ButtplugRelayRole::Client => 0,
+      ButtplugRelayRole::Server => 1,
+    }
+  }
+
+  fn peer(self) -> Self {
+    match self {
+      ButtplugRelayRole::Client => ButtplugRelayRole::Server,
+      ButtplugRelayRole::Server => ButtplugRelayRole::Client,
+    }
+  }
+}
+
+type RelayChannels = Arc<Mutex<HashMap<u16, Sender<ButtplugTransportIncomingMessage>>>>;
+
+/// Running link, shared by all channels.
+struct RelayLinkHandle {
+  outgoing: Sender<(u16, ButtplugSerializedMessage)>,
+  channels: RelayChannels,
+}
+
+struct RelayLinkState<T: ButtplugConnectorTransport> {
+  inner: T,
+  key: ButtplugRelayKey,
+  role: ButtplugRelayRole,
+  handle: OnceCell<RelayLinkHandle>,
+}
+
+/// Authenticated link to the other side of a relay, carried over another transport (usually a
+/// websocket client connected to the relay). The link comes up when the first of its channels
+/// connects.
+pub struct ButtplugRelayLink<T: ButtplugConnectorTransport> {
+  state: Arc<RelayLinkState<T>>,
+}
+
+impl<T: ButtplugConnectorTransport + 'static> ButtplugRelayLink<T> {
+  pub fn new(inner: T, key: &ButtplugRelayKey, role: ButtplugRelayRole) -> Self {
+    Self {
+      state: Arc::new(RelayLinkState {
+        inner,
+        key: key.clone(),
+        role,
+        handle: OnceCell::new(),
+      }),
+    }
+  }
+
+  /// Transport for one channel of the link, to hand to a connector. Each channel id can only be
+  /// connected once at a time.
+  pub fn channel(&self, channel_id: u16) -> ButtplugRelayChannelTransport<T> {
+    ButtplugRelayChannelTransport {
+      link: self.state.clone(),
+      channel_id,
+    }
+  }
+}
+
+/// A single connection multiplexed over a [ButtplugRelayLink].
+pub struct ButtplugRelayChannelTransport<T: ButtplugConnectorTransport> {
+  link: Arc<RelayLinkState<T>>,
+  channel_id: u16,
+}
+
+impl<T: ButtplugConnectorTransport + 'static> ButtplugConnectorTransport
+  for ButtplugRelayChannelTransport<T>
+{
+  fn connect(
+    &self,
+    mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let link = self.link.clone();
+    let channel_id = self.channel_id;
+    async move {
+      let handle = link.handle.get_or_try_init(|| connect_link(&link)).await?;
+      {
+        let mut channels = handle.channels.lock().expect("Lock is never poisoned");
+        if channels.contains_key(&channel_id) {
+          return Err(relay_error(format!(
+            "Relay channel {} is already connected",
+            channel_id
+          )));
+        }
+        channels.insert(channel_id, incoming_sender);
+      }
+      let link_sender = handle.outgoing.clone();
+      let channels = handle.channels.clone();
+      async_manager::spawn(async move {
+        while let Some(msg) = outgoing_receiver.recv().await {
+          if link_sender.send((channel_id, msg)).await.is_err() {
+            break;
+          }
+        }
+        channels
+          .lock()
+          .expect("Lock is never poisoned")
+          .remove(&channel_id);
+      });
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    if let Some(handle) = self.link.handle.get() {
+      handle
+        .channels
+        .lock()
+        .expect("Lock is never poisoned")
+        .remove(&self.channel_id);
+    }
+    async { Ok(()) }.boxed()
+  }
+}
+
+async fn connect_link<T: ButtplugConnectorTransport>(
+  state: &RelayLinkState<T>,
+) -> Result<RelayLinkHandle, ButtplugConnectorError> {
+  let (inner_sender, inner_outgoing_receiver) = channel(256);
+  let (inner_incoming_sender, mut inner_receiver) = channel(256);
+  state
+    .inner
+    .connect(inner_outgoing_receiver, inner_incoming_sender)
+    .await?;
+  let session_key =
+    run_handshake(&state.key, state.role, &inner_sender, &mut inner_receiver).await?;
+  info!("Relay link established.");
+  let (outgoing, outgoing_receiver) = channel(256);
+  let channels = RelayChannels::default();
+  async_manager::spawn(run_relay_link(
+    session_key,
+    state.role,
+    outgoing_receiver,
+    inner_sender,
+    inner_receiver,
+    channels.clone(),
+  ));
+  Ok(RelayLinkHandle { outgoing, channels })
+}
+
+async fn send_frame(
+  sender: &Sender<ButtplugSerializedMessage>,
+  frame: Vec<u8>,
+) -> Result<(), ButtplugConnectorError> {
+  sender
+    .send(ButtplugSerializedMessage::Binary(frame))
+    .await
+    .map_err(|_| ButtplugConnectorError::ConnectorChannelClosed)
+}
+
+async fn next_frame(
+  receiver: &mut Receiver<ButtplugTransportIncomingMessage>,
+) -> Result<Vec<u8>, ButtplugConnectorError> {
+  loop {
+    match receiver.recv().await {
+      Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(data))) => {
+        return Ok(data)
+      }
+      Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Text(_))) => {
+        return Err(relay_error(
+          "Received unauthenticated message during handshake",
+        ))
+      }
+      Some(ButtplugTransportIncomingMessage::Connected) => continue,
+      Some(ButtplugTransportIncomingMessage::Error(err))
+      | Some(ButtplugTransportIncomingMessage::Close(err)) => {
+        return Err(ButtplugConnectorError::ConnectorGenericError(err))
+      }
+      None => return Err(ButtplugConnectorError::ConnectorChannelClosed),
+    }
+  }
+}
+
+/// Authenticates the other side, returning the key for this session.
+///
+/// The client sends a hello with a random nonce (repeating it until it gets an answer), and the
+/// server answers with a hello carrying its own nonce. Hellos are MACed with the pre-shared key.
+/// Both sides derive the session key from the two nonces, and prove they did by sending a confirm
+/// MACed with it, so replaying an old handshake gets nowhere.
+async fn run_handshake(
+  key: &ButtplugRelayKey,
+  role: ButtplugRelayRole,
+  sender: &Sender<ButtplugSerializedMessage>,
+  receiver: &mut Receiver<ButtplugTransportIncomingMessage>,
+) -> Result<[u8; KEY_LEN], ButtplugConnectorError> {
+  let nonce: [u8; NONCE_LEN] = thread_rng().gen();
+  let mut hello = vec![FRAME_HELLO, role.id()];
+  hello.extend_from_slice(&nonce);
+  let tag = mac(&key.key, &[&hello]);
+  hello.extend_from_slice(&tag);
+  let confirm_for = |session_key: &[u8; KEY_LEN], role: ButtplugRelayRole| {
+    mac(session_key, &[b"confirm", &[role.id()]])
+  };
+
+  if role == ButtplugRelayRole::Client {
+    send_frame(sender, hello.clone()).await?;
+  }
+  let mut remote_nonce = None;
+  let mut session_key = None;
+  loop {
+    let frame = select! {
+      frame = next_frame(receiver).fuse() => frame?,
+      _ = sleep(HELLO_RETRY_INTERVAL).fuse() => {
+        if role == ButtplugRelayRole::Client && session_key.is_none() {
+          send_frame(sender, hello.clone()).await?;
+        }
+        continue;
+      }
+    };
+    match frame.first() {
+      Some(&FRAME_HELLO) => {
+        if frame.len() != HELLO_LEN + MAC_LEN
+          || !verify_mac(&key.key, &[&frame[..HELLO_LEN]], &frame[HELLO_LEN..])
+        {
+          return Err(relay_error(
+            "Remote failed authentication, check both sides' relay secrets",
+          ));
+        }
+        // A relay reflecting our own hello back would pass the MAC check.
+        if frame[1] != role.peer().id() {
+          return Err(relay_error(
+            "Received hello from the wrong side of the relay",
+          ));
+        }
+        let hello_nonce = frame[2..HELLO_LEN].to_vec();
+        if remote_nonce.as_ref() == Some(&hello_nonce) {
+          // Client retry crossing paths with our answer, answer again in case ours was lost.
+          if role == ButtplugRelayRole::Server {
+            send_frame(sender, hello.clone()).await?;
+          }
+          continue;
+        }
+        let derived = match role {
+          ButtplugRelayRole::Client => mac(&key.key, &[b"session", &nonce, &hello_nonce]),
+          ButtplugRelayRole::Server => mac(&key.key, &[b"session", &hello_nonce, &nonce]),
+        };
+        match role {
+          ButtplugRelayRole::Client => {
+            // Only the first answer counts, later ones are for our retries.
+            if session_key.is_some() {
+              continue;
+            }
+            let mut confirm = vec![FRAME_CONFIRM];
+            confirm.extend_from_slice(&confirm_for(&derived, role));
+            send_frame(sender, confirm).await?;
+          }
+          ButtplugRelayRole::Server => send_frame(sender, hello.clone()).await?,
+        }
+        remote_nonce = Some(hello_nonce);
+        session_key = Some(derived);
+      }
+      Some(&FRAME_CONFIRM) => {
+        let Some(session_key) = session_key else {
+          return Err(relay_error("Received confirm before hello"));
+        };
+        if !verify_mac(
+          &session_key,
+          &[b"confirm", &[role.peer().id()]],
+          &frame[1..],
+        ) {
+          return Err(relay_error(
+            "Remote failed authentication, check both sides' relay secrets",
+          ));
+        }
+        if role == ButtplugRelayRole::Server {
+          let mut confirm = vec![FRAME_CONFIRM];
+          confirm.extend_from_slice(&confirm_for(&session_key, role));
+          send_frame(sender, confirm).await?;
+        }
+        return Ok(session_key);
+      }
+      _ => return Err(relay_error("Received unexpected frame during handshake")),
+    }
+  }
+}
+
+fn seal_frame(
+  session_key: &[u8; KEY_LEN],
+  role: ButtplugRelayRole,
+  channel_id: u16,
+  sequence: u64,
+  msg: ButtplugSerializedMessage,
+) -> Vec<u8> {
+  let (payload_type, payload) = match msg {
+    ButtplugSerializedMessage::Text(text) => (PAYLOAD_TEXT, text.into_bytes()),
+    ButtplugSerializedMessage::Binary(data) => (PAYLOAD_BINARY, data),
+  };
+  let mut frame = Vec::with_capacity(DATA_HEADER_LEN + payload.len() + MAC_LEN);
+  frame.push(FRAME_DATA);
+  frame.extend_from_slice(&channel_id.to_be_bytes());
+  frame.extend_from_slice(&sequence.to_be_bytes());
+  frame.push(payload_type);
+  frame.extend_from_slice(&payload);
+  // The sender's role is part of the MAC, so the relay can't reflect our own frames back at us.
+  let tag = mac(session_key, &[&[role.id()], &frame]);
+  frame.extend_from_slice(&tag);
+  frame
+}
+
+/// Checks and unpacks a frame from the other side. Returns None for frames that can be ignored.
+fn open_frame(
+  session_key: &[u8; KEY_LEN],
+  sender_role: ButtplugRelayRole,
+  expected_sequence: u64,
+  frame: &[u8],
+) -> Result<Option<(u16, ButtplugSerializedMessage)>, ButtplugConnectorError> {
+  match frame.first() {
+    // Hello retries from the handshake can still be in flight.
+    Some(&FRAME_HELLO) => return Ok(None),
+    Some(&FRAME_DATA) if frame.len() >= DATA_HEADER_LEN + MAC_LEN => {}
+    _ => return Err(relay_error("Relay injected a malformed frame")),
+  }
+  let (body, tag) = frame.split_at(frame.len() - MAC_LEN);
+  if !verify_mac(session_key, &[&[sender_role.id()], body], tag) {
+    return Err(relay_error("Relay injected or modified a frame"));
+  }
+  let sequence = u64::from_be_bytes(body[3..11].try_into().expect("Length checked above"));
+  if sequence != expected_sequence {
+    return Err(relay_error(format!(
+      "Relay replayed, dropped or reordered frames (expected frame {}, got {})",
+      expected_sequence, sequence
+    )));
+  }
+  let channel_id = u16::from_be_bytes([body[1], body[2]]);
+  let payload = body[DATA_HEADER_LEN..].to_vec();
+  let msg = match body[11] {
+    PAYLOAD_TEXT => ButtplugSerializedMessage::Text(
+      String::from_utf8(payload).map_err(|_| relay_error("Received invalid text message"))?,
+    ),
+    PAYLOAD_BINARY => ButtplugSerializedMessage::Binary(payload),
+    _ => return Err(relay_error("Received unknown payload type")),
+  };
+  Ok(Some((channel_id, msg)))
+}
+
+async fn close_channels(channels: &RelayChannels, reason: String) {
+  let senders: Vec<_> = channels
+    .lock()
+    .expect("Lock is never poisoned")
+    .drain()
+    .map(|(_, sender)| sender)
+    .collect();
+  for sender in senders {
+    let _ = sender
+      .send(ButtplugTransportIncomingMessage::Close(reason.clone()))
+      .await;
+  }
+}
+
+async fn run_relay_link(
+  session_key: [u8; KEY_LEN],
+  role: ButtplugRelayRole,
+  mut outgoing_receiver: Receiver<(u16, ButtplugSerializedMessage)>,
+  inner_sender: Sender<ButtplugSerializedMessage>,
+  mut inner_receiver: Receiver<ButtplugTransportIncomingMessage>,
+  channels: RelayChannels,
+) {
+  let mut send_sequence = 0u64;
+  let mut receive_sequence = 0u64;
+  // Dropping the inner sender on return tells the wrapped transport to close too.
+  loop {
+    select! {
+      outgoing = outgoing_receiver.recv().fuse() => {
+        let Some((channel_id, msg)) = outgoing else {
+          return;
+        };
+        let frame = seal_frame(&session_key, role, channel_id, send_sequence, msg);
+        send_sequence += 1;
+        if inner_sender.send(ButtplugSerializedMessage::Binary(frame)).await.is_err() {
+          close_channels(&channels, "Relay connection closed".to_owned()).await;
+          return;
+        }
+      }
+      incoming = inner_receiver.recv().fuse() => {
+        let result = match incoming {
+          Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(data))) => {
+            open_frame(&session_key, role.peer(), receive_sequence, &data)
+          }
+          Some(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Text(_))) => {
+            Err(relay_error("Relay injected an unauthenticated message"))
+          }
+          Some(ButtplugTransportIncomingMessage::Connected) => continue,
+          Some(ButtplugTransportIncomingMessage::Error(err))
+          | Some(ButtplugTransportIncomingMessage::Close(err)) => {
+            close_channels(&channels, err).await;
+            return;
+          }
+          None => {
+            close_channels(&channels, "Relay connection closed".to_owned()).await;
+            return;
+          }
+        };
+        match result {
+          Ok(Some((channel_id, msg))) => {
+            receive_sequence += 1;
+            let sender = channels
+              .lock()
+              .expect("Lock is never poisoned")
+              .get(&channel_id)
+              .cloned();
+            let Some(sender) = sender else {
+              warn!("Received message for unconnected relay channel {}, dropping.", channel_id);
+              continue;
+            };
+            if sender.send(ButtplugTransportIncomingMessage::Message(msg)).await.is_err() {
+              channels.lock().expect("Lock is never poisoned").remove(&channel_id);
+            }
+          }
+          Ok(None) => {}
+          // Once the relay has tampered with the stream, nothing more from it can be trusted.
+          Err(err) => {
+            error!("{}, closing relay link.", err);
+            close_channels(&channels, err.to_string()).await;
+            return;
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  /// Stands in for a relay server, passing messages between two transports. Keeps a copy of
+  /// everything the client sends, and can inject messages toward the server.
+  struct TestRelayTransport {
+    sender: Mutex<Option<Sender<ButtplugSerializedMessage>>>,
+    receiver: Mutex<Option<Receiver<ButtplugSerializedMessage>>>,
+    observed: Option<Sender<ButtplugSerializedMessage>>,
+  }
+
+  impl ButtplugConnectorTransport for TestRelayTransport {
+    fn connect(
+      &self,
+      mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+      incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+    ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+      let sender = self
+        .sender
+        .lock()
+        .expect("Test, assuming infallible.")
+        .take()
+        .expect("Test, assuming infallible.");
+      let mut receiver = self
+        .receiver
+        .lock()
+        .expect("Test, assuming infallible.")
+        .take()
+        .expect("Test, assuming infallible.");
+      let observed = self.observed.clone();
+      async_manager::spawn(async move {
+        while let Some(msg) = outgoing_receiver.recv().await {
+          if let Some(observed) = &observed {
+            let _ = observed.send(msg.clone()).await;
+          }
+          if sender.send(msg).await.is_err() {
+            break;
+          }
+        }
+      });
+      async_manager::spawn(async move {
+        while let Some(msg) = receiver.recv().await {
+          if incoming_sender
+            .send(ButtplugTransportIncomingMessage::Message(msg))
+            .await
+            .is_err()
+          {
+            break;
+          }
+        }
+      });
+      async { Ok(()) }.boxed()
+    }
+
+    fn disconnect(self) -> ButtplugConnectorResultFuture {
+      async { Ok(()) }.boxed()
+    }
+  }
+
+  struct TestRelay {
+    client: TestRelayTransport,
+    server: TestRelayTransport,
+    observed: Receiver<ButtplugSerializedMessage>,
+    inject: Sender<ButtplugSerializedMessage>,
+  }
+
+  fn test_relay() -> TestRelay {
+    let (client_sender, server_receiver) = channel(256);
+    let (server_sender, client_receiver) = channel(256);
+    let (observed_sender, observed) = channel(256);
+    TestRelay {
+      inject: client_sender.clone(),
+      client: TestRelayTransport {
+        sender: Mutex::new(Some(client_sender)),
+        receiver: Mutex::new(Some(client_receiver)),
+        observed: Some(observed_sender),
+      },
+      server: TestRelayTransport {
+        sender: Mutex::new(Some(server_sender)),
+        receiver: Mutex::new(Some(server_receiver)),
+        observed: None,
+      },
+      observed,
+    }
+  }
+
+  struct ConnectedChannel {
+    sender: Sender<ButtplugSerializedMessage>,
+    receiver: Receiver<ButtplugTransportIncomingMessage>,
+  }
+
+  async fn connect_channel<T: ButtplugConnectorTransport>(
+    transport: T,
+  ) -> Result<ConnectedChannel, ButtplugConnectorError> {
+    let (sender, outgoing_receiver) = channel(256);
+    let (incoming_sender, receiver) = channel(256);
+    transport
+      .connect(outgoing_receiver, incoming_sender)
+      .await?;
+    Ok(ConnectedChannel { sender, receiver })
+  }
+
+  fn text(msg: &str) -> ButtplugSerializedMessage {
+    ButtplugSerializedMessage::Text(msg.to_owned())
+  }
+
+  async fn next_message(channel: &mut ConnectedChannel) -> ButtplugTransportIncomingMessage {
+    channel
+      .receiver
+      .recv()
+      .await
+      .expect("Test, assuming infallible.")
+  }
+
+  fn test_links(
+    relay: TestRelay,
+  ) -> (
+    ButtplugRelayLink<TestRelayTransport>,
+    ButtplugRelayLink<TestRelayTransport>,
+    Receiver<ButtplugSerializedMessage>,
+    Sender<ButtplugSerializedMessage>,
+  ) {
+    let key = ButtplugRelayKey::new(&ButtplugRelayKey::generate_secret())
+      .expect("Test, assuming infallible.");
+    (
+      ButtplugRelayLink::new(relay.client, &key, ButtplugRelayRole::Client),
+      ButtplugRelayLink::new(relay.server, &key, ButtplugRelayRole::Server),
+      relay.observed,
+      relay.inject,
+    )
+  }
+
+  #[test]
+  fn test_relay_key() {
+    assert!(ButtplugRelayKey::new("short").is_err());
+    let secret = ButtplugRelayKey::generate_secret();
+    assert_eq!(secret.len(), GENERATED_SECRET_LEN);
+    let key = ButtplugRelayKey::new(&secret).expect("Test, assuming infallible.");
+    assert_eq!(
+      key.key,
+      ButtplugRelayKey::new(&secret)
+        .expect("Test, assuming infallible.")
+        .key
+    );
+    assert!(!format!("{:?}", key).contains(&secret));
+  }
+
+  #[tokio::test]
+  async fn test_relay_multiplexed_round_trip() {
+    let (client_link, server_link, _observed, _inject) = test_links(test_relay());
+    let (client_one, server_one) = futures::join!(
+      connect_channel(client_link.channel(1)),
+      connect_channel(server_link.channel(1))
+    );
+    let (client_one, mut server_one) = (
+      client_one.expect("Test, assuming infallible."),
+      server_one.expect("Test, assuming infallible."),
+    );
+    let mut client_two = connect_channel(client_link.channel(2))
+      .await
+      .expect("Test, assuming infallible.");
+    let server_two = connect_channel(server_link.channel(2))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(connect_channel(client_link.channel(2)).await.is_err());
+
+    client_one
+      .sender
+      .send(text("one"))
+      .await
+      .expect("Test, assuming infallible.");
+    server_two
+      .sender
+      .send(ButtplugSerializedMessage::Binary(vec![1, 2, 3]))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      next_message(&mut server_one).await,
+      ButtplugTransportIncomingMessage::Message(msg) if msg == text("one")
+    ));
+    assert!(matches!(
+      next_message(&mut client_two).await,
+      ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(data)) if data == vec![1, 2, 3]
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_relay_wrong_secret() {
+    let relay = test_relay();
+    let client = ButtplugRelayLink::new(
+      relay.client,
+      &ButtplugRelayKey::new(&ButtplugRelayKey::generate_secret())
+        .expect("Test, assuming infallible."),
+      ButtplugRelayRole::Client,
+    );
+    let server = ButtplugRelayLink::new(
+      relay.server,
+      &ButtplugRelayKey::new(&ButtplugRelayKey::generate_secret())
+        .expect("Test, assuming infallible."),
+      ButtplugRelayRole::Server,
+    );
+    let (client_channel, server_channel) = futures::join!(
+      connect_channel(client.channel(0)),
+      connect_channel(server.channel(0))
+    );
+    assert!(client_channel.is_err());
+    assert!(server_channel.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_relay_detects_replayed_frame() {
+    let (client_link, server_link, mut observed, inject) = test_links(test_relay());
+    let (client_channel, server_channel) = futures::join!(
+      connect_channel(client_link.channel(0)),
+      connect_channel(server_link.channel(0))
+    );
+    let (client_channel, mut server_channel) = (
+      client_channel.expect("Test, assuming infallible."),
+      server_channel.expect("Test, assuming infallible."),
+    );
+    client_channel
+      .sender
+      .send(text("stop"))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      next_message(&mut server_channel).await,
+      ButtplugTransportIncomingMessage::Message(msg) if msg == text("stop")
+    ));
+    // The last thing the client sent is the data frame, which carries a valid MAC.
+    let mut last_frame = None;
+    while let Ok(frame) = observed.try_recv() {
+      last_frame = Some(frame);
+    }
+    inject
+      .send(last_frame.expect("Test, assuming infallible."))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      next_message(&mut server_channel).await,
+      ButtplugTransportIncomingMessage::Close(_)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_relay_detects_injected_frame() {
+    let (client_link, server_link, _observed, inject) = test_links(test_relay());
+    let (client_channel, server_channel) = futures::join!(
+      connect_channel(client_link.channel(0)),
+      connect_channel(server_link.channel(0))
+    );
+    let (_client_channel, mut server_channel) = (
+      client_channel.expect("Test, assuming infallible."),
+      server_channel.expect("Test, assuming infallible."),
+    );
+    let forged = seal_frame(
+      &[0u8; KEY_LEN],
+      ButtplugRelayRole::Client,
+      0,
+      0,
+      text("vibrate"),
+    );
+    inject
+      .send(ButtplugSerializedMessage::Binary(forged))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(matches!(
+      next_message(&mut server_channel).await,
+      ButtplugTransportIncomingMessage::Close(_)
+    ));
+  }
+}
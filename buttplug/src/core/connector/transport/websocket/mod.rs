@@ -8,12 +8,23 @@
 //! Websocket connector for client/server communication
 
 pub mod websocket_client;
+#[cfg(feature = "websocket-mdns")]
+mod websocket_mdns;
 pub mod websocket_server;
+mod websocket_server_stream;
+#[cfg(feature = "websocket-ssdp")]
+mod websocket_ssdp;
 
 pub use tokio_tungstenite::tungstenite::Error as TungsteniteError;
 pub use websocket_client::ButtplugWebsocketClientTransport;
+#[cfg(feature = "websocket-mdns")]
+pub use websocket_mdns::BUTTPLUG_MDNS_SERVICE_TYPE;
 
 pub use websocket_server::{
   ButtplugWebsocketServerTransport,
   ButtplugWebsocketServerTransportBuilder,
 };
+pub use websocket_server_stream::ButtplugWebsocketServerStream;
+pub(crate) use websocket_server_stream::WebsocketServerAcceptor;
+#[cfg(feature = "websocket-ssdp")]
+pub use websocket_ssdp::BUTTPLUG_SSDP_SEARCH_TARGET;
@@ -0,0 +1,80 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! mDNS/DNS-SD advertisement of the websocket server, so clients on the local network (i.e. phone
+//! apps) can find it without users having to type in an IP address.
+
+use crate::core::message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+/// DNS-SD service type clients browse for.
+pub const BUTTPLUG_MDNS_SERVICE_TYPE: &str = "_buttplug._tcp.local.";
+
+/// Advertises the server for as long as it's alive.
+pub(super) struct MdnsAdvertisement {
+  daemon: ServiceDaemon,
+  fullname: String,
+}
+
+impl MdnsAdvertisement {
+  /// Start advertising a server listening on `port`. Advertisement is a convenience, so failures
+  /// are logged and return None rather than stopping the server from listening.
+  pub fn start(instance_name: &str, port: u16) -> Option<Self> {
+    let daemon = match ServiceDaemon::new() {
+      Ok(daemon) => daemon,
+      Err(err) => {
+        warn!(
+          "Cannot start mDNS daemon, server will not be advertised: {}",
+          err
+        );
+        return None;
+      }
+    };
+    // The host name only has to be unique on the local network, and a random one saves asking the
+    // OS, which differs per platform.
+    let host_name = format!("buttplug-{:08x}.local.", rand::random::<u32>());
+    let spec_version = (BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION as u32).to_string();
+    let service_info = match ServiceInfo::new(
+      BUTTPLUG_MDNS_SERVICE_TYPE,
+      instance_name,
+      &host_name,
+      (),
+      port,
+      &[("spec", spec_version.as_str())][..],
+    ) {
+      Ok(info) => info.enable_addr_auto(),
+      Err(err) => {
+        warn!(
+          "Cannot create mDNS service info, server will not be advertised: {}",
+          err
+        );
+        let _ = daemon.shutdown();
+        return None;
+      }
+    };
+    let fullname = service_info.get_fullname().to_owned();
+    if let Err(err) = daemon.register(service_info) {
+      warn!(
+        "Cannot register mDNS service, server will not be advertised: {}",
+        err
+      );
+      let _ = daemon.shutdown();
+      return None;
+    }
+    info!("Advertising websocket server via mDNS as {}", fullname);
+    Some(Self { daemon, fullname })
+  }
+}
+
+impl Drop for MdnsAdvertisement {
+  fn drop(&mut self) {
+    debug!("Removing mDNS advertisement {}", self.fullname);
+    // The daemon handles commands in order, so the goodbye packets go out before it shuts down.
+    let _ = self.daemon.unregister(&self.fullname);
+    let _ = self.daemon.shutdown();
+  }
+}
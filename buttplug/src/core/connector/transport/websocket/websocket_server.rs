@@ -5,6 +5,8 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+#[cfg(feature = "websocket-tls")]
+use crate::util::certificate::SelfSignedCertificate;
 use crate::{
   core::{
     connector::{
@@ -12,18 +14,20 @@ use crate::{
         ButtplugConnectorTransport,
         ButtplugConnectorTransportSpecificError,
         ButtplugTransportIncomingMessage,
+        ButtplugWebsocketServerStream,
+        WebsocketServerAcceptor,
       },
       ButtplugConnectorError,
       ButtplugConnectorResultFuture,
     },
     message::serializer::ButtplugSerializedMessage,
   },
-  util::async_manager,
+  util::{async_manager, environment::environment_override},
 };
 use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
-use std::{sync::Arc, time::Duration};
+use std::{fmt::Display, str::FromStr, sync::Arc, time::Duration};
 use tokio::{
-  net::{TcpListener, TcpStream},
+  net::TcpListener,
   sync::{
     mpsc::{Receiver, Sender},
     Notify,
@@ -31,12 +35,36 @@ use tokio::{
   time::sleep,
 };
 
+// Only called while building the transport, so the size of the error doesn't matter.
+#[allow(clippy::result_large_err)]
+fn connector_environment_override<T>(name: &str) -> Result<Option<T>, ButtplugConnectorError>
+where
+  T: FromStr,
+  T::Err: Display,
+{
+  environment_override(name).map_err(|err| {
+    ButtplugConnectorError::ConnectorGenericError(format!(
+      "Environment variable {} could not be applied: {}",
+      name, err
+    ))
+  })
+}
+
 #[derive(Clone, Debug)]
 pub struct ButtplugWebsocketServerTransportBuilder {
   /// If true, listens all on available interfaces. Otherwise, only listens on 127.0.0.1.
   listen_on_all_interfaces: bool,
   /// Insecure port for listening for websocket connections.
   port: u16,
+  /// Instance name to advertise the server under via mDNS, if advertising.
+  #[cfg(feature = "websocket-mdns")]
+  mdns_instance_name: Option<String>,
+  /// Server name to answer SSDP searches with, if answering.
+  #[cfg(feature = "websocket-ssdp")]
+  ssdp_server_name: Option<String>,
+  /// Certificate to serve secure websockets with. If None, serves insecure websockets.
+  #[cfg(feature = "websocket-tls")]
+  tls_certificate: Option<SelfSignedCertificate>,
 }
 
 impl Default for ButtplugWebsocketServerTransportBuilder {
@@ -44,6 +72,12 @@ impl Default for ButtplugWebsocketServerTransportBuilder {
     Self {
       listen_on_all_interfaces: false,
       port: 12345,
+      #[cfg(feature = "websocket-mdns")]
+      mdns_instance_name: None,
+      #[cfg(feature = "websocket-ssdp")]
+      ssdp_server_name: None,
+      #[cfg(feature = "websocket-tls")]
+      tls_certificate: None,
     }
   }
 }
@@ -59,17 +93,69 @@ impl ButtplugWebsocketServerTransportBuilder {
     self
   }
 
+  /// Advertise the server via mDNS/DNS-SD (service type `_buttplug._tcp`, with the message spec
+  /// version in the `spec` TXT record) under the given instance name, while it's waiting for a
+  /// client to connect. Only takes effect when listening on all interfaces, as clients on other
+  /// machines couldn't connect otherwise.
+  #[cfg(feature = "websocket-mdns")]
+  pub fn advertise_mdns(&mut self, instance_name: &str) -> &mut Self {
+    self.mdns_instance_name = Some(instance_name.to_owned());
+    self
+  }
+
+  /// Answer SSDP searches (search target `urn:buttplug-io:service:buttplug:1`, or `ssdp:all`) with
+  /// the address of the server while it's waiting for a client to connect, for networks where mDNS
+  /// doesn't get through. The server name is sent in the `X-BUTTPLUG-NAME` header. Like mDNS
+  /// advertisement, only takes effect when listening on all interfaces.
+  #[cfg(feature = "websocket-ssdp")]
+  pub fn advertise_ssdp(&mut self, server_name: &str) -> &mut Self {
+    self.ssdp_server_name = Some(server_name.to_owned());
+    self
+  }
+
+  /// Serve secure websockets (`wss://`) using the given certificate, i.e. one from
+  /// [load_or_generate_certificate](crate::util::certificate::load_or_generate_certificate).
+  #[cfg(feature = "websocket-tls")]
+  pub fn tls_certificate(&mut self, certificate: SelfSignedCertificate) -> &mut Self {
+    self.tls_certificate = Some(certificate);
+    self
+  }
+
+  /// Override settings from environment variables, for container and headless deployments.
+  /// Variables that aren't set leave the current settings alone.
+  ///
+  /// - `BUTTPLUG_WEBSOCKET_PORT`: Port to listen on.
+  /// - `BUTTPLUG_WEBSOCKET_ALL_INTERFACES`: `true` or `false`, whether to listen on all interfaces.
+  #[allow(clippy::result_large_err)]
+  pub fn environment_overrides(&mut self) -> Result<&mut Self, ButtplugConnectorError> {
+    if let Some(port) = connector_environment_override("BUTTPLUG_WEBSOCKET_PORT")? {
+      self.port = port;
+    }
+    if let Some(listen_on_all_interfaces) =
+      connector_environment_override("BUTTPLUG_WEBSOCKET_ALL_INTERFACES")?
+    {
+      self.listen_on_all_interfaces = listen_on_all_interfaces;
+    }
+    Ok(self)
+  }
+
   pub fn finish(&self) -> ButtplugWebsocketServerTransport {
     ButtplugWebsocketServerTransport {
       port: self.port,
       listen_on_all_interfaces: self.listen_on_all_interfaces,
       disconnect_notifier: Arc::new(Notify::new()),
+      #[cfg(feature = "websocket-mdns")]
+      mdns_instance_name: self.mdns_instance_name.clone(),
+      #[cfg(feature = "websocket-ssdp")]
+      ssdp_server_name: self.ssdp_server_name.clone(),
+      #[cfg(feature = "websocket-tls")]
+      tls_certificate: self.tls_certificate.clone(),
     }
   }
 }
 
 async fn run_connection_loop(
-  ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
+  ws_stream: tokio_tungstenite::WebSocketStream<ButtplugWebsocketServerStream>,
   mut request_receiver: Receiver<ButtplugSerializedMessage>,
   response_sender: Sender<ButtplugTransportIncomingMessage>,
   disconnect_notifier: Arc<Notify>,
@@ -198,6 +284,12 @@ pub struct ButtplugWebsocketServerTransport {
   port: u16,
   listen_on_all_interfaces: bool,
   disconnect_notifier: Arc<Notify>,
+  #[cfg(feature = "websocket-mdns")]
+  mdns_instance_name: Option<String>,
+  #[cfg(feature = "websocket-ssdp")]
+  ssdp_server_name: Option<String>,
+  #[cfg(feature = "websocket-tls")]
+  tls_certificate: Option<SelfSignedCertificate>,
 }
 
 impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
@@ -218,7 +310,33 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
     debug!("Websocket: Trying to listen on {}", addr);
     let response_sender_clone = incoming_sender;
     let disconnect_notifier_clone = disconnect_notifier;
+    #[cfg(feature = "websocket-mdns")]
+    let mdns_instance_name = if self.listen_on_all_interfaces {
+      self.mdns_instance_name.clone()
+    } else {
+      if self.mdns_instance_name.is_some() {
+        warn!("Websocket server only listening on localhost, not advertising via mDNS.");
+      }
+      None
+    };
+    #[cfg(feature = "websocket-ssdp")]
+    let ssdp_server_name = if self.listen_on_all_interfaces {
+      self.ssdp_server_name.clone()
+    } else {
+      if self.ssdp_server_name.is_some() {
+        warn!("Websocket server only listening on localhost, not answering SSDP searches.");
+      }
+      None
+    };
+    #[cfg(any(feature = "websocket-mdns", feature = "websocket-ssdp"))]
+    let port = self.port;
+    #[cfg(feature = "websocket-tls")]
+    let acceptor = WebsocketServerAcceptor::new(self.tls_certificate.as_ref())
+      .map_err(|err| ButtplugConnectorError::ConnectorGenericError(err.to_string()));
+    #[cfg(not(feature = "websocket-tls"))]
+    let acceptor: Result<_, ButtplugConnectorError> = Ok(WebsocketServerAcceptor::default());
     let fut = async move {
+      let acceptor = acceptor?;
       // Create the event loop and TCP listener we'll accept connections on.
       let try_socket = TcpListener::bind(&addr).await;
       debug!("Websocket: Socket bound.");
@@ -228,16 +346,21 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
         )
       })?;
       debug!("Websocket: Listening on: {}", addr);
+      // Only advertise while we're waiting for a client, we can't take another one after that.
+      #[cfg(feature = "websocket-mdns")]
+      let _mdns_advertisement = mdns_instance_name
+        .and_then(|name| super::websocket_mdns::MdnsAdvertisement::start(&name, port));
+      #[cfg(feature = "websocket-ssdp")]
+      let _ssdp_responder =
+        ssdp_server_name.and_then(|name| super::websocket_ssdp::SsdpResponder::start(&name, port));
       if let Ok((stream, _)) = listener.accept().await {
         info!("Websocket: Got connection");
-        let ws_stream = tokio_tungstenite::accept_async(stream)
-          .await
-          .map_err(|err| {
-            error!("Websocket server accept error: {:?}", err);
-            ButtplugConnectorError::TransportSpecificError(
-              ButtplugConnectorTransportSpecificError::TungsteniteError(err),
-            )
-          })?;
+        let ws_stream = acceptor.accept(stream).await.map_err(|err| {
+          error!("Websocket server accept error: {:?}", err);
+          ButtplugConnectorError::TransportSpecificError(
+            ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+          )
+        })?;
         async_manager::spawn(async move {
           run_connection_loop(
             ws_stream,
@@ -0,0 +1,107 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Connections accepted by websocket servers, with or without TLS.
+
+#[cfg(feature = "websocket-tls")]
+use crate::util::certificate::{ButtplugCertificateError, SelfSignedCertificate};
+use std::{
+  io,
+  pin::Pin,
+  task::{Context, Poll},
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf},
+  net::TcpStream,
+};
+use tokio_tungstenite::{tungstenite::Error as TungsteniteError, WebSocketStream};
+
+/// Stream under a websocket accepted by a websocket server.
+pub enum ButtplugWebsocketServerStream {
+  Plain(TcpStream),
+  #[cfg(feature = "websocket-tls")]
+  Tls(Box<tokio_rustls::server::TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for ButtplugWebsocketServerStream {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+      #[cfg(feature = "websocket-tls")]
+      Self::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+    }
+  }
+}
+
+impl AsyncWrite for ButtplugWebsocketServerStream {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    match self.get_mut() {
+      Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+      #[cfg(feature = "websocket-tls")]
+      Self::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+    }
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+      #[cfg(feature = "websocket-tls")]
+      Self::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+    }
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+      #[cfg(feature = "websocket-tls")]
+      Self::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+    }
+  }
+}
+
+/// Turns accepted TCP connections into websockets, doing the TLS handshake first if the server has
+/// a certificate.
+#[derive(Clone, Default)]
+pub(crate) struct WebsocketServerAcceptor {
+  #[cfg(feature = "websocket-tls")]
+  tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+}
+
+impl WebsocketServerAcceptor {
+  #[cfg(feature = "websocket-tls")]
+  pub(crate) fn new(
+    certificate: Option<&SelfSignedCertificate>,
+  ) -> Result<Self, ButtplugCertificateError> {
+    let tls_acceptor = certificate
+      .map(|certificate| {
+        certificate
+          .server_config()
+          .map(tokio_rustls::TlsAcceptor::from)
+      })
+      .transpose()?;
+    Ok(Self { tls_acceptor })
+  }
+
+  pub(crate) async fn accept(
+    &self,
+    stream: TcpStream,
+  ) -> Result<WebSocketStream<ButtplugWebsocketServerStream>, TungsteniteError> {
+    #[cfg(feature = "websocket-tls")]
+    if let Some(tls_acceptor) = &self.tls_acceptor {
+      let tls_stream = tls_acceptor.accept(stream).await?;
+      return tokio_tungstenite::accept_async(ButtplugWebsocketServerStream::Tls(Box::new(
+        tls_stream,
+      )))
+      .await;
+    }
+    tokio_tungstenite::accept_async(ButtplugWebsocketServerStream::Plain(stream)).await
+  }
+}
@@ -0,0 +1,272 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! SSDP (the discovery part of UPnP) responder for the websocket server. Does the same job as
+//! mDNS advertisement, for networks where mDNS is blocked or unsupported, which includes a lot of
+//! Windows home setups.
+//!
+//! We only implement as much of SSDP as discovery needs: answering M-SEARCH requests for our search
+//! target (or `ssdp:all`), and announcing the server when it comes up and goes away. There's no
+//! UPnP device description, LOCATION points straight at the websocket server.
+
+use crate::{core::message::BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION, util::async_manager};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+  io,
+  net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+  sync::Arc,
+};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+/// SSDP search target clients search for.
+pub const BUTTPLUG_SSDP_SEARCH_TARGET: &str = "urn:buttplug-io:service:buttplug:1";
+
+const SSDP_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// Seconds clients can cache our responses for.
+const SSDP_MAX_AGE: u32 = 1800;
+
+/// Search request, parsed only as far as we need to answer it.
+#[derive(Debug, PartialEq, Eq)]
+struct SearchRequest {
+  search_target: String,
+}
+
+fn parse_search_request(packet: &str) -> Option<SearchRequest> {
+  let mut lines = packet.lines();
+  if !lines
+    .next()?
+    .trim()
+    .eq_ignore_ascii_case("M-SEARCH * HTTP/1.1")
+  {
+    return None;
+  }
+  let mut is_discover = false;
+  let mut search_target = None;
+  for line in lines {
+    let Some((name, value)) = line.split_once(':') else {
+      continue;
+    };
+    let value = value.trim();
+    match name.trim().to_ascii_uppercase().as_str() {
+      "MAN" => is_discover = value.trim_matches('"') == "ssdp:discover",
+      "ST" => search_target = Some(value.to_owned()),
+      _ => {}
+    }
+  }
+  if !is_discover {
+    return None;
+  }
+  Some(SearchRequest {
+    search_target: search_target?,
+  })
+}
+
+/// Local address we'd use to talk to `peer`, which is the address to tell `peer` to connect to.
+fn local_address_for(peer: &SocketAddr) -> io::Result<IpAddr> {
+  // Connecting a UDP socket doesn't send anything, it only picks a route.
+  let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+  socket.connect(peer)?;
+  Ok(socket.local_addr()?.ip())
+}
+
+fn bind_multicast_socket() -> io::Result<UdpSocket> {
+  let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+  // Other SSDP responders (including the one built into Windows) usually have the port already.
+  socket.set_reuse_address(true)?;
+  #[cfg(unix)]
+  socket.set_reuse_port(true)?;
+  socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, SSDP_PORT).into())?;
+  socket.join_multicast_v4(&SSDP_MULTICAST_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+  socket.set_nonblocking(true)?;
+  UdpSocket::from_std(socket.into())
+}
+
+struct SsdpService {
+  server_name: String,
+  port: u16,
+  unique_service_name: String,
+}
+
+impl SsdpService {
+  fn headers(&self, location_ip: IpAddr) -> String {
+    format!(
+      "CACHE-CONTROL: max-age={}\r\n\
+       LOCATION: ws://{}:{}\r\n\
+       SERVER: Buttplug/{} UPnP/1.1\r\n\
+       USN: {}\r\n\
+       X-BUTTPLUG-NAME: {}\r\n\
+       X-BUTTPLUG-SPEC: {}\r\n",
+      SSDP_MAX_AGE,
+      location_ip,
+      self.port,
+      env!("CARGO_PKG_VERSION"),
+      self.unique_service_name,
+      self.server_name,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION as u32,
+    )
+  }
+
+  fn search_response(&self, location_ip: IpAddr) -> String {
+    format!(
+      "HTTP/1.1 200 OK\r\nEXT:\r\nST: {}\r\n{}\r\n",
+      BUTTPLUG_SSDP_SEARCH_TARGET,
+      self.headers(location_ip)
+    )
+  }
+
+  fn notify(&self, location_ip: IpAddr, alive: bool) -> String {
+    format!(
+      "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nNT: {}\r\nNTS: {}\r\n{}\r\n",
+      SSDP_MULTICAST_ADDRESS,
+      SSDP_PORT,
+      BUTTPLUG_SSDP_SEARCH_TARGET,
+      if alive { "ssdp:alive" } else { "ssdp:byebye" },
+      self.headers(location_ip)
+    )
+  }
+
+  fn matches(&self, request: &SearchRequest) -> bool {
+    request.search_target == "ssdp:all" || request.search_target == BUTTPLUG_SSDP_SEARCH_TARGET
+  }
+}
+
+fn send_notify(service: &SsdpService, alive: bool) {
+  let multicast_address = SocketAddr::V4(SocketAddrV4::new(SSDP_MULTICAST_ADDRESS, SSDP_PORT));
+  let result = local_address_for(&multicast_address).and_then(|local_ip| {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(
+      service.notify(local_ip, alive).as_bytes(),
+      multicast_address,
+    )
+  });
+  if let Err(err) = result {
+    debug!("Cannot send SSDP notification: {}", err);
+  }
+}
+
+async fn run_responder(
+  socket: UdpSocket,
+  service: &SsdpService,
+  cancellation_token: CancellationToken,
+) {
+  let mut buffer = [0u8; 2048];
+  loop {
+    let (len, peer) = tokio::select! {
+      _ = cancellation_token.cancelled() => return,
+      result = socket.recv_from(&mut buffer) => match result {
+        Ok(received) => received,
+        Err(err) => {
+          warn!("SSDP socket error, stopping responder: {}", err);
+          return;
+        }
+      }
+    };
+    let Some(request) = std::str::from_utf8(&buffer[..len])
+      .ok()
+      .and_then(parse_search_request)
+    else {
+      continue;
+    };
+    if !service.matches(&request) {
+      continue;
+    }
+    trace!("Answering SSDP search from {}", peer);
+    let response = match local_address_for(&peer) {
+      Ok(local_ip) => service.search_response(local_ip),
+      Err(err) => {
+        debug!("Cannot find local address for SSDP peer {}: {}", peer, err);
+        continue;
+      }
+    };
+    if let Err(err) = socket.send_to(response.as_bytes(), peer).await {
+      debug!("Cannot send SSDP response to {}: {}", peer, err);
+    }
+  }
+}
+
+/// Answers SSDP searches for the server for as long as it's alive.
+pub(super) struct SsdpResponder {
+  service: Arc<SsdpService>,
+  cancellation_token: CancellationToken,
+}
+
+impl SsdpResponder {
+  /// Start answering searches for a server listening on `port`. Like mDNS advertisement, failures
+  /// are logged and return None rather than stopping the server from listening.
+  pub fn start(server_name: &str, port: u16) -> Option<Self> {
+    let socket = match bind_multicast_socket() {
+      Ok(socket) => socket,
+      Err(err) => {
+        warn!(
+          "Cannot listen for SSDP searches, server will not be discoverable via SSDP: {}",
+          err
+        );
+        return None;
+      }
+    };
+    let uuid = uuid::Builder::from_random_bytes(rand::random()).into_uuid();
+    let service = Arc::new(SsdpService {
+      server_name: server_name.to_owned(),
+      port,
+      unique_service_name: format!("uuid:{}::{}", uuid, BUTTPLUG_SSDP_SEARCH_TARGET),
+    });
+    let cancellation_token = CancellationToken::new();
+    let responder_service = service.clone();
+    let responder_token = cancellation_token.child_token();
+    async_manager::spawn(async move {
+      run_responder(socket, &responder_service, responder_token).await;
+    });
+    send_notify(&service, true);
+    info!(
+      "Answering SSDP searches for websocket server on port {}",
+      port
+    );
+    Some(Self {
+      service,
+      cancellation_token,
+    })
+  }
+}
+
+impl Drop for SsdpResponder {
+  fn drop(&mut self) {
+    debug!("Stopping SSDP responder");
+    self.cancellation_token.cancel();
+    send_notify(&self.service, false);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_search_request() {
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 1\r\n\
+                   ST: urn:buttplug-io:service:buttplug:1\r\n\r\n";
+    assert_eq!(
+      parse_search_request(request),
+      Some(SearchRequest {
+        search_target: BUTTPLUG_SSDP_SEARCH_TARGET.to_owned()
+      })
+    );
+    // Not a discovery request.
+    assert_eq!(
+      parse_search_request("M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\n\r\n"),
+      None
+    );
+    assert_eq!(
+      parse_search_request("NOTIFY * HTTP/1.1\r\nNT: ssdp:all\r\n\r\n"),
+      None
+    );
+  }
+}
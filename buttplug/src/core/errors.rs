@@ -15,13 +15,16 @@ use super::message::{
   ButtplugMessageSpecVersion,
   Endpoint,
   ErrorCode,
+  RotationMode,
   SensorType,
 };
 #[cfg(feature = "server")]
 use crate::server::device::hardware::communication::HardwareSpecificError;
 use displaydoc::Display;
 use futures::future::BoxFuture;
+use getset::Getters;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
 
 pub type ButtplugResult<T = ()> = Result<T, ButtplugError>;
@@ -80,6 +83,10 @@ pub enum ButtplugMessageError {
   UnhandledMessage(String),
   /// Message validation error(s): {0}
   ValidationError(String),
+  /// {0} requires the {1} connection scope
+  InsufficientScope(String, String),
+  /// {0} is not allowed on this server
+  MessageNotAllowed(String),
   /// Message serialization error
   #[error(transparent)]
   MessageSerializationError(#[from] ButtplugSerializerError),
@@ -157,6 +164,8 @@ pub enum ButtplugDeviceError {
   DeviceScanningAlreadyStarted,
   /// Device scanning already stopped.
   DeviceScanningAlreadyStopped,
+  /// Device scanning has been disabled by a server administrator.
+  DeviceScanningDisabled,
   /// Device permission error: {0}
   DevicePermissionError(String),
   /// {0}
@@ -173,12 +182,92 @@ pub enum ButtplugDeviceError {
   UntypedDeserializedError(String),
   /// Device Configuration Error: {0}
   DeviceConfigurationError(String),
+  /// Device configuration does not match schema: {0}
+  DeviceConfigurationValidationError(DeviceConfigurationValidationError),
   /// Actuator Type Mismatch: Index {0} got command for {1}, but expects {2}
   DeviceActuatorTypeMismatch(String, ActuatorType, ActuatorType),
   /// Sensor Type Mismatch: Index {0} got command for {1}, but expects {2}
   DeviceSensorTypeMismatch(u32, SensorType, SensorType),
+  /// Rotation Mode Mismatch: Index {0} got command for a {1} rotator, but is {2}
+  DeviceRotationModeMismatch(u32, RotationMode, RotationMode),
   /// Protocol does not have an implementation available for Sensor Type {0}
   ProtocolSensorNotSupported(SensorType),
+  /// No funscript loaded for device {0}
+  FunscriptNotLoaded(u32),
+  /// No pattern loaded for device {0}
+  PatternNotLoaded(u32),
+  /// Scheduled command for device {0} was cancelled by a stop command
+  ScheduledCommandCancelled(u32),
+  /// Device {0} is locked by another client
+  DeviceLocked(u32),
+  /// Command queue for device {0} is full
+  DeviceCommandQueueFull(String),
+  /// Device {0} is updating its firmware
+  DeviceFirmwareUpdating(String),
+  /// Firmware update failed: {0}
+  DeviceFirmwareUpdateError(String),
+  /// Command {0} was cancelled before it reached the device
+  DeviceCommandCancelled(u32),
+}
+
+/// A single schema violation found while loading a device configuration file.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get = "pub")]
+pub struct DeviceConfigurationIssue {
+  /// JSON pointer to the offending value, i.e. "/user-configs/devices/0/config/index". TOML
+  /// configurations use the same paths, as they're converted to JSON before validation.
+  path: String,
+  /// The offending value, serialized as JSON.
+  value: String,
+  /// What the schema expects at this path, i.e. "integer" or "property \"version\"".
+  expected: String,
+}
+
+impl DeviceConfigurationIssue {
+  pub fn new(path: &str, value: &str, expected: &str) -> Self {
+    Self {
+      path: path.to_owned(),
+      value: value.to_owned(),
+      expected: expected.to_owned(),
+    }
+  }
+}
+
+impl fmt::Display for DeviceConfigurationIssue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let path = if self.path.is_empty() {
+      "(root)"
+    } else {
+      &self.path
+    };
+    write!(
+      f,
+      "{}: expected {}, got {}",
+      path, self.expected, self.value
+    )
+  }
+}
+
+/// All schema violations found while loading a device configuration file.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get = "pub")]
+pub struct DeviceConfigurationValidationError {
+  issues: Vec<DeviceConfigurationIssue>,
+}
+
+impl DeviceConfigurationValidationError {
+  pub fn new(issues: Vec<DeviceConfigurationIssue>) -> Self {
+    Self { issues }
+  }
+}
+
+impl fmt::Display for DeviceConfigurationValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let issues: Vec<String> = self.issues.iter().map(|issue| issue.to_string()).collect();
+    write!(f, "{}", issues.join("; "))
+  }
 }
 
 /// Unknown errors occur in exceptional circumstances where no other error type
@@ -203,6 +292,10 @@ pub enum ButtplugUnknownError {
   UntypedDeserializedError(String),
   /// Device Manager has been shut down by its owning server and is no longer available.
   DeviceManagerNotRunning,
+  /// Client was disconnected by a server administrator.
+  ClientKicked,
+  /// Server is shutting down.
+  ServerShuttingDown,
 }
 
 /// Aggregation enum for protocol error types.
@@ -11,7 +11,10 @@ use serde::{Deserialize, Serialize};
 
 /// Battery level request
 #[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct BatteryLevelCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -12,7 +12,10 @@ use serde::{Deserialize, Serialize};
 
 /// Battery level response
 #[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct BatteryLevelReading {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -0,0 +1,63 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Cancel a device command sent earlier on the same connection, by its message id. Commands still
+/// waiting for the device are dropped and answered with an error. Commands already being written to
+/// the hardware finish, but are also answered with an error. Cancelling a command that has already
+/// been answered (or never existed) does nothing, and still gets an Ok back.
+///
+/// Only available if the [Cancellation](ButtplugCapability::Cancellation) capability was agreed on
+/// during the handshake.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct CancelCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "MessageId"))]
+  #[getset(get_copy = "pub")]
+  message_id: u32,
+}
+
+impl CancelCmd {
+  pub fn new(message_id: u32) -> Self {
+    Self { id: 1, message_id }
+  }
+}
+
+impl ButtplugMessageValidator for CancelCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    self.is_not_system_id(self.message_id)
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::CancelCmd;
+
+  #[test]
+  fn test_cancel_cmd_json() {
+    let json = r#"{"Id":2,"MessageId":1}"#;
+    let mut expected = CancelCmd::new(1);
+    expected.id = 2;
+    let msg: CancelCmd = serde_json::from_str(json).expect("Test, assuming infallible");
+    assert_eq!(msg, expected);
+    assert_eq!(
+      serde_json::to_string(&msg).expect("Test, assuming infallible"),
+      json
+    );
+  }
+}
@@ -0,0 +1,114 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Optional protocol features negotiated during the handshake.
+//!
+//! The message spec version covers the whole protocol at once, so shipping a single new feature
+//! used to mean bumping it. Capabilities let client and server agree on features separately: the
+//! client lists what it would like to use in
+//! [RequestServerInfo](super::RequestServerInfo), and the server answers with the ones it also
+//! supports in [ServerInfo](super::ServerInfo). Capability names that a peer doesn't know are
+//! ignored, so newer peers can offer capabilities that older ones have never heard of.
+
+#[cfg(feature = "serialize-json")]
+use super::ButtplugJsonSchema;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(feature = "serialize-json")]
+use serde_json::Value;
+#[cfg(feature = "serialize-json")]
+use std::str::FromStr;
+
+/// Optional protocol feature, negotiated independently of the message spec version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Display, EnumString)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize))]
+pub enum ButtplugCapability {
+  /// Server side pattern playback, via [PatternLoadCmd](super::PatternLoadCmd) and friends.
+  PatternEngine,
+  /// Sensor reads and subscriptions.
+  Sensors,
+  /// Multiple messages sent in a single message array.
+  Batching,
+  /// Compressed message frames.
+  Compression,
+  /// Cancelling device commands that haven't reached the hardware yet, via
+  /// [CancelCmd](super::CancelCmd).
+  Cancellation,
+}
+
+/// Capabilities the library implements, requested by clients and offered by servers unless
+/// configured otherwise. Compression is left out, as none of the connectors support it yet.
+pub const BUTTPLUG_DEFAULT_CAPABILITIES: [ButtplugCapability; 4] = [
+  ButtplugCapability::PatternEngine,
+  ButtplugCapability::Sensors,
+  ButtplugCapability::Batching,
+  ButtplugCapability::Cancellation,
+];
+
+// Unknown capability names are accepted (and dropped) when reading, so the schema can't restrict
+// them to the names we know.
+#[cfg(feature = "serialize-json")]
+impl ButtplugJsonSchema for ButtplugCapability {
+  fn json_schema() -> Value {
+    String::json_schema()
+  }
+}
+
+/// Reads a capability list, skipping names we don't know instead of failing the whole message.
+#[cfg(feature = "serialize-json")]
+pub(super) fn deserialize_capabilities<'de, D>(
+  deserializer: D,
+) -> Result<Vec<ButtplugCapability>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let names = Vec::<String>::deserialize(deserializer)?;
+  let mut capabilities = vec![];
+  for name in names {
+    match ButtplugCapability::from_str(&name) {
+      Ok(capability) if !capabilities.contains(&capability) => capabilities.push(capability),
+      Ok(_) => {}
+      Err(_) => debug!("Ignoring unknown capability {}", name),
+    }
+  }
+  Ok(capabilities)
+}
+
+#[cfg(all(test, feature = "serialize-json"))]
+mod test {
+  use super::*;
+  use crate::core::message::{ButtplugMessageSpecVersion, RequestServerInfo};
+
+  #[test]
+  fn test_capability_json_conversion() {
+    let mut msg = RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3);
+    msg.set_capabilities(vec![
+      ButtplugCapability::PatternEngine,
+      ButtplugCapability::Compression,
+    ]);
+    let json = serde_json::to_string(&msg).expect("Test, assuming infallible.");
+    assert!(json.contains(r#""Capabilities":["PatternEngine","Compression"]"#));
+    assert_eq!(
+      serde_json::from_str::<RequestServerInfo>(&json).expect("Test, assuming infallible."),
+      msg
+    );
+  }
+
+  #[test]
+  fn test_unknown_capabilities_ignored() {
+    let json = r#"
+{
+        "Id": 1,
+        "ClientName": "Test Client",
+        "MessageVersion": 3,
+        "Capabilities": ["Sensors", "Teleportation", "Sensors"]
+}
+        "#;
+    let msg = serde_json::from_str::<RequestServerInfo>(json).expect("Test, assuming infallible.");
+    assert_eq!(*msg.capabilities(), vec![ButtplugCapability::Sensors]);
+  }
+}
@@ -7,34 +7,70 @@
 
 use crate::core::{
   errors::ButtplugDeviceError,
-  message::{ButtplugDeviceMessageType, Endpoint},
+  message::{
+    ButtplugDeviceMessageType,
+    ButtplugJsonSchema,
+    ButtplugMessageSpecVersion,
+    Endpoint,
+  },
 };
 use getset::{Getters, MutGetters, Setters};
 use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
 use std::ops::RangeInclusive;
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(
+  Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ButtplugJsonSchema,
+)]
 pub enum ActuatorType {
   Unknown,
   Vibrate,
   // Single Direction Rotation Speed
   Rotate,
   Oscillate,
+  // Suction, air pumps and pressure waves. Anything that squeezes or pulls rather than vibrates.
   Constrict,
   Inflate,
   // For instances where we specify a position to move to ASAP. Usually servos, probably for the
   // OSR-2/SR-6.
   Position,
+  // Heating elements. Levels are relative to the heater's step range, not actual temperatures, and
+  // may be capped by the device configuration for safety.
+  Temperature,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+// How a rotation feature moves. Continuous rotators are driven by speed and direction via
+// RotateCmd, positional rotators (usually servos, like TCode twist axes) are driven to an angle
+// within their range via RotatePositionCmd.
+#[derive(
+  Debug,
+  Display,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Hash,
+  Default,
+  Serialize,
+  Deserialize,
+  ButtplugJsonSchema,
+)]
+pub enum RotationMode {
+  #[default]
+  Continuous,
+  Positional,
+}
+
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Display,
+)]
 pub enum SensorType {
   Unknown,
   Battery,
   RSSI,
   Button,
   Pressure,
-  // Temperature,
+  Position,
+  Temperature,
   // Accelerometer,
   // Gyro,
 }
@@ -48,7 +84,10 @@ pub enum SensorType {
 // then we denote this by prefixing the type with Client/Server. Server attributes will usually be
 // hosted in the server/device/configuration module.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Getters, MutGetters, Setters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct ClientDeviceMessageAttributes {
   // Generic commands
   #[getset(get = "pub", get_mut = "pub(super)")]
@@ -230,14 +269,16 @@ impl ClientDeviceMessageAttributesBuilder {
   }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema)]
 pub struct NullDeviceMessageAttributes {}
 
 fn unspecified_feature() -> String {
   "N/A".to_string()
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct ClientGenericDeviceMessageAttributes {
   #[getset(get = "pub")]
   #[serde(rename = "FeatureDescriptor")]
@@ -249,6 +290,12 @@ pub struct ClientGenericDeviceMessageAttributes {
   #[serde(rename = "StepCount")]
   #[getset(get = "pub")]
   step_count: u32,
+  // Only relevant for RotateCmd features. Omitted for continuous rotators to keep older clients
+  // happy.
+  #[getset(get = "pub", set = "pub")]
+  #[serde(rename = "RotationMode")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  rotation_mode: Option<RotationMode>,
   // TODO This needs to actually be part of the device info relayed to the client in spec v4.
   #[getset(get = "pub")]
   #[serde(skip, default)]
@@ -261,10 +308,16 @@ impl ClientGenericDeviceMessageAttributes {
       feature_descriptor: feature_descriptor.to_owned(),
       actuator_type,
       step_count,
+      rotation_mode: None,
       index: 0,
     }
   }
 
+  /// True if this is a rotation feature driven to absolute positions, rather than by speed.
+  pub fn is_positional_rotator(&self) -> bool {
+    self.rotation_mode == Some(RotationMode::Positional)
+  }
+
   // This is created out of already verified server device message attributes, so we'll assume it's
   // fine.
   pub fn is_valid(&self, _: &ButtplugDeviceMessageType) -> Result<(), ButtplugDeviceError> {
@@ -272,7 +325,9 @@ impl ClientGenericDeviceMessageAttributes {
   }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct RawDeviceMessageAttributes {
   #[getset(get = "pub")]
   #[serde(rename = "Endpoints")]
@@ -301,7 +356,9 @@ where
   seq.end()
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct SensorDeviceMessageAttributes {
   #[getset(get = "pub")]
   #[serde(rename = "FeatureDescriptor")]
@@ -318,15 +375,146 @@ pub struct SensorDeviceMessageAttributes {
   index: u32,
 }
 
-/*
 impl SensorDeviceMessageAttributes {
-  pub fn new(feature_descriptor: &str, sensor_type: SensorType) -> Self {
-    Self { feature_descriptor: feature_descriptor.to_owned(), sensor_type }
+  pub fn new(
+    feature_descriptor: &str,
+    sensor_type: SensorType,
+    sensor_range: &[RangeInclusive<u32>],
+  ) -> Self {
+    Self {
+      feature_descriptor: feature_descriptor.to_owned(),
+      sensor_type,
+      sensor_range: sensor_range.to_vec(),
+      index: 0,
+    }
+  }
+}
+
+/// A device feature that a client can't see or use, because its message spec version is too old to
+/// describe it. See [ClientDeviceMessageAttributes::lost_features].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LostDeviceFeature {
+  /// Actuator at an index of the message's attribute list.
+  Actuator {
+    message_type: ButtplugDeviceMessageType,
+    index: u32,
+    actuator_type: ActuatorType,
+  },
+  /// Sensor at an index of the message's attribute list.
+  Sensor {
+    message_type: ButtplugDeviceMessageType,
+    index: u32,
+    sensor_type: SensorType,
+  },
+  /// Raw endpoint access.
+  Raw {
+    message_type: ButtplugDeviceMessageType,
+  },
+}
+
+fn lost_actuators(
+  lost: &mut Vec<LostDeviceFeature>,
+  message_type: ButtplugDeviceMessageType,
+  attrs: &Option<Vec<ClientGenericDeviceMessageAttributes>>,
+  is_lost: impl Fn(&ClientGenericDeviceMessageAttributes) -> bool,
+) {
+  for (index, attr) in attrs.iter().flatten().enumerate() {
+    if is_lost(attr) {
+      lost.push(LostDeviceFeature::Actuator {
+        message_type,
+        index: index as u32,
+        actuator_type: *attr.actuator_type(),
+      });
+    }
+  }
+}
+
+fn lost_sensors(
+  lost: &mut Vec<LostDeviceFeature>,
+  message_type: ButtplugDeviceMessageType,
+  attrs: &Option<Vec<SensorDeviceMessageAttributes>>,
+  is_lost: impl Fn(&SensorDeviceMessageAttributes) -> bool,
+) {
+  for (index, attr) in attrs.iter().flatten().enumerate() {
+    if is_lost(attr) {
+      lost.push(LostDeviceFeature::Sensor {
+        message_type,
+        index: index as u32,
+        sensor_type: *attr.sensor_type(),
+      });
+    }
+  }
+}
+
+impl ClientDeviceMessageAttributes {
+  /// Features that are dropped when these attributes are converted down to an older message spec
+  /// version, for telling users which features they'd get by upgrading their client.
+  pub fn lost_features(&self, version: ButtplugMessageSpecVersion) -> Vec<LostDeviceFeature> {
+    let mut lost = vec![];
+    if version >= ButtplugMessageSpecVersion::Version3 {
+      return lost;
+    }
+    // Before v3, VibrateCmd is all that's left of ScalarCmd, and rotation is always by speed. v0
+    // only has device specific rotation and linear messages.
+    let v0 = version == ButtplugMessageSpecVersion::Version0;
+    lost_actuators(
+      &mut lost,
+      ButtplugDeviceMessageType::ScalarCmd,
+      &self.scalar_cmd,
+      |attr| *attr.actuator_type() != ActuatorType::Vibrate,
+    );
+    lost_actuators(
+      &mut lost,
+      ButtplugDeviceMessageType::RotateCmd,
+      &self.rotate_cmd,
+      |attr| (v0 && self.vorze_a10_cyclone_cmd.is_none()) || attr.is_positional_rotator(),
+    );
+    lost_actuators(
+      &mut lost,
+      ButtplugDeviceMessageType::LinearCmd,
+      &self.linear_cmd,
+      |_| v0 && self.fleshlight_launch_fw12_cmd.is_none(),
+    );
+
+    // v2 only has battery and RSSI readings, and nothing older has sensors at all.
+    lost_sensors(
+      &mut lost,
+      ButtplugDeviceMessageType::SensorReadCmd,
+      &self.sensor_read_cmd,
+      |attr| {
+        version < ButtplugMessageSpecVersion::Version2
+          || !matches!(attr.sensor_type(), SensorType::Battery | SensorType::RSSI)
+      },
+    );
+    lost_sensors(
+      &mut lost,
+      ButtplugDeviceMessageType::SensorSubscribeCmd,
+      &self.sensor_subscribe_cmd,
+      |_| true,
+    );
+
+    // Raw messages were added in v2.
+    if version < ButtplugMessageSpecVersion::Version2 {
+      for (message_type, attrs) in [
+        (ButtplugDeviceMessageType::RawReadCmd, &self.raw_read_cmd),
+        (ButtplugDeviceMessageType::RawWriteCmd, &self.raw_write_cmd),
+        (
+          ButtplugDeviceMessageType::RawSubscribeCmd,
+          &self.raw_subscribe_cmd,
+        ),
+      ] {
+        if attrs.is_some() {
+          lost.push(LostDeviceFeature::Raw { message_type });
+        }
+      }
+    }
+    lost
   }
 }
- */
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct ClientDeviceMessageAttributesV2 {
   // Generic commands
   #[getset(get = "pub")]
@@ -440,7 +628,9 @@ impl From<ClientDeviceMessageAttributes> for ClientDeviceMessageAttributesV2 {
   }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct GenericDeviceMessageAttributesV2 {
   #[getset(get = "pub")]
   #[serde(rename = "FeatureCount")]
@@ -478,7 +668,9 @@ impl From<Vec<ClientGenericDeviceMessageAttributes>> for GenericDeviceMessageAtt
   }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct ClientDeviceMessageAttributesV1 {
   // Generic commands
   #[getset(get = "pub")]
@@ -537,7 +729,9 @@ impl From<ClientDeviceMessageAttributesV2> for ClientDeviceMessageAttributesV1 {
   }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Getters, Setters)]
+#[derive(
+  Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ButtplugJsonSchema, Getters, Setters,
+)]
 pub struct GenericDeviceMessageAttributesV1 {
   #[serde(rename = "FeatureCount")]
   feature_count: u32,
@@ -12,10 +12,14 @@ use getset::{CopyGetters, Getters};
 
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Notification that a device has been found and connected to the server.
 #[derive(ButtplugMessage, Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceAdded {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -42,17 +46,39 @@ pub struct DeviceAdded {
   )]
   #[getset(get = "pub")]
   device_message_timing_gap: Option<u32>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceTransport", skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  device_transport: Option<DeviceTransport>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceVersion", skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  device_version: Option<DeviceVersion>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceMetadata", skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  device_metadata: Option<HashMap<String, String>>,
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceMessages"))]
   #[getset(get = "pub")]
   device_messages: ClientDeviceMessageAttributes,
 }
 
 impl DeviceAdded {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     device_index: u32,
     device_name: &str,
     device_display_name: &Option<String>,
     device_message_timing_gap: &Option<u32>,
+    device_transport: &Option<DeviceTransport>,
+    device_version: &Option<DeviceVersion>,
+    device_metadata: &Option<HashMap<String, String>>,
     device_messages: &ClientDeviceMessageAttributes,
   ) -> Self {
     let mut obj = Self {
@@ -61,6 +87,9 @@ impl DeviceAdded {
       device_name: device_name.to_string(),
       device_display_name: device_display_name.clone(),
       device_message_timing_gap: *device_message_timing_gap,
+      device_transport: device_transport.clone(),
+      device_version: device_version.clone(),
+      device_metadata: device_metadata.clone(),
       device_messages: device_messages.clone(),
     };
     obj.finalize();
@@ -81,7 +110,10 @@ impl ButtplugMessageFinalizer for DeviceAdded {
 }
 
 #[derive(ButtplugMessage, Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceAddedV2 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -121,7 +153,10 @@ impl ButtplugMessageFinalizer for DeviceAddedV2 {
 }
 
 #[derive(ButtplugMessage, Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceAddedV1 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -162,7 +197,10 @@ impl ButtplugMessageFinalizer for DeviceAddedV1 {
 }
 
 #[derive(Default, ButtplugMessage, Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceAddedV0 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -0,0 +1,71 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Set the user display name of a device, i.e. "Left Toy". The server stores display names in the
+/// user device configuration, so they're kept across restarts. A display name of None clears it.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct DeviceDisplayNameCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DisplayName",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub")]
+  display_name: Option<String>,
+}
+
+impl DeviceDisplayNameCmd {
+  pub fn new(device_index: u32, display_name: Option<&str>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      display_name: display_name.map(|name| name.to_owned()),
+    }
+  }
+}
+
+impl ButtplugMessageValidator for DeviceDisplayNameCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::DeviceDisplayNameCmd;
+
+  #[test]
+  fn test_device_display_name_cmd_json() {
+    let json = r#"{"Id":1,"DeviceIndex":0,"DisplayName":"Left Toy"}"#;
+    let msg: DeviceDisplayNameCmd = serde_json::from_str(json).expect("Test, assuming infallible");
+    assert_eq!(msg, DeviceDisplayNameCmd::new(0, Some("Left Toy")));
+    assert_eq!(
+      serde_json::to_string(&msg).expect("Test, assuming infallible"),
+      json
+    );
+    let cleared: DeviceDisplayNameCmd =
+      serde_json::from_str(r#"{"Id":1,"DeviceIndex":0}"#).expect("Test, assuming infallible");
+    assert_eq!(cleared.display_name(), &None);
+  }
+}
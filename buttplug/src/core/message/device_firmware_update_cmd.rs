@@ -0,0 +1,77 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Update the firmware of a device through its bootloader, i.e. with the init packet (.dat) and
+/// firmware image (.bin) from a Nordic DFU package. The server replies once the update is done, at
+/// which point the device restarts and is disconnected. Servers only accept this if firmware
+/// updates were allowed when they were built.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct DeviceFirmwareUpdateCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "InitPacket"))]
+  #[getset(get = "pub")]
+  init_packet: Vec<u8>,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Firmware"))]
+  #[getset(get = "pub")]
+  firmware: Vec<u8>,
+}
+
+impl DeviceFirmwareUpdateCmd {
+  pub fn new(device_index: u32, init_packet: &[u8], firmware: &[u8]) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      init_packet: init_packet.to_vec(),
+      firmware: firmware.to_vec(),
+    }
+  }
+}
+
+impl ButtplugMessageValidator for DeviceFirmwareUpdateCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    if self.firmware.is_empty() {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "Firmware image cannot be empty.".to_owned(),
+      ));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessageValidator, DeviceFirmwareUpdateCmd};
+
+  #[test]
+  fn test_device_firmware_update_cmd_json() {
+    let json = r#"{"Id":1,"DeviceIndex":0,"InitPacket":[1,2],"Firmware":[3,4,5]}"#;
+    let msg: DeviceFirmwareUpdateCmd =
+      serde_json::from_str(json).expect("Test, assuming infallible");
+    assert_eq!(msg, DeviceFirmwareUpdateCmd::new(0, &[1, 2], &[3, 4, 5]));
+    assert_eq!(
+      serde_json::to_string(&msg).expect("Test, assuming infallible"),
+      json
+    );
+    assert!(DeviceFirmwareUpdateCmd::new(0, &[1, 2], &[])
+      .is_valid()
+      .is_err());
+  }
+}
@@ -13,7 +13,10 @@ use serde::{Deserialize, Serialize};
 
 /// List of all devices currently connected to the server.
 #[derive(Default, Clone, Debug, PartialEq, Eq, ButtplugMessage, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceList {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -43,7 +46,10 @@ impl ButtplugMessageFinalizer for DeviceList {
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Eq, ButtplugMessage, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceListV2 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -75,7 +81,10 @@ impl ButtplugMessageFinalizer for DeviceListV2 {
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Eq, ButtplugMessage, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceListV1 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -108,7 +117,10 @@ impl ButtplugMessageFinalizer for DeviceListV1 {
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Eq, ButtplugMessage, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceListV0 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
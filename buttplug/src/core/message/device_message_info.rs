@@ -9,10 +9,14 @@ use super::*;
 use getset::{CopyGetters, Getters, MutGetters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Substructure of device messages, used for attribute information (name, messages supported, etc...)
 #[derive(Clone, Debug, PartialEq, Eq, MutGetters, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceMessageInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
   #[getset(get_copy = "pub")]
@@ -35,17 +39,39 @@ pub struct DeviceMessageInfo {
   )]
   #[getset(get = "pub")]
   device_message_timing_gap: Option<u32>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceTransport", skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  device_transport: Option<DeviceTransport>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceVersion", skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  device_version: Option<DeviceVersion>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "DeviceMetadata", skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  device_metadata: Option<HashMap<String, String>>,
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceMessages"))]
   #[getset(get = "pub", get_mut = "pub(super)")]
   device_messages: ClientDeviceMessageAttributes,
 }
 
 impl DeviceMessageInfo {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     device_index: u32,
     device_name: &str,
     device_display_name: &Option<String>,
     device_message_timing_gap: &Option<u32>,
+    device_transport: &Option<DeviceTransport>,
+    device_version: &Option<DeviceVersion>,
+    device_metadata: &Option<HashMap<String, String>>,
     device_messages: ClientDeviceMessageAttributes,
   ) -> Self {
     Self {
@@ -53,6 +79,9 @@ impl DeviceMessageInfo {
       device_name: device_name.to_owned(),
       device_display_name: device_display_name.clone(),
       device_message_timing_gap: *device_message_timing_gap,
+      device_transport: device_transport.clone(),
+      device_version: device_version.clone(),
+      device_metadata: device_metadata.clone(),
       device_messages,
     }
   }
@@ -65,13 +94,19 @@ impl From<DeviceAdded> for DeviceMessageInfo {
       device_name: device_added.device_name().clone(),
       device_display_name: device_added.device_display_name().clone(),
       device_message_timing_gap: *device_added.device_message_timing_gap(),
+      device_transport: device_added.device_transport().clone(),
+      device_version: device_added.device_version().clone(),
+      device_metadata: device_added.device_metadata().clone(),
       device_messages: device_added.device_messages().clone(),
     }
   }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceMessageInfoV2 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
   #[getset(get_copy = "pub")]
@@ -114,7 +149,10 @@ impl From<DeviceMessageInfo> for DeviceMessageInfoV2 {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceMessageInfoV1 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
   #[getset(get_copy = "pub")]
@@ -146,7 +184,10 @@ impl From<DeviceMessageInfoV2> for DeviceMessageInfoV1 {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceMessageInfoV0 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
   #[getset(get_copy = "pub")]
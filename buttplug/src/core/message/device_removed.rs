@@ -13,7 +13,10 @@ use getset::CopyGetters;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, ButtplugMessage, Clone, PartialEq, Eq, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct DeviceRemoved {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -0,0 +1,39 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Run a short self-test on a device: pulse each actuator in turn, read each sensor, then stop the
+/// device. The server replies with a [DeviceSelfTestReport] once the test is done.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct DeviceSelfTestCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+}
+
+impl DeviceSelfTestCmd {
+  pub fn new(device_index: u32) -> Self {
+    Self {
+      id: 1,
+      device_index,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for DeviceSelfTestCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
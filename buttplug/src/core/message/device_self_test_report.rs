@@ -0,0 +1,190 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Result of pulsing one actuator during a device self-test.
+#[derive(Debug, PartialEq, Eq, Clone, Getters, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct ActuatorTestResult {
+  /// Index of the actuator within the command it's driven by (ScalarCmd, RotateCmd or LinearCmd).
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
+  #[getset(get_copy = "pub")]
+  index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "ActuatorType"))]
+  #[getset(get_copy = "pub")]
+  actuator_type: ActuatorType,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Success"))]
+  #[getset(get_copy = "pub")]
+  success: bool,
+  /// Time the device took to accept the pulse command, in milliseconds.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DurationMs"))]
+  #[getset(get_copy = "pub")]
+  duration_ms: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Error", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  error: Option<String>,
+}
+
+impl ActuatorTestResult {
+  pub fn new(
+    index: u32,
+    actuator_type: ActuatorType,
+    duration_ms: u32,
+    error: Option<String>,
+  ) -> Self {
+    Self {
+      index,
+      actuator_type,
+      success: error.is_none(),
+      duration_ms,
+      error,
+    }
+  }
+}
+
+/// Result of reading one sensor during a device self-test.
+#[derive(Debug, PartialEq, Eq, Clone, Getters, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct SensorTestResult {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
+  #[getset(get_copy = "pub")]
+  index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "SensorType"))]
+  #[getset(get_copy = "pub")]
+  sensor_type: SensorType,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Success"))]
+  #[getset(get_copy = "pub")]
+  success: bool,
+  /// Time the sensor read took, in milliseconds.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DurationMs"))]
+  #[getset(get_copy = "pub")]
+  duration_ms: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Data", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  data: Option<Vec<i32>>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Error", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get = "pub")]
+  error: Option<String>,
+}
+
+impl SensorTestResult {
+  pub fn new(
+    index: u32,
+    sensor_type: SensorType,
+    duration_ms: u32,
+    result: Result<Vec<i32>, String>,
+  ) -> Self {
+    let (data, error) = match result {
+      Ok(data) => (Some(data), None),
+      Err(error) => (None, Some(error)),
+    };
+    Self {
+      index,
+      sensor_type,
+      success: error.is_none(),
+      duration_ms,
+      data,
+      error,
+    }
+  }
+}
+
+/// Reply to [DeviceSelfTestCmd], with the results for every actuator and sensor the device has.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct DeviceSelfTestReport {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Actuators"))]
+  #[getset(get = "pub")]
+  actuators: Vec<ActuatorTestResult>,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Sensors"))]
+  #[getset(get = "pub")]
+  sensors: Vec<SensorTestResult>,
+}
+
+impl DeviceSelfTestReport {
+  pub fn new(
+    device_index: u32,
+    actuators: Vec<ActuatorTestResult>,
+    sensors: Vec<SensorTestResult>,
+  ) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      actuators,
+      sensors,
+    }
+  }
+
+  /// True if every actuator and sensor passed.
+  pub fn passed(&self) -> bool {
+    self.actuators.iter().all(|actuator| actuator.success)
+      && self.sensors.iter().all(|sensor| sensor.success)
+  }
+}
+
+impl ButtplugMessageValidator for DeviceSelfTestReport {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_device_self_test_report_json() {
+    let json = r#"{"Id":1,"DeviceIndex":0,"Actuators":[{"Index":0,"ActuatorType":"Vibrate","Success":true,"DurationMs":12},{"Index":1,"ActuatorType":"Vibrate","Success":false,"DurationMs":500,"Error":"Timed out"}],"Sensors":[{"Index":0,"SensorType":"Battery","Success":true,"DurationMs":30,"Data":[90]}]}"#;
+    let report = DeviceSelfTestReport::new(
+      0,
+      vec![
+        ActuatorTestResult::new(0, ActuatorType::Vibrate, 12, None),
+        ActuatorTestResult::new(1, ActuatorType::Vibrate, 500, Some("Timed out".to_owned())),
+      ],
+      vec![SensorTestResult::new(
+        0,
+        SensorType::Battery,
+        30,
+        Ok(vec![90]),
+      )],
+    );
+    assert!(!report.passed());
+    let msg: DeviceSelfTestReport = serde_json::from_str(json).expect("Test, assuming infallible");
+    assert_eq!(msg, report);
+    assert_eq!(
+      serde_json::to_string(&report).expect("Test, assuming infallible"),
+      json
+    );
+  }
+}
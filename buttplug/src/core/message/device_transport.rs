@@ -0,0 +1,62 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use super::ButtplugJsonSchema;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Type of hardware connection a device is using.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub enum DeviceTransportType {
+  BluetoothLE,
+  Serial,
+  HID,
+  USB,
+  XInput,
+  /// Devices driven directly from the host's GPIO/PWM pins
+  GPIO,
+  /// Devices driven by MIDI messages from one of the host's MIDI output ports
+  MIDI,
+  /// Devices connected over a network service (Lovense Connect, Websocket Device Server, etc...)
+  Network,
+}
+
+/// Substructure of device messages, describing how a device is connected to the server.
+///
+/// Mostly useful for showing connection details in UIs, and for telling apart multiple devices of
+/// the same type. The address format depends on the transport: a bluetooth address (or platform
+/// specific identifier on macOS/iOS/WebBluetooth), serial port name, HID serial number, XInput
+/// controller index, PWM chip name, MIDI port name, or the identifier the device or service
+/// reported for network devices.
+#[derive(Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct DeviceTransport {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Type"))]
+  #[getset(get_copy = "pub")]
+  transport_type: DeviceTransportType,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Address"))]
+  #[getset(get = "pub")]
+  address: String,
+}
+
+impl DeviceTransport {
+  pub fn new(transport_type: DeviceTransportType, address: &str) -> Self {
+    Self {
+      transport_type,
+      address: address.to_owned(),
+    }
+  }
+}
@@ -0,0 +1,60 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use getset::{Getters, Setters};
+#[cfg(feature = "serialize-json")]
+use super::ButtplugJsonSchema;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Substructure of device messages, with the firmware and hardware revisions the device reported
+/// while it was initialized.
+///
+/// Revisions are read from the BLE Device Information Service, or queried through the device
+/// protocol where it has a way to ask. Formats are whatever the manufacturer decided on, so these
+/// are only meant for display and bug reports, not for comparison.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Getters, Setters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct DeviceVersion {
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "FirmwareRevision",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  firmware_revision: Option<String>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "HardwareRevision",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  hardware_revision: Option<String>,
+}
+
+impl DeviceVersion {
+  pub fn new(firmware_revision: &Option<String>, hardware_revision: &Option<String>) -> Self {
+    Self {
+      firmware_revision: firmware_revision.clone(),
+      hardware_revision: hardware_revision.clone(),
+    }
+  }
+
+  /// True if the device didn't report any revisions.
+  pub fn is_empty(&self) -> bool {
+    self.firmware_revision.is_none() && self.hardware_revision.is_none()
+  }
+}
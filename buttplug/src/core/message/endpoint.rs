@@ -32,9 +32,13 @@ use core::hash::Hash;
 pub enum Endpoint {
   /// Expect to take commands, when multiple receive endpoints may be available
   Command,
-  /// Firmware updates (Buttplug does not update firmware, but some firmware endpoints are used for
-  /// mode setting)
+  /// Firmware updates (Buttplug updates firmware through the Dfu endpoints, but some vendor
+  /// firmware endpoints are used for mode setting)
   Firmware,
+  /// Control point of a device firmware update (DFU) bootloader, i.e. Nordic Secure DFU
+  DfuControl,
+  /// Packet endpoint that firmware data is written to during a device firmware update (DFU)
+  DfuPacket,
   /// Common receive endpoint name
   Rx,
   /// Receive endpoint for accelerometer data
@@ -43,6 +47,14 @@ pub enum Endpoint {
   RxBLEBattery,
   /// Receive endpoint for BLE model (usually expected to be BLE standard profile)
   RxBLEModel,
+  /// Receive endpoint for firmware revision string (usually expected to be BLE standard profile)
+  RxBLEFirmwareRevision,
+  /// Receive endpoint for hardware revision string (usually expected to be BLE standard profile)
+  RxBLEHardwareRevision,
+  /// Receive endpoint for manufacturer name string (usually expected to be BLE standard profile)
+  RxBLEManufacturerName,
+  /// Receive endpoint for serial number string (usually expected to be BLE standard profile)
+  RxBLESerialNumber,
   /// Receive endpoint for pressure sensors
   RxPressure,
   /// Receive endpoint for touch sensors
@@ -57,6 +69,8 @@ pub enum Endpoint {
   TxVibrate,
   /// Transmit endpoint for vendor (proprietary) control
   TxVendorControl,
+  /// Transmit endpoint for HID feature reports
+  TxFeature,
   /// Transmit endpoint for whitelist updating
   Whitelist,
   /// Generic endpoint (available for user configurations)
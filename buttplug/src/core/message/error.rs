@@ -18,7 +18,10 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
 /// Error codes pertaining to error classes that can be represented in the
 /// Buttplug [Error] message.
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize_repr, Deserialize_repr))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize_repr, Deserialize_repr, ButtplugJsonSchema)
+)]
 #[repr(u8)]
 pub enum ErrorCode {
   ErrorUnknown = 0,
@@ -42,7 +45,10 @@ pub enum ErrorCode {
   Getters,
   CopyGetters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct Error {
   /// Message Id, used for matching message pairs in remote connection instances.
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
@@ -131,7 +137,10 @@ impl From<ButtplugError> for Error {
   Getters,
   CopyGetters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct ErrorV0 {
   /// Message Id, used for matching message pairs in remote connection instances.
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
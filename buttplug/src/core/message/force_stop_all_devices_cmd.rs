@@ -0,0 +1,35 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Stop every device on the server, along with scheduled commands and playback started by any
+/// client. Unlike [StopAllDevices], which only halts the sending client's own playback, nothing
+/// started before this will move a device again.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct ForceStopAllDevicesCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+}
+
+impl Default for ForceStopAllDevicesCmd {
+  fn default() -> Self {
+    Self { id: 1 }
+  }
+}
+
+impl ButtplugMessageValidator for ForceStopAllDevicesCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
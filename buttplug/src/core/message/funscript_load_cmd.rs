@@ -0,0 +1,46 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Load a funscript into the server for playback on a device with linear features.
+///
+/// The script is sent as the contents of a .funscript file. Loading a script replaces any script
+/// already loaded for the device, and leaves playback paused at the start of the script.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct FunscriptLoadCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Script"))]
+  #[getset(get = "pub")]
+  script: String,
+}
+
+impl FunscriptLoadCmd {
+  pub fn new(device_index: u32, script: &str) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      script: script.to_owned(),
+    }
+  }
+}
+
+impl ButtplugMessageValidator for FunscriptLoadCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
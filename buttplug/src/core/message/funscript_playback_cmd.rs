@@ -0,0 +1,72 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Control playback of a funscript loaded via [FunscriptLoadCmd].
+///
+/// Position and rate are optional, and will keep their current values if not set. This allows a
+/// single message type to handle play, pause, seek, and playback rate changes.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct FunscriptPlaybackCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  /// True to play, false to pause.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Playing"))]
+  #[getset(get_copy = "pub")]
+  playing: bool,
+  /// Script time to seek to, in milliseconds.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Position", skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get_copy = "pub")]
+  position: Option<u32>,
+  /// Playback rate, where 1.0 is realtime.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Rate", skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get_copy = "pub")]
+  rate: Option<f64>,
+}
+
+impl FunscriptPlaybackCmd {
+  pub fn new(device_index: u32, playing: bool, position: Option<u32>, rate: Option<f64>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      playing,
+      position,
+      rate,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for FunscriptPlaybackCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    if let Some(rate) = self.rate {
+      if !rate.is_finite() || rate <= 0.0 {
+        return Err(ButtplugMessageError::InvalidMessageContents(format!(
+          "FunscriptPlaybackCmd rate {} is invalid, should be greater than 0.0",
+          rate
+        )));
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,66 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Set the highest intensity (0.0-1.0) any actuator on the server can be commanded to, by any
+/// client, until the server shuts down or the ceiling is changed again. Commands over the ceiling
+/// are capped to it, and actuators already running over it are brought down to it. A ceiling of
+/// 1.0 removes the limit. Raising the ceiling is only allowed if the server allows server
+/// management.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct IntensityCeilingCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Ceiling"))]
+  #[getset(get_copy = "pub")]
+  ceiling: f64,
+}
+
+impl IntensityCeilingCmd {
+  pub fn new(ceiling: f64) -> Self {
+    Self { id: 1, ceiling }
+  }
+}
+
+impl ButtplugMessageValidator for IntensityCeilingCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    self.is_in_command_range(
+      self.ceiling,
+      format!(
+        "Intensity ceiling {} is invalid. Ceiling should be a value between 0.0 and 1.0",
+        self.ceiling
+      ),
+    )
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::{ButtplugMessageValidator, IntensityCeilingCmd};
+
+  #[test]
+  fn test_intensity_ceiling_cmd_json() {
+    let json = r#"{"Id":1,"Ceiling":0.5}"#;
+    let msg: IntensityCeilingCmd = serde_json::from_str(json).expect("Test, assuming infallible");
+    assert_eq!(msg, IntensityCeilingCmd::new(0.5));
+    assert_eq!(
+      serde_json::to_string(&msg).expect("Test, assuming infallible"),
+      json
+    );
+    assert!(IntensityCeilingCmd::new(1.5).is_valid().is_err());
+  }
+}
@@ -0,0 +1,360 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! JSON schema generated from the message types.
+//!
+//! The schema in buttplug-schema is maintained by hand, and has to be kept in sync with the message
+//! structs whenever they change. The schema here is derived from the structs (and their serde
+//! attributes) via `#[derive(ButtplugJsonSchema)]`, so it always describes what the serializers
+//! actually read and write. The serializers still validate against the schema in buttplug-schema,
+//! so the tests here check that both list the same messages and fields for each spec version.
+
+use super::Endpoint;
+#[cfg(feature = "serialize-json")]
+use super::{
+  ButtplugMessageSpecVersion,
+  ButtplugSpecV0ClientMessage,
+  ButtplugSpecV0ServerMessage,
+  ButtplugSpecV1ClientMessage,
+  ButtplugSpecV1ServerMessage,
+  ButtplugSpecV2ClientMessage,
+  ButtplugSpecV2ServerMessage,
+  ButtplugSpecV3ClientMessage,
+  ButtplugSpecV3ServerMessage,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, ops::RangeInclusive};
+
+/// Types that can describe their JSON wire format as a JSON schema.
+pub trait ButtplugJsonSchema {
+  fn json_schema() -> Value;
+}
+
+macro_rules! integer_schema {
+  ($($t:ty),*) => {
+    $(
+      impl ButtplugJsonSchema for $t {
+        fn json_schema() -> Value {
+          json!({
+            "type": "integer",
+            "minimum": <$t>::MIN,
+            "maximum": <$t>::MAX,
+          })
+        }
+      }
+    )*
+  };
+}
+
+integer_schema!(u8, u16, u32, u64, i32);
+
+impl ButtplugJsonSchema for f64 {
+  fn json_schema() -> Value {
+    json!({ "type": "number" })
+  }
+}
+
+impl ButtplugJsonSchema for bool {
+  fn json_schema() -> Value {
+    json!({ "type": "boolean" })
+  }
+}
+
+impl ButtplugJsonSchema for String {
+  fn json_schema() -> Value {
+    json!({ "type": "string" })
+  }
+}
+
+impl<T: ButtplugJsonSchema> ButtplugJsonSchema for Option<T> {
+  fn json_schema() -> Value {
+    json!({ "anyOf": [T::json_schema(), { "type": "null" }] })
+  }
+}
+
+impl<T: ButtplugJsonSchema> ButtplugJsonSchema for Vec<T> {
+  fn json_schema() -> Value {
+    json!({
+      "type": "array",
+      "items": T::json_schema(),
+    })
+  }
+}
+
+impl<T: ButtplugJsonSchema> ButtplugJsonSchema for HashMap<String, T> {
+  fn json_schema() -> Value {
+    json!({
+      "type": "object",
+      "additionalProperties": T::json_schema(),
+    })
+  }
+}
+
+// Ranges are sent as [start, end] pairs.
+impl ButtplugJsonSchema for RangeInclusive<u32> {
+  fn json_schema() -> Value {
+    json!({
+      "type": "array",
+      "items": u32::json_schema(),
+      "minItems": 2,
+      "maxItems": 2,
+    })
+  }
+}
+
+// Endpoints are sent as their names, which are parsed by hand rather than via serde.
+impl ButtplugJsonSchema for Endpoint {
+  fn json_schema() -> Value {
+    String::json_schema()
+  }
+}
+
+/// JSON schema for message arrays of a message spec version, covering both client and server
+/// messages.
+#[cfg(feature = "serialize-json")]
+pub fn message_json_schema(version: ButtplugMessageSpecVersion) -> Value {
+  let (client_messages, server_messages) = match version {
+    ButtplugMessageSpecVersion::Version0 => (
+      ButtplugSpecV0ClientMessage::json_schema(),
+      ButtplugSpecV0ServerMessage::json_schema(),
+    ),
+    ButtplugMessageSpecVersion::Version1 => (
+      ButtplugSpecV1ClientMessage::json_schema(),
+      ButtplugSpecV1ServerMessage::json_schema(),
+    ),
+    ButtplugMessageSpecVersion::Version2 => (
+      ButtplugSpecV2ClientMessage::json_schema(),
+      ButtplugSpecV2ServerMessage::json_schema(),
+    ),
+    ButtplugMessageSpecVersion::Version3 => (
+      ButtplugSpecV3ClientMessage::json_schema(),
+      ButtplugSpecV3ServerMessage::json_schema(),
+    ),
+  };
+  json!({
+    "$schema": "http://json-schema.org/draft-07/schema#",
+    "title": format!("Buttplug Message Schema v{}", version as u32),
+    "type": "array",
+    "items": {
+      "anyOf": [client_messages, server_messages],
+    },
+  })
+}
+
+#[cfg(all(test, feature = "serialize-json"))]
+mod test {
+  use super::*;
+  use crate::core::message::{
+    serializer::vec_to_protocol_json,
+    ActuatorType,
+    ClientDeviceMessageAttributesBuilder,
+    ClientGenericDeviceMessageAttributes,
+    DeviceAdded,
+    Error,
+    ErrorCode,
+    RawWriteCmd,
+    RequestServerInfo,
+    ScalarCmd,
+    ScalarSubcommand,
+    SensorDeviceMessageAttributes,
+    SensorType,
+    SingleMotorVibrateCmd,
+  };
+  use jsonschema::JSONSchema;
+  use std::collections::{BTreeMap, BTreeSet};
+
+  /// Hand written schema shipped in buttplug-schema, which the serializers validate against.
+  const SHIPPED_SCHEMA: &str = include_str!("../../../buttplug-schema/schema/buttplug-schema.json");
+
+  fn validator(version: ButtplugMessageSpecVersion) -> JSONSchema {
+    JSONSchema::compile(&message_json_schema(version)).expect("Test, assuming infallible.")
+  }
+
+  fn is_valid(validator: &JSONSchema, msg: &str) -> bool {
+    validator.is_valid(&serde_json::from_str(msg).expect("Test, assuming infallible."))
+  }
+
+  #[test]
+  fn test_generated_schema_accepts_serialized_messages() {
+    let validator = validator(ButtplugMessageSpecVersion::Version3);
+    let client_msgs: Vec<ButtplugSpecV3ClientMessage> = vec![
+      RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3).into(),
+      ScalarCmd::new(
+        0,
+        vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+      RawWriteCmd::new(0, Endpoint::Tx, &[1, 2, 3], false).into(),
+    ];
+    assert!(is_valid(&validator, &vec_to_protocol_json(&client_msgs)));
+
+    let mut builder = ClientDeviceMessageAttributesBuilder::default();
+    builder.scalar_cmd(&[ClientGenericDeviceMessageAttributes::new(
+      "Motor",
+      20,
+      ActuatorType::Vibrate,
+    )]);
+    builder.sensor_read_cmd(&[SensorDeviceMessageAttributes::new(
+      "Battery",
+      SensorType::Battery,
+      &[0..=100],
+    )]);
+    builder.raw_write_cmd(&[Endpoint::Tx]);
+    let attributes = builder.finish();
+    let server_msgs: Vec<ButtplugSpecV3ServerMessage> = vec![
+      DeviceAdded::new(
+        0,
+        "Test Device",
+        &Some("My Device".to_owned()),
+        &None,
+        &None,
+        &None,
+        &None,
+        &attributes,
+      )
+      .into(),
+      Error::new(ErrorCode::ErrorDevice, "Test error", None).into(),
+    ];
+    assert!(is_valid(&validator, &vec_to_protocol_json(&server_msgs)));
+  }
+
+  #[test]
+  fn test_generated_schema_rejects_invalid_messages() {
+    let validator = validator(ButtplugMessageSpecVersion::Version3);
+    // Missing device index
+    assert!(!is_valid(
+      &validator,
+      r#"[{"ScalarCmd":{"Id":1,"Scalars":[]}}]"#
+    ));
+    // Unknown actuator type
+    assert!(!is_valid(
+      &validator,
+      r#"[{"ScalarCmd":{"Id":1,"DeviceIndex":0,"Scalars":[{"Index":0,"Scalar":0.5,"ActuatorType":"Wiggle"}]}}]"#
+    ));
+    // Not a message
+    assert!(!is_valid(&validator, r#"[{"NotAMessage":{"Id":1}}]"#));
+  }
+
+  #[test]
+  fn test_generated_schema_per_spec_version() {
+    let msgs = vec_to_protocol_json(&[ButtplugSpecV0ClientMessage::SingleMotorVibrateCmd(
+      SingleMotorVibrateCmd::new(0, 0.5),
+    )]);
+    assert!(is_valid(
+      &validator(ButtplugMessageSpecVersion::Version0),
+      &msgs
+    ));
+    assert!(!is_valid(
+      &validator(ButtplugMessageSpecVersion::Version3),
+      &msgs
+    ));
+  }
+
+  /// Field names of each message in a generated message schema, by message name.
+  fn generated_messages(schema: &Value) -> BTreeMap<String, BTreeSet<String>> {
+    let mut messages = BTreeMap::new();
+    for side in schema["items"]["anyOf"]
+      .as_array()
+      .expect("Test, assuming infallible.")
+    {
+      let variants = side["anyOf"]
+        .as_array()
+        .cloned()
+        .unwrap_or_else(|| vec![side.clone()]);
+      for variant in variants {
+        let (name, message) = variant["properties"]
+          .as_object()
+          .and_then(|x| x.iter().next())
+          .expect("Test, assuming infallible.");
+        let fields = message["properties"]
+          .as_object()
+          .map(|x| x.keys().cloned().collect())
+          .unwrap_or_default();
+        messages.insert(name.clone(), fields);
+      }
+    }
+    messages
+  }
+
+  /// Field names of a message in the shipped schema, following references and combined schemas.
+  fn shipped_fields(schema: &Value, message: &Value) -> BTreeSet<String> {
+    if let Some(reference) = message["$ref"].as_str() {
+      let target = schema
+        .pointer(reference.trim_start_matches('#'))
+        .expect("Test, assuming infallible.");
+      return shipped_fields(schema, target);
+    }
+    let mut fields: BTreeSet<String> = message["properties"]
+      .as_object()
+      .map(|x| x.keys().cloned().collect())
+      .unwrap_or_default();
+    for combined in ["anyOf", "allOf", "oneOf"] {
+      for sub_schema in message[combined].as_array().into_iter().flatten() {
+        fields.extend(shipped_fields(schema, sub_schema));
+      }
+    }
+    fields
+  }
+
+  /// Field names of each message in a spec version of the shipped schema, by message name.
+  fn shipped_messages(
+    schema: &Value,
+    version: ButtplugMessageSpecVersion,
+  ) -> BTreeMap<String, BTreeSet<String>> {
+    schema["specs"][format!("MessageSpecV{}", version as u32)]["items"]["properties"]
+      .as_object()
+      .expect("Test, assuming infallible.")
+      .iter()
+      .map(|(name, message)| (name.clone(), shipped_fields(schema, message)))
+      .collect()
+  }
+
+  #[test]
+  fn test_generated_schema_matches_shipped_schema() {
+    // Until the serializers validate against the generated schema, the shipped one needs to list
+    // the same messages and fields, or valid messages get refused.
+    let shipped: Value = serde_json::from_str(SHIPPED_SCHEMA).expect("Test, assuming infallible.");
+    let mut differences = vec![];
+    for version in [
+      ButtplugMessageSpecVersion::Version0,
+      ButtplugMessageSpecVersion::Version1,
+      ButtplugMessageSpecVersion::Version2,
+      ButtplugMessageSpecVersion::Version3,
+    ] {
+      let generated = generated_messages(&message_json_schema(version));
+      let shipped = shipped_messages(&shipped, version);
+      for name in generated
+        .keys()
+        .chain(shipped.keys())
+        .collect::<BTreeSet<_>>()
+      {
+        // RequestServerInfo is read before the spec version is known, so it's always read with the
+        // latest definition. Older spec versions only describe the fields their clients send.
+        let agrees =
+          if name == "RequestServerInfo" && version != ButtplugMessageSpecVersion::Version3 {
+            generated.contains_key(name) && shipped.contains_key(name)
+          } else {
+            generated.get(name) == shipped.get(name)
+          };
+        if !agrees {
+          differences.push(format!(
+            "v{} {}: generated {:?}, shipped {:?}",
+            version as u32,
+            name,
+            generated.get(name),
+            shipped.get(name)
+          ));
+        }
+      }
+    }
+    assert!(
+      differences.is_empty(),
+      "Generated and shipped schemas disagree:\n{}",
+      differences.join("\n")
+    );
+  }
+}
@@ -0,0 +1,56 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Disconnect the client connected to another server session. Devices it was commanding are
+/// stopped, its device locks are released, and it gets an error telling it why, as when it pings
+/// out.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct KickClientCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "SessionId"))]
+  #[getset(get_copy = "pub")]
+  session_id: u32,
+}
+
+impl KickClientCmd {
+  pub fn new(session_id: u32) -> Self {
+    Self { id: 1, session_id }
+  }
+}
+
+impl ButtplugMessageValidator for KickClientCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::KickClientCmd;
+
+  #[test]
+  fn test_kick_client_cmd_json() {
+    let json = r#"{"Id":1,"SessionId":3}"#;
+    let msg: KickClientCmd = serde_json::from_str(json).expect("Test, assuming infallible");
+    assert_eq!(msg, KickClientCmd::new(3));
+    assert_eq!(
+      serde_json::to_string(&msg).expect("Test, assuming infallible"),
+      json
+    );
+  }
+}
@@ -12,7 +12,10 @@ use serde::{Deserialize, Serialize};
 
 /// Move device to a certain position in a certain amount of time
 #[derive(Debug, PartialEq, Clone, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 #[getset(get_copy = "pub")]
 pub struct VectorSubcommand {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
@@ -33,8 +36,13 @@ impl VectorSubcommand {
   }
 }
 
-#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[derive(
+  Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters, CopyGetters,
+)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct LinearCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -43,6 +51,13 @@ pub struct LinearCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Vectors"))]
   #[getset(get = "pub")]
   vectors: Vec<VectorSubcommand>,
+  /// Server time to execute the command at, in milliseconds since the Unix epoch.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Timestamp", skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get_copy = "pub")]
+  timestamp: Option<u64>,
 }
 
 impl LinearCmd {
@@ -51,8 +66,16 @@ impl LinearCmd {
       id: 1,
       device_index,
       vectors,
+      timestamp: None,
     }
   }
+
+  /// Schedule the command to run at a certain server time, in milliseconds since the Unix epoch.
+  /// Commands with timestamps in the past run immediately.
+  pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+    self.timestamp = Some(timestamp);
+    self
+  }
 }
 
 impl ButtplugMessageValidator for LinearCmd {
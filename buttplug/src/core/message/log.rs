@@ -14,7 +14,10 @@ use serde::{Deserialize, Serialize};
 #[derive(
   Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters, CopyGetters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct Log {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
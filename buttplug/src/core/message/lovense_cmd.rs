@@ -15,7 +15,10 @@ use serde::{Deserialize, Serialize};
 // Lovense devices even on spec v1 connections, we can put a null validator on
 // it.
 #[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct LovenseCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
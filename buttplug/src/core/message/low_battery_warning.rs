@@ -0,0 +1,69 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Event sent when a device's battery level drops below the server's low battery threshold.
+///
+/// Sent once each time the level crosses the threshold, not for every reading below it, so clients
+/// can show a warning without tracking battery levels themselves.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct LowBatteryWarning {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "BatteryLevel"))]
+  #[getset(get_copy = "pub")]
+  battery_level: f64,
+}
+
+impl LowBatteryWarning {
+  pub fn new(device_index: u32, battery_level: f64) -> Self {
+    Self {
+      id: 0,
+      device_index,
+      battery_level,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for LowBatteryWarning {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_system_id(self.id)?;
+    self.is_in_command_range(
+      self.battery_level,
+      "LowBatteryWarning must be between 0.0 and 1.0".to_string(),
+    )
+  }
+}
+
+#[cfg(feature = "serialize-json")]
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_low_battery_warning_json() {
+    let json = r#"{"Id":0,"DeviceIndex":2,"BatteryLevel":0.1}"#;
+    let warning: LowBatteryWarning =
+      serde_json::from_str(json).expect("Test, assuming infallible.");
+    assert_eq!(warning, LowBatteryWarning::new(2, 0.1));
+    assert!(warning.is_valid().is_ok());
+    assert_eq!(
+      serde_json::to_string(&warning).expect("Test, assuming infallible."),
+      json
+    );
+  }
+}
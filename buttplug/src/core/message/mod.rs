@@ -15,20 +15,41 @@
 
 mod battery_level_cmd;
 mod battery_level_reading;
+mod cancel_cmd;
+mod capability;
 mod client_device_message_attributes;
 mod device_added;
+mod device_display_name_cmd;
+mod device_firmware_update_cmd;
 mod device_list;
+mod device_lock_cmd;
 mod device_message_info;
 mod device_removed;
+mod device_self_test_cmd;
+mod device_self_test_report;
+mod device_transport;
+mod device_unlock_cmd;
+mod device_version;
+mod end_session_cmd;
 mod endpoint;
 mod error;
 mod fleshlight_launch_fw12_cmd;
+mod force_stop_all_devices_cmd;
+mod funscript_load_cmd;
+mod funscript_playback_cmd;
+mod intensity_ceiling_cmd;
+mod json_schema;
+mod kick_client_cmd;
 mod kiiroo_cmd;
 mod linear_cmd;
 mod log;
 mod log_level;
 mod lovense_cmd;
+mod low_battery_warning;
 mod ok;
+mod pattern_load_cmd;
+mod pattern_play_cmd;
+mod pattern_stop_cmd;
 mod ping;
 mod raw_read_cmd;
 mod raw_reading;
@@ -39,9 +60,12 @@ mod request_device_list;
 mod request_log;
 mod request_server_info;
 mod rotate_cmd;
+mod rotate_position_cmd;
 mod rssi_level_cmd;
 mod rssi_level_reading;
 mod scalar_cmd;
+mod scalar_loop_cmd;
+mod scanning_enabled_cmd;
 mod scanning_finished;
 mod sensor_read_cmd;
 mod sensor_reading;
@@ -49,11 +73,13 @@ mod sensor_subscribe_cmd;
 mod sensor_unsubscribe_cmd;
 pub mod serializer;
 mod server_info;
+mod server_shutdown_cmd;
 mod single_motor_vibrate_cmd;
 mod start_scanning;
 mod stop_all_devices;
 mod stop_device_cmd;
 mod stop_scanning;
+mod stroke_cmd;
 mod test;
 mod vibrate_cmd;
 mod vorze_a10_cyclone_cmd;
@@ -61,6 +87,8 @@ mod vorze_a10_cyclone_cmd;
 pub use self::log::Log;
 pub use battery_level_cmd::BatteryLevelCmd;
 pub use battery_level_reading::BatteryLevelReading;
+pub use cancel_cmd::CancelCmd;
+pub use capability::{ButtplugCapability, BUTTPLUG_DEFAULT_CAPABILITIES};
 pub use client_device_message_attributes::{
   ActuatorType,
   ClientDeviceMessageAttributes,
@@ -68,13 +96,18 @@ pub use client_device_message_attributes::{
   ClientDeviceMessageAttributesV1,
   ClientDeviceMessageAttributesV2,
   ClientGenericDeviceMessageAttributes,
+  LostDeviceFeature,
   NullDeviceMessageAttributes,
   RawDeviceMessageAttributes,
+  RotationMode,
   SensorDeviceMessageAttributes,
   SensorType,
 };
 pub use device_added::{DeviceAdded, DeviceAddedV0, DeviceAddedV1, DeviceAddedV2};
+pub use device_display_name_cmd::DeviceDisplayNameCmd;
+pub use device_firmware_update_cmd::DeviceFirmwareUpdateCmd;
 pub use device_list::{DeviceList, DeviceListV0, DeviceListV1, DeviceListV2};
+pub use device_lock_cmd::DeviceLockCmd;
 pub use device_message_info::{
   DeviceMessageInfo,
   DeviceMessageInfoV0,
@@ -82,14 +115,32 @@ pub use device_message_info::{
   DeviceMessageInfoV2,
 };
 pub use device_removed::DeviceRemoved;
+pub use device_self_test_cmd::DeviceSelfTestCmd;
+pub use device_self_test_report::{ActuatorTestResult, DeviceSelfTestReport, SensorTestResult};
+pub use device_transport::{DeviceTransport, DeviceTransportType};
+pub use device_unlock_cmd::DeviceUnlockCmd;
+pub use device_version::DeviceVersion;
+pub use end_session_cmd::EndSessionCmd;
 pub use endpoint::Endpoint;
 pub use error::{Error, ErrorCode, ErrorV0};
 pub use fleshlight_launch_fw12_cmd::FleshlightLaunchFW12Cmd;
+pub use force_stop_all_devices_cmd::ForceStopAllDevicesCmd;
+pub use funscript_load_cmd::FunscriptLoadCmd;
+pub use funscript_playback_cmd::FunscriptPlaybackCmd;
+pub use intensity_ceiling_cmd::IntensityCeilingCmd;
+#[cfg(feature = "serialize-json")]
+pub use json_schema::message_json_schema;
+pub use json_schema::ButtplugJsonSchema;
+pub use kick_client_cmd::KickClientCmd;
 pub use kiiroo_cmd::KiirooCmd;
 pub use linear_cmd::{LinearCmd, VectorSubcommand};
 pub use log_level::LogLevel;
 pub use lovense_cmd::LovenseCmd;
+pub use low_battery_warning::LowBatteryWarning;
 pub use ok::Ok;
+pub use pattern_load_cmd::PatternLoadCmd;
+pub use pattern_play_cmd::PatternPlayCmd;
+pub use pattern_stop_cmd::PatternStopCmd;
 pub use ping::Ping;
 pub use raw_read_cmd::RawReadCmd;
 pub use raw_reading::RawReading;
@@ -100,20 +151,25 @@ pub use request_device_list::RequestDeviceList;
 pub use request_log::RequestLog;
 pub use request_server_info::RequestServerInfo;
 pub use rotate_cmd::{RotateCmd, RotationSubcommand};
+pub use rotate_position_cmd::RotatePositionCmd;
 pub use rssi_level_cmd::RSSILevelCmd;
 pub use rssi_level_reading::RSSILevelReading;
 pub use scalar_cmd::{ScalarCmd, ScalarSubcommand};
+pub use scalar_loop_cmd::{ScalarLoopCmd, ScalarLoopStep};
+pub use scanning_enabled_cmd::ScanningEnabledCmd;
 pub use scanning_finished::ScanningFinished;
 pub use sensor_read_cmd::SensorReadCmd;
 pub use sensor_reading::SensorReading;
 pub use sensor_subscribe_cmd::SensorSubscribeCmd;
 pub use sensor_unsubscribe_cmd::SensorUnsubscribeCmd;
 pub use server_info::{ServerInfo, ServerInfoV0};
+pub use server_shutdown_cmd::ServerShutdownCmd;
 pub use single_motor_vibrate_cmd::SingleMotorVibrateCmd;
 pub use start_scanning::StartScanning;
 pub use stop_all_devices::StopAllDevices;
 pub use stop_device_cmd::StopDeviceCmd;
 pub use stop_scanning::StopScanning;
+pub use stroke_cmd::{StrokeCmd, StrokeWaveform};
 pub use test::Test;
 pub use vibrate_cmd::{VibrateCmd, VibrateSubcommand};
 pub use vorze_a10_cyclone_cmd::VorzeA10CycloneCmd;
@@ -129,7 +185,10 @@ use std::convert::TryFrom;
 /// Spec](https://buttplug-spec.docs.buttplug.io) versions.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
 #[repr(u32)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize_repr, Deserialize_repr))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize_repr, Deserialize_repr, ButtplugJsonSchema)
+)]
 pub enum ButtplugMessageSpecVersion {
   Version0 = 0,
   Version1 = 1,
@@ -217,7 +276,9 @@ pub trait ButtplugDeviceMessage: ButtplugMessage {
 
 /// Used in [MessageAttributes][crate::core::messages::DeviceMessageAttributes] for denoting message
 /// capabilties.
-#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
+#[derive(
+  Copy, Debug, Clone, PartialEq, Eq, Hash, Display, Serialize, Deserialize, ButtplugJsonSchema,
+)]
 pub enum ButtplugDeviceMessageType {
   VibrateCmd,
   LinearCmd,
@@ -268,12 +329,14 @@ impl Ord for ButtplugDeviceMessageType {
   ButtplugMessageValidator,
   ButtplugClientMessageType,
   FromSpecificButtplugMessage,
+  IntoStaticStr,
 )]
 pub enum ButtplugClientMessage {
   Ping(Ping),
   RequestLog(RequestLog),
   // Handshake messages
   RequestServerInfo(RequestServerInfo),
+  EndSessionCmd(EndSessionCmd),
   // Device enumeration messages
   StartScanning(StartScanning),
   StopScanning(StopScanning),
@@ -283,6 +346,7 @@ pub enum ButtplugClientMessage {
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
+  RotatePositionCmd(RotatePositionCmd),
   RawWriteCmd(RawWriteCmd),
   RawReadCmd(RawReadCmd),
   StopDeviceCmd(StopDeviceCmd),
@@ -295,6 +359,31 @@ pub enum ButtplugClientMessage {
   SensorReadCmd(SensorReadCmd),
   SensorSubscribeCmd(SensorSubscribeCmd),
   SensorUnsubscribeCmd(SensorUnsubscribeCmd),
+  // Playback commands
+  FunscriptLoadCmd(FunscriptLoadCmd),
+  FunscriptPlaybackCmd(FunscriptPlaybackCmd),
+  PatternLoadCmd(PatternLoadCmd),
+  PatternPlayCmd(PatternPlayCmd),
+  PatternStopCmd(PatternStopCmd),
+  ScalarLoopCmd(ScalarLoopCmd),
+  StrokeCmd(StrokeCmd),
+  // Device ownership commands
+  DeviceLockCmd(DeviceLockCmd),
+  DeviceUnlockCmd(DeviceUnlockCmd),
+  // Device settings commands
+  DeviceDisplayNameCmd(DeviceDisplayNameCmd),
+  DeviceFirmwareUpdateCmd(DeviceFirmwareUpdateCmd),
+  // Diagnostics commands
+  DeviceSelfTestCmd(DeviceSelfTestCmd),
+  // Cancellation commands
+  CancelCmd(CancelCmd),
+  // Server settings commands
+  IntensityCeilingCmd(IntensityCeilingCmd),
+  // Server management commands
+  ServerShutdownCmd(ServerShutdownCmd),
+  ForceStopAllDevicesCmd(ForceStopAllDevicesCmd),
+  KickClientCmd(KickClientCmd),
+  ScanningEnabledCmd(ScanningEnabledCmd),
   // Deprecated generic commands
   SingleMotorVibrateCmd(SingleMotorVibrateCmd),
   // Deprecated device specific commands
@@ -305,6 +394,13 @@ pub enum ButtplugClientMessage {
   // To Add:
 }
 
+impl ButtplugClientMessage {
+  /// Name of the message type, i.e. "StartScanning".
+  pub fn name(&self) -> &'static str {
+    self.into()
+  }
+}
+
 /// Represents all possible messages a
 /// [ButtplugServer][crate::server::ButtplugServer] can send to a
 /// [ButtplugClient][crate::client::ButtplugClient].
@@ -318,6 +414,7 @@ pub enum ButtplugClientMessage {
   ButtplugServerMessageType,
   FromSpecificButtplugMessage,
 )]
+#[allow(clippy::large_enum_variant)]
 pub enum ButtplugServerMessage {
   // Status messages
   Ok(Ok),
@@ -335,6 +432,9 @@ pub enum ButtplugServerMessage {
   RawReading(RawReading),
   // Sensor Reading Messages
   SensorReading(SensorReading),
+  LowBatteryWarning(LowBatteryWarning),
+  // Diagnostics messages
+  DeviceSelfTestReport(DeviceSelfTestReport),
   // Deprecated Server Messages
   BatteryLevelReading(BatteryLevelReading),
   RSSILevelReading(RSSILevelReading),
@@ -388,11 +488,15 @@ pub type ButtplugCurrentSpecServerMessage = ButtplugSpecV3ServerMessage;
   FromSpecificButtplugMessage,
   TryFromButtplugClientMessage,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV3ClientMessage {
   // Handshake messages
   RequestServerInfo(RequestServerInfo),
   Ping(Ping),
+  EndSessionCmd(EndSessionCmd),
   // Device enumeration messages
   StartScanning(StartScanning),
   StopScanning(StopScanning),
@@ -402,6 +506,7 @@ pub enum ButtplugSpecV3ClientMessage {
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
+  RotatePositionCmd(RotatePositionCmd),
   RawWriteCmd(RawWriteCmd),
   RawReadCmd(RawReadCmd),
   StopDeviceCmd(StopDeviceCmd),
@@ -412,6 +517,31 @@ pub enum ButtplugSpecV3ClientMessage {
   SensorReadCmd(SensorReadCmd),
   SensorSubscribeCmd(SensorSubscribeCmd),
   SensorUnsubscribeCmd(SensorUnsubscribeCmd),
+  // Playback commands
+  FunscriptLoadCmd(FunscriptLoadCmd),
+  FunscriptPlaybackCmd(FunscriptPlaybackCmd),
+  PatternLoadCmd(PatternLoadCmd),
+  PatternPlayCmd(PatternPlayCmd),
+  PatternStopCmd(PatternStopCmd),
+  ScalarLoopCmd(ScalarLoopCmd),
+  StrokeCmd(StrokeCmd),
+  // Device ownership commands
+  DeviceLockCmd(DeviceLockCmd),
+  DeviceUnlockCmd(DeviceUnlockCmd),
+  // Device settings commands
+  DeviceDisplayNameCmd(DeviceDisplayNameCmd),
+  DeviceFirmwareUpdateCmd(DeviceFirmwareUpdateCmd),
+  // Diagnostics commands
+  DeviceSelfTestCmd(DeviceSelfTestCmd),
+  // Cancellation commands
+  CancelCmd(CancelCmd),
+  // Server settings commands
+  IntensityCeilingCmd(IntensityCeilingCmd),
+  // Server management commands
+  ServerShutdownCmd(ServerShutdownCmd),
+  ForceStopAllDevicesCmd(ForceStopAllDevicesCmd),
+  KickClientCmd(KickClientCmd),
+  ScanningEnabledCmd(ScanningEnabledCmd),
 }
 
 /// Represents all server-to-client messages in v3 of the Buttplug Spec
@@ -425,7 +555,11 @@ pub enum ButtplugSpecV3ClientMessage {
   FromSpecificButtplugMessage,
   TryFromButtplugServerMessage,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+#[allow(clippy::large_enum_variant)]
 pub enum ButtplugSpecV3ServerMessage {
   // Status messages
   Ok(Ok),
@@ -441,6 +575,9 @@ pub enum ButtplugSpecV3ServerMessage {
   RawReading(RawReading),
   // Sensor commands
   SensorReading(SensorReading),
+  LowBatteryWarning(LowBatteryWarning),
+  // Diagnostics messages
+  DeviceSelfTestReport(DeviceSelfTestReport),
 }
 
 impl ButtplugMessageFinalizer for ButtplugSpecV3ServerMessage {
@@ -465,7 +602,10 @@ impl ButtplugMessageFinalizer for ButtplugSpecV3ServerMessage {
   FromSpecificButtplugMessage,
   TryFromButtplugClientMessage,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV2ClientMessage {
   // Handshake messages
   RequestServerInfo(RequestServerInfo),
@@ -499,7 +639,10 @@ pub enum ButtplugSpecV2ClientMessage {
   ButtplugMessageFinalizer,
   ButtplugServerMessageType,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV2ServerMessage {
   // Status messages
   Ok(Ok),
@@ -560,7 +703,10 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV2ServerMessage {
   ButtplugMessageFinalizer,
   TryFromButtplugClientMessage,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV1ClientMessage {
   // Handshake messages
   RequestServerInfo(RequestServerInfo),
@@ -595,7 +741,10 @@ pub enum ButtplugSpecV1ClientMessage {
   ButtplugMessageFinalizer,
   ButtplugServerMessageType,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV1ServerMessage {
   // Status messages
   Ok(Ok),
@@ -655,7 +804,10 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV1ServerMessage {
   ButtplugMessageFinalizer,
   TryFromButtplugClientMessage,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV0ClientMessage {
   RequestLog(RequestLog),
   Ping(Ping),
@@ -688,7 +840,10 @@ pub enum ButtplugSpecV0ClientMessage {
   ButtplugMessageFinalizer,
   ButtplugServerMessageType,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugSpecV0ServerMessage {
   // Status messages
   Ok(Ok),
@@ -758,6 +913,20 @@ pub enum ButtplugDeviceManagerMessageUnion {
   StopScanning(StopScanning),
 }
 
+impl ButtplugDeviceManagerMessageUnion {
+  /// Returns true if the client message can be converted to this type, so routing code can check
+  /// message types without cloning messages to try conversions.
+  pub fn matches_client_message(msg: &ButtplugClientMessage) -> bool {
+    matches!(
+      msg,
+      ButtplugClientMessage::RequestDeviceList(_)
+        | ButtplugClientMessage::StopAllDevices(_)
+        | ButtplugClientMessage::StartScanning(_)
+        | ButtplugClientMessage::StopScanning(_)
+    )
+  }
+}
+
 /// Represents all possible device command message types.
 #[derive(
   Debug,
@@ -770,7 +939,10 @@ pub enum ButtplugDeviceManagerMessageUnion {
   FromSpecificButtplugMessage,
   TryFromButtplugClientMessage,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub enum ButtplugDeviceCommandMessageUnion {
   FleshlightLaunchFW12Cmd(FleshlightLaunchFW12Cmd),
   SingleMotorVibrateCmd(SingleMotorVibrateCmd),
@@ -780,6 +952,7 @@ pub enum ButtplugDeviceCommandMessageUnion {
   VibrateCmd(VibrateCmd),
   LinearCmd(LinearCmd),
   RotateCmd(RotateCmd),
+  RotatePositionCmd(RotatePositionCmd),
   RawWriteCmd(RawWriteCmd),
   RawReadCmd(RawReadCmd),
   StopDeviceCmd(StopDeviceCmd),
@@ -792,3 +965,65 @@ pub enum ButtplugDeviceCommandMessageUnion {
   SensorSubscribeCmd(SensorSubscribeCmd),
   SensorUnsubscribeCmd(SensorUnsubscribeCmd),
 }
+
+impl ButtplugDeviceCommandMessageUnion {
+  /// Returns true if the client message can be converted to this type, so routing code can check
+  /// message types without cloning messages to try conversions.
+  pub fn matches_client_message(msg: &ButtplugClientMessage) -> bool {
+    matches!(
+      msg,
+      ButtplugClientMessage::FleshlightLaunchFW12Cmd(_)
+        | ButtplugClientMessage::SingleMotorVibrateCmd(_)
+        | ButtplugClientMessage::VorzeA10CycloneCmd(_)
+        | ButtplugClientMessage::KiirooCmd(_)
+        | ButtplugClientMessage::VibrateCmd(_)
+        | ButtplugClientMessage::LinearCmd(_)
+        | ButtplugClientMessage::RotateCmd(_)
+        | ButtplugClientMessage::RotatePositionCmd(_)
+        | ButtplugClientMessage::RawWriteCmd(_)
+        | ButtplugClientMessage::RawReadCmd(_)
+        | ButtplugClientMessage::StopDeviceCmd(_)
+        | ButtplugClientMessage::RawSubscribeCmd(_)
+        | ButtplugClientMessage::RawUnsubscribeCmd(_)
+        | ButtplugClientMessage::BatteryLevelCmd(_)
+        | ButtplugClientMessage::RSSILevelCmd(_)
+        | ButtplugClientMessage::ScalarCmd(_)
+        | ButtplugClientMessage::SensorReadCmd(_)
+        | ButtplugClientMessage::SensorSubscribeCmd(_)
+        | ButtplugClientMessage::SensorUnsubscribeCmd(_)
+    )
+  }
+}
+
+#[cfg(test)]
+mod union_test {
+  use super::*;
+
+  #[test]
+  fn test_union_matches_client_message() {
+    let messages: Vec<ButtplugClientMessage> = vec![
+      Ping::default().into(),
+      RequestDeviceList::default().into(),
+      StartScanning::default().into(),
+      StopAllDevices::default().into(),
+      StopDeviceCmd::new(0).into(),
+      ScalarCmd::new(
+        0,
+        vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+      BatteryLevelCmd::new(0).into(),
+      RotatePositionCmd::new(0, vec![VectorSubcommand::new(0, 100, 0.5)]).into(),
+    ];
+    for msg in messages {
+      assert_eq!(
+        ButtplugDeviceCommandMessageUnion::matches_client_message(&msg),
+        ButtplugDeviceCommandMessageUnion::try_from(msg.clone()).is_ok()
+      );
+      assert_eq!(
+        ButtplugDeviceManagerMessageUnion::matches_client_message(&msg),
+        ButtplugDeviceManagerMessageUnion::try_from(msg.clone()).is_ok()
+      );
+    }
+  }
+}
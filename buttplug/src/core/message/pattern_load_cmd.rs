@@ -0,0 +1,46 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Load a pattern file into the server for playback on a device.
+///
+/// Loading a pattern replaces any pattern already loaded for the device, stopping it if it was
+/// playing. Tracks for actuators the device does not have are ignored.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct PatternLoadCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Pattern"))]
+  #[getset(get = "pub")]
+  pattern: String,
+}
+
+impl PatternLoadCmd {
+  pub fn new(device_index: u32, pattern: &str) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      pattern: pattern.to_owned(),
+    }
+  }
+}
+
+impl ButtplugMessageValidator for PatternLoadCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -0,0 +1,49 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Start playing a pattern loaded via [PatternLoadCmd], optionally from a labeled section.
+///
+/// Playing restarts the pattern from the beginning (or from the given section) even if it is
+/// already playing.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct PatternPlayCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Section", skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get = "pub")]
+  section: Option<String>,
+}
+
+impl PatternPlayCmd {
+  pub fn new(device_index: u32, section: Option<&str>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      section: section.map(|x| x.to_owned()),
+    }
+  }
+}
+
+impl ButtplugMessageValidator for PatternPlayCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -13,7 +13,10 @@ use serde::{Deserialize, Serialize};
 #[derive(
   Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct RawReadCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
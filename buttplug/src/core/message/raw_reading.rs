@@ -23,7 +23,10 @@ use serde::{Deserialize, Serialize};
   Getters,
   CopyGetters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct RawReading {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -6,7 +6,7 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
@@ -14,9 +14,20 @@ fn return_version0() -> ButtplugMessageSpecVersion {
   ButtplugMessageSpecVersion::Version0
 }
 #[derive(
-  Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq, Getters, CopyGetters,
+  Debug,
+  ButtplugMessage,
+  ButtplugMessageFinalizer,
+  Clone,
+  PartialEq,
+  Eq,
+  Getters,
+  CopyGetters,
+  Setters,
+)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct RequestServerInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -32,6 +43,43 @@ pub struct RequestServerInfo {
   )]
   #[getset(get_copy = "pub")]
   message_version: ButtplugMessageSpecVersion,
+  /// Ping timeout the client would like for this connection, in milliseconds. 0 asks for no ping
+  /// timeout. The server may limit this, the ping time actually used is returned in
+  /// [ServerInfo].
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "MaxPingTime",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub")]
+  max_ping_time: Option<u32>,
+  /// Token from the [ServerInfo] of a previous connection, to resume that session if the server
+  /// is still holding it.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ResumptionToken",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  resumption_token: Option<String>,
+  /// Capabilities the client would like to use on this connection. See [ButtplugCapability].
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "Capabilities",
+      default,
+      skip_serializing_if = "Vec::is_empty",
+      deserialize_with = "super::capability::deserialize_capabilities"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  capabilities: Vec<ButtplugCapability>,
 }
 
 impl RequestServerInfo {
@@ -40,6 +88,24 @@ impl RequestServerInfo {
       id: 1,
       client_name: client_name.to_string(),
       message_version,
+      max_ping_time: None,
+      resumption_token: None,
+      capabilities: vec![],
+    }
+  }
+
+  pub fn new_with_max_ping_time(
+    client_name: &str,
+    message_version: ButtplugMessageSpecVersion,
+    max_ping_time: u32,
+  ) -> Self {
+    Self {
+      id: 1,
+      client_name: client_name.to_string(),
+      message_version,
+      max_ping_time: Some(max_ping_time),
+      resumption_token: None,
+      capabilities: vec![],
     }
   }
 }
@@ -68,6 +134,9 @@ mod test {
       id: 1,
       client_name: "Test Client".to_owned(),
       message_version: ButtplugMessageSpecVersion::Version2,
+      max_ping_time: None,
+      resumption_token: None,
+      capabilities: vec![],
     };
     assert_eq!(
       serde_json::from_str::<RequestServerInfo>(new_json).expect("Test unwrap"),
@@ -88,10 +157,35 @@ mod test {
       id: 1,
       client_name: "Test Client".to_owned(),
       message_version: ButtplugMessageSpecVersion::Version0,
+      max_ping_time: None,
+      resumption_token: None,
+      capabilities: vec![],
     };
     assert_eq!(
       serde_json::from_str::<RequestServerInfo>(old_json).expect("Test unwrap"),
       old_msg
     );
   }
+
+  #[cfg(feature = "serialize-json")]
+  #[test]
+  fn test_request_server_info_max_ping_time_json_conversion() {
+    let json = r#"
+{
+        "Id": 1,
+        "ClientName": "Test Client",
+        "MessageVersion": 3,
+        "MaxPingTime": 5000
+}
+        "#;
+    let msg = RequestServerInfo::new_with_max_ping_time(
+      "Test Client",
+      ButtplugMessageSpecVersion::Version3,
+      5000,
+    );
+    assert_eq!(
+      serde_json::from_str::<RequestServerInfo>(json).expect("Test unwrap"),
+      msg
+    );
+  }
 }
@@ -11,7 +11,10 @@ pub use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq, Clone, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 #[getset(get_copy = "pub")]
 pub struct RotationSubcommand {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
@@ -32,8 +35,13 @@ impl RotationSubcommand {
   }
 }
 
-#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[derive(
+  Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters, CopyGetters,
+)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct RotateCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -43,6 +51,13 @@ pub struct RotateCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Rotations"))]
   #[getset(get = "pub")]
   rotations: Vec<RotationSubcommand>,
+  /// Server time to execute the command at, in milliseconds since the Unix epoch.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Timestamp", skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get_copy = "pub")]
+  timestamp: Option<u64>,
 }
 
 impl RotateCmd {
@@ -51,8 +66,16 @@ impl RotateCmd {
       id: 1,
       device_index,
       rotations,
+      timestamp: None,
     }
   }
+
+  /// Schedule the command to run at a certain server time, in milliseconds since the Unix epoch.
+  /// Commands with timestamps in the past run immediately.
+  pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+    self.timestamp = Some(timestamp);
+    self
+  }
 }
 
 impl ButtplugMessageValidator for RotateCmd {
@@ -0,0 +1,57 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::Getters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Turn positional rotators (servos, TCode twist axes, etc) to an angle in a certain amount of time.
+///
+/// Positions are 0.0-1.0 across the rotator's range of motion. Only valid for rotation features
+/// with a RotationMode of Positional, continuous rotators still use RotateCmd.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct RotatePositionCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Vectors"))]
+  #[getset(get = "pub")]
+  vectors: Vec<VectorSubcommand>,
+}
+
+impl RotatePositionCmd {
+  pub fn new(device_index: u32, vectors: Vec<VectorSubcommand>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      vectors,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for RotatePositionCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    for vec in &self.vectors {
+      self.is_in_command_range(
+        vec.position(),
+        format!(
+          "VectorSubcommand position {} for index {} is invalid, should be between 0.0 and 1.0",
+          vec.position(),
+          vec.index()
+        ),
+      )?;
+    }
+    Ok(())
+  }
+}
@@ -12,7 +12,10 @@ use serde::{Deserialize, Serialize};
 
 /// Generic command for setting a level (single magnitude value) of a device feature.
 #[derive(Debug, PartialEq, Clone, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 #[getset(get_copy = "pub")]
 pub struct ScalarSubcommand {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
@@ -34,9 +37,19 @@ impl ScalarSubcommand {
 }
 
 #[derive(
-  Debug, Default, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters,
+  Debug,
+  Default,
+  ButtplugDeviceMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Clone,
+  Getters,
+  CopyGetters,
+)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct ScalarCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -45,6 +58,13 @@ pub struct ScalarCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Scalars"))]
   #[getset(get = "pub")]
   scalars: Vec<ScalarSubcommand>,
+  /// Server time to execute the command at, in milliseconds since the Unix epoch.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Timestamp", skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get_copy = "pub")]
+  timestamp: Option<u64>,
 }
 
 impl ScalarCmd {
@@ -53,8 +73,16 @@ impl ScalarCmd {
       id: 1,
       device_index,
       scalars,
+      timestamp: None,
     }
   }
+
+  /// Schedule the command to run at a certain server time, in milliseconds since the Unix epoch.
+  /// Commands with timestamps in the past run immediately.
+  pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+    self.timestamp = Some(timestamp);
+    self
+  }
 }
 
 impl ButtplugMessageValidator for ScalarCmd {
@@ -84,6 +112,7 @@ impl From<VibrateCmd> for ScalarCmd {
       id: vibrate_cmd.id(),
       device_index: vibrate_cmd.device_index(),
       scalars: subcommands,
+      timestamp: None,
     }
   }
 }
@@ -0,0 +1,91 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Set of scalar values to hold for a certain amount of time.
+#[derive(Debug, PartialEq, Clone, Getters, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct ScalarLoopStep {
+  /// Time to hold the step values for, in milliseconds.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Duration"))]
+  #[getset(get_copy = "pub")]
+  duration: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Scalars"))]
+  #[getset(get = "pub")]
+  scalars: Vec<ScalarSubcommand>,
+}
+
+impl ScalarLoopStep {
+  pub fn new(duration: u32, scalars: Vec<ScalarSubcommand>) -> Self {
+    Self { duration, scalars }
+  }
+}
+
+/// Repeat a list of scalar steps on the server until the device is stopped or sent a new command.
+///
+/// This lets simple pulsing patterns run without continuous client traffic. The loop is cancelled
+/// by StopDeviceCmd, StopAllDevices, any ScalarCmd sent to the device, or another ScalarLoopCmd.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct ScalarLoopCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Steps"))]
+  #[getset(get = "pub")]
+  steps: Vec<ScalarLoopStep>,
+}
+
+impl ScalarLoopCmd {
+  pub fn new(device_index: u32, steps: Vec<ScalarLoopStep>) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      steps,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for ScalarLoopCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    if self.steps.is_empty() {
+      return Err(ButtplugMessageError::InvalidMessageContents(
+        "ScalarLoopCmd requires at least one step".to_owned(),
+      ));
+    }
+    for step in &self.steps {
+      if step.duration == 0 {
+        return Err(ButtplugMessageError::InvalidMessageContents(
+          "ScalarLoopCmd step durations should be greater than 0".to_owned(),
+        ));
+      }
+      for level in &step.scalars {
+        self.is_in_command_range(
+          level.scalar(),
+          format!(
+            "Level {} for ScalarLoopCmd index {} is invalid. Level should be a value between 0.0 and 1.0",
+            level.scalar(),
+            level.index()
+          ),
+        )?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,38 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Allow or refuse device scanning for every client. Disabling scanning stops any scan in
+/// progress, and [StartScanning] fails until scanning is enabled again.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct ScanningEnabledCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Enabled"))]
+  #[getset(get_copy = "pub")]
+  enabled: bool,
+}
+
+impl ScanningEnabledCmd {
+  pub fn new(enabled: bool) -> Self {
+    Self { id: 1, enabled }
+  }
+}
+
+impl ButtplugMessageValidator for ScanningEnabledCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -10,7 +10,10 @@ use super::*;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct ScanningFinished {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -23,7 +23,10 @@ use serde::{Deserialize, Serialize};
   PartialEq,
   Eq,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct SensorReading {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -11,7 +11,10 @@ use getset::Getters;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct SensorSubscribeCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -5,9 +5,14 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::{ButtplugMessageSerializer, ButtplugSerializedMessage, ButtplugSerializerError};
+use super::{
+  ButtplugDeserializedWithResponses,
+  ButtplugMessageSerializer,
+  ButtplugSerializedMessage,
+  ButtplugSerializerError,
+};
 use crate::core::{
-  errors::{ButtplugError, ButtplugHandshakeError},
+  errors::{ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
   message::{
     self,
     ButtplugClientMessage,
@@ -135,59 +140,146 @@ where
   Ok(result)
 }
 
+/// Messages deserialized from a batch, and the id and error of each message that couldn't be.
+type TolerantDeserializeResult<T> = (Vec<T>, Vec<(u32, ButtplugSerializerError)>);
+
+/// Reads the id of a message that may not be valid, so an error can be sent back for it. Returns 0
+/// if there's no readable id.
+fn message_id(json_msg: &Value) -> u32 {
+  json_msg
+    .as_object()
+    .and_then(|msg| msg.values().next())
+    .and_then(|fields| fields.get("Id"))
+    .and_then(Value::as_u64)
+    .and_then(|id| u32::try_from(id).ok())
+    .unwrap_or(0)
+}
+
+fn deserialize_single_message<T>(
+  validator: &JSONSchema,
+  json_msg: Value,
+) -> Result<T, ButtplugSerializerError>
+where
+  T: serde::de::DeserializeOwned + ButtplugMessageFinalizer + Clone + Debug,
+{
+  // The schema describes message arrays, so validate the message as an array of one.
+  let json_msg = Value::Array(vec![json_msg]);
+  if let Err(e) = validator.validate(&json_msg) {
+    let err_vec: Vec<String> = e.map(|err| err.to_string()).collect();
+    return Err(ButtplugSerializerError::JsonValidatorError(format!(
+      "Message: {} - Error: {:?}",
+      json_msg, err_vec
+    )));
+  }
+  let mut msg = serde_json::from_value::<Vec<T>>(json_msg.clone())
+    .map_err(|e| {
+      ButtplugSerializerError::JsonSerializerError(format!(
+        "Message: {} - Error: {:?}",
+        json_msg, e
+      ))
+    })?
+    .pop()
+    .ok_or_else(|| {
+      ButtplugSerializerError::JsonSerializerError(format!("Message: {} - Error: Empty", json_msg))
+    })?;
+  msg.finalize();
+  Ok(msg)
+}
+
+/// Deserializes each message of a batch on its own, so one malformed message doesn't fail the rest
+/// of the batch. Only fails if the text isn't JSON at all.
+pub fn deserialize_to_message_tolerant<T>(
+  validator: &JSONSchema,
+  msg_str: &str,
+) -> Result<TolerantDeserializeResult<T>, ButtplugSerializerError>
+where
+  T: serde::de::DeserializeOwned + ButtplugMessageFinalizer + Clone + Debug,
+{
+  let stream = Deserializer::from_str(msg_str).into_iter::<Value>();
+
+  let mut result = vec![];
+  let mut errors = vec![];
+
+  for msg in stream {
+    let json_msg = msg.map_err(|e| {
+      ButtplugSerializerError::JsonSerializerError(format!("Message: {} - Error: {:?}", msg_str, e))
+    })?;
+    let Value::Array(json_msgs) = json_msg else {
+      errors.push((
+        0,
+        ButtplugSerializerError::JsonValidatorError(format!(
+          "Message: {} - Error: Expected an array of messages",
+          json_msg
+        )),
+      ));
+      continue;
+    };
+    for json_msg in json_msgs {
+      let id = message_id(&json_msg);
+      match deserialize_single_message::<T>(validator, json_msg) {
+        Ok(msg) => result.push(msg),
+        Err(e) => errors.push((id, e)),
+      }
+    }
+  }
+  Ok((result, errors))
+}
+
+fn into_client_messages<T>(
+  (msgs, errors): TolerantDeserializeResult<T>,
+) -> TolerantDeserializeResult<ButtplugClientMessage>
+where
+  T: Into<ButtplugClientMessage>,
+{
+  (msgs.into_iter().map(|m| m.into()).collect(), errors)
+}
+
+/// Error message to answer a client message that couldn't be deserialized with.
+fn deserialization_error_message(id: u32, error: ButtplugSerializerError) -> ButtplugServerMessage {
+  let mut error_msg = message::Error::from(ButtplugError::from(
+    ButtplugMessageError::MessageSerializationError(error),
+  ));
+  error_msg.set_id(id);
+  ButtplugServerMessage::Error(error_msg)
+}
+
+/// Converts server messages to a message spec version. Messages the version doesn't have are
+/// replaced with an [Error](message::Error) saying so.
+fn server_messages_to_version<T>(msgs: &[ButtplugServerMessage]) -> Vec<T>
+where
+  T: TryFrom<ButtplugServerMessage, Error = ButtplugMessageError>,
+{
+  msgs
+    .iter()
+    .cloned()
+    .map(|msg| {
+      T::try_from(msg).unwrap_or_else(|err| {
+        T::try_from(ButtplugServerMessage::Error(
+          ButtplugError::from(err).into(),
+        ))
+        .expect("Every message spec version has Error messages")
+      })
+    })
+    .collect()
+}
+
 fn serialize_to_version(
   version: ButtplugMessageSpecVersion,
   msgs: &[ButtplugServerMessage],
 ) -> ButtplugSerializedMessage {
   ButtplugSerializedMessage::Text(match version {
-    ButtplugMessageSpecVersion::Version0 => {
-      let msg_vec: Vec<ButtplugSpecV0ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV0ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV0ServerMessage::Error(
-            message::Error::from(ButtplugError::from(err)).into(),
-          ),
-        })
-        .collect();
-      vec_to_protocol_json(&msg_vec)
-    }
-    ButtplugMessageSpecVersion::Version1 => {
-      let msg_vec: Vec<ButtplugSpecV1ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV1ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV1ServerMessage::Error(
-            message::Error::from(ButtplugError::from(err)).into(),
-          ),
-        })
-        .collect();
-      vec_to_protocol_json(&msg_vec)
-    }
-    ButtplugMessageSpecVersion::Version2 => {
-      let msg_vec: Vec<ButtplugSpecV2ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV2ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV2ServerMessage::Error(ButtplugError::from(err).into()),
-        })
-        .collect();
-      vec_to_protocol_json(&msg_vec)
-    }
-    ButtplugMessageSpecVersion::Version3 => {
-      let msg_vec: Vec<ButtplugSpecV3ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV3ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV3ServerMessage::Error(ButtplugError::from(err).into()),
-        })
-        .collect();
-      vec_to_protocol_json(&msg_vec)
-    }
+    ButtplugMessageSpecVersion::Version0 => vec_to_protocol_json(&server_messages_to_version::<
+      ButtplugSpecV0ServerMessage,
+    >(msgs)),
+    ButtplugMessageSpecVersion::Version1 => vec_to_protocol_json(&server_messages_to_version::<
+      ButtplugSpecV1ServerMessage,
+    >(msgs)),
+    ButtplugMessageSpecVersion::Version2 => vec_to_protocol_json(&server_messages_to_version::<
+      ButtplugSpecV2ServerMessage,
+    >(msgs)),
+    ButtplugMessageSpecVersion::Version3 => vec_to_protocol_json(&server_messages_to_version::<
+      ButtplugSpecV3ServerMessage,
+    >(msgs)),
   })
 }
 
@@ -284,6 +376,101 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
   }
 }
 
+/// Server serializer that answers malformed messages in a batch with an
+/// [Error](message::Error) each, instead of dropping the whole batch.
+///
+/// The regular [ButtplugServerJSONSerializer] fails a whole batch if any message in it is malformed,
+/// and the batch is dropped without a reply. This serializer still handles the rest of the batch,
+/// so buggy third party clients get an error for the bad message and carry on. The handshake
+/// (RequestServerInfo) still has to be valid, as nothing else can be read without knowing the
+/// message spec version.
+#[derive(Default)]
+pub struct ButtplugTolerantServerJSONSerializer {
+  serializer: ButtplugServerJSONSerializer,
+}
+
+impl ButtplugTolerantServerJSONSerializer {
+  pub fn force_message_version(&self, version: &ButtplugMessageSpecVersion) {
+    self.serializer.force_message_version(version);
+  }
+
+  fn deserialize_tolerant(
+    &self,
+    serialized_msg: &ButtplugSerializedMessage,
+  ) -> Result<TolerantDeserializeResult<ButtplugClientMessage>, ButtplugSerializerError> {
+    let msg = if let ButtplugSerializedMessage::Text(text_msg) = serialized_msg {
+      text_msg
+    } else {
+      return Err(ButtplugSerializerError::BinaryDeserializationError);
+    };
+    let validator = &self.serializer.validator;
+    Ok(match self.serializer.message_version.get() {
+      Some(ButtplugMessageSpecVersion::Version0) => {
+        into_client_messages(deserialize_to_message_tolerant::<
+          ButtplugSpecV0ClientMessage,
+        >(validator, msg)?)
+      }
+      Some(ButtplugMessageSpecVersion::Version1) => {
+        into_client_messages(deserialize_to_message_tolerant::<
+          ButtplugSpecV1ClientMessage,
+        >(validator, msg)?)
+      }
+      Some(ButtplugMessageSpecVersion::Version2) => {
+        into_client_messages(deserialize_to_message_tolerant::<
+          ButtplugSpecV2ClientMessage,
+        >(validator, msg)?)
+      }
+      Some(ButtplugMessageSpecVersion::Version3) => {
+        into_client_messages(deserialize_to_message_tolerant::<
+          ButtplugSpecV3ClientMessage,
+        >(validator, msg)?)
+      }
+      None => (self.serializer.deserialize(serialized_msg)?, vec![]),
+    })
+  }
+}
+
+impl ButtplugMessageSerializer for ButtplugTolerantServerJSONSerializer {
+  type Inbound = ButtplugClientMessage;
+  type Outbound = ButtplugServerMessage;
+
+  fn deserialize(
+    &self,
+    serialized_msg: &ButtplugSerializedMessage,
+  ) -> Result<Vec<ButtplugClientMessage>, ButtplugSerializerError> {
+    self
+      .deserialize_tolerant(serialized_msg)
+      .map(|(msgs, _)| msgs)
+  }
+
+  fn deserialize_with_responses(
+    &self,
+    serialized_msg: &ButtplugSerializedMessage,
+  ) -> Result<
+    ButtplugDeserializedWithResponses<ButtplugClientMessage, ButtplugServerMessage>,
+    ButtplugSerializerError,
+  > {
+    // Even frames that can't be read at all get an answer, so the client knows it was dropped.
+    let (msgs, errors) = self
+      .deserialize_tolerant(serialized_msg)
+      .unwrap_or_else(|e| (vec![], vec![(0, e)]));
+    for (id, error) in &errors {
+      warn!("Dropping malformed message {} from client: {}", id, error);
+    }
+    Ok((
+      msgs,
+      errors
+        .into_iter()
+        .map(|(id, error)| deserialization_error_message(id, error))
+        .collect(),
+    ))
+  }
+
+  fn serialize(&self, msgs: &[ButtplugServerMessage]) -> ButtplugSerializedMessage {
+    self.serializer.serialize(msgs)
+  }
+}
+
 pub struct ButtplugClientJSONSerializerImpl {
   validator: JSONSchema,
 }
@@ -343,7 +530,11 @@ impl ButtplugMessageSerializer for ButtplugClientJSONSerializer {
 #[cfg(test)]
 mod test {
   use super::*;
-  use crate::core::message::{RequestServerInfo, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION};
+  use crate::core::message::{
+    RequestServerInfo,
+    StrokeWaveform,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  };
 
   #[test]
   fn test_correct_message_version() {
@@ -378,6 +569,99 @@ mod test {
     assert!(msg.is_err());
   }
 
+  #[test]
+  fn test_scalar_loop_cmd_schema() {
+    let json = r#"[
+        {
+          "RequestServerInfo": {
+              "Id": 1,
+              "ClientName": "Test Client",
+              "MessageVersion": 3
+          }
+        },
+        {
+          "ScalarLoopCmd": {
+              "Id": 2,
+              "DeviceIndex": 0,
+              "Steps": [
+                { "Duration": 100, "Scalars": [{ "Index": 0, "Scalar": 1.0, "ActuatorType": "Vibrate" }] },
+                { "Duration": 100, "Scalars": [{ "Index": 0, "Scalar": 0.0, "ActuatorType": "Vibrate" }] }
+              ]
+          }
+        }]"#;
+    let serializer = ButtplugServerJSONSerializer::default();
+    let messages = serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json.to_owned()))
+      .expect("Infallible deserialization");
+    assert!(matches!(
+      messages[1],
+      ButtplugClientMessage::ScalarLoopCmd(ref m) if m.steps().len() == 2
+    ));
+  }
+
+  #[test]
+  fn test_stroke_cmd_schema() {
+    let json = r#"[
+        {
+          "RequestServerInfo": {
+              "Id": 1,
+              "ClientName": "Test Client",
+              "MessageVersion": 3
+          }
+        },
+        {
+          "StrokeCmd": {
+              "Id": 2,
+              "DeviceIndex": 0,
+              "Min": 0.2,
+              "Max": 0.8,
+              "Speed": 60,
+              "Waveform": "Sine"
+          }
+        }]"#;
+    let serializer = ButtplugServerJSONSerializer::default();
+    let messages = serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json.to_owned()))
+      .expect("Infallible deserialization");
+    assert!(matches!(
+      messages[1],
+      ButtplugClientMessage::StrokeCmd(ref m) if m.waveform() == StrokeWaveform::Sine
+    ));
+  }
+
+  #[test]
+  fn test_rotate_position_cmd_schema() {
+    let json = r#"[
+        {
+          "RequestServerInfo": {
+              "Id": 1,
+              "ClientName": "Test Client",
+              "MessageVersion": 3
+          }
+        },
+        {
+          "RotatePositionCmd": {
+              "Id": 2,
+              "DeviceIndex": 0,
+              "Vectors": [
+                {
+                  "Index": 0,
+                  "Duration": 500,
+                  "Position": 0.25
+                }
+              ]
+          }
+        }]"#;
+    let serializer = ButtplugServerJSONSerializer::default();
+    let messages = serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json.to_owned()))
+      .expect("Infallible deserialization");
+    assert!(matches!(
+      messages[1],
+      ButtplugClientMessage::RotatePositionCmd(ref m) if m.vectors()[0].position() == 0.25
+    ));
+  }
+
   #[test]
   fn test_message_array() {
     let json = r#"[
@@ -473,6 +757,38 @@ mod test {
     ));
   }
 
+  #[test]
+  fn test_tolerant_message_array() {
+    let serializer = ButtplugTolerantServerJSONSerializer::default();
+    serializer.force_message_version(&ButtplugMessageSpecVersion::Version3);
+    let json = r#"[
+        { "Ping": { "Id": 1 } },
+        { "Ping": { "Id": 2, "NotAField": 1 } },
+        { "NotAMessage": { "Id": 3 } },
+        { "StopAllDevices": { "Id": 4 } }
+    ]"#;
+    let (messages, responses) = serializer
+      .deserialize_with_responses(&ButtplugSerializedMessage::Text(json.to_owned()))
+      .expect("Infallible deserialization");
+    assert_eq!(
+      messages.iter().map(|m| m.id()).collect::<Vec<_>>(),
+      vec![1, 4]
+    );
+    assert_eq!(responses.len(), 2);
+    for (response, id) in responses.iter().zip([2, 3]) {
+      assert!(matches!(
+        response,
+        ButtplugServerMessage::Error(e) if e.id() == id && e.error_code() == message::ErrorCode::ErrorMessage
+      ));
+    }
+    // Frames that aren't JSON at all still get an answer.
+    let (messages, responses) = serializer
+      .deserialize_with_responses(&ButtplugSerializedMessage::Text("[{".to_owned()))
+      .expect("Infallible deserialization");
+    assert!(messages.is_empty());
+    assert!(matches!(&responses[..], [ButtplugServerMessage::Error(e)] if e.id() == 0));
+  }
+
   #[test]
   fn test_client_incorrect_messages() {
     let incorrect_incoming_messages = vec![
@@ -15,11 +15,19 @@ pub use json_serializer::{
   ButtplugClientJSONSerializer,
   ButtplugClientJSONSerializerImpl,
   ButtplugServerJSONSerializer,
+  ButtplugTolerantServerJSONSerializer,
 };
+#[cfg(feature = "serialize-json")]
+mod round_trip;
+#[cfg(feature = "serialize-json")]
+pub use round_trip::{check_round_trip, ButtplugRoundTripMessage, ButtplugRoundTripResult};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 pub type ButtplugSerializerResult<T> = Result<T, ButtplugSerializerError>;
+/// Messages deserialized from a batch, and the messages to send back for parts of it that couldn't
+/// be deserialized.
+pub type ButtplugDeserializedWithResponses<Inbound, Outbound> = (Vec<Inbound>, Vec<Outbound>);
 
 #[derive(Debug, Error, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ButtplugSerializerError {
@@ -63,5 +71,14 @@ pub trait ButtplugMessageSerializer: Default + Sync + Send {
     &self,
     msg: &ButtplugSerializedMessage,
   ) -> ButtplugSerializerResult<Vec<Self::Inbound>>;
+  /// Deserializes a batch of messages, along with messages to send back for parts of the batch
+  /// that couldn't be deserialized. By default, a malformed message fails the whole batch.
+  fn deserialize_with_responses(
+    &self,
+    msg: &ButtplugSerializedMessage,
+  ) -> ButtplugSerializerResult<ButtplugDeserializedWithResponses<Self::Inbound, Self::Outbound>>
+  {
+    Ok((self.deserialize(msg)?, vec![]))
+  }
   fn serialize(&self, msg: &[Self::Outbound]) -> ButtplugSerializedMessage;
 }
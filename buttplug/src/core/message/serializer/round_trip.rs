@@ -0,0 +1,247 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Checks for whether messages survive being sent in older message spec versions.
+//!
+//! Older spec versions can't represent everything the current version can. Devices in a v2
+//! DeviceList lose their display names and actuator types, and a ScalarCmd can't be sent to a v1
+//! server at all. [check_round_trip] serializes a message to every spec version, reads it back, and
+//! reports what didn't make it, so applications can decide whether to fall back to simpler
+//! features before talking to an older peer.
+
+use crate::core::message::{
+  ButtplugClientMessage,
+  ButtplugMessageSpecVersion,
+  ButtplugServerMessage,
+  ButtplugSpecV0ClientMessage,
+  ButtplugSpecV0ServerMessage,
+  ButtplugSpecV1ClientMessage,
+  ButtplugSpecV1ServerMessage,
+  ButtplugSpecV2ClientMessage,
+  ButtplugSpecV2ServerMessage,
+  ButtplugSpecV3ClientMessage,
+  ButtplugSpecV3ServerMessage,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, convert::TryFrom};
+
+/// Every message spec version, oldest first.
+const SPEC_VERSIONS: [ButtplugMessageSpecVersion; 4] = [
+  ButtplugMessageSpecVersion::Version0,
+  ButtplugMessageSpecVersion::Version1,
+  ButtplugMessageSpecVersion::Version2,
+  ButtplugMessageSpecVersion::Version3,
+];
+
+/// Result of sending a message through a single message spec version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ButtplugRoundTripResult {
+  /// The message arrives unchanged.
+  Lossless,
+  /// The message can be sent, but these fields are dropped or changed on the way. Fields are given
+  /// as JSON paths from the message name, i.e. `DeviceAdded.DeviceMessages.ScalarCmd[0]`.
+  DroppedFields(Vec<String>),
+  /// The spec version doesn't have the message at all.
+  Unsupported,
+}
+
+/// Messages that can be checked with [check_round_trip].
+pub trait ButtplugRoundTripMessage {
+  /// JSON form of the message after being serialized to a spec version and read back, or None if
+  /// the version doesn't have the message.
+  fn spec_version_json(&self, version: ButtplugMessageSpecVersion) -> Option<Value>;
+}
+
+/// Serializes a message in a spec version and reads it back, returning the JSON of what was read.
+fn round_trip_json<T>(msg: T) -> Option<Value>
+where
+  T: Serialize + DeserializeOwned,
+{
+  let json = serde_json::to_value(msg).ok()?;
+  let read_back: T = serde_json::from_value(json).ok()?;
+  serde_json::to_value(read_back).ok()
+}
+
+impl ButtplugRoundTripMessage for ButtplugClientMessage {
+  fn spec_version_json(&self, version: ButtplugMessageSpecVersion) -> Option<Value> {
+    let msg = self.clone();
+    match version {
+      ButtplugMessageSpecVersion::Version0 => {
+        round_trip_json(ButtplugSpecV0ClientMessage::try_from(msg).ok()?)
+      }
+      ButtplugMessageSpecVersion::Version1 => {
+        round_trip_json(ButtplugSpecV1ClientMessage::try_from(msg).ok()?)
+      }
+      ButtplugMessageSpecVersion::Version2 => {
+        round_trip_json(ButtplugSpecV2ClientMessage::try_from(msg).ok()?)
+      }
+      ButtplugMessageSpecVersion::Version3 => {
+        round_trip_json(ButtplugSpecV3ClientMessage::try_from(msg).ok()?)
+      }
+    }
+  }
+}
+
+impl ButtplugRoundTripMessage for ButtplugServerMessage {
+  fn spec_version_json(&self, version: ButtplugMessageSpecVersion) -> Option<Value> {
+    let msg = self.clone();
+    match version {
+      ButtplugMessageSpecVersion::Version0 => {
+        round_trip_json(ButtplugSpecV0ServerMessage::try_from(msg).ok()?)
+      }
+      ButtplugMessageSpecVersion::Version1 => {
+        round_trip_json(ButtplugSpecV1ServerMessage::try_from(msg).ok()?)
+      }
+      ButtplugMessageSpecVersion::Version2 => {
+        round_trip_json(ButtplugSpecV2ServerMessage::try_from(msg).ok()?)
+      }
+      ButtplugMessageSpecVersion::Version3 => {
+        round_trip_json(ButtplugSpecV3ServerMessage::try_from(msg).ok()?)
+      }
+    }
+  }
+}
+
+/// Collects the paths of everything in `reference` that's missing or different in `other`. Only
+/// the outermost missing field is reported, not everything under it.
+fn dropped_fields(
+  path: String,
+  reference: &Value,
+  other: Option<&Value>,
+  dropped: &mut Vec<String>,
+) {
+  match (reference, other) {
+    (_, None) => dropped.push(path),
+    (Value::Object(reference), Some(Value::Object(other))) => {
+      for (key, value) in reference {
+        let field_path = if path.is_empty() {
+          key.clone()
+        } else {
+          format!("{}.{}", path, key)
+        };
+        dropped_fields(field_path, value, other.get(key), dropped);
+      }
+    }
+    (Value::Array(reference), Some(Value::Array(other))) => {
+      for (index, value) in reference.iter().enumerate() {
+        dropped_fields(
+          format!("{}[{}]", path, index),
+          value,
+          other.get(index),
+          dropped,
+        );
+      }
+    }
+    (reference, Some(other)) => {
+      if reference != other {
+        dropped.push(path);
+      }
+    }
+  }
+}
+
+/// Checks what's left of a message after sending it in each message spec version.
+///
+/// The message is compared against how it's sent in the newest spec version that has it, which is
+/// always [Lossless](ButtplugRoundTripResult::Lossless). Versions older than that report the fields
+/// they drop, and versions without the message report it as
+/// [Unsupported](ButtplugRoundTripResult::Unsupported).
+pub fn check_round_trip<T>(msg: &T) -> BTreeMap<ButtplugMessageSpecVersion, ButtplugRoundTripResult>
+where
+  T: ButtplugRoundTripMessage,
+{
+  let version_json: BTreeMap<ButtplugMessageSpecVersion, Option<Value>> = SPEC_VERSIONS
+    .iter()
+    .map(|version| (*version, msg.spec_version_json(*version)))
+    .collect();
+  let reference = version_json.values().rev().flatten().next().cloned();
+  version_json
+    .into_iter()
+    .map(|(version, json)| {
+      let result = match (&reference, json) {
+        (Some(reference), Some(json)) => {
+          let mut dropped = vec![];
+          dropped_fields(String::new(), reference, Some(&json), &mut dropped);
+          if dropped.is_empty() {
+            ButtplugRoundTripResult::Lossless
+          } else {
+            ButtplugRoundTripResult::DroppedFields(dropped)
+          }
+        }
+        _ => ButtplugRoundTripResult::Unsupported,
+      };
+      (version, result)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{
+    ActuatorType,
+    ClientDeviceMessageAttributes,
+    DeviceAdded,
+    ScalarCmd,
+    ScalarSubcommand,
+    StopDeviceCmd,
+  };
+
+  #[test]
+  fn test_client_message_round_trip() {
+    let stop: ButtplugClientMessage = StopDeviceCmd::new(0).into();
+    assert!(check_round_trip(&stop)
+      .values()
+      .all(|x| *x == ButtplugRoundTripResult::Lossless));
+
+    let scalar: ButtplugClientMessage = ScalarCmd::new(
+      0,
+      vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+    )
+    .into();
+    let results = check_round_trip(&scalar);
+    assert_eq!(
+      results[&ButtplugMessageSpecVersion::Version3],
+      ButtplugRoundTripResult::Lossless
+    );
+    for version in [
+      ButtplugMessageSpecVersion::Version0,
+      ButtplugMessageSpecVersion::Version1,
+      ButtplugMessageSpecVersion::Version2,
+    ] {
+      assert_eq!(results[&version], ButtplugRoundTripResult::Unsupported);
+    }
+  }
+
+  #[test]
+  fn test_server_message_dropped_fields() {
+    let device_added: ButtplugServerMessage = DeviceAdded::new(
+      0,
+      "Test Device",
+      &Some("My Device".to_owned()),
+      &None,
+      &None,
+      &None,
+      &None,
+      &ClientDeviceMessageAttributes::default(),
+    )
+    .into();
+    let results = check_round_trip(&device_added);
+    assert_eq!(
+      results[&ButtplugMessageSpecVersion::Version3],
+      ButtplugRoundTripResult::Lossless
+    );
+    let ButtplugRoundTripResult::DroppedFields(dropped) =
+      &results[&ButtplugMessageSpecVersion::Version2]
+    else {
+      panic!("Display name should be dropped in v2");
+    };
+    assert!(dropped.contains(&"DeviceAdded.DeviceDisplayName".to_owned()));
+    assert!(!dropped.contains(&"DeviceAdded.DeviceName".to_owned()));
+  }
+}
@@ -6,14 +6,25 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 #[derive(
-  Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters, CopyGetters,
+  Debug,
+  ButtplugMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Eq,
+  Clone,
+  Getters,
+  CopyGetters,
+  Setters,
+)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct ServerInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -26,6 +37,43 @@ pub struct ServerInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "ServerName"))]
   #[getset(get = "pub")]
   server_name: String,
+  /// Token the client can send in [RequestServerInfo] when reconnecting, to resume this session
+  /// without its devices being stopped. Only sent if the server has session resumption enabled.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ResumptionToken",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  resumption_token: Option<String>,
+  /// Capabilities agreed on for this connection, the ones from [RequestServerInfo] that the
+  /// server also supports. See [ButtplugCapability].
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "Capabilities",
+      default,
+      skip_serializing_if = "Vec::is_empty",
+      deserialize_with = "super::capability::deserialize_capabilities"
+    )
+  )]
+  #[getset(get = "pub", set = "pub")]
+  capabilities: Vec<ButtplugCapability>,
+  /// Server clock at the time of the handshake, in milliseconds since the Unix epoch. Clients use
+  /// this to convert their own clock to server time for command timestamps.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ServerTime",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub", set = "pub")]
+  server_time: Option<u64>,
 }
 
 impl ServerInfo {
@@ -39,6 +87,9 @@ impl ServerInfo {
       message_version,
       max_ping_time,
       server_name: server_name.to_string(),
+      resumption_token: None,
+      capabilities: vec![],
+      server_time: None,
     }
   }
 }
@@ -52,7 +103,10 @@ impl ButtplugMessageValidator for ServerInfo {
 #[derive(
   Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters, CopyGetters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct ServerInfoV0 {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -0,0 +1,35 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Ask the server to shut down. Devices are stopped and every other client is disconnected, then
+/// the process hosting the server is told shutdown was requested, and is expected to close the
+/// connection to the requesting client on its way out.
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct ServerShutdownCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+}
+
+impl Default for ServerShutdownCmd {
+  fn default() -> Self {
+    Self { id: 1 }
+  }
+}
+
+impl ButtplugMessageValidator for ServerShutdownCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
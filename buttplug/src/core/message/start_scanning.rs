@@ -6,19 +6,44 @@
 // for full license information.
 
 use super::*;
+use getset::CopyGetters;
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct StartScanning {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
+  /// Time after which the server should stop scanning and send
+  /// [ScanningFinished], in milliseconds. If not set, the server falls back to its own default
+  /// scanning timeout, if it has one.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Timeout", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get_copy = "pub")]
+  timeout: Option<u32>,
+}
+
+impl StartScanning {
+  pub fn new_with_timeout(timeout: u32) -> Self {
+    Self {
+      id: 1,
+      timeout: Some(timeout),
+    }
+  }
 }
 
 impl Default for StartScanning {
   fn default() -> Self {
-    Self { id: 1 }
+    Self {
+      id: 1,
+      timeout: None,
+    }
   }
 }
 
@@ -27,3 +52,32 @@ impl ButtplugMessageValidator for StartScanning {
     self.is_not_system_id(self.id)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::StartScanning;
+
+  #[cfg(feature = "serialize-json")]
+  #[test]
+  fn test_start_scanning_json_conversion() {
+    let json = r#"
+{
+        "Id": 1
+}
+        "#;
+    assert_eq!(
+      serde_json::from_str::<StartScanning>(json).expect("Test unwrap"),
+      StartScanning::default()
+    );
+    let json = r#"
+{
+        "Id": 1,
+        "Timeout": 10000
+}
+        "#;
+    assert_eq!(
+      serde_json::from_str::<StartScanning>(json).expect("Test unwrap"),
+      StartScanning::new_with_timeout(10000)
+    );
+  }
+}
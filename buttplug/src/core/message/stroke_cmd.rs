@@ -0,0 +1,130 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Shape of the movement over a single stroke.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub enum StrokeWaveform {
+  /// Eases in and out at each end of the stroke.
+  Sine,
+  /// Constant speed between the ends of the stroke.
+  Triangle,
+  /// Moves to each end as fast as the stroke allows, then holds there.
+  Square,
+}
+
+/// Stroke linear devices back and forth on the server until the device is stopped or sent a new
+/// command.
+///
+/// This lets simple stroking run without the client streaming LinearCmd pairs. Strokes are cancelled
+/// by StopDeviceCmd, StopAllDevices, any LinearCmd sent to the device, or another StrokeCmd.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, CopyGetters)]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
+pub struct StrokeCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  /// Shallow end of the stroke, 0.0-1.0.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Min"))]
+  #[getset(get_copy = "pub")]
+  min: f64,
+  /// Deep end of the stroke, 0.0-1.0.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Max"))]
+  #[getset(get_copy = "pub")]
+  max: f64,
+  /// Full strokes (out and back) per minute.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Speed"))]
+  #[getset(get_copy = "pub")]
+  speed: f64,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Waveform"))]
+  #[getset(get_copy = "pub")]
+  waveform: StrokeWaveform,
+}
+
+impl StrokeCmd {
+  pub fn new(device_index: u32, min: f64, max: f64, speed: f64, waveform: StrokeWaveform) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      min,
+      max,
+      speed,
+      waveform,
+    }
+  }
+
+  /// Time taken by a full stroke, in milliseconds.
+  pub fn period(&self) -> f64 {
+    60000.0 / self.speed
+  }
+}
+
+impl ButtplugMessageValidator for StrokeCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    self.is_in_command_range(
+      self.min,
+      format!(
+        "StrokeCmd min {} is invalid. Should be a value between 0.0 and 1.0",
+        self.min
+      ),
+    )?;
+    self.is_in_command_range(
+      self.max,
+      format!(
+        "StrokeCmd max {} is invalid. Should be a value between 0.0 and 1.0",
+        self.max
+      ),
+    )?;
+    if self.min > self.max {
+      return Err(ButtplugMessageError::InvalidMessageContents(format!(
+        "StrokeCmd min {} is greater than max {}",
+        self.min, self.max
+      )));
+    }
+    if !self.speed.is_finite() || self.speed <= 0.0 {
+      return Err(ButtplugMessageError::InvalidMessageContents(format!(
+        "StrokeCmd speed {} is invalid, should be greater than 0.0",
+        self.speed
+      )));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_stroke_cmd_validity() {
+    assert!(StrokeCmd::new(0, 0.2, 0.8, 60.0, StrokeWaveform::Sine)
+      .is_valid()
+      .is_ok());
+    assert!(StrokeCmd::new(0, 0.8, 0.2, 60.0, StrokeWaveform::Sine)
+      .is_valid()
+      .is_err());
+    assert!(StrokeCmd::new(0, 0.2, 1.2, 60.0, StrokeWaveform::Triangle)
+      .is_valid()
+      .is_err());
+    assert!(StrokeCmd::new(0, 0.2, 0.8, 0.0, StrokeWaveform::Square)
+      .is_valid()
+      .is_err());
+  }
+}
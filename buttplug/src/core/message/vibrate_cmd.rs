@@ -11,7 +11,10 @@ use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, PartialEq, Clone, CopyGetters)]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 #[getset(get_copy = "pub")]
 pub struct VibrateSubcommand {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Index"))]
@@ -29,7 +32,10 @@ impl VibrateSubcommand {
 #[derive(
   Debug, Default, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters,
 )]
-#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "serialize-json",
+  derive(Serialize, Deserialize, ButtplugJsonSchema)
+)]
 pub struct VibrateCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
@@ -0,0 +1,153 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Append-only audit log of device commands, for shared or public installations that need to know
+//! who did what to which device.
+//!
+//! Each line of the log is a JSON [AuditLogEntry], recording when a command was received, which
+//! session and client sent it, the command itself, and the error if it was refused or failed. The
+//! file is only ever opened for appending, so existing entries are never rewritten, even across
+//! server restarts.
+
+use crate::core::message::{
+  self,
+  ButtplugClientMessage,
+  ButtplugCurrentSpecClientMessage,
+  ButtplugDeviceCommandMessageUnion,
+};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  sync::Mutex,
+  time::SystemTime,
+};
+
+/// A single command recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct AuditLogEntry {
+  /// When the command was received, in milliseconds since the unix epoch.
+  #[getset(get_copy = "pub")]
+  timestamp: u64,
+  /// Id of the [session](super::ButtplugServer::session_id) the command came from.
+  #[serde(rename = "session-id")]
+  #[getset(get_copy = "pub")]
+  session_id: u32,
+  /// Client name sent during the handshake.
+  #[serde(rename = "client-name", default)]
+  #[getset(get = "pub")]
+  client_name: Option<String>,
+  /// The command, in the same JSON format clients send it in. Commands from older spec versions
+  /// that have no current equivalent are stored as their debug representation instead.
+  #[getset(get = "pub")]
+  message: serde_json::Value,
+  /// Why the command was refused or failed, if it was.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[getset(get = "pub")]
+  error: Option<String>,
+}
+
+impl AuditLogEntry {
+  pub(super) fn new(
+    session_id: u32,
+    client_name: Option<String>,
+    msg: &ButtplugClientMessage,
+  ) -> Self {
+    let timestamp = SystemTime::now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis() as u64;
+    let message = ButtplugCurrentSpecClientMessage::try_from(msg.clone())
+      .ok()
+      .and_then(|msg| serde_json::to_value(msg).ok())
+      .unwrap_or_else(|| serde_json::Value::String(format!("{:?}", msg)));
+    Self {
+      timestamp,
+      session_id,
+      client_name,
+      message,
+      error: None,
+    }
+  }
+}
+
+/// Returns true if `msg` acts on devices, and should be recorded in the audit log.
+pub(super) fn is_audited(msg: &ButtplugClientMessage) -> bool {
+  ButtplugDeviceCommandMessageUnion::matches_client_message(msg)
+    || matches!(
+      msg,
+      ButtplugClientMessage::StopAllDevices(_)
+        | ButtplugClientMessage::LovenseCmd(_)
+        | ButtplugClientMessage::FunscriptLoadCmd(_)
+        | ButtplugClientMessage::FunscriptPlaybackCmd(_)
+        | ButtplugClientMessage::PatternLoadCmd(_)
+        | ButtplugClientMessage::PatternPlayCmd(_)
+        | ButtplugClientMessage::PatternStopCmd(_)
+        | ButtplugClientMessage::ScalarLoopCmd(_)
+        | ButtplugClientMessage::StrokeCmd(_)
+        | ButtplugClientMessage::DeviceLockCmd(_)
+        | ButtplugClientMessage::DeviceUnlockCmd(_)
+        | ButtplugClientMessage::DeviceDisplayNameCmd(_)
+        | ButtplugClientMessage::DeviceFirmwareUpdateCmd(_)
+        | ButtplugClientMessage::DeviceSelfTestCmd(_)
+        | ButtplugClientMessage::IntensityCeilingCmd(_)
+        | ButtplugClientMessage::ServerShutdownCmd(_)
+        | ButtplugClientMessage::ForceStopAllDevicesCmd(_)
+        | ButtplugClientMessage::KickClientCmd(_)
+        | ButtplugClientMessage::ScanningEnabledCmd(_)
+    )
+}
+
+/// Audit log file, shared by all sessions of a server.
+pub struct AuditLog {
+  path: PathBuf,
+  file: Mutex<File>,
+}
+
+impl AuditLog {
+  /// Opens the audit log at `path` for appending, creating it if it doesn't exist.
+  pub fn open(path: &Path) -> io::Result<Self> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Self {
+      path: path.to_owned(),
+      file: Mutex::new(file),
+    })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Appends an entry for a command, along with its outcome. Failing to write to the log doesn't
+  /// stop the command, as the log shouldn't be able to keep anyone from stopping a device.
+  pub(super) fn record(&self, mut entry: AuditLogEntry, result: Result<(), &message::Error>) {
+    entry.error = result.err().map(|err| err.error_message().clone());
+    let mut line = serde_json::to_string(&entry).expect("Entries are always serializable");
+    line.push('\n');
+    // Write each entry in one call, so entries from different sessions can't interleave.
+    let write_result = self
+      .file
+      .lock()
+      .expect("Lock is never poisoned")
+      .write_all(line.as_bytes());
+    if let Err(err) = write_result {
+      error!("Cannot write to audit log {}: {}", self.path.display(), err);
+    }
+  }
+}
+
+/// Reads all entries from an audit log, skipping any lines that can't be parsed.
+pub fn read_audit_log(path: &Path) -> io::Result<Vec<AuditLogEntry>> {
+  Ok(
+    std::fs::read_to_string(path)?
+      .lines()
+      .filter_map(|line| serde_json::from_str(line).ok())
+      .collect(),
+  )
+}
@@ -0,0 +1,110 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Delayed execution of device commands that carry a timestamp.
+//!
+//! Clients doing synchronized playback can send commands slightly ahead of when they should
+//! happen, with the time they should run at. The server holds them until then, which absorbs
+//! network jitter between the client and server. Timestamps are in milliseconds since the Unix
+//! epoch on the server clock. The server sends its current time in [ServerInfo] during the
+//! handshake, so clients can work out the offset from their own clock.
+//!
+//! [ServerInfo]: crate::core::message::ServerInfo
+
+use super::{device::ServerDeviceManager, ButtplugServerResultFuture};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugMessageError},
+    message::{ButtplugClientMessage, ButtplugDeviceMessage},
+  },
+  util::{sleep, unix_time_millis},
+};
+use dashmap::DashMap;
+use futures::FutureExt;
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Furthest in the future a command can be scheduled. This is meant for absorbing jitter, not
+/// building sequences, so anything further out is most likely a client clock problem.
+const MAX_SCHEDULE_DELAY: Duration = Duration::from_secs(10);
+
+/// Returns the device index and timestamp for device commands that have a timestamp set.
+pub(super) fn scheduled_timestamp(msg: &ButtplugClientMessage) -> Option<(u32, u64)> {
+  match msg {
+    ButtplugClientMessage::ScalarCmd(m) => m.timestamp().map(|t| (m.device_index(), t)),
+    ButtplugClientMessage::LinearCmd(m) => m.timestamp().map(|t| (m.device_index(), t)),
+    ButtplugClientMessage::RotateCmd(m) => m.timestamp().map(|t| (m.device_index(), t)),
+    _ => None,
+  }
+}
+
+/// Holds timestamped commands until their execution time. Pending commands for a device are
+/// cancelled if a stop command comes in for that device before they run.
+pub(super) struct CommandScheduler {
+  device_manager: Arc<ServerDeviceManager>,
+  cancellation_tokens: DashMap<u32, CancellationToken>,
+}
+
+impl CommandScheduler {
+  pub fn new(device_manager: Arc<ServerDeviceManager>) -> Self {
+    Self {
+      device_manager,
+      cancellation_tokens: DashMap::new(),
+    }
+  }
+
+  pub fn schedule(
+    &self,
+    device_index: u32,
+    timestamp: u64,
+    msg: ButtplugClientMessage,
+  ) -> ButtplugServerResultFuture {
+    let now = unix_time_millis();
+    if timestamp <= now {
+      return self.device_manager.parse_message(msg);
+    }
+    let delay = Duration::from_millis(timestamp - now);
+    if delay > MAX_SCHEDULE_DELAY {
+      return ButtplugMessageError::InvalidMessageContents(format!(
+        "Command timestamp is {}ms in the future, maximum schedule delay is {}ms",
+        delay.as_millis(),
+        MAX_SCHEDULE_DELAY.as_millis()
+      ))
+      .into();
+    }
+    let token = self
+      .cancellation_tokens
+      .entry(device_index)
+      .or_default()
+      .clone();
+    let device_manager = self.device_manager.clone();
+    async move {
+      select! {
+        _ = sleep(delay).fuse() => device_manager.parse_message(msg).await,
+        _ = token.cancelled().fuse() => {
+          Err(ButtplugDeviceError::ScheduledCommandCancelled(device_index).into())
+        }
+      }
+    }
+    .boxed()
+  }
+
+  /// Cancel all pending commands for a device.
+  pub fn cancel(&self, device_index: u32) {
+    if let Some((_, token)) = self.cancellation_tokens.remove(&device_index) {
+      token.cancel();
+    }
+  }
+
+  /// Cancel all pending commands for all devices.
+  pub fn cancel_all(&self) {
+    for token in self.cancellation_tokens.iter() {
+      token.cancel();
+    }
+    self.cancellation_tokens.clear();
+  }
+}
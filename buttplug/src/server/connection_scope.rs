@@ -0,0 +1,151 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Permission scopes, limiting what a connection to the server is allowed to do.
+
+use crate::core::{errors::ButtplugMessageError, message::ButtplugClientMessage};
+use std::fmt;
+
+/// What a connection is allowed to do. Each scope includes everything allowed by the scopes before
+/// it, so a connection with [ButtplugConnectionScope::Raw] can also control devices.
+///
+/// Stop commands are allowed in every scope, so any connection can stop devices for safety reasons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum ButtplugConnectionScope {
+  /// Handshake, ping and listing devices only.
+  DeviceList,
+  /// Scanning, device control, sensors, playback and device locks.
+  Control,
+  /// Raw reads/writes/subscriptions to device endpoints. Raw messages also need to be allowed on
+  /// the server itself, see [ButtplugServerBuilder::allow_raw_messages](super::ButtplugServerBuilder::allow_raw_messages).
+  ///
+  /// This is the default, so changing server settings always has to be allowed explicitly.
+  #[default]
+  Raw,
+  /// Changing server settings, i.e. device display names and the intensity ceiling, updating device
+  /// firmware, requesting server logs, and managing the server and other clients. Firmware updates
+  /// and server management also need to be allowed on the server itself, see
+  /// [ButtplugServerBuilder::allow_firmware_updates](super::ButtplugServerBuilder::allow_firmware_updates)
+  /// and [ButtplugServerBuilder::allow_server_management](super::ButtplugServerBuilder::allow_server_management).
+  Admin,
+}
+
+impl ButtplugConnectionScope {
+  /// Lowest scope needed to send `msg`.
+  pub fn required_for(msg: &ButtplugClientMessage) -> Self {
+    match msg {
+      ButtplugClientMessage::RequestServerInfo(_)
+      | ButtplugClientMessage::Ping(_)
+      | ButtplugClientMessage::EndSessionCmd(_)
+      | ButtplugClientMessage::RequestDeviceList(_)
+      | ButtplugClientMessage::StopAllDevices(_)
+      | ButtplugClientMessage::StopDeviceCmd(_) => Self::DeviceList,
+      ButtplugClientMessage::RawWriteCmd(_)
+      | ButtplugClientMessage::RawReadCmd(_)
+      | ButtplugClientMessage::RawSubscribeCmd(_)
+      | ButtplugClientMessage::RawUnsubscribeCmd(_) => Self::Raw,
+      ButtplugClientMessage::RequestLog(_)
+      | ButtplugClientMessage::DeviceDisplayNameCmd(_)
+      | ButtplugClientMessage::DeviceFirmwareUpdateCmd(_)
+      | ButtplugClientMessage::IntensityCeilingCmd(_)
+      | ButtplugClientMessage::ServerShutdownCmd(_)
+      | ButtplugClientMessage::ForceStopAllDevicesCmd(_)
+      | ButtplugClientMessage::KickClientCmd(_)
+      | ButtplugClientMessage::ScanningEnabledCmd(_) => Self::Admin,
+      // Listed out rather than matched with a wildcard, so new messages need a scope picked for
+      // them instead of falling into this one.
+      ButtplugClientMessage::StartScanning(_)
+      | ButtplugClientMessage::StopScanning(_)
+      | ButtplugClientMessage::VibrateCmd(_)
+      | ButtplugClientMessage::LinearCmd(_)
+      | ButtplugClientMessage::RotateCmd(_)
+      | ButtplugClientMessage::RotatePositionCmd(_)
+      | ButtplugClientMessage::ScalarCmd(_)
+      | ButtplugClientMessage::BatteryLevelCmd(_)
+      | ButtplugClientMessage::RSSILevelCmd(_)
+      | ButtplugClientMessage::SensorReadCmd(_)
+      | ButtplugClientMessage::SensorSubscribeCmd(_)
+      | ButtplugClientMessage::SensorUnsubscribeCmd(_)
+      | ButtplugClientMessage::FunscriptLoadCmd(_)
+      | ButtplugClientMessage::FunscriptPlaybackCmd(_)
+      | ButtplugClientMessage::PatternLoadCmd(_)
+      | ButtplugClientMessage::PatternPlayCmd(_)
+      | ButtplugClientMessage::PatternStopCmd(_)
+      | ButtplugClientMessage::ScalarLoopCmd(_)
+      | ButtplugClientMessage::StrokeCmd(_)
+      | ButtplugClientMessage::DeviceLockCmd(_)
+      | ButtplugClientMessage::DeviceUnlockCmd(_)
+      | ButtplugClientMessage::DeviceSelfTestCmd(_)
+      | ButtplugClientMessage::CancelCmd(_)
+      | ButtplugClientMessage::SingleMotorVibrateCmd(_)
+      | ButtplugClientMessage::FleshlightLaunchFW12Cmd(_)
+      | ButtplugClientMessage::LovenseCmd(_)
+      | ButtplugClientMessage::KiirooCmd(_)
+      | ButtplugClientMessage::VorzeA10CycloneCmd(_) => Self::Control,
+    }
+  }
+
+  /// True if this scope allows sending `msg`.
+  pub fn allows(&self, msg: &ButtplugClientMessage) -> bool {
+    *self >= Self::required_for(msg)
+  }
+
+  pub(super) fn check(&self, msg: &ButtplugClientMessage) -> Result<(), ButtplugMessageError> {
+    if self.allows(msg) {
+      return Ok(());
+    }
+    Err(ButtplugMessageError::InsufficientScope(
+      msg.name().to_owned(),
+      Self::required_for(msg).to_string(),
+    ))
+  }
+}
+
+impl fmt::Display for ButtplugConnectionScope {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Self::DeviceList => "device-list",
+      Self::Control => "control",
+      Self::Raw => "raw",
+      Self::Admin => "admin",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{
+    DeviceDisplayNameCmd,
+    Endpoint,
+    RawWriteCmd,
+    RequestDeviceList,
+    StartScanning,
+    StopAllDevices,
+  };
+
+  #[test]
+  fn test_connection_scope_allows() {
+    let list: ButtplugClientMessage = RequestDeviceList::default().into();
+    let stop: ButtplugClientMessage = StopAllDevices::default().into();
+    let scan: ButtplugClientMessage = StartScanning::default().into();
+    let raw: ButtplugClientMessage = RawWriteCmd::new(0, Endpoint::Tx, &[0], false).into();
+    let admin: ButtplugClientMessage = DeviceDisplayNameCmd::new(0, None).into();
+
+    let device_list = ButtplugConnectionScope::DeviceList;
+    assert!(device_list.allows(&list) && device_list.allows(&stop));
+    assert!(!device_list.allows(&scan));
+    let control = ButtplugConnectionScope::Control;
+    assert!(control.allows(&scan) && !control.allows(&raw) && !control.allows(&admin));
+    let raw_scope = ButtplugConnectionScope::Raw;
+    assert!(raw_scope.allows(&raw) && !raw_scope.allows(&admin));
+    assert!(!ButtplugConnectionScope::default().allows(&admin));
+    assert!(ButtplugConnectionScope::Admin.allows(&admin));
+    assert_eq!(admin.name(), "DeviceDisplayNameCmd");
+  }
+}
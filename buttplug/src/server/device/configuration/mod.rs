@@ -151,16 +151,16 @@ use super::protocol::{get_default_protocol_map, ProtocolIdentifierFactory, Proto
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{ButtplugDeviceMessageType, Endpoint},
+    message::{ActuatorType, ButtplugDeviceMessageType, Endpoint},
   },
   server::device::ServerDeviceIdentifier,
 };
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use derivative::Derivative;
-use getset::{Getters, MutGetters, Setters};
+use getset::{CopyGetters, Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   sync::{
     atomic::{AtomicU32, Ordering},
     Arc,
@@ -222,6 +222,35 @@ impl PartialEq<ServerDeviceIdentifier> for ProtocolAttributesIdentifier {
   }
 }
 
+/// Mechanical limits for linear devices, used to slow down moves the hardware can't physically
+/// make in the time requested.
+///
+/// Distances are fractions of the device's full range, so a max velocity of 2.0 means the device
+/// can cross its whole range in half a second.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct LinearMotionLimits {
+  /// Fastest the device can move, in full ranges per second.
+  #[serde(rename = "max-velocity")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  max_velocity: Option<f64>,
+  /// Fastest the device can speed up or slow down, in full ranges per second squared.
+  #[serde(rename = "max-acceleration")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  max_acceleration: Option<f64>,
+}
+
+impl LinearMotionLimits {
+  pub fn new(max_velocity: Option<f64>, max_acceleration: Option<f64>) -> Self {
+    Self {
+      max_velocity,
+      max_acceleration,
+    }
+  }
+}
+
 /// Device attribute storage and handling
 ///
 /// ProtocolDeviceAttributes represent information about a device in relation to its protocol. This
@@ -260,6 +289,17 @@ pub struct ProtocolDeviceAttributes {
   display_name: Option<String>,
   /// Message attributes for this device instance.
   pub(super) message_attributes: ServerDeviceMessageAttributes,
+  /// How often to read the battery level and send it to clients, in milliseconds. If unset, the
+  /// battery is only read when a client asks.
+  battery_polling_interval: Option<u32>,
+  /// Mechanical limits for linear movement. If unset, moves are passed to the device as requested.
+  linear_motion_limits: Option<LinearMotionLimits>,
+  /// Highest level heaters can be set to, 0.0-1.0 of their step range. If unset, heaters can use
+  /// their whole step range.
+  temperature_limit: Option<f64>,
+  /// Most commands per second the device can take without wedging. If unset, commands are only
+  /// paced by how fast the hardware handles them.
+  max_command_rate: Option<u32>,
 }
 
 impl ProtocolDeviceAttributes {
@@ -277,6 +317,24 @@ impl ProtocolDeviceAttributes {
       display_name,
       message_attributes,
       parent,
+      battery_polling_interval: None,
+      linear_motion_limits: None,
+      temperature_limit: None,
+      max_command_rate: None,
+    }
+  }
+
+  /// Create a copy of these attributes for a rebranded device, identified by `identifier`. The
+  /// rebrand keeps the original name unless it's given its own.
+  pub(crate) fn new_rebrand(
+    &self,
+    identifier: ProtocolAttributesType,
+    name: Option<String>,
+  ) -> Self {
+    Self {
+      identifier,
+      name: name.or_else(|| self.name.clone()),
+      ..self.clone()
     }
   }
 
@@ -292,6 +350,10 @@ impl ProtocolDeviceAttributes {
       name: Some(self.name().to_owned()),
       display_name: self.display_name(),
       message_attributes: self.message_attributes(),
+      battery_polling_interval: self.battery_polling_interval(),
+      linear_motion_limits: self.linear_motion_limits(),
+      temperature_limit: self.temperature_limit(),
+      max_command_rate: self.max_command_rate(),
     }
   }
 
@@ -330,6 +392,82 @@ impl ProtocolDeviceAttributes {
     }
   }
 
+  /// Set the user configured display name for this instance.
+  pub(crate) fn set_display_name(&mut self, display_name: Option<String>) {
+    self.display_name = display_name;
+  }
+
+  /// Return the battery polling interval for this instance, in milliseconds, assuming one is set.
+  pub fn battery_polling_interval(&self) -> Option<u32> {
+    if let Some(interval) = self.battery_polling_interval {
+      Some(interval)
+    } else if let Some(parent) = &self.parent {
+      parent.battery_polling_interval()
+    } else {
+      None
+    }
+  }
+
+  /// Set the battery polling interval for this instance, in milliseconds.
+  pub(crate) fn set_battery_polling_interval(&mut self, interval: Option<u32>) {
+    self.battery_polling_interval = interval;
+  }
+
+  /// Return the linear motion limits for this instance, assuming any are set.
+  pub fn linear_motion_limits(&self) -> Option<LinearMotionLimits> {
+    if let Some(limits) = self.linear_motion_limits {
+      Some(limits)
+    } else if let Some(parent) = &self.parent {
+      parent.linear_motion_limits()
+    } else {
+      None
+    }
+  }
+
+  /// Set the linear motion limits for this instance.
+  pub(crate) fn set_linear_motion_limits(&mut self, limits: Option<LinearMotionLimits>) {
+    self.linear_motion_limits = limits;
+  }
+
+  /// Return the heater limit for this instance, assuming one is set.
+  ///
+  /// Unlike other attributes, this doesn't stop at the first instance that sets it. Limits are safety
+  /// settings, so the lowest limit in the tree wins, and user configs can only tighten the limit
+  /// shipped with a device, never loosen it.
+  pub fn temperature_limit(&self) -> Option<f64> {
+    let parent_limit = self
+      .parent
+      .as_ref()
+      .and_then(|parent| parent.temperature_limit());
+    match (self.temperature_limit, parent_limit) {
+      (Some(limit), Some(parent_limit)) => Some(limit.min(parent_limit)),
+      (limit, parent_limit) => limit.or(parent_limit),
+    }
+  }
+
+  /// Set the heater limit for this instance, 0.0-1.0 of the heaters' step range.
+  pub(crate) fn set_temperature_limit(&mut self, limit: Option<f64>) {
+    self.temperature_limit = limit.map(|x| x.clamp(0.0, 1.0));
+  }
+
+  /// Return the maximum command rate for this instance, in commands per second, assuming one is
+  /// set.
+  pub fn max_command_rate(&self) -> Option<u32> {
+    if let Some(rate) = self.max_command_rate {
+      Some(rate)
+    } else if let Some(parent) = &self.parent {
+      parent.max_command_rate()
+    } else {
+      None
+    }
+  }
+
+  /// Set the maximum command rate for this instance, in commands per second. Zero is treated as
+  /// unset.
+  pub(crate) fn set_max_command_rate(&mut self, rate: Option<u32>) {
+    self.max_command_rate = rate.filter(|rate| *rate > 0);
+  }
+
   /// Check to make sure the message attributes of an instance are valid.
   fn is_valid(&self) -> Result<(), ButtplugDeviceError> {
     if let Some(attrs) = self.message_attributes.scalar_cmd() {
@@ -350,6 +488,19 @@ impl ProtocolDeviceAttributes {
     Ok(())
   }
 
+  /// True if this instance itself configures any Temperature actuators.
+  fn has_temperature_actuators(&self) -> bool {
+    self
+      .message_attributes
+      .scalar_cmd()
+      .as_ref()
+      .is_some_and(|attrs| {
+        attrs
+          .iter()
+          .any(|attr| *attr.actuator_type() == ActuatorType::Temperature)
+      })
+  }
+
   /// Check if a type of device message is supported by this instance.
   pub fn allows_message(&self, message_type: &ButtplugDeviceMessageType) -> bool {
     self.message_attributes.message_allowed(message_type)
@@ -369,6 +520,18 @@ impl ProtocolDeviceAttributes {
   pub fn add_raw_messages(&mut self, endpoints: &[Endpoint]) {
     self.message_attributes.add_raw_messages(endpoints);
   }
+
+  /// Add a battery sensor read through the standard BLE Battery Service, if no battery sensor is
+  /// configured. Expects flattened attributes. Returns the index of the added sensor.
+  pub fn add_ble_battery_sensor(&mut self) -> Option<u32> {
+    self.message_attributes.add_ble_battery_sensor()
+  }
+
+  /// Replace message attributes with ones the device announced itself. Messages the device didn't
+  /// announce are kept. Expects flattened attributes.
+  pub fn merge_announced_attributes(&mut self, announced: &ServerDeviceMessageAttributes) {
+    self.message_attributes = self.message_attributes.merge(announced);
+  }
 }
 
 #[derive(Default, Clone)]
@@ -387,7 +550,13 @@ pub struct DeviceConfigurationManagerBuilder {
   /// connect to a device, they're the string serialized version of the address, versus using a
   /// [ServerDeviceIdentifier].
   denied_addresses: Vec<String>,
+  /// Protocols that won't be used to connect to devices, even if a device matches them.
+  disabled_protocols: Vec<String>,
   reserved_indexes: Vec<(ServerDeviceIdentifier, u32)>,
+  /// User metadata (icon, color, notes, etc...) for devices.
+  device_metadata: Vec<(ServerDeviceIdentifier, HashMap<String, String>)>,
+  /// Devices allowed to use raw messages, even if raw messages aren't allowed for all devices.
+  raw_message_devices: Vec<ServerDeviceIdentifier>,
 }
 
 impl DeviceConfigurationManagerBuilder {
@@ -415,10 +584,19 @@ impl DeviceConfigurationManagerBuilder {
     self
       .denied_addresses
       .extend(other.denied_addresses.iter().map(|v| (v.clone())));
+    self
+      .disabled_protocols
+      .extend(other.disabled_protocols.iter().cloned());
     self
       .reserved_indexes
       .extend(other.reserved_indexes.iter().map(|v| (v.clone())));
     self
+      .device_metadata
+      .extend(other.device_metadata.iter().cloned());
+    self
+      .raw_message_devices
+      .extend(other.raw_message_devices.iter().cloned());
+    self
   }
 
   pub fn communication_specifier(
@@ -474,11 +652,35 @@ impl DeviceConfigurationManagerBuilder {
     self
   }
 
+  pub fn disabled_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self.disabled_protocols.push(protocol_name.to_owned());
+    self
+  }
+
   pub fn reserved_index(&mut self, identifier: &ServerDeviceIdentifier, index: u32) -> &mut Self {
     self.reserved_indexes.push((identifier.clone(), index));
     self
   }
 
+  pub fn device_metadata(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+    metadata: HashMap<String, String>,
+  ) -> &mut Self {
+    self.device_metadata.push((identifier.clone(), metadata));
+    self
+  }
+
+  /// Allow raw messages for a single device, instead of for all devices via
+  /// [DeviceConfigurationManagerBuilder::allow_raw_messages].
+  pub fn allow_raw_messages_for_device(
+    &mut self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> &mut Self {
+    self.raw_message_devices.push(identifier.clone());
+    self
+  }
+
   pub fn finish(&mut self) -> Result<DeviceConfigurationManager, ButtplugDeviceError> {
     // Map of protocol names to their respective protocol instance factories
     let mut protocol_map = if !self.skip_default_protocols {
@@ -583,8 +785,18 @@ impl DeviceConfigurationManagerBuilder {
     }
 
     // Make sure it's all valid.
-    for attrs in attribute_tree_map.values() {
+    for (ident, attrs) in &attribute_tree_map {
       attrs.is_valid()?;
+      if attrs.has_temperature_actuators()
+        && !protocol_map
+          .get(&ident.protocol)
+          .is_some_and(|protocol| protocol.supports_temperature_actuators())
+      {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Configuration {:?} has Temperature actuators, which the {} protocol can't drive.",
+          ident, ident.protocol
+        )));
+      }
     }
 
     Ok(DeviceConfigurationManager {
@@ -594,12 +806,56 @@ impl DeviceConfigurationManagerBuilder {
       protocol_map,
       allowed_addresses: self.allowed_addresses.clone(),
       denied_addresses: self.denied_addresses.clone(),
+      disabled_protocols: self.disabled_protocols.iter().cloned().collect(),
       reserved_indexes,
       current_index: AtomicU32::new(0),
+      display_names: DashMap::new(),
+      device_metadata: self.device_metadata.iter().cloned().collect(),
+      raw_message_devices: self.raw_message_devices.iter().cloned().collect(),
     })
   }
 }
 
+/// Configuration for a single device, with the base configuration and user configuration merged.
+///
+/// Returned by [DeviceConfigurationManager::device_configuration], so frontends can build device
+/// settings UIs without having to know how configuration layers are resolved.
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct EffectiveDeviceConfiguration {
+  /// Device this configuration applies to.
+  #[getset(get = "pub")]
+  identifier: ServerDeviceIdentifier,
+  /// Attributes the device will be created with, user configuration included.
+  #[getset(get = "pub")]
+  attributes: ProtocolDeviceAttributes,
+  /// Attributes from the base configuration only, i.e. what the device goes back to if its user
+  /// configuration is removed.
+  #[getset(get = "pub")]
+  base_attributes: ProtocolDeviceAttributes,
+  /// True if there is a user configuration for this device.
+  #[getset(get_copy = "pub")]
+  user_configured: bool,
+  /// True if the device address is on the allow list.
+  #[getset(get_copy = "pub")]
+  allowed: bool,
+  /// True if the device address is on the deny list.
+  #[getset(get_copy = "pub")]
+  denied: bool,
+  /// True if the protocol of the device exists and hasn't been disabled.
+  #[getset(get_copy = "pub")]
+  protocol_enabled: bool,
+  /// Device index reserved for the device, either by user configuration or by a previous
+  /// connection.
+  #[getset(get_copy = "pub")]
+  reserved_index: Option<u32>,
+  /// User metadata (icon, color, notes, etc...) for the device.
+  #[getset(get = "pub")]
+  metadata: Option<HashMap<String, String>>,
+  /// True if raw messages are allowed for the device, either for all devices or just this one.
+  #[getset(get_copy = "pub")]
+  raw_messages_allowed: bool,
+}
+
 /// Correlates information about protocols and which devices they support.
 ///
 /// The [DeviceConfigurationManager] handles stores information about which device protocols the
@@ -620,8 +876,17 @@ pub struct DeviceConfigurationManager {
   protocol_map: HashMap<String, Arc<dyn ProtocolIdentifierFactory>>,
   allowed_addresses: Vec<String>,
   denied_addresses: Vec<String>,
+  /// Protocols that won't be used to connect to devices. Can be changed at runtime.
+  disabled_protocols: DashSet<String>,
   reserved_indexes: DashMap<ServerDeviceIdentifier, u32>,
   current_index: AtomicU32,
+  /// Display names set at runtime, overriding the ones from configuration files. None means the
+  /// display name was cleared.
+  display_names: DashMap<ServerDeviceIdentifier, Option<String>>,
+  /// User metadata (icon, color, notes, etc...) for devices.
+  device_metadata: DashMap<ServerDeviceIdentifier, HashMap<String, String>>,
+  /// Devices allowed to use raw messages when raw messages aren't allowed for all devices.
+  raw_message_devices: HashSet<ServerDeviceIdentifier>,
 }
 
 impl Default for DeviceConfigurationManager {
@@ -658,6 +923,65 @@ impl DeviceConfigurationManager {
     }
   }
 
+  /// Returns true if the protocol exists and hasn't been disabled.
+  pub fn protocol_enabled(&self, protocol_name: &str) -> bool {
+    self.protocol_map.contains_key(protocol_name)
+      && !self.disabled_protocols.contains(protocol_name)
+  }
+
+  /// Enables or disables a protocol. Disabled protocols are skipped when looking for protocols that
+  /// match a device. Returns false if the protocol doesn't exist.
+  pub fn set_protocol_enabled(&self, protocol_name: &str, enabled: bool) -> bool {
+    if !self.protocol_map.contains_key(protocol_name) {
+      return false;
+    }
+    if enabled {
+      self.disabled_protocols.remove(protocol_name);
+    } else {
+      self.disabled_protocols.insert(protocol_name.to_owned());
+    }
+    true
+  }
+
+  /// Set or clear the display name of a device, overriding the display name from the user
+  /// configuration. Applies to devices created after this call.
+  pub fn set_display_name(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    display_name: Option<String>,
+  ) {
+    self.display_names.insert(identifier.clone(), display_name);
+  }
+
+  /// Returns the user metadata (icon, color, notes, etc...) stored for a device, if any.
+  pub fn device_metadata(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<HashMap<String, String>> {
+    self
+      .device_metadata
+      .get(identifier)
+      .map(|metadata| metadata.clone())
+  }
+
+  /// Set or clear the user metadata for a device.
+  pub fn set_device_metadata(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    metadata: Option<HashMap<String, String>>,
+  ) {
+    if let Some(metadata) = metadata {
+      self.device_metadata.insert(identifier.clone(), metadata);
+    } else {
+      self.device_metadata.remove(identifier);
+    }
+  }
+
+  /// True if raw messages are allowed for all devices, or for this device specifically.
+  pub fn raw_messages_allowed(&self, identifier: &ServerDeviceIdentifier) -> bool {
+    self.allow_raw_messages || self.raw_message_devices.contains(identifier)
+  }
+
   pub fn device_index(&self, identifier: &ServerDeviceIdentifier) -> u32 {
     // See if we have a reserved or reusable device index here.
     if let Some(id) = self.reserved_indexes.get(identifier) {
@@ -699,6 +1023,11 @@ impl DeviceConfigurationManager {
       if specifiers.contains(specifier) {
         info!("Found protocol {:?} for specifier {:?}.", name, specifier);
 
+        if self.disabled_protocols.contains(name) {
+          info!("Protocol {:?} is disabled, skipping.", name);
+          continue;
+        }
+
         if !self.protocol_map.contains_key(name) {
           warn!(
             "No protocol implementation for {:?} found for specifier {:?}.",
@@ -719,15 +1048,42 @@ impl DeviceConfigurationManager {
     specializers
   }
 
-  pub fn protocol_device_attributes(
+  /// Identifier captured from a device name by one of its protocol's BLE name patterns, for devices
+  /// with serial numbers or other varying text in their names.
+  fn captured_attributes_identifier(
     &self,
     identifier: &ServerDeviceIdentifier,
-    raw_endpoints: &[Endpoint],
-  ) -> Option<ProtocolDeviceAttributes> {
-    let mut flat_attrs = if let Some(attrs) = self.protocol_attributes.get(&identifier.into()) {
-      debug!("User device config found for {:?}", identifier);
-      attrs.flatten()
-    } else if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
+  ) -> Option<ProtocolAttributesIdentifier> {
+    let name = if let ProtocolAttributesType::Identifier(name) = identifier.attributes_identifier()
+    {
+      name
+    } else {
+      return None;
+    };
+    self
+      .communication_specifiers
+      .get(identifier.protocol())?
+      .iter()
+      .find_map(|specifier| {
+        if let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier {
+          btle.captured_identifier(name)
+        } else {
+          None
+        }
+      })
+      .map(|captured| ProtocolAttributesIdentifier {
+        address: None,
+        attributes_identifier: ProtocolAttributesType::Identifier(captured),
+        protocol: identifier.protocol().clone(),
+      })
+  }
+
+  /// Attributes from the base configuration for a device, ignoring user configuration.
+  fn base_device_attributes(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<&Arc<ProtocolDeviceAttributes>> {
+    if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
       address: None,
       attributes_identifier: identifier.attributes_identifier().clone(),
       protocol: identifier.protocol().clone(),
@@ -736,24 +1092,95 @@ impl DeviceConfigurationManager {
         "Protocol + Identifier device config found for {:?}",
         identifier
       );
-      attrs.flatten()
+      Some(attrs)
+    } else if let Some(attrs) = self
+      .captured_attributes_identifier(identifier)
+      .and_then(|x| self.protocol_attributes.get(&x))
+    {
+      debug!(
+        "Protocol + Name pattern device config found for {:?}",
+        identifier
+      );
+      Some(attrs)
     } else if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
       address: None,
       attributes_identifier: ProtocolAttributesType::Default,
       protocol: identifier.protocol().clone(),
     }) {
       debug!("Protocol device config found for {:?}", identifier);
+      Some(attrs)
+    } else {
+      None
+    }
+  }
+
+  /// True if there's configuration for this specific device or model, as opposed to only the
+  /// defaults for its protocol.
+  pub fn has_model_attributes(&self, identifier: &ServerDeviceIdentifier) -> bool {
+    self.protocol_attributes.contains_key(&identifier.into())
+      || (*identifier.attributes_identifier() != ProtocolAttributesType::Default
+        && self
+          .protocol_attributes
+          .contains_key(&ProtocolAttributesIdentifier {
+            address: None,
+            attributes_identifier: identifier.attributes_identifier().clone(),
+            protocol: identifier.protocol().clone(),
+          }))
+      || self
+        .captured_attributes_identifier(identifier)
+        .is_some_and(|x| self.protocol_attributes.contains_key(&x))
+  }
+
+  pub fn protocol_device_attributes(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    raw_endpoints: &[Endpoint],
+  ) -> Option<ProtocolDeviceAttributes> {
+    let mut flat_attrs = if let Some(attrs) = self.protocol_attributes.get(&identifier.into()) {
+      debug!("User device config found for {:?}", identifier);
       attrs.flatten()
     } else {
-      return None;
+      self.base_device_attributes(identifier)?.flatten()
     };
 
-    if self.allow_raw_messages {
+    if let Some(display_name) = self.display_names.get(identifier) {
+      flat_attrs.set_display_name(display_name.clone());
+    }
+
+    if self.raw_messages_allowed(identifier) {
       flat_attrs.add_raw_messages(raw_endpoints);
     }
 
     Some(flat_attrs)
   }
+
+  /// Returns the configuration a device will use, with the base configuration and user
+  /// configuration merged. Returns None if the protocol has no configuration for the device.
+  pub fn device_configuration(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<EffectiveDeviceConfiguration> {
+    let base_attributes = self.base_device_attributes(identifier)?.flatten();
+    let user_attributes = self.protocol_attributes.get(&identifier.into());
+    let mut attributes = user_attributes
+      .map(|attrs| attrs.flatten())
+      .unwrap_or_else(|| base_attributes.clone());
+    if let Some(display_name) = self.display_names.get(identifier) {
+      attributes.set_display_name(display_name.clone());
+    }
+    Some(EffectiveDeviceConfiguration {
+      identifier: identifier.clone(),
+      attributes,
+      base_attributes,
+      user_configured: user_attributes.is_some(),
+      allowed: self.allowed_addresses.contains(identifier.address()),
+      denied: self.denied_addresses.contains(identifier.address()),
+      protocol_enabled: self.protocol_enabled(identifier.protocol()),
+      reserved_index: self.reserved_indexes.get(identifier).map(|index| *index),
+      metadata: self.device_metadata(identifier),
+      raw_messages_allowed: self.raw_messages_allowed(identifier),
+    })
+  }
 }
 
 #[cfg(test)]
@@ -771,6 +1198,12 @@ mod test {
   };
 
   fn create_unit_test_dcm(allow_raw_messages: bool) -> DeviceConfigurationManager {
+    create_unit_test_dcm_builder(allow_raw_messages)
+      .finish()
+      .unwrap()
+  }
+
+  fn create_unit_test_dcm_builder(allow_raw_messages: bool) -> DeviceConfigurationManagerBuilder {
     let mut builder = DeviceConfigurationManagerBuilder::default();
     if allow_raw_messages {
       builder.allow_raw_messages();
@@ -809,7 +1242,7 @@ mod test {
         None,
       ),
     );
-    builder.finish().unwrap()
+    builder
   }
 
   #[test]
@@ -834,6 +1267,46 @@ mod test {
     assert!(!config.protocol_specializers(&spec).is_empty());
   }
 
+  #[test]
+  fn test_config_name_pattern_equals() {
+    let mut config =
+      BluetoothLESpecifier::new(HashSet::new(), vec![], HashSet::new(), HashMap::new());
+    config.set_name_patterns(vec![BluetoothLENamePattern::new(
+      "PT-(?<identifier>[A-Z]+)-[0-9]+",
+    )
+    .expect("Test, valid regex.")]);
+    let device = |name| BluetoothLESpecifier::new_from_device(name, &HashMap::new(), &[]);
+    assert_eq!(config, device("PT-AB-1234"));
+    assert_ne!(config, device("PT-AB"));
+    assert_ne!(config, device("XPT-AB-1234"));
+    assert_eq!(
+      config.captured_identifier("PT-AB-1234"),
+      Some("AB".to_owned())
+    );
+  }
+
+  #[test]
+  fn test_config_combined_match_equals() {
+    let config: BluetoothLESpecifier = serde_json::from_str(
+      r#"{
+        "combined-matches": [
+          {
+            "names": ["Combo*"],
+            "manufacturer-data": [{ "company": 4660 }]
+          }
+        ],
+        "services": {}
+      }"#,
+    )
+    .expect("Test, valid specifier.");
+    let device = |name, company| {
+      BluetoothLESpecifier::new_from_device(name, &HashMap::from([(company, vec![1, 2, 3])]), &[])
+    };
+    assert_eq!(config, device("Combo X", 4660));
+    assert_ne!(config, device("Combo X", 1));
+    assert_ne!(config, device("Other", 4660));
+  }
+
   #[test]
   fn test_specific_device_config_creation() {
     let dcm = create_unit_test_dcm(false);
@@ -925,6 +1398,81 @@ mod test {
     assert!(config.message_attributes().raw_unsubscribe_cmd().is_none());
   }
 
+  #[test]
+  fn test_effective_device_configuration() {
+    let mut builder = create_unit_test_dcm_builder(false);
+    let identifier = ServerDeviceIdentifier::new(
+      "user-addr",
+      "lovense",
+      &ProtocolAttributesType::Identifier("P".to_owned()),
+    );
+    builder.protocol_attributes(
+      (&identifier).into(),
+      ProtocolDeviceAttributes::new(
+        ProtocolAttributesType::Identifier("P".to_owned()),
+        None,
+        Some("My Edge".to_owned()),
+        ServerDeviceMessageAttributes::default(),
+        None,
+      ),
+    );
+    builder.denied_address("user-addr");
+    builder.reserved_index(&identifier, 5);
+    let dcm = builder.finish().unwrap();
+    let config = dcm
+      .device_configuration(&identifier)
+      .expect("Should be found");
+    assert!(config.user_configured());
+    assert!(config.denied());
+    assert!(!config.allowed());
+    assert!(config.protocol_enabled());
+    assert_eq!(config.reserved_index(), Some(5));
+    assert_eq!(
+      config.attributes().display_name(),
+      Some("My Edge".to_owned())
+    );
+    assert_eq!(config.attributes().name(), "Lovense Edge");
+    assert_eq!(config.base_attributes().display_name(), None);
+
+    let other_config = dcm
+      .device_configuration(&ServerDeviceIdentifier::new(
+        "other-addr",
+        "lovense",
+        &ProtocolAttributesType::Identifier("P".to_owned()),
+      ))
+      .expect("Should be found");
+    assert!(!other_config.user_configured());
+    assert!(!other_config.denied());
+    assert_eq!(other_config.reserved_index(), None);
+  }
+
+  #[test]
+  fn test_temperature_limit_resolution() {
+    let mut parent = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      Some("Heater".to_owned()),
+      None,
+      ServerDeviceMessageAttributes::default(),
+      None,
+    );
+    parent.set_temperature_limit(Some(0.6));
+    let mut child = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Identifier("P".to_owned()),
+      None,
+      None,
+      ServerDeviceMessageAttributes::default(),
+      Some(Arc::new(parent)),
+    );
+    assert_eq!(child.temperature_limit(), Some(0.6));
+    // Overrides can make the limit stricter, but never looser than the parent allows.
+    child.set_temperature_limit(Some(0.4));
+    assert_eq!(child.temperature_limit(), Some(0.4));
+    child.set_temperature_limit(Some(0.9));
+    assert_eq!(child.temperature_limit(), Some(0.6));
+    child.set_temperature_limit(Some(2.0));
+    assert_eq!(child.temperature_limit(), Some(0.6));
+  }
+
   /*
       #[test]
       fn test_user_config_loading() {
@@ -14,6 +14,7 @@ use crate::core::{
     Endpoint,
     NullDeviceMessageAttributes,
     RawDeviceMessageAttributes,
+    RotationMode,
     SensorDeviceMessageAttributes,
     SensorType,
   },
@@ -186,6 +187,25 @@ impl ServerDeviceMessageAttributes {
     self.raw_write_cmd = Some(raw_attrs.clone());
     self.raw_subscribe_cmd = Some(raw_attrs);
   }
+
+  /// Add a battery sensor backed by the standard BLE Battery Service, for devices that expose it
+  /// but have no battery sensor configured. Returns the index of the added sensor, or None if the
+  /// device already has a battery sensor.
+  pub fn add_ble_battery_sensor(&mut self) -> Option<u32> {
+    let sensors = self.sensor_read_cmd.get_or_insert_with(Vec::new);
+    if sensors
+      .iter()
+      .any(|x| *x.sensor_type() == SensorType::Battery)
+    {
+      return None;
+    }
+    sensors.push(SensorDeviceMessageAttributes::new(
+      "Battery Level",
+      SensorType::Battery,
+      &[RangeInclusive::new(0, 100)],
+    ));
+    Some(sensors.len() as u32 - 1)
+  }
 }
 
 impl From<ServerDeviceMessageAttributes> for ClientDeviceMessageAttributes {
@@ -225,6 +245,37 @@ impl From<ServerDeviceMessageAttributes> for ClientDeviceMessageAttributes {
   }
 }
 
+/// Rebuilds server attributes from attributes a client was sent, i.e. for devices bridged from
+/// another server. Raw endpoints aren't carried over, as they're added per server.
+impl From<ClientDeviceMessageAttributes> for ServerDeviceMessageAttributes {
+  fn from(attrs: ClientDeviceMessageAttributes) -> Self {
+    let convert = |attrs: &Vec<ClientGenericDeviceMessageAttributes>| {
+      attrs
+        .iter()
+        .cloned()
+        .map(|x| x.into())
+        .collect::<Vec<ServerGenericDeviceMessageAttributes>>()
+    };
+    let mut builder = ServerDeviceMessageAttributesBuilder::default();
+    if let Some(scalar_cmd) = attrs.scalar_cmd() {
+      builder.scalar_cmd(&convert(scalar_cmd));
+    }
+    if let Some(rotate_cmd) = attrs.rotate_cmd() {
+      builder.rotate_cmd(&convert(rotate_cmd));
+    }
+    if let Some(linear_cmd) = attrs.linear_cmd() {
+      builder.linear_cmd(&convert(linear_cmd));
+    }
+    if let Some(sensor_read_cmd) = attrs.sensor_read_cmd() {
+      builder.sensor_read_cmd(sensor_read_cmd);
+    }
+    if let Some(sensor_subscribe_cmd) = attrs.sensor_subscribe_cmd() {
+      builder.sensor_subscribe_cmd(sensor_subscribe_cmd);
+    }
+    builder.finish()
+  }
+}
+
 #[derive(Default)]
 pub struct ServerDeviceMessageAttributesBuilder {
   attrs: ServerDeviceMessageAttributes,
@@ -293,15 +344,33 @@ pub struct ServerGenericDeviceMessageAttributes {
   #[serde(skip_serializing)]
   #[getset(get = "pub", set = "pub")]
   step_range: RangeInclusive<u32>,
+  #[serde(rename = "RotationMode")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[getset(get = "pub", set = "pub")]
+  rotation_mode: Option<RotationMode>,
 }
 
 impl From<ServerGenericDeviceMessageAttributes> for ClientGenericDeviceMessageAttributes {
   fn from(attrs: ServerGenericDeviceMessageAttributes) -> Self {
-    ClientGenericDeviceMessageAttributes::new(
+    let mut client_attrs = ClientGenericDeviceMessageAttributes::new(
       &attrs.feature_descriptor,
       attrs.step_count(),
       attrs.actuator_type,
-    )
+    );
+    client_attrs.set_rotation_mode(attrs.rotation_mode);
+    client_attrs
+  }
+}
+
+impl From<ClientGenericDeviceMessageAttributes> for ServerGenericDeviceMessageAttributes {
+  fn from(attrs: ClientGenericDeviceMessageAttributes) -> Self {
+    let mut server_attrs = ServerGenericDeviceMessageAttributes::new(
+      attrs.feature_descriptor(),
+      &(0..=*attrs.step_count()),
+      *attrs.actuator_type(),
+    );
+    server_attrs.set_rotation_mode(*attrs.rotation_mode());
+    server_attrs
   }
 }
 
@@ -315,9 +384,15 @@ impl ServerGenericDeviceMessageAttributes {
       feature_descriptor: feature_descriptor.to_owned(),
       actuator_type,
       step_range: step_range.clone(),
+      rotation_mode: None,
     }
   }
 
+  /// True if this is a rotation feature driven to absolute positions, rather than by speed.
+  pub fn is_positional_rotator(&self) -> bool {
+    self.rotation_mode == Some(RotationMode::Positional)
+  }
+
   pub fn step_count(&self) -> u32 {
     self.step_range.end() - self.step_range.start()
   }
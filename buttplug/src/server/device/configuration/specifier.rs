@@ -5,10 +5,14 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use crate::core::message::Endpoint;
-use getset::{Getters, MutGetters, Setters};
+use crate::core::message::{DeviceTransportType, Endpoint};
+use getset::{CopyGetters, Getters, MutGetters, Setters};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+};
 use uuid::Uuid;
 
 // Note: There's a ton of extra structs in here just to deserialize the json
@@ -83,6 +87,138 @@ impl PartialEq for BluetoothLEManufacturerData {
   }
 }
 
+/// Regular expression matched against the whole advertised name of a BLE device.
+///
+/// Patterns are for devices that put serial numbers or other varying text in their names, which
+/// can't be listed as names or matched by a trailing wildcard. If the pattern has a capture group
+/// named `identifier` (or failing that, any capture group), the captured text is used in place of
+/// the advertised name when looking up device configurations.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct BluetoothLENamePattern {
+  pattern: String,
+  regex: Regex,
+}
+
+impl BluetoothLENamePattern {
+  pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+    Ok(Self {
+      pattern: pattern.to_owned(),
+      regex: Regex::new(&format!("^(?:{})$", pattern))?,
+    })
+  }
+
+  pub fn pattern(&self) -> &str {
+    &self.pattern
+  }
+
+  pub fn is_match(&self, name: &str) -> bool {
+    self.regex.is_match(name)
+  }
+
+  /// Returns the configuration identifier captured from the name, or None if the name doesn't match
+  /// or the pattern has no capture groups.
+  pub fn captured_identifier(&self, name: &str) -> Option<String> {
+    let captures = self.regex.captures(name)?;
+    captures
+      .name("identifier")
+      .or_else(|| captures.iter().skip(1).flatten().next())
+      .map(|capture| capture.as_str().to_owned())
+  }
+}
+
+impl TryFrom<String> for BluetoothLENamePattern {
+  type Error = regex::Error;
+
+  fn try_from(pattern: String) -> Result<Self, Self::Error> {
+    Self::new(&pattern)
+  }
+}
+
+impl From<BluetoothLENamePattern> for String {
+  fn from(pattern: BluetoothLENamePattern) -> Self {
+    pattern.pattern
+  }
+}
+
+impl fmt::Debug for BluetoothLENamePattern {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("BluetoothLENamePattern")
+      .field(&self.pattern)
+      .finish()
+  }
+}
+
+impl PartialEq for BluetoothLENamePattern {
+  fn eq(&self, other: &Self) -> bool {
+    self.pattern == other.pattern
+  }
+}
+
+impl Eq for BluetoothLENamePattern {
+}
+
+/// Advertisement criteria that must all match for a device to be considered part of a protocol.
+///
+/// Some brands share name prefixes with unrelated devices, so a name alone would connect the wrong
+/// protocol. Combined matches only match devices whose advertisement matches every criterion that's
+/// set, for instance a name pattern along with the manufacturer data of the brand.
+#[derive(
+  Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Getters, MutGetters, Setters,
+)]
+#[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
+pub struct BluetoothLECombinedMatch {
+  /// Advertised names, which may end with a wildcard.
+  #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+  names: HashSet<String>,
+  /// Patterns for advertised names.
+  #[serde(default, rename = "name-patterns")]
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  name_patterns: Vec<BluetoothLENamePattern>,
+  /// Manufacturer data, one of which must be advertised.
+  #[serde(default, rename = "manufacturer-data")]
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  manufacturer_data: Vec<BluetoothLEManufacturerData>,
+  /// Services, one of which must be advertised.
+  #[serde(default, rename = "advertised-services")]
+  #[serde(skip_serializing_if = "HashSet::is_empty")]
+  advertised_services: HashSet<Uuid>,
+}
+
+impl BluetoothLECombinedMatch {
+  fn has_names(&self) -> bool {
+    !self.names.is_empty() || !self.name_patterns.is_empty()
+  }
+
+  fn matches_name(&self, name: &str) -> bool {
+    self.names.iter().any(|expected| {
+      expected == name
+        || expected
+          .strip_suffix('*')
+          .is_some_and(|prefix| name.starts_with(prefix))
+    }) || self.name_patterns.iter().any(|x| x.is_match(name))
+  }
+
+  /// True if the advertisement a device specifier was created from matches every criterion. Matches
+  /// with no criteria never match anything.
+  pub fn matches(&self, device: &BluetoothLESpecifier) -> bool {
+    if !self.has_names() && self.manufacturer_data.is_empty() && self.advertised_services.is_empty()
+    {
+      return false;
+    }
+    (!self.has_names() || device.names.iter().any(|name| self.matches_name(name)))
+      && (self.manufacturer_data.is_empty()
+        || self
+          .manufacturer_data
+          .iter()
+          .any(|data| device.manufacturer_data.contains(data)))
+      && (self.advertised_services.is_empty()
+        || !self
+          .advertised_services
+          .is_disjoint(&device.advertised_services))
+  }
+}
+
 /// Specifier for Bluetooth LE Devices
 ///
 /// Used by protocols for identifying bluetooth devices via their advertisements, as well as
@@ -91,7 +227,16 @@ impl PartialEq for BluetoothLEManufacturerData {
 #[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
 pub struct BluetoothLESpecifier {
   /// Set of expected advertised names for this device.
+  #[serde(default)]
   names: HashSet<String>,
+  /// Patterns for advertised names that can't be listed in names.
+  #[serde(default, rename = "name-patterns")]
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  name_patterns: Vec<BluetoothLENamePattern>,
+  /// Matches that require several parts of the advertisement to match at once.
+  #[serde(default, rename = "combined-matches")]
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  combined_matches: Vec<BluetoothLECombinedMatch>,
   /// Array of possible manufacturer data values.
   #[serde(default, rename = "manufacturer-data")]
   manufacturer_data: Vec<BluetoothLEManufacturerData>,
@@ -132,6 +277,26 @@ impl PartialEq for BluetoothLESpecifier {
       }
     }
 
+    // Then name patterns, which only ever exist in configurations.
+    if self
+      .names
+      .iter()
+      .any(|name| other.name_patterns.iter().any(|x| x.is_match(name)))
+      || other
+        .names
+        .iter()
+        .any(|name| self.name_patterns.iter().any(|x| x.is_match(name)))
+    {
+      return true;
+    }
+
+    // Combined matches also only exist in configurations.
+    if self.combined_matches.iter().any(|x| x.matches(other))
+      || other.combined_matches.iter().any(|x| x.matches(self))
+    {
+      return true;
+    }
+
     if !self.manufacturer_data.is_empty() && !other.manufacturer_data.is_empty() {
       for data in &self.manufacturer_data {
         if other.manufacturer_data.contains(data) {
@@ -162,6 +327,8 @@ impl BluetoothLESpecifier {
   ) -> Self {
     Self {
       names,
+      name_patterns: vec![],
+      combined_matches: vec![],
       manufacturer_data,
       advertised_services,
       services,
@@ -186,23 +353,44 @@ impl BluetoothLESpecifier {
     let service_set = HashSet::from_iter(advertised_services.iter().copied());
     BluetoothLESpecifier {
       names: name_set,
+      name_patterns: vec![],
+      combined_matches: vec![],
       manufacturer_data: data_vec,
       advertised_services: service_set,
       services: HashMap::new(),
     }
   }
 
+  /// Configuration identifier captured from the device name by a name pattern, if any pattern with
+  /// capture groups matches it.
+  pub fn captured_identifier(&self, name: &str) -> Option<String> {
+    self
+      .name_patterns
+      .iter()
+      .find_map(|x| x.captured_identifier(name))
+  }
+
   /// Merge with another BLE specifier, used when loading user configs that extend a protocol
   /// definition.
   pub fn merge(&mut self, other: BluetoothLESpecifier) {
     // Add any new names.
     self.names = self.names.union(&other.names).cloned().collect();
+    for pattern in other.name_patterns {
+      if !self.name_patterns.contains(&pattern) {
+        self.name_patterns.push(pattern);
+      }
+    }
     // Add new services, overwrite matching services.
     self.advertised_services = self
       .advertised_services
       .union(&other.advertised_services)
       .cloned()
       .collect();
+    for combined_match in other.combined_matches {
+      if !self.combined_matches.contains(&combined_match) {
+        self.combined_matches.push(combined_match);
+      }
+    }
     self.services.extend(other.services);
   }
 }
@@ -232,6 +420,30 @@ impl PartialEq for LovenseConnectServiceSpecifier {
   }
 }
 
+/// Specifier for [Buttplug
+/// Federation](crate::server::device::communication_manager::buttplug_federation) devices
+///
+/// Network based services, has no attributes because devices are bridged from another Buttplug
+/// server, which has already identified them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ButtplugFederationSpecifier {
+  // Needed for deserialziation but unused.
+  #[allow(dead_code)]
+  exists: bool,
+}
+
+impl Default for ButtplugFederationSpecifier {
+  fn default() -> Self {
+    Self { exists: true }
+  }
+}
+
+impl PartialEq for ButtplugFederationSpecifier {
+  fn eq(&self, _other: &Self) -> bool {
+    true
+  }
+}
+
 /// Specifier for [XInput](crate::server::device::communication_manager::xinput) devices
 ///
 /// Network based services, has no attributes because the
@@ -360,6 +572,97 @@ impl WebsocketSpecifier {
   }
 }
 
+/// Specifier for GPIO PWM devices
+///
+/// Maps endpoints to channels of a Linux sysfs PWM chip (i.e. the PWM pins of a Raspberry Pi).
+/// GPIO builds are all different, so these are expected to come from the user device config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
+pub struct GpioSpecifier {
+  /// Name of the PWM chip in /sys/class/pwm, i.e. "pwmchip0".
+  chip: String,
+  /// PWM period, in nanoseconds.
+  #[serde(rename = "period-ns", default = "default_gpio_period_ns")]
+  period_ns: u32,
+  /// PWM channel of the chip for each endpoint.
+  #[serde(default)]
+  channels: HashMap<Endpoint, u32>,
+}
+
+// 10kHz, above the range where most motors whine audibly.
+fn default_gpio_period_ns() -> u32 {
+  100000
+}
+
+impl GpioSpecifier {
+  /// Given a PWM chip name (the only identifier we have for this type of device), create a
+  /// specifier instance.
+  pub fn new_from_chip(chip: &str) -> Self {
+    GpioSpecifier {
+      chip: chip.to_owned(),
+      period_ns: default_gpio_period_ns(),
+      ..Default::default()
+    }
+  }
+}
+
+impl PartialEq for GpioSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.chip == other.chip
+  }
+}
+
+/// MIDI message an endpoint of a [MidiSpecifier] is sent as. Set either `cc` or `note`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct MidiOutputMapping {
+  /// MIDI channel, from 1 to 16.
+  channel: u8,
+  /// Controller number, for values sent as control change messages.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  cc: Option<u8>,
+  /// Note number, for values sent as note velocity. A value of 0 sends note off.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  note: Option<u8>,
+}
+
+impl MidiOutputMapping {
+  pub fn new(channel: u8, cc: Option<u8>, note: Option<u8>) -> Self {
+    Self { channel, cc, note }
+  }
+}
+
+/// Specifier for MIDI output ports
+///
+/// Maps endpoints to MIDI messages on an output port. Like GPIO, what's plugged into a port is up
+/// to the user, so these are expected to come from the user device config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
+pub struct MidiSpecifier {
+  /// Name of the port, i.e. "midiC1D0" for ALSA raw MIDI ports.
+  port: String,
+  /// MIDI message each endpoint is sent as.
+  #[serde(default)]
+  outputs: HashMap<Endpoint, MidiOutputMapping>,
+}
+
+impl MidiSpecifier {
+  /// Given a port name (the only identifier we have for this type of device), create a specifier
+  /// instance.
+  pub fn new_from_port(port: &str) -> Self {
+    MidiSpecifier {
+      port: port.to_owned(),
+      ..Default::default()
+    }
+  }
+}
+
+impl PartialEq for MidiSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.port == other.port
+  }
+}
+
 /// Enum that covers all types of communication specifiers.
 ///
 /// Allows generalization of specifiers to handle checking for equality. Used for testing newly discovered
@@ -373,6 +676,28 @@ pub enum ProtocolCommunicationSpecifier {
   XInput(XInputSpecifier),
   LovenseConnectService(LovenseConnectServiceSpecifier),
   Websocket(WebsocketSpecifier),
+  ButtplugFederation(ButtplugFederationSpecifier),
+  Gpio(GpioSpecifier),
+  Midi(MidiSpecifier),
+}
+
+impl ProtocolCommunicationSpecifier {
+  /// Type of hardware connection devices matching this specifier will use.
+  pub fn transport_type(&self) -> DeviceTransportType {
+    use ProtocolCommunicationSpecifier::*;
+    match self {
+      BluetoothLE(_) => DeviceTransportType::BluetoothLE,
+      HID(_) => DeviceTransportType::HID,
+      USB(_) => DeviceTransportType::USB,
+      Serial(_) => DeviceTransportType::Serial,
+      XInput(_) => DeviceTransportType::XInput,
+      Gpio(_) => DeviceTransportType::GPIO,
+      Midi(_) => DeviceTransportType::MIDI,
+      LovenseConnectService(_) | Websocket(_) | ButtplugFederation(_) => {
+        DeviceTransportType::Network
+      }
+    }
+  }
 }
 
 impl PartialEq for ProtocolCommunicationSpecifier {
@@ -388,6 +713,9 @@ impl PartialEq for ProtocolCommunicationSpecifier {
       (LovenseConnectService(self_spec), LovenseConnectService(other_spec)) => {
         self_spec == other_spec
       }
+      (ButtplugFederation(self_spec), ButtplugFederation(other_spec)) => self_spec == other_spec,
+      (Gpio(self_spec), Gpio(other_spec)) => self_spec == other_spec,
+      (Midi(self_spec), Midi(other_spec)) => self_spec == other_spec,
       _ => false,
     }
   }
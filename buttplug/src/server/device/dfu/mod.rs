@@ -0,0 +1,110 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Device firmware updates (DFU), through bootloaders devices expose over their regular connection.
+//!
+//! Bootloaders are found through the device's endpoints, so a device can only be updated if its
+//! device configuration maps the bootloader characteristics, i.e. the
+//! [DfuControl](crate::core::message::Endpoint::DfuControl) and
+//! [DfuPacket](crate::core::message::Endpoint::DfuPacket) endpoints for Nordic Secure DFU. Updates
+//! are requested through the
+//! [DeviceFirmwareUpdateCmd](crate::core::message::DeviceFirmwareUpdateCmd) message, which servers
+//! only accept when built with
+//! [ButtplugServerBuilder::allow_firmware_updates](crate::server::ButtplugServerBuilder::allow_firmware_updates).
+//!
+//! Other bootloaders can be supported by implementing [DfuBootloader] and adding it to
+//! [bootloaders].
+
+mod nordic_secure_dfu;
+
+pub use nordic_secure_dfu::NordicSecureDfu;
+
+use super::hardware::Hardware;
+use crate::core::errors::ButtplugDeviceError;
+use async_trait::async_trait;
+use getset::Getters;
+use std::sync::Arc;
+
+/// Firmware to write to a device.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub")]
+pub struct DfuFirmwareImage {
+  /// Metadata the bootloader validates before accepting the firmware, i.e. the signed .dat file
+  /// from a Nordic DFU package. Empty for bootloaders that don't use one.
+  init_packet: Vec<u8>,
+  /// The firmware itself, i.e. the .bin file from a Nordic DFU package.
+  firmware: Vec<u8>,
+}
+
+impl DfuFirmwareImage {
+  pub fn new(init_packet: &[u8], firmware: &[u8]) -> Self {
+    Self {
+      init_packet: init_packet.to_vec(),
+      firmware: firmware.to_vec(),
+    }
+  }
+}
+
+/// A bootloader protocol that firmware updates can be run through.
+#[async_trait]
+pub trait DfuBootloader: Send + Sync {
+  fn name(&self) -> &'static str;
+
+  /// True if the hardware exposes the endpoints this bootloader needs.
+  fn supports(&self, hardware: &Hardware) -> bool;
+
+  /// Writes the image to the device. Devices usually restart into the new firmware once this
+  /// succeeds, dropping the connection.
+  async fn update(
+    &self,
+    hardware: Arc<Hardware>,
+    image: &DfuFirmwareImage,
+  ) -> Result<(), ButtplugDeviceError>;
+}
+
+/// Bootloaders that firmware updates can be run through, in order of preference.
+pub fn bootloaders() -> Vec<Box<dyn DfuBootloader>> {
+  vec![Box::new(NordicSecureDfu::default())]
+}
+
+/// The first bootloader from [bootloaders] the hardware supports, if any.
+pub fn bootloader_for(hardware: &Hardware) -> Option<Box<dyn DfuBootloader>> {
+  bootloaders()
+    .into_iter()
+    .find(|bootloader| bootloader.supports(hardware))
+}
+
+/// Continues the CRC-32 (IEEE 802.3) `crc` of previous data over `data`. Start with 0.
+pub(super) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+  let mut crc = !crc;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 {
+        (crc >> 1) ^ 0xEDB8_8320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+#[cfg(test)]
+mod test {
+  use super::crc32_update;
+
+  #[test]
+  fn test_crc32() {
+    assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+    // Continuing over split data matches the CRC of the whole.
+    assert_eq!(
+      crc32_update(crc32_update(0, b"1234"), b"56789"),
+      0xCBF4_3926
+    );
+  }
+}
@@ -0,0 +1,234 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Nordic Secure DFU, the bootloader used by most nRF5x based toys.
+//!
+//! Firmware is sent as a command object (the init packet) followed by data objects (the firmware,
+//! split in objects of the size the bootloader asks for). Each object is created through the
+//! control point, written to the packet characteristic, checksummed and then executed.
+
+use super::{crc32_update, DfuBootloader, DfuFirmwareImage};
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::hardware::{Hardware, HardwareEvent, HardwareSubscribeCmd, HardwareWriteCmd},
+  util::sleep,
+};
+use async_trait::async_trait;
+use futures::{pin_mut, FutureExt};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const OP_CREATE: u8 = 0x01;
+const OP_SET_PRN: u8 = 0x02;
+const OP_CALCULATE_CHECKSUM: u8 = 0x03;
+const OP_EXECUTE: u8 = 0x04;
+const OP_SELECT: u8 = 0x06;
+const OP_RESPONSE: u8 = 0x60;
+
+const OBJECT_COMMAND: u8 = 0x01;
+const OBJECT_DATA: u8 = 0x02;
+
+const RESULT_SUCCESS: u8 = 0x01;
+
+/// How long to wait for the bootloader to answer a control point request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest write to the packet characteristic, the payload size of the default BLE MTU.
+const PACKET_SIZE: usize = 20;
+
+fn result_description(result: u8) -> &'static str {
+  match result {
+    0x02 => "opcode not supported",
+    0x03 => "invalid parameter",
+    0x04 => "insufficient resources",
+    0x05 => "invalid object",
+    0x07 => "unsupported object type",
+    0x08 => "operation not permitted",
+    0x0A => "operation failed",
+    0x0B => "extended error",
+    _ => "unknown error",
+  }
+}
+
+fn dfu_error(msg: String) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceFirmwareUpdateError(msg)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, ButtplugDeviceError> {
+  data
+    .get(offset..offset + 4)
+    .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    .ok_or_else(|| dfu_error(format!("Bootloader response too short: {:?}", data)))
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NordicSecureDfu {}
+
+/// A running update, with the notification stream the control point answers on.
+struct NordicDfuTransfer {
+  hardware: Arc<Hardware>,
+  events: broadcast::Receiver<HardwareEvent>,
+}
+
+impl NordicDfuTransfer {
+  /// Writes a control point request and waits for the bootloader's response to it, returning the
+  /// response payload.
+  async fn request(&mut self, request: Vec<u8>) -> Result<Vec<u8>, ButtplugDeviceError> {
+    let opcode = request[0];
+    self
+      .hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::DfuControl, request, true))
+      .await?;
+    let timeout = sleep(RESPONSE_TIMEOUT).fuse();
+    pin_mut!(timeout);
+    loop {
+      let event = select! {
+        event = self.events.recv().fuse() => event,
+        _ = timeout => {
+          return Err(dfu_error(format!("Bootloader did not answer opcode {:#04x}", opcode)));
+        }
+      };
+      match event {
+        Ok(HardwareEvent::Notification(_, Endpoint::DfuControl, data)) => {
+          if data.len() < 3 || data[0] != OP_RESPONSE || data[1] != opcode {
+            warn!(
+              "Ignoring unexpected DFU control point notification {:?}",
+              data
+            );
+            continue;
+          }
+          if data[2] != RESULT_SUCCESS {
+            return Err(dfu_error(format!(
+              "Bootloader refused opcode {:#04x}: {}",
+              opcode,
+              result_description(data[2])
+            )));
+          }
+          return Ok(data[3..].to_vec());
+        }
+        Ok(HardwareEvent::Disconnected(_)) | Err(RecvError::Closed) => {
+          return Err(ButtplugDeviceError::DeviceNotConnected(
+            "Device disconnected during firmware update".to_owned(),
+          ));
+        }
+        Ok(HardwareEvent::Notification(..)) | Err(RecvError::Lagged(_)) => continue,
+      }
+    }
+  }
+
+  /// Selects an object type, returning the largest object size the bootloader accepts for it.
+  async fn select(&mut self, object_type: u8) -> Result<usize, ButtplugDeviceError> {
+    let response = self.request(vec![OP_SELECT, object_type]).await?;
+    Ok(read_u32(&response, 0)? as usize)
+  }
+
+  /// Creates, writes, verifies and executes one object. `offset` and `crc` cover everything sent
+  /// before this object of the same type, and the updated CRC is returned.
+  async fn transfer_object(
+    &mut self,
+    object_type: u8,
+    data: &[u8],
+    offset: usize,
+    crc: u32,
+  ) -> Result<u32, ButtplugDeviceError> {
+    let mut create = vec![OP_CREATE, object_type];
+    create.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    self.request(create).await?;
+    for packet in data.chunks(PACKET_SIZE) {
+      self
+        .hardware
+        .write_value(&HardwareWriteCmd::new(
+          Endpoint::DfuPacket,
+          packet.to_vec(),
+          false,
+        ))
+        .await?;
+    }
+    let expected_offset = offset + data.len();
+    let expected_crc = crc32_update(crc, data);
+    let checksum = self.request(vec![OP_CALCULATE_CHECKSUM]).await?;
+    let (device_offset, device_crc) = (read_u32(&checksum, 0)?, read_u32(&checksum, 4)?);
+    if device_offset as usize != expected_offset || device_crc != expected_crc {
+      return Err(dfu_error(format!(
+        "Bootloader received {} bytes with CRC {:#010x}, expected {} bytes with CRC {:#010x}",
+        device_offset, device_crc, expected_offset, expected_crc
+      )));
+    }
+    self.request(vec![OP_EXECUTE]).await?;
+    Ok(expected_crc)
+  }
+}
+
+#[async_trait]
+impl DfuBootloader for NordicSecureDfu {
+  fn name(&self) -> &'static str {
+    "Nordic Secure DFU"
+  }
+
+  fn supports(&self, hardware: &Hardware) -> bool {
+    let endpoints = hardware.endpoints();
+    endpoints.contains(&Endpoint::DfuControl) && endpoints.contains(&Endpoint::DfuPacket)
+  }
+
+  async fn update(
+    &self,
+    hardware: Arc<Hardware>,
+    image: &DfuFirmwareImage,
+  ) -> Result<(), ButtplugDeviceError> {
+    if image.init_packet().is_empty() {
+      return Err(dfu_error(
+        "Nordic Secure DFU requires an init packet".to_owned(),
+      ));
+    }
+    // Get the event stream before subscribing, so no responses can be missed.
+    let events = hardware.event_stream();
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::DfuControl))
+      .await?;
+    let mut transfer = NordicDfuTransfer {
+      hardware: hardware.clone(),
+      events,
+    };
+
+    // Turn off packet receipt notifications, every object is checksummed instead.
+    transfer.request(vec![OP_SET_PRN, 0, 0]).await?;
+
+    let max_command_size = transfer.select(OBJECT_COMMAND).await?;
+    if image.init_packet().len() > max_command_size {
+      return Err(dfu_error(format!(
+        "Init packet is {} bytes, bootloader accepts at most {}",
+        image.init_packet().len(),
+        max_command_size
+      )));
+    }
+    transfer
+      .transfer_object(OBJECT_COMMAND, image.init_packet(), 0, 0)
+      .await?;
+
+    let max_data_size = transfer.select(OBJECT_DATA).await?;
+    if max_data_size == 0 {
+      return Err(dfu_error(
+        "Bootloader reported a maximum data object size of 0".to_owned(),
+      ));
+    }
+    let total = image.firmware().len();
+    let mut offset = 0;
+    let mut crc = 0;
+    for object in image.firmware().chunks(max_data_size) {
+      crc = transfer
+        .transfer_object(OBJECT_DATA, object, offset, crc)
+        .await?;
+      offset += object.len();
+      info!(
+        "Firmware update for {}: {}/{} bytes written",
+        hardware.name(),
+        offset,
+        total
+      );
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,65 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Android setup for the btleplug communication manager.
+//!
+//! btleplug's Android backend goes through JNI into its Java library (which needs to be packaged
+//! with the app), and has to be initialized from a thread the JVM knows about before the server is
+//! built. Bluetooth also requires runtime permissions on Android (BLUETOOTH_SCAN/BLUETOOTH_CONNECT
+//! on API 31+, location on older versions), which can only be requested by the app. The app should
+//! report the result of the request via [set_bluetooth_permission_state], so that scanning can fail
+//! with a useful error instead of silently finding nothing.
+
+use crate::util::async_manager::android::set_java_vm;
+use jni::JNIEnv;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Bluetooth runtime permission status, as reported by the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AndroidBluetoothPermissionState {
+  /// App hasn't reported permission status. Scanning will be attempted, and marked as denied if
+  /// the system refuses it.
+  Unknown = 0,
+  Granted = 1,
+  Denied = 2,
+}
+
+impl From<u8> for AndroidBluetoothPermissionState {
+  fn from(value: u8) -> Self {
+    match value {
+      1 => Self::Granted,
+      2 => Self::Denied,
+      _ => Self::Unknown,
+    }
+  }
+}
+
+static PERMISSION_STATE: AtomicU8 = AtomicU8::new(AndroidBluetoothPermissionState::Unknown as u8);
+
+/// Set the Bluetooth permission status. Should be called by the app whenever the permission request
+/// result comes back, or the user changes permissions while the app is running.
+pub fn set_bluetooth_permission_state(state: AndroidBluetoothPermissionState) {
+  info!("Android Bluetooth permission state set to {:?}", state);
+  PERMISSION_STATE.store(state as u8, Ordering::SeqCst);
+}
+
+/// Returns the current Bluetooth permission status.
+pub fn bluetooth_permission_state() -> AndroidBluetoothPermissionState {
+  PERMISSION_STATE.load(Ordering::SeqCst).into()
+}
+
+/// Initialize btleplug's JNI backend, and store the JVM for runtime thread attachment.
+///
+/// Must be called from a Java thread (usually `JNI_OnLoad`, or a native method called from the
+/// app's activity/service), as btleplug needs the app's class loader to find its Java classes.
+/// Runtime threads that will run the server need to be attached to the JVM, see
+/// [crate::util::async_manager::android::runtime_builder].
+pub fn init(env: &JNIEnv) -> Result<(), btleplug::Error> {
+  set_java_vm(env)?;
+  btleplug::platform::init(env)
+}
@@ -5,7 +5,7 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::btleplug_hardware::BtleplugHardwareConnector;
+use super::btleplug_hardware::{BtleplugHardwareConnector, GattEndpointCache};
 use crate::server::device::hardware::communication::HardwareCommunicationManagerEvent;
 use btleplug::{
   api::{Central, CentralEvent, Manager as _, Peripheral, ScanFilter},
@@ -14,11 +14,12 @@ use btleplug::{
 use futures::{future::FutureExt, StreamExt};
 use std::{
   collections::HashMap,
+  hash::Hash,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
-  time::Duration,
+  time::{Duration, Instant},
 };
 use tokio::{
   sync::mpsc::{Receiver, Sender},
@@ -29,6 +30,8 @@ use tokio::{
 pub enum BtleplugAdapterCommand {
   StartScanning,
   StopScanning,
+  #[cfg(target_os = "ios")]
+  AppStateChanged(super::ios::IosAppState),
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -39,11 +42,74 @@ struct PeripheralInfo {
   services: Vec<uuid::Uuid>,
 }
 
+/// Advertisements recently passed on to the device manager. While scanning, devices advertise
+/// constantly, and every advertisement we pass on runs through config matching. That's a waste for
+/// devices no protocol supports, as they'll never connect. Entries expire so devices get
+/// reconsidered every so often, and so the cache doesn't grow with every device that's ever been in
+/// range.
+struct AdvertisementCache<K, V> {
+  ttl: Duration,
+  /// Peripherals can alternate between advertisement contents (i.e. with and without scan response
+  /// data), so every variant seen is kept, with when it was first seen.
+  entries: HashMap<K, Vec<(V, Instant)>>,
+  last_purge: Instant,
+}
+
+impl<K, V> AdvertisementCache<K, V>
+where
+  K: Eq + Hash,
+  V: PartialEq,
+{
+  fn new(ttl: Duration) -> Self {
+    Self {
+      ttl,
+      entries: HashMap::new(),
+      last_purge: Instant::now(),
+    }
+  }
+
+  /// Returns true if an advertisement hasn't been seen within the TTL, and records it as seen.
+  fn insert(&mut self, key: K, advertisement: V) -> bool {
+    let now = Instant::now();
+    if now.duration_since(self.last_purge) >= self.ttl {
+      self.purge_expired(now);
+    }
+    let ttl = self.ttl;
+    let variants = self.entries.entry(key).or_default();
+    variants.retain(|(_, first_seen)| now.duration_since(*first_seen) < ttl);
+    if variants.iter().any(|(seen, _)| *seen == advertisement) {
+      return false;
+    }
+    variants.push((advertisement, now));
+    true
+  }
+
+  fn remove(&mut self, key: &K) {
+    self.entries.remove(key);
+  }
+
+  fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  fn purge_expired(&mut self, now: Instant) {
+    let ttl = self.ttl;
+    self.entries.retain(|_, variants| {
+      variants.retain(|(_, first_seen)| now.duration_since(*first_seen) < ttl);
+      !variants.is_empty()
+    });
+    self.last_purge = now;
+  }
+}
+
 pub struct BtleplugAdapterTask {
   event_sender: Sender<HardwareCommunicationManagerEvent>,
   command_receiver: Receiver<BtleplugAdapterCommand>,
   adapter_connected: Arc<AtomicBool>,
   requires_keepalive: bool,
+  advertisement_cache_ttl: Duration,
+  /// Endpoints mapped on devices, if endpoint caching is on.
+  endpoint_cache: Option<GattEndpointCache>,
 }
 
 impl BtleplugAdapterTask {
@@ -52,12 +118,16 @@ impl BtleplugAdapterTask {
     command_receiver: Receiver<BtleplugAdapterCommand>,
     adapter_connected: Arc<AtomicBool>,
     requires_keepalive: bool,
+    advertisement_cache_ttl: Duration,
+    cache_gatt_endpoints: bool,
   ) -> Self {
     Self {
       event_sender,
       command_receiver,
       adapter_connected,
       requires_keepalive,
+      advertisement_cache_ttl,
+      endpoint_cache: cache_gatt_endpoints.then(GattEndpointCache::default),
     }
   }
 
@@ -65,7 +135,7 @@ impl BtleplugAdapterTask {
     &self,
     peripheral_id: &PeripheralId,
     adapter: &Adapter,
-    tried_addresses: &mut Vec<PeripheralInfo>,
+    seen_advertisements: &mut AdvertisementCache<PeripheralId, PeripheralInfo>,
   ) {
     let peripheral = if let Ok(peripheral) = adapter.peripheral(peripheral_id).await {
       peripheral
@@ -98,7 +168,7 @@ impl BtleplugAdapterTask {
     };
 
     if (!device_name.is_empty() || !properties.services.is_empty())
-      && !tried_addresses.contains(&peripheral_info)
+      && seen_advertisements.insert(peripheral_id.clone(), peripheral_info.clone())
     {
       let span = info_span!(
         "btleplug enumeration",
@@ -111,7 +181,6 @@ impl BtleplugAdapterTask {
         "Found new bluetooth device advertisement: {:?}",
         peripheral_info
       );
-      tried_addresses.push(peripheral_info.clone());
       let device_creator = Box::new(BtleplugHardwareConnector::new(
         &device_name,
         &properties.manufacturer_data,
@@ -119,6 +188,7 @@ impl BtleplugAdapterTask {
         peripheral.clone(),
         adapter.clone(),
         self.requires_keepalive,
+        self.endpoint_cache.clone(),
       ));
       if self
         .event_sender
@@ -134,7 +204,7 @@ impl BtleplugAdapterTask {
       }
     } else {
       trace!(
-        "Device {} found, no advertised name, ignoring.",
+        "Device {} found, no advertised name or recently seen, ignoring.",
         properties.address
       );
     }
@@ -224,7 +294,10 @@ impl BtleplugAdapterTask {
       .await
       .expect("Should always be able to retreive stream.");
 
-    let mut tried_addresses = vec![];
+    let mut seen_advertisements = AdvertisementCache::new(self.advertisement_cache_ttl);
+
+    #[cfg(target_os = "ios")]
+    let mut ios_scan_state = super::ios::IosScanState::new();
 
     loop {
       let event_fut = events.next();
@@ -234,11 +307,11 @@ impl BtleplugAdapterTask {
             if let Some(event) = event {
               match event {
                 CentralEvent::DeviceDiscovered(peripheral_id) | CentralEvent::DeviceUpdated(peripheral_id) => {
-                  self.maybe_add_peripheral(&peripheral_id, &adapter, &mut tried_addresses).await;
+                  self.maybe_add_peripheral(&peripheral_id, &adapter, &mut seen_advertisements).await;
                 }
                 CentralEvent::DeviceDisconnected(peripheral_id) => {
                   debug!("BTLEPlug Device disconnected: {:?}", peripheral_id);
-                  tried_addresses.retain(|info| info.peripheral_id != peripheral_id);
+                  seen_advertisements.remove(&peripheral_id);
                 }
                 event => {
                   trace!("Unhandled btleplug central event: {:?}", event)
@@ -253,16 +326,39 @@ impl BtleplugAdapterTask {
           if let Some(cmd) = command {
             match cmd {
               BtleplugAdapterCommand::StartScanning => {
-                tried_addresses.clear();
-                if let Err(err) = adapter.start_scan(ScanFilter::default()).await {
+                seen_advertisements.clear();
+                #[cfg(not(target_os = "ios"))]
+                let scan_filter = ScanFilter::default();
+                #[cfg(target_os = "ios")]
+                let scan_filter: ScanFilter = {
+                  ios_scan_state.set_scanning(true);
+                  ios_scan_state.scan_filter()
+                };
+                if let Err(err) = adapter.start_scan(scan_filter).await {
                   error!("Start scanning request failed: {}", err);
+                  // If the app didn't tell us about permissions, this is where we find out.
+                  #[cfg(all(target_os = "android", feature = "tokio-runtime"))]
+                  if matches!(err, btleplug::Error::PermissionDenied) {
+                    super::android::set_bluetooth_permission_state(
+                      super::android::AndroidBluetoothPermissionState::Denied,
+                    );
+                  }
                 }
               }
               BtleplugAdapterCommand::StopScanning => {
+                #[cfg(target_os = "ios")]
+                ios_scan_state.set_scanning(false);
                 if let Err(err) = adapter.stop_scan().await {
                   error!("Stop scanning request failed: {}", err);
                 }
               }
+              #[cfg(target_os = "ios")]
+              BtleplugAdapterCommand::AppStateChanged(state) => {
+                if ios_scan_state.app_state_changed(&adapter, state).await {
+                  // Anything we saw but didn't connect to should be reported again.
+                  seen_advertisements.clear();
+                }
+              }
             }
           } else {
             debug!("Command stream closed. Exiting btleplug adapter loop.");
@@ -273,3 +369,27 @@ impl BtleplugAdapterTask {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_advertisement_cache() {
+    let mut cache = AdvertisementCache::new(Duration::from_secs(60));
+    assert!(cache.insert(1, "Massage Demo"));
+    assert!(!cache.insert(1, "Massage Demo"));
+    // Another variant of the same peripheral's advertisement is new, but seen variants stay cached.
+    assert!(cache.insert(1, "Massage Demo (scan response)"));
+    assert!(!cache.insert(1, "Massage Demo"));
+    assert!(cache.insert(2, "Massage Demo"));
+    cache.remove(&1);
+    assert!(cache.insert(1, "Massage Demo"));
+
+    let mut cache = AdvertisementCache::new(Duration::ZERO);
+    assert!(cache.insert(1, "Massage Demo"));
+    assert!(cache.insert(1, "Massage Demo"));
+    cache.purge_expired(Instant::now());
+    assert!(cache.entries.is_empty());
+  }
+}
@@ -16,15 +16,24 @@ use crate::{
   util::async_manager,
 };
 use futures::future::FutureExt;
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
 };
 use tokio::sync::mpsc::{channel, Sender};
 
+/// How long an advertisement is remembered by default before it's passed on to the device manager
+/// again.
+const DEFAULT_ADVERTISEMENT_CACHE_TTL: Duration = Duration::from_secs(30);
+
 #[derive(Default, Clone)]
 pub struct BtlePlugCommunicationManagerBuilder {
   require_keepalive: bool,
+  advertisement_cache_ttl: Option<Duration>,
+  cache_gatt_endpoints: bool,
 }
 
 impl BtlePlugCommunicationManagerBuilder {
@@ -32,9 +41,32 @@ impl BtlePlugCommunicationManagerBuilder {
     self.require_keepalive = require;
     self
   }
+
+  /// Set how long repeated advertisements from a device are ignored after it's first passed on to
+  /// the device manager, unless the advertisement contents change. Longer times mean less work
+  /// while scanning continuously, but devices that couldn't connect (or didn't match any protocol)
+  /// take longer to be retried. Defaults to 30 seconds.
+  pub fn advertisement_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+    self.advertisement_cache_ttl = Some(ttl);
+    self
+  }
+
+  /// Remember the endpoints mapped on each device, along with its Device Information, and reuse
+  /// them when connecting to it again instead of mapping and reading them over. GATT services are
+  /// still discovered on every connection, as btleplug needs them to use a device's characteristics
+  /// on some platforms. If a connection using cached endpoints fails, the next attempt maps them
+  /// again. Off by default.
+  pub fn cache_gatt_endpoints(&mut self, cache: bool) -> &mut Self {
+    self.cache_gatt_endpoints = cache;
+    self
+  }
 }
 
 impl HardwareCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "BtlePlugCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
@@ -42,6 +74,10 @@ impl HardwareCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder
     Box::new(BtlePlugCommunicationManager::new(
       sender,
       self.require_keepalive,
+      self
+        .advertisement_cache_ttl
+        .unwrap_or(DEFAULT_ADVERTISEMENT_CACHE_TTL),
+      self.cache_gatt_endpoints,
     ))
   }
 }
@@ -56,16 +92,38 @@ impl BtlePlugCommunicationManager {
   pub fn new(
     event_sender: Sender<HardwareCommunicationManagerEvent>,
     require_keepalive: bool,
+    advertisement_cache_ttl: Duration,
+    cache_gatt_endpoints: bool,
   ) -> Self {
     let (sender, receiver) = channel(256);
     let adapter_connected = Arc::new(AtomicBool::new(false));
     let adapter_connected_clone = adapter_connected.clone();
+    #[cfg(target_os = "ios")]
+    {
+      // Forward app lifecycle changes into the adapter task, until the task goes away.
+      let mut app_state_receiver = super::ios::subscribe_app_state();
+      let command_sender = sender.clone();
+      async_manager::spawn(async move {
+        while app_state_receiver.changed().await.is_ok() {
+          let state = *app_state_receiver.borrow_and_update();
+          if command_sender
+            .send(BtleplugAdapterCommand::AppStateChanged(state))
+            .await
+            .is_err()
+          {
+            break;
+          }
+        }
+      });
+    }
     async_manager::spawn(async move {
       let mut task = BtleplugAdapterTask::new(
         event_sender,
         receiver,
         adapter_connected_clone,
         require_keepalive,
+        advertisement_cache_ttl,
+        cache_gatt_endpoints,
       );
       task.run().await;
     });
@@ -83,6 +141,18 @@ impl HardwareCommunicationManager for BtlePlugCommunicationManager {
   }
 
   fn start_scanning(&mut self) -> ButtplugResultFuture {
+    #[cfg(all(target_os = "android", feature = "tokio-runtime"))]
+    if super::android::bluetooth_permission_state()
+      == super::android::AndroidBluetoothPermissionState::Denied
+    {
+      return futures::future::ready(Err(
+        ButtplugDeviceError::DevicePermissionError(
+          "Bluetooth permissions have not been granted to the app.".to_owned(),
+        )
+        .into(),
+      ))
+      .boxed();
+    }
     let adapter_event_sender = self.adapter_event_sender.clone();
     let scanning_status = self.scanning_status.clone();
     // Set to true just to make sure we don't call ScanningFinished too early.
@@ -137,6 +207,12 @@ impl HardwareCommunicationManager for BtlePlugCommunicationManager {
   }
 
   fn can_scan(&self) -> bool {
+    #[cfg(all(target_os = "android", feature = "tokio-runtime"))]
+    if super::android::bluetooth_permission_state()
+      == super::android::AndroidBluetoothPermissionState::Denied
+    {
+      return false;
+    }
     self.adapter_connected.load(Ordering::SeqCst)
   }
 }
@@ -11,6 +11,8 @@ use crate::{
   server::device::{
     configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
     hardware::{
+      parse_device_information_string,
+      DeviceInformation,
       Hardware,
       HardwareConnector,
       HardwareEvent,
@@ -29,16 +31,16 @@ use async_trait::async_trait;
 use btleplug::api::CharPropFlags;
 use btleplug::{
   api::{Central, CentralEvent, Characteristic, Peripheral, ValueNotification, WriteType},
-  platform::Adapter,
+  platform::{Adapter, PeripheralId},
 };
-use dashmap::DashSet;
+use dashmap::{DashMap, DashSet};
 use futures::{
   future::{self, BoxFuture, FutureExt},
   Stream,
   StreamExt,
 };
 use std::{
-  collections::HashMap,
+  collections::{hash_map::Entry, HashMap},
   fmt::{self, Debug},
   pin::Pin,
   sync::Arc,
@@ -46,6 +48,62 @@ use std::{
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
+// Standard GATT services and characteristics, mapped to endpoints for any device that has them
+// and whose protocol doesn't map them itself: the Battery Service (0x180F) and the Device
+// Information Service (0x180A).
+const BLE_BATTERY_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+const BLE_DEVICE_INFORMATION_SERVICE_UUID: Uuid =
+  Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb);
+const BLE_STANDARD_CHARACTERISTICS: [(Uuid, Uuid, Endpoint); 6] = [
+  (
+    BLE_BATTERY_SERVICE_UUID,
+    Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb),
+    Endpoint::RxBLEBattery,
+  ),
+  (
+    BLE_DEVICE_INFORMATION_SERVICE_UUID,
+    Uuid::from_u128(0x00002a29_0000_1000_8000_00805f9b34fb),
+    Endpoint::RxBLEManufacturerName,
+  ),
+  (
+    BLE_DEVICE_INFORMATION_SERVICE_UUID,
+    Uuid::from_u128(0x00002a24_0000_1000_8000_00805f9b34fb),
+    Endpoint::RxBLEModel,
+  ),
+  (
+    BLE_DEVICE_INFORMATION_SERVICE_UUID,
+    Uuid::from_u128(0x00002a25_0000_1000_8000_00805f9b34fb),
+    Endpoint::RxBLESerialNumber,
+  ),
+  (
+    BLE_DEVICE_INFORMATION_SERVICE_UUID,
+    Uuid::from_u128(0x00002a26_0000_1000_8000_00805f9b34fb),
+    Endpoint::RxBLEFirmwareRevision,
+  ),
+  (
+    BLE_DEVICE_INFORMATION_SERVICE_UUID,
+    Uuid::from_u128(0x00002a27_0000_1000_8000_00805f9b34fb),
+    Endpoint::RxBLEHardwareRevision,
+  ),
+];
+
+/// Endpoints mapped on a device during an earlier connection, along with what was read from its
+/// Device Information Service.
+#[derive(Clone, Debug)]
+pub(super) struct CachedEndpoints {
+  endpoints: HashMap<Endpoint, Characteristic>,
+  uuid_map: HashMap<Uuid, Endpoint>,
+  device_information: DeviceInformation,
+}
+
+/// Endpoint mappings for each device, shared by the connectors of a comm manager.
+///
+/// This only caches our side of the connection. Services are still discovered on every connection,
+/// as some btleplug backends can't use characteristics without it (WinRT clears its services when a
+/// device disconnects, and writes then fail). What the cache saves us is mapping the services to
+/// endpoints and reading Device Information again when reconnecting to a device we've already seen.
+pub(super) type GattEndpointCache = Arc<DashMap<PeripheralId, CachedEndpoints>>;
+
 pub(super) struct BtleplugHardwareConnector<T: Peripheral + 'static> {
   // Passed in and stored as a member because otherwise it's annoying to get (properties require await)
   name: String,
@@ -56,6 +114,10 @@ pub(super) struct BtleplugHardwareConnector<T: Peripheral + 'static> {
   device: T,
   adapter: Adapter,
   requires_keepalive: bool,
+  endpoint_cache: Option<GattEndpointCache>,
+  // Set when the last connection used cached endpoints. Connectors are only asked to connect again
+  // when that connection failed, in which case the cached endpoints are suspect.
+  used_cached_endpoints: bool,
 }
 
 impl<T: Peripheral> BtleplugHardwareConnector<T> {
@@ -66,6 +128,7 @@ impl<T: Peripheral> BtleplugHardwareConnector<T> {
     device: T,
     adapter: Adapter,
     requires_keepalive: bool,
+    endpoint_cache: Option<GattEndpointCache>,
   ) -> Self {
     Self {
       name: name.to_owned(),
@@ -74,7 +137,27 @@ impl<T: Peripheral> BtleplugHardwareConnector<T> {
       device,
       adapter,
       requires_keepalive,
+      endpoint_cache,
+      used_cached_endpoints: false,
+    }
+  }
+
+  /// Endpoints cached from an earlier connection to the device, if any.
+  fn cached_endpoints(&mut self) -> Option<CachedEndpoints> {
+    let cache = self.endpoint_cache.as_ref()?;
+    let id = self.device.id();
+    if self.used_cached_endpoints {
+      debug!(
+        "Reconnecting to {} after a failed connection, remapping endpoints.",
+        self.name
+      );
+      cache.remove(&id);
+      self.used_cached_endpoints = false;
+      return None;
     }
+    let cached = cache.get(&id).map(|cached| cached.clone());
+    self.used_cached_endpoints = cached.is_some();
+    cached
   }
 }
 
@@ -118,11 +201,14 @@ impl<T: Peripheral> HardwareConnector for BtleplugHardwareConnector<T> {
         )));
       }
     }
+    let cached_endpoints = self.cached_endpoints();
     Ok(Box::new(BtleplugHardwareSpecializer::new(
       &self.name,
       self.device.clone(),
       self.adapter.clone(),
       self.requires_keepalive,
+      self.endpoint_cache.clone(),
+      cached_endpoints,
     )))
   }
 }
@@ -132,26 +218,37 @@ pub struct BtleplugHardwareSpecializer<T: Peripheral + 'static> {
   device: T,
   adapter: Adapter,
   requires_keepalive: bool,
+  endpoint_cache: Option<GattEndpointCache>,
+  /// Endpoints mapped during an earlier connection, used instead of mapping them again.
+  cached_endpoints: Option<CachedEndpoints>,
 }
 
 impl<T: Peripheral> BtleplugHardwareSpecializer<T> {
-  pub(super) fn new(name: &str, device: T, adapter: Adapter, requires_keepalive: bool) -> Self {
+  pub(super) fn new(
+    name: &str,
+    device: T,
+    adapter: Adapter,
+    requires_keepalive: bool,
+    endpoint_cache: Option<GattEndpointCache>,
+    cached_endpoints: Option<CachedEndpoints>,
+  ) -> Self {
     Self {
       name: name.to_owned(),
       device,
       adapter,
       requires_keepalive,
+      endpoint_cache,
+      cached_endpoints,
     }
   }
-}
 
-#[async_trait]
-impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
-  async fn specialize(
-    &mut self,
+  /// Maps the services the protocol needs, plus the standard ones, to endpoints.
+  async fn map_endpoints(
+    &self,
     specifiers: &[ProtocolCommunicationSpecifier],
-  ) -> Result<Hardware, ButtplugDeviceError> {
+  ) -> Result<CachedEndpoints, ButtplugDeviceError> {
     // Map UUIDs to endpoints
+    let services = self.device.services();
     let mut uuid_map = HashMap::<Uuid, Endpoint>::new();
     let mut endpoints = HashMap::<Endpoint, Characteristic>::new();
     let address = self.device.id();
@@ -161,7 +258,7 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
       .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
     {
       for (proto_uuid, proto_service) in btle.services() {
-        for service in self.device.services() {
+        for service in &services {
           if service.uuid != *proto_uuid {
             continue;
           }
@@ -194,6 +291,93 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
         self.name, address
       )));
     }
+
+    // If the protocol didn't map the standard services itself, map them anyways, so battery reads
+    // and device information work without protocol specific code.
+    for (service_uuid, chr_uuid, endpoint) in BLE_STANDARD_CHARACTERISTICS {
+      if let Entry::Vacant(entry) = endpoints.entry(endpoint) {
+        if let Some(chr) = services
+          .iter()
+          .filter(|service| service.uuid == service_uuid)
+          .flat_map(|service| service.characteristics.iter())
+          .find(|chr| chr.uuid == chr_uuid)
+        {
+          debug!(
+            "Found standard characteristic {} for endpoint {} on device {}",
+            chr_uuid, endpoint, self.name
+          );
+          uuid_map.insert(chr_uuid, endpoint);
+          entry.insert(chr.clone());
+        }
+      }
+    }
+    let device_information = self.read_device_information(&endpoints).await;
+    Ok(CachedEndpoints {
+      endpoints,
+      uuid_map,
+      device_information,
+    })
+  }
+
+  /// Reads the Device Information Service strings for whichever endpoints were mapped. Reads that
+  /// fail are logged and left empty, as this information is only used to help with identification.
+  async fn read_device_information(
+    &self,
+    endpoints: &HashMap<Endpoint, Characteristic>,
+  ) -> DeviceInformation {
+    let mut information = DeviceInformation::default();
+    for endpoint in [
+      Endpoint::RxBLEManufacturerName,
+      Endpoint::RxBLEModel,
+      Endpoint::RxBLESerialNumber,
+    ] {
+      let Some(chr) = endpoints.get(&endpoint) else {
+        continue;
+      };
+      let value = match self.device.read(chr).await {
+        Ok(data) => parse_device_information_string(&data),
+        Err(err) => {
+          debug!(
+            "Cannot read {} from device {}: {:?}",
+            endpoint, self.name, err
+          );
+          None
+        }
+      };
+      match endpoint {
+        Endpoint::RxBLEManufacturerName => information.set_manufacturer_name(value),
+        Endpoint::RxBLEModel => information.set_model_number(value),
+        _ => information.set_serial_number(value),
+      };
+    }
+    information
+  }
+}
+
+#[async_trait]
+impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let address = self.device.id();
+    let CachedEndpoints {
+      endpoints,
+      uuid_map,
+      device_information,
+    } = if let Some(cached) = self.cached_endpoints.take() {
+      debug!("Using cached endpoints for {}", self.name);
+      cached
+    } else {
+      let mapped = self.map_endpoints(specifiers).await?;
+      if let Some(cache) = &self.endpoint_cache {
+        if !mapped.endpoints.is_empty() {
+          cache.insert(address.clone(), mapped.clone());
+        }
+      }
+      mapped
+    };
+
     let notification_stream = self
       .device
       .notifications()
@@ -219,6 +403,8 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
       Box::new(device_internal_impl),
     );
 
+    hardware.set_device_information(device_information);
+
     // Let the hardware know if we need command resends or whatever. Fucking iOS.
     if self.requires_keepalive {
       hardware.set_requires_keepalive();
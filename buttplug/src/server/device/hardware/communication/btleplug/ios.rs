@@ -0,0 +1,142 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! iOS app lifecycle handling for the btleplug communication manager.
+//!
+//! iOS only allows scanning in the background if the scan is filtered by service UUID, and stops
+//! unfiltered scans as soon as the app leaves the foreground. Most toys only advertise their name,
+//! so we can't always use a service filter. Instead, the app reports lifecycle changes via
+//! [set_app_state], and the adapter task switches between an unfiltered scan in the foreground and a
+//! scan filtered to every service in the device configuration in the background.
+//!
+//! If we were scanning when the app changes state, the scan is restarted with the matching filter,
+//! and devices that were seen but not connected are re-reported.
+//!
+//! CoreBluetooth state restoration is not supported. btleplug creates its central manager without
+//! a restore identifier and doesn't implement `centralManager:willRestoreState:`, so if iOS
+//! terminates the app in the background, it won't be relaunched for Bluetooth events, and
+//! connections have to be set up again from scratch. This needs support in btleplug first.
+
+use crate::{
+  server::device::configuration::ProtocolCommunicationSpecifier,
+  util::device_configuration::load_protocol_configs,
+};
+use btleplug::{
+  api::{Central, ScanFilter},
+  platform::Adapter,
+};
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+/// Application lifecycle state, as reported by the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IosAppState {
+  #[default]
+  Foreground,
+  Background,
+}
+
+static APP_STATE: Lazy<watch::Sender<IosAppState>> =
+  Lazy::new(|| watch::Sender::new(IosAppState::default()));
+
+/// Set the app lifecycle state. Should be called from the app delegate's
+/// `applicationDidEnterBackground`/`applicationWillEnterForeground` (or the scene equivalents).
+pub fn set_app_state(state: IosAppState) {
+  info!("iOS app state set to {:?}", state);
+  APP_STATE.send_replace(state);
+}
+
+/// Returns the current app lifecycle state.
+pub fn app_state() -> IosAppState {
+  *APP_STATE.borrow()
+}
+
+pub(super) fn subscribe_app_state() -> watch::Receiver<IosAppState> {
+  APP_STATE.subscribe()
+}
+
+/// All Bluetooth LE services from the built in device configuration.
+fn background_scan_services() -> Vec<Uuid> {
+  let dcm = match load_protocol_configs(None, None, false).and_then(|mut builder| builder.finish())
+  {
+    Ok(dcm) => dcm,
+    Err(err) => {
+      error!(
+        "Cannot load device configuration for background scanning filter, background scanning will not find devices: {:?}",
+        err
+      );
+      return vec![];
+    }
+  };
+  let services: BTreeSet<Uuid> = dcm
+    .protocol_device_configurations()
+    .values()
+    .flatten()
+    .filter_map(|specifier| {
+      if let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier {
+        Some(btle.services().keys().copied().collect::<Vec<_>>())
+      } else {
+        None
+      }
+    })
+    .flatten()
+    .collect();
+  services.into_iter().collect()
+}
+
+/// Scanning state tracked by the adapter task, so scans can be swapped out when the app state
+/// changes.
+pub(super) struct IosScanState {
+  scanning: bool,
+  app_state: IosAppState,
+  background_services: Vec<Uuid>,
+}
+
+impl IosScanState {
+  pub fn new() -> Self {
+    Self {
+      scanning: false,
+      app_state: app_state(),
+      background_services: background_scan_services(),
+    }
+  }
+
+  pub fn set_scanning(&mut self, scanning: bool) {
+    self.scanning = scanning;
+  }
+
+  pub fn scan_filter(&self) -> ScanFilter {
+    match self.app_state {
+      IosAppState::Foreground => ScanFilter::default(),
+      IosAppState::Background => ScanFilter {
+        services: self.background_services.clone(),
+      },
+    }
+  }
+
+  /// Update the app state, restarting the scan with the matching filter if we're scanning. Returns
+  /// true if the scan was restarted.
+  pub async fn app_state_changed(&mut self, adapter: &Adapter, state: IosAppState) -> bool {
+    if self.app_state == state {
+      return false;
+    }
+    self.app_state = state;
+    if !self.scanning {
+      return false;
+    }
+    debug!("Restarting scan for iOS app state {:?}", state);
+    // iOS may have already stopped the scan, so failure here isn't an issue.
+    let _ = adapter.stop_scan().await;
+    if let Err(err) = adapter.start_scan(self.scan_filter()).await {
+      error!("Restarting scan after app state change failed: {}", err);
+      return false;
+    }
+    true
+  }
+}
@@ -5,7 +5,11 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+#[cfg(all(target_os = "android", feature = "tokio-runtime"))]
+pub mod android;
 pub mod btleplug_comm_manager;
 pub use btleplug_comm_manager::BtlePlugCommunicationManagerBuilder;
 mod btleplug_adapter_task;
 pub mod btleplug_hardware;
+#[cfg(target_os = "ios")]
+pub mod ios;
@@ -0,0 +1,152 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::buttplug_federation_hardware::ButtplugFederationHardwareConnector;
+use crate::{
+  client::ButtplugClient,
+  core::{connector::new_json_ws_client_connector, errors::ButtplugDeviceError},
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+    TimedRetryCommunicationManager,
+    TimedRetryCommunicationManagerImpl,
+  },
+};
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+#[derive(Clone)]
+pub struct ButtplugFederationCommunicationManagerBuilder {
+  server_address: String,
+  client_name: String,
+}
+
+impl ButtplugFederationCommunicationManagerBuilder {
+  /// Bridge devices from the server at `server_address`, which should be a websocket URL (i.e.
+  /// `ws://192.168.1.20:12345`).
+  pub fn new(server_address: &str) -> Self {
+    Self {
+      server_address: server_address.to_owned(),
+      client_name: "Buttplug Federation".to_owned(),
+    }
+  }
+
+  /// Client name to identify as on the remote server.
+  pub fn client_name(mut self, name: &str) -> Self {
+    self.client_name = name.to_owned();
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for ButtplugFederationCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "ButtplugFederationCommunicationManager"
+  }
+
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(TimedRetryCommunicationManager::new(
+      ButtplugFederationCommunicationManager::new(sender, &self.server_address, &self.client_name),
+    ))
+  }
+}
+
+pub struct ButtplugFederationCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  server_address: String,
+  client: ButtplugClient,
+}
+
+impl ButtplugFederationCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    server_address: &str,
+    client_name: &str,
+  ) -> Self {
+    Self {
+      sender,
+      server_address: server_address.to_owned(),
+      client: ButtplugClient::new(client_name),
+    }
+  }
+
+  async fn connect(&self) -> bool {
+    info!(
+      "Connecting to federated Buttplug server at {}",
+      self.server_address
+    );
+    if let Err(err) = self
+      .client
+      .connect(new_json_ws_client_connector(&self.server_address))
+      .await
+    {
+      debug!(
+        "Cannot connect to federated Buttplug server at {}, will retry: {}",
+        self.server_address, err
+      );
+      return false;
+    }
+    // Devices the remote server already knows about come in with the connection, but have the
+    // remote look for more too. The remote server decides when its own scan is done.
+    if let Err(err) = self.client.start_scanning().await {
+      warn!(
+        "Federated Buttplug server at {} cannot start scanning: {}",
+        self.server_address, err
+      );
+    }
+    true
+  }
+}
+
+#[async_trait]
+impl TimedRetryCommunicationManagerImpl for ButtplugFederationCommunicationManager {
+  fn name(&self) -> &'static str {
+    "ButtplugFederationCommunicationManager"
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+    if !self.client.connected() && !self.connect().await {
+      return Ok(());
+    }
+    for device in self.client.devices() {
+      if !device.connected() {
+        continue;
+      }
+      // Remote device indexes are unique per remote server, which makes them stable addresses.
+      let address = format!("{}#{}", self.server_address, device.index());
+      let name = device.name().clone();
+      let creator = Box::new(ButtplugFederationHardwareConnector::new(device, &address));
+      // As with other network managers, this emits every device each scan, and the device manager
+      // rejects the ones that are already connected.
+      if self
+        .sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name,
+          address,
+          creator,
+        })
+        .await
+        .is_err()
+      {
+        error!("Error sending device found message from Buttplug federation manager.");
+      }
+    }
+    Ok(())
+  }
+
+  fn rescan_wait_duration(&self) -> Duration {
+    Duration::from_secs(1)
+  }
+}
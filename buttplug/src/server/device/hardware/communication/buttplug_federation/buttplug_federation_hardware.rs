@@ -0,0 +1,273 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  client::{
+    ButtplugClientDevice,
+    ButtplugClientDeviceEvent,
+    LinearCommand,
+    RotateCommand,
+    ScalarCommand,
+  },
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ButtplugDeviceCommandMessageUnion, ClientGenericDeviceMessageAttributes, Endpoint},
+  },
+  server::device::{
+    configuration::{
+      ButtplugFederationSpecifier,
+      ProtocolAttributesType,
+      ProtocolCommunicationSpecifier,
+      ProtocolDeviceAttributes,
+      ServerDeviceMessageAttributesBuilder,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{
+      GenericHardwareSpecializer,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::async_manager,
+};
+use async_trait::async_trait;
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  StreamExt,
+};
+use std::{
+  fmt::{self, Debug},
+  sync::Arc,
+};
+use tokio::sync::broadcast;
+
+/// Converts the remote device's client attributes back into server attributes, so the local server
+/// exposes the same features. Sensors aren't bridged.
+fn server_device_attributes(device: &ButtplugClientDevice) -> ProtocolDeviceAttributes {
+  let convert = |attrs: &Vec<ClientGenericDeviceMessageAttributes>| {
+    attrs
+      .iter()
+      .cloned()
+      .map(|x| x.into())
+      .collect::<Vec<ServerGenericDeviceMessageAttributes>>()
+  };
+  let client_attrs = device.message_attributes();
+  let mut builder = ServerDeviceMessageAttributesBuilder::default();
+  if let Some(attrs) = client_attrs.scalar_cmd() {
+    builder.scalar_cmd(&convert(attrs));
+  }
+  if let Some(attrs) = client_attrs.rotate_cmd() {
+    builder.rotate_cmd(&convert(attrs));
+  }
+  if let Some(attrs) = client_attrs.linear_cmd() {
+    builder.linear_cmd(&convert(attrs));
+  }
+  ProtocolDeviceAttributes::new(
+    ProtocolAttributesType::Default,
+    Some(device.name().clone()),
+    device.display_name().clone(),
+    builder.finish(),
+    None,
+  )
+}
+
+pub struct ButtplugFederationHardwareConnector {
+  device: Arc<ButtplugClientDevice>,
+  address: String,
+}
+
+impl ButtplugFederationHardwareConnector {
+  pub(super) fn new(device: Arc<ButtplugClientDevice>, address: &str) -> Self {
+    Self {
+      device,
+      address: address.to_owned(),
+    }
+  }
+}
+
+impl Debug for ButtplugFederationHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ButtplugFederationHardwareConnector")
+      .field("name", self.device.name())
+      .field("address", &self.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for ButtplugFederationHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::ButtplugFederation(ButtplugFederationSpecifier::default())
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    if !self.device.connected() {
+      return Err(ButtplugDeviceError::DeviceNotConnected(format!(
+        "Federated device {} is no longer connected to its server.",
+        self.device.name()
+      )));
+    }
+    let hardware_internal = ButtplugFederationHardware::new(self.device.clone(), &self.address);
+    let mut hardware = Hardware::new(
+      self.device.name(),
+      &self.address,
+      &[Endpoint::Tx],
+      Box::new(hardware_internal),
+    );
+    hardware.set_device_attributes(server_device_attributes(&self.device));
+    Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
+  }
+}
+
+#[derive(Clone)]
+pub struct ButtplugFederationHardware {
+  device: Arc<ButtplugClientDevice>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl Debug for ButtplugFederationHardware {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ButtplugFederationHardware")
+      .field("name", self.device.name())
+      .finish()
+  }
+}
+
+impl ButtplugFederationHardware {
+  fn new(device: Arc<ButtplugClientDevice>, address: &str) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let sender_clone = event_sender.clone();
+    let address = address.to_owned();
+    let mut device_events = device.event_stream();
+    async_manager::spawn(async move {
+      while let Some(event) = device_events.next().await {
+        if matches!(
+          event,
+          ButtplugClientDeviceEvent::DeviceRemoved | ButtplugClientDeviceEvent::ClientDisconnect
+        ) {
+          break;
+        }
+      }
+      info!("Federated device {} disconnected from its server.", address);
+      let _ = sender_clone.send(HardwareEvent::Disconnected(address));
+    });
+    Self {
+      device,
+      event_sender,
+    }
+  }
+}
+
+impl HardwareInternal for ButtplugFederationHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  // The remote server owns the device connection, so there's nothing to do here.
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Buttplug federation does not support read".to_owned(),
+    )))
+    .boxed()
+  }
+
+  // The passthru protocol hands us the local command as JSON. Device indexes and message ids only
+  // mean something locally, so turn the command back into a client call on the remote device.
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let command: ButtplugDeviceCommandMessageUnion = match serde_json::from_slice(msg.data()) {
+      Ok(command) => command,
+      Err(err) => {
+        return future::ready(Err(ButtplugDeviceError::ProtocolSpecificError(
+          "buttplug-passthru".to_owned(),
+          format!("Cannot parse federated command: {}", err),
+        )))
+        .boxed()
+      }
+    };
+    let fut = match command {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+        self.device.scalar(&ScalarCommand::ScalarMap(
+          msg
+            .scalars()
+            .iter()
+            .map(|cmd| (cmd.index(), (cmd.scalar(), cmd.actuator_type())))
+            .collect(),
+        ))
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+        self.device.rotate(&RotateCommand::RotateMap(
+          msg
+            .rotations()
+            .iter()
+            .map(|cmd| (cmd.index(), (cmd.speed(), cmd.clockwise())))
+            .collect(),
+        ))
+      }
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => {
+        self.device.linear(&LinearCommand::LinearMap(
+          msg
+            .vectors()
+            .iter()
+            .map(|cmd| (cmd.index(), (cmd.duration(), cmd.position())))
+            .collect(),
+        ))
+      }
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => self.device.stop(),
+      other => {
+        return future::ready(Err(ButtplugDeviceError::UnhandledCommand(format!(
+          "Buttplug federation does not bridge {:?}",
+          other
+        ))))
+        .boxed()
+      }
+    };
+    async move {
+      fut
+        .await
+        .map_err(|err| ButtplugDeviceError::DeviceCommunicationError(err.to_string()))
+    }
+    .boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Buttplug federation does not support subscribe".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Buttplug federation does not support unsubscribe".to_owned(),
+    )))
+    .boxed()
+  }
+}
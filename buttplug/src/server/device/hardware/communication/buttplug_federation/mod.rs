@@ -0,0 +1,25 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Bridges devices from another Buttplug server, so devices attached to a remote machine (i.e. a
+//! Raspberry Pi elsewhere in the house) show up alongside local hardware.
+//!
+//! The communication manager connects to the remote server as a regular client, and exposes each
+//! remote device as local hardware using the `buttplug-passthru` protocol. Commands sent to the
+//! local device are forwarded to the remote device as client commands, so the remote server handles
+//! all of the actual protocol work. Sensors are not bridged.
+
+mod buttplug_federation_comm_manager;
+mod buttplug_federation_hardware;
+pub use buttplug_federation_comm_manager::{
+  ButtplugFederationCommunicationManager,
+  ButtplugFederationCommunicationManagerBuilder,
+};
+pub use buttplug_federation_hardware::{
+  ButtplugFederationHardware,
+  ButtplugFederationHardwareConnector,
+};
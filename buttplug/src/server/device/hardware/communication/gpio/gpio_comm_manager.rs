@@ -0,0 +1,121 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::gpio_hardware::{GpioHardwareConnector, PWM_SYSFS_PATH};
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+};
+use futures::future::{self, FutureExt};
+use std::{
+  fs,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Default, Clone)]
+pub struct GpioCommunicationManagerBuilder {}
+
+impl HardwareCommunicationManagerBuilder for GpioCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "GpioCommunicationManager"
+  }
+
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(GpioCommunicationManager::new(sender))
+  }
+}
+
+pub struct GpioCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl GpioCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+    Self {
+      sender,
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+impl HardwareCommunicationManager for GpioCommunicationManager {
+  fn name(&self) -> &'static str {
+    "GpioCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    // PWM chips don't come and go, so a scan just lists what's there. Chips without a gpio
+    // specifier in the user config won't match any protocol, and the device manager ignores chips
+    // that are already connected.
+    let chips: Vec<String> = match fs::read_dir(PWM_SYSFS_PATH) {
+      Ok(entries) => entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("pwmchip"))
+        .collect(),
+      Err(err) => {
+        debug!("Cannot list PWM chips in {}: {:?}", PWM_SYSFS_PATH, err);
+        vec![]
+      }
+    };
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      is_scanning.store(true, Ordering::SeqCst);
+      for chip in chips {
+        trace!("Found PWM chip {}", chip);
+        if sender
+          .send(HardwareCommunicationManagerEvent::DeviceFound {
+            name: format!("GPIO PWM {}", chip),
+            address: chip.clone(),
+            creator: Box::new(GpioHardwareConnector::new(&chip)),
+          })
+          .await
+          .is_err()
+        {
+          error!("Device manager disappeared, exiting.");
+          break;
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    // Scans finish on their own as soon as all chips are listed.
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
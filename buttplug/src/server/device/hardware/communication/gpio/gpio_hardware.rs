@@ -0,0 +1,284 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::hardware::communication::HardwareSpecificError,
+  server::device::{
+    configuration::{GpioSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  fs,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  thread,
+  time::Duration,
+};
+use tokio::sync::broadcast;
+
+pub(super) const PWM_SYSFS_PATH: &str = "/sys/class/pwm";
+
+fn gpio_error(path: &Path, err: std::io::Error) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::GpioError(format!(
+    "{}: {}",
+    path.display(),
+    err
+  )))
+}
+
+fn write_sysfs(path: &Path, value: impl ToString) -> Result<(), ButtplugDeviceError> {
+  fs::write(path, value.to_string()).map_err(|err| gpio_error(path, err))
+}
+
+/// A single exported PWM channel of a chip.
+struct PwmChannel {
+  path: PathBuf,
+  period_ns: u32,
+}
+
+impl PwmChannel {
+  fn open(chip_path: &Path, channel: u32, period_ns: u32) -> Result<Self, ButtplugDeviceError> {
+    let path = chip_path.join(format!("pwm{}", channel));
+    if !path.exists() {
+      write_sysfs(&chip_path.join("export"), channel)?;
+    }
+    let pwm = Self { path, period_ns };
+    // Exported channel files are created root owned, then handed to the gpio group by udev, so
+    // writes can fail for a moment after export.
+    let mut result = pwm.configure();
+    for _ in 0..10 {
+      if result.is_ok() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(50));
+      result = pwm.configure();
+    }
+    result.map(|_| pwm)
+  }
+
+  fn configure(&self) -> Result<(), ButtplugDeviceError> {
+    // Duty cycle can't be larger than the period, so zero it before changing the period.
+    write_sysfs(&self.path.join("duty_cycle"), 0)?;
+    write_sysfs(&self.path.join("period"), self.period_ns)?;
+    write_sysfs(&self.path.join("enable"), 1)
+  }
+
+  fn set_duty(&self, fraction: u16) -> Result<(), ButtplugDeviceError> {
+    let duty_ns = self.period_ns as u64 * fraction as u64 / u16::MAX as u64;
+    write_sysfs(&self.path.join("duty_cycle"), duty_ns)
+  }
+
+  fn disable(&self) {
+    let _ = write_sysfs(&self.path.join("duty_cycle"), 0);
+    let _ = write_sysfs(&self.path.join("enable"), 0);
+  }
+}
+
+pub struct GpioHardwareConnector {
+  chip: String,
+}
+
+impl GpioHardwareConnector {
+  pub fn new(chip: &str) -> Self {
+    Self {
+      chip: chip.to_owned(),
+    }
+  }
+}
+
+impl Debug for GpioHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("GpioHardwareConnector")
+      .field("chip", &self.chip)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for GpioHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::Gpio(GpioSpecifier::new_from_chip(&self.chip))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    Ok(Box::new(GpioHardwareSpecializer {
+      chip: self.chip.clone(),
+    }))
+  }
+}
+
+pub struct GpioHardwareSpecializer {
+  chip: String,
+}
+
+#[async_trait]
+impl HardwareSpecializer for GpioHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let spec = specifiers
+      .iter()
+      .find_map(|specifier| match specifier {
+        ProtocolCommunicationSpecifier::Gpio(spec) if *spec.chip() == self.chip => Some(spec),
+        _ => None,
+      })
+      .ok_or_else(|| {
+        ButtplugDeviceError::DeviceConnectionError(format!(
+          "No GPIO specifier found for PWM chip {}",
+          self.chip
+        ))
+      })?;
+    let hardware_internal = GpioHardware::try_create(spec)?;
+    let endpoints: Vec<Endpoint> = hardware_internal.channels.keys().cloned().collect();
+    Ok(Hardware::new(
+      &format!("GPIO PWM {}", self.chip),
+      &self.chip,
+      &endpoints,
+      Box::new(hardware_internal),
+    ))
+  }
+}
+
+/// PWM channels of a chip, addressed by endpoint.
+///
+/// Writes take a little endian u16, the duty cycle as a fraction of u16::MAX. Channels are stopped
+/// and disabled on disconnect or drop, so actuators don't keep running once the server lets go of
+/// them.
+pub struct GpioHardware {
+  chip: String,
+  channels: HashMap<Endpoint, Arc<PwmChannel>>,
+  connected: Arc<AtomicBool>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl GpioHardware {
+  fn try_create(spec: &GpioSpecifier) -> Result<Self, ButtplugDeviceError> {
+    let chip_path = Path::new(PWM_SYSFS_PATH).join(spec.chip());
+    let mut channels = HashMap::new();
+    for (endpoint, channel) in spec.channels() {
+      let pwm = PwmChannel::open(&chip_path, *channel, *spec.period_ns());
+      match pwm {
+        Ok(pwm) => {
+          channels.insert(*endpoint, Arc::new(pwm));
+        }
+        Err(err) => {
+          channels
+            .values()
+            .for_each(|pwm: &Arc<PwmChannel>| pwm.disable());
+          return Err(err);
+        }
+      }
+    }
+    let (event_sender, _) = broadcast::channel(256);
+    Ok(Self {
+      chip: spec.chip().clone(),
+      channels,
+      connected: Arc::new(AtomicBool::new(true)),
+      event_sender,
+    })
+  }
+
+  fn stop_all(&self) {
+    self.channels.values().for_each(|pwm| pwm.disable());
+  }
+}
+
+impl Drop for GpioHardware {
+  fn drop(&mut self) {
+    if self.connected.swap(false, Ordering::SeqCst) {
+      self.stop_all();
+    }
+  }
+}
+
+impl HardwareInternal for GpioHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.connected.swap(false, Ordering::SeqCst) {
+      self.stop_all();
+      let _ = self
+        .event_sender
+        .send(HardwareEvent::Disconnected(self.chip.clone()));
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "GPIO PWM devices do not support reads".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if !self.connected.load(Ordering::SeqCst) {
+      return future::ready(Err(ButtplugDeviceError::DeviceNotConnected(
+        self.chip.clone(),
+      )))
+      .boxed();
+    }
+    let result = match (self.channels.get(&msg.endpoint()), msg.data().as_slice()) {
+      (Some(pwm), [low, high]) => pwm.set_duty(u16::from_le_bytes([*low, *high])),
+      (Some(_), data) => Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+        "GPIO PWM writes take 2 bytes, got {}",
+        data.len()
+      ))),
+      (None, _) => Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint())),
+    };
+    future::ready(result).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "GPIO PWM devices do not support subscriptions".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "GPIO PWM devices do not support subscriptions".to_owned(),
+    )))
+    .boxed()
+  }
+}
@@ -0,0 +1,22 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Actuators driven directly from PWM pins (i.e. a Raspberry Pi with a motor driver board), through
+//! the Linux sysfs PWM interface.
+//!
+//! Every PWM chip in /sys/class/pwm is found as a device, addressed by chip name. Which channels of
+//! a chip drive which actuators is up to the build, so chips are only used if the user device
+//! config has a `gpio` specifier for them, mapping channels to endpoints, and a `devices` entry
+//! giving the build's features. On a Raspberry Pi, the PWM pins need to be enabled with the
+//! `pwm-2chan` device tree overlay first, and the user running the server needs to be in the `gpio`
+//! group (or have write access to /sys/class/pwm some other way).
+
+mod gpio_comm_manager;
+mod gpio_hardware;
+
+pub use gpio_comm_manager::{GpioCommunicationManager, GpioCommunicationManagerBuilder};
+pub use gpio_hardware::{GpioHardware, GpioHardwareConnector};
@@ -19,6 +19,10 @@ use super::hid_device_impl::HidHardwareConnector;
 pub struct HidCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for HidCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "HIDCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
@@ -79,7 +79,7 @@ impl HardwareConnector for HidHardwareConnector {
     let hardware = Hardware::new(
       &self.device_info.product_string().unwrap(),
       &self.device_info.serial_number().unwrap(),
-      &[Endpoint::Rx, Endpoint::Tx],
+      &[Endpoint::Rx, Endpoint::Tx, Endpoint::TxFeature],
       Box::new(device_impl_internal),
     );
     Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
@@ -129,6 +129,16 @@ impl HardwareInternal for HIDDeviceImpl {
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     let device = self.device.clone();
     let data = msg.data.clone();
+    if msg.endpoint == Endpoint::TxFeature {
+      return Box::pin(async move {
+        device.lock().await.send_feature_report(&data).map_err(|e| {
+          ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Cannot send feature report to HID Device: {:?}.",
+            e
+          ))
+        })
+      });
+    }
     Box::pin(async move {
       device.lock().await.write(&data).await.map_err(|e| {
         ButtplugDeviceError::DeviceCommunicationError(format!(
@@ -154,6 +154,27 @@ impl HidAsyncDevice {
       }))),
     })
   }
+
+  /// Send a feature report. As with writes, the report ID goes in the first byte, or 0 if the
+  /// device doesn't use report IDs.
+  pub fn send_feature_report(&self, data: &[u8]) -> Result<(), io::Error> {
+    let inner = self.inner.as_ref().ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Cannot write to a closed device",
+      )
+    })?;
+    let guard = inner
+      .lock()
+      .map_err(|e| io::Error::other(format!("Mutex broken: {:?}", e)))?;
+    let device = guard
+      .device
+      .lock()
+      .map_err(|e| io::Error::other(format!("Mutex broken: {:?}", e)))?;
+    device
+      .send_feature_report(data)
+      .map_err(|e| io::Error::other(format!("hidapi failed: {}", e)))
+  }
 }
 
 impl AsyncWrite for HidAsyncDevice {
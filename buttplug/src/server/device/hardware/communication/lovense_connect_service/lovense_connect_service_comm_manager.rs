@@ -114,6 +114,10 @@ type LovenseServiceInfo = HashMap<String, LovenseServiceHostInfo>;
 pub struct LovenseConnectServiceCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for LovenseConnectServiceCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "LovenseServiceDeviceCommManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
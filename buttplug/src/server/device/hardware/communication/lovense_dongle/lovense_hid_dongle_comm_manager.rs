@@ -161,6 +161,10 @@ fn hid_read_thread(
 pub struct LovenseHIDDongleCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for LovenseHIDDongleCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "LovenseHIDDongleCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
@@ -147,6 +147,10 @@ fn serial_read_thread(
 pub struct LovenseSerialDongleCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for LovenseSerialDongleCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "LovenseSerialDongleCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
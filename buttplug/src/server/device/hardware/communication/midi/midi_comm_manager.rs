@@ -0,0 +1,134 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::midi_hardware::{MidiHardwareConnector, MIDI_DEVICE_PATH};
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+};
+use futures::future::{self, FutureExt};
+use std::{
+  fs,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::mpsc::Sender;
+
+/// Gets the ALSA id of the sound card a port belongs to (i.e. "UM1" for a USB MIDI cable), to make
+/// ports a little easier to recognize in logs and UIs.
+fn card_id(port: &str) -> Option<String> {
+  let card = port.strip_prefix("midiC")?.split('D').next()?;
+  fs::read_to_string(format!("/proc/asound/card{}/id", card))
+    .ok()
+    .map(|id| id.trim().to_owned())
+}
+
+#[derive(Default, Clone)]
+pub struct MidiCommunicationManagerBuilder {}
+
+impl HardwareCommunicationManagerBuilder for MidiCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "MidiCommunicationManager"
+  }
+
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(MidiCommunicationManager::new(sender))
+  }
+}
+
+pub struct MidiCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl MidiCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+    Self {
+      sender,
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+impl HardwareCommunicationManager for MidiCommunicationManager {
+  fn name(&self) -> &'static str {
+    "MidiCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    // Like GPIO, a scan just lists the ports that are there. Ports without a midi specifier in the
+    // user config won't match any protocol, and the device manager ignores ports that are already
+    // connected.
+    let ports: Vec<String> = match fs::read_dir(MIDI_DEVICE_PATH) {
+      Ok(entries) => entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("midiC"))
+        .collect(),
+      Err(err) => {
+        debug!("Cannot list MIDI ports in {}: {:?}", MIDI_DEVICE_PATH, err);
+        vec![]
+      }
+    };
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      is_scanning.store(true, Ordering::SeqCst);
+      for port in ports {
+        let name = match card_id(&port) {
+          Some(card_id) => format!("MIDI {} ({})", card_id, port),
+          None => format!("MIDI {}", port),
+        };
+        trace!("Found MIDI port {}", name);
+        if sender
+          .send(HardwareCommunicationManagerEvent::DeviceFound {
+            name: name.clone(),
+            address: port.clone(),
+            creator: Box::new(MidiHardwareConnector::new(&name, &port)),
+          })
+          .await
+          .is_err()
+        {
+          error!("Device manager disappeared, exiting.");
+          break;
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    // Scans finish on their own as soon as all ports are listed.
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
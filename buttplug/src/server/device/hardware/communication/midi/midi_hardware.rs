@@ -0,0 +1,329 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::hardware::communication::HardwareSpecificError,
+  server::device::{
+    configuration::{MidiOutputMapping, MidiSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  fs::{File, OpenOptions},
+  io::Write,
+  path::Path,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Mutex,
+  },
+};
+use tokio::sync::broadcast;
+
+pub(super) const MIDI_DEVICE_PATH: &str = "/dev/snd";
+
+const MIDI_NOTE_OFF: u8 = 0x80;
+const MIDI_NOTE_ON: u8 = 0x90;
+const MIDI_CONTROL_CHANGE: u8 = 0xB0;
+
+fn midi_error(port: &str, err: std::io::Error) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::MidiError(format!(
+    "{}: {}",
+    port, err
+  )))
+}
+
+/// Checks a mapping from the user config can be sent, so typos show up on connect instead of on
+/// the first write.
+fn validate_mapping(endpoint: Endpoint, mapping: &MidiOutputMapping) -> Result<(), String> {
+  if !(1..=16).contains(&mapping.channel()) {
+    return Err(format!(
+      "{} has channel {}, MIDI channels are 1 to 16",
+      endpoint,
+      mapping.channel()
+    ));
+  }
+  match (mapping.cc(), mapping.note()) {
+    (Some(number), None) | (None, Some(number)) if number < 128 => Ok(()),
+    (Some(_), None) | (None, Some(_)) => Err(format!(
+      "{} has a controller or note number over 127",
+      endpoint
+    )),
+    _ => Err(format!(
+      "{} needs exactly one of cc or note to be set",
+      endpoint
+    )),
+  }
+}
+
+/// Builds the MIDI message for setting a mapped output to a value (0 to 127).
+fn midi_message(mapping: &MidiOutputMapping, value: u8) -> Vec<u8> {
+  let channel = mapping.channel() - 1;
+  let value = value.min(127);
+  match (mapping.cc(), mapping.note()) {
+    (Some(controller), _) => vec![MIDI_CONTROL_CHANGE | channel, controller, value],
+    (None, Some(note)) if value > 0 => vec![MIDI_NOTE_ON | channel, note, value],
+    (None, Some(note)) => vec![MIDI_NOTE_OFF | channel, note, 0],
+    (None, None) => vec![],
+  }
+}
+
+pub struct MidiHardwareConnector {
+  name: String,
+  port: String,
+}
+
+impl MidiHardwareConnector {
+  pub fn new(name: &str, port: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      port: port.to_owned(),
+    }
+  }
+}
+
+impl Debug for MidiHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MidiHardwareConnector")
+      .field("name", &self.name)
+      .field("port", &self.port)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for MidiHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::Midi(MidiSpecifier::new_from_port(&self.port))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    Ok(Box::new(MidiHardwareSpecializer {
+      name: self.name.clone(),
+      port: self.port.clone(),
+    }))
+  }
+}
+
+pub struct MidiHardwareSpecializer {
+  name: String,
+  port: String,
+}
+
+#[async_trait]
+impl HardwareSpecializer for MidiHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let spec = specifiers
+      .iter()
+      .find_map(|specifier| match specifier {
+        ProtocolCommunicationSpecifier::Midi(spec) if *spec.port() == self.port => Some(spec),
+        _ => None,
+      })
+      .ok_or_else(|| {
+        ButtplugDeviceError::DeviceConnectionError(format!(
+          "No MIDI specifier found for port {}",
+          self.port
+        ))
+      })?;
+    for (endpoint, mapping) in spec.outputs() {
+      validate_mapping(*endpoint, mapping).map_err(|err| {
+        ButtplugDeviceError::DeviceConnectionError(format!(
+          "Invalid MIDI output for port {}: {}",
+          self.port, err
+        ))
+      })?;
+    }
+    let file = OpenOptions::new()
+      .write(true)
+      .open(Path::new(MIDI_DEVICE_PATH).join(&self.port))
+      .map_err(|err| midi_error(&self.port, err))?;
+    let hardware_internal = MidiHardware::new(&self.port, file, spec.outputs().clone());
+    let endpoints: Vec<Endpoint> = spec.outputs().keys().cloned().collect();
+    Ok(Hardware::new(
+      &self.name,
+      &self.port,
+      &endpoints,
+      Box::new(hardware_internal),
+    ))
+  }
+}
+
+/// MIDI output port, with endpoints mapped to control change or note messages.
+///
+/// Writes take a single byte, the value (0 to 127) to send. Every mapped output is set to 0 on
+/// disconnect or drop, so rigs don't keep running once the server lets go of them.
+pub struct MidiHardware {
+  port: String,
+  file: Arc<Mutex<File>>,
+  outputs: HashMap<Endpoint, MidiOutputMapping>,
+  connected: Arc<AtomicBool>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl MidiHardware {
+  fn new(port: &str, file: File, outputs: HashMap<Endpoint, MidiOutputMapping>) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      port: port.to_owned(),
+      file: Arc::new(Mutex::new(file)),
+      outputs,
+      connected: Arc::new(AtomicBool::new(true)),
+      event_sender,
+    }
+  }
+
+  fn send(&self, message: &[u8]) -> Result<(), ButtplugDeviceError> {
+    self
+      .file
+      .lock()
+      .expect("Only held during writes, never poisoned")
+      .write_all(message)
+      .map_err(|err| midi_error(&self.port, err))
+  }
+
+  fn stop_all(&self) {
+    for mapping in self.outputs.values() {
+      if let Err(err) = self.send(&midi_message(mapping, 0)) {
+        warn!("Cannot stop MIDI output: {:?}", err);
+      }
+    }
+  }
+}
+
+impl Drop for MidiHardware {
+  fn drop(&mut self) {
+    if self.connected.swap(false, Ordering::SeqCst) {
+      self.stop_all();
+    }
+  }
+}
+
+impl HardwareInternal for MidiHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.connected.swap(false, Ordering::SeqCst) {
+      self.stop_all();
+      let _ = self
+        .event_sender
+        .send(HardwareEvent::Disconnected(self.port.clone()));
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "MIDI devices do not support reads".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if !self.connected.load(Ordering::SeqCst) {
+      return future::ready(Err(ButtplugDeviceError::DeviceNotConnected(
+        self.port.clone(),
+      )))
+      .boxed();
+    }
+    let result = match (self.outputs.get(&msg.endpoint()), msg.data().as_slice()) {
+      (Some(mapping), [value]) => self.send(&midi_message(mapping, *value)),
+      (Some(_), data) => Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+        "MIDI writes take 1 byte, got {}",
+        data.len()
+      ))),
+      (None, _) => Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint())),
+    };
+    future::ready(result).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "MIDI devices do not support subscriptions".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "MIDI devices do not support subscriptions".to_owned(),
+    )))
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_midi_message() {
+    let cc = MidiOutputMapping::new(2, Some(7), None);
+    assert_eq!(midi_message(&cc, 100), vec![0xB1, 7, 100]);
+    assert_eq!(midi_message(&cc, 255), vec![0xB1, 7, 127]);
+    let note = MidiOutputMapping::new(10, None, Some(36));
+    assert_eq!(midi_message(&note, 64), vec![0x99, 36, 64]);
+    assert_eq!(midi_message(&note, 0), vec![0x89, 36, 0]);
+  }
+
+  #[test]
+  fn test_validate_mapping() {
+    assert!(validate_mapping(
+      Endpoint::Generic0,
+      &MidiOutputMapping::new(1, Some(1), None)
+    )
+    .is_ok());
+    assert!(validate_mapping(
+      Endpoint::Generic0,
+      &MidiOutputMapping::new(0, Some(1), None)
+    )
+    .is_err());
+    assert!(validate_mapping(
+      Endpoint::Generic0,
+      &MidiOutputMapping::new(1, None, Some(128))
+    )
+    .is_err());
+    assert!(validate_mapping(
+      Endpoint::Generic0,
+      &MidiOutputMapping::new(1, Some(1), Some(1))
+    )
+    .is_err());
+    assert!(validate_mapping(Endpoint::Generic0, &MidiOutputMapping::new(1, None, None)).is_err());
+  }
+}
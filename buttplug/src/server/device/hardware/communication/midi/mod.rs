@@ -0,0 +1,21 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hardware driven by MIDI messages (DIY rigs, lighting and art installations, etc), through ALSA
+//! raw MIDI ports.
+//!
+//! Every raw MIDI port in /dev/snd is found as a device, addressed by port name (i.e. `midiC1D0`
+//! for device 0 of sound card 1). What's plugged into a port is up to the user, so ports are only
+//! used if the user device config has a `midi` specifier for them, mapping endpoints to control
+//! change or note messages, and a `devices` entry giving the rig's features. The user running the
+//! server needs write access to the port, usually through the `audio` group.
+
+mod midi_comm_manager;
+mod midi_hardware;
+
+pub use midi_comm_manager::{MidiCommunicationManager, MidiCommunicationManagerBuilder};
+pub use midi_hardware::{MidiHardware, MidiHardwareConnector};
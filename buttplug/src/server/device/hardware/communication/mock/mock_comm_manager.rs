@@ -0,0 +1,126 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{mock_hardware::MockHardwareConnector, MockDeviceHandle, MockDeviceState};
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+};
+use futures::future::{self, FutureExt};
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::{self, Sender};
+
+#[derive(Default)]
+pub struct MockCommunicationManagerBuilder {
+  devices: Vec<(String, MockHardwareConnector)>,
+}
+
+impl MockCommunicationManagerBuilder {
+  /// Adds a device advertising the given Bluetooth LE name, which is found the first time the
+  /// server scans.
+  pub fn add_device(&mut self, name: &str) -> MockDeviceHandle {
+    let state = Arc::new(MockDeviceState::new(&format!(
+      "mock-{}",
+      self.devices.len()
+    )));
+    let (sender, receiver) = mpsc::unbounded_channel();
+    self.devices.push((
+      name.to_owned(),
+      MockHardwareConnector::new(name, state.clone(), sender),
+    ));
+    MockDeviceHandle {
+      name: name.to_owned(),
+      state,
+      commands: receiver,
+    }
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for MockCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "MockCommunicationManager"
+  }
+
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(MockCommunicationManager {
+      sender,
+      devices: std::mem::take(&mut self.devices),
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    })
+  }
+}
+
+pub struct MockCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<(String, MockHardwareConnector)>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl HardwareCommunicationManager for MockCommunicationManager {
+  fn name(&self) -> &'static str {
+    "MockCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    // Mock devices can only be connected once, so each is only found by the first scan.
+    let events: Vec<_> = self
+      .devices
+      .drain(..)
+      .map(
+        |(name, connector)| HardwareCommunicationManagerEvent::DeviceFound {
+          name,
+          address: connector.address().to_owned(),
+          creator: Box::new(connector),
+        },
+      )
+      .collect();
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      is_scanning.store(true, Ordering::SeqCst);
+      for event in events {
+        if sender.send(event).await.is_err() {
+          error!("Device manager disappeared, exiting.");
+          break;
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    // Scans finish on their own as soon as all devices are found.
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
@@ -0,0 +1,207 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::MockDeviceState;
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::{self, Debug},
+  sync::Arc,
+};
+use tokio::sync::{broadcast, mpsc};
+
+pub struct MockHardwareConnector {
+  name: String,
+  state: Arc<MockDeviceState>,
+  commands: Option<mpsc::UnboundedSender<HardwareCommand>>,
+}
+
+impl MockHardwareConnector {
+  pub(super) fn new(
+    name: &str,
+    state: Arc<MockDeviceState>,
+    commands: mpsc::UnboundedSender<HardwareCommand>,
+  ) -> Self {
+    Self {
+      name: name.to_owned(),
+      state,
+      commands: Some(commands),
+    }
+  }
+
+  pub(super) fn address(&self) -> &str {
+    &self.state.address
+  }
+}
+
+impl Debug for MockHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MockHardwareConnector")
+      .field("name", &self.name)
+      .field("address", &self.state.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for MockHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      &self.name,
+      &HashMap::new(),
+      &[],
+    ))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    // The test only has one handle per device, so the device can only be connected once.
+    let commands = self.commands.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError("Mock device already connected".to_owned())
+    })?;
+    Ok(Box::new(MockHardwareSpecializer {
+      name: self.name.clone(),
+      state: self.state.clone(),
+      commands: Some(commands),
+    }))
+  }
+}
+
+pub struct MockHardwareSpecializer {
+  name: String,
+  state: Arc<MockDeviceState>,
+  commands: Option<mpsc::UnboundedSender<HardwareCommand>>,
+}
+
+#[async_trait]
+impl HardwareSpecializer for MockHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let commands = self.commands.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError("Mock device already specialized".to_owned())
+    })?;
+    // Mock whatever endpoints the protocol expects.
+    let mut endpoints = HashSet::new();
+    if let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+      .iter()
+      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    {
+      for endpoint_map in btle.services().values() {
+        endpoints.extend(endpoint_map.keys().copied());
+      }
+    }
+    let hardware_internal = MockHardware {
+      state: self.state.clone(),
+      endpoints: endpoints.clone(),
+      commands,
+    };
+    Ok(Hardware::new(
+      &self.name,
+      &self.state.address,
+      &endpoints.into_iter().collect::<Vec<_>>(),
+      Box::new(hardware_internal),
+    ))
+  }
+}
+
+pub struct MockHardware {
+  state: Arc<MockDeviceState>,
+  endpoints: HashSet<Endpoint>,
+  commands: mpsc::UnboundedSender<HardwareCommand>,
+}
+
+impl MockHardware {
+  fn send_command(
+    &self,
+    endpoint: Endpoint,
+    command: HardwareCommand,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if !self.endpoints.contains(&endpoint) {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(endpoint))).boxed();
+    }
+    // If the test dropped the handle, it doesn't care about commands anymore.
+    let _ = self.commands.send(command);
+    future::ready(Ok(())).boxed()
+  }
+}
+
+impl HardwareInternal for MockHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.state.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let _ = self
+      .state
+      .event_sender
+      .send(HardwareEvent::Disconnected(self.state.address.clone()));
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let result = if !self.endpoints.contains(&msg.endpoint()) {
+      Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))
+    } else {
+      self.state.take_read(msg.endpoint()).ok_or_else(|| {
+        ButtplugDeviceError::DeviceCommunicationError(format!(
+          "No reads queued for endpoint {} on mock device",
+          msg.endpoint()
+        ))
+      })
+    };
+    future::ready(result).boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.send_command(msg.endpoint(), msg.clone().into())
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.endpoints.contains(&msg.endpoint()) {
+      self.state.subscribed_endpoints.insert(msg.endpoint());
+    }
+    self.send_command(msg.endpoint(), (*msg).into())
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.state.subscribed_endpoints.remove(&msg.endpoint());
+    self.send_command(msg.endpoint(), (*msg).into())
+  }
+}
@@ -0,0 +1,133 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Mock devices, for end to end tests of apps and libraries built on Buttplug.
+//!
+//! Mock devices show up as Bluetooth LE devices with the given advertised names, so they're matched
+//! to protocols through the device configuration like real ones. Every command the protocol sends to
+//! the hardware comes out of the device's [MockDeviceHandle], so tests can check the exact bytes a
+//! client command turned into. Reads and notifications are answered with whatever the test queues
+//! up on the handle.
+//!
+//! Usually used through [ButtplugTestHarnessBuilder](crate::util::test_harness::ButtplugTestHarnessBuilder),
+//! which wires a server with mock devices to a client.
+
+pub mod mock_comm_manager;
+pub mod mock_hardware;
+
+use crate::{
+  core::message::Endpoint,
+  server::device::hardware::{HardwareCommand, HardwareEvent, HardwareReading, HardwareWriteCmd},
+};
+use dashmap::DashSet;
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+};
+use tokio::sync::{broadcast, mpsc};
+
+/// State shared between a mock device and the handle the test holds.
+struct MockDeviceState {
+  address: String,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  subscribed_endpoints: DashSet<Endpoint>,
+  reads: Mutex<VecDeque<HardwareReading>>,
+}
+
+impl MockDeviceState {
+  fn new(address: &str) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      address: address.to_owned(),
+      event_sender,
+      subscribed_endpoints: DashSet::new(),
+      reads: Mutex::new(VecDeque::new()),
+    }
+  }
+
+  /// Takes the oldest queued reading for an endpoint.
+  fn take_read(&self, endpoint: Endpoint) -> Option<HardwareReading> {
+    let mut reads = self
+      .reads
+      .lock()
+      .expect("Never poisoned, no panics while held");
+    let index = reads.iter().position(|x| *x.endpoint() == endpoint)?;
+    reads.remove(index)
+  }
+}
+
+/// Test side of a mock device, created by
+/// [MockCommunicationManagerBuilder::add_device](mock_comm_manager::MockCommunicationManagerBuilder::add_device).
+pub struct MockDeviceHandle {
+  name: String,
+  state: Arc<MockDeviceState>,
+  commands: mpsc::UnboundedReceiver<HardwareCommand>,
+}
+
+impl MockDeviceHandle {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn address(&self) -> &str {
+    &self.state.address
+  }
+
+  /// Waits for the next command sent to the hardware. Returns None once the device has been
+  /// disconnected and dropped by the server, or if it was never connected.
+  pub async fn next_command(&mut self) -> Option<HardwareCommand> {
+    self.commands.recv().await
+  }
+
+  /// Waits for the next write sent to the hardware, skipping subscribes and unsubscribes.
+  pub async fn next_write(&mut self) -> Option<HardwareWriteCmd> {
+    loop {
+      if let HardwareCommand::Write(write) = self.next_command().await? {
+        return Some(write);
+      }
+    }
+  }
+
+  /// Returns the next command sent to the hardware if there is one waiting, without waiting for
+  /// one.
+  pub fn try_next_command(&mut self) -> Option<HardwareCommand> {
+    self.commands.try_recv().ok()
+  }
+
+  /// Queues up data to answer a read from the endpoint with. Reads of endpoints with nothing queued
+  /// fail, so queue the reads a protocol does while initializing before scanning.
+  pub fn queue_read(&self, endpoint: Endpoint, data: &[u8]) {
+    self
+      .state
+      .reads
+      .lock()
+      .expect("Never poisoned, no panics while held")
+      .push_back(HardwareReading::new(endpoint, data));
+  }
+
+  /// Sends a notification from the endpoint. Returns false if the protocol hasn't subscribed to the
+  /// endpoint, in which case the notification is dropped like real hardware would.
+  pub fn notify(&self, endpoint: Endpoint, data: &[u8]) -> bool {
+    if !self.state.subscribed_endpoints.contains(&endpoint) {
+      return false;
+    }
+    let _ = self.state.event_sender.send(HardwareEvent::Notification(
+      self.state.address.clone(),
+      endpoint,
+      data.to_vec(),
+    ));
+    true
+  }
+
+  /// Disconnects the device, as if it was turned off or went out of range.
+  pub fn disconnect(&self) {
+    let _ = self
+      .state
+      .event_sender
+      .send(HardwareEvent::Disconnected(self.state.address.clone()));
+  }
+}
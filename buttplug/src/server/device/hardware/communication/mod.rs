@@ -6,11 +6,19 @@
 // for full license information.
 
 // Network DCMs work on all platforms
+#[cfg(feature = "buttplug-federation-manager")]
+pub mod buttplug_federation;
 #[cfg(feature = "lovense-connect-service-manager")]
 pub mod lovense_connect_service;
 #[cfg(feature = "websocket-server-manager")]
 pub mod websocket_server;
 
+// Simulated and mock devices work everywhere
+#[cfg(feature = "test-harness")]
+pub mod mock;
+#[cfg(feature = "simulation-manager")]
+pub mod simulated;
+
 // BTLEPlug works on anything not WASM
 #[cfg(all(
   feature = "btleplug-manager",
@@ -24,6 +32,10 @@ pub mod websocket_server;
 ))]
 pub mod btleplug;
 
+// WebBluetooth is WASM only
+#[cfg(all(feature = "webbluetooth-manager", target_arch = "wasm32"))]
+pub mod webbluetooth;
+
 // Lovense Dongles and Serial Ports work on all desktop platforms
 #[cfg(all(
   feature = "lovense-dongle-manager",
@@ -42,6 +54,14 @@ pub mod serialport;
 ))]
 pub mod hid;
 
+// GPIO PWM is linux only (sysfs)
+#[cfg(all(feature = "gpio-manager", target_os = "linux"))]
+pub mod gpio;
+
+// MIDI output is linux only (ALSA raw MIDI)
+#[cfg(all(feature = "midi-manager", target_os = "linux"))]
+pub mod midi;
+
 // XInput is windows only
 #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
 pub mod xinput;
@@ -72,6 +92,9 @@ pub enum HardwareCommunicationManagerEvent {
 }
 
 pub trait HardwareCommunicationManagerBuilder: Send {
+  /// Name of the manager this builder creates, the same as [HardwareCommunicationManager::name].
+  /// Lets the device manager decide whether to start a manager before building it.
+  fn name(&self) -> &'static str;
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
@@ -114,6 +137,15 @@ pub enum HardwareSpecificError {
   ))]
   #[error("Serial error: {0}")]
   SerialError(String),
+  #[cfg(all(feature = "gpio-manager", target_os = "linux"))]
+  #[error("GPIO error: {0}")]
+  GpioError(String),
+  #[cfg(all(feature = "midi-manager", target_os = "linux"))]
+  #[error("MIDI error: {0}")]
+  MidiError(String),
+  #[cfg(all(feature = "webbluetooth-manager", target_arch = "wasm32"))]
+  #[error("WebBluetooth error: {0}")]
+  WebBluetoothError(String),
 }
 
 #[async_trait]
@@ -26,6 +26,10 @@ use tokio::sync::mpsc::Sender;
 pub struct SerialPortCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for SerialPortCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "SerialPortCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
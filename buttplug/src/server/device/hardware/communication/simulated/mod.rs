@@ -0,0 +1,93 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Simulated devices, for running apps and their CI without any hardware.
+//!
+//! Simulated devices show up as Bluetooth LE devices with the given advertised names, so they're
+//! matched to protocols through the device configuration like real ones. Simulated hardware
+//! accepts any write and doesn't answer reads other than battery level, so use names of devices
+//! whose protocols don't need responses during initialization (the default simulated device is an
+//! Aneros Vivi, advertised as "Massage Demo").
+//!
+//! Each device can be scripted with a [SimulatedDeviceBehavior], adding latency to commands,
+//! disconnecting at random, and draining its battery. Devices that disconnect come back the next
+//! time the server scans, unless their battery is empty.
+
+pub mod simulated_comm_manager;
+pub mod simulated_hardware;
+
+use getset::{CopyGetters, Getters, Setters};
+use std::time::Duration;
+
+/// Scripted behavior for a simulated device.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct SimulatedDeviceBehavior {
+  /// Time every hardware command takes to run.
+  latency: Duration,
+  /// Average time between random disconnects. Never disconnects at random if None.
+  mean_time_between_disconnects: Option<Duration>,
+  /// Battery level the device starts at, from 0 to 100.
+  initial_battery_level: u8,
+  /// Battery level lost per minute. The device disconnects, and won't come back, once the battery
+  /// is empty.
+  battery_drain_per_minute: f64,
+}
+
+impl Default for SimulatedDeviceBehavior {
+  fn default() -> Self {
+    Self {
+      latency: Duration::from_millis(20),
+      mean_time_between_disconnects: None,
+      initial_battery_level: 100,
+      battery_drain_per_minute: 0.0,
+    }
+  }
+}
+
+/// A device for the simulated comm manager to find.
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct SimulatedDevice {
+  /// Bluetooth LE name the device advertises, used to match it to a protocol.
+  #[getset(get = "pub")]
+  name: String,
+  #[getset(get_copy = "pub")]
+  behavior: SimulatedDeviceBehavior,
+}
+
+impl SimulatedDevice {
+  pub fn new(name: &str, behavior: SimulatedDeviceBehavior) -> Self {
+    Self {
+      name: name.to_owned(),
+      behavior,
+    }
+  }
+}
+
+/// Devices to simulate, for
+/// [ButtplugServerBuilder::simulation](crate::server::ButtplugServerBuilder::simulation).
+#[derive(Debug, Clone, Getters)]
+#[getset(get = "pub")]
+pub struct SimulationSettings {
+  devices: Vec<SimulatedDevice>,
+}
+
+impl SimulationSettings {
+  pub fn new(devices: Vec<SimulatedDevice>) -> Self {
+    Self { devices }
+  }
+}
+
+impl Default for SimulationSettings {
+  /// A single simulated Aneros Vivi, with default behavior.
+  fn default() -> Self {
+    Self::new(vec![SimulatedDevice::new(
+      "Massage Demo",
+      SimulatedDeviceBehavior::default(),
+    )])
+  }
+}
@@ -0,0 +1,143 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  simulated_hardware::{SimulatedBattery, SimulatedHardwareConnector},
+  SimulatedDevice,
+  SimulationSettings,
+};
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+};
+use futures::future::{self, FutureExt};
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::Sender;
+
+pub struct SimulatedCommunicationManagerBuilder {
+  settings: SimulationSettings,
+}
+
+impl SimulatedCommunicationManagerBuilder {
+  pub fn new(settings: SimulationSettings) -> Self {
+    Self { settings }
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for SimulatedCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "SimulatedCommunicationManager"
+  }
+
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(SimulatedCommunicationManager::new(
+      sender,
+      self.settings.devices().clone(),
+    ))
+  }
+}
+
+struct SimulatedDeviceState {
+  device: SimulatedDevice,
+  address: String,
+  battery: Arc<SimulatedBattery>,
+}
+
+pub struct SimulatedCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<SimulatedDeviceState>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl SimulatedCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>, devices: Vec<SimulatedDevice>) -> Self {
+    let devices = devices
+      .into_iter()
+      .enumerate()
+      .map(|(index, device)| SimulatedDeviceState {
+        address: format!("simulated-{}", index),
+        battery: Arc::new(SimulatedBattery::new(&device.behavior())),
+        device,
+      })
+      .collect();
+    Self {
+      sender,
+      devices,
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+impl HardwareCommunicationManager for SimulatedCommunicationManager {
+  fn name(&self) -> &'static str {
+    "SimulatedCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    // Every scan finds all devices, so ones that disconnected come back. The device manager ignores
+    // devices that are still connected.
+    let events: Vec<_> = self
+      .devices
+      .iter()
+      .filter(|state| state.battery.level() > 0)
+      .map(|state| HardwareCommunicationManagerEvent::DeviceFound {
+        name: state.device.name().clone(),
+        address: state.address.clone(),
+        creator: Box::new(SimulatedHardwareConnector::new(
+          state.device.name(),
+          &state.address,
+          state.device.behavior(),
+          state.battery.clone(),
+        )),
+      })
+      .collect();
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      is_scanning.store(true, Ordering::SeqCst);
+      for event in events {
+        if sender.send(event).await.is_err() {
+          error!("Device manager disappeared, exiting.");
+          break;
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    // Scans finish on their own as soon as all devices are found.
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
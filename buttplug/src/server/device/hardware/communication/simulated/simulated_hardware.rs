@@ -0,0 +1,337 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::SimulatedDeviceBehavior;
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use instant::Instant;
+use rand::Rng;
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// Battery of a simulated device. Shared by every connection to the device, so it keeps draining
+/// across reconnects.
+#[derive(Debug)]
+pub(super) struct SimulatedBattery {
+  initial_level: u8,
+  drain_per_minute: f64,
+  started: Instant,
+}
+
+impl SimulatedBattery {
+  pub(super) fn new(behavior: &SimulatedDeviceBehavior) -> Self {
+    Self {
+      initial_level: behavior.initial_battery_level().min(100),
+      drain_per_minute: behavior.battery_drain_per_minute().max(0.0),
+      started: Instant::now(),
+    }
+  }
+
+  pub(super) fn level(&self) -> u8 {
+    let drained = self.drain_per_minute * self.started.elapsed().as_secs_f64() / 60.0;
+    (self.initial_level as f64 - drained).max(0.0).ceil() as u8
+  }
+
+  /// Time until the battery is empty, if it's draining.
+  fn time_until_empty(&self) -> Option<Duration> {
+    if self.drain_per_minute == 0.0 {
+      return None;
+    }
+    let total = Duration::from_secs_f64(self.initial_level as f64 / self.drain_per_minute * 60.0);
+    Some(total.saturating_sub(self.started.elapsed()))
+  }
+}
+
+pub struct SimulatedHardwareConnector {
+  name: String,
+  address: String,
+  behavior: SimulatedDeviceBehavior,
+  battery: Arc<SimulatedBattery>,
+}
+
+impl SimulatedHardwareConnector {
+  pub(super) fn new(
+    name: &str,
+    address: &str,
+    behavior: SimulatedDeviceBehavior,
+    battery: Arc<SimulatedBattery>,
+  ) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: address.to_owned(),
+      behavior,
+      battery,
+    }
+  }
+}
+
+impl Debug for SimulatedHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SimulatedHardwareConnector")
+      .field("name", &self.name)
+      .field("address", &self.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for SimulatedHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      &self.name,
+      &HashMap::new(),
+      &[],
+    ))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    sleep(self.behavior.latency()).await;
+    Ok(Box::new(SimulatedHardwareSpecializer {
+      name: self.name.clone(),
+      address: self.address.clone(),
+      behavior: self.behavior,
+      battery: self.battery.clone(),
+    }))
+  }
+}
+
+pub struct SimulatedHardwareSpecializer {
+  name: String,
+  address: String,
+  behavior: SimulatedDeviceBehavior,
+  battery: Arc<SimulatedBattery>,
+}
+
+#[async_trait]
+impl HardwareSpecializer for SimulatedHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    // Simulate whatever endpoints the protocol expects, plus a standard battery service.
+    let mut endpoints = vec![Endpoint::RxBLEBattery];
+    if let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+      .iter()
+      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    {
+      for endpoint_map in btle.services().values() {
+        endpoints.extend(
+          endpoint_map
+            .keys()
+            .filter(|e| **e != Endpoint::RxBLEBattery),
+        );
+      }
+    }
+    let hardware_internal =
+      SimulatedHardware::new(&self.address, self.behavior, self.battery.clone());
+    Ok(Hardware::new(
+      &self.name,
+      &self.address,
+      &endpoints,
+      Box::new(hardware_internal),
+    ))
+  }
+}
+
+/// Time until the next random disconnect, exponentially distributed so disconnects are equally
+/// likely at any moment.
+fn random_disconnect_delay(mean: Duration) -> Duration {
+  let sample: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+  mean.mul_f64(-sample.ln())
+}
+
+pub struct SimulatedHardware {
+  address: String,
+  latency: Duration,
+  connected: Arc<AtomicBool>,
+  battery: Arc<SimulatedBattery>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  cancellation_token: CancellationToken,
+}
+
+impl SimulatedHardware {
+  fn new(address: &str, behavior: SimulatedDeviceBehavior, battery: Arc<SimulatedBattery>) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let connected = Arc::new(AtomicBool::new(true));
+    let cancellation_token = CancellationToken::new();
+    let random_disconnect = behavior
+      .mean_time_between_disconnects()
+      .map(random_disconnect_delay);
+    let battery_empty = battery.time_until_empty();
+    // Whichever comes first, the random disconnect or the battery running out, drops the device.
+    if let Some(delay) = [random_disconnect, battery_empty]
+      .into_iter()
+      .flatten()
+      .min()
+    {
+      let address = address.to_owned();
+      let event_sender = event_sender.clone();
+      let connected = connected.clone();
+      let token = cancellation_token.child_token();
+      async_manager::spawn(async move {
+        select! {
+          _ = token.cancelled().fuse() => return,
+          _ = sleep(delay).fuse() => {}
+        }
+        info!("Simulated device {} disconnecting.", address);
+        connected.store(false, Ordering::SeqCst);
+        let _ = event_sender.send(HardwareEvent::Disconnected(address));
+      });
+    }
+    Self {
+      address: address.to_owned(),
+      latency: behavior.latency(),
+      connected,
+      battery,
+      event_sender,
+      cancellation_token,
+    }
+  }
+
+  /// Waits out the simulated latency, then fails if the device has disconnected in the meantime.
+  fn simulate_command(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let latency = self.latency;
+    let connected = self.connected.clone();
+    async move {
+      sleep(latency).await;
+      if connected.load(Ordering::SeqCst) {
+        Ok(())
+      } else {
+        Err(ButtplugDeviceError::DeviceNotConnected(
+          "Simulated device disconnected".to_owned(),
+        ))
+      }
+    }
+    .boxed()
+  }
+}
+
+impl Drop for SimulatedHardware {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+impl HardwareInternal for SimulatedHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.connected.store(false, Ordering::SeqCst);
+    self.cancellation_token.cancel();
+    let _ = self
+      .event_sender
+      .send(HardwareEvent::Disconnected(self.address.clone()));
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::RxBLEBattery {
+      return future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+        "Simulated devices only support battery reads".to_owned(),
+      )))
+      .boxed();
+    }
+    let command = self.simulate_command();
+    let battery = self.battery.clone();
+    async move {
+      command.await?;
+      Ok(HardwareReading::new(
+        Endpoint::RxBLEBattery,
+        &[battery.level()],
+      ))
+    }
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    trace!(
+      "Simulated device write to {}: {:?}",
+      msg.endpoint(),
+      msg.data()
+    );
+    self.simulate_command()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    // Simulated devices never send notifications, so there's nothing to set up.
+    self.simulate_command()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.simulate_command()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_simulated_battery_drain() {
+    let mut behavior = SimulatedDeviceBehavior::default();
+    assert!(SimulatedBattery::new(&behavior)
+      .time_until_empty()
+      .is_none());
+    behavior
+      .set_initial_battery_level(50)
+      .set_battery_drain_per_minute(60.0);
+    let battery = SimulatedBattery::new(&behavior);
+    assert_eq!(battery.level(), 50);
+    let until_empty = battery.time_until_empty().expect("Battery is draining");
+    assert!(until_empty <= Duration::from_secs(50) && until_empty > Duration::from_secs(49));
+  }
+
+  #[test]
+  fn test_random_disconnect_delay() {
+    let mean = Duration::from_secs(10);
+    let total: Duration = (0..1000).map(|_| random_disconnect_delay(mean)).sum();
+    // Loose bounds, this only checks the distribution is centered about right.
+    assert!(total > mean * 800 && total < mean * 1200);
+  }
+}
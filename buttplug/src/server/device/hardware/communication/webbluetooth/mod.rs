@@ -0,0 +1,14 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod webbluetooth_comm_manager;
+mod webbluetooth_hardware;
+pub use webbluetooth_comm_manager::{
+  WebBluetoothCommunicationManager,
+  WebBluetoothCommunicationManagerBuilder,
+};
+pub use webbluetooth_hardware::{WebBluetoothHardware, WebBluetoothHardwareConnector};
@@ -0,0 +1,219 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::webbluetooth_hardware::WebBluetoothHardwareConnector;
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::{
+    configuration::ProtocolCommunicationSpecifier,
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+    },
+  },
+  util::device_configuration::load_protocol_configs,
+};
+use futures::future::{self, FutureExt};
+use js_sys::JsString;
+use std::{collections::BTreeSet, sync::Arc};
+use tokio::sync::mpsc::Sender;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{BluetoothLeScanFilterInit, RequestDeviceOptions};
+
+/// Builds a [WebBluetoothCommunicationManager].
+///
+/// The browser will only show devices that match the filters passed to `requestDevice()`, and only
+/// allows access to services that were listed when the device was requested, so the manager needs
+/// to know about every Bluetooth LE protocol up front. By default, this comes from the device
+/// configuration built into the library. If the server is using a different or extended
+/// configuration, pass the same JSON here so user defined devices show up too.
+#[derive(Default)]
+pub struct WebBluetoothCommunicationManagerBuilder {
+  device_configuration_json: Option<String>,
+  user_device_configuration_json: Option<String>,
+}
+
+impl WebBluetoothCommunicationManagerBuilder {
+  pub fn device_configuration_json(mut self, config_json: Option<String>) -> Self {
+    self.device_configuration_json = config_json;
+    self
+  }
+
+  pub fn user_device_configuration_json(mut self, config_json: Option<String>) -> Self {
+    self.user_device_configuration_json = config_json;
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for WebBluetoothCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "WebBluetoothCommunicationManager"
+  }
+
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    let filters = match load_protocol_configs(
+      self.device_configuration_json.clone(),
+      self.user_device_configuration_json.clone(),
+      false,
+    )
+    .and_then(|mut builder| builder.finish())
+    {
+      Ok(dcm) => WebBluetoothFilters::new(dcm.protocol_device_configurations().values().flatten()),
+      Err(err) => {
+        error!(
+          "Cannot load device configuration for WebBluetooth filters, no devices will be found: {:?}",
+          err
+        );
+        WebBluetoothFilters::default()
+      }
+    };
+    Box::new(WebBluetoothCommunicationManager::new(sender, filters))
+  }
+}
+
+/// Names and services pulled out of the device configuration, for building `requestDevice()`
+/// options. Sorted sets, so that the chooser filters are built the same way every time.
+#[derive(Default, Debug)]
+struct WebBluetoothFilters {
+  names: BTreeSet<String>,
+  name_prefixes: BTreeSet<String>,
+  services: BTreeSet<String>,
+}
+
+impl WebBluetoothFilters {
+  fn new<'a>(specifiers: impl Iterator<Item = &'a ProtocolCommunicationSpecifier>) -> Self {
+    let mut filters = Self::default();
+    for specifier in specifiers {
+      let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier else {
+        continue;
+      };
+      for name in btle.names() {
+        if let Some(prefix) = name.strip_suffix('*') {
+          filters.name_prefixes.insert(prefix.to_owned());
+        } else {
+          filters.names.insert(name.clone());
+        }
+      }
+      for service in btle.services().keys() {
+        filters.services.insert(service.to_string());
+      }
+    }
+    filters
+  }
+
+  fn request_device_options(&self) -> RequestDeviceOptions {
+    let mut scan_filters = vec![];
+    for name in &self.names {
+      let filter = BluetoothLeScanFilterInit::new();
+      filter.set_name(name);
+      scan_filters.push(filter);
+    }
+    for prefix in &self.name_prefixes {
+      let filter = BluetoothLeScanFilterInit::new();
+      filter.set_name_prefix(prefix);
+      scan_filters.push(filter);
+    }
+    let services: Vec<JsString> = self
+      .services
+      .iter()
+      .map(|service| JsString::from(service.as_str()))
+      .collect();
+    let options = RequestDeviceOptions::new();
+    options.set_filters(&scan_filters);
+    options.set_optional_services(&services);
+    options
+  }
+}
+
+/// Device communication manager for browsers supporting the Web Bluetooth API.
+///
+/// Web Bluetooth has no background scanning. Each call to start scanning brings up the browser's
+/// device chooser, and the user picks (at most) one device from it. Browsers only allow this in
+/// response to a user action (clicking a button, etc), so scanning needs to be started from an
+/// event handler.
+pub struct WebBluetoothCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  filters: Arc<WebBluetoothFilters>,
+}
+
+impl WebBluetoothCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>, filters: WebBluetoothFilters) -> Self {
+    Self {
+      sender,
+      filters: Arc::new(filters),
+    }
+  }
+}
+
+impl HardwareCommunicationManager for WebBluetoothCommunicationManager {
+  fn name(&self) -> &'static str {
+    "WebBluetoothCommunicationManager"
+  }
+
+  fn can_scan(&self) -> bool {
+    web_sys::window()
+      .and_then(|window| window.navigator().bluetooth())
+      .is_some()
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    let sender = self.sender.clone();
+    let filters = self.filters.clone();
+    spawn_local(async move {
+      let Some(bluetooth) = web_sys::window().and_then(|window| window.navigator().bluetooth())
+      else {
+        error!("WebBluetooth is not supported in this browser.");
+        let _ = sender
+          .send(HardwareCommunicationManagerEvent::ScanningFinished)
+          .await;
+        return;
+      };
+      let options = filters.request_device_options();
+      match JsFuture::from(bluetooth.request_device(&options)).await {
+        Ok(device) => {
+          // Devices without names can't be matched against protocols, so there's nothing we can
+          // do with them.
+          if let Some(name) = device.name() {
+            let address = device.id();
+            info!("WebBluetooth device chosen: {} {}", name, address);
+            let creator = Box::new(WebBluetoothHardwareConnector::new(&name, device));
+            if sender
+              .send(HardwareCommunicationManagerEvent::DeviceFound {
+                name,
+                address,
+                creator,
+              })
+              .await
+              .is_err()
+            {
+              error!("Device manager receiver dropped, cannot send device found message.");
+              return;
+            }
+          }
+        }
+        // This is also what happens when the user cancels the chooser.
+        Err(err) => info!(
+          "WebBluetooth device request finished without device: {:?}",
+          err
+        ),
+      }
+      let _ = sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await;
+    });
+    future::ready(Ok(())).boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    // The chooser is controlled by the browser, we have no way to close it.
+    future::ready(Ok(())).boxed()
+  }
+}
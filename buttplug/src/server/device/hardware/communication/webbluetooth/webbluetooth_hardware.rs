@@ -0,0 +1,456 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      communication::HardwareSpecificError,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
+use js_sys::Uint8Array;
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{BluetoothDevice, BluetoothRemoteGattCharacteristic, Event};
+
+fn webbluetooth_error(err: JsValue) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::WebBluetoothError(format!(
+    "{:?}",
+    err
+  )))
+}
+
+/// Wrapper for JS handles, which are neither Send nor Sync.
+///
+/// wasm32-unknown-unknown is single threaded, so the handle can never actually be moved or shared
+/// across threads, but the hardware traits require Send + Sync since other platforms do need them.
+struct WebBluetoothDeviceHandle(BluetoothDevice);
+
+unsafe impl Send for WebBluetoothDeviceHandle {
+}
+unsafe impl Sync for WebBluetoothDeviceHandle {
+}
+
+pub struct WebBluetoothHardwareConnector {
+  name: String,
+  address: String,
+  device: Option<WebBluetoothDeviceHandle>,
+}
+
+impl WebBluetoothHardwareConnector {
+  pub(super) fn new(name: &str, device: BluetoothDevice) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: device.id(),
+      device: Some(WebBluetoothDeviceHandle(device)),
+    }
+  }
+}
+
+impl Debug for WebBluetoothHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("WebBluetoothHardwareConnector")
+      .field("name", &self.name)
+      .field("address", &self.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for WebBluetoothHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    // The browser chooser doesn't hand us advertisement data, so all we have to go on is the name.
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      &self.name,
+      &HashMap::new(),
+      &[],
+    ))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    let device = self.device.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError(format!(
+        "WebBluetooth device {} already connected",
+        self.name
+      ))
+    })?;
+    Ok(Box::new(WebBluetoothHardwareSpecializer {
+      name: self.name.clone(),
+      address: self.address.clone(),
+      device: Some(device),
+    }))
+  }
+}
+
+pub struct WebBluetoothHardwareSpecializer {
+  name: String,
+  address: String,
+  device: Option<WebBluetoothDeviceHandle>,
+}
+
+#[async_trait]
+impl HardwareSpecializer for WebBluetoothHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+      .iter()
+      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    else {
+      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+        "Can't find btle protocol specifier mapping for device {} {}",
+        self.name, self.address
+      )));
+    };
+    let device = self.device.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError(format!(
+        "WebBluetooth device {} already specialized",
+        self.name
+      ))
+    })?;
+    let (event_sender, _) = broadcast::channel(256);
+    let (command_sender, command_receiver) = mpsc::channel(256);
+    let (connected_sender, connected_receiver) = oneshot::channel();
+    // All access to the JS device object happens inside this task, so nothing that isn't Send is
+    // held across await points here.
+    spawn_local(run_webbluetooth_loop(
+      device.0,
+      btle.clone(),
+      event_sender.clone(),
+      command_receiver,
+      connected_sender,
+    ));
+    let endpoints = connected_receiver.await.map_err(|_| {
+      ButtplugDeviceError::DeviceConnectionError(
+        "WebBluetooth device task exited before connection finished".to_owned(),
+      )
+    })??;
+    Ok(Hardware::new(
+      &self.name,
+      &self.address,
+      &endpoints,
+      Box::new(WebBluetoothHardware {
+        event_sender,
+        command_sender,
+      }),
+    ))
+  }
+}
+
+enum WebBluetoothDeviceCommand {
+  Write(
+    HardwareWriteCmd,
+    oneshot::Sender<Result<(), ButtplugDeviceError>>,
+  ),
+  Read(
+    HardwareReadCmd,
+    oneshot::Sender<Result<HardwareReading, ButtplugDeviceError>>,
+  ),
+  Subscribe(
+    HardwareSubscribeCmd,
+    oneshot::Sender<Result<(), ButtplugDeviceError>>,
+  ),
+  Unsubscribe(
+    HardwareUnsubscribeCmd,
+    oneshot::Sender<Result<(), ButtplugDeviceError>>,
+  ),
+  Disconnect,
+}
+
+async fn connect_characteristics(
+  device: &BluetoothDevice,
+  btle: &BluetoothLESpecifier,
+) -> Result<HashMap<Endpoint, BluetoothRemoteGattCharacteristic>, ButtplugDeviceError> {
+  let gatt = device.gatt().ok_or_else(|| {
+    ButtplugDeviceError::DeviceConnectionError("WebBluetooth device has no GATT server".to_owned())
+  })?;
+  let server = JsFuture::from(gatt.connect())
+    .await
+    .map_err(webbluetooth_error)?;
+  let mut characteristics = HashMap::new();
+  for (service_uuid, service_endpoints) in btle.services() {
+    let service =
+      match JsFuture::from(server.get_primary_service_with_str(&service_uuid.to_string())).await {
+        Ok(service) => service,
+        Err(_) => {
+          debug!("Service {} not found on WebBluetooth device", service_uuid);
+          continue;
+        }
+      };
+    debug!("Found required service {}", service_uuid);
+    for (chr_name, chr_uuid) in service_endpoints.iter() {
+      match JsFuture::from(service.get_characteristic_with_str(&chr_uuid.to_string())).await {
+        Ok(chr) => {
+          debug!(
+            "Found characteristic {} for endpoint {}",
+            chr_uuid, *chr_name
+          );
+          characteristics.insert(*chr_name, chr);
+        }
+        Err(_) => error!(
+          "Characteristic {} ({}) not found, may cause issues in connection.",
+          chr_name, chr_uuid
+        ),
+      }
+    }
+  }
+  Ok(characteristics)
+}
+
+async fn run_webbluetooth_loop(
+  device: BluetoothDevice,
+  btle: BluetoothLESpecifier,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  mut command_receiver: mpsc::Receiver<WebBluetoothDeviceCommand>,
+  connected_sender: oneshot::Sender<Result<Vec<Endpoint>, ButtplugDeviceError>>,
+) {
+  let address = device.id();
+  let characteristics = match connect_characteristics(&device, &btle).await {
+    Ok(characteristics) => characteristics,
+    Err(err) => {
+      let _ = connected_sender.send(Err(err));
+      return;
+    }
+  };
+
+  // Closures need to live as long as the JS side may call them, so we hold them here and drop them
+  // when the loop exits (or the endpoint is unsubscribed).
+  let disconnect_sender = event_sender.clone();
+  let disconnect_address = address.clone();
+  let ondisconnected = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+    info!("WebBluetooth device {} disconnected", disconnect_address);
+    if disconnect_sender.receiver_count() != 0 {
+      let _ = disconnect_sender.send(HardwareEvent::Disconnected(disconnect_address.clone()));
+    }
+  });
+  device.set_ongattserverdisconnected(Some(ondisconnected.as_ref().unchecked_ref()));
+  let mut notification_callbacks = HashMap::new();
+
+  if connected_sender
+    .send(Ok(characteristics.keys().cloned().collect()))
+    .is_err()
+  {
+    return;
+  }
+
+  while let Some(command) = command_receiver.recv().await {
+    match command {
+      WebBluetoothDeviceCommand::Write(msg, reply) => {
+        let Some(chr) = characteristics.get(&msg.endpoint()).cloned() else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint())));
+          continue;
+        };
+        // Don't block the command loop on the write, otherwise a slow write-with-response will hold
+        // up everything behind it.
+        spawn_local(async move {
+          let data = Uint8Array::new_from_slice(msg.data());
+          let promise = if msg.write_with_response() {
+            chr.write_value_with_response_with_u8_array(&data)
+          } else {
+            chr.write_value_without_response_with_u8_array(&data)
+          };
+          let result = match promise {
+            Ok(promise) => JsFuture::from(promise)
+              .await
+              .map(|_| ())
+              .map_err(webbluetooth_error),
+            Err(err) => Err(webbluetooth_error(err)),
+          };
+          let _ = reply.send(result);
+        });
+      }
+      WebBluetoothDeviceCommand::Read(msg, reply) => {
+        let Some(chr) = characteristics.get(&msg.endpoint()).cloned() else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint())));
+          continue;
+        };
+        spawn_local(async move {
+          let result = JsFuture::from(chr.read_value())
+            .await
+            .map(|data_view| {
+              let data = Uint8Array::new_with_byte_offset_and_length(
+                &data_view.buffer(),
+                data_view.byte_offset() as u32,
+                data_view.byte_length() as u32,
+              );
+              HardwareReading::new(msg.endpoint(), &data.to_vec())
+            })
+            .map_err(webbluetooth_error);
+          let _ = reply.send(result);
+        });
+      }
+      WebBluetoothDeviceCommand::Subscribe(msg, reply) => {
+        let endpoint = msg.endpoint();
+        let Some(chr) = characteristics.get(&endpoint).cloned() else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(endpoint)));
+          continue;
+        };
+        if notification_callbacks.contains_key(&endpoint) {
+          let _ = reply.send(Ok(()));
+          continue;
+        }
+        let notification_sender = event_sender.clone();
+        let notification_address = address.clone();
+        let notification_chr = chr.clone();
+        let onchange = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+          let Some(data_view) = notification_chr.value() else {
+            return;
+          };
+          if notification_sender.receiver_count() == 0 {
+            return;
+          }
+          let data = Uint8Array::new_with_byte_offset_and_length(
+            &data_view.buffer(),
+            data_view.byte_offset() as u32,
+            data_view.byte_length() as u32,
+          );
+          let _ = notification_sender.send(HardwareEvent::Notification(
+            notification_address.clone(),
+            endpoint,
+            data.to_vec(),
+          ));
+        });
+        chr.set_oncharacteristicvaluechanged(Some(onchange.as_ref().unchecked_ref()));
+        notification_callbacks.insert(endpoint, onchange);
+        spawn_local(async move {
+          let result = JsFuture::from(chr.start_notifications())
+            .await
+            .map(|_| ())
+            .map_err(webbluetooth_error);
+          let _ = reply.send(result);
+        });
+      }
+      WebBluetoothDeviceCommand::Unsubscribe(msg, reply) => {
+        let endpoint = msg.endpoint();
+        let Some(chr) = characteristics.get(&endpoint).cloned() else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(endpoint)));
+          continue;
+        };
+        if notification_callbacks.remove(&endpoint).is_none() {
+          let _ = reply.send(Ok(()));
+          continue;
+        }
+        chr.set_oncharacteristicvaluechanged(None);
+        spawn_local(async move {
+          let result = JsFuture::from(chr.stop_notifications())
+            .await
+            .map(|_| ())
+            .map_err(webbluetooth_error);
+          let _ = reply.send(result);
+        });
+      }
+      WebBluetoothDeviceCommand::Disconnect => break,
+    }
+  }
+
+  // Either we were told to disconnect, or the hardware object was dropped.
+  for chr in characteristics.values() {
+    chr.set_oncharacteristicvaluechanged(None);
+  }
+  device.set_ongattserverdisconnected(None);
+  if let Some(gatt) = device.gatt() {
+    gatt.disconnect();
+  }
+  info!("Exiting WebBluetooth command loop for device {}", address);
+}
+
+pub struct WebBluetoothHardware {
+  event_sender: broadcast::Sender<HardwareEvent>,
+  command_sender: mpsc::Sender<WebBluetoothDeviceCommand>,
+}
+
+impl WebBluetoothHardware {
+  fn send_command<T: Send + 'static>(
+    &self,
+    command: impl FnOnce(oneshot::Sender<Result<T, ButtplugDeviceError>>) -> WebBluetoothDeviceCommand,
+  ) -> BoxFuture<'static, Result<T, ButtplugDeviceError>> {
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    let command = command(reply_sender);
+    let command_sender = self.command_sender.clone();
+    async move {
+      if command_sender.send(command).await.is_err() {
+        return Err(ButtplugDeviceError::DeviceNotConnected(
+          "WebBluetooth device has disconnected".to_owned(),
+        ));
+      }
+      reply_receiver.await.map_err(|_| {
+        ButtplugDeviceError::DeviceNotConnected("WebBluetooth device has disconnected".to_owned())
+      })?
+    }
+    .boxed()
+  }
+}
+
+impl HardwareInternal for WebBluetoothHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let command_sender = self.command_sender.clone();
+    async move {
+      // If the loop has already exited, we're already disconnected.
+      let _ = command_sender
+        .send(WebBluetoothDeviceCommand::Disconnect)
+        .await;
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let msg = *msg;
+    self.send_command(|reply| WebBluetoothDeviceCommand::Read(msg, reply))
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = msg.clone();
+    self.send_command(|reply| WebBluetoothDeviceCommand::Write(msg, reply))
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = *msg;
+    self.send_command(|reply| WebBluetoothDeviceCommand::Subscribe(msg, reply))
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = *msg;
+    self.send_command(|reply| WebBluetoothDeviceCommand::Unsubscribe(msg, reply))
+  }
+}
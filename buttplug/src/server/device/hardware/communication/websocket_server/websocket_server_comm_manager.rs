@@ -6,8 +6,14 @@
 // for full license information.
 
 use super::websocket_server_hardware::WebsocketServerHardwareConnector;
+#[cfg(feature = "websocket-tls")]
+use crate::util::certificate::SelfSignedCertificate;
 use crate::{
-  core::ButtplugResultFuture,
+  core::{
+    connector::transport::WebsocketServerAcceptor,
+    message::ClientDeviceMessageAttributes,
+    ButtplugResultFuture,
+  },
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
@@ -30,12 +36,53 @@ pub struct WebsocketServerDeviceCommManagerInitInfo {
   address: String,
   #[getset(get_copy = "pub")]
   version: u32,
+  /// Device name, for devices that know what they are (i.e. devices shared by another Buttplug
+  /// server).
+  #[getset(get = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  name: Option<String>,
+  /// Device features, for devices that can describe themselves. Used if the device configuration
+  /// has no attributes for the device.
+  #[getset(get = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  messages: Option<ClientDeviceMessageAttributes>,
+  /// UDP port the device listens on, for devices that want writes as UDP packets instead of
+  /// websocket messages. Wifi is lossy enough that a reliable stream stalls every write behind
+  /// retransmits, where a dropped actuator update is usually replaced by the next one before anyone
+  /// notices. Packets go to the address the websocket connected from, and are the write data
+  /// prefixed with a little endian u32 sequence number (starting at 0 and wrapping), so the device
+  /// can drop packets arriving out of order. Writes that ask for a response still go over the
+  /// websocket.
+  #[getset(get_copy = "pub")]
+  #[serde(rename = "udp-port", default, skip_serializing_if = "Option::is_none")]
+  udp_port: Option<u16>,
+}
+
+impl WebsocketServerDeviceCommManagerInitInfo {
+  pub fn new(
+    identifier: &str,
+    address: &str,
+    version: u32,
+    name: Option<String>,
+    messages: Option<ClientDeviceMessageAttributes>,
+  ) -> Self {
+    Self {
+      identifier: identifier.to_owned(),
+      address: address.to_owned(),
+      version,
+      name,
+      messages,
+      udp_port: None,
+    }
+  }
 }
 
 #[derive(Clone)]
 pub struct WebsocketServerDeviceCommunicationManagerBuilder {
   listen_on_all_interfaces: bool,
   server_port: u16,
+  #[cfg(feature = "websocket-tls")]
+  tls_certificate: Option<SelfSignedCertificate>,
 }
 
 impl Default for WebsocketServerDeviceCommunicationManagerBuilder {
@@ -43,6 +90,8 @@ impl Default for WebsocketServerDeviceCommunicationManagerBuilder {
     Self {
       listen_on_all_interfaces: false,
       server_port: 54817,
+      #[cfg(feature = "websocket-tls")]
+      tls_certificate: None,
     }
   }
 }
@@ -57,17 +106,40 @@ impl WebsocketServerDeviceCommunicationManagerBuilder {
     self.server_port = port;
     self
   }
+
+  /// Accept devices over secure websockets (`wss://`) using the given certificate.
+  #[cfg(feature = "websocket-tls")]
+  pub fn tls_certificate(mut self, certificate: SelfSignedCertificate) -> Self {
+    self.tls_certificate = Some(certificate);
+    self
+  }
 }
 
 impl HardwareCommunicationManagerBuilder for WebsocketServerDeviceCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "WebsocketServerCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
+    #[cfg(feature = "websocket-tls")]
+    let acceptor =
+      WebsocketServerAcceptor::new(self.tls_certificate.as_ref()).unwrap_or_else(|err| {
+        error!(
+          "Cannot use certificate for websocket device manager, not using TLS: {}",
+          err
+        );
+        WebsocketServerAcceptor::default()
+      });
+    #[cfg(not(feature = "websocket-tls"))]
+    let acceptor = WebsocketServerAcceptor::default();
     Box::new(WebsocketServerDeviceCommunicationManager::new(
       sender,
       self.server_port,
       self.listen_on_all_interfaces,
+      acceptor,
     ))
   }
 }
@@ -81,6 +153,7 @@ impl WebsocketServerDeviceCommunicationManager {
     sender: Sender<HardwareCommunicationManagerEvent>,
     port: u16,
     listen_on_all_interfaces: bool,
+    acceptor: WebsocketServerAcceptor,
   ) -> Self {
     trace!("Websocket server port created.");
     let server_cancellation_token = CancellationToken::new();
@@ -108,15 +181,14 @@ impl WebsocketServerDeviceCommunicationManager {
       loop {
         select! {
           listener_result = listener.accept().fuse() => {
-            let stream = if let Ok((stream, _)) = listener_result {
-              stream
+            let (stream, peer_address) = if let Ok(accepted) = listener_result {
+              accepted
             } else {
               error!("Cannot bind websocket server comm manager to address {}.", addr);
               return;
             };
             info!("Got connection");
-            let ws_fut = tokio_tungstenite::accept_async(stream);
-            let mut ws_stream = match ws_fut.await {
+            let mut ws_stream = match acceptor.accept(stream).await {
               Ok(ws_stream) => ws_stream,
               Err(err) => {
                 error!("Cannot accept socket: {}", err);
@@ -145,11 +217,15 @@ impl WebsocketServerDeviceCommunicationManager {
                   };
                 if sender_clone
                   .send(HardwareCommunicationManagerEvent::DeviceFound {
-                    name: format!("Websocket Device {}", info_packet.identifier),
+                    name: info_packet
+                      .name
+                      .clone()
+                      .unwrap_or_else(|| format!("Websocket Device {}", info_packet.identifier)),
                     address: info_packet.address.clone(),
                     creator: Box::new(WebsocketServerHardwareConnector::new(
                       info_packet,
                       ws_stream,
+                      peer_address,
                     )),
                   })
                   .await
@@ -7,9 +7,18 @@
 
 use super::websocket_server_comm_manager::WebsocketServerDeviceCommManagerInitInfo;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    connector::transport::ButtplugWebsocketServerStream,
+    errors::ButtplugDeviceError,
+    message::Endpoint,
+  },
   server::device::{
-    configuration::{ProtocolCommunicationSpecifier, WebsocketSpecifier},
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolCommunicationSpecifier,
+      ProtocolDeviceAttributes,
+      WebsocketSpecifier,
+    },
     hardware::{
       GenericHardwareSpecializer,
       Hardware,
@@ -35,14 +44,15 @@ use futures::{
 };
 use std::{
   fmt::{self, Debug},
+  net::{Ipv4Addr, Ipv6Addr, SocketAddr},
   sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU32, Ordering},
     Arc,
   },
   time::Duration,
 };
 use tokio::{
-  net::TcpStream,
+  net::UdpSocket,
   sync::{
     broadcast,
     mpsc::{channel, Receiver, Sender},
@@ -55,7 +65,7 @@ use tokio_util::sync::CancellationToken;
 async fn run_connection_loop(
   address: &str,
   event_sender: broadcast::Sender<HardwareEvent>,
-  ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
+  ws_stream: tokio_tungstenite::WebSocketStream<ButtplugWebsocketServerStream>,
   mut request_receiver: Receiver<Vec<u8>>,
   response_sender: broadcast::Sender<Vec<u8>>,
 ) {
@@ -155,12 +165,44 @@ impl Debug for WebsocketServerHardwareConnector {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct("WebsocketServerHardwareConnector")
       .field("info", &self.info)
+      .field("peer_address", &self.peer_address)
       .finish()
   }
 }
 
+/// UDP output for devices that asked for it in their info packet.
+pub struct UdpDeviceOutput {
+  socket: UdpSocket,
+  sequence: AtomicU32,
+}
+
+impl UdpDeviceOutput {
+  async fn connect(device_address: SocketAddr) -> std::io::Result<Self> {
+    let local_address: SocketAddr = if device_address.is_ipv4() {
+      (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+      (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local_address).await?;
+    socket.connect(device_address).await?;
+    Ok(Self {
+      socket,
+      sequence: AtomicU32::new(0),
+    })
+  }
+
+  async fn send(&self, data: &[u8]) -> std::io::Result<()> {
+    let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+    let mut packet = Vec::with_capacity(data.len() + 4);
+    packet.extend_from_slice(&sequence.to_le_bytes());
+    packet.extend_from_slice(data);
+    self.socket.send(&packet).await.map(|_| ())
+  }
+}
+
 pub struct WebsocketServerHardwareConnector {
   info: WebsocketServerDeviceCommManagerInitInfo,
+  peer_address: SocketAddr,
   outgoing_sender: Sender<Vec<u8>>,
   incoming_broadcaster: broadcast::Sender<Vec<u8>>,
   device_event_sender: broadcast::Sender<HardwareEvent>,
@@ -169,7 +211,8 @@ pub struct WebsocketServerHardwareConnector {
 impl WebsocketServerHardwareConnector {
   pub fn new(
     info: WebsocketServerDeviceCommManagerInitInfo,
-    ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
+    ws_stream: tokio_tungstenite::WebSocketStream<ButtplugWebsocketServerStream>,
+    peer_address: SocketAddr,
   ) -> Self {
     let (outgoing_sender, outgoing_receiver) = channel(256);
     let (incoming_broadcaster, _) = broadcast::channel(256);
@@ -189,6 +232,7 @@ impl WebsocketServerHardwareConnector {
     });
     Self {
       info,
+      peer_address,
       outgoing_sender,
       incoming_broadcaster,
       device_event_sender,
@@ -206,18 +250,46 @@ impl HardwareConnector for WebsocketServerHardwareConnector {
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    let udp_output = if let Some(udp_port) = self.info.udp_port() {
+      let device_address = SocketAddr::new(self.peer_address.ip(), udp_port);
+      let output = UdpDeviceOutput::connect(device_address)
+        .await
+        .map_err(|err| {
+          ButtplugDeviceError::DeviceConnectionError(format!(
+            "Cannot set up UDP output to {}: {}",
+            device_address, err
+          ))
+        })?;
+      info!(
+        "Sending writes to websocket device as UDP packets to {}",
+        device_address
+      );
+      Some(Arc::new(output))
+    } else {
+      None
+    };
     let hardware_internal = WebsocketServerHardware::new(
       self.device_event_sender.clone(),
       self.info.clone(),
       self.outgoing_sender.clone(),
       self.incoming_broadcaster.clone(),
+      udp_output,
     );
-    let hardware = Hardware::new(
+    let mut hardware = Hardware::new(
       self.info.identifier(),
       self.info.address(),
       &[Endpoint::Rx, Endpoint::Tx],
       Box::new(hardware_internal),
     );
+    if let Some(messages) = self.info.messages() {
+      hardware.set_device_attributes(ProtocolDeviceAttributes::new(
+        ProtocolAttributesType::Default,
+        self.info.name().clone(),
+        None,
+        messages.clone().into(),
+        None,
+      ));
+    }
     Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
   }
 }
@@ -230,6 +302,7 @@ pub struct WebsocketServerHardware {
   outgoing_sender: Sender<Vec<u8>>,
   incoming_broadcaster: broadcast::Sender<Vec<u8>>,
   device_event_sender: broadcast::Sender<HardwareEvent>,
+  udp_output: Option<Arc<UdpDeviceOutput>>,
 }
 
 impl WebsocketServerHardware {
@@ -238,6 +311,7 @@ impl WebsocketServerHardware {
     info: WebsocketServerDeviceCommManagerInitInfo,
     outgoing_sender: Sender<Vec<u8>>,
     incoming_broadcaster: broadcast::Sender<Vec<u8>>,
+    udp_output: Option<Arc<UdpDeviceOutput>>,
   ) -> Self {
     Self {
       connected: Arc::new(AtomicBool::new(true)),
@@ -245,6 +319,7 @@ impl WebsocketServerHardware {
       outgoing_sender,
       incoming_broadcaster,
       device_event_sender,
+      udp_output,
       subscribed: Arc::new(AtomicBool::new(false)),
       subscribe_token: Arc::new(Mutex::new(None)),
     }
@@ -279,8 +354,20 @@ impl HardwareInternal for WebsocketServerHardware {
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    let sender = self.outgoing_sender.clone();
     let data = msg.data.clone();
+    if let (Some(udp_output), false) = (&self.udp_output, msg.write_with_response()) {
+      let udp_output = udp_output.clone();
+      return async move {
+        udp_output.send(&data).await.map_err(|err| {
+          ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Could not write value to websocket device over UDP: {}",
+            err
+          ))
+        })
+      }
+      .boxed();
+    }
+    let sender = self.outgoing_sender.clone();
     // TODO Should check endpoint validity
     async move {
       sender.send(data).await.map_err(|err| {
@@ -35,6 +35,10 @@ pub enum XInputControllerIndex {
 pub struct XInputDeviceCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for XInputDeviceCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "XInputDeviceCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: mpsc::Sender<HardwareCommunicationManagerEvent>,
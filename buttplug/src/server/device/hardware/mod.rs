@@ -1,21 +1,64 @@
 pub mod communication;
 
-use std::{fmt::Debug, sync::Arc, time::Duration};
+use std::{fmt::Debug, sync::Mutex, time::Duration};
 
 use crate::{
   core::{
     errors::ButtplugDeviceError,
     message::{Endpoint, RawReadCmd, RawReading, RawSubscribeCmd, RawUnsubscribeCmd, RawWriteCmd},
   },
-  server::device::configuration::ProtocolCommunicationSpecifier,
+  server::device::configuration::{ProtocolCommunicationSpecifier, ProtocolDeviceAttributes},
 };
 use async_trait::async_trait;
 use futures::future::BoxFuture;
-use futures_util::FutureExt;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 use instant::Instant;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::broadcast;
+
+/// Information a device reports about itself through a standard service (i.e. the BLE Device
+/// Information Service), read while specializing the hardware.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Getters, Setters)]
+#[getset(get = "pub", set = "pub")]
+pub struct DeviceInformation {
+  manufacturer_name: Option<String>,
+  model_number: Option<String>,
+  serial_number: Option<String>,
+}
+
+impl DeviceInformation {
+  /// True if the device didn't report anything.
+  pub fn is_empty(&self) -> bool {
+    self.manufacturer_name.is_none() && self.model_number.is_none() && self.serial_number.is_none()
+  }
+
+  /// Configuration identifiers to try for the device, most specific first. White-label hardware
+  /// often reuses model numbers across manufacturers, so a "Manufacturer/Model" identifier is
+  /// checked before the bare model number.
+  pub fn identifiers(&self) -> Vec<String> {
+    let mut identifiers = vec![];
+    if let Some(model) = &self.model_number {
+      if let Some(manufacturer) = &self.manufacturer_name {
+        identifiers.push(format!("{}/{}", manufacturer, model));
+      }
+      identifiers.push(model.clone());
+    }
+    identifiers
+  }
+}
+
+/// Parses a string value from a device information characteristic, with surrounding NULs and
+/// whitespace trimmed. Returns None if the string is empty.
+pub fn parse_device_information_string(data: &[u8]) -> Option<String> {
+  let value = String::from_utf8_lossy(data)
+    .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+    .to_owned();
+  if value.is_empty() {
+    None
+  } else {
+    Some(value)
+  }
+}
 
 /// Parameters for reading data from a [Hardware](crate::device::Hardware) endpoint
 ///
@@ -233,7 +276,7 @@ pub enum HardwareEvent {
 /// HardwareInternal, which handles all of the actual hardware communication. However, the struct
 /// also needs to carry around identifying information, so we wrap it in this type instead of
 /// requiring that all implementors of deal with name/address/endpoint accessors.
-#[derive(CopyGetters)]
+#[derive(CopyGetters, Getters)]
 pub struct Hardware {
   /// Device name
   name: String,
@@ -246,7 +289,18 @@ pub struct Hardware {
   /// Requires a keepalive signal to be sent by the Server Device class
   #[getset(get_copy = "pub")]
   requires_keepalive: bool,
-  last_write_time: Arc<RwLock<Instant>>,
+  /// Time of the last write, only tracked if the device requires keepalive. Updated when the write
+  /// is issued, so writes can hand back the implementation's future without wrapping it.
+  last_write_time: Mutex<Instant>,
+  /// Attributes reported by the hardware itself, for devices that describe their own features (i.e.
+  /// devices bridged from another Buttplug server). Only used if the device configuration has no
+  /// attributes for the device.
+  #[getset(get = "pub")]
+  device_attributes: Option<ProtocolDeviceAttributes>,
+  /// Manufacturer, model and serial number the device reported while being specialized, if it
+  /// has a standard way to report them.
+  #[getset(get = "pub")]
+  device_information: DeviceInformation,
 }
 
 impl Hardware {
@@ -262,18 +316,33 @@ impl Hardware {
       endpoints: endpoints.into(),
       internal_impl,
       requires_keepalive: false,
-      last_write_time: Arc::new(RwLock::new(Instant::now())),
+      last_write_time: Mutex::new(Instant::now()),
+      device_attributes: None,
+      device_information: DeviceInformation::default(),
     }
   }
 
   pub async fn time_since_last_write(&self) -> Duration {
-    Instant::now().duration_since(*self.last_write_time.read().await)
+    Instant::now().duration_since(
+      *self
+        .last_write_time
+        .lock()
+        .expect("Lock is never held across a panic"),
+    )
   }
 
   pub fn set_requires_keepalive(&mut self) {
     self.requires_keepalive = true;
   }
 
+  pub fn set_device_attributes(&mut self, attributes: ProtocolDeviceAttributes) {
+    self.device_attributes = Some(attributes);
+  }
+
+  pub fn set_device_information(&mut self, information: DeviceInformation) {
+    self.device_information = information;
+  }
+
   /// Returns the device name
   pub fn name(&self) -> &str {
     &self.name
@@ -326,17 +395,13 @@ impl Hardware {
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    let write_fut = self.internal_impl.write_value(msg);
     if self.requires_keepalive {
-      let last_write_time = self.last_write_time.clone();
-      async move {
-        *last_write_time.write().await = Instant::now();
-        write_fut.await
-      }
-      .boxed()
-    } else {
-      write_fut
+      *self
+        .last_write_time
+        .lock()
+        .expect("Lock is never held across a panic") = Instant::now();
     }
+    self.internal_impl.write_value(msg)
   }
 
   /// Subscribe to a device endpoint, if it exists
@@ -362,6 +427,11 @@ impl Hardware {
 /// [DeviceCommunicationManager](crate::server::device::communication_manager::DeviceCommunicationManager) modules
 /// to represent and communicate with devices. It provides an abstract way to represent devices
 /// without having to consider what type of communication bus they may be using.
+///
+/// Methods return boxed futures as [Hardware] stores implementations as trait objects, and each
+/// bus returns its own future type. An associated future type (or `async fn`) would stop the trait
+/// being object safe, making [Hardware] and everything holding it generic over the bus. The
+/// [Hardware] wrappers pass these futures through as is, so each command costs one allocation.
 pub trait HardwareInternal: Sync + Send {
   /// Disconnect from the device (if it is connected)
   fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>>;
@@ -59,7 +59,8 @@
 //! - When the server receives a StartScanning message, all comm managers start looking for devices.
 //!   Strategies for scanning can vary between [DeviceCommunicationManager]s, either using long term
 //!   scans (bluetooth) or repeated timed scans (USB, HID, XInput, etc... which check their
-//!   respective busses once per second) for new devices.
+//!   respective busses once per second) for new devices. If background scanning is enabled, comm
+//!   managers also scan periodically without being asked to.
 //! - For each device that is found in any [DeviceCommunicationManager], we emit a DeviceFound event
 //!   with that device's identifying information. This information is sent to the
 //!   [DeviceConfigurationManager], in order to make sure we can connect (we won't try to connect to
@@ -96,11 +97,33 @@
 //!
 
 pub mod configuration;
+pub mod dfu;
 pub mod hardware;
 pub mod protocol;
 pub mod server_device;
+mod server_device_battery_monitor;
+mod server_device_command_queue;
 mod server_device_manager;
 mod server_device_manager_event_loop;
+mod server_device_motion_planner;
+mod server_device_self_test;
 
 pub use server_device::{ServerDevice, ServerDeviceEvent, ServerDeviceIdentifier};
-pub use server_device_manager::{ServerDeviceManager, ServerDeviceManagerBuilder};
+pub use server_device_command_queue::{
+  DeviceCommandOverflowPolicy,
+  DeviceCommandQueueSettings,
+  DeviceCommandStatistics,
+  STOP_PREEMPTION_GRACE,
+};
+pub(crate) use server_device_manager::SessionControl;
+pub use server_device_manager::{
+  ServerDeviceManager,
+  ServerDeviceManagerBuilder,
+  UnmatchedDevice,
+  DEFAULT_DEVICE_INITIALIZATION_CONCURRENCY,
+  DEFAULT_PROTOCOL_INITIALIZATION_RETRIES,
+  DEFAULT_PROTOCOL_INITIALIZATION_RETRY_DELAY,
+  DEFAULT_PROTOCOL_INITIALIZATION_TIMEOUT,
+  DEVICE_STOP_TIMEOUT,
+  MAX_DEVICE_METADATA_SIZE,
+};
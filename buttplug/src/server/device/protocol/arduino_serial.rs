@@ -0,0 +1,156 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Generic serial protocol for Arduino hobby builds.
+//!
+//! Lines are space separated commands, terminated with `\n` (a trailing `\r` is ignored). Lines the
+//! host doesn't recognize are ignored.
+//!
+//! Host to device:
+//!
+//! - `hello`: Device replies with `hello <identifier>`. The identifier is used to look up device
+//!   configuration, so a build can be given a name and features from a user device config without
+//!   any protocol code. Must be answered for the device to connect.
+//! - `caps`: Device replies with `caps <channel count>`. Only sent if the identifier isn't
+//!   configured, in which case every channel is a Vibrate feature with values from 0 to 255 (the
+//!   range of analogWrite).
+//! - `set <channel> <value>`: Set a channel. No reply.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerDeviceMessageAttributes,
+      ServerDeviceMessageAttributesBuilder,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{Hardware, HardwareCommand, HardwareSubscribeCmd},
+    protocol::{
+      text_line_helper::{text_line_request, text_line_write_cmd},
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::{ops::RangeInclusive, sync::Arc};
+
+pub mod setup {
+  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  #[derive(Default)]
+  pub struct ArduinoSerialIdentifierFactory {}
+
+  impl ProtocolIdentifierFactory for ArduinoSerialIdentifierFactory {
+    fn identifier(&self) -> &str {
+      "arduino-serial"
+    }
+
+    fn create(&self) -> Box<dyn ProtocolIdentifier> {
+      Box::new(super::ArduinoSerialIdentifier::default())
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct ArduinoSerialIdentifier {}
+
+#[async_trait]
+impl ProtocolIdentifier for ArduinoSerialIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+      .await?;
+    let identifier = text_line_request("arduino-serial", &hardware, "hello", |line| match line {
+      ["hello", identifier] => Some(identifier.to_string()),
+      _ => None,
+    })
+    .await?;
+    info!("Arduino serial device identified as {}", identifier);
+    Ok((
+      ServerDeviceIdentifier::new(
+        hardware.address(),
+        "arduino-serial",
+        &ProtocolAttributesType::Identifier(identifier),
+      ),
+      Box::new(ArduinoSerialInitializer::default()),
+    ))
+  }
+}
+
+#[derive(Default)]
+pub struct ArduinoSerialInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for ArduinoSerialInitializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    Ok(Arc::new(ArduinoSerial::default()))
+  }
+
+  async fn probe_attributes(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Option<ServerDeviceMessageAttributes> {
+    let channel_count = text_line_request("arduino-serial", &hardware, "caps", |line| match line {
+      ["caps", count] => count.parse::<u32>().ok(),
+      _ => None,
+    })
+    .await
+    .map_err(|err| warn!("Cannot get Arduino serial device capabilities: {:?}", err))
+    .ok()?;
+    if channel_count == 0 {
+      warn!("Arduino serial device reported no channels, using protocol defaults.");
+      return None;
+    }
+    let channels: Vec<ServerGenericDeviceMessageAttributes> = (0..channel_count)
+      .map(|index| {
+        ServerGenericDeviceMessageAttributes::new(
+          &format!("Channel {}", index),
+          &RangeInclusive::new(0, 255),
+          ActuatorType::Vibrate,
+        )
+      })
+      .collect();
+    let mut builder = ServerDeviceMessageAttributesBuilder::default();
+    builder.scalar_cmd(&channels);
+    Some(builder.finish())
+  }
+}
+
+#[derive(Default)]
+pub struct ArduinoSerial {}
+
+impl ProtocolHandler for ArduinoSerial {
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(
+      commands
+        .iter()
+        .enumerate()
+        .filter_map(|(index, command)| {
+          command
+            .map(|(_, scalar)| text_line_write_cmd(&format!("set {} {}", index, scalar)).into())
+        })
+        .collect(),
+    )
+  }
+}
@@ -0,0 +1,107 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Tracks the last value sent to each actuator of a device, so identical values aren't resent.
+//!
+//! Some of our communication busses are REALLY slow, so sending a vibration speed the device
+//! already has is a waste of bandwidth. The [GenericCommandManager][super::generic_command_manager::GenericCommandManager]
+//! runs all generic actuator commands through this filter before handing them to protocols, so
+//! protocols get duplicate suppression without having to implement it themselves.
+
+use crate::core::message::ActuatorType;
+use dashmap::DashMap;
+
+#[derive(Debug, Clone, Copy)]
+struct SentValue<V> {
+  value: V,
+  /// True if we can no longer be sure the device has this value, i.e. because the command that
+  /// carried it was dropped before reaching the hardware.
+  stale: bool,
+}
+
+/// Last values sent to actuators, keyed by actuator index and type.
+#[derive(Debug)]
+pub struct DuplicateValueFilter<V> {
+  sent_values: DashMap<(u32, ActuatorType), SentValue<V>>,
+}
+
+impl<V> Default for DuplicateValueFilter<V> {
+  fn default() -> Self {
+    Self {
+      sent_values: DashMap::new(),
+    }
+  }
+}
+
+impl<V> DuplicateValueFilter<V>
+where
+  V: Copy + PartialEq,
+{
+  /// Record a value for an actuator. Returns true if the value needs to be sent, meaning it's
+  /// different from the last value, or we don't know what the device currently has.
+  pub fn update(&self, index: u32, actuator: ActuatorType, value: V) -> bool {
+    let new_value = SentValue {
+      value,
+      stale: false,
+    };
+    match self.sent_values.insert((index, actuator), new_value) {
+      Some(old_value) => old_value.stale || old_value.value != value,
+      None => true,
+    }
+  }
+
+  /// Record a value for an actuator without marking it as needing to be sent, if we don't already
+  /// have a value for the actuator.
+  pub fn assume(&self, index: u32, actuator: ActuatorType, value: V) {
+    self
+      .sent_values
+      .entry((index, actuator))
+      .or_insert(SentValue {
+        value,
+        stale: false,
+      });
+  }
+
+  /// Last value recorded for an actuator, whether or not it's stale.
+  pub fn value(&self, index: u32, actuator: ActuatorType) -> Option<V> {
+    self
+      .sent_values
+      .get(&(index, actuator))
+      .map(|sent_value| sent_value.value)
+  }
+
+  /// Mark all recorded values as stale, so they'll be resent on the next update. Recorded values
+  /// are still available via [DuplicateValueFilter::value], for protocols that need to send full
+  /// command sets.
+  pub fn invalidate(&self) {
+    self
+      .sent_values
+      .iter_mut()
+      .for_each(|mut sent_value| sent_value.stale = true);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_duplicate_value_filter() {
+    let filter = DuplicateValueFilter::default();
+    assert!(filter.update(0, ActuatorType::Vibrate, 10u32));
+    assert!(!filter.update(0, ActuatorType::Vibrate, 10));
+    // Same index with a different actuator type is a different actuator.
+    assert!(filter.update(0, ActuatorType::Oscillate, 10));
+    assert!(filter.update(0, ActuatorType::Vibrate, 5));
+    filter.assume(1, ActuatorType::Vibrate, 0);
+    assert!(!filter.update(1, ActuatorType::Vibrate, 0));
+    filter.invalidate();
+    assert_eq!(filter.value(0, ActuatorType::Vibrate), Some(5));
+    assert!(filter.update(0, ActuatorType::Vibrate, 5));
+    assert!(!filter.update(0, ActuatorType::Vibrate, 5));
+  }
+}
@@ -0,0 +1,436 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Reference protocol for ESP32 based DIY devices.
+//!
+//! This is a line based text protocol, so the same firmware can be talked to over USB serial, a
+//! websocket connection to the websocket server device comm manager (usually over wifi), or a BLE
+//! UART service. Every line is a space separated command, terminated with `\n` (a trailing `\r` is
+//! ignored). Lines the host doesn't recognize are ignored, so firmware is free to print debug
+//! output.
+//!
+//! Host to device:
+//!
+//! - `HELLO`: Device replies with `HELLO <model> <firmware version>`. The model is used to look up
+//!   device configuration, so builds that are in a user device config get those features instead
+//!   of announcing their own. Must be answered for the device to connect.
+//! - `CAPS`: Device replies with `CAPS <channels> <sensors>`. Channels are a comma separated list
+//!   of `<ActuatorType>:<max value>`, one per PWM channel, in channel order (i.e.
+//!   `Vibrate:255,Rotate:100`). Sensors are a comma separated list of `<SensorType>:<max value>`,
+//!   in sensor order (i.e. `Battery:100,Pressure:4095`). Either list can be `-` if the device has
+//!   none. Only sent if the model isn't in the device configuration.
+//! - `PWM <channel> <value>`: Set a PWM channel, with the value from 0 to the channel's max. No
+//!   reply.
+//! - `READ <sensor>`: Device replies with `SENSOR <sensor> <value>`.
+//! - `SUB <sensor>`/`UNSUB <sensor>`: Start/stop reporting a sensor. While subscribed, the device
+//!   sends `SENSOR <sensor> <value>` lines whenever it wants to report a new value. No reply.
+//!
+//! Channel and sensor indexes are the indexes of the ScalarCmd and sensor features.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      DeviceVersion,
+      Endpoint,
+      SensorDeviceMessageAttributes,
+      SensorReading,
+      SensorType,
+    },
+  },
+  server::device::{
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerDeviceMessageAttributes,
+      ServerDeviceMessageAttributesBuilder,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{Hardware, HardwareCommand, HardwareEvent, HardwareSubscribeCmd},
+    protocol::{
+      text_line_helper::{text_line_request, text_line_write_cmd, LineBuffer},
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+};
+use async_trait::async_trait;
+use dashmap::DashSet;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
+};
+use std::{
+  ops::RangeInclusive,
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+pub mod setup {
+  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  #[derive(Default)]
+  pub struct Esp32DiyIdentifierFactory {}
+
+  impl ProtocolIdentifierFactory for Esp32DiyIdentifierFactory {
+    fn identifier(&self) -> &str {
+      "esp32-diy"
+    }
+
+    fn create(&self) -> Box<dyn ProtocolIdentifier> {
+      Box::new(super::Esp32DiyIdentifier::default())
+    }
+
+    // Channels are plain PWM outputs, so heating elements are driven like anything else.
+    fn supports_temperature_actuators(&self) -> bool {
+      true
+    }
+  }
+}
+
+fn parse_actuator_type(name: &str) -> Option<ActuatorType> {
+  Some(match name {
+    "Vibrate" => ActuatorType::Vibrate,
+    "Rotate" => ActuatorType::Rotate,
+    "Oscillate" => ActuatorType::Oscillate,
+    "Constrict" => ActuatorType::Constrict,
+    "Inflate" => ActuatorType::Inflate,
+    "Position" => ActuatorType::Position,
+    "Temperature" => ActuatorType::Temperature,
+    _ => return None,
+  })
+}
+
+fn parse_sensor_type(name: &str) -> Option<SensorType> {
+  Some(match name {
+    "Battery" => SensorType::Battery,
+    "RSSI" => SensorType::RSSI,
+    "Button" => SensorType::Button,
+    "Pressure" => SensorType::Pressure,
+    "Position" => SensorType::Position,
+    "Temperature" => SensorType::Temperature,
+    _ => return None,
+  })
+}
+
+/// Parses a comma separated list of `<type>:<max value>` features. Returns None if any feature is
+/// malformed or of an unknown type, as the indexes of everything after it would be wrong.
+fn parse_feature_list<T>(list: &str, parse_type: fn(&str) -> Option<T>) -> Option<Vec<(T, u32)>> {
+  if list == "-" {
+    return Some(vec![]);
+  }
+  list
+    .split(',')
+    .map(|feature| {
+      let (feature_type, max) = feature.split_once(':')?;
+      Some((parse_type(feature_type)?, max.parse::<u32>().ok()?))
+    })
+    .collect()
+}
+
+/// Builds message attributes from a CAPS reply, with the `CAPS` keyword already removed.
+fn parse_capabilities(reply: &[&str]) -> Option<ServerDeviceMessageAttributes> {
+  let channels = parse_feature_list(reply.first()?, parse_actuator_type)?;
+  let sensors = parse_feature_list(reply.get(1).unwrap_or(&"-"), parse_sensor_type)?;
+  let mut builder = ServerDeviceMessageAttributesBuilder::default();
+  if !channels.is_empty() {
+    let channel_attrs: Vec<ServerGenericDeviceMessageAttributes> = channels
+      .iter()
+      .enumerate()
+      .map(|(index, (actuator_type, max))| {
+        ServerGenericDeviceMessageAttributes::new(
+          &format!("PWM Channel {}", index),
+          &RangeInclusive::new(0, *max),
+          *actuator_type,
+        )
+      })
+      .collect();
+    builder.scalar_cmd(&channel_attrs);
+  }
+  if !sensors.is_empty() {
+    // Every sensor can be both read and subscribed to, so sensor indexes match for both messages.
+    let sensor_attrs: Vec<SensorDeviceMessageAttributes> = sensors
+      .iter()
+      .enumerate()
+      .map(|(index, (sensor_type, max))| {
+        SensorDeviceMessageAttributes::new(
+          &format!("Sensor {}", index),
+          *sensor_type,
+          &[RangeInclusive::new(0, *max)],
+        )
+      })
+      .collect();
+    builder.sensor_read_cmd(&sensor_attrs);
+    builder.sensor_subscribe_cmd(&sensor_attrs);
+  }
+  Some(builder.finish())
+}
+
+/// Parses a `SENSOR <sensor> <value>` line into the sensor index and value.
+fn parse_sensor_reading(line: &[&str]) -> Option<(u32, i32)> {
+  match line {
+    ["SENSOR", sensor, value] => Some((sensor.parse().ok()?, value.parse().ok()?)),
+    _ => None,
+  }
+}
+
+#[derive(Default)]
+pub struct Esp32DiyIdentifier {}
+
+#[async_trait]
+impl ProtocolIdentifier for Esp32DiyIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+      .await?;
+    let (model, firmware_version) =
+      text_line_request("esp32-diy", &hardware, "HELLO", |line| match line {
+        ["HELLO", model, firmware_version, ..] => {
+          Some((model.to_string(), Some(firmware_version.to_string())))
+        }
+        ["HELLO", model] => Some((model.to_string(), None)),
+        _ => None,
+      })
+      .await?;
+    info!(
+      "ESP32 DIY device identified as {} (firmware {:?})",
+      model, firmware_version
+    );
+    Ok((
+      ServerDeviceIdentifier::new(
+        hardware.address(),
+        "esp32-diy",
+        &ProtocolAttributesType::Identifier(model),
+      ),
+      Box::new(Esp32DiyInitializer { firmware_version }),
+    ))
+  }
+}
+
+pub struct Esp32DiyInitializer {
+  firmware_version: Option<String>,
+}
+
+#[async_trait]
+impl ProtocolInitializer for Esp32DiyInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let sensor_types = attributes
+      .message_attributes
+      .sensor_subscribe_cmd()
+      .as_ref()
+      .map(|sensors| sensors.iter().map(|x| *x.sensor_type()).collect())
+      .unwrap_or_default();
+    Ok(Arc::new(Esp32Diy::new(hardware, sensor_types)))
+  }
+
+  async fn probe_version(&mut self, _hardware: Arc<Hardware>) -> DeviceVersion {
+    DeviceVersion::new(&self.firmware_version, &None)
+  }
+
+  async fn probe_attributes(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Option<ServerDeviceMessageAttributes> {
+    text_line_request("esp32-diy", &hardware, "CAPS", |line| match line {
+      ["CAPS", reply @ ..] => Some(parse_capabilities(reply)),
+      _ => None,
+    })
+    .await
+    .map_err(|err| warn!("Cannot get ESP32 DIY device capabilities: {:?}", err))
+    .ok()?
+    .or_else(|| {
+      warn!("ESP32 DIY device sent invalid capabilities, using protocol defaults.");
+      None
+    })
+  }
+}
+
+pub struct Esp32Diy {
+  // Set of sensors we've subscribed to for updates.
+  subscribed_sensors: Arc<DashSet<u32>>,
+  // The handler doesn't know its device index until a subscribe message comes in.
+  device_index: Arc<AtomicU32>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl Esp32Diy {
+  fn new(hardware: Arc<Hardware>, sensor_types: Vec<SensorType>) -> Self {
+    let (sender, _) = broadcast::channel(256);
+    let subscribed_sensors = Arc::new(DashSet::new());
+    // Sensor reports can arrive at any time while subscribed, so listen for them for as long as the
+    // device is around. This also keeps a listener on the hardware event stream, which the serial
+    // port hardware requires once it's subscribed.
+    let mut hardware_stream = hardware.event_stream();
+    let event_sender = sender.clone();
+    let stream_sensors = subscribed_sensors.clone();
+    let device_index = Arc::new(AtomicU32::new(0));
+    let stream_device_index = device_index.clone();
+    async_manager::spawn(async move {
+      let mut lines = LineBuffer::default();
+      loop {
+        match hardware_stream.recv().await {
+          Ok(HardwareEvent::Notification(_, _, data)) => {
+            for line in lines.push(&data) {
+              let Some((sensor, value)) =
+                parse_sensor_reading(&line.split_whitespace().collect::<Vec<&str>>())
+              else {
+                continue;
+              };
+              if !stream_sensors.contains(&sensor) {
+                continue;
+              }
+              let Some(sensor_type) = sensor_types.get(sensor as usize) else {
+                warn!("ESP32 DIY device reported unknown sensor {}", sensor);
+                continue;
+              };
+              // No one listening is fine, the device may report before anyone is around.
+              let _ = event_sender.send(
+                SensorReading::new(
+                  stream_device_index.load(Ordering::SeqCst),
+                  sensor,
+                  *sensor_type,
+                  vec![value],
+                )
+                .into(),
+              );
+            }
+          }
+          Ok(HardwareEvent::Disconnected(_)) | Err(RecvError::Closed) => break,
+          Err(RecvError::Lagged(_)) => continue,
+        }
+      }
+      debug!("ESP32 DIY device listener shut down, returning from task.");
+    });
+    Self {
+      subscribed_sensors,
+      device_index,
+      event_stream: sender,
+    }
+  }
+}
+
+impl ProtocolHandler for Esp32Diy {
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(
+      commands
+        .iter()
+        .enumerate()
+        .filter_map(|(index, command)| {
+          command
+            .map(|(_, scalar)| text_line_write_cmd(&format!("PWM {} {}", index, scalar)).into())
+        })
+        .collect(),
+    )
+  }
+
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
+  fn handle_sensor_read_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorReadCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    async move {
+      let sensor_index = *message.sensor_index();
+      let value = text_line_request(
+        "esp32-diy",
+        &device,
+        &format!("READ {}", sensor_index),
+        |line| {
+          parse_sensor_reading(line)
+            .filter(|(sensor, _)| *sensor == sensor_index)
+            .map(|(_, value)| value)
+        },
+      )
+      .await?;
+      Ok(
+        SensorReading::new(
+          message.device_index(),
+          sensor_index,
+          *message.sensor_type(),
+          vec![value],
+        )
+        .into(),
+      )
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    self
+      .device_index
+      .store(message.device_index(), Ordering::SeqCst);
+    async move {
+      device
+        .write_value(&text_line_write_cmd(&format!(
+          "SUB {}",
+          message.sensor_index()
+        )))
+        .await?;
+      sensors.insert(*message.sensor_index());
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if !self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    async move {
+      sensors.remove(message.sensor_index());
+      device
+        .write_value(&text_line_write_cmd(&format!(
+          "UNSUB {}",
+          message.sensor_index()
+        )))
+        .await?;
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+}
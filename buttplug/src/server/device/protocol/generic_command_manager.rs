@@ -13,6 +13,7 @@ use crate::{
       ButtplugDeviceCommandMessageUnion,
       LinearCmd,
       RotateCmd,
+      RotationMode,
       RotationSubcommand,
       ScalarCmd,
       ScalarSubcommand,
@@ -21,17 +22,15 @@ use crate::{
   server::device::configuration::{ProtocolDeviceAttributes, ServerGenericDeviceMessageAttributes},
 };
 use getset::Getters;
-use std::{
-  ops::RangeInclusive,
-  sync::atomic::{AtomicBool, AtomicU32, Ordering::SeqCst},
-};
+use std::{ops::RangeInclusive, sync::Arc};
+
+use super::duplicate_value_filter::DuplicateValueFilter;
 
 #[derive(Getters)]
 #[getset(get = "pub")]
 struct ScalarGenericCommand {
   actuator: ActuatorType,
   step_range: RangeInclusive<u32>,
-  value: AtomicU32,
 }
 
 impl ScalarGenericCommand {
@@ -39,7 +38,6 @@ impl ScalarGenericCommand {
     Self {
       actuator: *attributes.actuator_type(),
       step_range: attributes.step_range().clone(),
-      value: AtomicU32::new(0),
     }
   }
 }
@@ -47,18 +45,15 @@ impl ScalarGenericCommand {
 // In order to make our lives easier, we make some assumptions about what's internally mutable in
 // the GenericCommandManager (GCM). Once the GCM is configured for a device, it won't change sizes,
 // because we don't support things like adding motors to devices randomly while Buttplug is running.
-// Therefore we know that we'll just be storing values like vibration/rotation speeds. We can assume
-// our storage of those can stay immutable (the vec sizes won't change) and make their internals
-// mutable. While this could be RefCell'd or whatever, they're also always atomic types (until the
-// horrible day some sex toy decides to use floats in its protocol), so we can just use atomics and
-// call it done.
+// Therefore we know that we'll just be storing values like vibration/rotation speeds, which live in
+// the duplicate value filters.
 pub struct GenericCommandManager {
-  sent_scalar: AtomicBool,
-  sent_rotation: AtomicBool,
   _sent_linear: bool,
   scalars: Vec<ScalarGenericCommand>,
-  rotations: Vec<(AtomicU32, AtomicBool)>,
+  scalar_values: Arc<DuplicateValueFilter<u32>>,
+  rotation_values: Arc<DuplicateValueFilter<(u32, bool)>>,
   rotation_step_ranges: Vec<RangeInclusive<u32>>,
+  rotation_modes: Vec<RotationMode>,
   _linears: Vec<(u32, u32)>,
   _linear_step_counts: Vec<u32>,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
@@ -67,8 +62,8 @@ pub struct GenericCommandManager {
 impl GenericCommandManager {
   pub fn new(attributes: &ProtocolDeviceAttributes) -> Self {
     let mut scalars = vec![];
-    let mut rotations = vec![];
     let mut rotation_step_ranges = vec![];
+    let mut rotation_modes = vec![];
     let mut linears = vec![];
     let mut linear_step_counts = vec![];
 
@@ -88,20 +83,26 @@ impl GenericCommandManager {
       stop_commands.push(ScalarCmd::new(0, subcommands).into());
     }
     if let Some(attrs) = attributes.message_attributes.rotate_cmd() {
-      rotations.resize_with(attrs.len(), || (AtomicU32::new(0), AtomicBool::new(false)));
       for attr in attrs {
         rotation_step_ranges.push(attr.step_range().clone());
+        rotation_modes.push(attr.rotation_mode().unwrap_or_default());
       }
 
       // TODO Can we assume clockwise is false here? We might send extra
       // messages on Lovense since it'll require both a speed and change
       // direction command, but is that really a big deal? We can just
       // have it ignore the direction difference on a 0.0 speed?
-      let mut subcommands = vec![];
-      for i in 0..rotations.len() {
-        subcommands.push(RotationSubcommand::new(i as u32, 0.0, false));
+      //
+      // Positional rotators have no "off" speed, so we leave them wherever they are.
+      let subcommands: Vec<RotationSubcommand> = rotation_modes
+        .iter()
+        .enumerate()
+        .filter(|(_, mode)| **mode == RotationMode::Continuous)
+        .map(|(i, _)| RotationSubcommand::new(i as u32, 0.0, false))
+        .collect();
+      if !subcommands.is_empty() {
+        stop_commands.push(RotateCmd::new(0, subcommands).into());
       }
-      stop_commands.push(RotateCmd::new(0, subcommands).into());
     }
     if let Some(attrs) = attributes.message_attributes.linear_cmd() {
       linears = vec![(0, 0); attrs.len()];
@@ -111,13 +112,13 @@ impl GenericCommandManager {
     }
 
     Self {
-      sent_scalar: AtomicBool::new(false),
-      sent_rotation: AtomicBool::new(false),
       _sent_linear: false,
       scalars,
-      rotations,
+      scalar_values: Arc::new(DuplicateValueFilter::default()),
+      rotation_values: Arc::new(DuplicateValueFilter::default()),
       _linears: linears,
       rotation_step_ranges,
+      rotation_modes,
       _linear_step_counts: linear_step_counts,
       stop_commands,
     }
@@ -162,38 +163,24 @@ impl GenericCommandManager {
         );
       }
 
-      let range_start = self.scalars[index].step_range().start();
-      let range = self.scalars[index].step_range().end() - range_start;
-      let scalar_modifier = scalar_command.scalar() * range as f64;
-      let scalar = if scalar_modifier < 0.0001 {
-        0
-      } else {
-        // When calculating speeds, round up. This follows how we calculated
-        // things in buttplug-js and buttplug-csharp, so it's more for history
-        // than anything, but it's what users will expect.
-        (scalar_modifier + *range_start as f64).ceil() as u32
-      };
+      let scalar = to_step(scalar_command.scalar(), self.scalars[index].step_range());
       trace!(
-        "{:?} {} {} {}",
+        "{:?} {} {}",
         self.scalars[index].step_range(),
-        range,
-        scalar_modifier,
+        scalar_command.scalar(),
         scalar
       );
-      // If we've already sent commands, we don't want to send them again,
-      // because some of our communication busses are REALLY slow. Make sure
-      // these values get None in our return vector.
-      let current_scalar = self.scalars[index].value().load(SeqCst);
-      let sent_scalar = self.sent_scalar.load(SeqCst);
-      if !sent_scalar || scalar != current_scalar {
-        self.scalars[index].value().store(scalar, SeqCst);
-        result[index] = Some((*self.scalars[index].actuator(), scalar));
-      }
-
-      if !sent_scalar {
-        self.sent_scalar.store(true, SeqCst);
+      // If we've already sent this value, we don't want to send it again. Make sure these values
+      // get None in our return vector.
+      let actuator = *self.scalars[index].actuator();
+      if self.scalar_values.update(index as u32, actuator, scalar) {
+        result[index] = Some((actuator, scalar));
       }
     }
+    // Once we've sent anything, assume actuators we haven't heard about yet are off.
+    for (index, cmd) in self.scalars.iter().enumerate() {
+      self.scalar_values.assume(index as u32, *cmd.actuator(), 0);
+    }
 
     // If we have no changes to the device, just send back an empty command array. We have nothing
     // to do.
@@ -204,7 +191,7 @@ impl GenericCommandManager {
       // values before switching them out.
       for (index, cmd) in self.scalars.iter().enumerate() {
         if result[index].is_none() {
-          result[index] = Some((*cmd.actuator(), cmd.value.load(SeqCst)));
+          result[index] = Some((*cmd.actuator(), self.scalar_value(index, cmd)));
         }
       }
     }
@@ -213,13 +200,28 @@ impl GenericCommandManager {
     Ok(result)
   }
 
+  fn scalar_value(&self, index: usize, cmd: &ScalarGenericCommand) -> u32 {
+    self
+      .scalar_values
+      .value(index as u32, *cmd.actuator())
+      .unwrap_or(0)
+  }
+
+  fn rotation_value(&self, index: usize) -> (u32, bool) {
+    self
+      .rotation_values
+      .value(index as u32, ActuatorType::Rotate)
+      .unwrap_or((0, false))
+  }
+
   // Test method
   #[cfg(test)]
   pub(super) fn scalars(&self) -> Vec<Option<(ActuatorType, u32)>> {
     self
       .scalars
       .iter()
-      .map(|x| Some((*x.actuator(), x.value().load(SeqCst))))
+      .enumerate()
+      .map(|(index, x)| Some((*x.actuator(), self.scalar_value(index, x))))
       .collect()
   }
 
@@ -245,59 +247,56 @@ impl GenericCommandManager {
     // If we've already sent commands before, we should check against our
     // old values. Otherwise, we should always send whatever command we're
     // going to send.
-    let mut result: Vec<Option<(u32, bool)>> = vec![None; self.rotations.len()];
+    let mut result: Vec<Option<(u32, bool)>> = vec![None; self.rotation_step_ranges.len()];
     for rotate_command in msg.rotations() {
       let index = rotate_command.index() as usize;
       // Since we're going to iterate here anyways, we do our index check
       // here instead of in a filter above.
-      if index >= self.rotations.len() {
+      if index >= self.rotation_step_ranges.len() {
         return Err(
           ButtplugDeviceError::ProtocolRequirementError(format!(
             "RotateCmd has {} commands, device has {} rotators.",
             msg.rotations().len(),
-            self.rotations.len()
+            self.rotation_step_ranges.len()
           ))
           .into(),
         );
       }
+      if self.rotation_modes[index] != RotationMode::Continuous {
+        return Err(
+          ButtplugDeviceError::DeviceRotationModeMismatch(
+            index as u32,
+            RotationMode::Continuous,
+            self.rotation_modes[index],
+          )
+          .into(),
+        );
+      }
 
-      // When calculating speeds, round up. This follows how we calculated
-      // things in buttplug-js and buttplug-csharp, so it's more for history
-      // than anything, but it's what users will expect.
-      let range = self.rotation_step_ranges[index].end() - self.rotation_step_ranges[index].start();
-      let speed_modifier = rotate_command.speed() * range as f64;
-      let speed = if speed_modifier < 0.0001 {
-        0
-      } else {
-        // When calculating speeds, round up. This follows how we calculated
-        // things in buttplug-js and buttplug-csharp, so it's more for history
-        // than anything, but it's what users will expect.
-        (speed_modifier + *self.rotation_step_ranges[index].start() as f64).ceil() as u32
-      };
+      let speed = to_step(rotate_command.speed(), &self.rotation_step_ranges[index]);
       let clockwise = rotate_command.clockwise();
-      // If we've already sent commands, we don't want to send them again,
-      // because some of our communication busses are REALLY slow. Make sure
-      // these values get None in our return vector.
-      let sent_rotation = self.sent_rotation.load(SeqCst);
-      if !sent_rotation
-        || speed != self.rotations[index].0.load(SeqCst)
-        || clockwise != self.rotations[index].1.load(SeqCst)
+      // If we've already sent this value, we don't want to send it again. Make sure these values
+      // get None in our return vector.
+      if self
+        .rotation_values
+        .update(index as u32, ActuatorType::Rotate, (speed, clockwise))
       {
-        self.rotations[index].0.store(speed, SeqCst);
-        self.rotations[index].1.store(clockwise, SeqCst);
         result[index] = Some((speed, clockwise));
       }
-      if !sent_rotation {
-        self.sent_rotation.store(true, SeqCst);
-      }
+    }
+    // Once we've sent anything, assume rotators we haven't heard about yet are off.
+    for index in 0..self.rotation_step_ranges.len() {
+      self
+        .rotation_values
+        .assume(index as u32, ActuatorType::Rotate, (0, false));
     }
 
     // If we're in a match all situation, set up the array with all prior
     // values before switching them out.
     if match_all && !result.iter().all(|x| x.is_none()) {
-      for (index, rotation) in self.rotations.iter().enumerate() {
-        if result[index].is_none() {
-          result[index] = Some((rotation.0.load(SeqCst), rotation.1.load(SeqCst)));
+      for (index, rotation) in result.iter_mut().enumerate() {
+        if rotation.is_none() && self.rotation_modes[index] == RotationMode::Continuous {
+          *rotation = Some(self.rotation_value(index));
         }
       }
     }
@@ -325,9 +324,72 @@ impl GenericCommandManager {
     Ok(None)
   }
 
+  /// Forget whether the device has the values we last generated commands for, so they're resent on
+  /// the next update. Used when commands are dropped before reaching the hardware.
+  pub fn invalidate_sent_values(&self) {
+    self.scalar_values.invalidate();
+    self.rotation_values.invalidate();
+  }
+
+  /// Returns a function that does the same as [GenericCommandManager::invalidate_sent_values], for
+  /// use from other tasks.
+  pub fn sent_values_invalidator(&self) -> impl Fn() + Send + Sync + 'static {
+    let scalar_values = self.scalar_values.clone();
+    let rotation_values = self.rotation_values.clone();
+    move || {
+      scalar_values.invalidate();
+      rotation_values.invalidate();
+    }
+  }
+
   pub fn stop_commands(&self) -> Vec<ButtplugDeviceCommandMessageUnion> {
     self.stop_commands.clone()
   }
+
+  /// Commands bringing actuators last sent a value over `ceiling` (0.0-1.0) down to it, leaving
+  /// everything else alone. Empty if nothing is over the ceiling.
+  pub fn ceiling_commands(&self, ceiling: f64) -> Vec<ButtplugDeviceCommandMessageUnion> {
+    let mut commands = vec![];
+    let scalars: Vec<ScalarSubcommand> = self
+      .scalars
+      .iter()
+      .enumerate()
+      .filter(|(index, cmd)| self.scalar_value(*index, cmd) > to_step(ceiling, cmd.step_range()))
+      .map(|(index, cmd)| ScalarSubcommand::new(index as u32, ceiling, *cmd.actuator()))
+      .collect();
+    if !scalars.is_empty() {
+      commands.push(ScalarCmd::new(0, scalars).into());
+    }
+    let rotations: Vec<RotationSubcommand> = self
+      .rotation_step_ranges
+      .iter()
+      .enumerate()
+      .filter_map(|(index, step_range)| {
+        let (speed, clockwise) = self.rotation_value(index);
+        (self.rotation_modes[index] == RotationMode::Continuous
+          && speed > to_step(ceiling, step_range))
+        .then(|| RotationSubcommand::new(index as u32, ceiling, clockwise))
+      })
+      .collect();
+    if !rotations.is_empty() {
+      commands.push(RotateCmd::new(0, rotations).into());
+    }
+    commands
+  }
+}
+
+/// Converts a 0.0-1.0 value to a step in `step_range`.
+fn to_step(value: f64, step_range: &RangeInclusive<u32>) -> u32 {
+  let range = step_range.end() - step_range.start();
+  let modifier = value * range as f64;
+  if modifier < 0.0001 {
+    0
+  } else {
+    // When calculating speeds, round up. This follows how we calculated
+    // things in buttplug-js and buttplug-csharp, so it's more for history
+    // than anything, but it's what users will expect.
+    (modifier + *step_range.start() as f64).ceil() as u32
+  }
 }
 
 #[cfg(test)]
@@ -335,7 +397,14 @@ mod test {
 
   use super::{GenericCommandManager, ProtocolDeviceAttributes};
   use crate::{
-    core::message::{ActuatorType, RotateCmd, RotationSubcommand, ScalarCmd, ScalarSubcommand},
+    core::message::{
+      ActuatorType,
+      RotateCmd,
+      RotationMode,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+    },
     server::device::configuration::{
       ProtocolAttributesType,
       ServerDeviceMessageAttributesBuilder,
@@ -627,5 +696,103 @@ mod test {
     let rotate_msg_invalid = RotateCmd::new(0, vec![RotationSubcommand::new(2, 0.5, true)]);
     assert!(mgr.update_rotation(&rotate_msg_invalid, false).is_err());
   }
+
+  #[test]
+  pub fn test_command_generator_positional_rotation() {
+    let continuous_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(0, 20),
+      ActuatorType::Rotate,
+    );
+    let mut positional_attrs = continuous_attrs.clone();
+    positional_attrs.set_rotation_mode(Some(RotationMode::Positional));
+
+    let rotate_attributes = ServerDeviceMessageAttributesBuilder::default()
+      .rotate_cmd(&[continuous_attrs, positional_attrs])
+      .finish();
+    let device_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      None,
+      None,
+      rotate_attributes,
+      None,
+    );
+    let mgr = GenericCommandManager::new(&device_attributes);
+
+    // Speeds can't be sent to positional rotators, and match all doesn't fill them in.
+    let rotate_msg_invalid = RotateCmd::new(0, vec![RotationSubcommand::new(1, 0.5, true)]);
+    assert!(mgr.update_rotation(&rotate_msg_invalid, false).is_err());
+    let rotate_msg = RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]);
+    assert_eq!(
+      mgr
+        .update_rotation(&rotate_msg, true)
+        .expect("Test, assuming infallible"),
+      vec![Some((10, true)), None]
+    );
+
+    // Stopping only touches the continuous rotator.
+    assert_eq!(
+      mgr.stop_commands(),
+      vec![RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.0, false)]).into()]
+    );
+  }
+
+  #[test]
+  pub fn test_command_generator_ceiling() {
+    let scalar_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(0, 20),
+      ActuatorType::Vibrate,
+    );
+    let rotate_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(0, 20),
+      ActuatorType::Rotate,
+    );
+    let mut attributes_builder = ServerDeviceMessageAttributesBuilder::default();
+    attributes_builder.scalar_cmd(&[scalar_attrs.clone(), scalar_attrs]);
+    attributes_builder.rotate_cmd(&[rotate_attrs]);
+    let attributes = attributes_builder.finish();
+    let device_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      None,
+      None,
+      attributes,
+      None,
+    );
+    let mgr = GenericCommandManager::new(&device_attributes);
+    // Nothing has been sent yet, so nothing is over the ceiling.
+    assert!(mgr.ceiling_commands(0.5).is_empty());
+    mgr
+      .update_scalar(
+        &ScalarCmd::new(
+          0,
+          vec![
+            ScalarSubcommand::new(0, 0.25, ActuatorType::Vibrate),
+            ScalarSubcommand::new(1, 1.0, ActuatorType::Vibrate),
+          ],
+        ),
+        false,
+      )
+      .expect("Test, assuming infallible");
+    mgr
+      .update_rotation(
+        &RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.75, true)]),
+        false,
+      )
+      .expect("Test, assuming infallible");
+    assert_eq!(
+      mgr.ceiling_commands(0.5),
+      vec![
+        ScalarCmd::new(
+          0,
+          vec![ScalarSubcommand::new(1, 0.5, ActuatorType::Vibrate)]
+        )
+        .into(),
+        RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]).into()
+      ]
+    );
+    assert!(mgr.ceiling_commands(1.0).is_empty());
+  }
   // TODO Write test for vibration stop generator
 }
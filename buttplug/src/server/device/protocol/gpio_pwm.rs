@@ -0,0 +1,135 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Actuators driven directly from PWM channels, through the GPIO comm manager.
+//!
+//! ScalarCmd feature N is written to endpoint genericN, which the gpio specifier in the user config
+//! maps to a PWM channel. Values are scaled to the feature's step range, so a feature with steps
+//! 0..100 running at 50 gets a 50% duty cycle.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::{str::FromStr, sync::Arc};
+
+pub mod setup {
+  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  #[derive(Default)]
+  pub struct GpioPwmIdentifierFactory {}
+
+  impl ProtocolIdentifierFactory for GpioPwmIdentifierFactory {
+    fn identifier(&self) -> &str {
+      "gpio-pwm"
+    }
+
+    fn create(&self) -> Box<dyn ProtocolIdentifier> {
+      Box::new(super::GpioPwmIdentifier::default())
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct GpioPwmIdentifier {}
+
+#[async_trait]
+impl ProtocolIdentifier for GpioPwmIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    // Chips don't know what's wired to them, so features come from the user config entry for the
+    // chip's address.
+    Ok((
+      ServerDeviceIdentifier::new(
+        hardware.address(),
+        "gpio-pwm",
+        &ProtocolAttributesType::Default,
+      ),
+      Box::new(GpioPwmInitializer::default()),
+    ))
+  }
+}
+
+#[derive(Default)]
+pub struct GpioPwmInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for GpioPwmInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let mut channels = vec![];
+    for (index, attr) in attributes
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .ok_or_else(|| {
+        ButtplugDeviceError::ProtocolRequirementError(
+          "GPIO PWM devices need at least one ScalarCmd feature.".to_owned(),
+        )
+      })?
+      .iter()
+      .enumerate()
+    {
+      let endpoint = Endpoint::from_str(&format!("generic{}", index)).map_err(|_| {
+        ButtplugDeviceError::ProtocolRequirementError(format!(
+          "GPIO PWM devices support at most 32 features, got {}.",
+          index + 1
+        ))
+      })?;
+      if !hardware.endpoints().contains(&endpoint) {
+        return Err(ButtplugDeviceError::ProtocolRequirementError(format!(
+          "No PWM channel mapped to {} for feature {}.",
+          endpoint, index
+        )));
+      }
+      channels.push((endpoint, *attr.step_range().end()));
+    }
+    Ok(Arc::new(GpioPwm { channels }))
+  }
+}
+
+pub struct GpioPwm {
+  /// Endpoint and maximum step of each ScalarCmd feature.
+  channels: Vec<(Endpoint, u32)>,
+}
+
+impl ProtocolHandler for GpioPwm {
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(
+      commands
+        .iter()
+        .zip(self.channels.iter())
+        .filter_map(|(command, (endpoint, max_step))| {
+          command.map(|(_, scalar)| {
+            let fraction = if *max_step == 0 {
+              0
+            } else {
+              (scalar.min(*max_step) as u64 * u16::MAX as u64 / *max_step as u64) as u16
+            };
+            HardwareWriteCmd::new(*endpoint, fraction.to_le_bytes().to_vec(), false).into()
+          })
+        })
+        .collect(),
+    )
+  }
+}
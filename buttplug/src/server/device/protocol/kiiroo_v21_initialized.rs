@@ -9,11 +9,27 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, Endpoint},
+    message::{
+      self,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorReading,
+      SensorType,
+    },
   },
   server::device::{
     configuration::ProtocolAttributesType,
-    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareEvent,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
     protocol::{
       fleshlight_launch_helper::calculate_speed,
       generic_protocol_initializer_setup,
@@ -23,12 +39,22 @@ use crate::{
     },
     ServerDeviceIdentifier,
   },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
 use async_trait::async_trait;
-use std::sync::{
-  atomic::{AtomicU8, Ordering},
-  Arc,
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
 };
+use std::{
+  pin::Pin,
+  sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::broadcast;
 
 generic_protocol_initializer_setup!(KiirooV21Initialized, "kiiroo-v21-initialized");
 
@@ -61,9 +87,23 @@ impl ProtocolInitializer for KiirooV21InitializedInitializer {
   }
 }
 
-#[derive(Default)]
 pub struct KiirooV21Initialized {
   previous_position: Arc<AtomicU8>,
+  // Whether we're streaming position reports. Strokers only have the one sensor, so no need to
+  // track indexes.
+  position_subscribed: Arc<AtomicBool>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl Default for KiirooV21Initialized {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      previous_position: Default::default(),
+      position_subscribed: Default::default(),
+      event_stream: sender,
+    }
+  }
 }
 
 impl ProtocolHandler for KiirooV21Initialized {
@@ -114,4 +154,85 @@ impl ProtocolHandler for KiirooV21Initialized {
     )
     .into()])
   }
+
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<'_, Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if *message.sensor_type() != SensorType::Position {
+      return future::ready(Err(ButtplugDeviceError::ProtocolSensorNotSupported(
+        *message.sensor_type(),
+      )))
+      .boxed();
+    }
+    if self.position_subscribed.load(Ordering::SeqCst) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let subscribed = self.position_subscribed.clone();
+    let sender = self.event_stream.clone();
+    // Devices with position feedback (Onyx+ and friends) report where the motor actually is while
+    // rx is subscribed. Reports mirror the movement command format:
+    // Byte 0: 0x03
+    // Byte 1: Current position, 0-99
+    async move {
+      let mut hardware_stream = device.event_stream();
+      device
+        .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+        .await?;
+      subscribed.store(true, Ordering::SeqCst);
+      let stream_subscribed = subscribed.clone();
+      let device_index = message.device_index();
+      let sensor_index = *message.sensor_index();
+      async_manager::spawn(async move {
+        while let Ok(info) = hardware_stream.recv().await {
+          // If we have no receivers or have been unsubscribed, quit.
+          if sender.receiver_count() == 0 || !stream_subscribed.load(Ordering::SeqCst) {
+            return;
+          }
+          if let HardwareEvent::Notification(_, Endpoint::Rx, data) = info {
+            if data.len() != 2 || data[0] != 0x03 {
+              debug!("Ignoring unknown Kiiroo notification: {:?}", data);
+              continue;
+            }
+            let reading = SensorReading::new(
+              device_index,
+              sensor_index,
+              SensorType::Position,
+              vec![data[1] as i32],
+            );
+            if sender.send(reading.into()).is_err() {
+              debug!("Hardware device listener for Kiiroo device shut down, returning from task.");
+              return;
+            }
+          }
+        }
+      });
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<'_, Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if !self.position_subscribed.swap(false, Ordering::SeqCst) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    async move {
+      device
+        .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::Rx))
+        .await?;
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
 }
@@ -9,12 +9,24 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, ActuatorType, ButtplugDeviceMessage, ButtplugServerMessage, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      DeviceVersion,
+      Endpoint,
+    },
   },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareEvent, HardwareSubscribeCmd, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{
+      probe_device_information_version,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
     ServerDeviceIdentifier,
   },
   util::sleep,
@@ -80,6 +92,15 @@ fn lovense_model_resolver(type_response: String) -> String {
   identifier
 }
 
+/// DeviceType responses are formatted as "type:firmware version:address;".
+fn lovense_firmware_version(type_response: &str) -> Option<String> {
+  type_response
+    .split(':')
+    .nth(1)
+    .map(|version| version.trim_end_matches(';').to_owned())
+    .filter(|version| !version.is_empty())
+}
+
 #[async_trait]
 impl ProtocolIdentifier for LovenseIdentifier {
   async fn identify(
@@ -101,8 +122,11 @@ impl ProtocolIdentifier for LovenseIdentifier {
           if let Ok(HardwareEvent::Notification(_, _, n)) = event {
             let type_response = std::str::from_utf8(&n).map_err(|_| ButtplugDeviceError::ProtocolSpecificError("lovense".to_owned(), "Lovense device init got back non-UTF8 string.".to_owned()))?.to_owned();
             debug!("Lovense Device Type Response: {}", type_response);
+            let firmware_version = lovense_firmware_version(&type_response);
             let ident = lovense_model_resolver(type_response);
-            return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Identifier(ident.clone())), Box::new(LovenseInitializer::new(ident))));
+            let mut initializer = LovenseInitializer::new(ident.clone());
+            initializer.firmware_version = firmware_version;
+            return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Identifier(ident)), Box::new(initializer)));
           } else {
             return Err(
               ButtplugDeviceError::ProtocolSpecificError(
@@ -130,11 +154,15 @@ impl ProtocolIdentifier for LovenseIdentifier {
 }
 pub struct LovenseInitializer {
   device_type: String,
+  firmware_version: Option<String>,
 }
 
 impl LovenseInitializer {
   pub fn new(device_type: String) -> Self {
-    Self { device_type }
+    Self {
+      device_type,
+      firmware_version: None,
+    }
   }
 }
 
@@ -173,6 +201,16 @@ impl ProtocolInitializer for LovenseInitializer {
     );
     Ok(Arc::new(protocol))
   }
+
+  async fn probe_version(&mut self, hardware: Arc<Hardware>) -> DeviceVersion {
+    let mut version = probe_device_information_version(hardware).await;
+    // Lovense devices don't have a Device Information Service, but DeviceType tells us the
+    // firmware version.
+    if version.firmware_revision().is_none() {
+      version.set_firmware_revision(self.firmware_version.clone());
+    }
+    version
+  }
 }
 
 #[derive(Default)]
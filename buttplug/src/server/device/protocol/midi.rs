@@ -0,0 +1,131 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hardware driven by MIDI messages, through the MIDI comm manager.
+//!
+//! ScalarCmd feature N is written to endpoint genericN, which the midi specifier in the user config
+//! maps to a control change or note message. Values are scaled from the feature's step range to
+//! the 0 to 127 range of MIDI values.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::{str::FromStr, sync::Arc};
+
+pub mod setup {
+  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  #[derive(Default)]
+  pub struct MidiIdentifierFactory {}
+
+  impl ProtocolIdentifierFactory for MidiIdentifierFactory {
+    fn identifier(&self) -> &str {
+      "midi"
+    }
+
+    fn create(&self) -> Box<dyn ProtocolIdentifier> {
+      Box::new(super::MidiIdentifier::default())
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct MidiIdentifier {}
+
+#[async_trait]
+impl ProtocolIdentifier for MidiIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    // Ports don't know what's plugged into them, so features come from the user config entry for
+    // the port's address.
+    Ok((
+      ServerDeviceIdentifier::new(hardware.address(), "midi", &ProtocolAttributesType::Default),
+      Box::new(MidiInitializer::default()),
+    ))
+  }
+}
+
+#[derive(Default)]
+pub struct MidiInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for MidiInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let mut outputs = vec![];
+    for (index, attr) in attributes
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .ok_or_else(|| {
+        ButtplugDeviceError::ProtocolRequirementError(
+          "MIDI devices need at least one ScalarCmd feature.".to_owned(),
+        )
+      })?
+      .iter()
+      .enumerate()
+    {
+      let endpoint = Endpoint::from_str(&format!("generic{}", index)).map_err(|_| {
+        ButtplugDeviceError::ProtocolRequirementError(format!(
+          "MIDI devices support at most 32 features, got {}.",
+          index + 1
+        ))
+      })?;
+      if !hardware.endpoints().contains(&endpoint) {
+        return Err(ButtplugDeviceError::ProtocolRequirementError(format!(
+          "No MIDI output mapped to {} for feature {}.",
+          endpoint, index
+        )));
+      }
+      outputs.push((endpoint, *attr.step_range().end()));
+    }
+    Ok(Arc::new(Midi { outputs }))
+  }
+}
+
+pub struct Midi {
+  /// Endpoint and maximum step of each ScalarCmd feature.
+  outputs: Vec<(Endpoint, u32)>,
+}
+
+impl ProtocolHandler for Midi {
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(
+      commands
+        .iter()
+        .zip(self.outputs.iter())
+        .filter_map(|(command, (endpoint, max_step))| {
+          command.map(|(_, scalar)| {
+            let value = if *max_step == 0 {
+              0
+            } else {
+              (scalar.min(*max_step) as u64 * 127 / *max_step as u64) as u8
+            };
+            HardwareWriteCmd::new(*endpoint, vec![value], false).into()
+          })
+        })
+        .collect(),
+    )
+  }
+}
@@ -7,23 +7,28 @@
 
 //! Implementations of communication protocols for hardware supported by Buttplug
 
+pub mod duplicate_value_filter;
 pub mod generic_command_manager;
 
 // Utility mods
 pub mod fleshlight_launch_helper;
+pub mod text_line_helper;
 
 // Since users can pick and choose protocols, we need all of these to be public.
 pub mod adrienlastic;
 pub mod aneros;
 pub mod ankni;
+pub mod arduino_serial;
 pub mod buttplug_passthru;
 pub mod cachito;
 pub mod cowgirl;
+pub mod esp32_diy;
 pub mod foreo;
 pub mod fox;
 pub mod fredorch;
 pub mod fredorch_rotary;
 pub mod galaku_pump;
+pub mod gpio_pwm;
 pub mod hgod;
 pub mod hismith;
 pub mod hismith_mini;
@@ -61,6 +66,7 @@ pub mod metaxsire;
 pub mod metaxsire_repeat;
 pub mod metaxsire_v2;
 pub mod metaxsire_v3;
+pub mod midi;
 pub mod mizzzee;
 pub mod mizzzee_v2;
 pub mod mizzzee_v3;
@@ -97,6 +103,7 @@ pub mod synchro;
 pub mod tcode_v03;
 pub mod thehandy;
 pub mod tryfun;
+pub mod valve_steam_controller;
 pub mod vibcrafter;
 pub mod vibratissimo;
 pub mod vorze_sa;
@@ -121,13 +128,18 @@ use crate::{
       ButtplugDeviceMessage,
       ButtplugServerDeviceMessage,
       ButtplugServerMessage,
+      DeviceVersion,
       Endpoint,
       SensorType,
     },
   },
   server::device::{
-    configuration::{ProtocolAttributesType, ProtocolCommunicationSpecifier},
-    hardware::{Hardware, HardwareCommand, HardwareReadCmd},
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolCommunicationSpecifier,
+      ServerDeviceMessageAttributes,
+    },
+    hardware::{parse_device_information_string, Hardware, HardwareCommand, HardwareReadCmd},
     ServerDeviceIdentifier,
   },
 };
@@ -177,6 +189,11 @@ pub enum ProtocolKeepaliveStrategy {
 pub trait ProtocolIdentifierFactory: Send + Sync {
   fn identifier(&self) -> &str;
   fn create(&self) -> Box<dyn ProtocolIdentifier>;
+  /// True if the protocol knows how to drive heaters. Configurations can only give Temperature
+  /// actuators to these protocols, anywhere else they'd be sent as some other kind of command.
+  fn supports_temperature_actuators(&self) -> bool {
+    false
+  }
 }
 
 pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierFactory>> {
@@ -227,7 +244,15 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
   );
 
   add_to_protocol_map(&mut map, ankni::setup::AnkniIdentifierFactory::default());
+  add_to_protocol_map(
+    &mut map,
+    arduino_serial::setup::ArduinoSerialIdentifierFactory::default(),
+  );
   add_to_protocol_map(&mut map, foreo::setup::ForeoIdentifierFactory::default());
+  add_to_protocol_map(
+    &mut map,
+    esp32_diy::setup::Esp32DiyIdentifierFactory::default(),
+  );
   add_to_protocol_map(&mut map, fox::setup::FoxIdentifierFactory::default());
   add_to_protocol_map(
     &mut map,
@@ -244,6 +269,10 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     galaku_pump::setup::GalakuPumpIdentifierFactory::default(),
   );
+  add_to_protocol_map(
+    &mut map,
+    gpio_pwm::setup::GpioPwmIdentifierFactory::default(),
+  );
 
   add_to_protocol_map(&mut map, itoys::setup::IToysIdentifierFactory::default());
   add_to_protocol_map(&mut map, jejoue::setup::JeJoueIdentifierFactory::default());
@@ -330,6 +359,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
   add_to_protocol_map(&mut map, mannuo::setup::ManNuoIdentifierFactory::default());
   add_to_protocol_map(&mut map, maxpro::setup::MaxproIdentifierFactory::default());
   add_to_protocol_map(&mut map, meese::setup::MeeseIdentifierFactory::default());
+  add_to_protocol_map(&mut map, midi::setup::MidiIdentifierFactory::default());
   add_to_protocol_map(
     &mut map,
     metaxsire::setup::MetaXSireIdentifierFactory::default(),
@@ -468,6 +498,10 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     tcode_v03::setup::TCodeV03IdentifierFactory::default(),
   );
+  add_to_protocol_map(
+    &mut map,
+    valve_steam_controller::setup::ValveSteamControllerIdentifierFactory::default(),
+  );
   add_to_protocol_map(
     &mut map,
     vibcrafter::setup::VibCrafterIdentifierFactory::default(),
@@ -509,6 +543,30 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
   map
 }
 
+/// Reads the battery level from the standard BLE Battery Service. The characteristic reports a
+/// percentage, so the value can be passed along as-is.
+pub(crate) fn read_ble_battery_level(
+  device: Arc<Hardware>,
+  message: message::SensorReadCmd,
+) -> BoxFuture<'static, Result<ButtplugServerMessage, ButtplugDeviceError>> {
+  debug!("Trying to get battery reading.");
+  let msg = HardwareReadCmd::new(Endpoint::RxBLEBattery, 1, 0);
+  let fut = device.read_value(&msg);
+  async move {
+    let hw_msg = fut.await?;
+    let battery_level = hw_msg.data()[0] as i32;
+    let battery_reading = message::SensorReading::new(
+      message.device_index(),
+      *message.sensor_index(),
+      *message.sensor_type(),
+      vec![battery_level],
+    );
+    debug!("Got battery reading: {}", battery_level);
+    Ok(battery_reading.into())
+  }
+  .boxed()
+}
+
 fn print_type_of<T>(_: &T) -> &'static str {
   std::any::type_name::<T>()
 }
@@ -553,6 +611,81 @@ pub trait ProtocolInitializer: Sync + Send {
     hardware: Arc<Hardware>,
     attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError>;
+
+  /// Called when the device matched the protocol, but there's no configuration for its specific
+  /// model, so it would get the protocol defaults. Returns an identifier, queried from the device,
+  /// to look up its configuration with instead. By default, reads the model number characteristic
+  /// if the protocol configuration maps one.
+  ///
+  /// This only identifies the model. Actuator counts still come from the device configuration for
+  /// that model, as none of our protocols can ask the device what actuators it has. If the probed
+  /// model isn't in the configuration either, the device gets the protocol defaults.
+  async fn probe_identifier(&mut self, hardware: Arc<Hardware>) -> Option<ProtocolAttributesType> {
+    probe_model_number(hardware).await
+  }
+
+  /// Called once the device is identified, to find out its firmware and hardware revisions for
+  /// device info. By default, reads the revision characteristics if the protocol configuration maps
+  /// them. Protocols that can query versions some other way should override this.
+  async fn probe_version(&mut self, hardware: Arc<Hardware>) -> DeviceVersion {
+    probe_device_information_version(hardware).await
+  }
+
+  /// Called when there's no configuration for the device's specific model, for protocols where
+  /// devices can announce their own features. Announced features replace the protocol defaults.
+  async fn probe_attributes(
+    &mut self,
+    _hardware: Arc<Hardware>,
+  ) -> Option<ServerDeviceMessageAttributes> {
+    None
+  }
+}
+
+/// Reads a string characteristic (usually from the BLE Device Information Service), with
+/// surrounding NULs and whitespace trimmed. Returns None if the device doesn't have the endpoint,
+/// the read fails, or the string is empty.
+async fn read_device_information_string(
+  hardware: &Arc<Hardware>,
+  endpoint: Endpoint,
+) -> Option<String> {
+  if !hardware.endpoints().contains(&endpoint) {
+    return None;
+  }
+  let reading = hardware
+    .read_value(&HardwareReadCmd::new(endpoint, 128, 500))
+    .await
+    .map_err(|err| {
+      debug!(
+        "Cannot read {} from {}: {:?}",
+        endpoint,
+        hardware.name(),
+        err
+      )
+    })
+    .ok()?;
+  parse_device_information_string(reading.data())
+}
+
+/// Reads the model number characteristic (usually from the BLE Device Information Service), for
+/// use as a device configuration identifier. Uses the model number read during specialization if
+/// there is one. Returns None if the device has no model endpoint or the read fails.
+pub async fn probe_model_number(hardware: Arc<Hardware>) -> Option<ProtocolAttributesType> {
+  if let Some(model) = hardware.device_information().model_number() {
+    return Some(ProtocolAttributesType::Identifier(model.clone()));
+  }
+  read_device_information_string(&hardware, Endpoint::RxBLEModel)
+    .await
+    .map(ProtocolAttributesType::Identifier)
+}
+
+/// Reads the firmware and hardware revision characteristics (usually from the BLE Device
+/// Information Service). Revisions the device has no endpoint for, or that can't be read, are left
+/// empty.
+pub async fn probe_device_information_version(hardware: Arc<Hardware>) -> DeviceVersion {
+  DeviceVersion::new(
+    &read_device_information_string(&hardware, Endpoint::RxBLEFirmwareRevision).await,
+    &read_device_information_string(&hardware, Endpoint::RxBLEHardwareRevision).await,
+  )
 }
 
 pub struct GenericProtocolIdentifier {
@@ -663,6 +796,7 @@ pub trait ProtocolHandler: Sync + Send {
           ActuatorType::Rotate => self.handle_scalar_rotate_cmd(index as u32, *scalar)?,
           ActuatorType::Vibrate => self.handle_scalar_vibrate_cmd(index as u32, *scalar)?,
           ActuatorType::Position => self.handle_scalar_position_cmd(index as u32, *scalar)?,
+          ActuatorType::Temperature => self.handle_scalar_temperature_cmd(index as u32, *scalar)?,
           ActuatorType::Unknown => Err(ButtplugDeviceError::UnhandledCommand(
             "Unknown actuator types are not controllable.".to_owned(),
           ))?,
@@ -720,6 +854,14 @@ pub trait ProtocolHandler: Sync + Send {
     self.command_unimplemented("ScalarCmd (Constrict Actuator)")
   }
 
+  fn handle_scalar_temperature_cmd(
+    &self,
+    _index: u32,
+    _scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("ScalarCmd (Temperature Actuator)")
+  }
+
   fn handle_vorze_a10_cyclone_cmd(
     &self,
     message: message::VorzeA10CycloneCmd,
@@ -755,6 +897,13 @@ pub trait ProtocolHandler: Sync + Send {
     self.command_unimplemented(print_type_of(&message))
   }
 
+  fn handle_rotate_position_cmd(
+    &self,
+    message: message::RotatePositionCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented(print_type_of(&message))
+  }
+
   fn handle_sensor_subscribe_cmd(
     &self,
     _device: Arc<Hardware>,
@@ -799,22 +948,7 @@ pub trait ProtocolHandler: Sync + Send {
     // If we have a standardized BLE Battery endpoint, handle that above the
     // protocol, as it'll always be the same.
     if device.endpoints().contains(&Endpoint::RxBLEBattery) {
-      debug!("Trying to get battery reading.");
-      let msg = HardwareReadCmd::new(Endpoint::RxBLEBattery, 1, 0);
-      let fut = device.read_value(&msg);
-      async move {
-        let hw_msg = fut.await?;
-        let battery_level = hw_msg.data()[0] as i32;
-        let battery_reading = message::SensorReading::new(
-          message.device_index(),
-          *message.sensor_index(),
-          *message.sensor_type(),
-          vec![battery_level],
-        );
-        debug!("Got battery reading: {}", battery_level);
-        Ok(battery_reading.into())
-      }
-      .boxed()
+      read_ble_battery_level(device, message)
     } else {
       future::ready(Err(ButtplugDeviceError::UnhandledCommand(
         "Command not implemented for this protocol: SensorReadCmd".to_string(),
@@ -1,7 +1,10 @@
 #[cfg(feature = "wasm")]
 use crate::util;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   generic_protocol_initializer_setup,
   server::device::{
     configuration::ProtocolDeviceAttributes,
@@ -18,6 +21,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use std::{
+  collections::HashMap,
   sync::{
     atomic::{AtomicBool, AtomicU16, Ordering},
     Arc,
@@ -221,6 +225,63 @@ impl Into<[u8; 4]> for Rumble {
   }
 }
 
+/// Frequency used when the device config has no frequency feature, or it's set to 0.
+const DEFAULT_RUMBLE_FREQUENCY: u16 = 200;
+
+/// Amplitude (in thousandths) and frequency (in Hz) of the left and right HD rumble actuators.
+#[derive(Default)]
+struct RumbleState {
+  amplitudes: [AtomicU16; 2],
+  frequencies: [AtomicU16; 2],
+}
+
+impl RumbleState {
+  fn rumble(&self, side: usize) -> Rumble {
+    let amp = self.amplitudes[side].load(Ordering::Relaxed) as f32 / 1000f32;
+    let freq = match self.frequencies[side].load(Ordering::Relaxed) {
+      0 => DEFAULT_RUMBLE_FREQUENCY,
+      freq => freq,
+    };
+    if amp > 0.001 {
+      Rumble::new(freq as f32, amp)
+    } else {
+      Rumble::stop()
+    }
+  }
+}
+
+/// Maps scalar feature indexes of the given actuator type to the rumble sides (0 is left, 1 is
+/// right) they drive. The nth feature drives the nth side, unless there's only one feature, in
+/// which case it drives both.
+fn feature_sides(
+  attributes: &ProtocolDeviceAttributes,
+  actuator_type: ActuatorType,
+) -> HashMap<u32, Vec<usize>> {
+  let indexes: Vec<u32> = attributes
+    .message_attributes()
+    .scalar_cmd()
+    .as_ref()
+    .map(|features| {
+      features
+        .iter()
+        .enumerate()
+        .filter(|(_, feature)| *feature.actuator_type() == actuator_type)
+        .map(|(index, _)| index as u32)
+        .collect()
+    })
+    .unwrap_or_default();
+  if indexes.len() == 1 {
+    HashMap::from([(indexes[0], vec![0, 1])])
+  } else {
+    indexes
+      .into_iter()
+      .take(2)
+      .enumerate()
+      .map(|(side, index)| (index, vec![side]))
+      .collect()
+  }
+}
+
 generic_protocol_initializer_setup!(NintendoJoycon, "nintendo-joycon");
 
 #[derive(Default)]
@@ -231,28 +292,57 @@ impl ProtocolInitializer for NintendoJoyconInitializer {
   async fn initialize(
     &mut self,
     hardware: Arc<Hardware>,
-    _: &ProtocolDeviceAttributes,
+    attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    // Over USB, the Pro Controller only accepts rumble reports once it's been told to talk HID
+    // directly, instead of waiting for a Bluetooth connection: handshake, then disable the USB
+    // timeout. These reports mean nothing over Bluetooth, so failures are expected there.
+    for usb_command in [0x02u8, 0x04] {
+      if let Err(err) = hardware
+        .write_value(&HardwareWriteCmd::new(
+          Endpoint::Tx,
+          vec![0x80, usb_command],
+          false,
+        ))
+        .await
+      {
+        debug!(
+          "Joycon USB setup command {:#04x} failed, assuming Bluetooth connection: {:?}",
+          usb_command, err
+        );
+        break;
+      }
+    }
     send_sub_command(hardware.clone(), 0, 72, &[0x01])
       .await
       .map_err(|_| {
         ButtplugDeviceError::DeviceConnectionError("Cannot initialize joycon".to_owned())
       })?;
-    Ok(Arc::new(NintendoJoycon::new(hardware)))
+    Ok(Arc::new(NintendoJoycon::new(
+      hardware,
+      feature_sides(attributes, ActuatorType::Vibrate),
+      feature_sides(attributes, ActuatorType::Oscillate),
+    )))
   }
 }
 
 pub struct NintendoJoycon {
   //packet_number: Arc<AtomicU8>,
-  speed_val: Arc<AtomicU16>,
+  rumble_state: Arc<RumbleState>,
+  amplitude_sides: HashMap<u32, Vec<usize>>,
+  frequency_sides: HashMap<u32, Vec<usize>>,
   notifier: Arc<Notify>,
   is_stopped: Arc<AtomicBool>,
 }
 
 impl NintendoJoycon {
-  fn new(hardware: Arc<Hardware>) -> Self {
-    let speed_val = Arc::new(AtomicU16::new(0));
-    let speed_val_clone = speed_val.clone();
+  fn new(
+    hardware: Arc<Hardware>,
+    amplitude_sides: HashMap<u32, Vec<usize>>,
+    frequency_sides: HashMap<u32, Vec<usize>>,
+  ) -> Self {
+    let rumble_state = Arc::new(RumbleState::default());
+    let rumble_state_clone = rumble_state.clone();
     let notifier = Arc::new(Notify::new());
     #[cfg(not(feature = "wasm"))]
     let notifier_clone = notifier.clone();
@@ -263,15 +353,19 @@ impl NintendoJoycon {
         if is_stopped_clone.load(Ordering::Relaxed) {
           return;
         }
-        let amp = speed_val_clone.load(Ordering::Relaxed) as f32 / 1000f32;
-        let rumble = if amp > 0.001 {
-          Rumble::new(200.0f32, amp)
-        } else {
-          Rumble::stop()
-        };
-
-        if let Err(_) =
-          send_command_raw(hardware.clone(), 1, 16, 0, &[], Some(rumble), Some(rumble)).await
+        let rumble_l = rumble_state_clone.rumble(0);
+        let rumble_r = rumble_state_clone.rumble(1);
+
+        if let Err(_) = send_command_raw(
+          hardware.clone(),
+          1,
+          16,
+          0,
+          &[],
+          Some(rumble_r),
+          Some(rumble_l),
+        )
+        .await
         {
           error!("Joycon command failed, exiting update loop");
           break;
@@ -288,7 +382,9 @@ impl NintendoJoycon {
     });
     Self {
       //packet_number: Arc::new(AtomicU8::new(0)),
-      speed_val,
+      rumble_state,
+      amplitude_sides,
+      frequency_sides,
       notifier,
       is_stopped,
     }
@@ -298,17 +394,30 @@ impl NintendoJoycon {
 impl ProtocolHandler for NintendoJoycon {
   fn handle_scalar_vibrate_cmd(
     &self,
-    _: u32,
+    index: u32,
     scalar: u32,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    self.speed_val.store(scalar as u16, Ordering::Relaxed);
+    for side in self.amplitude_sides.get(&index).into_iter().flatten() {
+      self.rumble_state.amplitudes[*side].store(scalar as u16, Ordering::Relaxed);
+    }
+    Ok(vec![])
+  }
+
+  fn handle_scalar_oscillate_cmd(
+    &self,
+    index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    for side in self.frequency_sides.get(&index).into_iter().flatten() {
+      self.rumble_state.frequencies[*side].store(scalar as u16, Ordering::Relaxed);
+    }
     Ok(vec![])
   }
 }
 
 impl Drop for NintendoJoycon {
   fn drop(&mut self) {
-    self.is_stopped.store(false, Ordering::Relaxed);
+    self.is_stopped.store(true, Ordering::Relaxed);
     self.notifier.notify_one();
   }
 }
@@ -44,6 +44,15 @@ impl ProtocolHandler for SvakomV3 {
     .into()])
   }
 
+  // The Alberta's suction lens is addressed the same way as a second vibrator.
+  fn handle_scalar_constrict_cmd(
+    &self,
+    index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.handle_scalar_vibrate_cmd(index, scalar)
+  }
+
   fn handle_scalar_rotate_cmd(
     &self,
     _index: u32,
@@ -36,6 +36,28 @@ impl ProtocolHandler for TCodeV03 {
     Ok(msg_vec)
   }
 
+  // Rotation axes (twist, roll, pitch on OSR/SR6 style machines) are servos, so they take the same
+  // position/interval format as linear axes.
+  fn handle_rotate_position_cmd(
+    &self,
+    msg: message::RotatePositionCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut msg_vec = vec![];
+    for v in msg.vectors() {
+      let position = (v.position() * 99f64) as u32;
+
+      let command = format!(
+        "R{}{:02}I{}
+",
+        v.index(),
+        position,
+        v.duration()
+      );
+      msg_vec.push(HardwareWriteCmd::new(Endpoint::Tx, command.as_bytes().to_vec(), false).into());
+    }
+    Ok(msg_vec)
+  }
+
   fn handle_scalar_vibrate_cmd(
     &self,
     index: u32,
@@ -0,0 +1,98 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Helpers for protocols made of newline terminated text commands, usually DIY firmware.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  server::device::hardware::{Hardware, HardwareEvent, HardwareWriteCmd},
+  util::sleep,
+};
+use futures::FutureExt;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+// Hobby boards (ESP32, Arduino Uno, etc) usually reset when a serial port is opened, and take a
+// second or two to boot, so keep asking for a while before giving up.
+const TEXT_LINE_COMMAND_TIMEOUT_MS: u64 = 500;
+const TEXT_LINE_COMMAND_RETRY: u64 = 6;
+
+/// Splits incoming data into lines. Serial ports hand over whatever bytes have arrived, so a line
+/// can be split across notifications, or a notification can hold multiple lines.
+#[derive(Default)]
+pub struct LineBuffer {
+  buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+  /// Adds data, returning any lines it completed with surrounding whitespace (including `\r`)
+  /// trimmed. Empty lines are skipped.
+  pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+    self.buffer.extend_from_slice(data);
+    let mut lines = vec![];
+    while let Some(pos) = self.buffer.iter().position(|x| *x == b'\n') {
+      let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+      let line = String::from_utf8_lossy(&line).trim().to_owned();
+      if !line.is_empty() {
+        lines.push(line);
+      }
+    }
+    lines
+  }
+}
+
+/// Builds a write of a single command line to the Tx endpoint.
+pub fn text_line_write_cmd(command: &str) -> HardwareWriteCmd {
+  HardwareWriteCmd::new(Endpoint::Tx, format!("{}\n", command).into_bytes(), false)
+}
+
+/// Sends a command line and waits for the first reply line that parse_reply accepts, resending the
+/// command if the device doesn't answer in time. Lines are handed to parse_reply split on
+/// whitespace.
+pub async fn text_line_request<T>(
+  protocol: &str,
+  hardware: &Hardware,
+  command: &str,
+  parse_reply: impl Fn(&[&str]) -> Option<T> + Send,
+) -> Result<T, ButtplugDeviceError> {
+  let mut event_receiver = hardware.event_stream();
+  let mut lines = LineBuffer::default();
+  for _ in 0..=TEXT_LINE_COMMAND_RETRY {
+    hardware.write_value(&text_line_write_cmd(command)).await?;
+    let timeout = sleep(Duration::from_millis(TEXT_LINE_COMMAND_TIMEOUT_MS)).fuse();
+    futures::pin_mut!(timeout);
+    loop {
+      select! {
+        event = event_receiver.recv().fuse() => match event {
+          Ok(HardwareEvent::Notification(_, _, data)) => {
+            let reply = lines.push(&data).into_iter().find_map(|line| {
+              parse_reply(&line.split_whitespace().collect::<Vec<&str>>())
+            });
+            if let Some(reply) = reply {
+              return Ok(reply);
+            }
+          }
+          Ok(HardwareEvent::Disconnected(_)) | Err(RecvError::Closed) => {
+            return Err(ButtplugDeviceError::ProtocolSpecificError(
+              protocol.to_owned(),
+              format!("Device disconnected while waiting for reply to {}.", command),
+            ));
+          }
+          Err(RecvError::Lagged(_)) => continue,
+        },
+        _ = timeout => break,
+      }
+    }
+  }
+  Err(ButtplugDeviceError::ProtocolSpecificError(
+    protocol.to_owned(),
+    format!(
+      "Device did not reply to {} after {} retries.",
+      command, TEXT_LINE_COMMAND_RETRY
+    ),
+  ))
+}
@@ -0,0 +1,174 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2023 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  generic_protocol_initializer_setup,
+  server::device::{
+    configuration::ProtocolDeviceAttributes,
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      ProtocolAttributesType,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+      ServerDeviceIdentifier,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::{
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::sync::Notify;
+
+/// Feature report that makes a haptic pad pulse.
+const HAPTIC_PULSE_REPORT: u8 = 0x8f;
+/// Length of a pulse (on time plus off time). Shorter pulses feel like a buzz, longer ones like
+/// separate clicks, so keep this short.
+const PULSE_PERIOD_US: u32 = 5000;
+/// How often pulses are resent. Each set of pulses lasts a bit longer than this, so they never run
+/// out between updates, but also don't keep going for long if we lose the device.
+const PULSE_UPDATE_INTERVAL: Duration = Duration::from_millis(1000);
+const PULSE_REPEAT_COUNT: u16 = 300;
+
+/// Builds a haptic pulse feature report for a pad (0 is right, 1 is left). Strength is the share
+/// of each pulse period the pad is on for, so 0 stops the pad.
+fn haptic_pulse(pad: u8, strength: u32, max_strength: u32) -> Vec<u8> {
+  let on_us = (PULSE_PERIOD_US * strength.min(max_strength))
+    .checked_div(max_strength)
+    .unwrap_or(0) as u16;
+  let (off_us, repeat_count) = if on_us == 0 {
+    (0, 0)
+  } else {
+    (PULSE_PERIOD_US as u16 - on_us, PULSE_REPEAT_COUNT)
+  };
+  // Feature reports are 64 bytes, following a 0 report ID.
+  let mut report = vec![0u8; 65];
+  report[1] = HAPTIC_PULSE_REPORT;
+  report[2] = 0x07;
+  report[3] = pad;
+  report[4..6].copy_from_slice(&on_us.to_le_bytes());
+  report[6..8].copy_from_slice(&off_us.to_le_bytes());
+  report[8..10].copy_from_slice(&repeat_count.to_le_bytes());
+  report
+}
+
+generic_protocol_initializer_setup!(ValveSteamController, "valve-steam-controller");
+
+#[derive(Default)]
+pub struct ValveSteamControllerInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for ValveSteamControllerInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let max_strengths: Vec<u32> = attributes
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .map(|features| {
+        features
+          .iter()
+          .map(|feature| *feature.step_range().end())
+          .collect()
+      })
+      .unwrap_or_default();
+    Ok(Arc::new(ValveSteamController::new(hardware, max_strengths)))
+  }
+}
+
+/// Valve's haptic pads (Steam Controller, Steam Deck) can only pulse, so vibration strength is the
+/// pulse duty cycle. Features are the left and right pads, in that order.
+pub struct ValveSteamController {
+  strengths: Arc<[AtomicU32; 2]>,
+  notifier: Arc<Notify>,
+  is_stopped: Arc<AtomicBool>,
+}
+
+impl ValveSteamController {
+  fn new(hardware: Arc<Hardware>, max_strengths: Vec<u32>) -> Self {
+    let strengths: Arc<[AtomicU32; 2]> = Arc::new([AtomicU32::new(0), AtomicU32::new(0)]);
+    let strengths_clone = strengths.clone();
+    let notifier = Arc::new(Notify::new());
+    let notifier_clone = notifier.clone();
+    let is_stopped = Arc::new(AtomicBool::new(false));
+    let is_stopped_clone = is_stopped.clone();
+    async_manager::spawn(async move {
+      let mut last_strengths = [0u32; 2];
+      loop {
+        if is_stopped_clone.load(Ordering::Relaxed) {
+          return;
+        }
+        for (index, strength) in strengths_clone.iter().enumerate() {
+          let strength = strength.load(Ordering::Relaxed);
+          // Stopped pads only need to be told once.
+          if strength == 0 && last_strengths[index] == 0 {
+            continue;
+          }
+          last_strengths[index] = strength;
+          // Feature order is left then right, the controller numbers them the other way around.
+          let pad = 1 - index as u8;
+          let max_strength = max_strengths.get(index).copied().unwrap_or_default();
+          if let Err(err) = hardware
+            .write_value(&HardwareWriteCmd::new(
+              Endpoint::TxFeature,
+              haptic_pulse(pad, strength, max_strength),
+              false,
+            ))
+            .await
+          {
+            error!(
+              "Steam Controller haptic command failed, exiting update loop: {:?}",
+              err
+            );
+            return;
+          }
+        }
+        select! {
+          _ = notifier_clone.notified().fuse() => {},
+          _ = sleep(PULSE_UPDATE_INTERVAL).fuse() => {},
+        }
+      }
+    });
+    Self {
+      strengths,
+      notifier,
+      is_stopped,
+    }
+  }
+}
+
+impl ProtocolHandler for ValveSteamController {
+  fn handle_scalar_vibrate_cmd(
+    &self,
+    index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    if let Some(strength) = self.strengths.get(index as usize) {
+      strength.store(scalar, Ordering::Relaxed);
+      self.notifier.notify_one();
+    }
+    Ok(vec![])
+  }
+}
+
+impl Drop for ValveSteamController {
+  fn drop(&mut self) {
+    self.is_stopped.store(true, Ordering::Relaxed);
+    self.notifier.notify_one();
+  }
+}
@@ -7,7 +7,12 @@
 
 use std::{
   fmt::{self, Debug},
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Mutex,
+    Weak,
+  },
   time::Duration,
 };
 
@@ -24,10 +29,14 @@ use crate::{
       ButtplugMessage,
       ButtplugServerDeviceMessage,
       ButtplugServerMessage,
+      DeviceTransport,
+      DeviceTransportType,
+      DeviceVersion,
       Endpoint,
       RSSILevelReading,
       RawReading,
       RawSubscribeCmd,
+      RotationMode,
       ScalarCmd,
       ScalarSubcommand,
       SensorDeviceMessageAttributes,
@@ -39,6 +48,7 @@ use crate::{
   server::{
     device::{
       configuration::{DeviceConfigurationManager, ProtocolAttributesType},
+      dfu::{self, DfuFirmwareImage},
       hardware::{Hardware, HardwareCommand, HardwareConnector, HardwareEvent},
       protocol::ProtocolHandler,
     },
@@ -48,20 +58,31 @@ use crate::{
 };
 use core::hash::{Hash, Hasher};
 use dashmap::DashSet;
-use futures::future::{self, FutureExt};
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  pin_mut,
+};
 use getset::{Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use tokio_stream::StreamExt;
 
 use super::{
   configuration::{ProtocolDeviceAttributes, ServerDeviceMessageAttributes},
-  hardware::HardwareWriteCmd,
   protocol::{
     generic_command_manager::GenericCommandManager,
+    read_ble_battery_level,
+    ProtocolIdentifier,
     ProtocolKeepaliveStrategy,
     ProtocolSpecializer,
   },
+  server_device_command_queue::{
+    DeviceCommandPriority,
+    DeviceCommandQueue,
+    DeviceCommandQueueSettings,
+    DeviceCommandStatistics,
+  },
+  server_device_motion_planner::MotionPlanner,
 };
 
 #[derive(Debug)]
@@ -98,16 +119,116 @@ impl ServerDeviceIdentifier {
   }
 }
 
+/// Limits on connecting to and initializing a device, set through the
+/// [ServerDeviceManagerBuilder](super::ServerDeviceManagerBuilder).
+#[derive(Debug, Clone, Copy)]
+pub(super) struct DeviceInitializationSettings {
+  /// How long protocol initialization can take before the attempt is given up on.
+  pub(super) timeout: Duration,
+  /// How many more times to try connecting and initializing BLE devices after a failed attempt.
+  pub(super) retries: u32,
+  /// How long to wait between attempts.
+  pub(super) retry_delay: Duration,
+}
+
 pub(super) async fn build_server_device(
   device_config_manager: Arc<DeviceConfigurationManager>,
   mut hardware_connector: Box<dyn HardwareConnector>,
   protocol_specializers: Vec<ProtocolSpecializer>,
+  command_queue_settings: DeviceCommandQueueSettings,
+  initialization_settings: DeviceInitializationSettings,
 ) -> Result<ServerDevice, ButtplugDeviceError> {
   // We've already checked to make sure we have specializers in the server device manager event
   // loop. That check used to be here for sake of continuity in building devices in this method, but
   // having that done before we get here fixes issues with some device advertisement timing (See
   // #462 for more info.)
+  let transport_type = hardware_connector.specifier().transport_type();
+
+  // BLE connections regularly come up in a state where the device never answers, which a fresh
+  // connection usually fixes. Other transports fail the same way every time, so don't bother.
+  let retries = if transport_type == DeviceTransportType::BluetoothLE {
+    initialization_settings.retries
+  } else {
+    0
+  };
+  let mut protocol_specializers = Some(protocol_specializers);
+  let mut attempt = 0;
+  let (hardware, identifier, attrs, version, handler) = loop {
+    // Specializers are used up by each attempt, so look them up again for retries.
+    let specializers = protocol_specializers.take().unwrap_or_else(|| {
+      device_config_manager.protocol_specializers(&hardware_connector.specifier())
+    });
+    match connect_and_initialize(
+      &device_config_manager,
+      hardware_connector.as_mut(),
+      specializers,
+      initialization_settings.timeout,
+    )
+    .await
+    {
+      Ok(initialized) => break initialized,
+      Err(err) if attempt < retries => {
+        attempt += 1;
+        warn!(
+          "Device initialization failed ({}), retrying in {:?} (attempt {} of {}).",
+          err, initialization_settings.retry_delay, attempt, retries
+        );
+        util::sleep(initialization_settings.retry_delay).await;
+      }
+      Err(err) => return Err(err),
+    }
+  };
+
+  let requires_keepalive = hardware.requires_keepalive();
+  let strategy = handler.keepalive_strategy();
 
+  // We now have fully initialized hardware, return a server device.
+  let device = ServerDevice::new(
+    identifier,
+    handler,
+    hardware,
+    transport_type,
+    version,
+    &attrs,
+    command_queue_settings,
+  );
+
+  // If we need a keepalive with a packet replay, set this up via stopping the device on connect.
+  if requires_keepalive
+    && matches!(
+      strategy,
+      ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
+    )
+  {
+    if let Err(e) = device.handle_stop_device_cmd().await {
+      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+        "Error setting up keepalive: {}",
+        e
+      )));
+    }
+  }
+
+  Ok(device)
+}
+
+/// Connects to the hardware and initializes its protocol, returning everything needed to build the
+/// [ServerDevice]. If initialization fails, the hardware is disconnected so it can be connected
+/// again.
+async fn connect_and_initialize(
+  device_config_manager: &DeviceConfigurationManager,
+  hardware_connector: &mut dyn HardwareConnector,
+  protocol_specializers: Vec<ProtocolSpecializer>,
+  timeout: Duration,
+) -> Result<
+  (
+    Arc<Hardware>,
+    ServerDeviceIdentifier,
+    ProtocolDeviceAttributes,
+    DeviceVersion,
+    Arc<dyn ProtocolHandler>,
+  ),
+  ButtplugDeviceError,
+> {
   // At this point, we know we've got hardware that is waiting to connect, and enough protocol
   // info to actually do something after we connect. So go ahead and connect.
   let mut hardware_specializer = hardware_connector.connect().await?;
@@ -132,22 +253,121 @@ pub(super) async fn build_server_device(
     ));
   }
 
-  let mut protocol_identifier_stage = protocol_identifier.unwrap();
+  let protocol_identifier_stage = protocol_identifier.unwrap();
   let hardware = Arc::new(hardware_out.unwrap());
 
-  let (identifier, mut protocol_initializer) =
+  // Protocol initialization talks to the device, and can wait forever on a flaky connection that
+  // never answers, so give up after the timeout.
+  let initialization = initialize_protocol(
+    device_config_manager,
+    protocol_identifier_stage,
+    hardware.clone(),
+  );
+  let timeout_fut = util::sleep(timeout);
+  pin_mut!(initialization, timeout_fut);
+  let result = match future::select(initialization, timeout_fut).await {
+    future::Either::Left((result, _)) => result,
+    future::Either::Right(_) => Err(ButtplugDeviceError::DeviceConnectionError(format!(
+      "Protocol initialization for device {} timed out after {:?}.",
+      hardware.name(),
+      timeout
+    ))),
+  };
+  match result {
+    Ok((identifier, attrs, version, handler)) => {
+      Ok((hardware, identifier, attrs, version, handler))
+    }
+    Err(err) => {
+      if let Err(disconnect_err) = hardware.disconnect().await {
+        warn!(
+          "Could not disconnect device {} after initialization failed: {:?}",
+          hardware.name(),
+          disconnect_err
+        );
+      }
+      Err(err)
+    }
+  }
+}
+
+/// Identifies the model of specialized hardware, works out its attributes and initializes its
+/// protocol, returning everything needed to build the [ServerDevice].
+async fn initialize_protocol(
+  device_config_manager: &DeviceConfigurationManager,
+  mut protocol_identifier_stage: Box<dyn ProtocolIdentifier>,
+  hardware: Arc<Hardware>,
+) -> Result<
+  (
+    ServerDeviceIdentifier,
+    ProtocolDeviceAttributes,
+    DeviceVersion,
+    Arc<dyn ProtocolHandler>,
+  ),
+  ButtplugDeviceError,
+> {
+  let (mut identifier, mut protocol_initializer) =
     protocol_identifier_stage.identify(hardware.clone()).await?;
 
+  if !hardware.device_information().is_empty() {
+    info!(
+      "Device {} reported device information {:?}",
+      hardware.name(),
+      hardware.device_information()
+    );
+  }
+
+  // If the device only matched its protocol and not a specific model, it'd get the protocol's
+  // default attributes, which may not match what it actually has. Ask the device what model it is,
+  // and use that configuration if we have one. Whatever the device reported about itself during
+  // specialization is tried first, then the protocol's own probe.
+  if !device_config_manager.has_model_attributes(&identifier) {
+    let mut candidates: Vec<ProtocolAttributesType> = hardware
+      .device_information()
+      .identifiers()
+      .into_iter()
+      .map(ProtocolAttributesType::Identifier)
+      .collect();
+    if let Some(probed) = protocol_initializer
+      .probe_identifier(hardware.clone())
+      .await
+    {
+      if !candidates.contains(&probed) {
+        candidates.push(probed);
+      }
+    }
+    for probed in candidates {
+      let probed_identifier =
+        ServerDeviceIdentifier::new(identifier.address(), identifier.protocol(), &probed);
+      if device_config_manager.has_model_attributes(&probed_identifier) {
+        info!(
+          "Device {} identified as {:?} by probing.",
+          hardware.name(),
+          probed
+        );
+        identifier = probed_identifier;
+        break;
+      } else {
+        debug!(
+          "Probed identifier {:?} for device {} has no configuration.",
+          probed,
+          hardware.name()
+        );
+      }
+    }
+  }
+
   // Now we have an identifier. After this point, if anything fails, consider it a complete
   // connection failure, as identify may have already run commands on the device, and therefore
   // put it in an unknown state if anything fails.
 
   // Check in the DeviceConfigurationManager to make sure we have attributes
-  // for this device.
-  let attrs = if let Some(attrs) =
+  // for this device, falling back to anything the hardware knows about itself.
+  let mut attrs = if let Some(attrs) =
     device_config_manager.protocol_device_attributes(&identifier, &hardware.endpoints())
   {
     attrs
+  } else if let Some(attrs) = hardware.device_attributes() {
+    attrs.clone()
   } else {
     return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
       "No protocols with viable protocol attributes for hardware {:?}.",
@@ -155,48 +375,66 @@ pub(super) async fn build_server_device(
     )));
   };
 
+  // Devices that can describe themselves get their announced features instead of the protocol
+  // defaults, unless someone configured the model or the device specifically.
+  if !device_config_manager.has_model_attributes(&identifier) {
+    if let Some(announced) = protocol_initializer
+      .probe_attributes(hardware.clone())
+      .await
+    {
+      info!(
+        "Device {} announced its own features, using them instead of protocol defaults.",
+        hardware.name()
+      );
+      attrs.merge_announced_attributes(&announced);
+    }
+  }
+
   // If we have attributes, go ahead and initialize, handing us back our hardware instance that
   // is now ready to use with the protocol handler.
 
   // Build the server device and return.
 
+  // Ask for revisions before initializing, as some protocols can only query them before the
+  // device is put into its control mode.
+  let version = protocol_initializer.probe_version(hardware.clone()).await;
+
   let handler = protocol_initializer
     .initialize(hardware.clone(), &attrs)
     .await?;
 
-  let requires_keepalive = hardware.requires_keepalive();
-  let strategy = handler.keepalive_strategy();
-
-  // We now have fully initialized hardware, return a server device.
-  let device = ServerDevice::new(identifier, handler, hardware, &attrs);
-
-  // If we need a keepalive with a packet replay, set this up via stopping the device on connect.
-  if requires_keepalive
-    && matches!(
-      strategy,
-      ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
-    )
-  {
-    if let Err(e) = device.handle_stop_device_cmd().await {
-      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
-        "Error setting up keepalive: {}",
-        e
-      )));
-    }
-  }
-
-  Ok(device)
+  Ok((identifier, attrs, version, handler))
 }
 
 pub struct ServerDevice {
   hardware: Arc<Hardware>,
   handler: Arc<dyn ProtocolHandler>,
   attributes: ProtocolDeviceAttributes,
+  /// Message attributes merged from the device attributes and their parents. These don't change
+  /// while the device is connected, so we resolve them once instead of on every command.
+  message_attributes: ServerDeviceMessageAttributes,
+  /// User configured display name. Kept separately from the attributes, as it can change while the
+  /// device is connected.
+  display_name: Mutex<Option<String>>,
   generic_command_manager: GenericCommandManager,
   /// Unique identifier for the device
   identifier: ServerDeviceIdentifier,
+  /// Type of connection the hardware is using
+  transport_type: DeviceTransportType,
+  /// Firmware and hardware revisions read while initializing
+  version: DeviceVersion,
   raw_subscribed_endpoints: Arc<DashSet<Endpoint>>,
-  keepalive_packet: Arc<RwLock<Option<HardwareWriteCmd>>>,
+  /// Queue that runs the hardware commands generated by the protocol handler, in priority order.
+  command_queue: DeviceCommandQueue,
+  /// Set while a firmware update runs, commands are refused until it's done.
+  firmware_updating: Arc<AtomicBool>,
+  /// Slows down linear moves the device can't keep up with, if it has configured limits.
+  motion_planner: Option<MotionPlanner>,
+  /// Highest level heating elements may be driven to, if the configuration caps them.
+  temperature_limit: Option<f64>,
+  /// Index of the battery sensor read through the standard BLE Battery Service, if the device
+  /// config doesn't provide one of its own.
+  ble_battery_sensor: Option<u32>,
 }
 impl Debug for ServerDevice {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -228,7 +466,10 @@ impl ServerDevice {
     identifier: ServerDeviceIdentifier,
     handler: Arc<dyn ProtocolHandler>,
     hardware: Arc<Hardware>,
+    transport_type: DeviceTransportType,
+    version: DeviceVersion,
     attributes: &ProtocolDeviceAttributes,
+    command_queue_settings: DeviceCommandQueueSettings,
   ) -> Self {
     let keepalive_packet = Arc::new(RwLock::new(None));
     let gcm = GenericCommandManager::new(attributes);
@@ -277,14 +518,44 @@ impl ServerDevice {
       });
     }
 
+    let mut attributes = attributes.clone();
+    let ble_battery_sensor = if hardware.endpoints().contains(&Endpoint::RxBLEBattery) {
+      attributes.add_ble_battery_sensor()
+    } else {
+      None
+    };
+    if ble_battery_sensor.is_some() {
+      info!(
+        "{} exposes the BLE Battery Service, adding battery sensor.",
+        hardware.name()
+      );
+    }
+
+    let command_queue = DeviceCommandQueue::new(
+      hardware.clone(),
+      handler.keepalive_strategy(),
+      keepalive_packet,
+      command_queue_settings,
+      attributes.max_command_rate(),
+      gcm.sent_values_invalidator(),
+    );
+
     Self {
       identifier,
+      transport_type,
+      version,
       generic_command_manager: gcm,
       handler,
       hardware,
-      keepalive_packet,
+      command_queue,
       attributes: attributes.clone(),
+      message_attributes: attributes.message_attributes(),
+      display_name: Mutex::new(attributes.display_name()),
       raw_subscribed_endpoints: Arc::new(DashSet::new()),
+      firmware_updating: Arc::new(AtomicBool::new(false)),
+      motion_planner: attributes.linear_motion_limits().map(MotionPlanner::new),
+      temperature_limit: attributes.temperature_limit(),
+      ble_battery_sensor,
     }
   }
 
@@ -295,12 +566,25 @@ impl ServerDevice {
 
   /// Get the user created display name for a device, if one exists.
   pub fn display_name(&self) -> Option<String> {
-    self.attributes.display_name()
+    self
+      .display_name
+      .lock()
+      .expect("Lock is never held across a panic")
+      .clone()
+  }
+
+  /// Set or clear the user created display name for a device.
+  pub(crate) fn set_display_name(&self, display_name: Option<String>) {
+    *self
+      .display_name
+      .lock()
+      .expect("Lock is never held across a panic") = display_name;
   }
 
   /// Get the name of the device as set in the Device Configuration File.
   ///
-  /// This will also append "(Raw Messaged Allowed)" to the device name if raw mode is on, to warn
+  /// This will also append "(Raw Messaged Allowed)" to the device name if raw messages are allowed
+  /// for the device, either for all devices or just this one via its user configuration, to warn
   /// users that the device is capable of direct communication.
   pub fn name(&self) -> String {
     // Instead of checking for raw messages at the protocol level, add the raw
@@ -321,15 +605,179 @@ impl ServerDevice {
     }
   }
 
+  /// Get the transport type and hardware address of the device.
+  pub fn transport(&self) -> DeviceTransport {
+    DeviceTransport::new(self.transport_type, self.hardware.address())
+  }
+
+  /// Get the firmware and hardware revisions the device reported while initializing, if it reported
+  /// any.
+  pub fn version(&self) -> Option<DeviceVersion> {
+    if self.version.is_empty() {
+      None
+    } else {
+      Some(self.version.clone())
+    }
+  }
+
+  /// Battery level (0.0-1.0) in a reply or event from this device, if it's a battery reading.
+  /// Sensor readings are scaled by the top of the sensor's configured range.
+  pub fn battery_level(&self, msg: &ButtplugServerMessage) -> Option<f64> {
+    match msg {
+      ButtplugServerMessage::BatteryLevelReading(reading) => Some(reading.battery_level()),
+      ButtplugServerMessage::SensorReading(reading)
+        if reading.sensor_type() == SensorType::Battery =>
+      {
+        let sensor = self
+          .message_attributes
+          .sensor_read_cmd()
+          .as_ref()?
+          .get(reading.sensor_index() as usize)?;
+        let range_end = *sensor.sensor_range().first()?.end();
+        let level = *reading.data().first()?;
+        (range_end > 0).then(|| (level as f64 / range_end as f64).clamp(0.0, 1.0))
+      }
+      _ => None,
+    }
+  }
+
+  /// Index of the battery sensor and the interval to poll it at, if the device configuration sets
+  /// a polling interval and the device has a battery sensor.
+  fn battery_polling(&self) -> Option<(u32, Duration)> {
+    let interval = self.attributes.battery_polling_interval()?;
+    let sensor_index = self
+      .message_attributes
+      .sensor_read_cmd()
+      .as_ref()?
+      .iter()
+      .position(|sensor| *sensor.sensor_type() == SensorType::Battery)?;
+    Some((sensor_index as u32, Duration::from_millis(interval as u64)))
+  }
+
+  /// Task that reads the battery at the interval set in the device configuration, sending each
+  /// reading out as a device event. Returns None if the device shouldn't be polled. The task ends
+  /// once the device is dropped or nothing is listening for events anymore.
+  pub(super) fn battery_polling_task(
+    self: &Arc<Self>,
+    device_index: u32,
+    event_sender: mpsc::Sender<ServerDeviceEvent>,
+  ) -> Option<BoxFuture<'static, ()>> {
+    let (sensor_index, interval) = self.battery_polling()?;
+    let device = Arc::downgrade(self);
+    let identifier = self.identifier.clone();
+    Some(
+      async move {
+        loop {
+          util::sleep(interval).await;
+          let reading = {
+            let Some(device) = Weak::upgrade(&device) else {
+              break;
+            };
+            device
+              .parse_message(
+                SensorReadCmd::new(device_index, sensor_index, SensorType::Battery).into(),
+              )
+              .await
+          };
+          match reading {
+            Ok(ButtplugServerMessage::SensorReading(reading)) => {
+              let event = ServerDeviceEvent::Notification(
+                identifier.clone(),
+                ButtplugServerDeviceMessage::SensorReading(reading),
+              );
+              if event_sender.send(event).await.is_err() {
+                break;
+              }
+            }
+            Ok(msg) => warn!("Unexpected reply to battery poll: {:?}", msg),
+            Err(err) => debug!("Battery poll for device {} failed: {:?}", device_index, err),
+          }
+        }
+        debug!("Exiting battery polling task for device {}", device_index);
+      }
+      .boxed(),
+    )
+  }
+
+  /// Statistics about the commands sent to the device since it connected.
+  pub fn command_statistics(&self) -> DeviceCommandStatistics {
+    self.command_queue.statistics()
+  }
+
   /// Disconnect from the device, if it's connected.
   pub fn disconnect(&self) -> ButtplugResultFuture {
     let fut = self.hardware.disconnect();
     async move { fut.await.map_err(|err| err.into()) }.boxed()
   }
 
+  /// True if the device exposes a bootloader its firmware can be updated through.
+  pub fn supports_firmware_update(&self) -> bool {
+    dfu::bootloader_for(&self.hardware).is_some()
+  }
+
+  /// Stop the device and write new firmware to it through its bootloader. Commands are refused
+  /// while the update runs. Once it succeeds, the device is disconnected, as it restarts into the
+  /// new firmware.
+  pub fn update_firmware(&self, image: DfuFirmwareImage) -> ButtplugResultFuture {
+    let Some(bootloader) = dfu::bootloader_for(&self.hardware) else {
+      return future::ready(Err(
+        ButtplugDeviceError::DeviceFirmwareUpdateError(format!(
+          "{} does not expose a supported bootloader",
+          self.name()
+        ))
+        .into(),
+      ))
+      .boxed();
+    };
+    // Stop before refusing commands, so the stop itself goes through.
+    let stop_fut = self.handle_stop_device_cmd();
+    if self.firmware_updating.swap(true, Ordering::SeqCst) {
+      return future::ready(Err(
+        ButtplugDeviceError::DeviceFirmwareUpdating(self.name()).into(),
+      ))
+      .boxed();
+    }
+    let hardware = self.hardware.clone();
+    let firmware_updating = self.firmware_updating.clone();
+    async move {
+      if let Err(err) = stop_fut.await {
+        warn!(
+          "Could not stop {} before firmware update: {:?}",
+          hardware.name(),
+          err
+        );
+      }
+      info!(
+        "Updating firmware of {} via {}",
+        hardware.name(),
+        bootloader.name()
+      );
+      if let Err(err) = bootloader.update(hardware.clone(), &image).await {
+        error!("Firmware update of {} failed: {:?}", hardware.name(), err);
+        firmware_updating.store(false, Ordering::SeqCst);
+        return Err(err.into());
+      }
+      info!(
+        "Firmware update of {} finished, disconnecting",
+        hardware.name()
+      );
+      // The bootloader restarts the device into the new firmware, make sure we let go of it so it
+      // shows up again on the next scan.
+      if let Err(err) = hardware.disconnect().await {
+        debug!(
+          "Error disconnecting {} after firmware update: {:?}",
+          hardware.name(),
+          err
+        );
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
   /// Retreive the message attributes for the device.
   pub fn message_attributes(&self) -> ServerDeviceMessageAttributes {
-    self.attributes.message_attributes()
+    self.message_attributes.clone()
   }
 
   /// Retreive the event stream for the device.
@@ -407,6 +855,10 @@ impl ServerDevice {
       ButtplugDeviceCommandMessageUnion::RotateCmd(_) => {
         check_msg(ButtplugDeviceMessageType::RotateCmd)
       }
+      // Positional rotators are still listed under RotateCmd attributes.
+      ButtplugDeviceCommandMessageUnion::RotatePositionCmd(_) => {
+        check_msg(ButtplugDeviceMessageType::RotateCmd)
+      }
       ButtplugDeviceCommandMessageUnion::RSSILevelCmd(_) => {
         check_msg(ButtplugDeviceMessageType::RSSILevelCmd)
       }
@@ -445,16 +897,56 @@ impl ServerDevice {
   pub fn parse_message(
     &self,
     command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    let priority = if matches!(
+      command_message,
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_)
+    ) {
+      DeviceCommandPriority::High
+    } else {
+      DeviceCommandPriority::Normal
+    };
+    self.parse_message_with_priority(command_message, priority)
+  }
+
+  /// Brings outputs last commanded over `ceiling` (0.0-1.0) down to it, for when an intensity
+  /// ceiling is set while the device is running.
+  pub(crate) fn apply_intensity_ceiling(&self, ceiling: f64) -> ButtplugServerResultFuture {
+    let fut_vec: Vec<_> = self
+      .generic_command_manager
+      .ceiling_commands(ceiling)
+      .into_iter()
+      .map(|msg| self.parse_message(msg))
+      .collect();
+    async move {
+      for fut in fut_vec {
+        fut.await?;
+      }
+      Ok(message::Ok::default().into())
+    }
+    .boxed()
+  }
+
+  fn parse_message_with_priority(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+    priority: DeviceCommandPriority,
   ) -> ButtplugServerResultFuture {
     if let Err(err) = self.supports_message(&command_message) {
       return future::ready(Err(err)).boxed();
     }
+    if self.firmware_updating.load(Ordering::SeqCst) {
+      return future::ready(Err(
+        ButtplugDeviceError::DeviceFirmwareUpdating(self.name()).into(),
+      ))
+      .boxed();
+    }
 
     // If a handler implements handle message, bypass all of our parsing and let it do its own
     // thing. This should be a very rare thing.
     if self.handler.has_handle_message() {
-      let fut = self.handle_generic_command_result(self.handler.handle_message(&command_message));
-      return async move { fut.await }.boxed();
+      return self
+        .handle_generic_command_result(self.handler.handle_message(&command_message), priority);
     }
 
     match command_message {
@@ -467,7 +959,7 @@ impl ServerDevice {
       }
       ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => self.handle_stop_device_cmd(),
       ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg) => {
-        self.handle_single_motor_vibrate_cmd(msg)
+        self.handle_single_motor_vibrate_cmd(msg, priority)
       }
       ButtplugDeviceCommandMessageUnion::BatteryLevelCmd(_) => self.handle_battery_level_cmd(),
       ButtplugDeviceCommandMessageUnion::RSSILevelCmd(_) => self.handle_rssi_level_cmd(),
@@ -476,13 +968,13 @@ impl ServerDevice {
       // use the generic command manager for, but still need protocol level translation.
       ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
         // TODO Add ability to turn off actuator matching
-        let attributes = self.attributes.message_attributes();
-        let attrs = attributes
+        let attrs = self
+          .message_attributes
           .scalar_cmd()
           .as_ref()
           .expect("Already checked existence");
         for command in msg.scalars() {
-          if command.index() > attrs.len() as u32 {
+          if command.index() >= attrs.len() as u32 {
             return future::ready(Err(
               ButtplugDeviceError::DeviceFeatureIndexError(attrs.len() as u32, command.index())
                 .into(),
@@ -501,6 +993,7 @@ impl ServerDevice {
             .boxed();
           }
         }
+        let msg = self.limit_temperature(msg);
 
         let commands = match self
           .generic_command_manager
@@ -517,7 +1010,7 @@ impl ServerDevice {
           return future::ready(Ok(message::Ok::default().into())).boxed();
         }
 
-        self.handle_generic_command_result(self.handler.handle_scalar_cmd(&commands))
+        self.handle_generic_command_result(self.handler.handle_scalar_cmd(&commands), priority)
       }
       ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
         let commands = match self
@@ -527,19 +1020,56 @@ impl ServerDevice {
           Ok(values) => values,
           Err(err) => return future::ready(Err(err)).boxed(),
         };
-        self.handle_generic_command_result(self.handler.handle_rotate_cmd(&commands))
+        self.handle_generic_command_result(self.handler.handle_rotate_cmd(&commands), priority)
       }
-      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
-        self.parse_message(ScalarCmd::from(msg).into())
+      ButtplugDeviceCommandMessageUnion::RotatePositionCmd(msg) => {
+        let attrs = self
+          .message_attributes
+          .rotate_cmd()
+          .as_ref()
+          .expect("Already checked existence");
+        for vector in msg.vectors() {
+          let Some(attr) = attrs.get(vector.index() as usize) else {
+            return future::ready(Err(
+              ButtplugDeviceError::DeviceFeatureIndexError(attrs.len() as u32, vector.index())
+                .into(),
+            ))
+            .boxed();
+          };
+          if !attr.is_positional_rotator() {
+            return future::ready(Err(
+              ButtplugDeviceError::DeviceRotationModeMismatch(
+                vector.index(),
+                RotationMode::Positional,
+                attr.rotation_mode().unwrap_or_default(),
+              )
+              .into(),
+            ))
+            .boxed();
+          }
+        }
+        self.handle_generic_command_result(self.handler.handle_rotate_position_cmd(msg), priority)
       }
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => self.handle_vibrate_cmd(msg, priority),
       ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_linear_cmd(msg))
+        let msg = match &self.motion_planner {
+          Some(planner) => planner.plan(msg),
+          None => msg,
+        };
+        self.handle_generic_command_result(self.handler.handle_linear_cmd(msg), priority)
       }
       ButtplugDeviceCommandMessageUnion::FleshlightLaunchFW12Cmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_fleshlight_launch_fw12_cmd(msg))
+        // These bypass planning, so we no longer know where the device is.
+        if let Some(planner) = &self.motion_planner {
+          planner.reset();
+        }
+        self.handle_generic_command_result(
+          self.handler.handle_fleshlight_launch_fw12_cmd(msg),
+          priority,
+        )
       }
       ButtplugDeviceCommandMessageUnion::VorzeA10CycloneCmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_vorze_a10_cyclone_cmd(msg))
+        self.handle_generic_command_result(self.handler.handle_vorze_a10_cyclone_cmd(msg), priority)
       }
       ButtplugDeviceCommandMessageUnion::SensorReadCmd(msg) => self.handle_sensor_read_cmd(msg),
       ButtplugDeviceCommandMessageUnion::SensorSubscribeCmd(msg) => {
@@ -556,53 +1086,30 @@ impl ServerDevice {
     }
   }
 
-  fn handle_hardware_commands(&self, commands: Vec<HardwareCommand>) -> ButtplugServerResultFuture {
-    let hardware = self.hardware.clone();
-    let keepalive_type = self.handler.keepalive_strategy();
-    let keepalive_packet = self.keepalive_packet.clone();
-    async move {
-      // Run commands in order, otherwise we may end up sending out of order. This may take a while,
-      // but it's what 99% of protocols expect. If they want something else, they can implement it
-      // themselves.
-      //
-      // If anything errors out, just bail on the command series. This most likely means the device
-      // disconnected.
-      for command in commands {
-        hardware.parse_message(&command).await?;
-        if hardware.requires_keepalive()
-          && matches!(
-            keepalive_type,
-            ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
-          )
-        {
-          if let HardwareCommand::Write(command) = command {
-            *keepalive_packet.write().await = Some(command);
-          }
-        }
-      }
-      Ok(message::Ok::default().into())
-    }
-    .boxed()
-  }
-
   fn handle_generic_command_result(
     &self,
     command_result: Result<Vec<HardwareCommand>, ButtplugDeviceError>,
+    priority: DeviceCommandPriority,
   ) -> ButtplugServerResultFuture {
     let hardware_commands = match command_result {
       Ok(commands) => commands,
       Err(err) => return future::ready(Err(err.into())).boxed(),
     };
 
-    self.handle_hardware_commands(hardware_commands)
+    self.command_queue.send(priority, hardware_commands)
   }
 
   fn handle_stop_device_cmd(&self) -> ButtplugServerResultFuture {
+    // Queued commands are about to be dropped in favor of the stop, so we can't assume the device
+    // has the values they carried. Make sure the stop is sent in full.
+    if self.command_queue.has_normal_commands() {
+      self.generic_command_manager.invalidate_sent_values();
+    }
     let commands = self.generic_command_manager.stop_commands();
     let mut fut_vec = vec![];
-    commands
-      .iter()
-      .for_each(|msg| fut_vec.push(self.parse_message(msg.clone())));
+    commands.iter().for_each(|msg| {
+      fut_vec.push(self.parse_message_with_priority(msg.clone(), DeviceCommandPriority::High))
+    });
     async move {
       for fut in fut_vec {
         fut.await?;
@@ -639,7 +1146,7 @@ impl ServerDevice {
   fn handle_sensor_read_cmd(&self, message: message::SensorReadCmd) -> ButtplugServerResultFuture {
     let result = self.check_sensor_command(
       self
-        .message_attributes()
+        .message_attributes
         .sensor_read_cmd()
         .as_ref()
         .expect("Already checked validity"),
@@ -647,6 +1154,14 @@ impl ServerDevice {
       message.sensor_type(),
     );
     let device = self.hardware.clone();
+    if result.is_ok() && self.ble_battery_sensor == Some(*message.sensor_index()) {
+      return async move {
+        read_ble_battery_level(device, message)
+          .await
+          .map_err(|e| e.into())
+      }
+      .boxed();
+    }
     let handler = self.handler.clone();
     async move {
       result?;
@@ -664,7 +1179,7 @@ impl ServerDevice {
   ) -> ButtplugServerResultFuture {
     let result = self.check_sensor_command(
       self
-        .message_attributes()
+        .message_attributes
         .sensor_subscribe_cmd()
         .as_ref()
         .expect("Already checked validity"),
@@ -689,7 +1204,7 @@ impl ServerDevice {
   ) -> ButtplugServerResultFuture {
     let result = self.check_sensor_command(
       self
-        .message_attributes()
+        .message_attributes
         .sensor_subscribe_cmd()
         .as_ref()
         .expect("Already checked validity"),
@@ -708,11 +1223,88 @@ impl ServerDevice {
     .boxed()
   }
 
+  // VibrateCmd comes from older clients, which only see the vibrators of a device (see
+  // GenericDeviceMessageAttributesV2::vibrate_cmd_from_scalar_cmd), so indexes need to be mapped
+  // to the vibrators' scalar indexes. Otherwise a device like [Oscillate, Vibrate] would have its
+  // oscillator driven by vibration speeds.
+  fn handle_vibrate_cmd(
+    &self,
+    message: message::VibrateCmd,
+    priority: DeviceCommandPriority,
+  ) -> ButtplugServerResultFuture {
+    let vibrator_indexes: Vec<u32> = self
+      .message_attributes
+      .scalar_cmd()
+      .as_ref()
+      .map(|attrs| {
+        attrs
+          .iter()
+          .enumerate()
+          .filter(|(_, x)| *x.actuator_type() == ActuatorType::Vibrate)
+          .map(|(index, _)| index as u32)
+          .collect()
+      })
+      .unwrap_or_default();
+    let mut scalars = vec![];
+    for speed in message.speeds() {
+      let Some(index) = vibrator_indexes.get(speed.index() as usize) else {
+        return ButtplugDeviceError::DeviceFeatureIndexError(
+          vibrator_indexes.len() as u32,
+          speed.index(),
+        )
+        .into();
+      };
+      scalars.push(ScalarSubcommand::new(
+        *index,
+        speed.speed(),
+        ActuatorType::Vibrate,
+      ));
+    }
+    let mut scalar_cmd = ScalarCmd::new(message.device_index(), scalars);
+    scalar_cmd.set_id(message.id());
+    self.parse_message_with_priority(scalar_cmd.into(), priority)
+  }
+
+  /// Caps temperature subcommands at the configured limit, so heaters never run hotter than the
+  /// device config allows regardless of what the client asks for.
+  fn limit_temperature(&self, message: ScalarCmd) -> ScalarCmd {
+    let Some(limit) = self.temperature_limit else {
+      return message;
+    };
+    if !message
+      .scalars()
+      .iter()
+      .any(|x| x.actuator_type() == ActuatorType::Temperature && x.scalar() > limit)
+    {
+      return message;
+    }
+    debug!(
+      "Limiting temperature commands for {} to {}",
+      self.name(),
+      limit
+    );
+    let scalars = message
+      .scalars()
+      .iter()
+      .map(|x| {
+        if x.actuator_type() == ActuatorType::Temperature && x.scalar() > limit {
+          ScalarSubcommand::new(x.index(), limit, ActuatorType::Temperature)
+        } else {
+          x.clone()
+        }
+      })
+      .collect();
+    let mut limited = ScalarCmd::new(message.device_index(), scalars);
+    limited.set_id(message.id());
+    limited
+  }
+
   fn handle_single_motor_vibrate_cmd(
     &self,
     message: message::SingleMotorVibrateCmd,
+    priority: DeviceCommandPriority,
   ) -> ButtplugServerResultFuture {
-    if let Some(attr) = self.attributes.message_attributes().scalar_cmd() {
+    if let Some(attr) = self.message_attributes.scalar_cmd() {
       let speed = message.speed();
       let cmds: Vec<ScalarSubcommand> = attr
         .iter()
@@ -729,7 +1321,7 @@ impl ServerDevice {
       } else {
         let mut vibrate_cmd = ScalarCmd::new(message.device_index(), cmds);
         vibrate_cmd.set_id(message.id());
-        self.parse_message(vibrate_cmd.into())
+        self.parse_message_with_priority(vibrate_cmd.into(), priority)
       }
     } else {
       ButtplugDeviceError::ProtocolRequirementError(format!(
@@ -814,7 +1406,7 @@ impl ServerDevice {
 
   fn handle_battery_level_cmd(&self) -> ButtplugServerResultFuture {
     // See if we have a battery sensor.
-    if let Some(sensor_attributes) = self.message_attributes().sensor_read_cmd() {
+    if let Some(sensor_attributes) = self.message_attributes.sensor_read_cmd() {
       for (index, sensor) in sensor_attributes.iter().enumerate() {
         if *sensor.sensor_type() == SensorType::Battery {
           let sensor_read_msg = SensorReadCmd::new(0, index as u32, SensorType::Battery);
@@ -851,7 +1443,7 @@ impl ServerDevice {
 
   fn handle_rssi_level_cmd(&self) -> ButtplugServerResultFuture {
     // See if we have a battery sensor.
-    if let Some(sensor_attributes) = self.message_attributes().sensor_read_cmd() {
+    if let Some(sensor_attributes) = self.message_attributes.sensor_read_cmd() {
       for (index, sensor) in sensor_attributes.iter().enumerate() {
         if *sensor.sensor_type() == SensorType::RSSI {
           let sensor_read_msg = SensorReadCmd::new(0, index as u32, SensorType::RSSI);
@@ -0,0 +1,102 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Low battery warnings, see
+//! [ServerDeviceManagerBuilder::low_battery_threshold](super::ServerDeviceManagerBuilder::low_battery_threshold).
+
+use crate::{core::message::LowBatteryWarning, server::event_fanout::EventFanout};
+use dashmap::DashSet;
+
+/// How far above the threshold a battery has to charge before the device can be warned about
+/// again, so readings jittering around the threshold don't send a warning each time.
+const REARM_MARGIN: f64 = 0.05;
+
+/// Watches battery readings from all devices, sending a [LowBatteryWarning] when one drops below
+/// the threshold.
+pub(super) struct BatteryMonitor {
+  threshold: f64,
+  output_sender: EventFanout,
+  /// Devices that have been warned about, and haven't charged back above the threshold since.
+  warned_devices: DashSet<u32>,
+}
+
+impl BatteryMonitor {
+  pub(super) fn new(threshold: f64, output_sender: EventFanout) -> Self {
+    Self {
+      threshold,
+      output_sender,
+      warned_devices: DashSet::new(),
+    }
+  }
+
+  /// Checks a battery level (0.0-1.0) read from a device, sending a warning if it just dropped
+  /// below the threshold.
+  pub(super) async fn check(&self, device_index: u32, battery_level: f64) {
+    if battery_level < self.threshold {
+      if self.warned_devices.insert(device_index) {
+        info!(
+          "Device {} battery at {:.0}%, sending low battery warning.",
+          device_index,
+          battery_level * 100.0
+        );
+        if !self
+          .output_sender
+          .send(LowBatteryWarning::new(device_index, battery_level).into())
+          .await
+        {
+          debug!("Server not currently available, dropping Low Battery Warning event.");
+        }
+      }
+    } else if battery_level >= self.threshold + REARM_MARGIN {
+      self.warned_devices.remove(&device_index);
+    }
+  }
+
+  /// Forgets whether a device was warned about, so it's warned again if it reconnects with a low
+  /// battery.
+  pub(super) fn forget(&self, device_index: u32) {
+    self.warned_devices.remove(&device_index);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{core::message::ButtplugServerMessage, server::event_fanout::EventDropPolicy};
+  use futures::{FutureExt, StreamExt};
+
+  #[tokio::test]
+  async fn test_battery_monitor_warns_once_per_crossing() {
+    let fanout = EventFanout::default();
+    let events = fanout.subscribe(EventDropPolicy::default());
+    futures::pin_mut!(events);
+    let monitor = BatteryMonitor::new(0.2, fanout);
+
+    monitor.check(0, 0.5).await;
+    monitor.check(0, 0.15).await;
+    monitor.check(0, 0.1).await;
+    // Not far enough above the threshold to rearm.
+    monitor.check(0, 0.22).await;
+    monitor.check(0, 0.18).await;
+    monitor.check(0, 0.3).await;
+    monitor.check(0, 0.05).await;
+
+    let mut warnings = vec![];
+    while let Some(Some(event)) = events.next().now_or_never() {
+      if let ButtplugServerMessage::LowBatteryWarning(warning) = event {
+        warnings.push(warning);
+      }
+    }
+    assert_eq!(
+      warnings,
+      vec![
+        LowBatteryWarning::new(0, 0.15),
+        LowBatteryWarning::new(0, 0.05)
+      ]
+    );
+  }
+}
@@ -0,0 +1,948 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-device queue for hardware commands generated by protocols.
+//!
+//! Each [ServerDevice][super::ServerDevice] owns a queue with a single worker task, which runs
+//! hardware command batches one at a time. Batches are scheduled by [DeviceCommandPriority], so
+//! that stop commands don't have to wait behind writes that are stuck on slow hardware. The number
+//! of batches waiting at normal priority is bounded, see [DeviceCommandQueueSettings]. The queue
+//! also keeps [DeviceCommandStatistics] about how the hardware is keeping up.
+//!
+//! Stops also get a channel of their own to the worker, so they don't have to wait for the batch
+//! that's already running either. A normal priority batch is cut short as soon as a stop is queued:
+//! its remaining hardware commands are skipped, and a write that's already in flight gets
+//! [STOP_PREEMPTION_GRACE] to finish before it's abandoned. However backed up the device is, a stop
+//! only ever waits on that grace period before its own writes go out.
+//!
+//! Workers pace normal priority batches by how long the hardware has been taking to run them, so a
+//! burst of commands to a slow device (i.e. a BLE stack that takes a while to ack writes) turns into
+//! coalesced commands instead of a backlog. Devices known to wedge under fast updates can also have
+//! a maximum command rate set in the device configuration, which puts a floor under the pacing
+//! interval. Workers also yield between batches, so a device that completes writes immediately
+//! can't keep others on the same executor thread from running.
+
+use super::{
+  hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+  protocol::ProtocolKeepaliveStrategy,
+};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message,
+  },
+  server::ButtplugServerResultFuture,
+  util::{async_manager, future::yield_now, sleep},
+};
+use futures::{
+  future::{self, Either, FutureExt},
+  pin_mut,
+};
+use getset::CopyGetters;
+use instant::Instant;
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::sync::{oneshot, Notify, RwLock};
+
+/// Scheduling priority for hardware command batches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum DeviceCommandPriority {
+  /// Regular actuator commands (scalar, rotate, linear, patterns, etc...)
+  Normal,
+  /// Stop commands and other safety actions. These run before any queued normal commands, and
+  /// drop normal commands queued before them, as those would undo the stop.
+  High,
+}
+
+/// What to do with a normal priority command when a device's command queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeviceCommandOverflowPolicy {
+  /// Drop the oldest queued command to make room. The dropped command resolves successfully
+  /// without being sent to the hardware.
+  DropOldest,
+  /// Merge the new command into the newest queued command, so both are sent in one batch.
+  /// Repeated identical hardware commands in the merged batch are only sent once.
+  #[default]
+  Coalesce,
+  /// Reject the new command with [ButtplugDeviceError::DeviceCommandQueueFull].
+  Error,
+}
+
+/// Bounds for the per-device command queues.
+///
+/// Capacity is the number of normal priority commands that can wait on a device while it's busy
+/// with hardware I/O. High priority (stop) commands are never rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct DeviceCommandQueueSettings {
+  capacity: usize,
+  overflow_policy: DeviceCommandOverflowPolicy,
+}
+
+impl DeviceCommandQueueSettings {
+  /// Create queue settings. Capacity must be at least 1, smaller values are treated as 1.
+  pub fn new(capacity: usize, overflow_policy: DeviceCommandOverflowPolicy) -> Self {
+    Self {
+      capacity: capacity.max(1),
+      overflow_policy,
+    }
+  }
+}
+
+impl Default for DeviceCommandQueueSettings {
+  fn default() -> Self {
+    Self::new(16, DeviceCommandOverflowPolicy::default())
+  }
+}
+
+/// Counters for the commands a device's queue has run, for finding devices that can't keep up.
+///
+/// Commands here are the hardware command batches generated for a single Buttplug message, which
+/// may be more than one write to the device. Raw commands bypass the queue, and aren't counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct DeviceCommandStatistics {
+  /// Commands run against the hardware, including failed ones.
+  commands_sent: u64,
+  /// Commands that failed while writing to the hardware.
+  write_failures: u64,
+  /// Commands dropped without reaching the hardware, either by the queue's overflow policy,
+  /// because a stop command superseded them, or because they were cancelled.
+  commands_dropped: u64,
+  /// Commands that were running when a stop command came in, and were cut short so the stop could
+  /// go out. These are also counted in commands_sent.
+  commands_preempted: u64,
+  /// Total time spent waiting on the hardware to run commands.
+  total_latency: Duration,
+  /// Longest time spent waiting on the hardware to run a single command.
+  max_latency: Duration,
+  /// Current minimum time between the starts of commands, adapted to how long the hardware has
+  /// recently been taking to run them, and never less than the device's configured maximum command
+  /// rate allows. High priority commands don't wait.
+  pacing_interval: Duration,
+}
+
+impl DeviceCommandStatistics {
+  /// Average time spent waiting on the hardware to run a command.
+  pub fn average_latency(&self) -> Duration {
+    if self.commands_sent == 0 {
+      Duration::ZERO
+    } else {
+      self.total_latency / self.commands_sent as u32
+    }
+  }
+
+  fn record_command(&mut self, latency: Duration, failed: bool) {
+    self.commands_sent += 1;
+    if failed {
+      self.write_failures += 1;
+    }
+    self.total_latency += latency;
+    self.max_latency = self.max_latency.max(latency);
+  }
+}
+
+/// Longest a worker will wait between commands, however slow the hardware has been. Commands that
+/// take longer than this already pace themselves.
+const MAX_PACING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest a stop waits on a normal priority write that was already in flight when it was queued.
+/// Most writes finish well within this, so packets aren't cut off on transports that care. Writes
+/// that don't (i.e. on a stalled BLE link) are abandoned, rather than holding the stop up for as
+/// long as the stall lasts.
+pub const STOP_PREEMPTION_GRACE: Duration = Duration::from_millis(50);
+
+/// Moving average of command completion times, used to pace commands to the hardware.
+#[derive(Debug, Default)]
+struct CommandPacer {
+  average_completion: Option<Duration>,
+  /// Interval required by the device's configured maximum command rate, if it has one.
+  min_interval: Duration,
+}
+
+impl CommandPacer {
+  fn new(max_command_rate: Option<u32>) -> Self {
+    Self {
+      average_completion: None,
+      min_interval: max_command_rate
+        .filter(|rate| *rate > 0)
+        .map(|rate| Duration::from_secs(1) / rate)
+        .unwrap_or_default(),
+    }
+  }
+
+  fn record(&mut self, completion: Duration) {
+    // Weight recent commands heavily, so pacing follows changes in link quality quickly.
+    self.average_completion = Some(match self.average_completion {
+      Some(average) => (average * 3 + completion) / 4,
+      None => completion,
+    });
+  }
+
+  fn interval(&self) -> Duration {
+    self
+      .average_completion
+      .unwrap_or_default()
+      .min(MAX_PACING_INTERVAL)
+      .max(self.min_interval)
+  }
+}
+
+type DeviceCommandResultSender = oneshot::Sender<Result<(), ButtplugDeviceError>>;
+type DroppedCommandHandler = Box<dyn Fn() + Send + Sync>;
+
+struct QueuedDeviceCommand {
+  priority: DeviceCommandPriority,
+  commands: Vec<HardwareCommand>,
+  /// Senders for everyone waiting on this batch. There can be more than one if commands were
+  /// coalesced.
+  result_senders: Vec<DeviceCommandResultSender>,
+}
+
+impl QueuedDeviceCommand {
+  /// True if everyone waiting on this batch has gone away, i.e. the command was cancelled or the
+  /// client dropped its future. Nobody is left to care whether the batch runs.
+  fn is_abandoned(&self) -> bool {
+    self.result_senders.iter().all(|sender| sender.is_closed())
+  }
+
+  fn resolve(self, result: Result<(), ButtplugDeviceError>) {
+    for sender in self.result_senders {
+      let _ = sender.send(result.clone());
+    }
+  }
+}
+
+struct DeviceCommandQueueState {
+  settings: DeviceCommandQueueSettings,
+  /// Called whenever queued commands are dropped without reaching the hardware.
+  on_commands_dropped: DroppedCommandHandler,
+  high: VecDeque<QueuedDeviceCommand>,
+  normal: VecDeque<QueuedDeviceCommand>,
+  /// Set when the owning device is dropped. The worker exits once the queue is drained.
+  closed: bool,
+  statistics: DeviceCommandStatistics,
+}
+
+impl DeviceCommandQueueState {
+  fn new(settings: DeviceCommandQueueSettings, on_commands_dropped: DroppedCommandHandler) -> Self {
+    Self {
+      settings,
+      on_commands_dropped,
+      high: VecDeque::new(),
+      normal: VecDeque::new(),
+      closed: false,
+      statistics: DeviceCommandStatistics::default(),
+    }
+  }
+
+  /// Queue a command, applying the overflow policy. If the command is rejected, it's handed back
+  /// so the caller can resolve it.
+  fn push(&mut self, mut command: QueuedDeviceCommand) -> Result<(), QueuedDeviceCommand> {
+    if command.priority == DeviceCommandPriority::High {
+      // Anything waiting at normal priority was generated before this command, and would override
+      // it if we let it run afterward. Consider them superseded.
+      if !self.normal.is_empty() {
+        trace!("Dropping device commands superseded by high priority command.");
+        self.statistics.commands_dropped += self.normal.len() as u64;
+        for superseded in self.normal.drain(..) {
+          superseded.resolve(Ok(()));
+        }
+        (self.on_commands_dropped)();
+      }
+      self.high.push_back(command);
+      return Ok(());
+    }
+    if self.normal.len() >= self.settings.capacity() {
+      match self.settings.overflow_policy() {
+        DeviceCommandOverflowPolicy::DropOldest => {
+          if let Some(oldest) = self.normal.pop_front() {
+            trace!("Device command queue full, dropping oldest command.");
+            oldest.resolve(Ok(()));
+            self.statistics.commands_dropped += 1;
+            (self.on_commands_dropped)();
+          }
+        }
+        DeviceCommandOverflowPolicy::Coalesce => {
+          let newest = self
+            .normal
+            .back_mut()
+            .expect("Queue is at capacity, so it can't be empty");
+          for hardware_command in command.commands.drain(..) {
+            newest.commands.retain(|queued| *queued != hardware_command);
+            newest.commands.push(hardware_command);
+          }
+          newest.result_senders.append(&mut command.result_senders);
+          return Ok(());
+        }
+        DeviceCommandOverflowPolicy::Error => return Err(command),
+      }
+    }
+    self.normal.push_back(command);
+    Ok(())
+  }
+
+  /// Next batch to run. Normal priority batches nobody is waiting on anymore are dropped instead of
+  /// being sent late. High priority batches always run, as stops must reach the device regardless.
+  fn pop(&mut self) -> Option<QueuedDeviceCommand> {
+    if let Some(command) = self.high.pop_front() {
+      return Some(command);
+    }
+    while let Some(command) = self.normal.pop_front() {
+      if !command.is_abandoned() {
+        return Some(command);
+      }
+      trace!("Dropping device command abandoned by its sender.");
+      self.statistics.commands_dropped += 1;
+      (self.on_commands_dropped)();
+    }
+    None
+  }
+
+  fn has_normal_commands(&self) -> bool {
+    !self.normal.is_empty()
+  }
+
+  fn has_high_commands(&self) -> bool {
+    !self.high.is_empty()
+  }
+
+  /// Records a normal priority batch that was cut short by a stop command. Like superseded
+  /// commands, the hardware may not be where the batch left it.
+  fn record_preempted(&mut self) {
+    self.statistics.commands_preempted += 1;
+    (self.on_commands_dropped)();
+  }
+
+  /// True if the worker should stop pacing and get back to running commands right away.
+  fn needs_immediate_run(&self) -> bool {
+    !self.high.is_empty() || self.closed
+  }
+}
+
+pub(super) struct DeviceCommandQueue {
+  name: String,
+  state: Arc<Mutex<DeviceCommandQueueState>>,
+  notifier: Arc<Notify>,
+  /// Signalled when a high priority command is queued, so the worker can preempt the normal
+  /// priority batch it's running.
+  stop_signal: Arc<Notify>,
+}
+
+impl DeviceCommandQueue {
+  /// Creates the queue and spawns its worker task. The worker exits once the queue is dropped and
+  /// all queued commands have been run.
+  ///
+  /// `max_command_rate` is the most normal priority commands per second the device can take, if
+  /// its configuration limits it. `on_commands_dropped` is called whenever queued commands are
+  /// dropped without being sent, so anything tracking the device's state can stop assuming those
+  /// commands went through.
+  pub(super) fn new<F>(
+    hardware: Arc<Hardware>,
+    keepalive_strategy: ProtocolKeepaliveStrategy,
+    keepalive_packet: Arc<RwLock<Option<HardwareWriteCmd>>>,
+    settings: DeviceCommandQueueSettings,
+    max_command_rate: Option<u32>,
+    on_commands_dropped: F,
+  ) -> Self
+  where
+    F: Fn() + Send + Sync + 'static,
+  {
+    let name = hardware.name().to_owned();
+    let state = Arc::new(Mutex::new(DeviceCommandQueueState::new(
+      settings,
+      Box::new(on_commands_dropped),
+    )));
+    let notifier = Arc::new(Notify::new());
+    let stop_signal = Arc::new(Notify::new());
+    let worker_state = state.clone();
+    let worker_notifier = notifier.clone();
+    let worker_stop_signal = stop_signal.clone();
+    async_manager::spawn(async move {
+      run_device_command_queue(
+        hardware,
+        keepalive_strategy,
+        keepalive_packet,
+        CommandPacer::new(max_command_rate),
+        worker_state,
+        worker_notifier,
+        worker_stop_signal,
+      )
+      .await;
+    });
+    Self {
+      name,
+      state,
+      notifier,
+      stop_signal,
+    }
+  }
+
+  /// Queue a batch of hardware commands. The batch is queued immediately, so batches of the same
+  /// priority run in the order this is called. The returned future resolves once the batch has
+  /// been run, or dropped by the queue's overflow policy. High priority batches also preempt the
+  /// normal priority batch the worker is running, if any.
+  pub(super) fn send(
+    &self,
+    priority: DeviceCommandPriority,
+    commands: Vec<HardwareCommand>,
+  ) -> ButtplugServerResultFuture {
+    let (result_sender, result_receiver) = oneshot::channel();
+    let command = QueuedDeviceCommand {
+      priority,
+      commands,
+      result_senders: vec![result_sender],
+    };
+    if self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .push(command)
+      .is_err()
+    {
+      return future::ready(Err(
+        ButtplugDeviceError::DeviceCommandQueueFull(self.name.clone()).into(),
+      ))
+      .boxed();
+    }
+    self.notifier.notify_one();
+    if priority == DeviceCommandPriority::High {
+      self.stop_signal.notify_one();
+    }
+    async move {
+      match result_receiver.await {
+        Ok(result) => result
+          .map(|_| message::Ok::default().into())
+          .map_err(ButtplugError::from),
+        Err(_) => Err(
+          ButtplugDeviceError::DeviceNotConnected("Device command queue has shut down".to_owned())
+            .into(),
+        ),
+      }
+    }
+    .boxed()
+  }
+}
+
+impl DeviceCommandQueue {
+  /// True if normal priority commands are waiting to be run. These will be dropped if a high
+  /// priority command is sent.
+  pub(super) fn has_normal_commands(&self) -> bool {
+    self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .has_normal_commands()
+  }
+
+  pub(super) fn statistics(&self) -> DeviceCommandStatistics {
+    self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .statistics
+  }
+}
+
+impl Drop for DeviceCommandQueue {
+  fn drop(&mut self) {
+    self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .closed = true;
+    self.notifier.notify_one();
+  }
+}
+
+async fn run_device_command_queue(
+  hardware: Arc<Hardware>,
+  keepalive_strategy: ProtocolKeepaliveStrategy,
+  keepalive_packet: Arc<RwLock<Option<HardwareWriteCmd>>>,
+  mut pacer: CommandPacer,
+  state: Arc<Mutex<DeviceCommandQueueState>>,
+  notifier: Arc<Notify>,
+  stop_signal: Arc<Notify>,
+) {
+  loop {
+    // Commands that arrived while we were busy with hardware are all in the queue at this point,
+    // so priorities are applied across the whole backlog.
+    let next = {
+      let mut state = state.lock().expect("Lock is never held across a panic");
+      match state.pop() {
+        Some(queued) => Some(queued),
+        None if state.closed => break,
+        None => None,
+      }
+    };
+    let Some(queued) = next else {
+      notifier.notified().await;
+      continue;
+    };
+    // Run commands in order, otherwise we may end up sending out of order. This may take a while,
+    // but it's what 99% of protocols expect. If they want something else, they can implement it
+    // themselves.
+    //
+    // If anything errors out, just bail on the command series. This most likely means the device
+    // disconnected.
+    //
+    // Normal priority batches also bail as soon as a stop is queued. There's no point finishing a
+    // command that the stop is about to undo.
+    let preemptible = queued.priority == DeviceCommandPriority::Normal;
+    let start = Instant::now();
+    let mut result = Ok(());
+    let mut preempted = false;
+    let mut last_write = None;
+    for command in &queued.commands {
+      let command_result = if preemptible {
+        run_preemptible_command(&hardware, command, &state, &stop_signal).await
+      } else {
+        Some(hardware.parse_message(command).await)
+      };
+      match command_result {
+        Some(Ok(())) => {}
+        Some(Err(err)) => {
+          result = Err(err);
+          break;
+        }
+        None => {
+          preempted = true;
+          break;
+        }
+      }
+      if let HardwareCommand::Write(command) = command {
+        last_write = Some(command);
+      }
+    }
+    // Only the last packet matters for keepalive replay, so only store that one.
+    if hardware.requires_keepalive()
+      && matches!(
+        keepalive_strategy,
+        ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
+      )
+    {
+      if let Some(command) = last_write {
+        *keepalive_packet.write().await = Some(command.clone());
+      }
+    }
+    let completion = start.elapsed();
+    pacer.record(completion);
+    {
+      let mut state = state.lock().expect("Lock is never held across a panic");
+      state.statistics.record_command(completion, result.is_err());
+      state.statistics.pacing_interval = pacer.interval();
+      if preempted {
+        debug!("Device command on {} preempted by stop.", hardware.name());
+        state.record_preempted();
+      }
+    }
+    queued.resolve(result);
+    let remaining = pacer.interval().saturating_sub(start.elapsed());
+    if remaining.is_zero() {
+      yield_now().await;
+    } else {
+      wait_for_pacing(&state, &notifier, remaining).await;
+    }
+  }
+  info!("Leaving device command queue for {}", hardware.name());
+}
+
+/// Runs a hardware command from a normal priority batch, unless a stop is queued first. If a stop
+/// comes in while the command is in flight, the command gets [STOP_PREEMPTION_GRACE] to finish.
+/// Returns None if the command was preempted, in which case it may or may not have reached the
+/// hardware.
+async fn run_preemptible_command(
+  hardware: &Hardware,
+  command: &HardwareCommand,
+  state: &Mutex<DeviceCommandQueueState>,
+  stop_signal: &Notify,
+) -> Option<Result<(), ButtplugDeviceError>> {
+  let has_stop = || {
+    state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .has_high_commands()
+  };
+  if has_stop() {
+    return None;
+  }
+  let command_fut = hardware.parse_message(command);
+  pin_mut!(command_fut);
+  loop {
+    // Register for the signal before checking the queue, so a stop can't slip in between.
+    let stopped = stop_signal.notified();
+    pin_mut!(stopped);
+    stopped.as_mut().enable();
+    if has_stop() {
+      break;
+    }
+    if let Either::Left((result, _)) = future::select(command_fut.as_mut(), stopped).await {
+      return Some(result);
+    }
+  }
+  let grace = sleep(STOP_PREEMPTION_GRACE);
+  pin_mut!(grace);
+  match future::select(command_fut, grace).await {
+    Either::Left((result, _)) => Some(result),
+    Either::Right(_) => {
+      warn!(
+        "Abandoning hardware command on {} that didn't finish within the stop grace period.",
+        hardware.name()
+      );
+      None
+    }
+  }
+}
+
+/// Waits out the pacing interval, unless a high priority command shows up or the queue closes.
+/// Normal priority commands arriving in the meantime stay queued, and are coalesced if the queue
+/// fills up.
+async fn wait_for_pacing(
+  state: &Mutex<DeviceCommandQueueState>,
+  notifier: &Notify,
+  duration: Duration,
+) {
+  let deadline = Instant::now() + duration;
+  loop {
+    if state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .needs_immediate_run()
+    {
+      return;
+    }
+    let now = Instant::now();
+    if now >= deadline {
+      return;
+    }
+    let timeout = sleep(deadline - now);
+    let notified = notifier.notified();
+    pin_mut!(timeout, notified);
+    future::select(timeout, notified).await;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    core::message::Endpoint,
+    server::device::hardware::{
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+    },
+  };
+  use futures::future::BoxFuture;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use tokio::sync::broadcast;
+
+  fn queued(
+    priority: DeviceCommandPriority,
+    data: u8,
+  ) -> (
+    QueuedDeviceCommand,
+    oneshot::Receiver<Result<(), ButtplugDeviceError>>,
+  ) {
+    let (result_sender, result_receiver) = oneshot::channel();
+    (
+      QueuedDeviceCommand {
+        priority,
+        commands: vec![HardwareWriteCmd::new(Endpoint::Tx, vec![data], false).into()],
+        result_senders: vec![result_sender],
+      },
+      result_receiver,
+    )
+  }
+
+  /// Pushes a command, keeping its result receiver in `waiting` so it isn't dropped as abandoned.
+  fn push(
+    queue: &mut DeviceCommandQueueState,
+    waiting: &mut Vec<oneshot::Receiver<Result<(), ButtplugDeviceError>>>,
+    priority: DeviceCommandPriority,
+    data: u8,
+  ) -> bool {
+    let (command, result_receiver) = queued(priority, data);
+    waiting.push(result_receiver);
+    queue.push(command).is_ok()
+  }
+
+  fn written_data(queue: &mut DeviceCommandQueueState) -> Vec<Vec<u8>> {
+    std::iter::from_fn(|| queue.pop())
+      .map(|command| {
+        command
+          .commands
+          .iter()
+          .map(|hardware_command| match hardware_command {
+            HardwareCommand::Write(cmd) => cmd.data()[0],
+            _ => panic!("Only writes are queued in tests"),
+          })
+          .collect()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_high_priority_runs_first_and_supersedes_normal() {
+    let mut queue =
+      DeviceCommandQueueState::new(DeviceCommandQueueSettings::default(), Box::new(|| {}));
+    let mut waiting = vec![];
+    push(&mut queue, &mut waiting, DeviceCommandPriority::Normal, 1);
+    push(&mut queue, &mut waiting, DeviceCommandPriority::Normal, 2);
+    push(&mut queue, &mut waiting, DeviceCommandPriority::High, 3);
+    push(&mut queue, &mut waiting, DeviceCommandPriority::Normal, 4);
+    push(&mut queue, &mut waiting, DeviceCommandPriority::High, 5);
+    push(&mut queue, &mut waiting, DeviceCommandPriority::Normal, 6);
+    assert_eq!(written_data(&mut queue), vec![vec![3], vec![5], vec![6]]);
+  }
+
+  #[test]
+  fn test_abandoned_commands_dropped() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let dropped_clone = dropped.clone();
+    let mut queue = DeviceCommandQueueState::new(
+      DeviceCommandQueueSettings::default(),
+      Box::new(move || dropped_clone.store(true, Ordering::Relaxed)),
+    );
+    let (first, first_result) = queued(DeviceCommandPriority::Normal, 1);
+    assert!(queue.push(first).is_ok());
+    let (second, _second_result) = queued(DeviceCommandPriority::Normal, 2);
+    assert!(queue.push(second).is_ok());
+    drop(first_result);
+    assert_eq!(written_data(&mut queue), vec![vec![2]]);
+    assert!(dropped.load(Ordering::Relaxed));
+    assert_eq!(queue.statistics.commands_dropped(), 1);
+
+    // Stops run even if nobody is waiting on them.
+    let (stop, stop_result) = queued(DeviceCommandPriority::High, 3);
+    assert!(queue.push(stop).is_ok());
+    drop(stop_result);
+    assert_eq!(written_data(&mut queue), vec![vec![3]]);
+  }
+
+  #[test]
+  fn test_command_statistics() {
+    let mut statistics = DeviceCommandStatistics::default();
+    assert_eq!(statistics.average_latency(), Duration::ZERO);
+    statistics.record_command(Duration::from_millis(10), false);
+    statistics.record_command(Duration::from_millis(30), true);
+    assert_eq!(statistics.commands_sent(), 2);
+    assert_eq!(statistics.write_failures(), 1);
+    assert_eq!(statistics.average_latency(), Duration::from_millis(20));
+    assert_eq!(statistics.max_latency(), Duration::from_millis(30));
+  }
+
+  #[test]
+  fn test_overflow_drop_oldest() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let dropped_clone = dropped.clone();
+    let mut queue = DeviceCommandQueueState::new(
+      DeviceCommandQueueSettings::new(2, DeviceCommandOverflowPolicy::DropOldest),
+      Box::new(move || dropped_clone.store(true, Ordering::Relaxed)),
+    );
+    let mut waiting = vec![];
+    let (first, mut first_result) = queued(DeviceCommandPriority::Normal, 1);
+    assert!(queue.push(first).is_ok());
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      2
+    ));
+    assert!(!dropped.load(Ordering::Relaxed));
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      3
+    ));
+    assert!(dropped.load(Ordering::Relaxed));
+    assert_eq!(queue.statistics.commands_dropped(), 1);
+    assert_eq!(first_result.try_recv(), Ok(Ok(())));
+    assert_eq!(written_data(&mut queue), vec![vec![2], vec![3]]);
+  }
+
+  #[test]
+  fn test_overflow_coalesce() {
+    let mut queue = DeviceCommandQueueState::new(
+      DeviceCommandQueueSettings::new(2, DeviceCommandOverflowPolicy::Coalesce),
+      Box::new(|| {}),
+    );
+    let mut waiting = vec![];
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      1
+    ));
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      2
+    ));
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      3
+    ));
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      2
+    ));
+    assert_eq!(written_data(&mut queue), vec![vec![1], vec![3, 2]]);
+  }
+
+  #[test]
+  fn test_command_pacer() {
+    let mut pacer = CommandPacer::default();
+    assert_eq!(pacer.interval(), Duration::ZERO);
+    pacer.record(Duration::from_millis(40));
+    assert_eq!(pacer.interval(), Duration::from_millis(40));
+    pacer.record(Duration::from_millis(0));
+    assert_eq!(pacer.interval(), Duration::from_millis(30));
+    pacer.record(Duration::from_secs(2));
+    assert_eq!(pacer.interval(), MAX_PACING_INTERVAL);
+
+    // Configured rates hold the interval up, even past the adaptive maximum.
+    let mut pacer = CommandPacer::new(Some(5));
+    assert_eq!(pacer.interval(), Duration::from_millis(200));
+    pacer.record(Duration::from_secs(2));
+    assert_eq!(pacer.interval(), Duration::from_millis(200));
+    assert_eq!(CommandPacer::new(Some(0)).interval(), Duration::ZERO);
+  }
+
+  #[test]
+  fn test_overflow_error() {
+    let mut queue = DeviceCommandQueueState::new(
+      DeviceCommandQueueSettings::new(1, DeviceCommandOverflowPolicy::Error),
+      Box::new(|| {}),
+    );
+    let mut waiting = vec![];
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      1
+    ));
+    assert!(!push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::Normal,
+      2
+    ));
+    // Stops are never rejected.
+    assert!(push(
+      &mut queue,
+      &mut waiting,
+      DeviceCommandPriority::High,
+      3
+    ));
+    assert_eq!(written_data(&mut queue), vec![vec![3]]);
+  }
+
+  /// Hardware that records the first byte of every write, and never finishes writes starting with
+  /// 1, like a stalled BLE link.
+  struct StallingHardware {
+    written: Arc<Mutex<Vec<u8>>>,
+  }
+
+  impl HardwareInternal for StallingHardware {
+    fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+      broadcast::channel(1).1
+    }
+
+    fn read_value(
+      &self,
+      _msg: &HardwareReadCmd,
+    ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+      future::pending().boxed()
+    }
+
+    fn write_value(
+      &self,
+      msg: &HardwareWriteCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      if msg.data()[0] == 1 {
+        return future::pending().boxed();
+      }
+      self
+        .written
+        .lock()
+        .expect("Test, assuming infallible.")
+        .push(msg.data()[0]);
+      future::ready(Ok(())).boxed()
+    }
+
+    fn subscribe(
+      &self,
+      _msg: &HardwareSubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn unsubscribe(
+      &self,
+      _msg: &HardwareUnsubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+  }
+
+  #[tokio::test]
+  async fn test_stop_preempts_stalled_write() {
+    let written = Arc::new(Mutex::new(vec![]));
+    let dropped = Arc::new(AtomicBool::new(false));
+    let dropped_clone = dropped.clone();
+    let queue = DeviceCommandQueue::new(
+      Arc::new(Hardware::new(
+        "Test",
+        "addr",
+        &[Endpoint::Tx],
+        Box::new(StallingHardware {
+          written: written.clone(),
+        }),
+      )),
+      ProtocolKeepaliveStrategy::NoStrategy,
+      Arc::new(RwLock::new(None)),
+      DeviceCommandQueueSettings::default(),
+      None,
+      move || dropped_clone.store(true, Ordering::SeqCst),
+    );
+    let write = |data: u8| HardwareWriteCmd::new(Endpoint::Tx, vec![data], false).into();
+    let stalled = queue.send(DeviceCommandPriority::Normal, vec![write(1), write(2)]);
+    // Let the worker get stuck on the stalled write.
+    sleep(Duration::from_millis(10)).await;
+    let stop = queue.send(DeviceCommandPriority::High, vec![write(0)]);
+    tokio::time::timeout(Duration::from_secs(1), stop)
+      .await
+      .expect("Stop should not wait on the stalled write")
+      .expect("Test, assuming infallible.");
+    assert!(stalled.await.is_ok());
+    assert_eq!(
+      *written.lock().expect("Test, assuming infallible."),
+      vec![0]
+    );
+    assert!(dropped.load(Ordering::SeqCst));
+    let statistics = queue.statistics();
+    assert_eq!(statistics.commands_preempted(), 1);
+    assert_eq!(statistics.commands_sent(), 2);
+  }
+}
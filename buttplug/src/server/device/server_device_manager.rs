@@ -8,10 +8,19 @@
 //! Buttplug Device Manager, manages Device Subtype (Platform/Communication bus
 //! specific) Managers
 
-use super::server_device_manager_event_loop::ServerDeviceManagerEventLoop;
+use super::{
+  server_device::DeviceInitializationSettings,
+  server_device_battery_monitor::BatteryMonitor,
+  server_device_manager_event_loop::{
+    start_comm_manager,
+    CommManagerEvent,
+    ServerDeviceManagerEventLoop,
+  },
+  server_device_self_test::run_self_test,
+};
 use crate::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugMessageError, ButtplugUnknownError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError, ButtplugUnknownError},
     message::{
       self,
       ButtplugClientMessage,
@@ -22,62 +31,189 @@ use crate::{
       ButtplugServerMessage,
       DeviceList,
       DeviceMessageInfo,
+      DeviceSelfTestReport,
+      RotateCmd,
+      RotatePositionCmd,
+      RotationSubcommand,
+      ScalarCmd,
+      ScalarSubcommand,
+      SingleMotorVibrateCmd,
+      StartScanning,
+      VectorSubcommand,
+      VibrateCmd,
+      VibrateSubcommand,
+      VorzeA10CycloneCmd,
     },
+    ButtplugResultFuture,
   },
   server::{
     device::{
       configuration::{
+        DeviceConfigurationManager,
         DeviceConfigurationManagerBuilder,
+        EffectiveDeviceConfiguration,
         ProtocolAttributesIdentifier,
         ProtocolCommunicationSpecifier,
         ProtocolDeviceAttributes,
+        ServerDeviceMessageAttributes,
       },
+      dfu::DfuFirmwareImage,
       hardware::communication::{
         HardwareCommunicationManager,
         HardwareCommunicationManagerBuilder,
       },
       protocol::ProtocolIdentifierFactory,
+      DeviceCommandQueueSettings,
+      DeviceCommandStatistics,
       ServerDevice,
       ServerDeviceIdentifier,
     },
+    event_fanout::{EventDropPolicy, EventFanout},
     ButtplugServerError,
     ButtplugServerResultFuture,
   },
-  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+  util::{
+    async_manager,
+    device_configuration::{set_user_config_display_name, set_user_config_metadata},
+    sleep,
+  },
 };
+use async_stream::stream;
 use dashmap::DashMap;
 use futures::{
-  future::{self, FutureExt},
+  future::{self, BoxFuture, FutureExt},
+  Future,
   Stream,
 };
 use getset::Getters;
+use serde::Serialize;
 use std::{
-  convert::TryFrom,
+  collections::{HashMap, HashSet},
+  fmt,
+  path::PathBuf,
   sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     Arc,
+    Mutex,
   },
+  time::Duration,
 };
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
 
-#[derive(Debug)]
+/// How long to wait for a device to take a stop command when a client disconnects or the server
+/// shuts down. Devices that take longer are logged and given up on, so one unresponsive device
+/// can't keep the others from being stopped.
+pub const DEVICE_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default for how long protocol initialization (handshakes, model queries, etc) can take before a
+/// connecting device is given up on. See [ServerDeviceManagerBuilder::protocol_initialization_timeout].
+pub const DEFAULT_PROTOCOL_INITIALIZATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default for how many more times BLE devices are connected and initialized after a failed
+/// attempt. See [ServerDeviceManagerBuilder::protocol_initialization_retries].
+pub const DEFAULT_PROTOCOL_INITIALIZATION_RETRIES: u32 = 2;
+
+/// Default for how long to wait between attempts to connect and initialize a BLE device.
+pub const DEFAULT_PROTOCOL_INITIALIZATION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Default for how many devices can connect and initialize at once. See
+/// [ServerDeviceManagerBuilder::device_initialization_concurrency].
+pub const DEFAULT_DEVICE_INITIALIZATION_CONCURRENCY: usize = 4;
+
+/// Largest amount of metadata that can be stored for a device, counting the bytes of all keys and
+/// values. See [ServerDeviceManager::set_device_metadata].
+pub const MAX_DEVICE_METADATA_SIZE: usize = 4096;
+
 pub(super) enum DeviceManagerCommand {
-  StartScanning,
+  /// Start scanning, stopping automatically after the timeout if one is given.
+  StartScanning(Option<Duration>),
   StopScanning,
+  AddCommManager(
+    Box<dyn HardwareCommunicationManagerBuilder>,
+    oneshot::Sender<Result<(), ButtplugServerError>>,
+  ),
+  RemoveCommManager(String, oneshot::Sender<Result<(), ButtplugServerError>>),
+}
+
+/// A device found while scanning that no protocol matched, from
+/// [ServerDeviceManager::unmatched_device_stream]. Holds everything the device advertised (for
+/// bluetooth, its name, manufacturer data and service UUIDs), which is what's needed to add support
+/// for it to the device configuration.
+#[derive(Debug, Clone, Getters, Serialize)]
+#[getset(get = "pub")]
+pub struct UnmatchedDevice {
+  /// Name of the comm manager that found the device.
+  comm_manager: String,
+  name: String,
+  address: String,
+  /// What the device advertised, in the same format protocols are matched against.
+  specifier: ProtocolCommunicationSpecifier,
+}
+
+impl UnmatchedDevice {
+  pub(super) fn new(
+    comm_manager: &str,
+    name: &str,
+    address: &str,
+    specifier: ProtocolCommunicationSpecifier,
+  ) -> Self {
+    Self {
+      comm_manager: comm_manager.to_owned(),
+      name: name.to_owned(),
+      address: address.to_owned(),
+      specifier,
+    }
+  }
+}
+
+/// Sessions involved with a device. Kept in one map entry, so checking and updating both on every
+/// output command only takes one map lookup.
+#[derive(Debug, Default, Clone, Copy)]
+struct DeviceSessions {
+  /// Id of the session holding the lock on the device.
+  lock_owner: Option<u32>,
+  /// Id of the session that last sent an output command to the device.
+  commander: Option<u32>,
+}
+
+impl fmt::Debug for DeviceManagerCommand {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::StartScanning(timeout) => write!(f, "StartScanning({:?})", timeout),
+      Self::StopScanning => write!(f, "StopScanning"),
+      Self::AddCommManager(..) => write!(f, "AddCommManager"),
+      Self::RemoveCommManager(name, _) => write!(f, "RemoveCommManager({})", name),
+    }
+  }
 }
 
 #[derive(Debug, Getters)]
 #[getset(get = "pub")]
 pub struct ServerDeviceInfo {
   identifier: ServerDeviceIdentifier,
+  name: String,
   display_name: Option<String>,
+  metadata: Option<HashMap<String, String>>,
 }
 
 #[derive(Default)]
 pub struct ServerDeviceManagerBuilder {
   configuration_manager_builder: DeviceConfigurationManagerBuilder,
   comm_managers: Vec<Box<dyn HardwareCommunicationManagerBuilder>>,
+  allowed_comm_managers: Option<HashSet<String>>,
+  user_device_configuration_json: Option<String>,
+  user_device_configuration_path: Option<PathBuf>,
+  command_queue_settings: DeviceCommandQueueSettings,
+  comm_manager_preference: Vec<String>,
+  scanning_timeout: Option<Duration>,
+  protocol_initialization_timeout: Option<Duration>,
+  protocol_initialization_retries: Option<(u32, Duration)>,
+  device_initialization_concurrency: Option<usize>,
+  background_scanning: bool,
+  allow_firmware_updates: bool,
+  allow_server_management: bool,
+  low_battery_threshold: Option<f64>,
 }
 
 impl ServerDeviceManagerBuilder {
@@ -89,6 +225,117 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
+  /// Only run comm managers with the given name, i.e. "BtlePlugCommunicationManager". Can be called
+  /// multiple times to allow multiple managers. If never called, all added comm managers run.
+  pub fn allowed_comm_manager(&mut self, name: &str) -> &mut Self {
+    self
+      .allowed_comm_managers
+      .get_or_insert_with(HashSet::new)
+      .insert(name.to_owned());
+    self
+  }
+
+  /// Set the user device configuration the manager was configured with. This doesn't load the
+  /// configuration (that happens via [ServerDeviceManagerBuilder::device_configuration_manager_builder]),
+  /// it's the starting point for settings changed at runtime, like device display names.
+  pub fn user_device_configuration_json(&mut self, config_json: Option<String>) -> &mut Self {
+    self.user_device_configuration_json = config_json;
+    self
+  }
+
+  /// Set a file path to save the user device configuration to whenever settings are changed at
+  /// runtime.
+  pub fn user_device_configuration_path(&mut self, path: PathBuf) -> &mut Self {
+    self.user_device_configuration_path = Some(path);
+    self
+  }
+
+  /// Prefer devices found by the named comm manager when the same physical device is found by more
+  /// than one, i.e. a Lovense toy that's reachable over bluetooth and through Lovense Connect. Call
+  /// once per manager, in order of preference. Managers that are never named rank below all named
+  /// ones. Between managers of equal rank, the connection made first is kept.
+  pub fn preferred_comm_manager(&mut self, name: &str) -> &mut Self {
+    self.comm_manager_preference.push(name.to_owned());
+    self
+  }
+
+  /// Stop scanning automatically after this long, for StartScanning requests that don't specify
+  /// their own timeout. Without this, those scans run until StopScanning is sent or every comm
+  /// manager finishes on its own.
+  pub fn scanning_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.scanning_timeout = Some(timeout);
+    self
+  }
+
+  /// Give up on connecting a device if its protocol initialization (handshakes, model queries, etc)
+  /// takes longer than this. The device is disconnected, so it can be connected again the next
+  /// time it's found. Defaults to [DEFAULT_PROTOCOL_INITIALIZATION_TIMEOUT].
+  pub fn protocol_initialization_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.protocol_initialization_timeout = Some(timeout);
+    self
+  }
+
+  /// When connecting or initializing a BLE device fails (including timing out), disconnect, wait
+  /// `delay`, and try again up to `retries` more times before giving up on the device. Defaults to
+  /// [DEFAULT_PROTOCOL_INITIALIZATION_RETRIES] retries, [DEFAULT_PROTOCOL_INITIALIZATION_RETRY_DELAY]
+  /// apart. Set `retries` to 0 to give up after the first failure.
+  pub fn protocol_initialization_retries(&mut self, retries: u32, delay: Duration) -> &mut Self {
+    self.protocol_initialization_retries = Some((retries, delay));
+    self
+  }
+
+  /// Set how many devices can connect and initialize at the same time, so devices found in the same
+  /// scan don't wait on each other. Lower limits go easier on radios that struggle with many
+  /// connections in flight. Limits below 1 are treated as 1. Defaults to
+  /// [DEFAULT_DEVICE_INITIALIZATION_CONCURRENCY].
+  pub fn device_initialization_concurrency(&mut self, limit: usize) -> &mut Self {
+    self.device_initialization_concurrency = Some(limit);
+    self
+  }
+
+  /// Keep scanning in the background at a low duty cycle for as long as the manager runs, connecting
+  /// devices as soon as they turn on without clients having to send StartScanning. Client scans still
+  /// work as usual (running at full duty cycle, ending in ScanningFinished), and background scanning
+  /// picks back up once they end.
+  pub fn background_scanning(&mut self) -> &mut Self {
+    self.background_scanning = true;
+    self
+  }
+
+  /// Accept firmware updates for devices that expose a supported bootloader, see
+  /// [ServerDeviceManager::update_device_firmware]. Off by default, as a bad image can leave a
+  /// device unusable.
+  pub fn allow_firmware_updates(&mut self) -> &mut Self {
+    self.allow_firmware_updates = true;
+    self
+  }
+
+  /// Accept messages managing the whole server from admin connections: forcing every device to
+  /// stop, kicking other clients, toggling scanning and requesting shutdown. Off by default, as
+  /// they let one client interfere with every other client. Calling the matching methods on the
+  /// device manager directly is always allowed.
+  pub fn allow_server_management(&mut self) -> &mut Self {
+    self.allow_server_management = true;
+    self
+  }
+
+  /// Send a [LowBatteryWarning](crate::core::message::LowBatteryWarning) when a device's battery
+  /// level (0.0-1.0) drops below `threshold`. Levels are checked whenever the battery is read, by
+  /// clients or by [battery polling](crate::server::device::configuration::ProtocolDeviceAttributes::battery_polling_interval).
+  pub fn low_battery_threshold(&mut self, threshold: f64) -> &mut Self {
+    self.low_battery_threshold = Some(threshold);
+    self
+  }
+
+  /// Set the capacity and overflow policy of the per-device command queues.
+  pub fn device_command_queue_settings(
+    &mut self,
+    settings: DeviceCommandQueueSettings,
+  ) -> &mut Self {
+    self.command_queue_settings = settings;
+    self
+  }
+
   pub fn device_configuration_manager_builder(
     &mut self,
     dcm_builder: &DeviceConfigurationManagerBuilder,
@@ -102,6 +349,13 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
+  pub fn disabled_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self
+      .configuration_manager_builder
+      .disabled_protocol(protocol_name);
+    self
+  }
+
   pub fn denied_address(&mut self, address: &str) -> &mut Self {
     self.configuration_manager_builder.denied_address(address);
     self
@@ -155,33 +409,40 @@ impl ServerDeviceManagerBuilder {
   }
 
   pub fn finish(&mut self) -> Result<ServerDeviceManager, ButtplugServerError> {
-    let config_mgr = self
-      .configuration_manager_builder
-      .finish()
-      .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+    let config_mgr = Arc::new(
+      self
+        .configuration_manager_builder
+        .finish()
+        .map_err(ButtplugServerError::DeviceConfigurationManagerError)?,
+    );
 
     let (device_command_sender, device_command_receiver) = mpsc::channel(256);
-    let (device_event_sender, device_event_receiver) = mpsc::channel(256);
-    let mut comm_managers: Vec<Box<dyn HardwareCommunicationManager>> = Vec::new();
+    let (device_event_sender, device_event_receiver) = mpsc::channel::<CommManagerEvent>(256);
+    let mut comm_managers: Vec<(Box<dyn HardwareCommunicationManager>, _)> = Vec::new();
     for builder in &mut self.comm_managers {
-      let comm_mgr = builder.finish(device_event_sender.clone());
+      // Check names before building, so skipped or duplicate managers never start up.
+      let name = builder.name();
+      if let Some(allowed) = &self.allowed_comm_managers {
+        if !allowed.contains(name) {
+          info!("{} is not an allowed comm manager, skipping.", name);
+          continue;
+        }
+      }
 
-      if comm_managers
-        .iter()
-        .any(|mgr| mgr.name() == comm_mgr.name())
-      {
+      if comm_managers.iter().any(|(mgr, _)| mgr.name() == name) {
         return Err(
-          ButtplugServerError::DeviceCommunicationManagerTypeAlreadyAdded(
-            comm_mgr.name().to_owned(),
-          ),
+          ButtplugServerError::DeviceCommunicationManagerTypeAlreadyAdded(name.to_owned()),
         );
       }
 
-      comm_managers.push(comm_mgr);
+      comm_managers.push(start_comm_manager(
+        builder.as_mut(),
+        device_event_sender.clone(),
+      ));
     }
 
     let mut colliding_dcms = vec![];
-    for mgr in comm_managers.iter() {
+    for (mgr, _) in comm_managers.iter() {
       info!("{}: {}", mgr.name(), mgr.can_scan());
       // Hack: Lovense and Bluetooth dongles will fight with each other over devices, possibly
       // interrupting each other connecting and causing very weird issues for users. Print a
@@ -205,50 +466,269 @@ impl ServerDeviceManagerBuilder {
     let devices = Arc::new(DashMap::new());
     let loop_cancellation_token = CancellationToken::new();
 
-    let output_sender = broadcast::channel(255).0;
+    let output_sender = EventFanout::default();
+    let (command_sender, _) = broadcast::channel(256);
+    let (unmatched_device_sender, _) = broadcast::channel(256);
+    let battery_monitor = self
+      .low_battery_threshold
+      .map(|threshold| Arc::new(BatteryMonitor::new(threshold, output_sender.clone())));
+
+    let (retries, retry_delay) = self.protocol_initialization_retries.unwrap_or((
+      DEFAULT_PROTOCOL_INITIALIZATION_RETRIES,
+      DEFAULT_PROTOCOL_INITIALIZATION_RETRY_DELAY,
+    ));
+    let initialization_settings = DeviceInitializationSettings {
+      timeout: self
+        .protocol_initialization_timeout
+        .unwrap_or(DEFAULT_PROTOCOL_INITIALIZATION_TIMEOUT),
+      retries,
+      retry_delay,
+    };
 
     let mut event_loop = ServerDeviceManagerEventLoop::new(
       comm_managers,
-      config_mgr,
+      config_mgr.clone(),
       devices.clone(),
       loop_cancellation_token.child_token(),
       output_sender.clone(),
+      device_event_sender,
       device_event_receiver,
       device_command_receiver,
+      self.command_queue_settings,
+      initialization_settings,
+      self
+        .device_initialization_concurrency
+        .unwrap_or(DEFAULT_DEVICE_INITIALIZATION_CONCURRENCY),
+      self.comm_manager_preference.clone(),
+      self.background_scanning,
+      battery_monitor.clone(),
+      unmatched_device_sender.clone(),
     );
     async_manager::spawn(async move {
       event_loop.run().await;
     });
     Ok(ServerDeviceManager {
+      config_mgr,
       devices,
       device_command_sender,
       loop_cancellation_token,
       running: Arc::new(AtomicBool::new(true)),
       output_sender,
+      command_sender,
+      unmatched_device_sender,
+      session_counter: AtomicU32::new(0),
+      device_sessions: DashMap::new(),
+      user_device_configuration: Mutex::new(self.user_device_configuration_json.clone()),
+      user_device_configuration_update: tokio::sync::Mutex::new(()),
+      user_device_configuration_path: self.user_device_configuration_path.clone(),
+      scanning_timeout: self.scanning_timeout,
+      intensity_ceiling: AtomicU64::new(1.0f64.to_bits()),
+      allow_firmware_updates: self.allow_firmware_updates,
+      allow_server_management: self.allow_server_management,
+      battery_monitor,
+      session_controls: DashMap::new(),
+      scanning_enabled: AtomicBool::new(true),
+      shutdown_requested: CancellationToken::new(),
     })
   }
 }
 
+/// Caps actuator intensities in `msg` to `ceiling`. Positional rotators are slowed down instead,
+/// by stretching the time each move takes. Linear positions aren't intensities, so they're left
+/// alone.
+fn cap_intensity(
+  msg: ButtplugDeviceCommandMessageUnion,
+  ceiling: f64,
+) -> ButtplugDeviceCommandMessageUnion {
+  let mut capped: ButtplugDeviceCommandMessageUnion = match &msg {
+    ButtplugDeviceCommandMessageUnion::ScalarCmd(cmd) => {
+      let scalars = cmd
+        .scalars()
+        .iter()
+        .map(|s| ScalarSubcommand::new(s.index(), s.scalar().min(ceiling), s.actuator_type()))
+        .collect();
+      let capped = ScalarCmd::new(cmd.device_index(), scalars);
+      match cmd.timestamp() {
+        Some(timestamp) => capped.with_timestamp(timestamp),
+        None => capped,
+      }
+      .into()
+    }
+    ButtplugDeviceCommandMessageUnion::VibrateCmd(cmd) => {
+      let speeds = cmd
+        .speeds()
+        .iter()
+        .map(|s| VibrateSubcommand::new(s.index(), s.speed().min(ceiling)))
+        .collect();
+      VibrateCmd::new(cmd.device_index(), speeds).into()
+    }
+    ButtplugDeviceCommandMessageUnion::RotateCmd(cmd) => {
+      let rotations = cmd
+        .rotations()
+        .iter()
+        .map(|r| RotationSubcommand::new(r.index(), r.speed().min(ceiling), r.clockwise()))
+        .collect();
+      let capped = RotateCmd::new(cmd.device_index(), rotations);
+      match cmd.timestamp() {
+        Some(timestamp) => capped.with_timestamp(timestamp),
+        None => capped,
+      }
+      .into()
+    }
+    ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(cmd) => {
+      SingleMotorVibrateCmd::new(cmd.device_index(), cmd.speed().min(ceiling)).into()
+    }
+    ButtplugDeviceCommandMessageUnion::VorzeA10CycloneCmd(cmd) => VorzeA10CycloneCmd::new(
+      cmd.device_index(),
+      // Vorze speeds run 0-99.
+      cmd.speed().min((99.0 * ceiling) as u32),
+      cmd.clockwise(),
+    )
+    .into(),
+    ButtplugDeviceCommandMessageUnion::RotatePositionCmd(cmd) => {
+      let vectors = cmd
+        .vectors()
+        .iter()
+        .map(|v| {
+          let duration = (v.duration() as f64 / ceiling).min(u32::MAX as f64) as u32;
+          VectorSubcommand::new(v.index(), duration, v.position())
+        })
+        .collect();
+      RotatePositionCmd::new(cmd.device_index(), vectors).into()
+    }
+    _ => return msg,
+  };
+  capped.set_id(msg.id());
+  capped
+}
+
 pub struct ServerDeviceManager {
+  config_mgr: Arc<DeviceConfigurationManager>,
   devices: Arc<DashMap<u32, Arc<ServerDevice>>>,
   device_command_sender: mpsc::Sender<DeviceManagerCommand>,
   loop_cancellation_token: CancellationToken,
   running: Arc<AtomicBool>,
-  output_sender: broadcast::Sender<ButtplugServerMessage>,
+  output_sender: EventFanout,
+  /// Reports device commands that were run, for [ServerDeviceManager::command_stream].
+  command_sender: broadcast::Sender<ButtplugDeviceCommandMessageUnion>,
+  /// Reports devices no protocol matched, for [ServerDeviceManager::unmatched_device_stream].
+  unmatched_device_sender: broadcast::Sender<UnmatchedDevice>,
+  /// Source of ids for server sessions sharing this device manager.
+  session_counter: AtomicU32,
+  /// Device index to sessions locking or commanding the device.
+  device_sessions: DashMap<u32, DeviceSessions>,
+  /// Current user device configuration, including settings changed at runtime.
+  user_device_configuration: Mutex<Option<String>>,
+  /// Held while a user device configuration change is being saved, so each change builds on the
+  /// one before it.
+  user_device_configuration_update: tokio::sync::Mutex<()>,
+  /// Where to save the user device configuration when settings change, if anywhere.
+  user_device_configuration_path: Option<PathBuf>,
+  /// Timeout for scans started without one.
+  scanning_timeout: Option<Duration>,
+  /// Highest actuator intensity allowed, as f64 bits.
+  intensity_ceiling: AtomicU64,
+  /// Whether device firmware updates are accepted.
+  allow_firmware_updates: bool,
+  /// Whether clients can send server management messages, see
+  /// [ServerDeviceManagerBuilder::allow_server_management].
+  allow_server_management: bool,
+  /// Sends low battery warnings, if a threshold was set.
+  battery_monitor: Option<Arc<BatteryMonitor>>,
+  /// Session id to the channel for controlling that session, for admin messages.
+  session_controls: DashMap<u32, mpsc::UnboundedSender<SessionControl>>,
+  /// Whether scans can be started.
+  scanning_enabled: AtomicBool,
+  /// Cancelled once shutdown has been requested via [ServerDeviceManager::request_shutdown].
+  shutdown_requested: CancellationToken,
+}
+
+/// Requests from one server session to another, sent by admin messages.
+#[derive(Debug, Clone)]
+pub(crate) enum SessionControl {
+  /// Cancel scheduled commands and stop playback.
+  StopPlayback,
+  /// Cancel scheduled commands and stop playback on a single device, i.e. when another session
+  /// locks it.
+  StopDevicePlayback(u32),
+  /// Disconnect the client, sending it the error as the reason.
+  Disconnect(ButtplugError),
 }
 
 impl ServerDeviceManager {
   pub fn event_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
+    self.event_stream_with_policy(EventDropPolicy::default())
+  }
+
+  /// Like [ServerDeviceManager::event_stream], with a choice of what happens to events when the
+  /// stream isn't read fast enough.
+  pub fn event_stream_with_policy(
+    &self,
+    policy: EventDropPolicy,
+  ) -> impl Stream<Item = ButtplugServerMessage> {
     // Unlike the client API, we can expect anyone using the server to pin this
     // themselves.
-    convert_broadcast_receiver_to_stream(self.output_sender.subscribe())
+    self.output_sender.subscribe(policy)
   }
 
-  fn start_scanning(&self) -> ButtplugServerResultFuture {
+  /// Device commands that ran successfully, after intensity capping, along with the stops sent
+  /// when sessions disconnect or all devices are stopped. Meant for mirroring device state
+  /// elsewhere, commands are dropped for subscribers that fall too far behind.
+  pub fn command_stream(&self) -> impl Stream<Item = ButtplugDeviceCommandMessageUnion> {
+    let mut receiver = self.command_sender.subscribe();
+    stream! {
+      loop {
+        match receiver.recv().await {
+          Ok(command) => yield command,
+          Err(broadcast::error::RecvError::Lagged(count)) => {
+            warn!("Command stream subscriber fell behind, dropped {} commands.", count)
+          }
+          Err(broadcast::error::RecvError::Closed) => break,
+        }
+      }
+    }
+  }
+
+  /// Devices found while scanning that no protocol matched, with their full advertisement contents.
+  /// Meant for diagnosing devices that aren't detected, i.e. to include with a support request.
+  /// Bluetooth devices are reported again every so often while they're in range, reports are
+  /// dropped for subscribers that fall too far behind.
+  pub fn unmatched_device_stream(&self) -> impl Stream<Item = UnmatchedDevice> {
+    let mut receiver = self.unmatched_device_sender.subscribe();
+    stream! {
+      loop {
+        match receiver.recv().await {
+          Ok(device) => yield device,
+          Err(broadcast::error::RecvError::Lagged(count)) => {
+            warn!("Unmatched device stream subscriber fell behind, dropped {} reports.", count)
+          }
+          Err(broadcast::error::RecvError::Closed) => break,
+        }
+      }
+    }
+  }
+
+  fn report_stop(&self, device_index: u32) {
+    // Having no subscribers isn't an error.
+    let _ = self
+      .command_sender
+      .send(message::StopDeviceCmd::new(device_index).into());
+  }
+
+  fn start_scanning(&self, msg: &StartScanning) -> ButtplugServerResultFuture {
+    if !self.scanning_enabled() {
+      return ButtplugDeviceError::DeviceScanningDisabled.into();
+    }
+    // A timeout of 0 asks for a scan with no timeout, even if we have a default.
+    let timeout = match msg.timeout() {
+      Some(0) => None,
+      Some(timeout) => Some(Duration::from_millis(timeout.into())),
+      None => self.scanning_timeout,
+    };
     let command_sender = self.device_command_sender.clone();
     async move {
       if command_sender
-        .send(DeviceManagerCommand::StartScanning)
+        .send(DeviceManagerCommand::StartScanning(timeout))
         .await
         .is_err()
       {
@@ -274,35 +754,278 @@ impl ServerDeviceManager {
     .boxed()
   }
 
-  pub(crate) fn stop_all_devices(&self) -> ButtplugServerResultFuture {
-    let device_map = self.devices.clone();
-    // TODO This could use some error reporting.
+  /// Adds a comm manager to the running device manager, for instance to enable a connection type
+  /// only once the user asks for it. If a scan is running, the new manager starts scanning too.
+  pub fn add_comm_manager<T>(
+    &self,
+    builder: T,
+  ) -> BoxFuture<'static, Result<(), ButtplugServerError>>
+  where
+    T: HardwareCommunicationManagerBuilder + 'static,
+  {
+    let (result_sender, result_receiver) = oneshot::channel();
+    self.send_comm_manager_command(
+      DeviceManagerCommand::AddCommManager(Box::new(builder), result_sender),
+      result_receiver,
+    )
+  }
+
+  /// Removes a comm manager from the running device manager by name, stopping its scan and
+  /// disconnecting all devices it found.
+  pub fn remove_comm_manager(
+    &self,
+    name: &str,
+  ) -> BoxFuture<'static, Result<(), ButtplugServerError>> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    self.send_comm_manager_command(
+      DeviceManagerCommand::RemoveCommManager(name.to_owned(), result_sender),
+      result_receiver,
+    )
+  }
+
+  fn send_comm_manager_command(
+    &self,
+    command: DeviceManagerCommand,
+    result_receiver: oneshot::Receiver<Result<(), ButtplugServerError>>,
+  ) -> BoxFuture<'static, Result<(), ButtplugServerError>> {
+    let command_sender = self.device_command_sender.clone();
     async move {
-      let fut_vec: Vec<_> = device_map
-        .iter()
-        .map(|dev| {
-          let device = dev.value();
-          device.parse_message(message::StopDeviceCmd::new(1).into())
+      if command_sender.send(command).await.is_err() {
+        return Err(ButtplugServerError::DeviceManagerNotRunning);
+      }
+      result_receiver
+        .await
+        .unwrap_or(Err(ButtplugServerError::DeviceManagerNotRunning))
+    }
+    .boxed()
+  }
+
+  /// Returns the configuration a device uses (or will use once connected), with base and user
+  /// configuration merged. Device identifiers for connected devices can be retrieved via
+  /// [ServerDeviceManager::device_info].
+  pub fn device_configuration(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+  ) -> Option<EffectiveDeviceConfiguration> {
+    self.config_mgr.device_configuration(identifier)
+  }
+
+  /// Set or clear the display name of a connected device. The display name is stored in the user
+  /// device configuration (see [ServerDeviceManager::user_device_configuration_json]), and saved to
+  /// the user device configuration file if one was set when building the server. Nothing changes
+  /// if the file can't be saved.
+  pub async fn set_device_display_name(
+    &self,
+    device_index: u32,
+    display_name: Option<&str>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let identifier = self
+      .devices
+      .get(&device_index)
+      .map(|device| device.identifier().clone())
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(device_index))?;
+    self
+      .update_user_device_configuration(|user_config| {
+        set_user_config_display_name(user_config, &identifier, display_name)
+      })
+      .await?;
+    if let Some(device) = self.devices.get(&device_index) {
+      device.set_display_name(display_name.map(|name| name.to_owned()));
+    }
+    self
+      .config_mgr
+      .set_display_name(&identifier, display_name.map(|name| name.to_owned()));
+    Ok(())
+  }
+
+  /// Set or clear the user metadata (icon, color, notes, etc...) of a connected device. Metadata is
+  /// stored and saved the same way as display names, see
+  /// [ServerDeviceManager::set_device_display_name]. Metadata is meant for small things, anything
+  /// over [MAX_DEVICE_METADATA_SIZE] is rejected.
+  pub async fn set_device_metadata(
+    &self,
+    device_index: u32,
+    metadata: Option<HashMap<String, String>>,
+  ) -> Result<(), ButtplugDeviceError> {
+    let metadata_size: usize = metadata
+      .iter()
+      .flatten()
+      .map(|(key, value)| key.len() + value.len())
+      .sum();
+    if metadata_size > MAX_DEVICE_METADATA_SIZE {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Device metadata is {} bytes, over the limit of {} bytes.",
+        metadata_size, MAX_DEVICE_METADATA_SIZE
+      )));
+    }
+    let identifier = self
+      .devices
+      .get(&device_index)
+      .map(|device| device.identifier().clone())
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(device_index))?;
+    self
+      .update_user_device_configuration(|user_config| {
+        set_user_config_metadata(user_config, &identifier, metadata.as_ref())
+      })
+      .await?;
+    self.config_mgr.set_device_metadata(&identifier, metadata);
+    Ok(())
+  }
+
+  /// Apply a change to the user device configuration, saving it to the user device configuration
+  /// file (if there is one) before it takes effect.
+  async fn update_user_device_configuration<F>(&self, update: F) -> Result<(), ButtplugDeviceError>
+  where
+    F: FnOnce(Option<&str>) -> Result<String, ButtplugDeviceError>,
+  {
+    let _update_guard = self.user_device_configuration_update.lock().await;
+    let user_config = self
+      .user_device_configuration
+      .lock()
+      .expect("Lock is never held across a panic")
+      .clone();
+    let updated_config = update(user_config.as_deref())?;
+    if let Some(path) = self.user_device_configuration_path.clone() {
+      let config = updated_config.clone();
+      async_manager::spawn_blocking(move || {
+        std::fs::write(&path, config).map_err(|err| {
+          ButtplugDeviceError::DeviceConfigurationError(format!(
+            "Cannot save user device configuration to {}: {}",
+            path.display(),
+            err
+          ))
         })
-        .collect();
-      future::join_all(fut_vec).await;
+      })
+      .await?;
+    }
+    *self
+      .user_device_configuration
+      .lock()
+      .expect("Lock is never held across a panic") = Some(updated_config);
+    Ok(())
+  }
+
+  /// Returns the current user device configuration, including settings changed at runtime (like
+  /// device display names), for the application to store.
+  pub fn user_device_configuration_json(&self) -> Option<String> {
+    self
+      .user_device_configuration
+      .lock()
+      .expect("Lock is never held across a panic")
+      .clone()
+  }
+
+  /// Returns true if the protocol exists and hasn't been disabled.
+  pub fn protocol_enabled(&self, protocol_name: &str) -> bool {
+    self.config_mgr.protocol_enabled(protocol_name)
+  }
+
+  /// Enables or disables a protocol at runtime. Devices won't be connected using disabled protocols,
+  /// and any connected devices using the protocol are disconnected when it's disabled.
+  pub fn set_protocol_enabled(
+    &self,
+    protocol_name: &str,
+    enabled: bool,
+  ) -> BoxFuture<'static, Result<(), ButtplugServerError>> {
+    if !self.config_mgr.set_protocol_enabled(protocol_name, enabled) {
+      return future::ready(Err(ButtplugServerError::ProtocolDoesNotExist(
+        protocol_name.to_owned(),
+      )))
+      .boxed();
+    }
+    info!(
+      "Protocol {} {}",
+      protocol_name,
+      if enabled { "enabled" } else { "disabled" }
+    );
+    let devices: Vec<_> = if enabled {
+      vec![]
+    } else {
+      self
+        .devices
+        .iter()
+        .filter(|device| device.value().identifier().protocol() == protocol_name)
+        .map(|device| device.value().clone())
+        .collect()
+    };
+    async move {
+      // Disconnection events will remove the devices from the device map and let clients know
+      // they're gone.
+      for device in devices {
+        if let Err(err) = device.disconnect().await {
+          error!("Error disconnecting device: {:?}", err);
+        }
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  /// Stops all devices. Stop commands are queued on the devices before this returns, ahead of
+  /// anything else waiting to be written, so they go out even if the returned future is dropped.
+  /// Commands the devices are already in the middle of are cut short, see
+  /// [STOP_PREEMPTION_GRACE](super::STOP_PREEMPTION_GRACE).
+  pub(crate) fn stop_all_devices(&self) -> ButtplugServerResultFuture {
+    let fut_vec: Vec<_> = self
+      .devices
+      .iter()
+      .map(|dev| {
+        let device_index = *dev.key();
+        self.report_stop(device_index);
+        (
+          device_index,
+          dev
+            .value()
+            .parse_message(message::StopDeviceCmd::new(device_index).into()),
+        )
+      })
+      .collect();
+    async move {
+      await_device_stops(fut_vec).await;
       Ok(message::Ok::default().into())
     }
     .boxed()
   }
 
-  fn parse_device_message(
+  pub(crate) fn parse_device_message(
     &self,
     device_msg: ButtplugDeviceCommandMessageUnion,
   ) -> ButtplugServerResultFuture {
-    match self.devices.get(&device_msg.device_index()) {
-      Some(device) => {
-        let fut = device.parse_message(device_msg);
-        // Create a future to run the message through the device, then handle adding the id to the result.
-        async move { fut.await }.boxed()
+    let ceiling = self.intensity_ceiling();
+    let device_msg = if ceiling < 1.0 {
+      cap_intensity(device_msg, ceiling)
+    } else {
+      device_msg
+    };
+    let device_index = device_msg.device_index();
+    let Some(device) = self
+      .devices
+      .get(&device_index)
+      .map(|dev| dev.value().clone())
+    else {
+      return ButtplugDeviceError::DeviceNotAvailable(device_index).into();
+    };
+    // Only copy the command if someone is listening for it.
+    let reported_msg = (self.command_sender.receiver_count() > 0).then(|| device_msg.clone());
+    let fut = device.parse_message(device_msg);
+    let monitor = self.battery_monitor.clone();
+    if reported_msg.is_none() && monitor.is_none() {
+      return fut;
+    }
+    let command_sender = self.command_sender.clone();
+    async move {
+      let result = fut.await;
+      if let Ok(msg) = &result {
+        if let Some(reported_msg) = reported_msg {
+          let _ = command_sender.send(reported_msg);
+        }
+        if let (Some(monitor), Some(level)) = (monitor, device.battery_level(msg)) {
+          monitor.check(device_index, level).await;
+        }
       }
-      None => ButtplugDeviceError::DeviceNotAvailable(device_msg.device_index()).into(),
+      result
     }
+    .boxed()
   }
 
   fn parse_device_manager_message(
@@ -321,6 +1044,9 @@ impl ServerDeviceManager {
               &dev.name(),
               &dev.display_name(),
               &None,
+              &Some(dev.transport()),
+              &dev.version(),
+              &self.config_mgr.device_metadata(dev.identifier()),
               dev.message_attributes().into(),
             )
           })
@@ -330,7 +1056,7 @@ impl ServerDeviceManager {
         future::ready(Ok(device_list.into())).boxed()
       }
       ButtplugDeviceManagerMessageUnion::StopAllDevices(_) => self.stop_all_devices(),
-      ButtplugDeviceManagerMessageUnion::StartScanning(_) => self.start_scanning(),
+      ButtplugDeviceManagerMessageUnion::StartScanning(msg) => self.start_scanning(&msg),
       ButtplugDeviceManagerMessageUnion::StopScanning(_) => self.stop_scanning(),
     }
   }
@@ -339,24 +1065,368 @@ impl ServerDeviceManager {
     if !self.running.load(Ordering::SeqCst) {
       return future::ready(Err(ButtplugUnknownError::DeviceManagerNotRunning.into())).boxed();
     }
-    // If this is a device command message, just route it directly to the
-    // device.
-    match ButtplugDeviceCommandMessageUnion::try_from(msg.clone()) {
-      Ok(device_msg) => self.parse_device_message(device_msg),
-      Err(_) => match ButtplugDeviceManagerMessageUnion::try_from(msg.clone()) {
-        Ok(manager_msg) => self.parse_device_manager_message(manager_msg),
-        Err(_) => ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into(),
-      },
+    // If this is a device command message, just route it directly to the device. Check before
+    // converting, so we don't have to clone the message to try conversions.
+    if ButtplugDeviceCommandMessageUnion::matches_client_message(&msg) {
+      self.parse_device_message(msg.try_into().expect("Already checked message type"))
+    } else if ButtplugDeviceManagerMessageUnion::matches_client_message(&msg) {
+      self.parse_device_manager_message(msg.try_into().expect("Already checked message type"))
+    } else {
+      ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into()
+    }
+  }
+
+  /// Write new firmware to a device through its bootloader, see [ServerDevice::update_firmware].
+  /// Fails unless firmware updates were allowed when the manager was built.
+  pub fn update_device_firmware(
+    &self,
+    device_index: u32,
+    image: DfuFirmwareImage,
+  ) -> ButtplugResultFuture {
+    if !self.allow_firmware_updates {
+      return future::ready(Err(
+        ButtplugDeviceError::DevicePermissionError(
+          "Firmware updates are not allowed on this server".to_owned(),
+        )
+        .into(),
+      ))
+      .boxed();
     }
+    match self.devices.get(&device_index) {
+      Some(device) => device.update_firmware(image),
+      None => future::ready(Err(
+        ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+      ))
+      .boxed(),
+    }
+  }
+
+  /// Run a short self-test on a device, pulsing each of its actuators in turn and reading each of
+  /// its sensors, then stopping it. Commands go through the same path as client commands, so the
+  /// intensity ceiling still applies.
+  pub fn run_device_self_test(
+    &self,
+    device_index: u32,
+  ) -> BoxFuture<'static, Result<DeviceSelfTestReport, ButtplugDeviceError>> {
+    let Some(device) = self
+      .devices
+      .get(&device_index)
+      .map(|device| device.value().clone())
+    else {
+      return future::ready(Err(ButtplugDeviceError::DeviceNotAvailable(device_index))).boxed();
+    };
+    let ceiling = self.intensity_ceiling();
+    let attributes = device.message_attributes();
+    async move {
+      let report = run_self_test(device_index, attributes, |msg| {
+        let msg = if ceiling < 1.0 {
+          cap_intensity(msg, ceiling)
+        } else {
+          msg
+        };
+        device.parse_message(msg)
+      })
+      .await;
+      info!(
+        "Self-test of device {} {}",
+        device_index,
+        if report.passed() { "passed" } else { "failed" }
+      );
+      Ok(report)
+    }
+    .boxed()
+  }
+
+  /// Highest intensity (0.0-1.0) actuators can currently be commanded to.
+  pub fn intensity_ceiling(&self) -> f64 {
+    f64::from_bits(self.intensity_ceiling.load(Ordering::SeqCst))
+  }
+
+  /// Cap actuator intensities from all sessions to `ceiling` (0.0-1.0). Devices already running
+  /// above the ceiling are brought down to it, with the commands queued before this returns.
+  pub fn set_intensity_ceiling(&self, ceiling: f64) -> ButtplugServerResultFuture {
+    let ceiling = ceiling.clamp(0.0, 1.0);
+    info!("Setting intensity ceiling to {}", ceiling);
+    self
+      .intensity_ceiling
+      .store(ceiling.to_bits(), Ordering::SeqCst);
+    let fut_vec: Vec<_> = self
+      .devices
+      .iter()
+      .map(|dev| (*dev.key(), dev.value().apply_intensity_ceiling(ceiling)))
+      .collect();
+    async move {
+      for (device_index, fut) in fut_vec {
+        if let Err(err) = fut.await {
+          error!(
+            "Could not bring device {} down to the intensity ceiling: {:?}",
+            device_index, err
+          );
+        }
+      }
+      Ok(message::Ok::default().into())
+    }
+    .boxed()
   }
 
   pub fn device_info(&self, index: u32) -> Option<ServerDeviceInfo> {
     self.devices.get(&index).map(|device| ServerDeviceInfo {
       identifier: device.value().identifier().clone(),
+      name: device.value().name(),
       display_name: device.value().display_name(),
+      metadata: self.config_mgr.device_metadata(device.value().identifier()),
     })
   }
 
+  /// Statistics about the commands sent to a connected device, to help find devices that can't keep
+  /// up with the commands they're sent.
+  pub fn device_statistics(&self, index: u32) -> Option<DeviceCommandStatistics> {
+    self
+      .devices
+      .get(&index)
+      .map(|device| device.value().command_statistics())
+  }
+
+  pub(crate) fn device_message_attributes(
+    &self,
+    index: u32,
+  ) -> Option<ServerDeviceMessageAttributes> {
+    self
+      .devices
+      .get(&index)
+      .map(|device| device.value().message_attributes())
+  }
+
+  /// Returns a new id for a server session using this device manager, and the receiver for
+  /// requests to control the session. The session needs to be unregistered when it goes away.
+  pub(crate) fn register_session(&self) -> (u32, mpsc::UnboundedReceiver<SessionControl>) {
+    let session_id = self.session_counter.fetch_add(1, Ordering::SeqCst);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    self.session_controls.insert(session_id, sender);
+    (session_id, receiver)
+  }
+
+  pub(crate) fn unregister_session(&self, session_id: u32) {
+    self.session_controls.remove(&session_id);
+  }
+
+  /// Ids of the server sessions using this device manager, whether or not a client is connected.
+  pub fn session_ids(&self) -> Vec<u32> {
+    let mut session_ids: Vec<u32> = self.session_controls.iter().map(|x| *x.key()).collect();
+    session_ids.sort_unstable();
+    session_ids
+  }
+
+  fn send_session_control(&self, session_id: u32, control: SessionControl) -> bool {
+    self
+      .session_controls
+      .get(&session_id)
+      .is_some_and(|sender| sender.send(control).is_ok())
+  }
+
+  /// True if clients can send server management messages, see
+  /// [ServerDeviceManagerBuilder::allow_server_management].
+  pub fn server_management_allowed(&self) -> bool {
+    self.allow_server_management
+  }
+
+  /// Disconnects the client of a session, stopping the devices it was commanding and telling it it
+  /// was kicked. Returns false if there is no session with the id.
+  pub fn kick_session(&self, session_id: u32) -> bool {
+    info!("Kicking client of session {}", session_id);
+    self.send_session_control(
+      session_id,
+      SessionControl::Disconnect(ButtplugUnknownError::ClientKicked.into()),
+    )
+  }
+
+  /// Stops all devices, along with scheduled commands and playback in every session, so nothing
+  /// starts them again.
+  pub fn force_stop_all_devices(&self) -> ButtplugServerResultFuture {
+    for session in self.session_controls.iter() {
+      // A session going away at the same time has nothing left to stop.
+      let _ = session.value().send(SessionControl::StopPlayback);
+    }
+    self.stop_all_devices()
+  }
+
+  /// Whether clients can start scanning.
+  pub fn scanning_enabled(&self) -> bool {
+    self.scanning_enabled.load(Ordering::SeqCst)
+  }
+
+  /// Allow or refuse scanning for every client. Disabling scanning stops the scan in progress, if
+  /// any.
+  pub fn set_scanning_enabled(&self, enabled: bool) -> ButtplugServerResultFuture {
+    info!(
+      "Device scanning {}",
+      if enabled { "enabled" } else { "disabled" }
+    );
+    self.scanning_enabled.store(enabled, Ordering::SeqCst);
+    if enabled {
+      future::ready(Ok(message::Ok::default().into())).boxed()
+    } else {
+      self.stop_scanning()
+    }
+  }
+
+  /// Stops scanning and all devices, and disconnects the clients of every session other than
+  /// `requesting_session`, then resolves [ServerDeviceManager::shutdown_requested]. Actually
+  /// shutting down is left to whatever is hosting the server.
+  pub fn request_shutdown(&self, requesting_session: Option<u32>) -> ButtplugServerResultFuture {
+    info!("Server shutdown requested");
+    for session in self.session_controls.iter() {
+      if Some(*session.key()) != requesting_session {
+        let _ = session.value().send(SessionControl::Disconnect(
+          ButtplugUnknownError::ServerShuttingDown.into(),
+        ));
+      }
+    }
+    let stop_scanning = self.stop_scanning();
+    let stop_devices = self.force_stop_all_devices();
+    let shutdown_requested = self.shutdown_requested.clone();
+    async move {
+      let _ = stop_scanning.await;
+      let _ = stop_devices.await;
+      shutdown_requested.cancel();
+      Ok(message::Ok::default().into())
+    }
+    .boxed()
+  }
+
+  /// Resolves once shutdown has been requested, i.e. by an admin client sending
+  /// [ServerShutdownCmd](crate::core::message::ServerShutdownCmd).
+  pub fn shutdown_requested(&self) -> impl Future<Output = ()> + Send + 'static {
+    self.shutdown_requested.clone().cancelled_owned()
+  }
+
+  /// Gives a session exclusive control of a device. Locking a device the session already holds
+  /// succeeds.
+  ///
+  /// Playback and scheduled commands that other sessions started on the device are stopped, as
+  /// they run outside of the lock checks on client messages.
+  pub(crate) fn lock_device(
+    &self,
+    device_index: u32,
+    session_id: u32,
+  ) -> Result<(), ButtplugDeviceError> {
+    if !self.devices.contains_key(&device_index) {
+      return Err(ButtplugDeviceError::DeviceNotAvailable(device_index));
+    }
+    {
+      let mut sessions = self.device_sessions.entry(device_index).or_default();
+      match sessions.lock_owner {
+        Some(owner) if owner == session_id => return Ok(()),
+        Some(_) => return Err(ButtplugDeviceError::DeviceLocked(device_index)),
+        None => sessions.lock_owner = Some(session_id),
+      }
+    }
+    for session in self.session_controls.iter() {
+      if *session.key() != session_id {
+        // A session going away at the same time has nothing left to stop.
+        let _ = session
+          .value()
+          .send(SessionControl::StopDevicePlayback(device_index));
+      }
+    }
+    Ok(())
+  }
+
+  /// Releases a session's lock on a device. Unlocking a device that isn't locked succeeds.
+  pub(crate) fn unlock_device(
+    &self,
+    device_index: u32,
+    session_id: u32,
+  ) -> Result<(), ButtplugDeviceError> {
+    if let Some(mut sessions) = self.device_sessions.get_mut(&device_index) {
+      check_device_lock(&sessions, device_index, session_id)?;
+      sessions.lock_owner = None;
+    }
+    Ok(())
+  }
+
+  /// Returns an error if the device is locked by a session other than the one given. If
+  /// `commanding` is true, also records the session as the one currently commanding the device.
+  ///
+  /// This runs for every device command, so the usual case of a session that's already commanding
+  /// the device only takes a read lock.
+  pub(crate) fn check_device_access(
+    &self,
+    device_index: u32,
+    session_id: u32,
+    commanding: bool,
+  ) -> Result<(), ButtplugDeviceError> {
+    if let Some(sessions) = self.device_sessions.get(&device_index) {
+      check_device_lock(&sessions, device_index, session_id)?;
+      if !commanding || sessions.commander == Some(session_id) {
+        return Ok(());
+      }
+    } else if !commanding {
+      return Ok(());
+    }
+    // The device may have been locked between dropping the read lock and taking the write lock, so
+    // check again.
+    let mut sessions = self.device_sessions.entry(device_index).or_default();
+    check_device_lock(&sessions, device_index, session_id)?;
+    sessions.commander = Some(session_id);
+    Ok(())
+  }
+
+  /// Releases all locks held by a session, usually on disconnect.
+  pub(crate) fn release_device_locks(&self, session_id: u32) {
+    self.device_sessions.iter_mut().for_each(|mut sessions| {
+      if sessions.lock_owner == Some(session_id) {
+        sessions.lock_owner = None;
+      }
+    });
+  }
+
+  /// Stops only the devices that a session was the last to command, so that one client going away
+  /// doesn't stop devices other clients are still using.
+  pub(crate) fn stop_session_devices(&self, session_id: u32) -> ButtplugServerResultFuture {
+    let mut device_indexes = vec![];
+    self.device_sessions.iter_mut().for_each(|mut sessions| {
+      if sessions.commander == Some(session_id) {
+        sessions.commander = None;
+        device_indexes.push(*sessions.key());
+      }
+    });
+    let fut_vec: Vec<_> = device_indexes
+      .into_iter()
+      .filter_map(|device_index| {
+        self.devices.get(&device_index).map(|device| {
+          self.report_stop(device_index);
+          (
+            device_index,
+            device.parse_message(message::StopDeviceCmd::new(device_index).into()),
+          )
+        })
+      })
+      .collect();
+    async move {
+      await_device_stops(fut_vec).await;
+      Ok(message::Ok::default().into())
+    }
+    .boxed()
+  }
+
+  /// Returns the id of the session that last sent an output command to a device, if it hasn't
+  /// disconnected since.
+  pub fn device_commander(&self, device_index: u32) -> Option<u32> {
+    self
+      .device_sessions
+      .get(&device_index)
+      .and_then(|sessions| sessions.commander)
+  }
+
+  /// Returns the id of the session currently holding the lock on a device, if any.
+  pub fn device_lock_owner(&self, device_index: u32) -> Option<u32> {
+    self
+      .device_sessions
+      .get(&device_index)
+      .and_then(|sessions| sessions.lock_owner)
+  }
+
   // Only a ButtplugServer should be able to call this. We don't want to expose this capability to
   // the outside world. Note that this could cause issues for lifetimes if someone holds this longer
   // than the lifetime of the server that originally created it. Ideally we should lock the Server
@@ -388,6 +1458,50 @@ impl ServerDeviceManager {
 impl Drop for ServerDeviceManager {
   fn drop(&mut self) {
     info!("Dropping device manager!");
+    // If we weren't shut down, whatever owned us went away without cleaning up, i.e. it panicked.
+    // Stop everything on the way out, so devices don't keep running with no one to stop them.
+    if self.running.load(Ordering::SeqCst) {
+      if async_manager::can_spawn() {
+        let stop_devices = self.stop_all_devices();
+        async_manager::spawn(async move {
+          let _ = stop_devices.await;
+        });
+      } else {
+        error!("Device manager dropped outside of an async runtime, cannot stop devices.");
+      }
+    }
     self.loop_cancellation_token.cancel();
   }
 }
+
+/// Waits for stop commands to go through, giving each device up to [DEVICE_STOP_TIMEOUT]. There's
+/// nothing else to try if a stop fails, so failures are logged rather than returned.
+async fn await_device_stops(stops: Vec<(u32, ButtplugServerResultFuture)>) {
+  let fut_vec = stops.into_iter().map(|(device_index, stop)| async move {
+    select! {
+      result = stop.fuse() => {
+        if let Err(err) = result {
+          error!("Could not stop device {}: {:?}", device_index, err);
+        }
+      }
+      _ = sleep(DEVICE_STOP_TIMEOUT).fuse() => {
+        error!(
+          "Device {} did not stop within {:?}, giving up on it.",
+          device_index, DEVICE_STOP_TIMEOUT
+        );
+      }
+    }
+  });
+  future::join_all(fut_vec).await;
+}
+
+fn check_device_lock(
+  sessions: &DeviceSessions,
+  device_index: u32,
+  session_id: u32,
+) -> Result<(), ButtplugDeviceError> {
+  match sessions.lock_owner {
+    Some(owner) if owner != session_id => Err(ButtplugDeviceError::DeviceLocked(device_index)),
+    _ => Ok(()),
+  }
+}
@@ -7,37 +7,100 @@
 
 use crate::{
   core::message::{ButtplugServerMessage, DeviceAdded, DeviceRemoved, ScanningFinished},
-  server::device::{
-    configuration::DeviceConfigurationManager,
-    hardware::communication::{HardwareCommunicationManager, HardwareCommunicationManagerEvent},
-    server_device::build_server_device,
-    ServerDevice,
-    ServerDeviceEvent,
+  server::{
+    device::{
+      configuration::DeviceConfigurationManager,
+      hardware::communication::{
+        HardwareCommunicationManager,
+        HardwareCommunicationManagerBuilder,
+        HardwareCommunicationManagerEvent,
+      },
+      server_device::{build_server_device, DeviceInitializationSettings},
+      DeviceCommandQueueSettings,
+      ServerDevice,
+      ServerDeviceEvent,
+    },
+    event_fanout::EventFanout,
+    ButtplugServerError,
   },
-  util::async_manager,
+  util::{async_manager, sleep},
 };
 use dashmap::{DashMap, DashSet};
 use futures::{future, FutureExt, StreamExt};
-use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
-use tokio_util::sync::CancellationToken;
+use instant::Instant;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio_util::sync::{CancellationToken, DropGuard};
 use tracing;
 use tracing_futures::Instrument;
 
-use super::server_device_manager::DeviceManagerCommand;
+use super::{
+  server_device_battery_monitor::BatteryMonitor,
+  server_device_manager::{DeviceManagerCommand, UnmatchedDevice},
+};
+
+/// How long each background scan runs for.
+const BACKGROUND_SCAN_DURATION: Duration = Duration::from_secs(5);
+/// How long to wait between background scans. Keeps radios mostly idle, while devices that turn on
+/// are still found within half a minute.
+const BACKGROUND_SCAN_PAUSE: Duration = Duration::from_secs(25);
+
+/// Event from a comm manager, tagged with the name of the manager it came from.
+pub(super) type CommManagerEvent = (&'static str, HardwareCommunicationManagerEvent);
+
+/// Builds a comm manager, forwarding its events (tagged with its name) to the device manager until
+/// the returned guard is dropped.
+pub(super) fn start_comm_manager(
+  builder: &mut dyn HardwareCommunicationManagerBuilder,
+  event_sender: mpsc::Sender<CommManagerEvent>,
+) -> (Box<dyn HardwareCommunicationManager>, DropGuard) {
+  let (sender, mut receiver) = mpsc::channel(256);
+  let comm_mgr = builder.finish(sender);
+  let name = comm_mgr.name();
+  debug_assert_eq!(
+    name,
+    builder.name(),
+    "Comm manager builder and manager names should match."
+  );
+  let token = CancellationToken::new();
+  let child_token = token.child_token();
+  async_manager::spawn(async move {
+    loop {
+      tokio::select! {
+        event = receiver.recv() => {
+          let Some(event) = event else {
+            break;
+          };
+          if event_sender.send((name, event)).await.is_err() {
+            break;
+          }
+        }
+        _ = child_token.cancelled() => break,
+      }
+    }
+  });
+  (comm_mgr, token.drop_guard())
+}
 
 pub(super) struct ServerDeviceManagerEventLoop {
   comm_managers: Vec<Box<dyn HardwareCommunicationManager>>,
+  /// Stops event forwarding for each comm manager when dropped, keyed by manager name.
+  comm_manager_guards: HashMap<&'static str, DropGuard>,
+  /// Sender for comm manager events, handed to comm managers added after startup.
+  device_comm_sender: mpsc::Sender<CommManagerEvent>,
+  /// Maps device addresses to the name of the comm manager that found them, so a manager's devices
+  /// can be disconnected when it's removed.
+  device_comm_managers: HashMap<String, &'static str>,
   device_config_manager: Arc<DeviceConfigurationManager>,
   device_command_receiver: mpsc::Receiver<DeviceManagerCommand>,
   /// Maps device index (exposed to the outside world) to actual device objects held by the server.
   device_map: Arc<DashMap<u32, Arc<ServerDevice>>>,
   /// Broadcaster that relays device events in the form of Buttplug Messages to
   /// whoever owns the Buttplug Server.
-  server_sender: broadcast::Sender<ButtplugServerMessage>,
+  server_sender: EventFanout,
   /// As the device manager owns the Device Communication Managers, it will have
   /// a receiver that the comm managers all send thru.
-  device_comm_receiver: mpsc::Receiver<HardwareCommunicationManagerEvent>,
+  device_comm_receiver: mpsc::Receiver<CommManagerEvent>,
   /// Sender for device events, passed to new devices when they are created.
   device_event_sender: mpsc::Sender<ServerDeviceEvent>,
   /// Receiver for device events, which the event loops to handle events.
@@ -47,26 +110,83 @@ pub(super) struct ServerDeviceManagerEventLoop {
   scanning_bringup_in_progress: bool,
   /// Denote whether scanning has been started since we last sent a ScanningFinished message.
   scanning_started: bool,
+  /// When the current scan should be stopped, if it was started with a timeout.
+  scanning_deadline: Option<Instant>,
+  /// True if comm managers are running a background scan, as opposed to a client requested one.
+  background_scan_active: bool,
+  /// When to next start or stop a background scan. Only set if background scanning is enabled.
+  next_background_scan_change: Option<Instant>,
   /// Devices currently trying to connect.
   connecting_devices: Arc<DashSet<String>>,
   /// Cancellation token for the event loop
   loop_cancellation_token: CancellationToken,
+  /// Bounds for the command queues of devices created by this loop.
+  command_queue_settings: DeviceCommandQueueSettings,
+  /// Timeout and retries for connecting devices.
+  initialization_settings: DeviceInitializationSettings,
+  /// Limits how many devices connect and initialize at once. Devices waiting for a permit are still
+  /// marked as connecting.
+  initialization_permits: Arc<Semaphore>,
+  /// Comm manager names, most preferred first, for choosing between connections to the same
+  /// physical device.
+  comm_manager_preference: Vec<String>,
+  /// Sends low battery warnings for battery readings in device events, if a threshold was set.
+  battery_monitor: Option<Arc<BatteryMonitor>>,
+  /// Reports devices no protocol matched, for
+  /// [ServerDeviceManager::unmatched_device_stream](super::ServerDeviceManager::unmatched_device_stream).
+  unmatched_device_sender: broadcast::Sender<UnmatchedDevice>,
+}
+
+/// Key identifying the physical device behind an address. Comm managers format the same hardware
+/// address differently (i.e. "AA:BB:CC:DD:EE:FF" from bluetooth, "aabbccddeeff" from Lovense
+/// Connect), so this drops case and separators.
+fn physical_device_key(address: &str) -> String {
+  address
+    .chars()
+    .filter(char::is_ascii_alphanumeric)
+    .map(|c| c.to_ascii_lowercase())
+    .collect()
+}
+
+/// Time left until `deadline`, if there is one.
+fn time_until(deadline: Option<Instant>) -> Option<Duration> {
+  deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
 }
 
 impl ServerDeviceManagerEventLoop {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
-    comm_managers: Vec<Box<dyn HardwareCommunicationManager>>,
-    device_config_manager: DeviceConfigurationManager,
+    comm_managers: Vec<(Box<dyn HardwareCommunicationManager>, DropGuard)>,
+    device_config_manager: Arc<DeviceConfigurationManager>,
     device_map: Arc<DashMap<u32, Arc<ServerDevice>>>,
     loop_cancellation_token: CancellationToken,
-    server_sender: broadcast::Sender<ButtplugServerMessage>,
-    device_comm_receiver: mpsc::Receiver<HardwareCommunicationManagerEvent>,
+    server_sender: EventFanout,
+    device_comm_sender: mpsc::Sender<CommManagerEvent>,
+    device_comm_receiver: mpsc::Receiver<CommManagerEvent>,
     device_command_receiver: mpsc::Receiver<DeviceManagerCommand>,
+    command_queue_settings: DeviceCommandQueueSettings,
+    initialization_settings: DeviceInitializationSettings,
+    initialization_concurrency: usize,
+    comm_manager_preference: Vec<String>,
+    background_scanning: bool,
+    battery_monitor: Option<Arc<BatteryMonitor>>,
+    unmatched_device_sender: broadcast::Sender<UnmatchedDevice>,
   ) -> Self {
     let (device_event_sender, device_event_receiver) = mpsc::channel(256);
+    let mut comm_manager_guards = HashMap::new();
+    let comm_managers = comm_managers
+      .into_iter()
+      .map(|(mgr, guard)| {
+        comm_manager_guards.insert(mgr.name(), guard);
+        mgr
+      })
+      .collect();
     Self {
       comm_managers,
-      device_config_manager: Arc::new(device_config_manager),
+      comm_manager_guards,
+      device_comm_sender,
+      device_comm_managers: HashMap::new(),
+      device_config_manager,
       server_sender,
       device_map,
       device_comm_receiver,
@@ -75,11 +195,54 @@ impl ServerDeviceManagerEventLoop {
       device_command_receiver,
       scanning_bringup_in_progress: false,
       scanning_started: false,
+      scanning_deadline: None,
+      background_scan_active: false,
+      // Start the first background scan as soon as the loop runs.
+      next_background_scan_change: background_scanning.then(Instant::now),
       connecting_devices: Arc::new(DashSet::new()),
       loop_cancellation_token,
+      command_queue_settings,
+      initialization_settings,
+      initialization_permits: Arc::new(Semaphore::new(initialization_concurrency.max(1))),
+      comm_manager_preference,
+      battery_monitor,
+      unmatched_device_sender,
     }
   }
 
+  /// Position of a comm manager in the preference order, lower is more preferred.
+  fn comm_manager_rank(&self, comm_mgr_name: &str) -> usize {
+    self
+      .comm_manager_preference
+      .iter()
+      .position(|name| name == comm_mgr_name)
+      .unwrap_or(self.comm_manager_preference.len())
+  }
+
+  /// Finds another connection to the same physical device as `address`, made through a different
+  /// address. Returns its address and the name of the comm manager that found it. If
+  /// `include_connecting` is false, only devices that have finished connecting count.
+  fn find_duplicate_device(
+    &self,
+    address: &str,
+    include_connecting: bool,
+  ) -> Option<(String, &'static str)> {
+    let key = physical_device_key(address);
+    self
+      .device_comm_managers
+      .iter()
+      .find(|(other_address, _)| {
+        other_address.as_str() != address
+          && physical_device_key(other_address) == key
+          && ((include_connecting && self.connecting_devices.contains(*other_address))
+            || self
+              .device_map
+              .iter()
+              .any(|entry| entry.value().identifier().address() == *other_address))
+      })
+      .map(|(other_address, comm_mgr_name)| (other_address.clone(), *comm_mgr_name))
+  }
+
   fn scanning_status(&self) -> bool {
     if self.comm_managers.iter().any(|x| x.scanning_status()) {
       debug!("At least one manager still scanning, continuing event loop.");
@@ -88,15 +251,7 @@ impl ServerDeviceManagerEventLoop {
     false
   }
 
-  async fn handle_start_scanning(&mut self) {
-    if self.scanning_status() || self.scanning_bringup_in_progress {
-      debug!("System already scanning, ignoring new scanning request");
-      return;
-    }
-
-    info!("No scan currently in progress, starting new scan.");
-    self.scanning_bringup_in_progress = true;
-    self.scanning_started = true;
+  async fn start_comm_manager_scans(&mut self) {
     let fut_vec: Vec<_> = self
       .comm_managers
       .iter_mut()
@@ -104,11 +259,9 @@ impl ServerDeviceManagerEventLoop {
       .collect();
     // TODO If start_scanning fails anywhere, this will ignore it. We should maybe at least log?
     future::join_all(fut_vec).await;
-    debug!("Scanning started for all hardware comm managers.");
-    self.scanning_bringup_in_progress = false;
   }
 
-  async fn handle_stop_scanning(&mut self) {
+  async fn stop_comm_manager_scans(&mut self) {
     let fut_vec: Vec<_> = self
       .comm_managers
       .iter_mut()
@@ -118,7 +271,154 @@ impl ServerDeviceManagerEventLoop {
     future::join_all(fut_vec).await;
   }
 
-  async fn handle_device_communication(&mut self, event: HardwareCommunicationManagerEvent) {
+  async fn handle_start_scanning(&mut self, timeout: Option<Duration>) {
+    // The most recent request decides when scanning stops, even if we're already scanning.
+    self.scanning_deadline = timeout.map(|timeout| Instant::now() + timeout);
+    if self.background_scan_active {
+      // Managers are already scanning, the client scan takes the background scan over instead of
+      // restarting it.
+      info!("Background scan in progress, continuing it as a client scan.");
+      self.background_scan_active = false;
+      self.scanning_started = true;
+      return;
+    }
+    if self.scanning_status() || self.scanning_bringup_in_progress {
+      debug!("System already scanning, ignoring new scanning request");
+      return;
+    }
+
+    info!("No scan currently in progress, starting new scan.");
+    self.scanning_bringup_in_progress = true;
+    self.scanning_started = true;
+    self.start_comm_manager_scans().await;
+    debug!("Scanning started for all hardware comm managers.");
+    self.scanning_bringup_in_progress = false;
+  }
+
+  async fn handle_stop_scanning(&mut self) {
+    self.scanning_deadline = None;
+    self.stop_comm_manager_scans().await;
+  }
+
+  async fn handle_background_scan_change(&mut self) {
+    if self.background_scan_active {
+      debug!("Background scan window over, stopping scan.");
+      self.background_scan_active = false;
+      self.next_background_scan_change = Some(Instant::now() + BACKGROUND_SCAN_PAUSE);
+      self.stop_comm_manager_scans().await;
+    } else if self.scanning_started || self.scanning_status() {
+      // Someone else is scanning already, check back after they've had a while to finish.
+      self.next_background_scan_change = Some(Instant::now() + BACKGROUND_SCAN_PAUSE);
+    } else {
+      debug!("Starting background scan.");
+      self.background_scan_active = true;
+      self.next_background_scan_change = Some(Instant::now() + BACKGROUND_SCAN_DURATION);
+      self.start_comm_manager_scans().await;
+    }
+  }
+
+  async fn handle_scanning_timeout(&mut self) {
+    info!("Scanning timeout reached, stopping scan.");
+    self.handle_stop_scanning().await;
+    // Managers that stopped will report it, and we'll send ScanningFinished then. If no manager was
+    // actually scanning anymore, nothing will report, so make sure clients still hear about it.
+    if self.scanning_started && !self.scanning_status() {
+      self.scanning_started = false;
+      if !self
+        .server_sender
+        .send(ScanningFinished::default().into())
+        .await
+      {
+        info!("Server disappeared, exiting loop.");
+      }
+    }
+  }
+
+  async fn handle_add_comm_manager(
+    &mut self,
+    mut builder: Box<dyn HardwareCommunicationManagerBuilder>,
+  ) -> Result<(), ButtplugServerError> {
+    // Check before building, so a rejected manager never starts up or sends us events.
+    if self
+      .comm_managers
+      .iter()
+      .any(|mgr| mgr.name() == builder.name())
+    {
+      return Err(
+        ButtplugServerError::DeviceCommunicationManagerTypeAlreadyAdded(builder.name().to_owned()),
+      );
+    }
+    let (mut comm_mgr, guard) =
+      start_comm_manager(builder.as_mut(), self.device_comm_sender.clone());
+    info!("Adding comm manager {}", comm_mgr.name());
+    // If we're in the middle of a scan, the new manager should join it.
+    if self.scanning_started || self.background_scan_active {
+      if let Err(err) = comm_mgr.start_scanning().await {
+        error!("Cannot start scanning on {}: {:?}", comm_mgr.name(), err);
+      }
+    }
+    self.comm_manager_guards.insert(comm_mgr.name(), guard);
+    self.comm_managers.push(comm_mgr);
+    Ok(())
+  }
+
+  async fn handle_remove_comm_manager(&mut self, name: &str) -> Result<(), ButtplugServerError> {
+    let Some(position) = self.comm_managers.iter().position(|mgr| mgr.name() == name) else {
+      return Err(ButtplugServerError::DeviceCommunicationManagerDoesNotExist(
+        name.to_owned(),
+      ));
+    };
+    info!("Removing comm manager {}", name);
+    let mut comm_mgr = self.comm_managers.remove(position);
+    // Stop forwarding events before anything else, so we don't pick up devices while tearing down.
+    self.comm_manager_guards.remove(name);
+    if comm_mgr.scanning_status() {
+      if let Err(err) = comm_mgr.stop_scanning().await {
+        error!("Cannot stop scanning on {}: {:?}", name, err);
+      }
+    }
+    // Disconnect the manager's devices. Disconnection events will remove them from the device map
+    // and let clients know they're gone. Devices that are still connecting lose their manager entry
+    // here too, and will be dropped once they finish connecting.
+    let addresses: Vec<String> = self
+      .device_comm_managers
+      .iter()
+      .filter(|(_, mgr_name)| **mgr_name == name)
+      .map(|(address, _)| address.clone())
+      .collect();
+    for address in addresses {
+      self.device_comm_managers.remove(&address);
+      let device = self
+        .device_map
+        .iter()
+        .find(|entry| *entry.value().identifier().address() == address)
+        .map(|entry| entry.value().clone());
+      if let Some(device) = device {
+        if let Err(err) = device.disconnect().await {
+          error!("Error disconnecting device {}: {:?}", address, err);
+        }
+      }
+    }
+    // Our last manager may have been the only one still scanning.
+    if self.scanning_started && !self.scanning_status() {
+      self.scanning_started = false;
+      self.scanning_deadline = None;
+      if !self
+        .server_sender
+        .send(ScanningFinished::default().into())
+        .await
+      {
+        info!("Server disappeared, exiting loop.");
+      }
+    }
+    Ok(())
+  }
+
+  async fn handle_device_communication(
+    &mut self,
+    comm_mgr_name: &'static str,
+    event: HardwareCommunicationManagerEvent,
+  ) {
     match event {
       HardwareCommunicationManagerEvent::ScanningFinished => {
         debug!(
@@ -128,13 +428,20 @@ impl ServerDeviceManagerEventLoop {
           debug!("Hardware Comm Manager finished before scanning was fully started, continuing event loop.");
           return;
         }
+        if !self.scanning_status() && self.background_scan_active {
+          // Background scans end quietly, clients never asked for them.
+          debug!("All managers finished background scan.");
+          self.background_scan_active = false;
+          self.next_background_scan_change = Some(Instant::now() + BACKGROUND_SCAN_PAUSE);
+        }
         if !self.scanning_status() && self.scanning_started {
           debug!("All managers finished, emitting ScanningFinished");
           self.scanning_started = false;
-          if self
+          self.scanning_deadline = None;
+          if !self
             .server_sender
             .send(ScanningFinished::default().into())
-            .is_err()
+            .await
           {
             info!("Server disappeared, exiting loop.");
           }
@@ -146,6 +453,14 @@ impl ServerDeviceManagerEventLoop {
         creator,
       } => {
         info!("Device {} ({}) found.", name, address);
+        // Events can still be queued from a comm manager that has since been removed.
+        if !self.comm_manager_guards.contains_key(comm_mgr_name) {
+          debug!(
+            "Comm manager {} was removed, ignoring device.",
+            comm_mgr_name
+          );
+          return;
+        }
         // Make sure the device isn't on the deny list, or is on the allow list if anything is on it.
         if !self.device_config_manager.address_allowed(&address) {
           return;
@@ -169,6 +484,25 @@ impl ServerDeviceManagerEventLoop {
           return;
         }
 
+        // The same device may be reachable through another comm manager. Only go ahead if this path
+        // is preferred. The other connection is replaced once this one is up, in case connecting
+        // fails.
+        if let Some((other_address, other_comm_mgr_name)) =
+          self.find_duplicate_device(&address, true)
+        {
+          if self.comm_manager_rank(comm_mgr_name) >= self.comm_manager_rank(other_comm_mgr_name) {
+            debug!(
+              "Device {} is already reachable as {} via {}, ignoring new device event.",
+              address, other_address, other_comm_mgr_name
+            );
+            return;
+          }
+          info!(
+            "Device {} is reachable as {} via {}, connecting via preferred {}.",
+            address, other_address, other_comm_mgr_name, comm_mgr_name
+          );
+        }
+
         // First off, we need to see if we even have a configuration available for the device we're
         // trying to create. If we don't, exit, because this isn't actually an error. However, if we
         // actually *do* have a configuration but something goes wrong after this, then it's an
@@ -180,15 +514,24 @@ impl ServerDeviceManagerEventLoop {
           .device_config_manager
           .protocol_specializers(&creator.specifier());
 
-        // If we have no identifiers, then there's nothing to do here. Throw an error.
+        // If we have no identifiers, then there's nothing to do here. Report everything the device
+        // told us about itself, so unsupported devices can be added to the configuration.
         if protocol_specializers.is_empty() {
+          let specifier = creator.specifier();
           debug!(
-            "{}",
-            format!(
-              "No viable protocols for hardware {:?}, ignoring.",
-              creator.specifier()
-            )
+            name = %name,
+            address = %address,
+            comm_manager = comm_mgr_name,
+            specifier = ?specifier,
+            "No viable protocols for hardware, ignoring."
           );
+          // Having no subscribers isn't an error.
+          let _ = self.unmatched_device_sender.send(UnmatchedDevice::new(
+            comm_mgr_name,
+            &name,
+            &address,
+            specifier,
+          ));
           return;
         }
 
@@ -205,11 +548,17 @@ impl ServerDeviceManagerEventLoop {
         }
 
         self.connecting_devices.insert(address.clone());
+        self
+          .device_comm_managers
+          .insert(address.clone(), comm_mgr_name);
 
         let device_event_sender_clone = self.device_event_sender.clone();
 
         let device_config_manager = self.device_config_manager.clone();
         let connecting_devices = self.connecting_devices.clone();
+        let command_queue_settings = self.command_queue_settings;
+        let initialization_settings = self.initialization_settings;
+        let initialization_permits = self.initialization_permits.clone();
         let span = info_span!(
           "device creation",
           name = tracing::field::display(name),
@@ -217,7 +566,19 @@ impl ServerDeviceManagerEventLoop {
         );
 
         async_manager::spawn(async move {
-          match build_server_device(device_config_manager, creator, protocol_specializers).await {
+          let _permit = initialization_permits
+            .acquire_owned()
+            .await
+            .expect("Initialization semaphore is never closed.");
+          match build_server_device(
+            device_config_manager,
+            creator,
+            protocol_specializers,
+            command_queue_settings,
+            initialization_settings,
+          )
+          .await
+          {
             Ok(device) => {
               if device_event_sender_clone
                 .send(ServerDeviceEvent::Connected(Arc::new(device)))
@@ -247,6 +608,80 @@ impl ServerDeviceManagerEventLoop {
         );
         let _enter = span.enter();
 
+        // The protocol may have been disabled while the device was connecting.
+        if !self
+          .device_config_manager
+          .protocol_enabled(device.identifier().protocol())
+        {
+          info!(
+            "Protocol {} disabled while device was connecting, disconnecting.",
+            device.identifier().protocol()
+          );
+          if let Err(err) = device.disconnect().await {
+            error!("Error disconnecting device: {:?}", err);
+          }
+          return;
+        }
+
+        // The comm manager that found the device may have been removed while it was connecting.
+        let address = device.identifier().address().clone();
+        if !self.device_comm_managers.contains_key(&address) {
+          info!("Comm manager removed while device was connecting, disconnecting.");
+          if let Err(err) = device.disconnect().await {
+            error!("Error disconnecting device: {:?}", err);
+          }
+          return;
+        }
+
+        // Arbitrate between connections to the same physical device through different comm
+        // managers, keeping the preferred one.
+        if let Some((other_address, other_comm_mgr_name)) =
+          self.find_duplicate_device(&address, false)
+        {
+          let comm_mgr_rank = self
+            .device_comm_managers
+            .get(&address)
+            .map(|comm_mgr_name| self.comm_manager_rank(comm_mgr_name))
+            .unwrap_or(usize::MAX);
+          if comm_mgr_rank < self.comm_manager_rank(other_comm_mgr_name) {
+            info!(
+              "Replacing connection to {} via {} with preferred connection.",
+              other_address, other_comm_mgr_name
+            );
+            // Remove the other device right away, so clients never see both. Its disconnect event
+            // won't find it in the map, so send the removal here.
+            let other_index = self
+              .device_map
+              .iter()
+              .find(|entry| *entry.value().identifier().address() == other_address)
+              .map(|entry| *entry.key());
+            if let Some((other_index, other_device)) =
+              other_index.and_then(|index| self.device_map.remove(&index))
+            {
+              if !self
+                .server_sender
+                .send(DeviceRemoved::new(other_index).into())
+                .await
+              {
+                debug!("Server not currently available, dropping Device Removed event.");
+              }
+              if let Err(err) = other_device.disconnect().await {
+                error!("Error disconnecting duplicate device: {:?}", err);
+              }
+            }
+          } else {
+            info!(
+              "Device is already connected as {} via {}, disconnecting duplicate.",
+              other_address, other_comm_mgr_name
+            );
+            self.device_comm_managers.remove(&address);
+            if let Err(err) = device.disconnect().await {
+              error!("Error disconnecting duplicate device: {:?}", err);
+            }
+            return;
+          }
+        }
+
         // See if we have a reserved or reusable device index here.
         let device_index = self.device_config_manager.device_index(device.identifier());
         // Since we can now reuse device indexes, this means we might possibly
@@ -289,20 +724,28 @@ impl ServerDeviceManagerEventLoop {
           &device.name(),
           &device.display_name(),
           &None,
+          &Some(device.transport()),
+          &device.version(),
+          &self
+            .device_config_manager
+            .device_metadata(device.identifier()),
           &device.message_attributes().into(),
         );
+        let battery_polling_task =
+          device.battery_polling_task(device_index, self.device_event_sender.clone());
         self.device_map.insert(device_index, device);
         // After that, we can send out to the server's event listeners to let
         // them know a device has been added.
-        if self
-          .server_sender
-          .send(device_added_message.into())
-          .is_err()
-        {
+        if !self.server_sender.send(device_added_message.into()).await {
           debug!("Server not currently available, dropping Device Added event.");
         }
+        // Start polling once clients know about the device, so readings never arrive first.
+        if let Some(task) = battery_polling_task {
+          async_manager::spawn(task);
+        }
       }
       ServerDeviceEvent::Disconnected(identifier) => {
+        self.device_comm_managers.remove(identifier.address());
         let mut device_index = None;
         for device_pair in self.device_map.iter() {
           if *device_pair.value().identifier() == identifier {
@@ -315,17 +758,31 @@ impl ServerDeviceManagerEventLoop {
             .device_map
             .remove(&device_index)
             .expect("Remove will always work.");
-          if self
+          if let Some(monitor) = &self.battery_monitor {
+            monitor.forget(device_index);
+          }
+          if !self
             .server_sender
             .send(DeviceRemoved::new(device_index).into())
-            .is_err()
+            .await
           {
             debug!("Server not currently available, dropping Device Removed event.");
           }
         }
       }
-      ServerDeviceEvent::Notification(_, message) => {
-        if self.server_sender.send(message.into()).is_err() {
+      ServerDeviceEvent::Notification(identifier, message) => {
+        let message: ButtplugServerMessage = message.into();
+        if let Some(monitor) = &self.battery_monitor {
+          let battery_level = self
+            .device_map
+            .iter()
+            .find(|device| *device.value().identifier() == identifier)
+            .and_then(|device| Some((*device.key(), device.value().battery_level(&message)?)));
+          if let Some((device_index, level)) = battery_level {
+            monitor.check(device_index, level).await;
+          }
+        }
+        if !self.server_sender.send(message).await {
           debug!("Server not currently available, dropping Device Added event.");
         }
       }
@@ -335,11 +792,19 @@ impl ServerDeviceManagerEventLoop {
   pub async fn run(&mut self) {
     debug!("Starting Device Manager Loop");
     loop {
+      let scanning_timeout = time_until(self.scanning_deadline);
+      let background_scan_timeout = time_until(self.next_background_scan_change);
       tokio::select! {
+        _ = sleep(scanning_timeout.unwrap_or_default()), if scanning_timeout.is_some() => {
+          self.handle_scanning_timeout().await;
+        }
+        _ = sleep(background_scan_timeout.unwrap_or_default()), if background_scan_timeout.is_some() => {
+          self.handle_background_scan_change().await;
+        }
         device_comm_msg = self.device_comm_receiver.recv() => {
-          if let Some(msg) = device_comm_msg {
-            trace!("Got device communication message {:?}", msg);
-            self.handle_device_communication(msg).await;
+          if let Some((comm_mgr_name, msg)) = device_comm_msg {
+            trace!("Got device communication message {:?} from {}", msg, comm_mgr_name);
+            self.handle_device_communication(comm_mgr_name, msg).await;
           } else {
             break;
           }
@@ -357,8 +822,16 @@ impl ServerDeviceManagerEventLoop {
           if let Some(msg) = device_command_msg {
             trace!("Got device command message {:?}", msg);
             match msg {
-              DeviceManagerCommand::StartScanning => self.handle_start_scanning().await,
+              DeviceManagerCommand::StartScanning(timeout) => self.handle_start_scanning(timeout).await,
               DeviceManagerCommand::StopScanning => self.handle_stop_scanning().await,
+              DeviceManagerCommand::AddCommManager(builder, result_sender) => {
+                let result = self.handle_add_comm_manager(builder).await;
+                let _ = result_sender.send(result);
+              }
+              DeviceManagerCommand::RemoveCommManager(name, result_sender) => {
+                let result = self.handle_remove_comm_manager(&name).await;
+                let _ = result_sender.send(result);
+              }
             }
           } else {
             debug!("Channel to Device Manager frontend dropped, exiting event loop.");
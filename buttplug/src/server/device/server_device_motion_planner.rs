@@ -0,0 +1,142 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Velocity and acceleration limits for linear devices, see
+//! [LinearMotionLimits](super::configuration::LinearMotionLimits).
+
+use super::configuration::LinearMotionLimits;
+use crate::core::message::{ButtplugDeviceMessage, ButtplugMessage, LinearCmd, VectorSubcommand};
+use dashmap::DashMap;
+
+/// Stretches out [LinearCmd] moves that are faster than the device can physically manage.
+///
+/// Moves are only ever slowed down, never sped up, and still end at the position the client asked
+/// for, so clients just see the device arrive a little late instead of stalling or skipping.
+pub(super) struct MotionPlanner {
+  max_velocity: Option<f64>,
+  max_acceleration: Option<f64>,
+  /// Last position sent to each linear feature.
+  positions: DashMap<u32, f64>,
+}
+
+impl MotionPlanner {
+  pub(super) fn new(limits: LinearMotionLimits) -> Self {
+    Self {
+      max_velocity: limits.max_velocity().filter(|x| *x > 0.0),
+      max_acceleration: limits.max_acceleration().filter(|x| *x > 0.0),
+      positions: DashMap::new(),
+    }
+  }
+
+  /// Shortest time the device can move `distance` in, in milliseconds, assuming it starts and ends
+  /// at rest.
+  fn min_duration(&self, distance: f64) -> f64 {
+    let seconds = match (self.max_velocity, self.max_acceleration) {
+      (None, None) => 0.0,
+      (Some(velocity), None) => distance / velocity,
+      // Speed up for half the distance, slow down for the other half.
+      (None, Some(acceleration)) => 2.0 * (distance / acceleration).sqrt(),
+      (Some(velocity), Some(acceleration)) => {
+        if distance <= velocity * velocity / acceleration {
+          // Never gets up to max velocity before it has to start slowing down.
+          2.0 * (distance / acceleration).sqrt()
+        } else {
+          // Trapezoidal profile, cruising at max velocity between speeding up and slowing down.
+          distance / velocity + velocity / acceleration
+        }
+      }
+    };
+    seconds * 1000.0
+  }
+
+  /// Returns the command with any move durations stretched to fit the device's limits.
+  pub(super) fn plan(&self, msg: LinearCmd) -> LinearCmd {
+    let vectors = msg
+      .vectors()
+      .iter()
+      .map(|vector| {
+        // We don't know where the device is before the first move, so assume the worst.
+        let distance = self
+          .positions
+          .insert(vector.index(), vector.position())
+          .map_or(1.0, |previous| (vector.position() - previous).abs());
+        let min_duration = self.min_duration(distance).ceil() as u32;
+        if vector.duration() < min_duration {
+          debug!(
+            "Linear move for feature {} too fast for device, stretching from {}ms to {}ms.",
+            vector.index(),
+            vector.duration(),
+            min_duration
+          );
+          VectorSubcommand::new(vector.index(), min_duration, vector.position())
+        } else {
+          vector.clone()
+        }
+      })
+      .collect();
+    let mut planned = LinearCmd::new(msg.device_index(), vectors);
+    planned.set_id(msg.id());
+    planned
+  }
+
+  /// Forgets the positions of all features, for when the device may have been moved by something
+  /// other than a linear command.
+  pub(super) fn reset(&self) {
+    self.positions.clear();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn durations(planner: &MotionPlanner, moves: &[(u32, f64)]) -> Vec<u32> {
+    moves
+      .iter()
+      .map(|(duration, position)| {
+        planner
+          .plan(LinearCmd::new(
+            0,
+            vec![VectorSubcommand::new(0, *duration, *position)],
+          ))
+          .vectors()[0]
+          .duration()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_motion_planner_velocity_limit() {
+    let planner = MotionPlanner::new(LinearMotionLimits::new(Some(2.0), None));
+    // First move is assumed to cross the full range, then moves of 0.5, 0.1 and 0.8.
+    assert_eq!(
+      durations(&planner, &[(100, 0.5), (100, 0.0), (100, 0.1), (1000, 0.9)]),
+      vec![500, 250, 100, 1000]
+    );
+  }
+
+  #[test]
+  fn test_motion_planner_acceleration_limit() {
+    let planner = MotionPlanner::new(LinearMotionLimits::new(Some(2.0), Some(8.0)));
+    planner.plan(LinearCmd::new(0, vec![VectorSubcommand::new(0, 0, 0.0)]));
+    // Short moves never reach max velocity, long ones cruise for a bit.
+    assert_eq!(
+      durations(&planner, &[(10, 0.125), (10, 0.125), (10, 1.0)]),
+      vec![250, 10, 688]
+    );
+  }
+
+  #[test]
+  fn test_motion_planner_keeps_message_details() {
+    let planner = MotionPlanner::new(LinearMotionLimits::new(Some(1.0), None));
+    let mut msg = LinearCmd::new(3, vec![VectorSubcommand::new(1, 2000, 0.5)]);
+    msg.set_id(7);
+    let planned = planner.plan(msg.clone());
+    assert_eq!(planned, msg);
+    assert_eq!(planned.device_index(), 3);
+  }
+}
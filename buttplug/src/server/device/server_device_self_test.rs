@@ -0,0 +1,168 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Scripted device self-test, run for
+//! [DeviceSelfTestCmd](crate::core::message::DeviceSelfTestCmd).
+
+use super::configuration::ServerDeviceMessageAttributes;
+use crate::{
+  core::message::{
+    ActuatorTestResult,
+    ActuatorType,
+    ButtplugDeviceCommandMessageUnion,
+    ButtplugServerMessage,
+    DeviceSelfTestReport,
+    LinearCmd,
+    RotateCmd,
+    RotationSubcommand,
+    ScalarCmd,
+    ScalarSubcommand,
+    SensorReadCmd,
+    SensorTestResult,
+    StopDeviceCmd,
+    VectorSubcommand,
+  },
+  server::ButtplugServerResultFuture,
+  util::sleep,
+};
+use instant::Instant;
+use std::time::Duration;
+
+/// Intensity actuators are pulsed at, enough to be felt or heard without surprising anyone.
+const SELF_TEST_INTENSITY: f64 = 0.25;
+/// How long each actuator runs before it's turned off again.
+const SELF_TEST_PULSE: Duration = Duration::from_millis(250);
+
+fn elapsed_ms(start: Instant) -> u32 {
+  start.elapsed().as_millis().min(u32::MAX as u128) as u32
+}
+
+/// Sends `on`, waits out the pulse, then sends `off`. Times how long the device took to accept
+/// `on`, and fails if either command does.
+async fn pulse_actuator<F>(
+  send: &F,
+  index: u32,
+  actuator_type: ActuatorType,
+  on: ButtplugDeviceCommandMessageUnion,
+  off: ButtplugDeviceCommandMessageUnion,
+) -> ActuatorTestResult
+where
+  F: Fn(ButtplugDeviceCommandMessageUnion) -> ButtplugServerResultFuture,
+{
+  let start = Instant::now();
+  let on_result = send(on).await;
+  let duration_ms = elapsed_ms(start);
+  sleep(SELF_TEST_PULSE).await;
+  let off_result = send(off).await;
+  let error = on_result.and(off_result).err().map(|err| err.to_string());
+  ActuatorTestResult::new(index, actuator_type, duration_ms, error)
+}
+
+/// Pulses each actuator in turn, then reads each sensor, sending every command through `send`.
+/// The device is stopped once the test is done, whatever the results.
+pub(super) async fn run_self_test<F>(
+  device_index: u32,
+  attributes: ServerDeviceMessageAttributes,
+  send: F,
+) -> DeviceSelfTestReport
+where
+  F: Fn(ButtplugDeviceCommandMessageUnion) -> ButtplugServerResultFuture,
+{
+  let mut actuators = vec![];
+  for (index, attrs) in attributes.scalar_cmd().iter().flatten().enumerate() {
+    let index = index as u32;
+    let actuator_type = *attrs.actuator_type();
+    let scalar = |value| {
+      ScalarCmd::new(
+        device_index,
+        vec![ScalarSubcommand::new(index, value, actuator_type)],
+      )
+      .into()
+    };
+    actuators.push(
+      pulse_actuator(
+        &send,
+        index,
+        actuator_type,
+        scalar(SELF_TEST_INTENSITY),
+        scalar(0.0),
+      )
+      .await,
+    );
+  }
+  for (index, attrs) in attributes.rotate_cmd().iter().flatten().enumerate() {
+    let index = index as u32;
+    let rotate = |speed| {
+      RotateCmd::new(
+        device_index,
+        vec![RotationSubcommand::new(index, speed, true)],
+      )
+      .into()
+    };
+    actuators.push(
+      pulse_actuator(
+        &send,
+        index,
+        *attrs.actuator_type(),
+        rotate(SELF_TEST_INTENSITY),
+        rotate(0.0),
+      )
+      .await,
+    );
+  }
+  for (index, attrs) in attributes.linear_cmd().iter().flatten().enumerate() {
+    let index = index as u32;
+    // Stroke a little way out and back, over the length of the pulse each way.
+    let linear = |position| {
+      LinearCmd::new(
+        device_index,
+        vec![VectorSubcommand::new(
+          index,
+          SELF_TEST_PULSE.as_millis() as u32,
+          position,
+        )],
+      )
+      .into()
+    };
+    actuators.push(
+      pulse_actuator(
+        &send,
+        index,
+        *attrs.actuator_type(),
+        linear(SELF_TEST_INTENSITY),
+        linear(0.0),
+      )
+      .await,
+    );
+  }
+
+  let mut sensors = vec![];
+  for (index, attrs) in attributes.sensor_read_cmd().iter().flatten().enumerate() {
+    let index = index as u32;
+    let sensor_type = *attrs.sensor_type();
+    let start = Instant::now();
+    let result = match send(SensorReadCmd::new(device_index, index, sensor_type).into()).await {
+      Ok(ButtplugServerMessage::SensorReading(reading)) => Ok(reading.data().clone()),
+      Ok(msg) => Err(format!("Unexpected reply to sensor read: {:?}", msg)),
+      Err(err) => Err(err.to_string()),
+    };
+    sensors.push(SensorTestResult::new(
+      index,
+      sensor_type,
+      elapsed_ms(start),
+      result,
+    ));
+  }
+
+  if let Err(err) = send(StopDeviceCmd::new(device_index).into()).await {
+    warn!(
+      "Could not stop device {} after self-test: {:?}",
+      device_index, err
+    );
+  }
+  DeviceSelfTestReport::new(device_index, actuators, sensors)
+}
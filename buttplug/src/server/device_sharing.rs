@@ -0,0 +1,247 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Device sharing, the inverse of [federation](crate::server::device::hardware::communication::buttplug_federation).
+//!
+//! Shares chosen local devices with another Buttplug server through that server's websocket device
+//! manager, so it can control them as if they were attached to it. Each shared device gets its own
+//! websocket connection, which announces the device as a `buttplug-passthru` device along with its
+//! name and features. The remote server then sends device commands back as JSON, which are run
+//! against the local device. Only actuators are shared, sensors stay local.
+
+use super::device::{
+  configuration::ServerDeviceMessageAttributes,
+  hardware::communication::websocket_server::websocket_server_comm_manager::WebsocketServerDeviceCommManagerInitInfo,
+  ServerDeviceManager,
+};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      ClientDeviceMessageAttributes,
+      ClientDeviceMessageAttributesBuilder,
+    },
+  },
+  util::async_manager,
+};
+use dashmap::DashMap;
+use futures::{pin_mut, SinkExt, StreamExt};
+use std::{sync::Arc, time::Duration};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_util::sync::CancellationToken;
+
+/// Websocket device identifier shared devices announce themselves with.
+pub const DEVICE_SHARING_IDENTIFIER: &str = "buttplug-passthru";
+
+/// Time to wait before reconnecting when the remote server goes away.
+const RECONNECT_WAIT: Duration = Duration::from_secs(5);
+
+/// Features of the device to tell the remote server about.
+fn shared_attributes(attrs: ServerDeviceMessageAttributes) -> ClientDeviceMessageAttributes {
+  let attrs: ClientDeviceMessageAttributes = attrs.into();
+  let mut builder = ClientDeviceMessageAttributesBuilder::default();
+  if let Some(scalar_cmd) = attrs.scalar_cmd() {
+    builder.scalar_cmd(scalar_cmd);
+  }
+  if let Some(rotate_cmd) = attrs.rotate_cmd() {
+    builder.rotate_cmd(rotate_cmd);
+  }
+  if let Some(linear_cmd) = attrs.linear_cmd() {
+    builder.linear_cmd(linear_cmd);
+  }
+  builder.finish()
+}
+
+async fn run_command(device_manager: &ServerDeviceManager, device_index: u32, data: &[u8]) {
+  let mut command: ButtplugDeviceCommandMessageUnion = match serde_json::from_slice(data) {
+    Ok(command) => command,
+    Err(err) => {
+      warn!(
+        "Cannot parse command for shared device {}: {}",
+        device_index, err
+      );
+      return;
+    }
+  };
+  // The remote server addresses the device by its own index.
+  command.set_device_index(device_index);
+  if let Err(err) = device_manager.parse_device_message(command).await {
+    warn!("Shared device {} command failed: {}", device_index, err);
+  }
+}
+
+/// Runs a single connection to the remote server, until either side closes it.
+async fn run_shared_connection(
+  server_address: &str,
+  init_info: &str,
+  device_manager: &ServerDeviceManager,
+  device_index: u32,
+  cancellation_token: &CancellationToken,
+) -> Result<(), String> {
+  let (ws_stream, _) = connect_async(server_address)
+    .await
+    .map_err(|err| err.to_string())?;
+  let (mut sender, mut receiver) = ws_stream.split();
+  sender
+    .send(Message::Text(init_info.to_owned()))
+    .await
+    .map_err(|err| err.to_string())?;
+  info!(
+    "Sharing device {} with Buttplug server at {}",
+    device_index, server_address
+  );
+  loop {
+    tokio::select! {
+      _ = cancellation_token.cancelled() => {
+        let _ = sender.close().await;
+        return Ok(());
+      }
+      msg = receiver.next() => match msg {
+        // Ping replies are handled by the websocket library.
+        Some(Ok(Message::Binary(data))) => run_command(device_manager, device_index, &data).await,
+        Some(Ok(Message::Text(text))) => {
+          run_command(device_manager, device_index, text.as_bytes()).await
+        }
+        Some(Ok(Message::Close(_))) | None => {
+          return Err("Connection closed by remote server".to_owned())
+        }
+        Some(Ok(_)) => continue,
+        Some(Err(err)) => return Err(err.to_string()),
+      }
+    }
+  }
+}
+
+/// Shares local devices with another Buttplug server. Sharing is toggled per device, and stops for
+/// all devices when this is dropped.
+pub struct DeviceSharing {
+  device_manager: Arc<ServerDeviceManager>,
+  server_address: String,
+  shared_devices: Arc<DashMap<u32, CancellationToken>>,
+}
+
+impl DeviceSharing {
+  /// Share devices from `device_manager` with the websocket device manager at `server_address`
+  /// (i.e. `ws://192.168.1.20:54817`). No devices are shared until
+  /// [DeviceSharing::share_device] is called.
+  pub fn new(device_manager: Arc<ServerDeviceManager>, server_address: &str) -> Self {
+    Self {
+      device_manager,
+      server_address: server_address.to_owned(),
+      shared_devices: Arc::new(DashMap::new()),
+    }
+  }
+
+  /// Start sharing a connected device. Sharing reconnects if the remote server goes away, and
+  /// continues until the device is unshared or disconnects.
+  pub fn share_device(&self, device_index: u32) -> Result<(), ButtplugDeviceError> {
+    if self.shared_devices.contains_key(&device_index) {
+      return Ok(());
+    }
+    let (Some(info), Some(attributes)) = (
+      self.device_manager.device_info(device_index),
+      self.device_manager.device_message_attributes(device_index),
+    ) else {
+      return Err(ButtplugDeviceError::DeviceNotAvailable(device_index));
+    };
+    // Use the local address, so the remote server sees the same device across connections.
+    let init_info = serde_json::to_string(&WebsocketServerDeviceCommManagerInitInfo::new(
+      DEVICE_SHARING_IDENTIFIER,
+      info.identifier().address(),
+      1,
+      Some(info.name().clone()),
+      Some(shared_attributes(attributes)),
+    ))
+    .expect("Type is always serializable");
+
+    let cancellation_token = CancellationToken::new();
+    self
+      .shared_devices
+      .insert(device_index, cancellation_token.clone());
+    let device_manager = self.device_manager.clone();
+    let shared_devices = self.shared_devices.clone();
+    let server_address = self.server_address.clone();
+    async_manager::spawn(async move {
+      let device_events = device_manager.event_stream();
+      pin_mut!(device_events);
+      let sharing = async {
+        loop {
+          if let Err(err) = run_shared_connection(
+            &server_address,
+            &init_info,
+            &device_manager,
+            device_index,
+            &cancellation_token,
+          )
+          .await
+          {
+            warn!(
+              "Lost connection sharing device {} with {}, retrying: {}",
+              device_index, server_address, err
+            );
+          }
+          tokio::select! {
+            _ = cancellation_token.cancelled() => return,
+            _ = tokio::time::sleep(RECONNECT_WAIT) => {}
+          }
+        }
+      };
+      let device_removed = async {
+        while let Some(msg) = device_events.next().await {
+          if let ButtplugServerMessage::DeviceRemoved(removed) = msg {
+            if removed.device_index() == device_index {
+              return;
+            }
+          }
+        }
+      };
+      let stop_on_removal = async {
+        tokio::select! {
+          _ = cancellation_token.cancelled() => {}
+          _ = device_removed => {
+            info!("Shared device {} disconnected, no longer sharing.", device_index);
+            shared_devices.remove(&device_index);
+            cancellation_token.cancel();
+          }
+        }
+      };
+      // Let the sharing loop see the cancellation, so it can close the connection properly.
+      futures::join!(sharing, stop_on_removal);
+    });
+    Ok(())
+  }
+
+  /// Stop sharing a device. Returns false if the device wasn't shared.
+  pub fn unshare_device(&self, device_index: u32) -> bool {
+    if let Some((_, cancellation_token)) = self.shared_devices.remove(&device_index) {
+      cancellation_token.cancel();
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn is_device_shared(&self, device_index: u32) -> bool {
+    self.shared_devices.contains_key(&device_index)
+  }
+
+  /// Indexes of the devices currently shared.
+  pub fn shared_devices(&self) -> Vec<u32> {
+    self.shared_devices.iter().map(|x| *x.key()).collect()
+  }
+}
+
+impl Drop for DeviceSharing {
+  fn drop(&mut self) {
+    for entry in self.shared_devices.iter() {
+      entry.value().cancel();
+    }
+  }
+}
@@ -0,0 +1,315 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Delivers server events to every event stream subscriber, with a per-subscriber policy for what
+//! happens when a subscriber falls behind.
+//!
+//! Tokio's broadcast channels drop the oldest events for lagging receivers no matter what they are,
+//! and our stream conversion ends the stream on lag, so a busy sensor could cost a client a
+//! DeviceAdded event (or its whole event stream). Here each subscriber gets its own queue, and
+//! device enumeration and error events are never dropped, only delayed.
+
+use crate::core::message::{ButtplugDeviceMessage, ButtplugServerMessage, SensorType};
+use async_stream::stream;
+use futures::Stream;
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+/// Number of events a subscriber can have queued before its [EventDropPolicy] kicks in.
+const SUBSCRIBER_QUEUE_CAPACITY: usize = 256;
+
+/// What to do with new events when an event stream subscriber isn't keeping up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventDropPolicy {
+  /// Wait for the subscriber to make room before delivering the event. Nothing is ever dropped, but
+  /// a subscriber that stops reading its stream without dropping it stalls event delivery for
+  /// everyone.
+  Block,
+  /// Drop the oldest queued events that can be dropped (readings and logs) to make room.
+  DropOldest,
+  /// Replace queued readings from the same device sensor with the newest reading, falling back to
+  /// dropping the oldest queued events that can be dropped.
+  #[default]
+  Collapse,
+}
+
+/// Identifies events that are newer versions of the same state, so only the newest one needs to be
+/// delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CollapseKey {
+  Sensor(u32, u32, SensorType),
+  BatteryLevel(u32),
+  RSSILevel(u32),
+}
+
+fn collapse_key(event: &ButtplugServerMessage) -> Option<CollapseKey> {
+  match event {
+    ButtplugServerMessage::SensorReading(msg) => Some(CollapseKey::Sensor(
+      msg.device_index(),
+      msg.sensor_index(),
+      msg.sensor_type(),
+    )),
+    ButtplugServerMessage::BatteryLevelReading(msg) => {
+      Some(CollapseKey::BatteryLevel(msg.device_index()))
+    }
+    ButtplugServerMessage::RSSILevelReading(msg) => {
+      Some(CollapseKey::RSSILevel(msg.device_index()))
+    }
+    _ => None,
+  }
+}
+
+/// Device enumeration and error events change what the client knows about the server, and losing
+/// them leaves the client out of sync, so they're queued even over capacity.
+fn is_droppable(event: &ButtplugServerMessage) -> bool {
+  !matches!(
+    event,
+    ButtplugServerMessage::DeviceAdded(_)
+      | ButtplugServerMessage::DeviceRemoved(_)
+      | ButtplugServerMessage::DeviceList(_)
+      | ButtplugServerMessage::ScanningFinished(_)
+      | ButtplugServerMessage::Error(_)
+  )
+}
+
+#[derive(Default)]
+struct SubscriberState {
+  events: VecDeque<ButtplugServerMessage>,
+  /// Set when either the subscriber stream or the fanout goes away.
+  closed: bool,
+}
+
+struct Subscriber {
+  policy: EventDropPolicy,
+  state: Mutex<SubscriberState>,
+  event_notify: Notify,
+  space_notify: Notify,
+}
+
+impl Subscriber {
+  fn lock(&self) -> std::sync::MutexGuard<'_, SubscriberState> {
+    self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic")
+  }
+
+  fn close(&self) {
+    self.lock().closed = true;
+    self.event_notify.notify_one();
+    self.space_notify.notify_one();
+  }
+
+  async fn deliver(&self, event: ButtplugServerMessage) {
+    loop {
+      {
+        let mut state = self.lock();
+        if state.closed {
+          return;
+        }
+        if self.policy == EventDropPolicy::Collapse {
+          if let Some(key) = collapse_key(&event) {
+            if let Some(queued) = state
+              .events
+              .iter_mut()
+              .find(|queued| collapse_key(queued) == Some(key))
+            {
+              *queued = event;
+              return;
+            }
+          }
+        }
+        if state.events.len() < SUBSCRIBER_QUEUE_CAPACITY {
+          state.events.push_back(event);
+          self.event_notify.notify_one();
+          return;
+        }
+        if self.policy != EventDropPolicy::Block {
+          if let Some(oldest) = state.events.iter().position(is_droppable) {
+            state.events.remove(oldest);
+            warn!("Event stream subscriber is lagging, dropping oldest event.");
+          }
+          state.events.push_back(event);
+          self.event_notify.notify_one();
+          return;
+        }
+      }
+      self.space_notify.notified().await;
+    }
+  }
+
+  async fn next_event(&self) -> Option<ButtplugServerMessage> {
+    loop {
+      {
+        let mut state = self.lock();
+        if let Some(event) = state.events.pop_front() {
+          self.space_notify.notify_one();
+          return Some(event);
+        }
+        if state.closed {
+          return None;
+        }
+      }
+      self.event_notify.notified().await;
+    }
+  }
+}
+
+/// Closes the subscriber when its stream is dropped, so senders stop queuing (or blocking) for it.
+struct SubscriberGuard(Arc<Subscriber>);
+
+impl Drop for SubscriberGuard {
+  fn drop(&mut self) {
+    self.0.close();
+  }
+}
+
+#[derive(Default)]
+struct EventFanoutInner {
+  subscribers: Mutex<Vec<Arc<Subscriber>>>,
+}
+
+impl Drop for EventFanoutInner {
+  fn drop(&mut self) {
+    // Let subscribers drain what they have queued, then end their streams.
+    for subscriber in self
+      .subscribers
+      .get_mut()
+      .expect("Lock is never held across a panic")
+      .iter()
+    {
+      subscriber.close();
+    }
+  }
+}
+
+/// Sender side of server event streams. Clones share the same set of subscribers, and streams end
+/// once the last clone is dropped.
+#[derive(Clone, Default)]
+pub(crate) struct EventFanout {
+  inner: Arc<EventFanoutInner>,
+}
+
+impl EventFanout {
+  /// Returns a stream of all events sent after this call, queued according to `policy`.
+  pub fn subscribe(&self, policy: EventDropPolicy) -> impl Stream<Item = ButtplugServerMessage> {
+    let subscriber = Arc::new(Subscriber {
+      policy,
+      state: Mutex::new(SubscriberState::default()),
+      event_notify: Notify::new(),
+      space_notify: Notify::new(),
+    });
+    self
+      .inner
+      .subscribers
+      .lock()
+      .expect("Lock is never held across a panic")
+      .push(subscriber.clone());
+    let guard = SubscriberGuard(subscriber);
+    stream! {
+      while let Some(event) = guard.0.next_event().await {
+        yield event;
+      }
+    }
+  }
+
+  /// Delivers an event to all current subscribers. Returns false if there were no subscribers to
+  /// deliver to.
+  pub async fn send(&self, event: ButtplugServerMessage) -> bool {
+    let subscribers: Vec<Arc<Subscriber>> = {
+      let mut subscribers = self
+        .inner
+        .subscribers
+        .lock()
+        .expect("Lock is never held across a panic");
+      subscribers.retain(|subscriber| !subscriber.lock().closed);
+      subscribers.clone()
+    };
+    if subscribers.is_empty() {
+      return false;
+    }
+    for subscriber in subscribers {
+      subscriber.deliver(event.clone()).await;
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{BatteryLevelReading, DeviceRemoved, Log, LogLevel};
+  use futures::{pin_mut, FutureExt, StreamExt};
+
+  fn log_event() -> ButtplugServerMessage {
+    Log::new(LogLevel::Info, "test").into()
+  }
+
+  #[tokio::test]
+  async fn test_event_fanout_drop_oldest_keeps_device_events() {
+    let fanout = EventFanout::default();
+    let stream = fanout.subscribe(EventDropPolicy::DropOldest);
+    pin_mut!(stream);
+    assert!(fanout.send(DeviceRemoved::new(1).into()).await);
+    for _ in 0..SUBSCRIBER_QUEUE_CAPACITY {
+      fanout.send(log_event()).await;
+    }
+    // The first log is dropped to make room, the device event stays at the front of the queue.
+    assert_eq!(stream.next().await, Some(DeviceRemoved::new(1).into()));
+    for _ in 1..SUBSCRIBER_QUEUE_CAPACITY {
+      assert_eq!(stream.next().await, Some(log_event()));
+    }
+    drop(fanout);
+    assert_eq!(stream.next().await, None);
+  }
+
+  #[tokio::test]
+  async fn test_event_fanout_collapse() {
+    let fanout = EventFanout::default();
+    let stream = fanout.subscribe(EventDropPolicy::Collapse);
+    pin_mut!(stream);
+    fanout.send(BatteryLevelReading::new(0, 0.5).into()).await;
+    fanout.send(BatteryLevelReading::new(1, 0.5).into()).await;
+    fanout.send(BatteryLevelReading::new(0, 0.4).into()).await;
+    assert_eq!(
+      stream.next().await,
+      Some(BatteryLevelReading::new(0, 0.4).into())
+    );
+    assert_eq!(
+      stream.next().await,
+      Some(BatteryLevelReading::new(1, 0.5).into())
+    );
+  }
+
+  #[tokio::test]
+  async fn test_event_fanout_block() {
+    let fanout = EventFanout::default();
+    let stream = fanout.subscribe(EventDropPolicy::Block);
+    pin_mut!(stream);
+    for _ in 0..SUBSCRIBER_QUEUE_CAPACITY {
+      fanout.send(log_event()).await;
+    }
+    let blocked_send = fanout.send(DeviceRemoved::new(1).into());
+    pin_mut!(blocked_send);
+    assert!((&mut blocked_send).now_or_never().is_none());
+    assert_eq!(stream.next().await, Some(log_event()));
+    assert!(blocked_send.await);
+  }
+
+  #[tokio::test]
+  async fn test_event_fanout_dropped_subscriber() {
+    let fanout = EventFanout::default();
+    assert!(!fanout.send(log_event()).await);
+    let stream = fanout.subscribe(EventDropPolicy::Block);
+    assert!(fanout.send(log_event()).await);
+    drop(stream);
+    assert!(!fanout.send(log_event()).await);
+  }
+}
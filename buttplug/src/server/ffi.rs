@@ -0,0 +1,395 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! C FFI surface for embedding a [ButtplugServer] in-process.
+//!
+//! Game engines and other non-Rust applications usually talk to Buttplug by spawning an external
+//! server executable and connecting over websockets. This module allows linking the library (built
+//! as a `cdylib` with the `ffi` feature) and running the server inside the host process instead,
+//! using the same JSON message format that would be sent over the wire.
+//!
+//! The server owns its own async runtime, so the host never has to care about Rust futures. All
+//! calls are non-blocking except shutdown. Messages are fed in via [buttplug_server_send_message],
+//! and replies plus server events (device added/removed, sensor readings, etc.) are queued until
+//! the host pulls them with [buttplug_server_poll_event], usually once per frame:
+//!
+//! ```c
+//! ButtplugFFIServer *server = buttplug_server_create("My Game", 0, false, NULL, NULL);
+//! buttplug_server_send_message(server, "[{\"RequestServerInfo\": {...}}]");
+//! // Each frame:
+//! char *msg;
+//! while ((msg = buttplug_server_poll_event(server)) != NULL) {
+//!   handle_message(msg);
+//!   buttplug_free_string(msg);
+//! }
+//! // On exit:
+//! buttplug_server_shutdown(server);
+//! ```
+
+use super::{ButtplugServer, ButtplugServerBuilder};
+use crate::{
+  core::{
+    errors::{ButtplugError, ButtplugMessageError},
+    message::{
+      serializer::{
+        ButtplugMessageSerializer,
+        ButtplugSerializedMessage,
+        ButtplugServerJSONSerializer,
+      },
+      ButtplugServerMessage,
+    },
+  },
+  util::add_default_comm_managers,
+};
+use futures::{pin_mut, StreamExt};
+use std::{
+  ffi::{CStr, CString},
+  os::raw::c_char,
+  ptr,
+  sync::{Arc, Mutex},
+};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+/// Return value of [buttplug_server_send_message] when the message was queued.
+pub const BUTTPLUG_FFI_OK: i32 = 0;
+/// Return value of [buttplug_server_send_message] when the server pointer or message was null.
+pub const BUTTPLUG_FFI_NULL_ARGUMENT: i32 = -1;
+/// Return value of [buttplug_server_send_message] when the message was not valid UTF-8.
+pub const BUTTPLUG_FFI_INVALID_STRING: i32 = -2;
+
+/// Opaque handle to an embedded server, created by [buttplug_server_create].
+pub struct ButtplugFFIServer {
+  server: Arc<ButtplugServer>,
+  serializer: Arc<ButtplugServerJSONSerializer>,
+  outgoing_sender: mpsc::UnboundedSender<String>,
+  outgoing_receiver: Mutex<mpsc::UnboundedReceiver<String>>,
+  runtime: Runtime,
+}
+
+impl ButtplugFFIServer {
+  fn new(builder: &mut ButtplugServerBuilder) -> Option<Self> {
+    let runtime = match Runtime::new() {
+      Ok(runtime) => runtime,
+      Err(err) => {
+        error!("Cannot create FFI server runtime: {:?}", err);
+        return None;
+      }
+    };
+    // The server spawns its internal tasks while building, so this needs to happen inside the
+    // runtime context.
+    let server = {
+      let _guard = runtime.enter();
+      match builder.finish() {
+        Ok(server) => Arc::new(server),
+        Err(err) => {
+          error!("Cannot create FFI server: {:?}", err);
+          return None;
+        }
+      }
+    };
+    let serializer = Arc::new(ButtplugServerJSONSerializer::default());
+    let (outgoing_sender, outgoing_receiver) = mpsc::unbounded_channel();
+    let event_stream = server.event_stream();
+    let event_serializer = serializer.clone();
+    let event_sender = outgoing_sender.clone();
+    runtime.spawn(async move {
+      pin_mut!(event_stream);
+      while let Some(event) = event_stream.next().await {
+        if send_serialized(&event_serializer, &event_sender, event).is_err() {
+          break;
+        }
+      }
+    });
+    Some(Self {
+      server,
+      serializer,
+      outgoing_sender,
+      outgoing_receiver: Mutex::new(outgoing_receiver),
+      runtime,
+    })
+  }
+
+  fn send_message(&self, json: String) {
+    let msgs = match self
+      .serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json))
+    {
+      Ok(msgs) => msgs,
+      Err(err) => {
+        // We have no way to know the message id here, so this goes out with the system id.
+        let error = ButtplugError::from(ButtplugMessageError::MessageSerializationError(err));
+        let _ = send_serialized(
+          &self.serializer,
+          &self.outgoing_sender,
+          ButtplugServerMessage::Error(error.into()),
+        );
+        return;
+      }
+    };
+    let server = self.server.clone();
+    let serializer = self.serializer.clone();
+    let sender = self.outgoing_sender.clone();
+    // Handle the whole batch in one task, so replies come back in the order messages were sent.
+    self.runtime.spawn(async move {
+      for msg in msgs {
+        let reply = match server.parse_message(msg).await {
+          Ok(reply) => reply,
+          Err(err) => err.into(),
+        };
+        if send_serialized(&serializer, &sender, reply).is_err() {
+          return;
+        }
+      }
+    });
+  }
+
+  fn poll_event(&self) -> Option<String> {
+    self
+      .outgoing_receiver
+      .lock()
+      .expect("Only poisoned if a poll panicked, which it can't.")
+      .try_recv()
+      .ok()
+  }
+
+  fn shutdown(self) {
+    let Self {
+      server, runtime, ..
+    } = self;
+    // Parts of the server spawn cleanup tasks when dropped, so the last reference needs to go away
+    // inside the runtime.
+    runtime.block_on(async move {
+      if let Err(err) = server.disconnect().await {
+        warn!("Error disconnecting FFI server: {:?}", err);
+      }
+      if let Err(err) = server.shutdown().await {
+        warn!("Error shutting down FFI server: {:?}", err);
+      }
+    });
+  }
+}
+
+fn send_serialized(
+  serializer: &ButtplugServerJSONSerializer,
+  sender: &mpsc::UnboundedSender<String>,
+  msg: ButtplugServerMessage,
+) -> Result<(), mpsc::error::SendError<String>> {
+  match serializer.serialize(&[msg]) {
+    ButtplugSerializedMessage::Text(json) => sender.send(json),
+    ButtplugSerializedMessage::Binary(_) => {
+      unreachable!("JSON serializer never produces binary messages.")
+    }
+  }
+}
+
+/// Converts a nullable C string, returning `Ok(None)` for null pointers.
+///
+/// # Safety
+///
+/// `string` must be null or point to a valid nul-terminated string.
+unsafe fn optional_string(string: *const c_char) -> Result<Option<String>, std::str::Utf8Error> {
+  if string.is_null() {
+    return Ok(None);
+  }
+  Ok(Some(CStr::from_ptr(string).to_str()?.to_owned()))
+}
+
+/// Create a new embedded server, with all device communication managers built into the library.
+///
+/// `name` may be null to use the default server name. `max_ping_time` is in milliseconds, 0 means
+/// no ping timeout. `device_config_json` and `user_device_config_json` may be null to use the
+/// built-in device configuration and no user configuration.
+///
+/// Returns null if the server could not be created. The returned handle must be released with
+/// [buttplug_server_shutdown].
+///
+/// # Safety
+///
+/// All string arguments must be null or point to valid nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn buttplug_server_create(
+  name: *const c_char,
+  max_ping_time: u32,
+  allow_raw_messages: bool,
+  device_config_json: *const c_char,
+  user_device_config_json: *const c_char,
+) -> *mut ButtplugFFIServer {
+  let (Ok(name), Ok(device_config_json), Ok(user_device_config_json)) = (
+    optional_string(name),
+    optional_string(device_config_json),
+    optional_string(user_device_config_json),
+  ) else {
+    error!("FFI server creation arguments were not valid UTF-8 strings.");
+    return ptr::null_mut();
+  };
+  let mut builder = ButtplugServerBuilder::default();
+  if let Some(name) = name {
+    builder.name(&name);
+  }
+  builder
+    .max_ping_time(max_ping_time)
+    .device_configuration_json(device_config_json)
+    .user_device_configuration_json(user_device_config_json);
+  if allow_raw_messages {
+    builder.allow_raw_messages();
+  }
+  add_default_comm_managers(&mut builder);
+  match ButtplugFFIServer::new(&mut builder) {
+    Some(server) => Box::into_raw(Box::new(server)),
+    None => ptr::null_mut(),
+  }
+}
+
+/// Queue a JSON message array for the server to handle. Replies will show up in
+/// [buttplug_server_poll_event] once the server has processed the messages.
+///
+/// Malformed messages are replied to with an `Error` message with an id of 0. Returns
+/// [BUTTPLUG_FFI_OK] if the message was queued, or a negative error code if the arguments were
+/// invalid.
+///
+/// # Safety
+///
+/// `server` must be a handle returned from [buttplug_server_create] that has not been shut down.
+/// `json` must be null or point to a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn buttplug_server_send_message(
+  server: *const ButtplugFFIServer,
+  json: *const c_char,
+) -> i32 {
+  let Some(server) = server.as_ref() else {
+    return BUTTPLUG_FFI_NULL_ARGUMENT;
+  };
+  match optional_string(json) {
+    Ok(Some(json)) => {
+      server.send_message(json);
+      BUTTPLUG_FFI_OK
+    }
+    Ok(None) => BUTTPLUG_FFI_NULL_ARGUMENT,
+    Err(_) => BUTTPLUG_FFI_INVALID_STRING,
+  }
+}
+
+/// Pop the next outgoing JSON message (reply or event) from the server, or null if there is
+/// nothing waiting. Returned strings must be released with [buttplug_free_string].
+///
+/// # Safety
+///
+/// `server` must be a handle returned from [buttplug_server_create] that has not been shut down.
+#[no_mangle]
+pub unsafe extern "C" fn buttplug_server_poll_event(
+  server: *const ButtplugFFIServer,
+) -> *mut c_char {
+  server
+    .as_ref()
+    .and_then(|server| server.poll_event())
+    // Serialized JSON escapes control characters, so there can't be interior nuls.
+    .and_then(|json| CString::new(json).ok())
+    .map(|json| json.into_raw())
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Release a string returned from [buttplug_server_poll_event].
+///
+/// # Safety
+///
+/// `string` must be null or a string returned from [buttplug_server_poll_event] that has not
+/// already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn buttplug_free_string(string: *mut c_char) {
+  if !string.is_null() {
+    drop(CString::from_raw(string));
+  }
+}
+
+/// Stop all devices, disconnect, and release the server. Blocks until shutdown is complete. Any
+/// messages still waiting to be polled are dropped.
+///
+/// # Safety
+///
+/// `server` must be null or a handle returned from [buttplug_server_create] that has not already
+/// been shut down. The handle is invalid after this call.
+#[no_mangle]
+pub unsafe extern "C" fn buttplug_server_shutdown(server: *mut ButtplugFFIServer) {
+  if !server.is_null() {
+    Box::from_raw(server).shutdown();
+  }
+}
+
+/// Report iOS app lifecycle changes, so Bluetooth scanning can switch to the service filtered scan
+/// iOS requires in the background. Should be called with `true` from
+/// `applicationDidEnterBackground` and `false` from `applicationWillEnterForeground`. Applies to all
+/// servers in the process.
+#[cfg(all(target_os = "ios", feature = "btleplug-manager"))]
+#[no_mangle]
+pub extern "C" fn buttplug_ios_set_app_background(background: bool) {
+  use crate::server::device::hardware::communication::btleplug::ios::{set_app_state, IosAppState};
+  set_app_state(if background {
+    IosAppState::Background
+  } else {
+    IosAppState::Foreground
+  });
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::time::{Duration, Instant};
+
+  fn poll_until(server: *const ButtplugFFIServer) -> String {
+    let start = Instant::now();
+    loop {
+      let msg = unsafe { buttplug_server_poll_event(server) };
+      if !msg.is_null() {
+        let json = unsafe { CStr::from_ptr(msg) }
+          .to_str()
+          .expect("Test, assuming infallible.")
+          .to_owned();
+        unsafe { buttplug_free_string(msg) };
+        return json;
+      }
+      assert!(
+        start.elapsed() < Duration::from_secs(5),
+        "Timed out waiting for server reply."
+      );
+      std::thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  #[test]
+  fn test_ffi_server_lifecycle() {
+    let server = unsafe { buttplug_server_create(ptr::null(), 0, false, ptr::null(), ptr::null()) };
+    assert!(!server.is_null());
+    assert!(unsafe { buttplug_server_poll_event(server) }.is_null());
+
+    let rsi = CString::new(
+      r#"[{"RequestServerInfo":{"Id":1,"ClientName":"Test Client","MessageVersion":3}}]"#,
+    )
+    .expect("Test, assuming infallible.");
+    assert_eq!(
+      unsafe { buttplug_server_send_message(server, rsi.as_ptr()) },
+      BUTTPLUG_FFI_OK
+    );
+    let reply = poll_until(server);
+    assert!(reply.contains("ServerInfo"));
+    assert!(reply.contains(r#""Id":1"#));
+
+    let bad = CString::new("not json").expect("Test, assuming infallible.");
+    assert_eq!(
+      unsafe { buttplug_server_send_message(server, bad.as_ptr()) },
+      BUTTPLUG_FFI_OK
+    );
+    let reply = poll_until(server);
+    assert!(reply.contains("Error"));
+    assert!(reply.contains(r#""Id":0"#));
+
+    assert_eq!(
+      unsafe { buttplug_server_send_message(server, ptr::null()) },
+      BUTTPLUG_FFI_NULL_ARGUMENT
+    );
+    unsafe { buttplug_server_shutdown(server) };
+  }
+}
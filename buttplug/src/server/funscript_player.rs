@@ -0,0 +1,262 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side funscript playback.
+//!
+//! Clients can hand a funscript to the server via [FunscriptLoadCmd], then control playback with
+//! [FunscriptPlaybackCmd]. Timing is handled completely on the server side, using a monotonic clock
+//! anchored at the last playback state change, so the client only needs to send messages when the
+//! user actually does something (play, pause, seek, rate change). This keeps movement smooth even
+//! when the client is something like a browser tab that may be throttled while in the background.
+
+use super::{device::ServerDeviceManager, ButtplugServerResultFuture};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugMessageError},
+    message::{
+      self,
+      ButtplugDeviceMessage,
+      ButtplugDeviceMessageType,
+      ButtplugServerMessage,
+      FunscriptLoadCmd,
+      FunscriptPlaybackCmd,
+      LinearCmd,
+      VectorSubcommand,
+    },
+  },
+  util::{
+    async_manager,
+    funscript::{Funscript, FunscriptAxis, FunscriptTimeline},
+    sleep,
+  },
+};
+use dashmap::DashMap;
+use futures::{future, pin_mut, FutureExt, StreamExt};
+use instant::Instant;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy)]
+struct PlaybackState {
+  playing: bool,
+  /// Script time at the anchor instant, in milliseconds.
+  position: f64,
+  rate: f64,
+  anchor: Instant,
+}
+
+impl PlaybackState {
+  /// Current script time, in milliseconds.
+  fn script_time(&self) -> f64 {
+    if self.playing {
+      self.position + self.anchor.elapsed().as_secs_f64() * 1000.0 * self.rate
+    } else {
+      self.position
+    }
+  }
+}
+
+impl Default for PlaybackState {
+  fn default() -> Self {
+    Self {
+      playing: false,
+      position: 0.0,
+      rate: 1.0,
+      anchor: Instant::now(),
+    }
+  }
+}
+
+type Sessions = Arc<DashMap<u32, watch::Sender<PlaybackState>>>;
+
+async fn playback_loop(
+  device_manager: Arc<ServerDeviceManager>,
+  sessions: Sessions,
+  device_index: u32,
+  feature_count: u32,
+  timeline: FunscriptTimeline,
+  mut state_receiver: watch::Receiver<PlaybackState>,
+) {
+  // Action to send next, once we've waited out the one before it. Cleared whenever the playback
+  // state changes, so the next action is looked up from where the new state starts playing.
+  let mut next_action: Option<usize> = None;
+  loop {
+    let state = *state_receiver.borrow_and_update();
+    if state.playing {
+      let now = state.script_time();
+      let action_index = match next_action.take() {
+        Some(index) => Some(index).filter(|index| *index < timeline.actions().len()),
+        None => timeline.next_action_index(state.position.ceil() as u32),
+      };
+      if let Some(action_index) = action_index {
+        let action = timeline.actions()[action_index];
+        let wait =
+          Duration::from_secs_f64(((action.at as f64 - now) / state.rate).max(0.0) / 1000.0);
+        let sent_at = Instant::now();
+        let vectors = (0..feature_count)
+          .map(|i| VectorSubcommand::new(i, wait.as_millis() as u32, action.pos))
+          .collect();
+        if let Err(err) = device_manager
+          .parse_message(LinearCmd::new(device_index, vectors).into())
+          .await
+        {
+          // Most likely the device disconnected. Pause, so the session doesn't keep counting script
+          // time for a device that isn't moving, and idle until we're told to do something else.
+          warn!(
+            "Funscript playback for device {} failed, pausing: {:?}",
+            device_index, err
+          );
+          if let Some(session) = sessions.get(&device_index) {
+            // Only pause our own session, not one that was loaded in the meantime.
+            if state_receiver.same_channel(&session.subscribe()) {
+              session.send_if_modified(pause_state);
+            }
+          }
+          // Pausing marks the state as changed, skip over that so we don't retry straight away.
+          state_receiver.borrow_and_update();
+        } else {
+          select! {
+            _ = sleep(wait.saturating_sub(sent_at.elapsed())).fuse() => {
+              next_action = Some(action_index + 1);
+              continue;
+            }
+            changed = state_receiver.changed().fuse() => {
+              if changed.is_err() {
+                return;
+              }
+              continue;
+            }
+          }
+        }
+      }
+    }
+    // Either paused, finished, or errored. Wait for a state change, or exit if the session has been
+    // replaced or dropped.
+    if state_receiver.changed().await.is_err() {
+      return;
+    }
+  }
+}
+
+/// Tracks loaded scripts and their playback tasks, one per device.
+pub(super) struct FunscriptPlayer {
+  device_manager: Arc<ServerDeviceManager>,
+  sessions: Sessions,
+}
+
+impl FunscriptPlayer {
+  pub fn new(device_manager: Arc<ServerDeviceManager>) -> Self {
+    let sessions = Sessions::default();
+    // Drop sessions for devices that go away. Dropping the sender ends the playback task.
+    let device_events = device_manager.event_stream();
+    let removed_sessions = sessions.clone();
+    async_manager::spawn(async move {
+      pin_mut!(device_events);
+      while let Some(msg) = device_events.next().await {
+        if let ButtplugServerMessage::DeviceRemoved(removed) = msg {
+          removed_sessions.remove(&removed.device_index());
+        }
+      }
+    });
+    Self {
+      device_manager,
+      sessions,
+    }
+  }
+
+  pub fn load(&self, msg: FunscriptLoadCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let feature_count = match self.device_manager.device_message_attributes(device_index) {
+      Some(attrs) => match attrs.linear_cmd() {
+        Some(linear_attrs) => linear_attrs.len() as u32,
+        None => {
+          return ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::LinearCmd)
+            .into()
+        }
+      },
+      None => return ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+    };
+    let script = match msg.script().parse::<Funscript>() {
+      Ok(script) => script,
+      Err(err) => {
+        return ButtplugMessageError::InvalidMessageContents(format!(
+          "FunscriptLoadCmd script could not be loaded: {}",
+          err
+        ))
+        .into()
+      }
+    };
+    let timeline = match script.timeline(&FunscriptAxis::Stroke) {
+      Some(timeline) => timeline.clone(),
+      None => {
+        return ButtplugMessageError::InvalidMessageContents(
+          "FunscriptLoadCmd script has no stroke axis actions".to_owned(),
+        )
+        .into()
+      }
+    };
+    let (state_sender, state_receiver) = watch::channel(PlaybackState::default());
+    // Replacing the sender drops any previous session, which will end its playback task.
+    self.sessions.insert(device_index, state_sender);
+    async_manager::spawn(playback_loop(
+      self.device_manager.clone(),
+      self.sessions.clone(),
+      device_index,
+      feature_count,
+      timeline,
+      state_receiver,
+    ));
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  pub fn playback(&self, msg: FunscriptPlaybackCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let Some(session) = self.sessions.get(&device_index) else {
+      return ButtplugDeviceError::FunscriptNotLoaded(device_index).into();
+    };
+    session.send_modify(|state| {
+      let position = msg
+        .position()
+        .map(|x| x as f64)
+        .unwrap_or_else(|| state.script_time());
+      *state = PlaybackState {
+        playing: msg.playing(),
+        position,
+        rate: msg.rate().unwrap_or(state.rate),
+        anchor: Instant::now(),
+      };
+    });
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  /// Pause playback for a device, if it has a script loaded.
+  pub fn pause(&self, device_index: u32) {
+    if let Some(session) = self.sessions.get(&device_index) {
+      session.send_if_modified(pause_state);
+    }
+  }
+
+  /// Pause playback on all devices.
+  pub fn pause_all(&self) {
+    for session in self.sessions.iter() {
+      session.send_if_modified(pause_state);
+    }
+  }
+}
+
+fn pause_state(state: &mut PlaybackState) -> bool {
+  if !state.playing {
+    return false;
+  }
+  *state = PlaybackState {
+    playing: false,
+    position: state.script_time(),
+    rate: state.rate,
+    anchor: Instant::now(),
+  };
+  true
+}
@@ -35,8 +35,8 @@
 //!     [DeviceManager], which manages discovery of and communication with devices. The only thing
 //!     the server instance manages at this point is ownership of the [DeviceManager] and
 //!     ping timer, but doesn't really do much itself. The server remains in this state until the
-//!     connection to the client is severed, at which point all devices connected to the device
-//!     manager will be stopped.
+//!     connection to the client is severed, at which point all devices the client was commanding
+//!     will be stopped.
 //! - Disconnection
 //!   - The server can be put back in Connection mode without being recreated after disconnection,
 //!     to listen for another client connection while still maintaining connection to whatever
@@ -44,9 +44,91 @@
 //! - Destruction
 //!   - If the server object is dropped, all devices are stopped and disconnected as part
 //!     of the [DeviceManager] teardown.
+//!
+//! ## Multiple Clients
+//!
+//! A [ButtplugServer] handles one client connection at a time. For setups where more than one
+//! client needs access to the same devices (a game and a remote partner app, for instance),
+//! [ButtplugServer::new_session] creates additional sessions sharing the same device manager. Clients
+//! can take turns controlling a device by locking it with
+//! [DeviceLockCmd](crate::core::message::DeviceLockCmd), which makes the server refuse output
+//! commands for that device from every other session until it's unlocked or the owner disconnects.
+//! Patterns, funscripts, loops and scheduled commands other sessions were running on the device
+//! are stopped when it's locked.
+//! When a session disconnects or pings out, only the devices it was the last to send output
+//! commands to are stopped, so other clients can keep using their devices.
+//!
+//! ## Session Resumption
+//!
+//! Remote connections can drop for a moment, like when a phone switches wifi access points. With
+//! [ButtplugServerBuilder::session_resumption_window] set, the server hands out a resumption token
+//! during the handshake, and holds on to the session for the given window after the connection
+//! drops instead of stopping devices right away. A client that reconnects with the token in time
+//! keeps its device locks, sensor subscriptions and running playback. If the window passes, or a
+//! client connects without the token, the session is cleaned up as usual. Clients disconnecting on
+//! purpose send [EndSessionCmd](message::EndSessionCmd) first, so their session is cleaned up right
+//! away.
+//!
+//! ## Capabilities
+//!
+//! Besides the message spec version, clients can ask for optional protocol features during the
+//! handshake, like the pattern engine or sensors, as a list of
+//! [ButtplugCapability](crate::core::message::ButtplugCapability) names. The server agrees to the
+//! ones it offers, set via [ButtplugServerBuilder::capabilities], and returns them in
+//! [ServerInfo](crate::core::message::ServerInfo). Features can then ship behind a capability
+//! without needing a new spec version. See [ButtplugServer::capabilities].
+//!
+//! ## Cancellation
+//!
+//! With the [Cancellation](crate::core::message::ButtplugCapability::Cancellation) capability, a
+//! client can take back a device command it no longer needs via
+//! [CancelCmd](crate::core::message::CancelCmd), i.e. when a newer command makes it pointless. If
+//! the command is still waiting in the device's command queue, it's dropped without reaching the
+//! hardware, rather than being sent late once a stalled connection catches up. Commands already
+//! being written finish, but are answered as cancelled either way. Stop commands can't be
+//! cancelled.
+//!
+//! ## Audit Log
+//!
+//! With the `audit-log` feature, [ButtplugServerBuilder::audit_log_path] makes the server record
+//! every device command, along with the session and client that sent it, to an append-only file.
+//! See the [audit_log] module for the format.
+//!
+//! ## Server Management
+//!
+//! With [ButtplugServerBuilder::allow_server_management] set, admin scoped clients can manage the
+//! whole server, for front ends controlling an embedded or remote server: stopping devices and
+//! playback for every client ([ForceStopAllDevicesCmd](message::ForceStopAllDevicesCmd)),
+//! disconnecting another session's client ([KickClientCmd](message::KickClientCmd)), refusing
+//! scans ([ScanningEnabledCmd](message::ScanningEnabledCmd)), and asking for shutdown
+//! ([ServerShutdownCmd](message::ServerShutdownCmd)). Shutting down is up to the hosting process,
+//! which can wait on [ButtplugServer::shutdown_requested]. Without it, these messages are refused
+//! with a [MessageNotAllowed](ButtplugMessageError::MessageNotAllowed) error, as are
+//! [IntensityCeilingCmd](message::IntensityCeilingCmd) messages raising the ceiling. Admin clients
+//! can always lower it.
+//!
+//! ## OSC Output
+//!
+//! With the `osc-output` feature, [osc_output::OscOutput] sends device state and sensor readings
+//! to an OSC listener, for creative tools that speak OSC but not Buttplug.
 
+#[cfg(feature = "audit-log")]
+pub mod audit_log;
+mod command_scheduler;
+mod connection_scope;
 pub mod device;
+#[cfg(feature = "device-sharing")]
+pub mod device_sharing;
+mod event_fanout;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod funscript_player;
+#[cfg(feature = "osc-output")]
+pub mod osc_output;
+mod pattern_player;
 mod ping_timer;
+mod session_resumption;
+mod stroke_generator;
 
 use self::device::{
   configuration::{
@@ -54,47 +136,67 @@ use self::device::{
     ProtocolCommunicationSpecifier,
     ProtocolDeviceAttributes,
   },
+  dfu::DfuFirmwareImage,
   hardware::communication::HardwareCommunicationManagerBuilder,
   protocol::ProtocolIdentifierFactory,
+  DeviceCommandQueueSettings,
   ServerDeviceIdentifier,
   ServerDeviceManager,
   ServerDeviceManagerBuilder,
+  SessionControl,
 };
 use crate::{
   core::{
     errors::*,
     message::{
       self,
+      ButtplugCapability,
       ButtplugClientMessage,
       ButtplugDeviceCommandMessageUnion,
       ButtplugDeviceManagerMessageUnion,
+      ButtplugDeviceMessage,
       ButtplugMessage,
+      ButtplugMessageSpecVersion,
       ButtplugServerMessage,
-      StopAllDevices,
+      ClientDeviceMessageAttributes,
+      LostDeviceFeature,
       StopScanning,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+      BUTTPLUG_DEFAULT_CAPABILITIES,
     },
   },
   util::{
     async_manager,
     device_configuration::{load_protocol_configs, DEVICE_CONFIGURATION_JSON},
-    stream::convert_broadcast_receiver_to_stream,
+    environment::{environment_override, environment_variable},
+    unix_time_millis,
   },
 };
+use command_scheduler::{scheduled_timestamp, CommandScheduler};
+pub use connection_scope::ButtplugConnectionScope;
+use dashmap::DashMap;
+pub use event_fanout::EventDropPolicy;
+use event_fanout::EventFanout;
+use funscript_player::FunscriptPlayer;
 use futures::{
-  future::{self, BoxFuture, FutureExt},
+  future::{self, AbortHandle, Abortable, BoxFuture, FutureExt},
+  Future,
   Stream,
 };
+use pattern_player::PatternPlayer;
 use ping_timer::PingTimer;
+use session_resumption::SessionResumption;
 use std::{
   fmt,
+  path::{Path, PathBuf},
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
+  time::Duration,
 };
+use stroke_generator::StrokeGenerator;
 use thiserror::Error;
-use tokio::sync::broadcast;
 use tokio_stream::StreamExt;
 use tracing_futures::Instrument;
 
@@ -122,6 +224,31 @@ pub enum ButtplugServerError {
   /// Requested protocol has not been registered with the system.
   #[error("Buttplug Protocol of type {0} does not exist in the system and cannot be removed.")]
   ProtocolDoesNotExist(String),
+  /// Requested DeviceCommunicationManager has not been added to the system.
+  #[error(
+    "DeviceCommunicationManager of type {0} does not exist in the system and cannot be removed."
+  )]
+  DeviceCommunicationManagerDoesNotExist(String),
+  /// Device manager has been shut down.
+  #[error("Device manager is not running.")]
+  DeviceManagerNotRunning,
+  /// Environment variable override could not be applied.
+  #[error("Environment variable {0} could not be applied: {1}")]
+  InvalidEnvironmentOverride(String, String),
+  /// Audit log could not be opened.
+  #[cfg(feature = "audit-log")]
+  #[error("Audit log could not be opened: {0}")]
+  AuditLogError(String),
+}
+
+/// Returns the value of an environment variable, treating empty values as unset.
+fn read_environment_config(variable: &str, path: &str) -> Result<String, ButtplugServerError> {
+  std::fs::read_to_string(path).map_err(|err| {
+    ButtplugServerError::InvalidEnvironmentOverride(
+      variable.to_owned(),
+      format!("cannot read {}: {}", path, err),
+    )
+  })
 }
 
 /// Configures and creates [ButtplugServer] instances.
@@ -132,12 +259,29 @@ pub struct ButtplugServerBuilder {
   /// Maximum time system will live without receiving a Ping message before disconnecting. If None,
   /// ping timer does not run.
   max_ping_time: Option<u32>,
+  /// Largest ping time clients can request during the handshake. If None, the max ping time is
+  /// used as the limit.
+  client_ping_time_limit: Option<u32>,
   /// JSON string, with the contents of the base Device Configuration file
   device_configuration_json: Option<String>,
   /// JSON string, with the contents of the User Device Configuration file
   user_device_configuration_json: Option<String>,
+  /// Path of the User Device Configuration file, loaded during build if no JSON string was given,
+  /// and saved to when settings change at runtime.
+  user_device_configuration_path: Option<PathBuf>,
   /// Device manager builder for the server
   device_manager_builder: ServerDeviceManagerBuilder,
+  /// What happens to events when a [ButtplugServer::event_stream] isn't read fast enough.
+  event_drop_policy: EventDropPolicy,
+  /// What connections to the server are allowed to do.
+  connection_scope: ButtplugConnectionScope,
+  /// How long sessions are held for their client to reconnect, zero if resumption is disabled.
+  session_resumption_window: Duration,
+  /// Capabilities offered to clients during the handshake.
+  capabilities: Vec<ButtplugCapability>,
+  /// File to record device commands to.
+  #[cfg(feature = "audit-log")]
+  audit_log_path: Option<PathBuf>,
 }
 
 impl Default for ButtplugServerBuilder {
@@ -145,9 +289,17 @@ impl Default for ButtplugServerBuilder {
     Self {
       name: "Buttplug Server".to_owned(),
       max_ping_time: None,
+      client_ping_time_limit: None,
       device_configuration_json: Some(DEVICE_CONFIGURATION_JSON.to_owned()),
       user_device_configuration_json: None,
+      user_device_configuration_path: None,
       device_manager_builder: ServerDeviceManagerBuilder::default(),
+      event_drop_policy: EventDropPolicy::default(),
+      connection_scope: ButtplugConnectionScope::default(),
+      session_resumption_window: Duration::ZERO,
+      capabilities: BUTTPLUG_DEFAULT_CAPABILITIES.to_vec(),
+      #[cfg(feature = "audit-log")]
+      audit_log_path: None,
     }
   }
 }
@@ -172,18 +324,43 @@ impl ButtplugServerBuilder {
     self
   }
 
-  /// Set the device configuration json file contents, to be loaded during build.
+  /// Set the largest ping time, in milliseconds, that clients can request for their connection
+  /// during the handshake. Clients that don't request a ping time get the one set via
+  /// [ButtplugServerBuilder::max_ping_time]. A limit of 0 lets clients request any ping time,
+  /// including none at all. If this is not called, the max ping time is used as the limit.
+  ///
+  /// This is useful when clients have very different connection conditions, like a local game
+  /// that should be stopped quickly if it hangs, and a remote app connecting over the internet
+  /// that needs a much longer window.
+  pub fn client_ping_time_limit(&mut self, limit: u32) -> &mut Self {
+    self.client_ping_time_limit = Some(limit);
+    self
+  }
+
+  /// Set the device configuration json file contents, to be loaded during build. With the
+  /// `toml-config` feature, the file can also be TOML, using the same structure as the JSON
+  /// version.
   pub fn device_configuration_json(&mut self, config_json: Option<String>) -> &mut Self {
     self.device_configuration_json = config_json;
     self
   }
 
-  /// Set the user device configuration json file contents, to be loaded during build.
+  /// Set the user device configuration json file contents, to be loaded during build. With the
+  /// `toml-config` feature, the file can also be TOML, using the same structure as the JSON
+  /// version.
   pub fn user_device_configuration_json(&mut self, config_json: Option<String>) -> &mut Self {
     self.user_device_configuration_json = config_json;
     self
   }
 
+  /// Set the path of the user device configuration file. If no user device configuration json was
+  /// given, the file is loaded during build (if it exists). Settings changed at runtime, like
+  /// device display names set by clients, are saved to the file.
+  pub fn user_device_configuration_path(&mut self, path: &Path) -> &mut Self {
+    self.user_device_configuration_path = Some(path.to_owned());
+    self
+  }
+
   pub fn comm_manager<T>(&mut self, builder: T) -> &mut Self
   where
     T: HardwareCommunicationManagerBuilder + 'static,
@@ -192,6 +369,156 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Adds simulated devices, for running apps (and their CI) without hardware. Real comm managers
+  /// are only used if they're added too.
+  #[cfg(feature = "simulation-manager")]
+  pub fn simulation(
+    &mut self,
+    settings: device::hardware::communication::simulated::SimulationSettings,
+  ) -> &mut Self {
+    self.comm_manager(
+      device::hardware::communication::simulated::simulated_comm_manager::SimulatedCommunicationManagerBuilder::new(settings),
+    )
+  }
+
+  /// Only run comm managers with the given name, i.e. "BtlePlugCommunicationManager". Can be called
+  /// multiple times to allow multiple managers. If never called, all added comm managers run.
+  pub fn allowed_comm_manager(&mut self, name: &str) -> &mut Self {
+    self.device_manager_builder.allowed_comm_manager(name);
+    self
+  }
+
+  /// Prefer devices found by the named comm manager when the same physical device is found by more
+  /// than one. See [ServerDeviceManagerBuilder::preferred_comm_manager].
+  pub fn preferred_comm_manager(&mut self, name: &str) -> &mut Self {
+    self.device_manager_builder.preferred_comm_manager(name);
+    self
+  }
+
+  /// Stop scanning automatically after this long, for StartScanning requests that don't specify
+  /// their own timeout. See [ServerDeviceManagerBuilder::scanning_timeout].
+  pub fn scanning_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.device_manager_builder.scanning_timeout(timeout);
+    self
+  }
+
+  /// Keep scanning for devices in the background while the server runs. See
+  /// [ServerDeviceManagerBuilder::background_scanning].
+  pub fn background_scanning(&mut self) -> &mut Self {
+    self.device_manager_builder.background_scanning();
+    self
+  }
+
+  /// Accept [DeviceFirmwareUpdateCmd](crate::core::message::DeviceFirmwareUpdateCmd) messages from
+  /// admin connections. See [ServerDeviceManagerBuilder::allow_firmware_updates].
+  pub fn allow_firmware_updates(&mut self) -> &mut Self {
+    self.device_manager_builder.allow_firmware_updates();
+    self
+  }
+
+  /// Accept server management messages from admin connections. See
+  /// [ServerDeviceManagerBuilder::allow_server_management].
+  pub fn allow_server_management(&mut self) -> &mut Self {
+    self.device_manager_builder.allow_server_management();
+    self
+  }
+
+  /// Send [LowBatteryWarning](crate::core::message::LowBatteryWarning) events when a device's
+  /// battery drops below `threshold` (0.0-1.0). See
+  /// [ServerDeviceManagerBuilder::low_battery_threshold].
+  pub fn low_battery_threshold(&mut self, threshold: f64) -> &mut Self {
+    self.device_manager_builder.low_battery_threshold(threshold);
+    self
+  }
+
+  /// Give up on connecting a device if its protocol initialization takes longer than this. See
+  /// [ServerDeviceManagerBuilder::protocol_initialization_timeout].
+  pub fn protocol_initialization_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self
+      .device_manager_builder
+      .protocol_initialization_timeout(timeout);
+    self
+  }
+
+  /// Retry connecting and initializing BLE devices that fail. See
+  /// [ServerDeviceManagerBuilder::protocol_initialization_retries].
+  pub fn protocol_initialization_retries(&mut self, retries: u32, delay: Duration) -> &mut Self {
+    self
+      .device_manager_builder
+      .protocol_initialization_retries(retries, delay);
+    self
+  }
+
+  /// Set how many devices can connect and initialize at the same time. See
+  /// [ServerDeviceManagerBuilder::device_initialization_concurrency].
+  pub fn device_initialization_concurrency(&mut self, limit: usize) -> &mut Self {
+    self
+      .device_manager_builder
+      .device_initialization_concurrency(limit);
+    self
+  }
+
+  /// Set how many commands can wait on each device while it's busy, and what happens to commands
+  /// sent past that limit. Stop commands are never subject to the limit.
+  pub fn device_command_queue_settings(
+    &mut self,
+    settings: DeviceCommandQueueSettings,
+  ) -> &mut Self {
+    self
+      .device_manager_builder
+      .device_command_queue_settings(settings);
+    self
+  }
+
+  /// Set what happens to events when a stream from [ButtplugServer::event_stream] isn't read fast
+  /// enough. Device added/removed, scanning finished and error events are never dropped, whatever
+  /// the policy. Defaults to [EventDropPolicy::Collapse].
+  pub fn event_drop_policy(&mut self, policy: EventDropPolicy) -> &mut Self {
+    self.event_drop_policy = policy;
+    self
+  }
+
+  /// Override builder settings from environment variables, for deployments (containers, headless
+  /// machines) where changing the code or command line of the host application isn't an option.
+  /// Variables that aren't set leave the current settings alone.
+  ///
+  /// - `BUTTPLUG_MAX_PING_TIME`: Max ping time, in milliseconds. See
+  ///   [ButtplugServerBuilder::max_ping_time].
+  /// - `BUTTPLUG_DEVICE_CONFIG_PATH`: Path to a base device configuration file.
+  /// - `BUTTPLUG_USER_DEVICE_CONFIG_PATH`: Path to a user device configuration file. See
+  ///   [ButtplugServerBuilder::user_device_configuration_path].
+  /// - `BUTTPLUG_COMM_MANAGERS`: Comma separated list of comm manager names to run. See
+  ///   [ButtplugServerBuilder::allowed_comm_manager].
+  ///
+  /// Returns an error if a variable is set to something that can't be used, i.e. a config path
+  /// that can't be read.
+  pub fn environment_overrides(&mut self) -> Result<&mut Self, ButtplugServerError> {
+    if let Some(ping_time) = environment_override("BUTTPLUG_MAX_PING_TIME").map_err(|err| {
+      ButtplugServerError::InvalidEnvironmentOverride("BUTTPLUG_MAX_PING_TIME".to_owned(), err)
+    })? {
+      self.max_ping_time(ping_time);
+    }
+    if let Some(path) = environment_variable("BUTTPLUG_DEVICE_CONFIG_PATH") {
+      let config = read_environment_config("BUTTPLUG_DEVICE_CONFIG_PATH", &path)?;
+      self.device_configuration_json(Some(config));
+    }
+    if let Some(path) = environment_variable("BUTTPLUG_USER_DEVICE_CONFIG_PATH") {
+      let config = read_environment_config("BUTTPLUG_USER_DEVICE_CONFIG_PATH", &path)?;
+      self.user_device_configuration_json(Some(config));
+      self.user_device_configuration_path(Path::new(&path));
+    }
+    if let Some(comm_managers) = environment_variable("BUTTPLUG_COMM_MANAGERS") {
+      for name in comm_managers
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+      {
+        self.allowed_comm_manager(name);
+      }
+    }
+    Ok(self)
+  }
+
   pub fn allowed_address(&mut self, address: &str) -> &mut Self {
     self.device_manager_builder.allowed_address(address);
     self
@@ -202,6 +529,14 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Keep a protocol from being used to connect to devices, i.e. "xinput" to keep game controllers
+  /// from showing up. Protocols can also be disabled in the user device configuration, or at
+  /// runtime via [ServerDeviceManager::set_protocol_enabled].
+  pub fn disabled_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self.device_manager_builder.disabled_protocol(protocol_name);
+    self
+  }
+
   pub fn reserved_index(&mut self, identifier: &ServerDeviceIdentifier, index: u32) -> &mut Self {
     self
       .device_manager_builder
@@ -249,6 +584,40 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Scope for the server and sessions created with [ButtplugServer::new_session], defaults to
+  /// [ButtplugConnectionScope::Raw]. Messages outside of the scope are refused with an
+  /// [ButtplugMessageError::InsufficientScope] error.
+  pub fn connection_scope(&mut self, scope: ButtplugConnectionScope) -> &mut Self {
+    self.connection_scope = scope;
+    self
+  }
+
+  /// Hold sessions for this long after their connection drops, so clients can reconnect with the
+  /// resumption token they got during the handshake without their devices being stopped. Devices
+  /// keep running whatever they were last told to do during the window, so it should be kept
+  /// short, a few seconds is usually enough to get over a wifi hiccup. Pinging out still stops
+  /// devices right away.
+  pub fn session_resumption_window(&mut self, window: Duration) -> &mut Self {
+    self.session_resumption_window = window;
+    self
+  }
+
+  /// Set the capabilities offered to clients during the handshake, replacing the
+  /// [defaults](crate::core::message::BUTTPLUG_DEFAULT_CAPABILITIES). Each connection uses the ones
+  /// that its client also asks for, see [ButtplugServer::capabilities].
+  pub fn capabilities(&mut self, capabilities: &[ButtplugCapability]) -> &mut Self {
+    self.capabilities = capabilities.to_vec();
+    self
+  }
+
+  /// Append a record of every device command to the file at `path`, creating it if needed. Entries
+  /// are never removed, so rotating or trimming the file is left to the user.
+  #[cfg(feature = "audit-log")]
+  pub fn audit_log_path(&mut self, path: &Path) -> &mut Self {
+    self.audit_log_path = Some(path.to_owned());
+    self
+  }
+
   /// Try to build a [ButtplugServer] using the parameters given.
   pub fn finish(&mut self) -> Result<ButtplugServer, ButtplugServerError> {
     // Create the server
@@ -257,65 +626,63 @@ impl ButtplugServerBuilder {
 
     // First, try loading our configs. If this doesn't work, nothing else will, so get it out of
     // the way first.
+    let mut user_device_configuration_json = self.user_device_configuration_json.clone();
+    if let Some(path) = &self.user_device_configuration_path {
+      self
+        .device_manager_builder
+        .user_device_configuration_path(path.clone());
+      if user_device_configuration_json.is_none() && path.exists() {
+        user_device_configuration_json = Some(std::fs::read_to_string(path).map_err(|err| {
+          ButtplugServerError::DeviceConfigurationManagerError(
+            ButtplugDeviceError::DeviceConfigurationError(format!(
+              "Cannot read user device configuration {}: {}",
+              path.display(),
+              err
+            )),
+          )
+        })?);
+      }
+    }
     let dcm_builder = load_protocol_configs(
       self.device_configuration_json.clone(),
-      self.user_device_configuration_json.clone(),
+      user_device_configuration_json.clone(),
       false,
     )
     .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
 
     self
       .device_manager_builder
-      .device_configuration_manager_builder(&dcm_builder);
-    // Set up our channels to different parts of the system.
-    let (output_sender, _) = broadcast::channel(256);
-    let output_sender_clone = output_sender.clone();
-
+      .device_configuration_manager_builder(&dcm_builder)
+      .user_device_configuration_json(user_device_configuration_json);
+    #[cfg(feature = "audit-log")]
+    let audit_log = self
+      .audit_log_path
+      .as_ref()
+      .map(|path| {
+        audit_log::AuditLog::open(path)
+          .map(Arc::new)
+          .map_err(|err| ButtplugServerError::AuditLogError(format!("{}: {}", path.display(), err)))
+      })
+      .transpose()?;
     let device_manager = Arc::new(self.device_manager_builder.finish()?);
 
-    let connected = Arc::new(AtomicBool::new(false));
-    let connected_clone = connected.clone();
-
-    // TODO this should use a cancellation token instead of passing around the timer itself.
-    let ping_time = self.max_ping_time.unwrap_or(0);
-    let ping_timer = Arc::new(PingTimer::new(ping_time));
-    let ping_timeout_notifier = ping_timer.ping_timeout_waiter();
-
-    // Spawn the ping timer task, assuming the ping time is > 0.
-    if ping_time > 0 {
-      let device_manager_clone = device_manager.clone();
-      async_manager::spawn(
-        async move {
-          // This will only exit if we've pinged out.
-          ping_timeout_notifier.await;
-          error!("Ping out signal received, stopping server");
-          connected_clone.store(false, Ordering::SeqCst);
-          async_manager::spawn(async move {
-            if let Err(e) = device_manager_clone.stop_all_devices().await {
-              error!("Could not stop devices on ping timeout: {:?}", e);
-            }
-          });
-          // TODO Should the event sender return a result instead of an error message?
-          if output_sender_clone
-            .send(message::Error::from(ButtplugError::from(ButtplugPingError::PingedOut)).into())
-            .is_err()
-          {
-            error!("Server disappeared, cannot update about ping out.");
-          };
-        }
-        .instrument(tracing::info_span!("Buttplug Server Ping Timeout Task")),
-      );
-    }
-
     // Assuming everything passed, return the server.
-    Ok(ButtplugServer {
-      server_name: self.name.clone(),
-      max_ping_time: ping_time,
+    let max_ping_time = self.max_ping_time.unwrap_or(0);
+    let mut server = ButtplugServer::with_device_manager(
+      &self.name,
+      max_ping_time,
+      self.client_ping_time_limit.unwrap_or(max_ping_time),
       device_manager,
-      ping_timer,
-      connected,
-      output_sender,
-    })
+      self.event_drop_policy,
+      self.connection_scope,
+      self.session_resumption_window,
+    );
+    server.offered_capabilities = self.capabilities.clone();
+    #[cfg(feature = "audit-log")]
+    {
+      server.audit_log = audit_log;
+    }
+    Ok(server)
   }
 }
 
@@ -325,23 +692,61 @@ pub struct ButtplugServer {
   /// The name of the server, which is relayed to the client on connection (mostly for
   /// confirmation in UI dialogs)
   server_name: String,
-  /// The maximum ping time, in milliseconds, for the server. If the server does not receive a
-  /// [Ping](crate::core::messages::Ping) message in this amount of time after the handshake has
-  /// succeeded, the server will automatically disconnect. If this is not called, the ping timer
-  /// will not be activated.
+  /// The default maximum ping time, in milliseconds, for the server. If the server does not
+  /// receive a [Ping](crate::core::messages::Ping) message in this amount of time after the
+  /// handshake has succeeded, the server will automatically disconnect. Clients can request their
+  /// own ping time during the handshake, up to client_ping_time_limit.
   ///
   /// Note that this has nothing to do with communication medium specific pings, like those built
   /// into the Websocket protocol. This ping is specific to the Buttplug protocol.
   max_ping_time: u32,
-  /// Timer for managing ping time tracking, if max_ping_time > 0.
+  /// Largest ping time clients can request during the handshake, 0 if unlimited.
+  client_ping_time_limit: u32,
+  /// Timer for managing ping time tracking, running with the ping time negotiated during the
+  /// handshake.
   ping_timer: Arc<PingTimer>,
   /// Manages device discovery and communication.
   device_manager: Arc<ServerDeviceManager>,
+  /// Holds device commands with timestamps until they should run.
+  command_scheduler: Arc<CommandScheduler>,
+  /// Handles server side funscript playback.
+  funscript_player: Arc<FunscriptPlayer>,
+  /// Handles server side pattern playback.
+  pattern_player: Arc<PatternPlayer>,
+  /// Handles server side stroke generation.
+  stroke_generator: Arc<StrokeGenerator>,
+  /// Id of this session, for device lock ownership. Unique among sessions sharing the device
+  /// manager.
+  session_id: u32,
   /// If true, client is currently connected to server
   connected: Arc<AtomicBool>,
   /// Broadcaster for server events. Receivers for this are handed out through the
   /// [ButtplugServer::event_stream()] method.
-  output_sender: broadcast::Sender<ButtplugServerMessage>,
+  output_sender: EventFanout,
+  /// Drop policy for streams from [ButtplugServer::event_stream()].
+  event_drop_policy: EventDropPolicy,
+  /// Scope given to new sessions.
+  default_connection_scope: ButtplugConnectionScope,
+  /// What this session is allowed to do.
+  connection_scope: ButtplugConnectionScope,
+  /// Grace window given to new sessions.
+  session_resumption_window: Duration,
+  /// Resumption token and cleanup held while waiting for the client to reconnect.
+  resumption: Arc<SessionResumption>,
+  /// Name the client sent during the handshake.
+  client_name: Arc<std::sync::Mutex<Option<String>>>,
+  /// Message spec version the client asked for during the handshake.
+  client_message_version: Arc<std::sync::Mutex<Option<ButtplugMessageSpecVersion>>>,
+  /// Capabilities offered to clients during the handshake.
+  offered_capabilities: Vec<ButtplugCapability>,
+  /// Capabilities agreed on with the client during the handshake.
+  capabilities: Arc<std::sync::Mutex<Vec<ButtplugCapability>>>,
+  /// Abort handles for device commands that haven't been answered yet, by message id, so they
+  /// can be cancelled via [CancelCmd](message::CancelCmd).
+  in_flight_commands: Arc<DashMap<u32, AbortHandle>>,
+  /// Where device commands are recorded, shared with other sessions.
+  #[cfg(feature = "audit-log")]
+  audit_log: Option<Arc<audit_log::AuditLog>>,
 }
 
 impl std::fmt::Debug for ButtplugServer {
@@ -349,7 +754,9 @@ impl std::fmt::Debug for ButtplugServer {
     f.debug_struct("ButtplugServer")
       .field("server_name", &self.server_name)
       .field("max_ping_time", &self.max_ping_time)
+      .field("client_ping_time_limit", &self.client_ping_time_limit)
       .field("connected", &self.connected)
+      .field("connection_scope", &self.connection_scope)
       .finish()
   }
 }
@@ -365,14 +772,263 @@ impl Default for ButtplugServer {
 }
 
 impl ButtplugServer {
+  fn with_device_manager(
+    server_name: &str,
+    ping_time: u32,
+    client_ping_time_limit: u32,
+    device_manager: Arc<ServerDeviceManager>,
+    event_drop_policy: EventDropPolicy,
+    connection_scope: ButtplugConnectionScope,
+    session_resumption_window: Duration,
+  ) -> Self {
+    // Set up our channels to different parts of the system.
+    let output_sender = EventFanout::default();
+    let output_sender_clone = output_sender.clone();
+
+    let (session_id, mut session_control) = device_manager.register_session();
+
+    let connected = Arc::new(AtomicBool::new(false));
+    let connected_clone = connected.clone();
+
+    // TODO this should use a cancellation token instead of passing around the timer itself.
+    let ping_timer = Arc::new(PingTimer::new());
+    let ping_timeout_notifier = ping_timer.ping_timeout_waiter();
+
+    let command_scheduler = Arc::new(CommandScheduler::new(device_manager.clone()));
+    let funscript_player = Arc::new(FunscriptPlayer::new(device_manager.clone()));
+    let pattern_player = Arc::new(PatternPlayer::new(device_manager.clone()));
+    let stroke_generator = Arc::new(StrokeGenerator::new(device_manager.clone()));
+    let resumption = Arc::new(SessionResumption::new(session_resumption_window));
+
+    // Spawn the ping timer task. Whether the timer runs depends on the ping time negotiated during
+    // the handshake, so this is always needed.
+    {
+      let device_manager_clone = device_manager.clone();
+      let command_scheduler_clone = command_scheduler.clone();
+      let funscript_player_clone = funscript_player.clone();
+      let pattern_player_clone = pattern_player.clone();
+      let stroke_generator_clone = stroke_generator.clone();
+      let resumption_clone = resumption.clone();
+      async_manager::spawn(
+        async move {
+          // This will exit if we've pinged out, or if the ping timer has been dropped.
+          if !ping_timeout_notifier.await {
+            return;
+          }
+          error!("Ping out signal received, stopping server");
+          connected_clone.store(false, Ordering::SeqCst);
+          // Pinging out means the client stopped responding, so don't wait around for it.
+          resumption_clone.revoke_token();
+          command_scheduler_clone.cancel_all();
+          funscript_player_clone.pause_all();
+          pattern_player_clone.stop_all();
+          stroke_generator_clone.stop_all();
+          device_manager_clone.release_device_locks(session_id);
+          async_manager::spawn(async move {
+            if let Err(e) = device_manager_clone.stop_session_devices(session_id).await {
+              error!("Could not stop devices on ping timeout: {:?}", e);
+            }
+          });
+          // TODO Should the event sender return a result instead of an error message?
+          if !output_sender_clone
+            .send(message::Error::from(ButtplugError::from(ButtplugPingError::PingedOut)).into())
+            .await
+          {
+            error!("Server disappeared, cannot update about ping out.");
+          };
+        }
+        .instrument(tracing::info_span!("Buttplug Server Ping Timeout Task")),
+      );
+    }
+
+    // Spawn the task handling requests from admin messages in other sessions. Exits once the
+    // session is unregistered on drop.
+    {
+      let device_manager = device_manager.clone();
+      let command_scheduler = command_scheduler.clone();
+      let funscript_player = funscript_player.clone();
+      let pattern_player = pattern_player.clone();
+      let stroke_generator = stroke_generator.clone();
+      let resumption = resumption.clone();
+      let ping_timer = ping_timer.clone();
+      let connected = connected.clone();
+      let output_sender = output_sender.clone();
+      async_manager::spawn(async move {
+        while let Some(control) = session_control.recv().await {
+          if let SessionControl::StopDevicePlayback(device_index) = control {
+            command_scheduler.cancel(device_index);
+            funscript_player.pause(device_index);
+            pattern_player.stop_device(device_index);
+            stroke_generator.stop(device_index);
+            continue;
+          }
+          command_scheduler.cancel_all();
+          funscript_player.pause_all();
+          pattern_player.stop_all();
+          stroke_generator.stop_all();
+          let SessionControl::Disconnect(reason) = control else {
+            continue;
+          };
+          // A session waiting for its client to resume shouldn't be resumed anymore, its cleanup
+          // runs once the window passes as usual.
+          resumption.revoke_token();
+          if !connected.swap(false, Ordering::SeqCst) {
+            continue;
+          }
+          warn!("Disconnecting session {}: {}", session_id, reason);
+          ping_timer.stop_ping_timer().await;
+          device_manager.release_device_locks(session_id);
+          if let Err(e) = device_manager.stop_session_devices(session_id).await {
+            error!("Could not stop devices on forced disconnect: {:?}", e);
+          }
+          if !output_sender
+            .send(message::Error::from(reason).into())
+            .await
+          {
+            error!("Server disappeared, cannot update about forced disconnect.");
+          }
+        }
+      });
+    }
+
+    ButtplugServer {
+      server_name: server_name.to_owned(),
+      max_ping_time: ping_time,
+      client_ping_time_limit,
+      device_manager,
+      command_scheduler,
+      funscript_player,
+      pattern_player,
+      stroke_generator,
+      session_id,
+      ping_timer,
+      connected,
+      output_sender,
+      event_drop_policy,
+      default_connection_scope: connection_scope,
+      connection_scope,
+      session_resumption_window,
+      resumption,
+      client_name: Arc::new(std::sync::Mutex::new(None)),
+      client_message_version: Arc::new(std::sync::Mutex::new(None)),
+      offered_capabilities: BUTTPLUG_DEFAULT_CAPABILITIES.to_vec(),
+      capabilities: Arc::new(std::sync::Mutex::new(vec![])),
+      in_flight_commands: Arc::new(DashMap::new()),
+      #[cfg(feature = "audit-log")]
+      audit_log: None,
+    }
+  }
+
+  /// Creates another server session sharing this server's device manager, so that multiple clients
+  /// can be connected to the same devices at once. Each session has its own handshake, ping timer
+  /// and playback state, and can hold device locks via
+  /// [DeviceLockCmd](crate::core::message::DeviceLockCmd).
+  pub fn new_session(&self) -> ButtplugServer {
+    self.new_session_with_scope(self.default_connection_scope)
+  }
+
+  /// Like [ButtplugServer::new_session], but with its own [ButtplugConnectionScope] instead of the
+  /// one set via [ButtplugServerBuilder::connection_scope]. Useful for limiting what less trusted
+  /// clients, like web pages, can do.
+  pub fn new_session_with_scope(&self, scope: ButtplugConnectionScope) -> ButtplugServer {
+    let mut session = Self::with_device_manager(
+      &self.server_name,
+      self.max_ping_time,
+      self.client_ping_time_limit,
+      self.device_manager.clone(),
+      self.event_drop_policy,
+      self.default_connection_scope,
+      self.session_resumption_window,
+    );
+    session.connection_scope = scope;
+    session.offered_capabilities = self.offered_capabilities.clone();
+    #[cfg(feature = "audit-log")]
+    {
+      session.audit_log = self.audit_log.clone();
+    }
+    session
+  }
+
+  /// What this session is allowed to do.
+  pub fn connection_scope(&self) -> ButtplugConnectionScope {
+    self.connection_scope
+  }
+
+  /// Name of the connected client, as sent during the handshake. Kept after the client disconnects,
+  /// until the next handshake.
+  pub fn client_name(&self) -> Option<String> {
+    self
+      .client_name
+      .lock()
+      .expect("Lock is never poisoned")
+      .clone()
+  }
+
+  /// Message spec version of the connected client, as sent during the handshake. Kept after the
+  /// client disconnects, until the next handshake.
+  pub fn client_message_version(&self) -> Option<ButtplugMessageSpecVersion> {
+    *self
+      .client_message_version
+      .lock()
+      .expect("Lock is never poisoned")
+  }
+
+  /// Capabilities agreed on with the connected client during the handshake, the ones it asked for
+  /// that the server also offers. Kept after the client disconnects, until the next handshake.
+  pub fn capabilities(&self) -> Vec<ButtplugCapability> {
+    self
+      .capabilities
+      .lock()
+      .expect("Lock is never poisoned")
+      .clone()
+  }
+
+  /// True if the capability was agreed on with the connected client.
+  pub fn has_capability(&self, capability: ButtplugCapability) -> bool {
+    self
+      .capabilities
+      .lock()
+      .expect("Lock is never poisoned")
+      .contains(&capability)
+  }
+
+  /// Features of a connected device that the client can't see, because it connected with an older
+  /// message spec version. Empty if the client is current, or hasn't connected yet. Returns None if
+  /// there's no device at the index.
+  pub fn lost_device_features(&self, device_index: u32) -> Option<Vec<LostDeviceFeature>> {
+    let attributes = self
+      .device_manager
+      .device_message_attributes(device_index)?;
+    Some(match self.client_message_version() {
+      Some(version) => ClientDeviceMessageAttributes::from(attributes).lost_features(version),
+      None => vec![],
+    })
+  }
+
+  /// Id of this session, as returned by [ServerDeviceManager::device_lock_owner].
+  pub fn session_id(&self) -> u32 {
+    self.session_id
+  }
+
   /// Retreive an async stream of ButtplugServerMessages. This is how the server sends out
   /// non-query-related updates to the system, including information on devices being added/removed,
   /// client disconnection, etc...
   pub fn event_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
+    self.event_stream_with_policy(self.event_drop_policy)
+  }
+
+  /// Like [ButtplugServer::event_stream], with a drop policy for this stream instead of the one set
+  /// via [ButtplugServerBuilder::event_drop_policy]. Useful when subscribers have different needs,
+  /// i.e. a UI that only cares about the latest sensor values next to a logger that needs all of
+  /// them.
+  pub fn event_stream_with_policy(
+    &self,
+    policy: EventDropPolicy,
+  ) -> impl Stream<Item = ButtplugServerMessage> {
     // Unlike the client API, we can expect anyone using the server to pin this
     // themselves.
-    let server_receiver = convert_broadcast_receiver_to_stream(self.output_sender.subscribe());
-    let device_receiver = self.device_manager.event_stream();
+    let server_receiver = self.output_sender.subscribe(policy);
+    let device_receiver = self.device_manager.event_stream_with_policy(policy);
     device_receiver.merge(server_receiver)
   }
 
@@ -381,30 +1037,79 @@ impl ButtplugServer {
     self.device_manager.clone()
   }
 
+  /// Adds a comm manager after the server has been built. See
+  /// [ServerDeviceManager::add_comm_manager].
+  pub fn add_comm_manager<T>(
+    &self,
+    builder: T,
+  ) -> BoxFuture<'static, Result<(), ButtplugServerError>>
+  where
+    T: HardwareCommunicationManagerBuilder + 'static,
+  {
+    self.device_manager.add_comm_manager(builder)
+  }
+
+  /// Removes a comm manager by name, disconnecting its devices. See
+  /// [ServerDeviceManager::remove_comm_manager].
+  pub fn remove_comm_manager(
+    &self,
+    name: &str,
+  ) -> BoxFuture<'static, Result<(), ButtplugServerError>> {
+    self.device_manager.remove_comm_manager(name)
+  }
+
   /// If true, client is currently connected to the server.
   pub fn connected(&self) -> bool {
     self.connected.load(Ordering::SeqCst)
   }
 
-  /// Disconnects the server from a client, if it is connected.
+  /// Disconnects the server from a client, if it is connected. If the client was given a
+  /// resumption token, cleaning up the session is held off until the
+  /// [resumption window](ButtplugServerBuilder::session_resumption_window) passes.
   pub fn disconnect(&self) -> BoxFuture<Result<(), message::Error>> {
     debug!("Buttplug Server {} disconnect requested", self.server_name);
     let ping_timer = self.ping_timer.clone();
-    let stop_scanning_fut =
-      self.parse_message(ButtplugClientMessage::StopScanning(StopScanning::default()));
-    let stop_fut = self.parse_message(ButtplugClientMessage::StopAllDevices(
-      StopAllDevices::default(),
-    ));
     let connected = self.connected.clone();
+    // Anything handed back couldn't be deferred, so it needs to run now.
+    let cleanup = self.resumption.defer_cleanup(self.session_cleanup()).err();
     async move {
       connected.store(false, Ordering::SeqCst);
       ping_timer.stop_ping_timer().await;
+      if let Some(cleanup) = cleanup {
+        cleanup.await;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  /// Stops scanning, playback and devices this session started, and releases its device locks.
+  /// Nothing happens until the returned future is polled.
+  fn session_cleanup(&self) -> BoxFuture<'static, ()> {
+    let stop_scanning = self.connected();
+    let session_id = self.session_id;
+    let device_manager = self.device_manager.clone();
+    let command_scheduler = self.command_scheduler.clone();
+    let funscript_player = self.funscript_player.clone();
+    let pattern_player = self.pattern_player.clone();
+    let stroke_generator = self.stroke_generator.clone();
+    async move {
       // Ignore returns here, we just want to stop.
-      info!("Server disconnected, stopping device scanning if it was started...");
-      let _ = stop_scanning_fut.await;
-      info!("Server disconnected, stopping all devices...");
+      if stop_scanning {
+        info!("Server disconnected, stopping device scanning if it was started...");
+        let _ = device_manager
+          .parse_message(ButtplugClientMessage::StopScanning(StopScanning::default()))
+          .await;
+      }
+      // Other sessions may still be using devices, so only stop what this session was commanding.
+      command_scheduler.cancel_all();
+      funscript_player.pause_all();
+      pattern_player.stop_all();
+      stroke_generator.stop_all();
+      let stop_fut = device_manager.stop_session_devices(session_id);
+      device_manager.release_device_locks(session_id);
+      info!("Server disconnected, stopping devices commanded by this session...");
       let _ = stop_fut.await;
-      Ok(())
     }
     .boxed()
   }
@@ -414,6 +1119,28 @@ impl ButtplugServer {
   pub fn parse_message(
     &self,
     msg: ButtplugClientMessage,
+  ) -> BoxFuture<'static, Result<ButtplugServerMessage, message::Error>> {
+    #[cfg(feature = "audit-log")]
+    if let Some(audit_log) = &self.audit_log {
+      if audit_log::is_audited(&msg) {
+        // Take the entry now, so the timestamp is when the command arrived rather than finished.
+        let entry = audit_log::AuditLogEntry::new(self.session_id, self.client_name(), &msg);
+        let audit_log = audit_log.clone();
+        let fut = self.handle_message(msg);
+        return async move {
+          let result = fut.await;
+          audit_log.record(entry, result.as_ref().map(|_| ()));
+          result
+        }
+        .boxed();
+      }
+    }
+    self.handle_message(msg)
+  }
+
+  fn handle_message(
+    &self,
+    msg: ButtplugClientMessage,
   ) -> BoxFuture<'static, Result<ButtplugServerMessage, message::Error>> {
     trace!(
       "Buttplug Server {} received message to client parse: {:?}",
@@ -442,22 +1169,150 @@ impl ButtplugServer {
       }
       // If we haven't pinged out and we got an RSI message, fall thru.
     }
+    if let Err(err) = self.connection_scope.check(&msg) {
+      let mut error = message::Error::from(ButtplugError::from(err));
+      error.set_id(id);
+      return future::ready(Err(error)).boxed();
+    }
+    if let Some(device_index) = locked_device_index(&msg) {
+      // Track which session is moving the device, so only that session's devices are stopped
+      // when it goes away.
+      let commanding = !matches!(
+        msg,
+        ButtplugClientMessage::RawReadCmd(_)
+          | ButtplugClientMessage::RawSubscribeCmd(_)
+          | ButtplugClientMessage::RawUnsubscribeCmd(_)
+          | ButtplugClientMessage::FunscriptLoadCmd(_)
+          | ButtplugClientMessage::PatternLoadCmd(_)
+      );
+      if let Err(err) =
+        self
+          .device_manager
+          .check_device_access(device_index, self.session_id, commanding)
+      {
+        let mut error = message::Error::from(ButtplugError::from(err));
+        error.set_id(id);
+        return future::ready(Err(error)).boxed();
+      }
+    }
+    // Stops have to go through whatever happens, so they can't be cancelled.
+    let cancellable = ButtplugDeviceCommandMessageUnion::matches_client_message(&msg)
+      && !matches!(msg, ButtplugClientMessage::StopDeviceCmd(_));
     // Produce whatever future is needed to reply to the message, this may be a
     // device command future, or something the server handles. All futures will
     // return Result<ButtplugServerMessage, ButtplugError>, and we'll handle
     // tagging the result with the message id in the future we put out as the
     // return value from this method.
-    let out_fut = if ButtplugDeviceManagerMessageUnion::try_from(msg.clone()).is_ok()
-      || ButtplugDeviceCommandMessageUnion::try_from(msg.clone()).is_ok()
+    let out_fut = if ButtplugDeviceManagerMessageUnion::matches_client_message(&msg)
+      || ButtplugDeviceCommandMessageUnion::matches_client_message(&msg)
     {
-      self.device_manager.parse_message(msg.clone())
+      // Stop messages should also drop any pending scheduled commands and halt any running script
+      // playback, otherwise the device will start moving again right after stopping. New scalar
+      // commands replace any running loop, and new linear commands replace any running strokes.
+      match &msg {
+        ButtplugClientMessage::StopAllDevices(_) => {
+          self.command_scheduler.cancel_all();
+          self.funscript_player.pause_all();
+          self.pattern_player.stop_all();
+          self.stroke_generator.stop_all();
+        }
+        ButtplugClientMessage::StopDeviceCmd(stop_msg) => {
+          self.command_scheduler.cancel(stop_msg.device_index());
+          self.funscript_player.pause(stop_msg.device_index());
+          self.pattern_player.stop_device(stop_msg.device_index());
+          self.stroke_generator.stop(stop_msg.device_index());
+        }
+        ButtplugClientMessage::ScalarCmd(scalar_msg) => {
+          self.pattern_player.stop_loop(scalar_msg.device_index())
+        }
+        ButtplugClientMessage::LinearCmd(linear_msg) => {
+          self.stroke_generator.stop(linear_msg.device_index())
+        }
+        _ => {}
+      }
+      if let Some((device_index, timestamp)) = scheduled_timestamp(&msg) {
+        self
+          .command_scheduler
+          .schedule(device_index, timestamp, msg)
+      } else {
+        self.device_manager.parse_message(msg)
+      }
     } else {
       match msg {
         ButtplugClientMessage::RequestServerInfo(rsi_msg) => self.perform_handshake(rsi_msg),
         ButtplugClientMessage::Ping(p) => self.handle_ping(p),
+        ButtplugClientMessage::EndSessionCmd(end_msg) => {
+          // With no token to resume with, disconnecting cleans the session up right away.
+          self.resumption.revoke_token();
+          future::ready(Ok(message::Ok::new(end_msg.id()).into())).boxed()
+        }
+        ButtplugClientMessage::FunscriptLoadCmd(load_msg) => self.funscript_player.load(load_msg),
+        ButtplugClientMessage::FunscriptPlaybackCmd(playback_msg) => {
+          self.funscript_player.playback(playback_msg)
+        }
+        ButtplugClientMessage::PatternLoadCmd(load_msg) => self.pattern_player.load(load_msg),
+        ButtplugClientMessage::PatternPlayCmd(play_msg) => self.pattern_player.play(play_msg),
+        ButtplugClientMessage::PatternStopCmd(stop_msg) => self.pattern_player.stop(stop_msg),
+        ButtplugClientMessage::ScalarLoopCmd(loop_msg) => self.pattern_player.play_loop(loop_msg),
+        ButtplugClientMessage::StrokeCmd(stroke_msg) => self.stroke_generator.start(stroke_msg),
+        ButtplugClientMessage::DeviceLockCmd(lock_msg) => self.handle_device_lock(lock_msg),
+        ButtplugClientMessage::DeviceUnlockCmd(unlock_msg) => self.handle_device_unlock(unlock_msg),
+        ButtplugClientMessage::DeviceDisplayNameCmd(display_name_msg) => {
+          self.handle_device_display_name(display_name_msg)
+        }
+        ButtplugClientMessage::DeviceFirmwareUpdateCmd(update_msg) => {
+          self.handle_device_firmware_update(update_msg)
+        }
+        ButtplugClientMessage::DeviceSelfTestCmd(self_test_msg) => {
+          self.handle_device_self_test(self_test_msg)
+        }
+        ButtplugClientMessage::CancelCmd(cancel_msg) => self.handle_cancel(cancel_msg),
+        // Lowering the ceiling only makes things safer, raising it is server management.
+        ButtplugClientMessage::IntensityCeilingCmd(ref ceiling_msg)
+          if ceiling_msg.ceiling() > self.device_manager.intensity_ceiling()
+            && !self.device_manager.server_management_allowed() =>
+        {
+          ButtplugMessageError::MessageNotAllowed(msg.name().to_owned()).into()
+        }
+        ButtplugClientMessage::IntensityCeilingCmd(ceiling_msg) => self
+          .device_manager
+          .set_intensity_ceiling(ceiling_msg.ceiling()),
+        ButtplugClientMessage::ServerShutdownCmd(_)
+        | ButtplugClientMessage::ForceStopAllDevicesCmd(_)
+        | ButtplugClientMessage::KickClientCmd(_)
+        | ButtplugClientMessage::ScanningEnabledCmd(_)
+          if !self.device_manager.server_management_allowed() =>
+        {
+          ButtplugMessageError::MessageNotAllowed(msg.name().to_owned()).into()
+        }
+        ButtplugClientMessage::ServerShutdownCmd(_) => {
+          self.device_manager.request_shutdown(Some(self.session_id))
+        }
+        ButtplugClientMessage::ForceStopAllDevicesCmd(_) => {
+          self.device_manager.force_stop_all_devices()
+        }
+        ButtplugClientMessage::KickClientCmd(kick_msg) => {
+          if self.device_manager.kick_session(kick_msg.session_id()) {
+            future::ready(Ok(message::Ok::new(kick_msg.id()).into())).boxed()
+          } else {
+            ButtplugMessageError::InvalidMessageContents(format!(
+              "No server session with id {}",
+              kick_msg.session_id()
+            ))
+            .into()
+          }
+        }
+        ButtplugClientMessage::ScanningEnabledCmd(scanning_msg) => self
+          .device_manager
+          .set_scanning_enabled(scanning_msg.enabled()),
         _ => ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into(),
       }
     };
+    let out_fut = if cancellable {
+      self.track_in_flight(id, out_fut)
+    } else {
+      out_fut
+    };
     // Simple way to set the ID on the way out. Just rewrap
     // the returned future to make sure it happens.
     async move {
@@ -498,13 +1353,62 @@ impl ButtplugServer {
       )
       .into();
     }
+    if msg.message_version() < BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION {
+      info!(
+        "Client {} uses message spec version {}, device features added in later versions will be hidden from it.",
+        msg.client_name(),
+        msg.message_version()
+      );
+    }
+    let max_ping_time = negotiate_ping_time(
+      self.max_ping_time,
+      self.client_ping_time_limit,
+      msg.max_ping_time(),
+    );
+    info!("Using ping time of {}ms for connection.", max_ping_time);
     // Only start the ping timer after we've received the handshake.
     let ping_timer = self.ping_timer.clone();
-    let out_msg =
-      message::ServerInfo::new(&self.server_name, msg.message_version(), self.max_ping_time);
+    let mut out_msg =
+      message::ServerInfo::new(&self.server_name, msg.message_version(), max_ping_time);
     let connected = self.connected.clone();
+    let client_name = self.client_name.clone();
+    let new_client_name = msg.client_name().clone();
+    let client_message_version = self.client_message_version.clone();
+    let new_client_message_version = msg.message_version();
+    let new_capabilities: Vec<ButtplugCapability> = msg
+      .capabilities()
+      .iter()
+      .filter(|x| self.offered_capabilities.contains(x))
+      .copied()
+      .collect();
+    if !new_capabilities.is_empty() {
+      info!("Using capabilities {:?} for connection.", new_capabilities);
+    }
+    out_msg.set_capabilities(new_capabilities.clone());
+    let capabilities = self.capabilities.clone();
+    let resumption = self.resumption.clone();
+    let resumption_token = msg.resumption_token().clone();
+    // Older clients don't know about resumption tokens, so they can't send them back.
+    let issue_token = msg.message_version() == BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION;
+    // They also can't timestamp commands, so they have no use for the server time.
+    if msg.message_version() == BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION {
+      out_msg.set_server_time(Some(unix_time_millis()));
+    }
     async move {
-      ping_timer.start_ping_timer().await;
+      // Either picks the previous session back up, or finishes cleaning it up before the new
+      // client can use it.
+      resumption.resume(resumption_token.as_deref()).await;
+      if issue_token {
+        out_msg.set_resumption_token(resumption.issue_token());
+      } else {
+        resumption.revoke_token();
+      }
+      ping_timer.start_ping_timer(max_ping_time).await;
+      *client_name.lock().expect("Lock is never poisoned") = Some(new_client_name);
+      *client_message_version
+        .lock()
+        .expect("Lock is never poisoned") = Some(new_client_message_version);
+      *capabilities.lock().expect("Lock is never poisoned") = new_capabilities;
       connected.store(true, Ordering::SeqCst);
       debug!("Server handshake check successful.");
       Result::Ok(out_msg.into())
@@ -512,9 +1416,46 @@ impl ButtplugServer {
     .boxed()
   }
 
+  /// Makes a device command cancellable via [CancelCmd](message::CancelCmd) until it's answered.
+  /// Cancelling drops the command's future, which takes it out of the device's command queue if
+  /// it's still waiting there.
+  fn track_in_flight(
+    &self,
+    id: u32,
+    fut: ButtplugServerResultFuture,
+  ) -> ButtplugServerResultFuture {
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    self.in_flight_commands.insert(id, abort_handle);
+    let in_flight_commands = self.in_flight_commands.clone();
+    async move {
+      let result = Abortable::new(fut, abort_registration).await;
+      in_flight_commands.remove(&id);
+      result.unwrap_or_else(|_| Err(ButtplugDeviceError::DeviceCommandCancelled(id).into()))
+    }
+    .boxed()
+  }
+
+  /// Cancels a device command sent earlier by this session. Commands that have already been
+  /// answered can't be cancelled, but that isn't an error, as the client can't know whether the
+  /// reply is already on its way.
+  fn handle_cancel(&self, msg: message::CancelCmd) -> ButtplugServerResultFuture {
+    if !self.has_capability(ButtplugCapability::Cancellation) {
+      return ButtplugMessageError::UnhandledMessage(format!(
+        "CancelCmd requires the {} capability",
+        ButtplugCapability::Cancellation
+      ))
+      .into();
+    }
+    if let Some((_, abort_handle)) = self.in_flight_commands.remove(&msg.message_id()) {
+      debug!("Cancelling device command {}", msg.message_id());
+      abort_handle.abort();
+    }
+    future::ready(Ok(message::Ok::new(msg.id()).into())).boxed()
+  }
+
   /// Update the [PingTimer] with the latest received ping message.
   fn handle_ping(&self, msg: message::Ping) -> ButtplugServerResultFuture {
-    if self.max_ping_time == 0 {
+    if self.ping_timer.max_ping_time() == 0 {
       return ButtplugPingError::PingTimerNotRunning.into();
     }
     let fut = self.ping_timer.update_ping_time();
@@ -525,6 +1466,75 @@ impl ButtplugServer {
     .boxed()
   }
 
+  fn handle_device_lock(&self, msg: message::DeviceLockCmd) -> ButtplugServerResultFuture {
+    let result = self
+      .device_manager
+      .lock_device(msg.device_index(), self.session_id)
+      .map(|_| message::Ok::new(msg.id()).into())
+      .map_err(|err| err.into());
+    future::ready(result).boxed()
+  }
+
+  fn handle_device_unlock(&self, msg: message::DeviceUnlockCmd) -> ButtplugServerResultFuture {
+    let result = self
+      .device_manager
+      .unlock_device(msg.device_index(), self.session_id)
+      .map(|_| message::Ok::new(msg.id()).into())
+      .map_err(|err| err.into());
+    future::ready(result).boxed()
+  }
+
+  fn handle_device_display_name(
+    &self,
+    msg: message::DeviceDisplayNameCmd,
+  ) -> ButtplugServerResultFuture {
+    let device_manager = self.device_manager.clone();
+    async move {
+      device_manager
+        .set_device_display_name(msg.device_index(), msg.display_name().as_deref())
+        .await
+        .map(|_| message::Ok::new(msg.id()).into())
+        .map_err(|err| err.into())
+    }
+    .boxed()
+  }
+
+  fn handle_device_firmware_update(
+    &self,
+    msg: message::DeviceFirmwareUpdateCmd,
+  ) -> ButtplugServerResultFuture {
+    let id = msg.id();
+    let image = DfuFirmwareImage::new(msg.init_packet(), msg.firmware());
+    let update_fut = self
+      .device_manager
+      .update_device_firmware(msg.device_index(), image);
+    async move { update_fut.await.map(|_| message::Ok::new(id).into()) }.boxed()
+  }
+
+  fn handle_device_self_test(&self, msg: message::DeviceSelfTestCmd) -> ButtplugServerResultFuture {
+    // Anything playing on the device would fight the test pulses, so stop it the same way a
+    // StopDeviceCmd would.
+    self.command_scheduler.cancel(msg.device_index());
+    self.funscript_player.pause(msg.device_index());
+    self.pattern_player.stop_device(msg.device_index());
+    self.stroke_generator.stop(msg.device_index());
+    let id = msg.id();
+    let self_test_fut = self.device_manager.run_device_self_test(msg.device_index());
+    async move {
+      let mut report = self_test_fut.await?;
+      report.set_id(id);
+      Ok(report.into())
+    }
+    .boxed()
+  }
+
+  /// Resolves once an admin client has asked for the server to shut down, see
+  /// [ServerDeviceManager::request_shutdown]. Processes hosting the server should shut it down
+  /// and close the remaining connection when this happens.
+  pub fn shutdown_requested(&self) -> impl Future<Output = ()> + Send + 'static {
+    self.device_manager.shutdown_requested()
+  }
+
   pub fn shutdown(&self) -> ButtplugServerResultFuture {
     let device_manager = self.device_manager.clone();
     //let disconnect_future = self.disconnect();
@@ -532,13 +1542,88 @@ impl ButtplugServer {
   }
 }
 
+impl Drop for ButtplugServer {
+  fn drop(&mut self) {
+    self.device_manager.unregister_session(self.session_id);
+    // Still being connected here means the connection went away without disconnecting, i.e. the
+    // connector was dropped mid-write or the task owning it panicked. There's no client left to
+    // resume the session, so clean it up now rather than leaving its devices running.
+    if self.connected() {
+      self.connected.store(false, Ordering::SeqCst);
+      if async_manager::can_spawn() {
+        warn!("Server dropped while connected, stopping devices commanded by this session.");
+        async_manager::spawn(self.session_cleanup());
+      } else {
+        error!(
+          "Server dropped while connected outside of an async runtime, cannot stop devices commanded by this session."
+        );
+      }
+    }
+  }
+}
+
+/// Ping time for a connection, given the server default, the limit for client requests (0 for no
+/// limit), and the ping time the client requested, if any. A ping time of 0 means no ping timeout.
+fn negotiate_ping_time(default: u32, limit: u32, requested: Option<u32>) -> u32 {
+  match (requested, limit) {
+    (None, _) => default,
+    (Some(requested), 0) => requested,
+    // Asking for no timeout when the server has a limit gets the longest timeout allowed.
+    (Some(0), limit) => limit,
+    (Some(requested), limit) => requested.min(limit),
+  }
+}
+
+/// Device index for messages that change device output, which are refused if another session holds
+/// a lock on the device. Stops are always allowed, so any client can stop a device for safety
+/// reasons, and sensor reads don't interfere with the session holding the lock.
+fn locked_device_index(msg: &ButtplugClientMessage) -> Option<u32> {
+  match msg {
+    ButtplugClientMessage::VibrateCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::LinearCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::RotateCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::RotatePositionCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::ScalarCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::RawWriteCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::RawReadCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::RawSubscribeCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::RawUnsubscribeCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::FunscriptLoadCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::FunscriptPlaybackCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::PatternLoadCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::PatternPlayCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::ScalarLoopCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::StrokeCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::SingleMotorVibrateCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::FleshlightLaunchFW12Cmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::LovenseCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::KiirooCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::VorzeA10CycloneCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::DeviceFirmwareUpdateCmd(m) => Some(m.device_index()),
+    ButtplugClientMessage::DeviceSelfTestCmd(m) => Some(m.device_index()),
+    _ => None,
+  }
+}
+
 #[cfg(test)]
 mod test {
   use crate::{
     core::message::{self, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION},
-    server::ButtplugServer,
+    server::{negotiate_ping_time, ButtplugServer},
   };
 
+  #[test]
+  fn test_negotiate_ping_time() {
+    assert_eq!(negotiate_ping_time(1000, 1000, None), 1000);
+    assert_eq!(negotiate_ping_time(1000, 1000, Some(500)), 500);
+    assert_eq!(negotiate_ping_time(1000, 1000, Some(5000)), 1000);
+    assert_eq!(negotiate_ping_time(1000, 1000, Some(0)), 1000);
+    assert_eq!(negotiate_ping_time(1000, 10000, Some(5000)), 5000);
+    assert_eq!(negotiate_ping_time(1000, 0, Some(0)), 0);
+    assert_eq!(negotiate_ping_time(0, 0, Some(5000)), 5000);
+    assert_eq!(negotiate_ping_time(0, 0, None), 0);
+  }
+
   #[tokio::test]
   async fn test_server_reuse() {
     let server = ButtplugServer::default();
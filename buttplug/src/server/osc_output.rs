@@ -0,0 +1,398 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Republishes device state and sensor readings as OSC messages over UDP, so OSC tools
+//! (TouchDesigner, VRChat OSC listeners, etc) can react to device activity without a Buttplug
+//! client.
+//!
+//! Messages are sent to a single target address, under a prefix that defaults to
+//! [DEFAULT_OSC_ADDRESS_PREFIX]. For a device with index `i`:
+//!
+//! - `<prefix>/device/<i>/name` (string): Sent when the device connects.
+//! - `<prefix>/device/<i>/connected` (int): 1 when the device connects, 0 when it disconnects.
+//! - `<prefix>/device/<i>/<actuator>/<feature>` (float): Level of a ScalarCmd feature, from 0 to 1.
+//!   The actuator is the lowercased [ActuatorType](crate::core::message::ActuatorType), i.e.
+//!   `vibrate`. Legacy vibrate commands are reported against the matching ScalarCmd feature.
+//! - `<prefix>/device/<i>/rotation/<feature>` (float): Rotation speed from RotateCmd, from -1 to 1,
+//!   negative when counter-clockwise.
+//! - `<prefix>/device/<i>/position/<feature>` (float): Target position from LinearCmd, from 0 to 1.
+//! - `<prefix>/device/<i>/battery` (float): Battery level, from 0 to 1.
+//! - `<prefix>/device/<i>/sensor/<sensor type>/<sensor>` (ints): Sensor readings, with the sensor
+//!   type lowercased. Only readings some client asked for are sent.
+//!
+//! When a device is stopped or disconnects, every level and speed sent for it is set back to 0.
+//! Positions are left alone, as stopping doesn't move the device.
+
+use super::device::ServerDeviceManager;
+use crate::{
+  core::message::{
+    ActuatorType,
+    ButtplugDeviceCommandMessageUnion,
+    ButtplugDeviceMessage,
+    ButtplugServerMessage,
+    RequestDeviceList,
+  },
+  util::async_manager,
+};
+use futures::{pin_mut, Stream, StreamExt};
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+/// Address prefix used when none is given.
+pub const DEFAULT_OSC_ADDRESS_PREFIX: &str = "/buttplug";
+
+/// An OSC message argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+  Int(i32),
+  Float(f32),
+  String(String),
+}
+
+/// Appends a null terminated string, padded to a multiple of 4 bytes.
+fn encode_osc_string(buffer: &mut Vec<u8>, value: &str) {
+  buffer.extend_from_slice(value.as_bytes());
+  let padding = 4 - value.len() % 4;
+  buffer.resize(buffer.len() + padding, 0);
+}
+
+/// Encodes an OSC 1.0 message.
+pub fn encode_osc_message(address: &str, arguments: &[OscArgument]) -> Vec<u8> {
+  let mut buffer = vec![];
+  encode_osc_string(&mut buffer, address);
+  let type_tags: String = std::iter::once(',')
+    .chain(arguments.iter().map(|argument| match argument {
+      OscArgument::Int(_) => 'i',
+      OscArgument::Float(_) => 'f',
+      OscArgument::String(_) => 's',
+    }))
+    .collect();
+  encode_osc_string(&mut buffer, &type_tags);
+  for argument in arguments {
+    match argument {
+      OscArgument::Int(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+      OscArgument::Float(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+      OscArgument::String(value) => encode_osc_string(&mut buffer, value),
+    }
+  }
+  buffer
+}
+
+/// Turns device commands and events into OSC messages, tracking the levels sent for each device so
+/// they can be zeroed when it stops.
+struct OscStateTracker {
+  device_manager: Arc<ServerDeviceManager>,
+  prefix: String,
+  /// Device index to the level and speed addresses sent for it.
+  active_addresses: HashMap<u32, Vec<String>>,
+}
+
+impl OscStateTracker {
+  fn device_address(&self, device_index: u32, path: &str) -> String {
+    format!("{}/device/{}/{}", self.prefix, device_index, path)
+  }
+
+  fn set_level(&mut self, device_index: u32, path: &str, level: f64) -> (String, Vec<OscArgument>) {
+    let address = self.device_address(device_index, path);
+    let active = self.active_addresses.entry(device_index).or_default();
+    if !active.contains(&address) {
+      active.push(address.clone());
+    }
+    (address, vec![OscArgument::Float(level as f32)])
+  }
+
+  fn stop(&mut self, device_index: u32) -> Vec<(String, Vec<OscArgument>)> {
+    self
+      .active_addresses
+      .remove(&device_index)
+      .unwrap_or_default()
+      .into_iter()
+      .map(|address| (address, vec![OscArgument::Float(0.0)]))
+      .collect()
+  }
+
+  /// ScalarCmd feature indexes of the device's vibrators, in the order legacy vibrate commands
+  /// address them.
+  fn vibrator_indexes(&self, device_index: u32) -> Vec<u32> {
+    self
+      .device_manager
+      .device_message_attributes(device_index)
+      .and_then(|attrs| attrs.scalar_cmd().clone())
+      .map(|attrs| {
+        attrs
+          .iter()
+          .enumerate()
+          .filter(|(_, attr)| *attr.actuator_type() == ActuatorType::Vibrate)
+          .map(|(index, _)| index as u32)
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  fn vibrate_path(index: u32) -> String {
+    format!("{}/{}", actuator_name(ActuatorType::Vibrate), index)
+  }
+
+  fn command_messages(
+    &mut self,
+    command: &ButtplugDeviceCommandMessageUnion,
+  ) -> Vec<(String, Vec<OscArgument>)> {
+    let device_index = command.device_index();
+    match command {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => msg
+        .scalars()
+        .iter()
+        .map(|scalar| {
+          let path = format!(
+            "{}/{}",
+            actuator_name(scalar.actuator_type()),
+            scalar.index()
+          );
+          self.set_level(device_index, &path, scalar.scalar())
+        })
+        .collect(),
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
+        let vibrators = self.vibrator_indexes(device_index);
+        msg
+          .speeds()
+          .iter()
+          .filter_map(|speed| {
+            let index = *vibrators.get(speed.index() as usize)?;
+            Some(self.set_level(device_index, &Self::vibrate_path(index), speed.speed()))
+          })
+          .collect()
+      }
+      ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg) => self
+        .vibrator_indexes(device_index)
+        .into_iter()
+        .map(|index| self.set_level(device_index, &Self::vibrate_path(index), msg.speed()))
+        .collect(),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => msg
+        .rotations()
+        .iter()
+        .map(|rotation| {
+          let speed = if rotation.clockwise() {
+            rotation.speed()
+          } else {
+            -rotation.speed()
+          };
+          self.set_level(
+            device_index,
+            &format!("rotation/{}", rotation.index()),
+            speed,
+          )
+        })
+        .collect(),
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => msg
+        .vectors()
+        .iter()
+        .map(|vector| {
+          (
+            self.device_address(device_index, &format!("position/{}", vector.index())),
+            vec![OscArgument::Float(vector.position() as f32)],
+          )
+        })
+        .collect(),
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) => self.stop(device_index),
+      _ => vec![],
+    }
+  }
+
+  fn event_messages(&mut self, event: &ButtplugServerMessage) -> Vec<(String, Vec<OscArgument>)> {
+    match event {
+      ButtplugServerMessage::DeviceAdded(msg) => {
+        self.connected_messages(msg.device_index(), msg.device_name())
+      }
+      ButtplugServerMessage::DeviceRemoved(msg) => {
+        let device_index = msg.device_index();
+        let mut messages = self.stop(device_index);
+        messages.push((
+          self.device_address(device_index, "connected"),
+          vec![OscArgument::Int(0)],
+        ));
+        messages
+      }
+      ButtplugServerMessage::BatteryLevelReading(msg) => vec![(
+        self.device_address(msg.device_index(), "battery"),
+        vec![OscArgument::Float(msg.battery_level() as f32)],
+      )],
+      ButtplugServerMessage::SensorReading(msg) => vec![(
+        self.device_address(
+          msg.device_index(),
+          &format!(
+            "sensor/{}/{}",
+            msg.sensor_type().to_string().to_lowercase(),
+            msg.sensor_index()
+          ),
+        ),
+        msg.data().iter().map(|x| OscArgument::Int(*x)).collect(),
+      )],
+      _ => vec![],
+    }
+  }
+
+  fn connected_messages(
+    &self,
+    device_index: u32,
+    device_name: &str,
+  ) -> Vec<(String, Vec<OscArgument>)> {
+    vec![
+      (
+        self.device_address(device_index, "name"),
+        vec![OscArgument::String(device_name.to_owned())],
+      ),
+      (
+        self.device_address(device_index, "connected"),
+        vec![OscArgument::Int(1)],
+      ),
+    ]
+  }
+}
+
+fn actuator_name(actuator: ActuatorType) -> String {
+  actuator.to_string().to_lowercase()
+}
+
+async fn send_messages(
+  socket: &UdpSocket,
+  target: SocketAddr,
+  messages: Vec<(String, Vec<OscArgument>)>,
+) {
+  for (address, arguments) in messages {
+    if let Err(err) = socket
+      .send_to(&encode_osc_message(&address, &arguments), target)
+      .await
+    {
+      warn!("Cannot send OSC message {} to {}: {}", address, target, err);
+    }
+  }
+}
+
+async fn run_osc_output(
+  socket: UdpSocket,
+  target: SocketAddr,
+  mut tracker: OscStateTracker,
+  commands: impl Stream<Item = ButtplugDeviceCommandMessageUnion>,
+  events: impl Stream<Item = ButtplugServerMessage>,
+  cancellation_token: CancellationToken,
+) {
+  pin_mut!(commands, events);
+  let device_manager = tracker.device_manager.clone();
+  // Devices that connected before we started won't get a DeviceAdded event.
+  if let Ok(ButtplugServerMessage::DeviceList(list)) = device_manager
+    .parse_message(RequestDeviceList::default().into())
+    .await
+  {
+    for device in list.devices() {
+      let messages = tracker.connected_messages(device.device_index(), device.device_name());
+      send_messages(&socket, target, messages).await;
+    }
+  }
+  loop {
+    let messages = tokio::select! {
+      _ = cancellation_token.cancelled() => return,
+      command = commands.next() => match command {
+        Some(command) => tracker.command_messages(&command),
+        None => return,
+      },
+      event = events.next() => match event {
+        Some(event) => tracker.event_messages(&event),
+        None => return,
+      },
+    };
+    send_messages(&socket, target, messages).await;
+  }
+}
+
+/// Sends device state and sensor readings from a device manager to an OSC listener. Output stops
+/// when this is dropped.
+pub struct OscOutput {
+  target: SocketAddr,
+  cancellation_token: CancellationToken,
+}
+
+impl OscOutput {
+  /// Send OSC messages for devices in `device_manager` to `target` (i.e. `127.0.0.1:9000`), with
+  /// addresses starting with `prefix`, or [DEFAULT_OSC_ADDRESS_PREFIX] if it's None.
+  pub fn new(
+    device_manager: Arc<ServerDeviceManager>,
+    target: SocketAddr,
+    prefix: Option<&str>,
+  ) -> Result<Self, io::Error> {
+    let bind_address: SocketAddr = if target.is_ipv4() {
+      ([0, 0, 0, 0], 0).into()
+    } else {
+      ([0u16; 8], 0).into()
+    };
+    let socket = std::net::UdpSocket::bind(bind_address)?;
+    socket.set_nonblocking(true)?;
+    // Subscribe now, so nothing that happens after this returns is missed.
+    let commands = device_manager.command_stream();
+    let events = device_manager.event_stream();
+    let tracker = OscStateTracker {
+      device_manager,
+      prefix: prefix
+        .unwrap_or(DEFAULT_OSC_ADDRESS_PREFIX)
+        .trim_end_matches('/')
+        .to_owned(),
+      active_addresses: HashMap::new(),
+    };
+    let cancellation_token = CancellationToken::new();
+    let task_token = cancellation_token.clone();
+    async_manager::spawn(async move {
+      match UdpSocket::from_std(socket) {
+        Ok(socket) => run_osc_output(socket, target, tracker, commands, events, task_token).await,
+        Err(err) => error!("Cannot start OSC output to {}: {}", target, err),
+      }
+    });
+    info!("Sending OSC output to {}", target);
+    Ok(Self {
+      target,
+      cancellation_token,
+    })
+  }
+
+  /// Address OSC messages are sent to.
+  pub fn target(&self) -> SocketAddr {
+    self.target
+  }
+}
+
+impl Drop for OscOutput {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{encode_osc_message, OscArgument};
+
+  #[test]
+  fn test_encode_osc_message() {
+    assert_eq!(encode_osc_message("/a", &[]), b"/a\0\0,\0\0\0".to_vec());
+    assert_eq!(
+      encode_osc_message(
+        "/test",
+        &[
+          OscArgument::Int(1),
+          OscArgument::Float(0.5),
+          OscArgument::String("abcd".to_owned())
+        ]
+      ),
+      [
+        b"/test\0\0\0".as_slice(),
+        b",ifs\0\0\0\0",
+        &[0, 0, 0, 1],
+        &[0x3f, 0, 0, 0],
+        b"abcd\0\0\0\0",
+      ]
+      .concat()
+    );
+  }
+}
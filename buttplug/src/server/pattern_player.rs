@@ -0,0 +1,427 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side pattern playback.
+//!
+//! Patterns (see [crate::util::pattern]) are loaded via [PatternLoadCmd], at which point tracks are
+//! resolved against the device's scalar actuators and flattened into timed [ScalarCmd] steps.
+//! Playback is then controlled with [PatternPlayCmd] and [PatternStopCmd].
+//!
+//! [ScalarLoopCmd] reuses the same playback machinery, treating its steps as a single section that
+//! loops forever.
+
+use super::{device::ServerDeviceManager, ButtplugServerResultFuture};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugMessageError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugDeviceMessageType,
+      PatternLoadCmd,
+      PatternPlayCmd,
+      PatternStopCmd,
+      ScalarCmd,
+      ScalarLoopCmd,
+      ScalarSubcommand,
+    },
+  },
+  server::device::configuration::ServerDeviceMessageAttributes,
+  util::{
+    async_manager,
+    pattern::{Pattern, PatternSection},
+    sleep,
+  },
+};
+use dashmap::DashMap;
+use futures::{future, FutureExt};
+use instant::Instant;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Section with tracks mapped to device features, and keyframes grouped into commands.
+struct ResolvedSection {
+  duration: u32,
+  repeat: u32,
+  steps: Vec<(u32, Vec<ScalarSubcommand>)>,
+}
+
+impl ResolvedSection {
+  fn new(section: &PatternSection, features: &[(ActuatorType, u32)]) -> Self {
+    let duration = section.duration();
+    let mut steps: BTreeMap<u32, Vec<ScalarSubcommand>> = BTreeMap::new();
+    for track in section.tracks() {
+      let Some(feature_index) = resolve_feature(features, track.actuator(), track.index()) else {
+        continue;
+      };
+      for keyframe in track.keyframes().iter().filter(|x| x.at <= duration) {
+        steps
+          .entry(keyframe.at)
+          .or_default()
+          .push(ScalarSubcommand::new(
+            feature_index,
+            keyframe.value,
+            track.actuator(),
+          ));
+      }
+    }
+    Self {
+      duration,
+      repeat: section.repeat(),
+      steps: steps.into_iter().collect(),
+    }
+  }
+}
+
+/// Pattern resolved against a specific device.
+struct ResolvedPattern {
+  looping: bool,
+  labels: Vec<String>,
+  sections: Vec<ResolvedSection>,
+  /// Every feature the pattern touches, so they can all be zeroed when playback ends.
+  used_features: Vec<ScalarSubcommand>,
+}
+
+/// Find the scalar feature index for the nth actuator of a certain type.
+fn resolve_feature(
+  features: &[(ActuatorType, u32)],
+  actuator: ActuatorType,
+  index: u32,
+) -> Option<u32> {
+  features
+    .iter()
+    .filter(|(actuator_type, _)| *actuator_type == actuator)
+    .nth(index as usize)
+    .map(|(_, feature_index)| *feature_index)
+}
+
+impl ResolvedPattern {
+  fn zero_command(&self, device_index: u32) -> ScalarCmd {
+    ScalarCmd::new(device_index, self.used_features.clone())
+  }
+
+  /// Returns None if the loop is too long for its total duration to fit in a u32.
+  fn from_loop(msg: &ScalarLoopCmd) -> Option<Self> {
+    let mut steps = vec![];
+    let mut used_features: Vec<ScalarSubcommand> = vec![];
+    let mut at: u32 = 0;
+    for step in msg.steps() {
+      steps.push((at, step.scalars().clone()));
+      at = at.checked_add(step.duration())?;
+      for scalar in step.scalars() {
+        if !used_features.iter().any(|x| x.index() == scalar.index()) {
+          used_features.push(ScalarSubcommand::new(
+            scalar.index(),
+            0.0,
+            scalar.actuator_type(),
+          ));
+        }
+      }
+    }
+    Some(Self {
+      looping: true,
+      labels: vec![],
+      sections: vec![ResolvedSection {
+        duration: at,
+        repeat: 1,
+        steps,
+      }],
+      used_features,
+    })
+  }
+
+  fn new(pattern: &Pattern, attrs: &ServerDeviceMessageAttributes) -> Option<Self> {
+    let features: Vec<(ActuatorType, u32)> = attrs
+      .scalar_cmd()
+      .as_ref()?
+      .iter()
+      .enumerate()
+      .map(|(i, attr)| (*attr.actuator_type(), i as u32))
+      .collect();
+    let mut used_features = vec![];
+    for track in pattern.sections().iter().flat_map(|x| x.tracks()) {
+      match resolve_feature(&features, track.actuator(), track.index()) {
+        Some(feature_index) => {
+          if !used_features
+            .iter()
+            .any(|x: &ScalarSubcommand| x.index() == feature_index)
+          {
+            used_features.push(ScalarSubcommand::new(feature_index, 0.0, track.actuator()));
+          }
+        }
+        None => debug!(
+          "Pattern track for {} {} has no matching device feature, ignoring.",
+          track.actuator(),
+          track.index()
+        ),
+      }
+    }
+    let sections: Vec<ResolvedSection> = pattern
+      .sections()
+      .iter()
+      .map(|x| ResolvedSection::new(x, &features))
+      .collect();
+    if used_features.is_empty() || sections.iter().all(|x| x.steps.is_empty()) {
+      return None;
+    }
+    Some(Self {
+      looping: pattern.looping(),
+      labels: pattern
+        .sections()
+        .iter()
+        .map(|x| x.label().to_owned())
+        .collect(),
+      sections,
+      used_features,
+    })
+  }
+}
+
+async fn playback_loop(
+  device_manager: Arc<ServerDeviceManager>,
+  device_index: u32,
+  pattern: Arc<ResolvedPattern>,
+  start_section: usize,
+  token: CancellationToken,
+) {
+  // Step times are calculated from an anchor that advances by exact section durations, so slow
+  // command round trips don't cause the pattern to drift over long loops.
+  let mut anchor = Instant::now();
+  let mut section_index = start_section;
+  loop {
+    let section = &pattern.sections[section_index];
+    for _ in 0..section.repeat {
+      for (at, scalars) in &section.steps {
+        let target = anchor + Duration::from_millis(*at as u64);
+        select! {
+          _ = sleep(target.saturating_duration_since(Instant::now())).fuse() => {},
+          _ = token.cancelled().fuse() => return,
+        }
+        if let Err(err) = device_manager
+          .parse_message(ScalarCmd::new(device_index, scalars.clone()).into())
+          .await
+        {
+          // Most likely the device disconnected, nothing left to clean up.
+          warn!(
+            "Pattern playback for device {} failed, stopping: {:?}",
+            device_index, err
+          );
+          return;
+        }
+      }
+      anchor += Duration::from_millis(section.duration as u64);
+      // Always wait out the end of the pass, even if there were no steps to send. This holds the
+      // final values until the end of the section, and keeps a pattern without any steps for the
+      // device from spinning.
+      select! {
+        _ = sleep(anchor.saturating_duration_since(Instant::now())).fuse() => {},
+        _ = token.cancelled().fuse() => return,
+      }
+    }
+    section_index += 1;
+    if section_index == pattern.sections.len() {
+      if !pattern.looping {
+        break;
+      }
+      section_index = 0;
+    }
+  }
+  // Only zero out on natural completion. When cancelled, whatever cancelled us (a stop, a new
+  // pattern, a new command) is now in charge of the device, and zeroing here would race it.
+  if let Err(err) = device_manager
+    .parse_message(pattern.zero_command(device_index).into())
+    .await
+  {
+    debug!(
+      "Could not zero device {} after pattern playback: {:?}",
+      device_index, err
+    );
+  }
+}
+
+struct PatternSession {
+  pattern: Arc<ResolvedPattern>,
+  playback: Option<CancellationToken>,
+}
+
+impl PatternSession {
+  fn stop(&mut self) {
+    if let Some(token) = self.playback.take() {
+      token.cancel();
+    }
+  }
+}
+
+/// Tracks loaded patterns and their playback tasks, one per device, as well as running
+/// [ScalarLoopCmd] loops.
+pub(super) struct PatternPlayer {
+  device_manager: Arc<ServerDeviceManager>,
+  sessions: DashMap<u32, PatternSession>,
+  loops: DashMap<u32, CancellationToken>,
+}
+
+impl PatternPlayer {
+  pub fn new(device_manager: Arc<ServerDeviceManager>) -> Self {
+    Self {
+      device_manager,
+      sessions: DashMap::new(),
+      loops: DashMap::new(),
+    }
+  }
+
+  fn spawn_playback(
+    &self,
+    device_index: u32,
+    pattern: Arc<ResolvedPattern>,
+    start_section: usize,
+  ) -> CancellationToken {
+    let token = CancellationToken::new();
+    async_manager::spawn(playback_loop(
+      self.device_manager.clone(),
+      device_index,
+      pattern,
+      start_section,
+      token.clone(),
+    ));
+    token
+  }
+
+  pub fn load(&self, msg: PatternLoadCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let Some(attrs) = self.device_manager.device_message_attributes(device_index) else {
+      return ButtplugDeviceError::DeviceNotAvailable(device_index).into();
+    };
+    let pattern = match msg.pattern().parse::<Pattern>() {
+      Ok(pattern) => pattern,
+      Err(err) => {
+        return ButtplugMessageError::InvalidMessageContents(format!(
+          "PatternLoadCmd pattern could not be loaded: {}",
+          err
+        ))
+        .into()
+      }
+    };
+    let Some(resolved) = ResolvedPattern::new(&pattern, &attrs) else {
+      return ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::ScalarCmd).into();
+    };
+    let session = PatternSession {
+      pattern: Arc::new(resolved),
+      playback: None,
+    };
+    if let Some(mut old_session) = self.sessions.insert(device_index, session) {
+      old_session.stop();
+    }
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  pub fn play(&self, msg: PatternPlayCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let Some(mut session) = self.sessions.get_mut(&device_index) else {
+      return ButtplugDeviceError::PatternNotLoaded(device_index).into();
+    };
+    let start_section = match msg.section() {
+      Some(label) => match session.pattern.labels.iter().position(|x| x == label) {
+        Some(index) => index,
+        None => {
+          return ButtplugMessageError::InvalidMessageContents(format!(
+            "PatternPlayCmd section {} does not exist in loaded pattern",
+            label
+          ))
+          .into()
+        }
+      },
+      None => 0,
+    };
+    session.stop();
+    self.stop_loop(device_index);
+    session.playback =
+      Some(self.spawn_playback(device_index, session.pattern.clone(), start_section));
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  pub fn stop(&self, msg: PatternStopCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let Some(mut session) = self.sessions.get_mut(&device_index) else {
+      return ButtplugDeviceError::PatternNotLoaded(device_index).into();
+    };
+    session.stop();
+    let zero_command = session.pattern.zero_command(device_index);
+    drop(session);
+    // The zero command replies with Ok on success, pass along any failure to the client.
+    self.device_manager.parse_message(zero_command.into())
+  }
+
+  pub fn play_loop(&self, msg: ScalarLoopCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let Some(attrs) = self.device_manager.device_message_attributes(device_index) else {
+      return ButtplugDeviceError::DeviceNotAvailable(device_index).into();
+    };
+    let Some(scalar_attrs) = attrs.scalar_cmd() else {
+      return ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::ScalarCmd).into();
+    };
+    // Check features up front, otherwise errors wouldn't show up until the loop task tries to run
+    // the step, and the client would never see them.
+    for scalar in msg.steps().iter().flat_map(|x| x.scalars()) {
+      let Some(attr) = scalar_attrs.get(scalar.index() as usize) else {
+        return ButtplugDeviceError::DeviceFeatureIndexError(
+          scalar_attrs.len() as u32,
+          scalar.index(),
+        )
+        .into();
+      };
+      if *attr.actuator_type() != scalar.actuator_type() {
+        return ButtplugDeviceError::DeviceActuatorTypeMismatch(
+          scalar.index().to_string(),
+          scalar.actuator_type(),
+          *attr.actuator_type(),
+        )
+        .into();
+      }
+    }
+    let Some(pattern) = ResolvedPattern::from_loop(&msg) else {
+      return ButtplugMessageError::InvalidMessageContents(
+        "ScalarLoopCmd total duration is too long".to_owned(),
+      )
+      .into();
+    };
+    self.stop_device(device_index);
+    let token = self.spawn_playback(device_index, Arc::new(pattern), 0);
+    self.loops.insert(device_index, token);
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  /// Cancel a running [ScalarLoopCmd] loop for a device, leaving actuators at their current values.
+  pub fn stop_loop(&self, device_index: u32) {
+    // This runs for every ScalarCmd, so avoid the write lock when there's no loop to stop.
+    if !self.loops.contains_key(&device_index) {
+      return;
+    }
+    if let Some((_, token)) = self.loops.remove(&device_index) {
+      token.cancel();
+    }
+  }
+
+  /// Stop pattern playback and loops for a device.
+  pub fn stop_device(&self, device_index: u32) {
+    if let Some(mut session) = self.sessions.get_mut(&device_index) {
+      session.stop();
+    }
+    self.stop_loop(device_index);
+  }
+
+  /// Stop pattern playback and loops on all devices.
+  pub fn stop_all(&self) {
+    for mut session in self.sessions.iter_mut() {
+      session.stop();
+    }
+    for token in self.loops.iter() {
+      token.cancel();
+    }
+    self.loops.clear();
+  }
+}
@@ -6,10 +6,10 @@
 // for full license information.
 
 use crate::util::{async_manager, sleep};
-use futures::{Future, FutureExt};
+use futures::{future, Future, FutureExt};
 use std::{
   sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU32, Ordering},
     Arc,
   },
   time::Duration,
@@ -18,48 +18,56 @@ use tokio::sync::{mpsc, Notify};
 
 pub enum PingMessage {
   Ping,
-  StartTimer,
+  /// Start the timer with the given ping time, in milliseconds. A ping time of 0 leaves the timer
+  /// stopped.
+  StartTimer(u32),
   StopTimer,
   End,
 }
 
 async fn ping_timer(
-  max_ping_time: u32,
   mut ping_msg_receiver: mpsc::Receiver<PingMessage>,
   notifier: Arc<Notify>,
   pinged_out_status: Arc<AtomicBool>,
 ) {
-  let mut started = false;
+  let mut max_ping_time = 0u32;
   let mut pinged = false;
   loop {
+    // Ping time is negotiated per connection, so we only know it once the timer is started.
+    let timeout = if max_ping_time > 0 {
+      sleep(Duration::from_millis(max_ping_time.into())).boxed()
+    } else {
+      future::pending().boxed()
+    };
     select! {
-      _ = sleep(Duration::from_millis(max_ping_time.into())).fuse() => {
-        if started {
-          if !pinged {
-            notifier.notify_waiters();
-            pinged_out_status.store(true, Ordering::SeqCst);
-            return;
-          }
-          pinged = false;
+      _ = timeout.fuse() => {
+        if !pinged {
+          pinged_out_status.store(true, Ordering::SeqCst);
+          notifier.notify_one();
+          return;
         }
+        pinged = false;
       }
       msg = ping_msg_receiver.recv().fuse() => {
-        if msg.is_none() {
-          return;
-        }
-        match msg.expect("Already checked") {
-          PingMessage::StartTimer => started = true,
-          PingMessage::StopTimer => started = false,
-          PingMessage::Ping => pinged = true,
-          PingMessage::End => break,
+        match msg {
+          Some(PingMessage::StartTimer(ping_time)) => {
+            max_ping_time = ping_time;
+            pinged = false;
+          }
+          Some(PingMessage::StopTimer) => max_ping_time = 0,
+          Some(PingMessage::Ping) => pinged = true,
+          Some(PingMessage::End) | None => break,
         }
       }
     };
   }
+  // Wake up anything waiting on a ping out, so it can exit.
+  notifier.notify_one();
 }
 
 pub struct PingTimer {
-  max_ping_time: u32,
+  /// Ping time the timer was last started with, 0 if the timer isn't running.
+  max_ping_time: Arc<AtomicU32>,
   ping_msg_sender: mpsc::Sender<PingMessage>,
   ping_timeout_notifier: Arc<Notify>,
   pinged_out: Arc<AtomicBool>,
@@ -70,6 +78,13 @@ impl Drop for PingTimer {
     // This cannot block, otherwise it will throw in WASM contexts on
     // destruction. We must use send(), not blocking_send().
     let sender = self.ping_msg_sender.clone();
+    if !async_manager::can_spawn() {
+      // Without a runtime to spawn on, all we can do is not wait for room in the channel.
+      if sender.try_send(PingMessage::End).is_err() {
+        debug!("Cannot end ping timer event loop, assuming it's already dead.");
+      }
+      return;
+    }
     async_manager::spawn(async move {
       if sender.send(PingMessage::End).await.is_err() {
         debug!("Receiver does not exist, assuming ping timer event loop already dead.");
@@ -79,57 +94,59 @@ impl Drop for PingTimer {
 }
 
 impl PingTimer {
-  pub fn new(max_ping_time: u32) -> Self {
+  pub fn new() -> Self {
     let ping_timeout_notifier = Arc::new(Notify::new());
     let (sender, receiver) = mpsc::channel(256);
     let pinged_out = Arc::new(AtomicBool::new(false));
-    if max_ping_time > 0 {
-      let fut = ping_timer(
-        max_ping_time,
-        receiver,
-        ping_timeout_notifier.clone(),
-        pinged_out.clone(),
-      );
-      async_manager::spawn(async move { fut.await });
-    }
+    let fut = ping_timer(receiver, ping_timeout_notifier.clone(), pinged_out.clone());
+    async_manager::spawn(async move { fut.await });
     Self {
-      max_ping_time,
+      max_ping_time: Arc::new(AtomicU32::new(0)),
       ping_msg_sender: sender,
       ping_timeout_notifier,
       pinged_out,
     }
   }
 
-  pub fn ping_timeout_waiter(&self) -> impl Future<Output = ()> {
+  /// Returns a future that resolves to true if the timer pinged out, or false if the timer was
+  /// dropped without pinging out.
+  pub fn ping_timeout_waiter(&self) -> impl Future<Output = bool> {
     let notify = self.ping_timeout_notifier.clone();
+    let pinged_out = self.pinged_out.clone();
     async move {
       notify.notified().await;
+      pinged_out.load(Ordering::SeqCst)
     }
   }
 
   fn send_ping_msg(&self, msg: PingMessage) -> impl Future<Output = ()> {
     let ping_msg_sender = self.ping_msg_sender.clone();
-    let max_ping_time = self.max_ping_time;
     async move {
-      if max_ping_time == 0 {
-        return;
-      }
       if ping_msg_sender.send(msg).await.is_err() {
         error!("Cannot ping, no event loop available.");
       }
     }
   }
 
-  pub fn start_ping_timer(&self) -> impl Future<Output = ()> {
+  /// Start the timer with the ping time negotiated for the connection. A ping time of 0 means the
+  /// connection has no ping timeout.
+  pub fn start_ping_timer(&self, max_ping_time: u32) -> impl Future<Output = ()> {
     // If we're starting the timer, clear our status.
     self.pinged_out.store(false, Ordering::SeqCst);
-    self.send_ping_msg(PingMessage::StartTimer)
+    self.max_ping_time.store(max_ping_time, Ordering::SeqCst);
+    self.send_ping_msg(PingMessage::StartTimer(max_ping_time))
   }
 
   pub fn stop_ping_timer(&self) -> impl Future<Output = ()> {
+    self.max_ping_time.store(0, Ordering::SeqCst);
     self.send_ping_msg(PingMessage::StopTimer)
   }
 
+  /// Ping time the timer is currently running with, 0 if it isn't running.
+  pub fn max_ping_time(&self) -> u32 {
+    self.max_ping_time.load(Ordering::SeqCst)
+  }
+
   pub fn update_ping_time(&self) -> impl Future<Output = ()> {
     self.send_ping_msg(PingMessage::Ping)
   }
@@ -0,0 +1,183 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Resumption tokens, letting clients reconnect to a session without it being cleaned up.
+
+use crate::util::{async_manager, sleep};
+use futures::{future::BoxFuture, FutureExt};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+const RESUMPTION_TOKEN_LENGTH: usize = 32;
+
+/// Cleanup waiting for the grace window to pass.
+struct PendingCleanup {
+  cancellation_token: CancellationToken,
+  cleanup: BoxFuture<'static, ()>,
+}
+
+/// Tracks the resumption token issued to a session's client, and holds off cleaning up the session
+/// after a disconnect until the grace window has passed without the client coming back.
+pub(super) struct SessionResumption {
+  /// Grace window, resumption is disabled if zero.
+  window: Duration,
+  token: Mutex<Option<String>>,
+  pending: Arc<Mutex<Option<PendingCleanup>>>,
+}
+
+impl SessionResumption {
+  pub fn new(window: Duration) -> Self {
+    Self {
+      window,
+      token: Mutex::new(None),
+      pending: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Issues a new token for the connected client, replacing any previous one. Returns None if
+  /// resumption is disabled.
+  pub fn issue_token(&self) -> Option<String> {
+    if self.window.is_zero() {
+      return None;
+    }
+    let token: String = thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(RESUMPTION_TOKEN_LENGTH)
+      .map(char::from)
+      .collect();
+    *self.token.lock().expect("Lock is never poisoned") = Some(token.clone());
+    Some(token)
+  }
+
+  /// Invalidates the current token, i.e. after a ping out, where the session is cleaned up right
+  /// away.
+  pub fn revoke_token(&self) {
+    *self.token.lock().expect("Lock is never poisoned") = None;
+  }
+
+  /// Runs `cleanup` once the grace window passes, unless the client resumes the session first.
+  /// Returns `cleanup` back if no token has been issued, in which case it should run right away.
+  pub fn defer_cleanup(
+    &self,
+    cleanup: BoxFuture<'static, ()>,
+  ) -> Result<(), BoxFuture<'static, ()>> {
+    if self.token.lock().expect("Lock is never poisoned").is_none() {
+      return Err(cleanup);
+    }
+    let cancellation_token = CancellationToken::new();
+    let previous = self
+      .pending
+      .lock()
+      .expect("Lock is never poisoned")
+      .replace(PendingCleanup {
+        cancellation_token: cancellation_token.clone(),
+        cleanup,
+      });
+    if let Some(previous) = previous {
+      previous.cancellation_token.cancel();
+    }
+    info!(
+      "Client disconnected, holding session for {:?} in case it reconnects.",
+      self.window
+    );
+    let pending = self.pending.clone();
+    let window = self.window;
+    async_manager::spawn(async move {
+      select! {
+        _ = cancellation_token.cancelled().fuse() => return,
+        _ = sleep(window).fuse() => {}
+      }
+      // Whoever takes the cleanup out first gets to decide what happens to it.
+      let cleanup = pending.lock().expect("Lock is never poisoned").take();
+      if let Some(cleanup) = cleanup {
+        info!("Client did not reconnect within the resumption window, cleaning up session.");
+        cleanup.cleanup.await;
+      }
+    });
+    Ok(())
+  }
+
+  /// Called on handshake. If `token` matches the last token issued and cleanup of the previous
+  /// connection is still pending, the cleanup is dropped and true is returned. Otherwise any
+  /// pending cleanup is run before returning false, so the new client starts from a clean session.
+  pub async fn resume(&self, token: Option<&str>) -> bool {
+    let pending = self.pending.lock().expect("Lock is never poisoned").take();
+    let Some(pending) = pending else {
+      return false;
+    };
+    pending.cancellation_token.cancel();
+    let matches = {
+      let current = self.token.lock().expect("Lock is never poisoned");
+      token.is_some() && current.as_deref() == token
+    };
+    if matches {
+      info!("Client presented a valid resumption token, resuming session.");
+      true
+    } else {
+      info!("New client connected, cleaning up the previous session.");
+      pending.cleanup.await;
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::atomic::{AtomicBool, Ordering};
+
+  fn cleanup_flag() -> (Arc<AtomicBool>, BoxFuture<'static, ()>) {
+    let flag = Arc::new(AtomicBool::new(false));
+    let flag_clone = flag.clone();
+    (
+      flag,
+      async move { flag_clone.store(true, Ordering::SeqCst) }.boxed(),
+    )
+  }
+
+  #[tokio::test]
+  async fn test_session_resumption() {
+    // Disabled, so cleanup should happen right away.
+    let resumption = SessionResumption::new(Duration::ZERO);
+    assert!(resumption.issue_token().is_none());
+    let (_, cleanup) = cleanup_flag();
+    assert!(resumption.defer_cleanup(cleanup).is_err());
+
+    let resumption = SessionResumption::new(Duration::from_secs(60));
+    let token = resumption
+      .issue_token()
+      .expect("Test, assuming infallible.");
+    let (cleaned_up, cleanup) = cleanup_flag();
+    assert!(resumption.defer_cleanup(cleanup).is_ok());
+    assert!(resumption.resume(Some(&token)).await);
+    assert!(!cleaned_up.load(Ordering::SeqCst));
+
+    // A wrong token runs the cleanup before the new client continues.
+    resumption.issue_token();
+    let (cleaned_up, cleanup) = cleanup_flag();
+    assert!(resumption.defer_cleanup(cleanup).is_ok());
+    assert!(!resumption.resume(Some("not-the-token")).await);
+    assert!(cleaned_up.load(Ordering::SeqCst));
+  }
+
+  #[tokio::test]
+  async fn test_session_resumption_window_expiry() {
+    let resumption = SessionResumption::new(Duration::from_millis(50));
+    let token = resumption
+      .issue_token()
+      .expect("Test, assuming infallible.");
+    let (cleaned_up, cleanup) = cleanup_flag();
+    assert!(resumption.defer_cleanup(cleanup).is_ok());
+    sleep(Duration::from_millis(200)).await;
+    assert!(cleaned_up.load(Ordering::SeqCst));
+    assert!(!resumption.resume(Some(&token)).await);
+  }
+}
@@ -0,0 +1,163 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side stroke generation for linear devices.
+//!
+//! [StrokeCmd] describes a stroke (depth range, speed and waveform) once, and the server turns it
+//! into a stream of [LinearCmd] moves until it's cancelled. Linear devices only understand "move to
+//! this position over this much time", so waveforms are approximated by a set of moves per stroke.
+
+use super::{device::ServerDeviceManager, ButtplugServerResultFuture};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{
+      self,
+      ButtplugDeviceMessage,
+      ButtplugDeviceMessageType,
+      LinearCmd,
+      StrokeCmd,
+      StrokeWaveform,
+      VectorSubcommand,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use dashmap::DashMap;
+use futures::{future, FutureExt};
+use instant::Instant;
+use std::{f64::consts::PI, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Number of moves used to approximate a sine stroke. Most linear devices take a while to process
+/// each move, so more than this just clogs up the command queue.
+const SINE_SEGMENTS: u32 = 12;
+
+/// A single move within a stroke. Times are fractions of the stroke period, and the target is a
+/// fraction of the stroke depth, with 0.0 at the stroke's min.
+#[derive(Debug, Clone, Copy)]
+struct StrokeSegment {
+  start: f64,
+  duration: f64,
+  target: f64,
+}
+
+fn stroke_segments(waveform: StrokeWaveform) -> Vec<StrokeSegment> {
+  let segment = |start, duration, target| StrokeSegment {
+    start,
+    duration,
+    target,
+  };
+  match waveform {
+    StrokeWaveform::Triangle => vec![segment(0.0, 0.5, 1.0), segment(0.5, 0.5, 0.0)],
+    // Moves take an eighth of the stroke, leaving the rest of each half to hold at the end.
+    StrokeWaveform::Square => vec![segment(0.0, 0.125, 1.0), segment(0.5, 0.125, 0.0)],
+    StrokeWaveform::Sine => {
+      let step = 1.0 / SINE_SEGMENTS as f64;
+      (0..SINE_SEGMENTS)
+        .map(|i| {
+          let end = (i + 1) as f64 * step;
+          segment(i as f64 * step, step, (1.0 - (2.0 * PI * end).cos()) / 2.0)
+        })
+        .collect()
+    }
+  }
+}
+
+async fn stroke_loop(
+  device_manager: Arc<ServerDeviceManager>,
+  msg: StrokeCmd,
+  feature_count: u32,
+  token: CancellationToken,
+) {
+  let device_index = msg.device_index();
+  let period = msg.period();
+  let segments = stroke_segments(msg.waveform());
+  // Move times are calculated from an anchor that advances by exact stroke periods, so slow command
+  // round trips don't cause strokes to drift.
+  let mut anchor = Instant::now();
+  loop {
+    for segment in &segments {
+      let target = anchor + Duration::from_secs_f64(segment.start * period / 1000.0);
+      select! {
+        _ = sleep(target.saturating_duration_since(Instant::now())).fuse() => {},
+        _ = token.cancelled().fuse() => return,
+      }
+      let position = msg.min() + (msg.max() - msg.min()) * segment.target;
+      let duration = (segment.duration * period) as u32;
+      let vectors = (0..feature_count)
+        .map(|i| VectorSubcommand::new(i, duration, position))
+        .collect();
+      if let Err(err) = device_manager
+        .parse_message(LinearCmd::new(device_index, vectors).into())
+        .await
+      {
+        // Most likely the device disconnected, nothing left to clean up.
+        warn!(
+          "Stroke generation for device {} failed, stopping: {:?}",
+          device_index, err
+        );
+        return;
+      }
+    }
+    anchor += Duration::from_secs_f64(period / 1000.0);
+  }
+}
+
+/// Tracks running [StrokeCmd] strokes, one per device.
+pub(super) struct StrokeGenerator {
+  device_manager: Arc<ServerDeviceManager>,
+  strokes: DashMap<u32, CancellationToken>,
+}
+
+impl StrokeGenerator {
+  pub fn new(device_manager: Arc<ServerDeviceManager>) -> Self {
+    Self {
+      device_manager,
+      strokes: DashMap::new(),
+    }
+  }
+
+  pub fn start(&self, msg: StrokeCmd) -> ButtplugServerResultFuture {
+    let device_index = msg.device_index();
+    let Some(attrs) = self.device_manager.device_message_attributes(device_index) else {
+      return ButtplugDeviceError::DeviceNotAvailable(device_index).into();
+    };
+    let Some(linear_attrs) = attrs.linear_cmd() else {
+      return ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::LinearCmd).into();
+    };
+    self.stop(device_index);
+    let token = CancellationToken::new();
+    async_manager::spawn(stroke_loop(
+      self.device_manager.clone(),
+      msg,
+      linear_attrs.len() as u32,
+      token.clone(),
+    ));
+    self.strokes.insert(device_index, token);
+    future::ready(Ok(message::Ok::default().into())).boxed()
+  }
+
+  /// Stop strokes for a device, leaving it wherever it currently is.
+  pub fn stop(&self, device_index: u32) {
+    // This runs for every LinearCmd, so avoid the write lock when there's nothing to stop.
+    if !self.strokes.contains_key(&device_index) {
+      return;
+    }
+    if let Some((_, token)) = self.strokes.remove(&device_index) {
+      token.cancel();
+    }
+  }
+
+  /// Stop strokes on all devices.
+  pub fn stop_all(&self) {
+    for token in self.strokes.iter() {
+      token.cancel();
+    }
+    self.strokes.clear();
+  }
+}
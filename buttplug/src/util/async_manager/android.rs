@@ -0,0 +1,54 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! JVM attachment for runtimes running inside Android apps.
+//!
+//! Anything that calls into Java (like btleplug's Android backend) needs to run on a thread that is
+//! attached to the JVM. Tokio creates its own worker threads, which the JVM knows nothing about, so
+//! each of them needs to be attached when it starts. Apps should call [set_java_vm] from
+//! `JNI_OnLoad` (or any other JNI entry point), then build their runtime with [runtime_builder], or
+//! call [attach_current_thread] from `on_thread_start` if they need to configure their own.
+
+use jni::{JNIEnv, JavaVM};
+use once_cell::sync::OnceCell;
+
+static JAVA_VM: OnceCell<JavaVM> = OnceCell::new();
+
+/// Store the JVM for the app, so runtime threads can be attached to it. Only the first call has any
+/// effect.
+pub fn set_java_vm(env: &JNIEnv) -> Result<(), jni::errors::Error> {
+  let vm = env.get_java_vm()?;
+  // If the VM was already set, it's the same VM, as Android only allows one per process.
+  let _ = JAVA_VM.set(vm);
+  Ok(())
+}
+
+/// Returns the JVM stored via [set_java_vm], if any.
+pub fn java_vm() -> Option<&'static JavaVM> {
+  JAVA_VM.get()
+}
+
+/// Attach the calling thread to the JVM for the rest of its lifetime. Does nothing (other than
+/// logging an error) if [set_java_vm] hasn't been called yet.
+pub fn attach_current_thread() {
+  let Some(vm) = JAVA_VM.get() else {
+    error!(
+      "Java VM not set, cannot attach thread. Call set_java_vm() before creating the runtime."
+    );
+    return;
+  };
+  if let Err(err) = vm.attach_current_thread_permanently() {
+    error!("Cannot attach thread to Java VM: {:?}", err);
+  }
+}
+
+/// Multithreaded tokio runtime builder, with all worker threads attached to the JVM as they start.
+pub fn runtime_builder() -> tokio::runtime::Builder {
+  let mut builder = tokio::runtime::Builder::new_multi_thread();
+  builder.enable_all().on_thread_start(attach_current_thread);
+  builder
+}
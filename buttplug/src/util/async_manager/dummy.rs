@@ -26,6 +26,18 @@ where
   unimplemented!("Dummy executor can't actually spawn!")
 }
 
+pub fn can_spawn() -> bool {
+  false
+}
+
+pub async fn spawn_blocking<F, R>(_: F) -> R
+where
+  F: FnOnce() -> R + Send + 'static,
+  R: Send + 'static,
+{
+  unimplemented!("Dummy executor can't actually spawn!")
+}
+
 pub fn spawn_with_handle<Fut>(_: Fut) -> Result<RemoteHandle<Fut::Output>, SpawnError>
 where
   Fut: Future + Send + 'static,
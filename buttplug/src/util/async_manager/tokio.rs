@@ -30,6 +30,23 @@ where
     .expect("Infallible, only returns result to match trait")
 }
 
+/// True if there's a runtime to spawn on from the current thread. Spawning panics otherwise, which
+/// matters in places we can't choose where we're called from, like Drop impls.
+pub fn can_spawn() -> bool {
+  tokio::runtime::Handle::try_current().is_ok()
+}
+
+/// Run blocking work (i.e. file IO) on a thread where it won't hold up other tasks.
+pub async fn spawn_blocking<F, R>(f: F) -> R
+where
+  F: FnOnce() -> R + Send + 'static,
+  R: Send + 'static,
+{
+  tokio::task::spawn_blocking(f)
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
+
 pub fn spawn_with_handle<Fut>(future: Fut) -> Result<RemoteHandle<Fut::Output>, SpawnError>
 where
   Fut: Future + Send + 'static,
@@ -29,6 +29,19 @@ where
   spawn_local(future);
 }
 
+pub fn can_spawn() -> bool {
+  true
+}
+
+/// There are no threads to move work to in wasm, so this just runs the work.
+pub async fn spawn_blocking<F, R>(f: F) -> R
+where
+  F: FnOnce() -> R + Send + 'static,
+  R: Send + 'static,
+{
+  f()
+}
+
 pub fn spawn_with_handle<Fut>(future: Fut) -> Result<RemoteHandle<Fut::Output>, SpawnError>
 where
   Fut: Future + Send + 'static,
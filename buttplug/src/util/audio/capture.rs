@@ -0,0 +1,178 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Audio capture via cpal, for feeding [AudioAnalyzer](super::AudioAnalyzer).
+//!
+//! System loopback capture ("whatever is playing right now") works differently on every platform.
+//! On Windows, WASAPI allows opening an input stream on an output device. On Linux, PulseAudio and
+//! PipeWire expose monitor sources as input devices. [AudioCapture::loopback] tries both, but other
+//! platforms (i.e. macOS) require a virtual loopback device to be installed and selected via
+//! [AudioCapture::from_device_name].
+
+use super::{AudioAnalyzer, AudioBand};
+use cpal::{
+  traits::{DeviceTrait, HostTrait, StreamTrait},
+  FromSample,
+  SampleFormat,
+  SizedSample,
+};
+use futures::Stream;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Number of sample blocks to buffer between the audio callback and the consumer. If the consumer
+/// falls further behind than this, blocks are dropped rather than stalling the audio thread.
+const CAPTURE_BUFFER_BLOCKS: usize = 32;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum AudioCaptureError {
+  #[error("No audio device found: {0}")]
+  NoDevice(String),
+  #[error("Could not get audio device configuration: {0}")]
+  ConfigurationError(String),
+  #[error("Unsupported audio sample format: {0}")]
+  UnsupportedSampleFormat(String),
+  #[error("Could not start audio stream: {0}")]
+  StreamError(String),
+}
+
+/// Running capture stream. Capture stops when this is dropped.
+pub struct AudioCapture {
+  // Never read, only held so the stream stays open.
+  _stream: cpal::Stream,
+  sample_rate: u32,
+  channels: u16,
+}
+
+impl AudioCapture {
+  /// Capture from the system default input device (usually a microphone).
+  pub fn default_input() -> Result<(Self, impl Stream<Item = Vec<f32>>), AudioCaptureError> {
+    let device = cpal::default_host()
+      .default_input_device()
+      .ok_or_else(|| AudioCaptureError::NoDevice("No default input device".to_owned()))?;
+    let config = device
+      .default_input_config()
+      .map_err(|e| AudioCaptureError::ConfigurationError(e.to_string()))?;
+    Self::start(&device, config)
+  }
+
+  /// Capture whatever the system is currently playing.
+  pub fn loopback() -> Result<(Self, impl Stream<Item = Vec<f32>>), AudioCaptureError> {
+    let host = cpal::default_host();
+    // Monitor sources show up as regular inputs on Linux.
+    if let Ok(mut inputs) = host.input_devices() {
+      if let Some(device) = inputs.find(|device| {
+        device
+          .name()
+          .map(|name| name.to_lowercase().contains("monitor"))
+          .unwrap_or(false)
+      }) {
+        let config = device
+          .default_input_config()
+          .map_err(|e| AudioCaptureError::ConfigurationError(e.to_string()))?;
+        return Self::start(&device, config);
+      }
+    }
+    // Otherwise try opening an input stream on the output device, which WASAPI treats as loopback.
+    let device = host
+      .default_output_device()
+      .ok_or_else(|| AudioCaptureError::NoDevice("No default output device".to_owned()))?;
+    let config = device
+      .default_output_config()
+      .map_err(|e| AudioCaptureError::ConfigurationError(e.to_string()))?;
+    Self::start(&device, config)
+  }
+
+  /// Capture from the first input device with a name containing `name`.
+  pub fn from_device_name(
+    name: &str,
+  ) -> Result<(Self, impl Stream<Item = Vec<f32>>), AudioCaptureError> {
+    let device = cpal::default_host()
+      .input_devices()
+      .map_err(|e| AudioCaptureError::NoDevice(e.to_string()))?
+      .find(|device| {
+        device
+          .name()
+          .map(|device_name| device_name.contains(name))
+          .unwrap_or(false)
+      })
+      .ok_or_else(|| AudioCaptureError::NoDevice(name.to_owned()))?;
+    let config = device
+      .default_input_config()
+      .map_err(|e| AudioCaptureError::ConfigurationError(e.to_string()))?;
+    Self::start(&device, config)
+  }
+
+  fn start(
+    device: &cpal::Device,
+    config: cpal::SupportedStreamConfig,
+  ) -> Result<(Self, impl Stream<Item = Vec<f32>>), AudioCaptureError> {
+    let (sender, receiver) = mpsc::channel(CAPTURE_BUFFER_BLOCKS);
+    let sample_format = config.sample_format();
+    let stream_config: cpal::StreamConfig = config.into();
+    let stream = match sample_format {
+      SampleFormat::F32 => build_stream::<f32>(device, &stream_config, sender),
+      SampleFormat::I16 => build_stream::<i16>(device, &stream_config, sender),
+      SampleFormat::U16 => build_stream::<u16>(device, &stream_config, sender),
+      SampleFormat::I32 => build_stream::<i32>(device, &stream_config, sender),
+      format => {
+        return Err(AudioCaptureError::UnsupportedSampleFormat(
+          format.to_string(),
+        ))
+      }
+    }?;
+    stream
+      .play()
+      .map_err(|e| AudioCaptureError::StreamError(e.to_string()))?;
+    Ok((
+      Self {
+        _stream: stream,
+        sample_rate: stream_config.sample_rate.0,
+        channels: stream_config.channels,
+      },
+      ReceiverStream::new(receiver),
+    ))
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  pub fn channels(&self) -> u16 {
+    self.channels
+  }
+
+  /// Create an analyzer matching the format of this capture stream.
+  pub fn analyzer(&self, bands: &[AudioBand]) -> AudioAnalyzer {
+    AudioAnalyzer::new(self.sample_rate, self.channels, bands)
+  }
+}
+
+fn build_stream<T>(
+  device: &cpal::Device,
+  config: &cpal::StreamConfig,
+  sender: mpsc::Sender<Vec<f32>>,
+) -> Result<cpal::Stream, AudioCaptureError>
+where
+  T: SizedSample,
+  f32: FromSample<T>,
+{
+  device
+    .build_input_stream(
+      config,
+      move |data: &[T], _: &cpal::InputCallbackInfo| {
+        let block = data.iter().map(|x| f32::from_sample_(*x)).collect();
+        if sender.try_send(block).is_err() {
+          trace!("Audio capture consumer is behind, dropping sample block.");
+        }
+      },
+      |err| error!("Audio capture stream error: {}", err),
+      None,
+    )
+    .map_err(|e| AudioCaptureError::StreamError(e.to_string()))
+}
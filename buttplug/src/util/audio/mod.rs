@@ -0,0 +1,545 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Audio to haptics analysis and routing.
+//!
+//! Driving devices from music or system audio is one of the most rebuilt features in the
+//! ecosystem, so the library ships a small pipeline for it:
+//!
+//! - [AudioAnalyzer] takes blocks of interleaved PCM samples and splits them into frequency
+//!   [AudioBand]s, following the amplitude envelope of each band and detecting beats.
+//! - [AudioRouter] maps the resulting [AudioFrame] to device actuators, using a list of
+//!   [AudioRoute]s that say which band (or the beat pulse) drives which actuator, and over what
+//!   range.
+//! - With the `client` feature, [run_audio_haptics] ties those together with a sample stream and a
+//!   [ButtplugClientDevice](crate::client::ButtplugClientDevice).
+//! - With the `audio-capture` feature, [capture] provides sample streams from system inputs or
+//!   loopback devices via cpal.
+//!
+//! None of the analysis requires an FFT, just biquad filters and envelope followers, so it is cheap
+//! enough to run on every sample block as it arrives.
+
+#[cfg(feature = "audio-capture")]
+pub mod capture;
+
+use crate::core::message::{ActuatorType, ScalarSubcommand};
+use std::{
+  collections::BTreeMap,
+  f32::consts::{FRAC_1_SQRT_2, PI},
+  time::Duration,
+};
+
+/// Frequency range to extract from the audio signal, in Hz.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioBand {
+  low: f32,
+  high: f32,
+}
+
+impl AudioBand {
+  /// Create a band covering `low` to `high` Hz. A `low` of 0 makes this a low pass band, and a `high`
+  /// at or above the nyquist frequency makes it a high pass band.
+  pub fn new(low: f32, high: f32) -> Self {
+    Self { low, high }
+  }
+
+  /// Kick drums and bass lines, usually what people want to feel.
+  pub fn bass() -> Self {
+    Self::new(0.0, 250.0)
+  }
+
+  pub fn mid() -> Self {
+    Self::new(250.0, 4000.0)
+  }
+
+  pub fn treble() -> Self {
+    Self::new(4000.0, f32::MAX)
+  }
+
+  pub fn low(&self) -> f32 {
+    self.low
+  }
+
+  pub fn high(&self) -> f32 {
+    self.high
+  }
+}
+
+/// Biquad filter, using the coefficient formulas from the RBJ audio EQ cookbook.
+#[derive(Debug, Clone)]
+struct Biquad {
+  b0: f32,
+  b1: f32,
+  b2: f32,
+  a1: f32,
+  a2: f32,
+  z1: f32,
+  z2: f32,
+}
+
+impl Biquad {
+  fn new(b: [f32; 3], a: [f32; 3]) -> Self {
+    Self {
+      b0: b[0] / a[0],
+      b1: b[1] / a[0],
+      b2: b[2] / a[0],
+      a1: a[1] / a[0],
+      a2: a[2] / a[0],
+      z1: 0.0,
+      z2: 0.0,
+    }
+  }
+
+  fn low_pass(frequency: f32, sample_rate: f32) -> Self {
+    let w0 = 2.0 * PI * frequency / sample_rate;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * FRAC_1_SQRT_2);
+    Self::new(
+      [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+      [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+    )
+  }
+
+  fn high_pass(frequency: f32, sample_rate: f32) -> Self {
+    let w0 = 2.0 * PI * frequency / sample_rate;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * FRAC_1_SQRT_2);
+    Self::new(
+      [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+      [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+    )
+  }
+
+  /// Filter chain for a band. Each edge gets a pair of cascaded butterworth filters (4th order
+  /// Linkwitz-Riley), otherwise bass leaks into the mid band badly enough to make it useless.
+  fn chain_for_band(band: &AudioBand, sample_rate: f32) -> Vec<Self> {
+    let nyquist = sample_rate / 2.0;
+    let mut chain = vec![];
+    if band.low > 0.0 && band.low < nyquist {
+      chain.push(Self::high_pass(band.low, sample_rate));
+      chain.push(Self::high_pass(band.low, sample_rate));
+    }
+    if band.high < nyquist {
+      chain.push(Self::low_pass(band.high, sample_rate));
+      chain.push(Self::low_pass(band.high, sample_rate));
+    }
+    chain
+  }
+
+  fn process(&mut self, x: f32) -> f32 {
+    let y = self.b0 * x + self.z1;
+    self.z1 = self.b1 * x - self.a1 * y + self.z2;
+    self.z2 = self.b2 * x - self.a2 * y;
+    y
+  }
+}
+
+/// Per sample smoothing coefficient for a one pole filter with the given time constant.
+fn time_coefficient(time: Duration, sample_rate: f32) -> f32 {
+  let samples = time.as_secs_f32() * sample_rate;
+  if samples <= 0.0 {
+    0.0
+  } else {
+    (-1.0 / samples).exp()
+  }
+}
+
+#[derive(Debug, Clone)]
+struct BandState {
+  filters: Vec<Biquad>,
+  envelope: f32,
+  peak: f32,
+}
+
+/// Analysis results for a single block of samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioFrame {
+  levels: Vec<f64>,
+  beat: bool,
+}
+
+impl AudioFrame {
+  /// Envelope level for each band (in the order bands were given to the analyzer), 0.0-1.0.
+  pub fn levels(&self) -> &[f64] {
+    &self.levels
+  }
+
+  /// True if a beat was detected in this block.
+  pub fn beat(&self) -> bool {
+    self.beat
+  }
+}
+
+/// Splits audio into bands and tracks their envelopes and beats.
+#[derive(Debug, Clone)]
+pub struct AudioAnalyzer {
+  sample_rate: f32,
+  channels: usize,
+  bands: Vec<BandState>,
+  attack: f32,
+  release: f32,
+  peak_decay: f32,
+  beat_band: usize,
+  beat_sensitivity: f32,
+  beat_average: f32,
+  samples_since_beat: usize,
+  min_beat_samples: usize,
+}
+
+impl AudioAnalyzer {
+  /// Create an analyzer for interleaved audio with the given sample rate and channel count. Channels
+  /// are mixed down to mono before analysis.
+  pub fn new(sample_rate: u32, channels: u16, bands: &[AudioBand]) -> Self {
+    let sample_rate = sample_rate as f32;
+    Self {
+      sample_rate,
+      channels: channels.max(1) as usize,
+      bands: bands
+        .iter()
+        .map(|band| BandState {
+          filters: Biquad::chain_for_band(band, sample_rate),
+          envelope: 0.0,
+          peak: 0.0,
+        })
+        .collect(),
+      attack: time_coefficient(Duration::from_millis(10), sample_rate),
+      release: time_coefficient(Duration::from_millis(150), sample_rate),
+      peak_decay: time_coefficient(Duration::from_secs(5), sample_rate),
+      beat_band: 0,
+      beat_sensitivity: 1.5,
+      beat_average: 0.0,
+      samples_since_beat: usize::MAX,
+      min_beat_samples: (sample_rate * 0.1) as usize,
+    }
+  }
+
+  /// Set how quickly band levels rise and fall. Defaults to 10ms attack, 150ms release.
+  pub fn with_envelope(mut self, attack: Duration, release: Duration) -> Self {
+    self.attack = time_coefficient(attack, self.sample_rate);
+    self.release = time_coefficient(release, self.sample_rate);
+    self
+  }
+
+  /// Set which band is used for beat detection, and how far above its running average energy a
+  /// block has to be to count as a beat. Defaults to the first band, and 1.5x.
+  pub fn with_beat_detection(mut self, band: usize, sensitivity: f32) -> Self {
+    self.beat_band = band;
+    self.beat_sensitivity = sensitivity;
+    self
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate as u32
+  }
+
+  pub fn channels(&self) -> u16 {
+    self.channels as u16
+  }
+
+  /// Process a block of interleaved samples. Levels are normalized against a slowly decaying peak
+  /// for each band, so quiet and loud sources both use the full output range.
+  pub fn process(&mut self, samples: &[f32]) -> AudioFrame {
+    let mut beat_energy = 0.0;
+    let mut frames = 0;
+    for frame in samples.chunks(self.channels) {
+      let mono = frame.iter().sum::<f32>() / frame.len() as f32;
+      for (index, band) in self.bands.iter_mut().enumerate() {
+        let filtered = band
+          .filters
+          .iter_mut()
+          .fold(mono, |sample, filter| filter.process(sample));
+        if index == self.beat_band {
+          beat_energy += filtered * filtered;
+        }
+        let rectified = filtered.abs();
+        let coefficient = if rectified > band.envelope {
+          self.attack
+        } else {
+          self.release
+        };
+        band.envelope = coefficient * band.envelope + (1.0 - coefficient) * rectified;
+        band.peak = band.envelope.max(band.peak * self.peak_decay);
+      }
+      frames += 1;
+    }
+    self.samples_since_beat = self.samples_since_beat.saturating_add(frames);
+
+    let mut beat = false;
+    if frames > 0 && self.beat_band < self.bands.len() {
+      let energy = beat_energy / frames as f32;
+      if energy > self.beat_average * self.beat_sensitivity
+        && energy > f32::EPSILON
+        && self.samples_since_beat >= self.min_beat_samples
+      {
+        beat = true;
+        self.samples_since_beat = 0;
+      }
+      // Running average of block energy over roughly the last second.
+      let smoothing =
+        time_coefficient(Duration::from_secs(1), self.sample_rate).powi(frames as i32);
+      self.beat_average = smoothing * self.beat_average + (1.0 - smoothing) * energy;
+    }
+
+    AudioFrame {
+      levels: self
+        .bands
+        .iter()
+        .map(|band| {
+          if band.peak > 1e-4 {
+            (band.envelope / band.peak).clamp(0.0, 1.0) as f64
+          } else {
+            0.0
+          }
+        })
+        .collect(),
+      beat,
+    }
+  }
+}
+
+/// Part of an [AudioFrame] to drive an actuator from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioRouteSource {
+  /// Envelope level of the band at this index.
+  Band(usize),
+  /// Full output on frames with a beat, minimum output otherwise.
+  Beat,
+}
+
+/// Mapping from part of the analyzed audio to a scalar actuator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioRoute {
+  source: AudioRouteSource,
+  feature_index: u32,
+  actuator_type: ActuatorType,
+  min: f64,
+  max: f64,
+}
+
+impl AudioRoute {
+  /// Route `source` to the scalar feature at `feature_index`, using the full 0.0-1.0 range.
+  pub fn new(source: AudioRouteSource, feature_index: u32, actuator_type: ActuatorType) -> Self {
+    Self {
+      source,
+      feature_index,
+      actuator_type,
+      min: 0.0,
+      max: 1.0,
+    }
+  }
+
+  /// Scale output to a range inside of 0.0-1.0, i.e. to keep a vibrator from ever fully stopping.
+  pub fn with_range(mut self, min: f64, max: f64) -> Self {
+    self.min = min.clamp(0.0, 1.0);
+    self.max = max.clamp(0.0, 1.0);
+    self
+  }
+
+  pub fn source(&self) -> AudioRouteSource {
+    self.source
+  }
+
+  pub fn feature_index(&self) -> u32 {
+    self.feature_index
+  }
+
+  pub fn actuator_type(&self) -> ActuatorType {
+    self.actuator_type
+  }
+}
+
+/// Turns [AudioFrame]s into scalar commands using a set of [AudioRoute]s.
+#[derive(Debug, Clone, Default)]
+pub struct AudioRouter {
+  routes: Vec<AudioRoute>,
+}
+
+impl AudioRouter {
+  pub fn new(routes: &[AudioRoute]) -> Self {
+    Self {
+      routes: routes.to_vec(),
+    }
+  }
+
+  pub fn routes(&self) -> &[AudioRoute] {
+    &self.routes
+  }
+
+  /// Scalar values for every routed feature. If multiple routes drive the same feature, the highest
+  /// value wins.
+  pub fn scalars(&self, frame: &AudioFrame) -> Vec<ScalarSubcommand> {
+    let mut values: BTreeMap<u32, (f64, ActuatorType)> = BTreeMap::new();
+    for route in &self.routes {
+      let level = match route.source {
+        AudioRouteSource::Band(band) => frame.levels.get(band).copied().unwrap_or(0.0),
+        AudioRouteSource::Beat => {
+          if frame.beat {
+            1.0
+          } else {
+            0.0
+          }
+        }
+      };
+      let value = route.min + (route.max - route.min) * level;
+      let entry = values
+        .entry(route.feature_index)
+        .or_insert((value, route.actuator_type));
+      if value > entry.0 {
+        *entry = (value, route.actuator_type);
+      }
+    }
+    values
+      .into_iter()
+      .map(|(index, (value, actuator_type))| ScalarSubcommand::new(index, value, actuator_type))
+      .collect()
+  }
+
+  /// Commands to zero every routed feature.
+  pub fn zero_scalars(&self) -> Vec<ScalarSubcommand> {
+    self
+      .scalars(&AudioFrame {
+        levels: vec![],
+        beat: false,
+      })
+      .into_iter()
+      .map(|x| ScalarSubcommand::new(x.index(), 0.0, x.actuator_type()))
+      .collect()
+  }
+}
+
+/// Drive a client device from a stream of interleaved sample blocks until the stream ends, then
+/// zero all routed features.
+///
+/// Updates are sent at most once per `update_interval`, and only when a value has changed, so
+/// sample block size doesn't affect how much traffic goes to the device.
+#[cfg(feature = "client")]
+pub async fn run_audio_haptics<S>(
+  device: &crate::client::ButtplugClientDevice,
+  samples: S,
+  mut analyzer: AudioAnalyzer,
+  router: AudioRouter,
+  update_interval: Duration,
+) -> Result<(), crate::client::ButtplugClientError>
+where
+  S: futures::Stream<Item = Vec<f32>>,
+{
+  use crate::client::ScalarCommand;
+  use futures::StreamExt;
+  use instant::Instant;
+  use std::collections::HashMap;
+
+  fn to_command(scalars: &[ScalarSubcommand]) -> ScalarCommand {
+    ScalarCommand::ScalarMap(
+      scalars
+        .iter()
+        .map(|x| (x.index(), (x.scalar(), x.actuator_type())))
+        .collect::<HashMap<_, _>>(),
+    )
+  }
+
+  futures::pin_mut!(samples);
+  let mut last_sent: Option<(Instant, Vec<ScalarSubcommand>)> = None;
+  // Beats only last a single block, so hold on to them until the next send or they'll be missed
+  // whenever blocks are shorter than the update interval.
+  let mut pending_beat = false;
+  while let Some(block) = samples.next().await {
+    let mut frame = analyzer.process(&block);
+    pending_beat |= frame.beat;
+    if let Some((sent_at, _)) = &last_sent {
+      if sent_at.elapsed() < update_interval {
+        continue;
+      }
+    }
+    frame.beat = pending_beat;
+    pending_beat = false;
+    let scalars = router.scalars(&frame);
+    if scalars.is_empty() || matches!(&last_sent, Some((_, last)) if *last == scalars) {
+      continue;
+    }
+    device.scalar(&to_command(&scalars)).await?;
+    last_sent = Some((Instant::now(), scalars));
+  }
+  let zero = router.zero_scalars();
+  if !zero.is_empty() {
+    device.scalar(&to_command(&zero)).await?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn sine(frequency: f32, sample_rate: u32, samples: usize, amplitude: f32) -> Vec<f32> {
+    (0..samples)
+      .map(|i| amplitude * (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin())
+      .collect()
+  }
+
+  #[test]
+  fn test_band_separation() {
+    let mut analyzer = AudioAnalyzer::new(
+      44100,
+      1,
+      &[AudioBand::bass(), AudioBand::mid(), AudioBand::treble()],
+    );
+    // Warm up on a mixed signal so every band has seen a peak, then feed bass only.
+    let mixed: Vec<f32> = sine(60.0, 44100, 44100, 0.5)
+      .iter()
+      .zip(sine(1000.0, 44100, 44100, 0.5))
+      .zip(sine(8000.0, 44100, 44100, 0.5))
+      .map(|((a, b), c)| a + b + c)
+      .collect();
+    analyzer.process(&mixed);
+    // Long enough for the mid and treble envelopes to fully release.
+    let frame = analyzer.process(&sine(60.0, 44100, 44100, 0.5));
+    assert!(frame.levels()[0] > 0.5);
+    assert!(frame.levels()[1] < 0.2);
+    assert!(frame.levels()[2] < 0.2);
+  }
+
+  #[test]
+  fn test_beat_detection() {
+    let mut analyzer = AudioAnalyzer::new(44100, 2, &[AudioBand::bass()]);
+    // Silence, then a sudden kick.
+    assert!(!analyzer.process(&vec![0.0; 8820]).beat());
+    let kick: Vec<f32> = sine(60.0, 44100, 2205, 1.0)
+      .into_iter()
+      .flat_map(|x| [x, x])
+      .collect();
+    assert!(analyzer.process(&kick).beat());
+    // Sustained signal at the same level shouldn't keep triggering.
+    analyzer.process(&kick);
+    analyzer.process(&kick);
+    assert!(!analyzer.process(&kick).beat());
+  }
+
+  #[test]
+  fn test_router() {
+    let router = AudioRouter::new(&[
+      AudioRoute::new(AudioRouteSource::Band(0), 0, ActuatorType::Vibrate).with_range(0.2, 0.8),
+      AudioRoute::new(AudioRouteSource::Band(1), 1, ActuatorType::Vibrate),
+      AudioRoute::new(AudioRouteSource::Beat, 1, ActuatorType::Vibrate),
+    ]);
+    let frame = AudioFrame {
+      levels: vec![0.5, 0.25],
+      beat: false,
+    };
+    let scalars = router.scalars(&frame);
+    assert_eq!(scalars.len(), 2);
+    assert!((scalars[0].scalar() - 0.5).abs() < f64::EPSILON);
+    assert_eq!(scalars[1].scalar(), 0.25);
+    let frame = AudioFrame {
+      levels: vec![0.0, 0.25],
+      beat: true,
+    };
+    let scalars = router.scalars(&frame);
+    assert!((scalars[0].scalar() - 0.2).abs() < f64::EPSILON);
+    assert_eq!(scalars[1].scalar(), 1.0);
+    assert!(router.zero_scalars().iter().all(|x| x.scalar() == 0.0));
+  }
+}
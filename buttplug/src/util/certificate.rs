@@ -0,0 +1,368 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Self signed certificate management, for serving secure websockets on local networks.
+//!
+//! Browsers won't let pages served over https connect to insecure websockets, so servers that want
+//! to be reachable from those pages need a certificate. There's no certificate authority that will
+//! sign for a LAN address, so this module generates self signed certificates covering localhost and
+//! every LAN IP of the machine, stores them, and regenerates them when they're about to expire or
+//! the machine's addresses change. Clients can check the
+//! [fingerprint](SelfSignedCertificate::fingerprint) of the certificate instead of a chain of
+//! trust.
+//!
+//! Certificates are used by
+//! [ButtplugWebsocketServerTransportBuilder::tls_certificate](crate::core::connector::ButtplugWebsocketServerTransportBuilder::tls_certificate)
+//! and the websocket device manager.
+
+use getset::{CopyGetters, Getters};
+use rcgen::{CertificateParams, DnType, KeyPair};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  fmt,
+  fs,
+  net::IpAddr,
+  path::Path,
+  sync::Arc,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+/// How long generated certificates are valid for.
+pub const DEFAULT_CERTIFICATE_VALIDITY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+/// How long before expiry [load_or_generate_certificate] replaces certificates by default.
+pub const DEFAULT_CERTIFICATE_ROTATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+const CERTIFICATE_FILE: &str = "certificate.pem";
+const PRIVATE_KEY_FILE: &str = "private-key.pem";
+const CERTIFICATE_INFO_FILE: &str = "certificate.json";
+
+#[derive(Error, Debug)]
+pub enum ButtplugCertificateError {
+  /// Certificate could not be generated.
+  #[error("Cannot generate certificate: {0}")]
+  GenerationError(String),
+  /// Certificate could not be read from or written to disk.
+  #[error("Cannot access certificate storage: {0}")]
+  StorageError(String),
+  /// Certificate or private key is not valid.
+  #[error("Invalid certificate: {0}")]
+  InvalidCertificate(String),
+}
+
+/// Information that can't easily be read back out of the certificate, stored next to it.
+#[derive(Serialize, Deserialize)]
+struct CertificateInfo {
+  #[serde(rename = "not-after")]
+  not_after: u64,
+  #[serde(rename = "subject-alt-names")]
+  subject_alt_names: Vec<String>,
+}
+
+/// Names and addresses a certificate for this machine should cover: localhost, plus the IP of every
+/// network interface.
+pub fn lan_subject_alt_names() -> Vec<String> {
+  let mut names = vec![
+    "localhost".to_owned(),
+    "127.0.0.1".to_owned(),
+    "::1".to_owned(),
+  ];
+  match if_addrs::get_if_addrs() {
+    Ok(interfaces) => {
+      for interface in interfaces {
+        let ip = interface.ip();
+        // IPv6 link local addresses need a scope id to be used, which can't go in a certificate.
+        let link_local = matches!(ip, IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80);
+        let name = ip.to_string();
+        if !ip.is_loopback() && !link_local && !names.contains(&name) {
+          names.push(name);
+        }
+      }
+    }
+    Err(err) => warn!("Cannot list network interfaces for certificate: {}", err),
+  }
+  names
+}
+
+/// A self signed certificate and its private key.
+#[derive(Clone, Getters, CopyGetters)]
+pub struct SelfSignedCertificate {
+  /// PEM encoded certificate, for importing into browsers or operating system trust stores.
+  #[getset(get = "pub")]
+  certificate_pem: String,
+  /// PEM encoded private key.
+  #[getset(get = "pub")]
+  private_key_pem: String,
+  /// DNS names and IPs the certificate is valid for.
+  #[getset(get = "pub")]
+  subject_alt_names: Vec<String>,
+  /// When the certificate expires.
+  #[getset(get_copy = "pub")]
+  not_after: SystemTime,
+  certificate_der: Vec<u8>,
+  private_key_der: Vec<u8>,
+}
+
+impl SelfSignedCertificate {
+  /// Generate a new certificate, valid for the given DNS names and IPs.
+  pub fn generate(
+    subject_alt_names: &[String],
+    validity: Duration,
+  ) -> Result<Self, ButtplugCertificateError> {
+    let generation_error =
+      |err: rcgen::Error| ButtplugCertificateError::GenerationError(err.to_string());
+    let key_pair = KeyPair::generate().map_err(generation_error)?;
+    let mut params =
+      CertificateParams::new(subject_alt_names.to_vec()).map_err(generation_error)?;
+    params
+      .distinguished_name
+      .push(DnType::CommonName, "Buttplug Server");
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + validity;
+    let certificate = params.self_signed(&key_pair).map_err(generation_error)?;
+    Ok(Self {
+      certificate_pem: certificate.pem(),
+      private_key_pem: key_pair.serialize_pem(),
+      subject_alt_names: subject_alt_names.to_vec(),
+      not_after: SystemTime::from(now + validity),
+      certificate_der: certificate.der().to_vec(),
+      private_key_der: key_pair.serialize_der(),
+    })
+  }
+
+  /// Generate a new certificate covering [lan_subject_alt_names].
+  pub fn generate_for_lan(validity: Duration) -> Result<Self, ButtplugCertificateError> {
+    Self::generate(&lan_subject_alt_names(), validity)
+  }
+
+  /// Load a certificate previously stored with [SelfSignedCertificate::save].
+  pub fn load(directory: &Path) -> Result<Self, ButtplugCertificateError> {
+    let read = |file: &str| {
+      fs::read_to_string(directory.join(file)).map_err(|err| {
+        ButtplugCertificateError::StorageError(format!(
+          "Cannot read {}: {}",
+          directory.join(file).display(),
+          err
+        ))
+      })
+    };
+    let certificate_pem = read(CERTIFICATE_FILE)?;
+    let private_key_pem = read(PRIVATE_KEY_FILE)?;
+    let info: CertificateInfo = serde_json::from_str(&read(CERTIFICATE_INFO_FILE)?)
+      .map_err(|err| ButtplugCertificateError::InvalidCertificate(err.to_string()))?;
+    let certificate_der = pem::parse(&certificate_pem)
+      .map_err(|err| ButtplugCertificateError::InvalidCertificate(err.to_string()))?
+      .into_contents();
+    let private_key_der = KeyPair::from_pem(&private_key_pem)
+      .map_err(|err| ButtplugCertificateError::InvalidCertificate(err.to_string()))?
+      .serialize_der();
+    Ok(Self {
+      certificate_pem,
+      private_key_pem,
+      subject_alt_names: info.subject_alt_names,
+      not_after: UNIX_EPOCH + Duration::from_secs(info.not_after),
+      certificate_der,
+      private_key_der,
+    })
+  }
+
+  /// Store the certificate in `directory`, creating it if needed. The certificate is stored as
+  /// `certificate.pem` and the key as `private-key.pem`, so they can be used by other tools too.
+  pub fn save(&self, directory: &Path) -> Result<(), ButtplugCertificateError> {
+    let storage_error = |err: std::io::Error| {
+      ButtplugCertificateError::StorageError(format!("{}: {}", directory.display(), err))
+    };
+    fs::create_dir_all(directory).map_err(storage_error)?;
+    let info = CertificateInfo {
+      not_after: self
+        .not_after
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs(),
+      subject_alt_names: self.subject_alt_names.clone(),
+    };
+    fs::write(directory.join(CERTIFICATE_FILE), &self.certificate_pem).map_err(storage_error)?;
+    write_private_file(
+      &directory.join(PRIVATE_KEY_FILE),
+      self.private_key_pem.as_bytes(),
+    )
+    .map_err(storage_error)?;
+    fs::write(
+      directory.join(CERTIFICATE_INFO_FILE),
+      serde_json::to_string_pretty(&info).expect("Type is always serializable"),
+    )
+    .map_err(storage_error)
+  }
+
+  /// SHA-256 fingerprint of the certificate, as colon separated hex (i.e. `AB:CD:...`), the format
+  /// browsers show it in. Clients can use this to pin the certificate.
+  pub fn fingerprint(&self) -> String {
+    Sha256::digest(&self.certificate_der)
+      .iter()
+      .map(|b| format!("{:02X}", b))
+      .collect::<Vec<String>>()
+      .join(":")
+  }
+
+  /// True if the certificate has expired, or will within `duration`.
+  pub fn expires_within(&self, duration: Duration) -> bool {
+    SystemTime::now() + duration >= self.not_after
+  }
+
+  /// True if the certificate is valid for all of `names`.
+  pub fn covers(&self, names: &[String]) -> bool {
+    names
+      .iter()
+      .all(|name| self.subject_alt_names.contains(name))
+  }
+
+  /// TLS configuration for servers using this certificate.
+  pub(crate) fn server_config(
+    &self,
+  ) -> Result<Arc<rustls::ServerConfig>, ButtplugCertificateError> {
+    let config = rustls::ServerConfig::builder()
+      .with_no_client_auth()
+      .with_single_cert(
+        vec![rustls::pki_types::CertificateDer::from(
+          self.certificate_der.clone(),
+        )],
+        rustls::pki_types::PrivateKeyDer::Pkcs8(self.private_key_der.clone().into()),
+      )
+      .map_err(|err| ButtplugCertificateError::InvalidCertificate(err.to_string()))?;
+    Ok(Arc::new(config))
+  }
+}
+
+impl fmt::Debug for SelfSignedCertificate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SelfSignedCertificate")
+      .field("fingerprint", &self.fingerprint())
+      .field("subject_alt_names", &self.subject_alt_names)
+      .field("not_after", &self.not_after)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(unix)]
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+  use std::{io::Write, os::unix::fs::OpenOptionsExt};
+  // Only the owner should be able to read the key.
+  fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(path)?
+    .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+  fs::write(path, contents)
+}
+
+/// Load the certificate stored in `directory`, replacing it with a newly generated one if there
+/// isn't one, it expires within `rotate_before`, or it doesn't cover all of the machine's current
+/// addresses (i.e. the machine got a new IP).
+pub fn load_or_generate_certificate(
+  directory: &Path,
+  rotate_before: Duration,
+) -> Result<SelfSignedCertificate, ButtplugCertificateError> {
+  let names = lan_subject_alt_names();
+  match SelfSignedCertificate::load(directory) {
+    Ok(certificate) if !certificate.expires_within(rotate_before) && certificate.covers(&names) => {
+      return Ok(certificate);
+    }
+    Ok(_) => info!("Stored certificate is expiring or out of date, generating a new one."),
+    Err(err) => info!(
+      "No usable stored certificate ({}), generating a new one.",
+      err
+    ),
+  }
+  let certificate = SelfSignedCertificate::generate(&names, DEFAULT_CERTIFICATE_VALIDITY)?;
+  certificate.save(directory)?;
+  info!(
+    "Generated certificate with fingerprint {}",
+    certificate.fingerprint()
+  );
+  Ok(certificate)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn test_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!(
+      "buttplug-certificate-test-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+  }
+
+  #[test]
+  fn test_generate_certificate() {
+    let names = vec!["localhost".to_owned(), "192.168.1.20".to_owned()];
+    let certificate =
+      SelfSignedCertificate::generate(&names, DEFAULT_CERTIFICATE_VALIDITY).unwrap();
+    assert!(certificate
+      .certificate_pem()
+      .starts_with("-----BEGIN CERTIFICATE-----"));
+    assert!(certificate.covers(&names));
+    assert!(!certificate.covers(&["192.168.1.21".to_owned()]));
+    assert!(!certificate.expires_within(Duration::from_secs(60)));
+    assert!(certificate.expires_within(DEFAULT_CERTIFICATE_VALIDITY * 2));
+    // 32 bytes, as hex pairs separated by colons.
+    assert_eq!(certificate.fingerprint().len(), 32 * 3 - 1);
+    assert!(certificate.server_config().is_ok());
+    assert!(!format!("{:?}", certificate).contains("PRIVATE KEY"));
+  }
+
+  #[test]
+  fn test_certificate_storage_and_rotation() {
+    let directory = test_directory("rotation");
+    assert!(SelfSignedCertificate::load(&directory).is_err());
+
+    let certificate =
+      load_or_generate_certificate(&directory, DEFAULT_CERTIFICATE_ROTATION).unwrap();
+    let loaded = SelfSignedCertificate::load(&directory).unwrap();
+    assert_eq!(certificate.fingerprint(), loaded.fingerprint());
+    assert_eq!(certificate.subject_alt_names(), loaded.subject_alt_names());
+    assert!(loaded.server_config().is_ok());
+
+    // Still valid, so it's reused.
+    let reused = load_or_generate_certificate(&directory, DEFAULT_CERTIFICATE_ROTATION).unwrap();
+    assert_eq!(certificate.fingerprint(), reused.fingerprint());
+
+    // Expiring within the rotation window, so it's replaced.
+    let rotated =
+      load_or_generate_certificate(&directory, DEFAULT_CERTIFICATE_VALIDITY * 2).unwrap();
+    assert_ne!(certificate.fingerprint(), rotated.fingerprint());
+    assert_eq!(
+      rotated.fingerprint(),
+      SelfSignedCertificate::load(&directory)
+        .unwrap()
+        .fingerprint()
+    );
+
+    // Missing addresses also cause a new certificate.
+    SelfSignedCertificate::generate(&["localhost".to_owned()], DEFAULT_CERTIFICATE_VALIDITY)
+      .unwrap()
+      .save(&directory)
+      .unwrap();
+    let regenerated =
+      load_or_generate_certificate(&directory, DEFAULT_CERTIFICATE_ROTATION).unwrap();
+    assert!(regenerated.covers(&lan_subject_alt_names()));
+    let _ = fs::remove_dir_all(&directory);
+  }
+}
@@ -7,14 +7,22 @@
 
 use super::json::JSONValidator;
 use crate::{
-  core::errors::ButtplugDeviceError,
+  core::errors::{
+    ButtplugDeviceError,
+    DeviceConfigurationIssue,
+    DeviceConfigurationValidationError,
+  },
   server::device::{
     configuration::{
       BluetoothLESpecifier,
+      ButtplugFederationSpecifier,
       DeviceConfigurationManager,
       DeviceConfigurationManagerBuilder,
+      GpioSpecifier,
       HIDSpecifier,
+      LinearMotionLimits,
       LovenseConnectServiceSpecifier,
+      MidiSpecifier,
       ProtocolAttributesIdentifier,
       ProtocolAttributesType,
       ProtocolCommunicationSpecifier,
@@ -29,8 +37,10 @@ use crate::{
   },
 };
 use getset::{CopyGetters, Getters, MutGetters, Setters};
+use jsonschema::error::{TypeKind, ValidationErrorKind};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt::Display, ops::RangeInclusive};
+use uuid::Uuid;
 
 pub static DEVICE_CONFIGURATION_JSON: &str =
   include_str!("../../buttplug-device-config/buttplug-device-config.json");
@@ -93,6 +103,35 @@ pub struct UserDeviceConfig {
   #[serde(skip_serializing_if = "Option::is_none")]
   #[serde(default)]
   index: Option<u32>,
+  /// Free form key/value data for frontends, i.e. icon, color or notes.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  metadata: Option<HashMap<String, String>>,
+  /// Allow raw messages for this device only.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "allow-raw-messages")]
+  allow_raw_messages: Option<bool>,
+  /// Milliseconds between battery readings sent to clients, overriding the protocol configuration.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "battery-polling-interval")]
+  battery_polling_interval: Option<u32>,
+  /// Mechanical limits for linear movement, overriding the protocol configuration.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "linear-motion-limits")]
+  linear_motion_limits: Option<LinearMotionLimits>,
+  /// Highest heater level (0.0-1.0), can only lower the limit from the protocol configuration.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "temperature-limit")]
+  temperature_limit: Option<f64>,
+  /// Most commands per second sent to the device, overriding the protocol configuration.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "max-command-rate")]
+  max_command_rate: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
@@ -104,6 +143,82 @@ pub struct ProtocolAttributes {
   name: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   messages: Option<ServerDeviceMessageAttributes>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "battery-polling-interval")]
+  battery_polling_interval: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "linear-motion-limits")]
+  linear_motion_limits: Option<LinearMotionLimits>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "temperature-limit")]
+  temperature_limit: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "max-command-rate")]
+  max_command_rate: Option<u32>,
+}
+
+/// Bluetooth devices sold under another brand that are the same hardware as a device the protocol
+/// already supports. Rebrands match the protocol by their advertised names or services, and get the
+/// attributes of the configuration they're a rebrand of.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
+pub struct ProtocolRebrand {
+  /// Advertised names of the rebranded devices. Devices are also identified by name, so these pick
+  /// the attributes the device gets.
+  #[serde(default)]
+  names: Vec<String>,
+  /// Advertised service UUIDs of the rebranded devices.
+  #[serde(default, rename = "advertised-services")]
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  advertised_services: Vec<Uuid>,
+  /// Identifier of the configuration these are rebrands of. If unset, the protocol defaults.
+  #[serde(default, rename = "base-identifier")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  base_identifier: Option<String>,
+  /// Name to use for the rebranded devices, instead of the name of the base configuration.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  name: Option<String>,
+}
+
+impl ProtocolRebrand {
+  fn base_attributes_type(&self) -> ProtocolAttributesType {
+    self
+      .base_identifier
+      .clone()
+      .map_or(ProtocolAttributesType::Default, |identifier| {
+        ProtocolAttributesType::Identifier(identifier)
+      })
+  }
+
+  /// Specifier matching the rebranded devices, added to the protocol's bluetooth specifiers.
+  fn specifier(&self) -> BluetoothLESpecifier {
+    BluetoothLESpecifier::new(
+      self.names.iter().cloned().collect(),
+      vec![],
+      self.advertised_services.iter().copied().collect(),
+      HashMap::new(),
+    )
+  }
+
+  /// Attributes for each rebranded device name, copied from the base configuration.
+  fn attributes(
+    &self,
+    base: &ProtocolDeviceAttributes,
+  ) -> Vec<(ProtocolAttributesType, ProtocolDeviceAttributes)> {
+    self
+      .names
+      .iter()
+      .map(|name| {
+        let identifier = ProtocolAttributesType::Identifier(name.clone());
+        let attrs = base.new_rebrand(identifier.clone(), self.name.clone());
+        (identifier, attrs)
+      })
+      .collect()
+  }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
@@ -128,9 +243,18 @@ pub struct ProtocolDefinition {
   #[serde(rename = "lovense-connect-service")]
   lovense_connect_service: Option<LovenseConnectServiceSpecifier>,
   #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "buttplug-federation")]
+  buttplug_federation: Option<ButtplugFederationSpecifier>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  gpio: Option<Vec<GpioSpecifier>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  midi: Option<Vec<MidiSpecifier>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   defaults: Option<ProtocolAttributes>,
   #[serde(default)]
   configurations: Vec<ProtocolAttributes>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  rebrands: Vec<ProtocolRebrand>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
@@ -153,6 +277,14 @@ pub struct UserConfigDefinition {
   specifiers: Option<HashMap<String, ProtocolDefinition>>,
   #[serde(rename = "devices", default, skip_serializing_if = "Option::is_none")]
   user_device_configs: Option<Vec<UserDeviceConfigPair>>,
+  /// Protocols that shouldn't be used to connect to devices, i.e. "xinput" to keep game
+  /// controllers from showing up.
+  #[serde(
+    rename = "disabled-protocols",
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  disabled_protocols: Option<Vec<String>>,
 }
 
 #[derive(
@@ -198,7 +330,10 @@ impl From<ServerDeviceIdentifier> for UserConfigDeviceIdentifier {
 struct ExternalDeviceConfiguration {
   allow_list: Vec<String>,
   deny_list: Vec<String>,
+  disabled_protocols: Vec<String>,
   reserved_indexes: HashMap<u32, ServerDeviceIdentifier>,
+  device_metadata: HashMap<ServerDeviceIdentifier, HashMap<String, String>>,
+  raw_message_devices: Vec<ServerDeviceIdentifier>,
   protocol_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
   protocol_attributes: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
   user_configs: HashMap<ServerDeviceIdentifier, ProtocolDeviceAttributes>,
@@ -224,7 +359,14 @@ impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
         .for_each(|spec| specifiers.push(ProtocolCommunicationSpecifier::HID(*spec)));
     }
     if let Some(btle) = &protocol_def.btle {
-      specifiers.push(ProtocolCommunicationSpecifier::BluetoothLE(btle.clone()));
+      let mut btle = btle.clone();
+      for rebrand in &protocol_def.rebrands {
+        btle.names_mut().extend(rebrand.names.iter().cloned());
+        btle
+          .advertised_services_mut()
+          .extend(rebrand.advertised_services.iter().copied());
+      }
+      specifiers.push(ProtocolCommunicationSpecifier::BluetoothLE(btle));
     }
     if let Some(xinput) = &protocol_def.xinput {
       specifiers.push(ProtocolCommunicationSpecifier::XInput(*xinput));
@@ -232,41 +374,75 @@ impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
     if let Some(websocket) = &protocol_def.websocket {
       specifiers.push(ProtocolCommunicationSpecifier::Websocket(websocket.clone()));
     }
+    if let Some(gpio_vec) = &protocol_def.gpio {
+      gpio_vec
+        .iter()
+        .for_each(|spec| specifiers.push(ProtocolCommunicationSpecifier::Gpio(spec.clone())));
+    }
+    if let Some(midi_vec) = &protocol_def.midi {
+      midi_vec
+        .iter()
+        .for_each(|spec| specifiers.push(ProtocolCommunicationSpecifier::Midi(spec.clone())));
+    }
     if let Some(lcs) = &protocol_def.lovense_connect_service {
       specifiers.push(ProtocolCommunicationSpecifier::LovenseConnectService(
         lcs.clone(),
       ));
     }
+    if let Some(federation) = &protocol_def.buttplug_federation {
+      specifiers.push(ProtocolCommunicationSpecifier::ButtplugFederation(
+        *federation,
+      ));
+    }
 
     let mut configurations = HashMap::new();
 
     // TODO We should probably make a From for ProtocolAttributes into ProtocolDeviceAttributes.
     if let Some(defaults) = protocol_def.defaults() {
-      let config_attrs = ProtocolDeviceAttributes::new(
+      let mut config_attrs = ProtocolDeviceAttributes::new(
         ProtocolAttributesType::Default,
         defaults.name.clone(),
         None,
         defaults.messages.clone().unwrap_or_default(),
         None,
       );
+      config_attrs.set_battery_polling_interval(defaults.battery_polling_interval);
+      config_attrs.set_linear_motion_limits(defaults.linear_motion_limits);
+      config_attrs.set_temperature_limit(defaults.temperature_limit);
+      config_attrs.set_max_command_rate(defaults.max_command_rate);
       configurations.insert(ProtocolAttributesType::Default, config_attrs);
     }
 
     for config in protocol_def.configurations {
       if let Some(identifiers) = config.identifier {
         for identifier in identifiers {
-          let config_attrs = ProtocolDeviceAttributes::new(
+          let mut config_attrs = ProtocolDeviceAttributes::new(
             ProtocolAttributesType::Identifier(identifier.clone()),
             config.name.clone(),
             None,
             config.messages.clone().unwrap_or_default(),
             None,
           );
+          config_attrs.set_battery_polling_interval(config.battery_polling_interval);
+          config_attrs.set_linear_motion_limits(config.linear_motion_limits);
+          config_attrs.set_temperature_limit(config.temperature_limit);
+          config_attrs.set_max_command_rate(config.max_command_rate);
           configurations.insert(ProtocolAttributesType::Identifier(identifier), config_attrs);
         }
       }
     }
 
+    for rebrand in &protocol_def.rebrands {
+      if let Some(base) = configurations.get(&rebrand.base_attributes_type()).cloned() {
+        configurations.extend(rebrand.attributes(&base));
+      } else {
+        warn!(
+          "Rebrand base {:?} has no configuration, rebranded devices {:?} will use protocol defaults.",
+          rebrand.base_identifier, rebrand.names
+        );
+      }
+    }
+
     Self::new(specifiers, configurations)
   }
 }
@@ -308,11 +484,57 @@ fn add_user_configs_to_protocol(
       if let Some(btle) = &protocol_def.btle {
         base_protocol_def.push(ProtocolCommunicationSpecifier::BluetoothLE(btle.clone()));
       }
+      for rebrand in &protocol_def.rebrands {
+        base_protocol_def.push(ProtocolCommunicationSpecifier::BluetoothLE(
+          rebrand.specifier(),
+        ));
+      }
       if let Some(websocket) = &protocol_def.websocket {
         base_protocol_def.push(ProtocolCommunicationSpecifier::Websocket(websocket.clone()));
       }
+      if let Some(gpio_vec) = &protocol_def.gpio {
+        gpio_vec.iter().for_each(|spec| {
+          base_protocol_def.push(ProtocolCommunicationSpecifier::Gpio(spec.clone()))
+        });
+      }
+      if let Some(midi_vec) = &protocol_def.midi {
+        midi_vec.iter().for_each(|spec| {
+          base_protocol_def.push(ProtocolCommunicationSpecifier::Midi(spec.clone()))
+        });
+      }
+
+      for rebrand in &protocol_def.rebrands {
+        let base_ident = ProtocolAttributesIdentifier::new(
+          user_config_protocol,
+          &rebrand.base_attributes_type(),
+          &None,
+        );
+        if let Some(base) = external_config
+          .protocol_attributes
+          .get(&base_ident)
+          .cloned()
+        {
+          for (identifier, attrs) in rebrand.attributes(&base) {
+            external_config.protocol_attributes.insert(
+              ProtocolAttributesIdentifier::new(user_config_protocol, &identifier, &None),
+              attrs,
+            );
+          }
+        } else {
+          warn!(
+            "Rebrand base {:?} has no configuration, rebranded devices {:?} will use protocol defaults.",
+            rebrand.base_identifier, rebrand.names
+          );
+        }
+      }
     }
   }
+  if let Some(disabled_protocols) = user_config_def.disabled_protocols() {
+    external_config
+      .disabled_protocols
+      .extend(disabled_protocols.iter().cloned());
+  }
+
   if let Some(user_device_configs) = user_config_def.user_device_configs() {
     for user_config in user_device_configs {
       if *user_config.config().allow().as_ref().unwrap_or(&false) {
@@ -331,14 +553,33 @@ fn add_user_configs_to_protocol(
           .insert(*index, user_config.identifier().clone().into());
       }
       let server_ident: ServerDeviceIdentifier = user_config.identifier.clone().into();
+      if let Some(metadata) = user_config.config().metadata() {
+        external_config
+          .device_metadata
+          .insert(server_ident.clone(), metadata.clone());
+      }
+      if *user_config
+        .config()
+        .allow_raw_messages()
+        .as_ref()
+        .unwrap_or(&false)
+      {
+        external_config
+          .raw_message_devices
+          .push(server_ident.clone());
+      }
 
-      let config_attrs = ProtocolDeviceAttributes::new(
+      let mut config_attrs = ProtocolDeviceAttributes::new(
         server_ident.attributes_identifier().clone(),
         None,
         user_config.config().display_name.clone(),
         user_config.config().messages.clone().unwrap_or_default(),
         None,
       );
+      config_attrs.set_battery_polling_interval(user_config.config().battery_polling_interval);
+      config_attrs.set_linear_motion_limits(user_config.config().linear_motion_limits);
+      config_attrs.set_temperature_limit(user_config.config().temperature_limit);
+      config_attrs.set_max_command_rate(user_config.config().max_command_rate);
       info!("Adding user config for {:?}", server_ident);
       external_config
         .user_configs
@@ -409,35 +650,433 @@ fn get_internal_config_version() -> ConfigVersion {
   config.version
 }
 
+fn is_json_config(config_str: &str) -> bool {
+  config_str.trim_start().starts_with('{')
+}
+
+/// Configuration files can be written in JSON or TOML. TOML files can't start with a '{', so that's
+/// enough to tell them apart. Both are validated against the same schema, so TOML is converted to a
+/// JSON value before loading.
+fn parse_config_str(config_str: &str) -> Result<serde_json::Value, ButtplugDeviceError> {
+  if is_json_config(config_str) {
+    return serde_json::from_str(config_str).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Configuration is not valid JSON: {}",
+        err
+      ))
+    });
+  }
+  #[cfg(feature = "toml-config")]
+  {
+    toml::from_str(config_str).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Configuration is not valid TOML: {}",
+        err
+      ))
+    })
+  }
+  #[cfg(not(feature = "toml-config"))]
+  Err(ButtplugDeviceError::DeviceConfigurationError(
+    "Configuration is not JSON, and TOML support (toml-config feature) is not enabled.".to_owned(),
+  ))
+}
+
+/// Describes what the schema expected for a validation error, in terms a user editing a
+/// configuration file can act on.
+fn schema_expectation(kind: &ValidationErrorKind) -> String {
+  match kind {
+    ValidationErrorKind::Type { kind } => match kind {
+      TypeKind::Single(primitive) => primitive.to_string(),
+      TypeKind::Multiple(primitives) => primitives
+        .into_iter()
+        .map(|primitive| primitive.to_string())
+        .collect::<Vec<_>>()
+        .join(" or "),
+    },
+    ValidationErrorKind::Required { property } => format!("property {}", property),
+    ValidationErrorKind::AdditionalProperties { unexpected } => {
+      format!("no unknown properties (found {})", unexpected.join(", "))
+    }
+    ValidationErrorKind::Enum { options } => format!("one of {}", options),
+    ValidationErrorKind::Minimum { limit } => format!("value >= {}", limit),
+    ValidationErrorKind::Maximum { limit } => format!("value <= {}", limit),
+    ValidationErrorKind::MinItems { limit } => format!("at least {} items", limit),
+    ValidationErrorKind::MaxItems { limit } => format!("at most {} items", limit),
+    ValidationErrorKind::Pattern { pattern } => format!("string matching {}", pattern),
+    ValidationErrorKind::AnyOf | ValidationErrorKind::OneOfNotValid => {
+      "value matching one of the allowed formats".to_owned()
+    }
+    _ => "value matching the configuration schema".to_owned(),
+  }
+}
+
 fn load_protocol_config_from_json(
   config_str: &str,
   skip_version_check: bool,
 ) -> Result<ProtocolConfiguration, ButtplugDeviceError> {
+  let config_value = parse_config_str(config_str)?;
   let config_validator = JSONValidator::new(DEVICE_CONFIGURATION_JSON_SCHEMA);
-  match config_validator.validate(config_str) {
-    Ok(_) => match serde_json::from_str::<ProtocolConfiguration>(config_str) {
-      Ok(protocol_config) => {
-        let internal_config_version = get_internal_config_version();
-        if !skip_version_check && protocol_config.version.major != internal_config_version.major {
-          Err(ButtplugDeviceError::DeviceConfigurationError(format!(
-            "Device configuration file major version {} is different than internal major version {}. Cannot load external files that do not have matching major version numbers.",
-            protocol_config.version,
-            internal_config_version
-          )))
-        } else {
-          Ok(protocol_config)
+  if let Err(errors) = config_validator.validate_value(&config_value) {
+    let issues = errors
+      .iter()
+      .map(|err| {
+        DeviceConfigurationIssue::new(
+          &err.instance_path.to_string(),
+          &err.instance.to_string(),
+          &schema_expectation(&err.kind),
+        )
+      })
+      .collect();
+    return Err(ButtplugDeviceError::DeviceConfigurationValidationError(
+      DeviceConfigurationValidationError::new(issues),
+    ));
+  }
+  let protocol_config =
+    serde_json::from_value::<ProtocolConfiguration>(config_value).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Configuration matches schema but could not be loaded: {}",
+        err
+      ))
+    })?;
+  let internal_config_version = get_internal_config_version();
+  if !skip_version_check && protocol_config.version.major != internal_config_version.major {
+    Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Device configuration file major version {} is different than internal major version {}. Cannot load external files that do not have matching major version numbers.",
+      protocol_config.version,
+      internal_config_version
+    )))
+  } else {
+    Ok(protocol_config)
+  }
+}
+
+/// Result of upgrading a configuration file to the format this version of the library loads. See
+/// [migrate_protocol_config].
+#[derive(Debug, Getters)]
+#[getset(get = "pub")]
+pub struct ProtocolConfigMigration {
+  /// Version the configuration was written in.
+  original_version: ConfigVersion,
+  /// Migrated configuration, as JSON in the current format.
+  config: String,
+  /// Settings that have no equivalent in the current format, and were left out of the migrated
+  /// configuration.
+  dropped_settings: Vec<String>,
+}
+
+/// Upgrade a device or user configuration file (JSON or TOML) from an older version to the current
+/// format, keeping all user settings that can still be expressed.
+///
+/// Version 1 user configurations identify devices by address only, while the current format also
+/// needs a protocol name. Devices from those files are dropped (and listed in
+/// [ProtocolConfigMigration::dropped_settings]); use [migrate_protocol_config_with_protocols] if
+/// the protocol for an address is known, i.e. from a device history.
+pub fn migrate_protocol_config(
+  config_str: &str,
+) -> Result<ProtocolConfigMigration, ButtplugDeviceError> {
+  migrate_protocol_config_with_protocols(config_str, |_| None)
+}
+
+/// Same as [migrate_protocol_config], with a resolver that returns the protocol name for a device
+/// address, for upgrading device settings from version 1 user configurations.
+pub fn migrate_protocol_config_with_protocols<F>(
+  config_str: &str,
+  protocol_for_address: F,
+) -> Result<ProtocolConfigMigration, ButtplugDeviceError>
+where
+  F: Fn(&str) -> Option<String>,
+{
+  let mut config_value = parse_config_str(config_str)?;
+  // Version 1 files used a single integer version.
+  let original_version = match config_value.get("version") {
+    Some(serde_json::Value::Number(version)) => ConfigVersion {
+      major: 1,
+      minor: version
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or_default(),
+    },
+    Some(version) => serde_json::from_value(version.clone()).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot read configuration version: {}",
+        err
+      ))
+    })?,
+    None => {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(
+        "Configuration has no version, cannot migrate.".to_owned(),
+      ))
+    }
+  };
+  let internal_config_version = get_internal_config_version();
+  if original_version.major > internal_config_version.major {
+    return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Configuration version {} is newer than internal version {}, cannot migrate.",
+      original_version, internal_config_version
+    )));
+  }
+
+  let mut dropped_settings = vec![];
+  if original_version.major == 1 {
+    migrate_v1_config(
+      &mut config_value,
+      &protocol_for_address,
+      &mut dropped_settings,
+    );
+  }
+  config_value["version"] =
+    serde_json::to_value(&internal_config_version).expect("Version is always serializable");
+  let config = serde_json::to_string_pretty(&config_value)
+    .expect("Value was deserialized, it can be serialized");
+  // Make sure whatever we hand back can actually be loaded.
+  load_protocol_config_from_json(&config, false)?;
+  Ok(ProtocolConfigMigration {
+    original_version,
+    config,
+    dropped_settings,
+  })
+}
+
+fn migrate_v1_config<F>(
+  config_value: &mut serde_json::Value,
+  protocol_for_address: &F,
+  dropped_settings: &mut Vec<String>,
+) where
+  F: Fn(&str) -> Option<String>,
+{
+  let Some(config) = config_value.as_object_mut() else {
+    return;
+  };
+  // Version 1 protocol definitions are in a completely different message format. Anything shipping
+  // a base configuration will need to ship a new one anyways.
+  if config.remove("protocols").is_some() {
+    dropped_settings.push(
+      "protocols: version 1 protocol definitions cannot be converted, use a current base configuration"
+        .to_owned(),
+    );
+  }
+  let Some(user_configs) = config
+    .get_mut("user-configs")
+    .and_then(|user_configs| user_configs.as_object_mut())
+  else {
+    return;
+  };
+  // Specifiers haven't changed format, so they carry over as is. Devices went from an address
+  // keyed map to a list of identifier/config pairs.
+  if let Some(serde_json::Value::Object(devices)) = user_configs.remove("devices") {
+    let mut migrated_devices = vec![];
+    for (address, device_config) in devices {
+      let Some(protocol) = protocol_for_address(&address) else {
+        dropped_settings.push(format!(
+          "user-configs/devices/{}: protocol for device address is unknown",
+          address
+        ));
+        continue;
+      };
+      migrated_devices.push(serde_json::json!({
+        "identifier": {
+          "address": address,
+          "protocol": protocol
+        },
+        "config": migrate_v1_user_device_config(&address, device_config, dropped_settings)
+      }));
+    }
+    user_configs.insert(
+      "devices".to_owned(),
+      serde_json::Value::Array(migrated_devices),
+    );
+  }
+}
+
+fn migrate_v1_user_device_config(
+  address: &str,
+  device_config: serde_json::Value,
+  dropped_settings: &mut Vec<String>,
+) -> serde_json::Value {
+  let mut migrated_config = serde_json::Map::new();
+  let serde_json::Value::Object(device_config) = device_config else {
+    dropped_settings.push(format!(
+      "user-configs/devices/{}: device configuration is not an object",
+      address
+    ));
+    return serde_json::Value::Object(migrated_config);
+  };
+  for (key, value) in device_config {
+    match key.as_str() {
+      "allow" | "deny" | "display-name" | "index" => {
+        migrated_config.insert(key, value);
+      }
+      "messages" => {
+        let messages = migrate_v1_messages(address, value, dropped_settings);
+        if !messages.is_empty() {
+          migrated_config.insert(key, serde_json::Value::Object(messages));
         }
       }
-      Err(err) => Err(ButtplugDeviceError::DeviceConfigurationError(format!(
-        "{}",
+      _ => dropped_settings.push(format!(
+        "user-configs/devices/{}/{}: setting no longer exists",
+        address, key
+      )),
+    }
+  }
+  serde_json::Value::Object(migrated_config)
+}
+
+/// Version 1 messages stored a list of step ranges per generic command. The current format stores
+/// a list of actuators, each with its own step range and actuator type.
+fn migrate_v1_messages(
+  address: &str,
+  messages: serde_json::Value,
+  dropped_settings: &mut Vec<String>,
+) -> serde_json::Map<String, serde_json::Value> {
+  let mut migrated_messages = serde_json::Map::new();
+  let serde_json::Value::Object(messages) = messages else {
+    return migrated_messages;
+  };
+  for (message, attributes) in messages {
+    let (migrated_message, actuator_type) = match message.as_str() {
+      "VibrateCmd" => ("ScalarCmd", "Vibrate"),
+      "RotateCmd" => ("RotateCmd", "Rotate"),
+      "LinearCmd" => ("LinearCmd", "Position"),
+      _ => {
+        dropped_settings.push(format!(
+          "user-configs/devices/{}/messages/{}: message cannot be configured anymore",
+          address, message
+        ));
+        continue;
+      }
+    };
+    let Some(step_ranges) = attributes
+      .get("StepRange")
+      .and_then(|step_ranges| step_ranges.as_array())
+      .filter(|step_ranges| !step_ranges.is_empty())
+    else {
+      dropped_settings.push(format!(
+        "user-configs/devices/{}/messages/{}: no step ranges to convert",
+        address, message
+      ));
+      continue;
+    };
+    let actuators = step_ranges
+      .iter()
+      .map(|step_range| {
+        serde_json::json!({
+          "StepRange": step_range,
+          "ActuatorType": actuator_type
+        })
+      })
+      .collect::<Vec<_>>();
+    migrated_messages
+      .entry(migrated_message)
+      .or_insert_with(|| serde_json::Value::Array(vec![]))
+      .as_array_mut()
+      .expect("Always inserted as an array")
+      .extend(actuators);
+  }
+  migrated_messages
+}
+
+/// Set or clear the display name of a device in a user configuration (JSON or TOML), keeping the
+/// rest of the configuration as is. Creates a new user configuration if none is given. Returns the
+/// updated configuration in the same format, or as JSON for a new configuration.
+pub fn set_user_config_display_name(
+  user_config_str: Option<&str>,
+  identifier: &ServerDeviceIdentifier,
+  display_name: Option<&str>,
+) -> Result<String, ButtplugDeviceError> {
+  update_user_device_config(
+    user_config_str,
+    identifier,
+    "display-name",
+    display_name.map(|name| name.into()),
+  )
+}
+
+/// Set or clear the metadata of a device in a user configuration (JSON or TOML), keeping the rest of
+/// the configuration as is. Creates a new user configuration if none is given. Returns the updated
+/// configuration in the same format, or as JSON for a new configuration.
+pub fn set_user_config_metadata(
+  user_config_str: Option<&str>,
+  identifier: &ServerDeviceIdentifier,
+  metadata: Option<&HashMap<String, String>>,
+) -> Result<String, ButtplugDeviceError> {
+  update_user_device_config(
+    user_config_str,
+    identifier,
+    "metadata",
+    metadata.map(|metadata| serde_json::json!(metadata)),
+  )
+}
+
+/// Set (or remove, if value is None) a setting in the config of a device in a user configuration,
+/// adding an entry for the device if needed.
+fn update_user_device_config(
+  user_config_str: Option<&str>,
+  identifier: &ServerDeviceIdentifier,
+  key: &str,
+  value: Option<serde_json::Value>,
+) -> Result<String, ButtplugDeviceError> {
+  let mut config_value = if let Some(config_str) = user_config_str {
+    parse_config_str(config_str)?
+  } else {
+    serde_json::json!({ "version": get_internal_config_version() })
+  };
+  let invalid_config = || {
+    ButtplugDeviceError::DeviceConfigurationError(format!(
+      "User configuration does not match the configuration schema, cannot set {}.",
+      key
+    ))
+  };
+  let devices = config_value
+    .as_object_mut()
+    .ok_or_else(invalid_config)?
+    .entry("user-configs")
+    .or_insert_with(|| serde_json::json!({}))
+    .as_object_mut()
+    .ok_or_else(invalid_config)?
+    .entry("devices")
+    .or_insert_with(|| serde_json::json!([]))
+    .as_array_mut()
+    .ok_or_else(invalid_config)?;
+  let user_identifier = UserConfigDeviceIdentifier::from(identifier.clone());
+  let existing_config = devices.iter_mut().find_map(|device| {
+    let device_identifier = device.get("identifier")?.clone();
+    if serde_json::from_value::<UserConfigDeviceIdentifier>(device_identifier).ok()?
+      == user_identifier
+    {
+      device.get_mut("config")?.as_object_mut()
+    } else {
+      None
+    }
+  });
+  match (existing_config, value) {
+    (Some(config), Some(value)) => {
+      config.insert(key.to_owned(), value);
+    }
+    (Some(config), None) => {
+      config.remove(key);
+    }
+    (None, Some(value)) => devices.push(serde_json::json!({
+      "identifier": user_identifier,
+      "config": {
+        key: value
+      }
+    })),
+    (None, None) => {}
+  }
+  // Parsing would have already failed for TOML without the toml-config feature.
+  #[cfg(feature = "toml-config")]
+  if user_config_str.is_some_and(|config_str| !is_json_config(config_str)) {
+    return toml::to_string_pretty(&config_value).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot write configuration as TOML: {}",
         err
-      ))),
-    },
-    Err(err) => Err(ButtplugDeviceError::DeviceConfigurationError(format!(
-      "{}",
-      err
-    ))),
+      ))
+    });
   }
+  Ok(
+    serde_json::to_string_pretty(&config_value)
+      .expect("Value was deserialized, it can be serialized"),
+  )
 }
 
 fn load_protocol_configs_internal(
@@ -517,10 +1156,22 @@ pub fn load_protocol_configs(
     dcm_builder.denied_address(address);
   }
 
+  for protocol in external_config.disabled_protocols() {
+    dcm_builder.disabled_protocol(protocol);
+  }
+
   for (index, address) in external_config.reserved_indexes() {
     dcm_builder.reserved_index(address, *index);
   }
 
+  for (identifier, metadata) in external_config.device_metadata() {
+    dcm_builder.device_metadata(identifier, metadata.clone());
+  }
+
+  for identifier in external_config.raw_message_devices() {
+    dcm_builder.allow_raw_messages_for_device(identifier);
+  }
+
   for (name, specifiers) in external_config.protocol_specifiers() {
     for spec in specifiers {
       dcm_builder.communication_specifier(name, spec.clone());
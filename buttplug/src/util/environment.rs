@@ -0,0 +1,29 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Environment variable lookups for builders that allow settings to be overridden from the
+//! environment, i.e. for container and headless deployments.
+
+use std::{fmt::Display, str::FromStr};
+
+/// Value of an environment variable. Empty variables count as unset, so an override can be
+/// cleared without having to unset it.
+pub fn environment_variable(name: &str) -> Option<String> {
+  std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Parsed value of an environment variable, see [environment_variable]. Returns a description of
+/// the problem if the variable is set but can't be parsed.
+pub fn environment_override<T>(name: &str) -> Result<Option<T>, String>
+where
+  T: FromStr,
+  T::Err: Display,
+{
+  environment_variable(name)
+    .map(|value| value.parse().map_err(|err: T::Err| err.to_string()))
+    .transpose()
+}
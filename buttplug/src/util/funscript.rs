@@ -0,0 +1,407 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Parsing for .funscript files.
+//!
+//! Funscripts are JSON files containing a list of timed positions, usually synced to a video. The
+//! original format only carries a single stroke axis, but there are two common ways that multi-axis
+//! scripts are distributed:
+//!
+//! - As separate files alongside the main script, with the axis in the file name (i.e.
+//!   `video.funscript`, `video.twist.funscript`, `video.roll.funscript`)
+//! - As a single file with an `axes` array, where each entry has an axis `id` (`R0`, `L1`, etc...)
+//!   and its own `actions` list.
+//!
+//! Both forms are parsed into a [Funscript], which holds one [FunscriptTimeline] per
+//! [FunscriptAxis]. Positions are normalized to 0.0-1.0, with script range and inversion already
+//! applied, so players do not need to care about the file format.
+
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt, path::Path, str::FromStr};
+use thiserror::Error;
+
+/// Errors that can happen while parsing funscripts.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum FunscriptError {
+  #[error("Funscript JSON could not be parsed: {0}")]
+  ParseError(String),
+  #[error("Funscript contains no actions")]
+  NoActions,
+  #[error("Funscript range of {0} is invalid")]
+  InvalidRange(u32),
+  #[error("Axis {0} was specified more than once")]
+  DuplicateAxis(FunscriptAxis),
+}
+
+/// Axis a funscript timeline drives, using the naming from the T-Code axis convention.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FunscriptAxis {
+  /// L0, the main up/down axis. This is what single axis scripts target.
+  Stroke,
+  /// L1, forward/backward.
+  Surge,
+  /// L2, left/right.
+  Sway,
+  /// R0, rotation around the stroke axis.
+  Twist,
+  /// R1, rotation around the surge axis.
+  Roll,
+  /// R2, rotation around the sway axis.
+  Pitch,
+  /// V0, vibration.
+  Vibrate,
+  /// A0, suction/pump.
+  Suck,
+  /// Anything we don't have a name for.
+  Other(String),
+}
+
+impl FunscriptAxis {
+  /// Resolve either a T-Code axis id (`R0`) or a multi-file name suffix (`twist`).
+  pub fn from_name(name: &str) -> Self {
+    match name.to_ascii_lowercase().as_str() {
+      "l0" | "stroke" => FunscriptAxis::Stroke,
+      "l1" | "surge" => FunscriptAxis::Surge,
+      "l2" | "sway" => FunscriptAxis::Sway,
+      "r0" | "twist" => FunscriptAxis::Twist,
+      "r1" | "roll" => FunscriptAxis::Roll,
+      "r2" | "pitch" => FunscriptAxis::Pitch,
+      "v0" | "vib" | "vibrate" => FunscriptAxis::Vibrate,
+      "a0" | "suck" | "suction" => FunscriptAxis::Suck,
+      _ => FunscriptAxis::Other(name.to_owned()),
+    }
+  }
+
+  /// Figure out the axis of a file from its name, i.e. `video.twist.funscript` is
+  /// [FunscriptAxis::Twist]. Files without an axis suffix are [FunscriptAxis::Stroke].
+  pub fn from_path(path: &Path) -> Self {
+    let stem = path
+      .file_name()
+      .and_then(|x| x.to_str())
+      .map(|x| x.strip_suffix(".funscript").unwrap_or(x))
+      .unwrap_or_default();
+    match stem.rsplit_once('.') {
+      Some((_, suffix)) => match FunscriptAxis::from_name(suffix) {
+        // Dots show up in plenty of video names, so only take suffixes we know about.
+        FunscriptAxis::Other(_) => FunscriptAxis::Stroke,
+        axis => axis,
+      },
+      None => FunscriptAxis::Stroke,
+    }
+  }
+}
+
+impl fmt::Display for FunscriptAxis {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FunscriptAxis::Stroke => write!(f, "L0"),
+      FunscriptAxis::Surge => write!(f, "L1"),
+      FunscriptAxis::Sway => write!(f, "L2"),
+      FunscriptAxis::Twist => write!(f, "R0"),
+      FunscriptAxis::Roll => write!(f, "R1"),
+      FunscriptAxis::Pitch => write!(f, "R2"),
+      FunscriptAxis::Vibrate => write!(f, "V0"),
+      FunscriptAxis::Suck => write!(f, "A0"),
+      FunscriptAxis::Other(name) => write!(f, "{}", name),
+    }
+  }
+}
+
+/// A single timed position in a funscript.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FunscriptAction {
+  /// Time of the action from the start of the script, in milliseconds.
+  pub at: u32,
+  /// Normalized position, 0.0-1.0.
+  pub pos: f64,
+}
+
+/// Time-ordered list of actions for a single axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunscriptTimeline {
+  axis: FunscriptAxis,
+  actions: Vec<FunscriptAction>,
+}
+
+impl FunscriptTimeline {
+  pub fn new(axis: FunscriptAxis, mut actions: Vec<FunscriptAction>) -> Self {
+    actions.sort_by_key(|x| x.at);
+    Self { axis, actions }
+  }
+
+  pub fn axis(&self) -> &FunscriptAxis {
+    &self.axis
+  }
+
+  pub fn actions(&self) -> &[FunscriptAction] {
+    &self.actions
+  }
+
+  /// Time of the last action, in milliseconds.
+  pub fn duration(&self) -> u32 {
+    self.actions.last().map(|x| x.at).unwrap_or(0)
+  }
+
+  /// Index of the first action at or after the given time, or None if the timeline is finished.
+  pub fn next_action_index(&self, time: u32) -> Option<usize> {
+    let idx = self.actions.partition_point(|x| x.at < time);
+    if idx < self.actions.len() {
+      Some(idx)
+    } else {
+      None
+    }
+  }
+
+  /// Linearly interpolated position at a point in time. Times before the first action return the
+  /// first position, times after the last action return the last position.
+  pub fn position_at(&self, time: u32) -> Option<f64> {
+    let first = self.actions.first()?;
+    let last = self.actions.last()?;
+    if time <= first.at {
+      return Some(first.pos);
+    }
+    if time >= last.at {
+      return Some(last.pos);
+    }
+    let idx = self.actions.partition_point(|x| x.at <= time);
+    let (prev, next) = (self.actions[idx - 1], self.actions[idx]);
+    let span = (next.at - prev.at) as f64;
+    let progress = (time - prev.at) as f64 / span;
+    Some(prev.pos + (next.pos - prev.pos) * progress)
+  }
+}
+
+/// Parsed funscript, with a timeline per axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Funscript {
+  version: Option<String>,
+  metadata: Option<serde_json::Value>,
+  axes: BTreeMap<FunscriptAxis, FunscriptTimeline>,
+}
+
+#[derive(Deserialize)]
+struct RawFunscriptAction {
+  at: f64,
+  pos: f64,
+}
+
+#[derive(Deserialize)]
+struct RawFunscriptAxis {
+  id: String,
+  #[serde(default)]
+  actions: Vec<RawFunscriptAction>,
+}
+
+#[derive(Deserialize)]
+struct RawFunscript {
+  #[serde(default)]
+  version: Option<String>,
+  #[serde(default)]
+  inverted: bool,
+  #[serde(default)]
+  range: Option<u32>,
+  #[serde(default)]
+  actions: Vec<RawFunscriptAction>,
+  #[serde(default)]
+  axes: Vec<RawFunscriptAxis>,
+  #[serde(default)]
+  metadata: Option<serde_json::Value>,
+}
+
+fn normalize_actions(
+  raw: &[RawFunscriptAction],
+  range: f64,
+  inverted: bool,
+) -> Vec<FunscriptAction> {
+  raw
+    .iter()
+    .map(|action| {
+      let pos = (action.pos / range).clamp(0.0, 1.0);
+      FunscriptAction {
+        at: action.at.max(0.0) as u32,
+        pos: if inverted { 1.0 - pos } else { pos },
+      }
+    })
+    .collect()
+}
+
+impl Funscript {
+  /// Parse a funscript, treating top level actions as belonging to `axis`. Use
+  /// [FunscriptAxis::Stroke] unless the axis was taken from a multi-file name.
+  pub fn parse(json: &str, axis: FunscriptAxis) -> Result<Self, FunscriptError> {
+    let raw: RawFunscript =
+      serde_json::from_str(json).map_err(|e| FunscriptError::ParseError(e.to_string()))?;
+    let range = raw.range.unwrap_or(100);
+    if range == 0 {
+      return Err(FunscriptError::InvalidRange(range));
+    }
+    let mut axes = BTreeMap::new();
+    if !raw.actions.is_empty() {
+      axes.insert(
+        axis.clone(),
+        FunscriptTimeline::new(
+          axis,
+          normalize_actions(&raw.actions, range as f64, raw.inverted),
+        ),
+      );
+    }
+    for raw_axis in &raw.axes {
+      let axis = FunscriptAxis::from_name(&raw_axis.id);
+      if axes.contains_key(&axis) {
+        return Err(FunscriptError::DuplicateAxis(axis));
+      }
+      axes.insert(
+        axis.clone(),
+        FunscriptTimeline::new(
+          axis,
+          normalize_actions(&raw_axis.actions, range as f64, raw.inverted),
+        ),
+      );
+    }
+    if axes.values().all(|x| x.actions.is_empty()) {
+      return Err(FunscriptError::NoActions);
+    }
+    Ok(Self {
+      version: raw.version,
+      metadata: raw.metadata,
+      axes,
+    })
+  }
+
+  /// Merge the axes of another script (usually loaded from a sibling multi-axis file) into this
+  /// one. If any axis is already in this script, nothing is merged.
+  pub fn merge(&mut self, other: Funscript) -> Result<(), FunscriptError> {
+    if let Some(axis) = other.axes.keys().find(|x| self.axes.contains_key(x)) {
+      return Err(FunscriptError::DuplicateAxis(axis.clone()));
+    }
+    self.axes.extend(other.axes);
+    Ok(())
+  }
+
+  pub fn version(&self) -> &Option<String> {
+    &self.version
+  }
+
+  /// Free-form metadata block (title, creator, tags, etc...), if the script has one.
+  pub fn metadata(&self) -> &Option<serde_json::Value> {
+    &self.metadata
+  }
+
+  pub fn axes(&self) -> &BTreeMap<FunscriptAxis, FunscriptTimeline> {
+    &self.axes
+  }
+
+  pub fn timeline(&self, axis: &FunscriptAxis) -> Option<&FunscriptTimeline> {
+    self.axes.get(axis)
+  }
+
+  /// Time of the last action across all axes, in milliseconds.
+  pub fn duration(&self) -> u32 {
+    self.axes.values().map(|x| x.duration()).max().unwrap_or(0)
+  }
+}
+
+impl FromStr for Funscript {
+  type Err = FunscriptError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Funscript::parse(s, FunscriptAxis::Stroke)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_single_axis_parse() {
+    let script: Funscript = r#"{
+      "version": "1.0",
+      "inverted": false,
+      "range": 100,
+      "actions": [{"at": 500, "pos": 100}, {"at": 0, "pos": 0}, {"at": 1000, "pos": 50}]
+    }"#
+      .parse()
+      .expect("Test, assuming infallible.");
+    let timeline = script
+      .timeline(&FunscriptAxis::Stroke)
+      .expect("Test, assuming infallible.");
+    assert_eq!(timeline.actions()[0].at, 0);
+    assert_eq!(script.duration(), 1000);
+    assert_eq!(timeline.position_at(250), Some(0.5));
+    assert_eq!(timeline.position_at(750), Some(0.75));
+    assert_eq!(timeline.position_at(5000), Some(0.5));
+    assert_eq!(timeline.next_action_index(501), Some(2));
+    assert_eq!(timeline.next_action_index(1001), None);
+  }
+
+  #[test]
+  fn test_inverted_range_parse() {
+    let script: Funscript =
+      r#"{"inverted": true, "range": 50, "actions": [{"at": 0, "pos": 25}, {"at": 10, "pos": 90}]}"#
+        .parse()
+        .expect("Test, assuming infallible.");
+    let actions = script
+      .timeline(&FunscriptAxis::Stroke)
+      .expect("Test, assuming infallible.")
+      .actions();
+    assert_eq!(actions[0].pos, 0.5);
+    assert_eq!(actions[1].pos, 0.0);
+  }
+
+  #[test]
+  fn test_multi_axis_parse() {
+    let mut script: Funscript = r#"{
+      "actions": [{"at": 0, "pos": 0}],
+      "axes": [{"id": "R0", "actions": [{"at": 100, "pos": 100}]}]
+    }"#
+      .parse()
+      .expect("Test, assuming infallible.");
+    assert!(script.timeline(&FunscriptAxis::Twist).is_some());
+    let roll_axis = FunscriptAxis::from_path(Path::new("my.video.roll.funscript"));
+    assert_eq!(roll_axis, FunscriptAxis::Roll);
+    let roll = Funscript::parse(r#"{"actions": [{"at": 0, "pos": 10}]}"#, roll_axis)
+      .expect("Test, assuming infallible.");
+    script.merge(roll).expect("Test, assuming infallible.");
+    assert_eq!(script.axes().len(), 3);
+    // Surge is new but twist isn't, so neither should be merged.
+    let twist = Funscript::parse(
+      r#"{
+        "actions": [{"at": 0, "pos": 10}],
+        "axes": [{"id": "L1", "actions": [{"at": 0, "pos": 10}]}]
+      }"#,
+      FunscriptAxis::Twist,
+    )
+    .expect("Test, assuming infallible.");
+    assert_eq!(
+      script.merge(twist),
+      Err(FunscriptError::DuplicateAxis(FunscriptAxis::Twist))
+    );
+    assert_eq!(script.axes().len(), 3);
+    assert!(script.timeline(&FunscriptAxis::Surge).is_none());
+    assert_eq!(
+      FunscriptAxis::from_path(Path::new("my.video.funscript")),
+      FunscriptAxis::Stroke
+    );
+  }
+
+  #[test]
+  fn test_invalid_scripts() {
+    assert!(matches!(
+      "not json".parse::<Funscript>(),
+      Err(FunscriptError::ParseError(_))
+    ));
+    assert_eq!(
+      r#"{"actions": []}"#.parse::<Funscript>(),
+      Err(FunscriptError::NoActions)
+    );
+    assert_eq!(
+      r#"{"range": 0, "actions": [{"at": 0, "pos": 0}]}"#.parse::<Funscript>(),
+      Err(FunscriptError::InvalidRange(0))
+    );
+  }
+}
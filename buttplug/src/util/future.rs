@@ -117,6 +117,11 @@ impl<T> ButtplugFutureStateShared<T> {
   pub fn set_reply(&self, reply: T) {
     self.lock().set_reply(reply);
   }
+
+  /// True if both refer to the same future.
+  pub fn ptr_eq(&self, other: &Self) -> bool {
+    Arc::ptr_eq(&self.state, &other.state)
+  }
 }
 
 impl<T> Default for ButtplugFutureStateShared<T> {
@@ -193,3 +198,27 @@ impl<T> Future for ButtplugFuture<T> {
     }
   }
 }
+
+/// Future for [yield_now].
+struct YieldNow {
+  yielded: bool,
+}
+
+impl Future for YieldNow {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    if self.yielded {
+      return Poll::Ready(());
+    }
+    self.yielded = true;
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+/// Gives control back to the executor once, so other tasks on the same thread get to run. Works on
+/// any runtime we support, unlike the runtime specific versions.
+pub async fn yield_now() {
+  YieldNow { yielded: false }.await
+}
@@ -30,6 +30,18 @@ impl JSONValidator {
     Self { schema }
   }
 
+  /// Validates an already parsed json value, returning every schema violation found.
+  ///
+  /// # Parameters
+  ///
+  /// - `value`: JSON value to validate.
+  pub fn validate_value<'a>(
+    &'a self,
+    value: &'a serde_json::Value,
+  ) -> Result<(), Vec<jsonschema::ValidationError<'a>>> {
+    self.schema.validate(value).map_err(|err| err.collect())
+  }
+
   /// Validates a json string, based on the schema the validator was created
   /// with.
   ///
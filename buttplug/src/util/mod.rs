@@ -9,63 +9,45 @@
 //! the library.
 
 pub mod async_manager;
+pub mod audio;
+#[cfg(feature = "websocket-tls")]
+pub mod certificate;
 #[cfg(feature = "server")]
 pub mod device_configuration;
+pub mod environment;
+pub mod funscript;
 pub mod future;
 pub mod json;
 pub mod logging;
+pub mod pattern;
 pub mod stream;
+#[cfg(feature = "test-harness")]
+pub mod test_harness;
 
-#[cfg(not(feature = "wasm"))]
+#[cfg(not(feature = "wasm-bindgen-runtime"))]
 pub use tokio::time::sleep;
-#[cfg(feature = "wasm")]
+#[cfg(feature = "wasm-bindgen-runtime")]
 pub use wasmtimer::tokio::sleep;
 
-#[cfg(all(feature = "server", feature = "client"))]
-use crate::{
-  client::ButtplugClient,
-  core::connector::ButtplugInProcessClientConnectorBuilder,
-  server::ButtplugServerBuilder,
-};
+/// Current time in milliseconds since the Unix epoch. This is the clock command timestamps and
+/// [ServerInfo](crate::core::message::ServerInfo)'s server time are measured against.
+pub fn unix_time_millis() -> u64 {
+  instant::SystemTime::now()
+    .duration_since(instant::SystemTime::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as u64
+}
 
-/// Convenience function for creating in-process connectors.
-///
-/// Creates a [ButtplugClient] event loop, with an in-process connector with
-/// all device managers that ship with the library and work on the current
-/// platform added to it already. Takes a maximum ping time to build the
-/// server with, other parameters match `run()`.
-///
-/// # When To Use This Instead of `run()`
-///
-/// If you just want to build a quick example and save yourself a few use
-/// statements and setup, this will get you going. For anything *production*,
-/// we recommend using `run()` as you will have more control over what
-/// happens. This method may gain/lose device comm managers at any time.
-///
-/// # The Device I Want To Use Doesn't Show Up
-///
-/// If you are trying to use this method to create your client, and do not see
-/// the devices you want, there are a couple of things to check:
-///
-/// - Are you on a platform that the device communication manager supports?
-///   For instance, we only support XInput on windows.
-/// - Did the developers add a new Device CommunicationManager type and forget
-///   to add it to this method? _It's more likely than you think!_ [File a
-///   bug](https://github.com/buttplugio/buttplug-rs/issues).
-///
-/// # Errors
-///
-/// If the library was compiled without any device managers, the
-/// [ButtplugClient] will have nothing to do. This is considered a
-/// catastrophic failure and the library will return an error.
-///
-/// If the library is using outside device managers, it is recommended to
-/// build your own connector, add your device manager to those, and use the
-/// `run()` method to pass it in.
+#[cfg(feature = "server")]
+use crate::server::ButtplugServerBuilder;
 #[cfg(all(feature = "server", feature = "client"))]
-pub async fn in_process_client(client_name: &str, allow_raw_messages: bool) -> ButtplugClient {
-  let mut server_builder = ButtplugServerBuilder::default();
+use crate::{client::ButtplugClient, core::connector::ButtplugInProcessClientConnectorBuilder};
 
+/// Add all device communication managers that ship with the library and work on the current
+/// platform to a server builder.
+#[cfg(feature = "server")]
+#[allow(unused_variables)]
+pub fn add_default_comm_managers(server_builder: &mut ButtplugServerBuilder) {
   #[cfg(all(
     feature = "btleplug-manager",
     any(
@@ -112,11 +94,66 @@ pub async fn in_process_client(client_name: &str, allow_raw_messages: bool) -> B
     server_builder.comm_manager(LovenseHIDDongleCommunicationManagerBuilder::default());
     server_builder.comm_manager(LovenseSerialDongleCommunicationManagerBuilder::default());
   }
+  #[cfg(all(feature = "gpio-manager", target_os = "linux"))]
+  {
+    use crate::server::device::hardware::communication::gpio::GpioCommunicationManagerBuilder;
+    server_builder.comm_manager(GpioCommunicationManagerBuilder::default());
+  }
+  #[cfg(all(feature = "midi-manager", target_os = "linux"))]
+  {
+    use crate::server::device::hardware::communication::midi::MidiCommunicationManagerBuilder;
+    server_builder.comm_manager(MidiCommunicationManagerBuilder::default());
+  }
   #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
   {
     use crate::server::device::hardware::communication::xinput::XInputDeviceCommunicationManagerBuilder;
     server_builder.comm_manager(XInputDeviceCommunicationManagerBuilder::default());
   }
+  #[cfg(all(feature = "webbluetooth-manager", target_arch = "wasm32"))]
+  {
+    use crate::server::device::hardware::communication::webbluetooth::WebBluetoothCommunicationManagerBuilder;
+    server_builder.comm_manager(WebBluetoothCommunicationManagerBuilder::default());
+  }
+}
+
+/// Convenience function for creating in-process connectors.
+///
+/// Creates a [ButtplugClient] event loop, with an in-process connector with
+/// all device managers that ship with the library and work on the current
+/// platform added to it already. Takes a maximum ping time to build the
+/// server with, other parameters match `run()`.
+///
+/// # When To Use This Instead of `run()`
+///
+/// If you just want to build a quick example and save yourself a few use
+/// statements and setup, this will get you going. For anything *production*,
+/// we recommend using `run()` as you will have more control over what
+/// happens. This method may gain/lose device comm managers at any time.
+///
+/// # The Device I Want To Use Doesn't Show Up
+///
+/// If you are trying to use this method to create your client, and do not see
+/// the devices you want, there are a couple of things to check:
+///
+/// - Are you on a platform that the device communication manager supports?
+///   For instance, we only support XInput on windows.
+/// - Did the developers add a new Device CommunicationManager type and forget
+///   to add it to this method? _It's more likely than you think!_ [File a
+///   bug](https://github.com/buttplugio/buttplug-rs/issues).
+///
+/// # Errors
+///
+/// If the library was compiled without any device managers, the
+/// [ButtplugClient] will have nothing to do. This is considered a
+/// catastrophic failure and the library will return an error.
+///
+/// If the library is using outside device managers, it is recommended to
+/// build your own connector, add your device manager to those, and use the
+/// `run()` method to pass it in.
+#[cfg(all(feature = "server", feature = "client"))]
+pub async fn in_process_client(client_name: &str, allow_raw_messages: bool) -> ButtplugClient {
+  let mut server_builder = ButtplugServerBuilder::default();
+  add_default_comm_managers(&mut server_builder);
   if allow_raw_messages {
     server_builder.allow_raw_messages();
   }
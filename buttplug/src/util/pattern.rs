@@ -0,0 +1,366 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Native pattern file format.
+//!
+//! Funscripts work well for strokers, but there's no common way to share vibration (or any other
+//! scalar actuator) patterns between applications. Pattern files fill that gap with a small JSON
+//! container:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "name": "Heartbeat",
+//!   "loop": true,
+//!   "sections": [
+//!     {
+//!       "label": "beat",
+//!       "repeat": 2,
+//!       "duration": 1000,
+//!       "tracks": [
+//!         {
+//!           "actuator": "Vibrate",
+//!           "index": 0,
+//!           "keyframes": [{ "at": 0, "value": 1.0 }, { "at": 150, "value": 0.0 }]
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! A pattern is a list of labeled sections, played in order. Each section holds one track per
+//! actuator it drives, where the track is addressed by [ActuatorType] and the index of that actuator
+//! type on the device (so `Vibrate`/`1` is the second vibrator, regardless of what other actuators
+//! the device has). Keyframe values are held until the next keyframe on the same track. Sections can
+//! be repeated, and the whole pattern can loop.
+
+use crate::core::message::ActuatorType;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, str::FromStr};
+use thiserror::Error;
+
+/// Newest pattern format version this library can read.
+pub const PATTERN_FORMAT_VERSION: u32 = 1;
+
+/// Errors that can happen while parsing or validating patterns.
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum PatternError {
+  #[error("Pattern JSON could not be parsed: {0}")]
+  ParseError(String),
+  #[error("Pattern format version {0} is newer than supported version {PATTERN_FORMAT_VERSION}")]
+  UnsupportedVersion(u32),
+  #[error("Pattern contains no sections")]
+  NoSections,
+  #[error("Section label {0} was specified more than once")]
+  DuplicateSection(String),
+  #[error("Section {0} has no duration, repeat count or keyframes to play")]
+  EmptySection(String),
+  #[error("Keyframe value {0} is invalid, should be between 0.0 and 1.0")]
+  InvalidValue(f64),
+}
+
+/// Value for an actuator at a certain time, in milliseconds from the start of the section.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PatternKeyframe {
+  pub at: u32,
+  pub value: f64,
+}
+
+/// Keyframes for a single actuator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternTrack {
+  actuator: ActuatorType,
+  #[serde(default)]
+  index: u32,
+  keyframes: Vec<PatternKeyframe>,
+}
+
+impl PatternTrack {
+  pub fn new(actuator: ActuatorType, index: u32, keyframes: &[PatternKeyframe]) -> Self {
+    let mut keyframes = keyframes.to_vec();
+    keyframes.sort_by_key(|x| x.at);
+    Self {
+      actuator,
+      index,
+      keyframes,
+    }
+  }
+
+  pub fn actuator(&self) -> ActuatorType {
+    self.actuator
+  }
+
+  /// Index of the actuator among actuators of the same type on the device.
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  pub fn keyframes(&self) -> &[PatternKeyframe] {
+    &self.keyframes
+  }
+}
+
+fn default_repeat() -> u32 {
+  1
+}
+
+fn is_default_repeat(repeat: &u32) -> bool {
+  *repeat == 1
+}
+
+/// Labeled block of tracks, played `repeat` times before moving on to the next section.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternSection {
+  label: String,
+  #[serde(default = "default_repeat", skip_serializing_if = "is_default_repeat")]
+  repeat: u32,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  duration: Option<u32>,
+  tracks: Vec<PatternTrack>,
+}
+
+impl PatternSection {
+  pub fn new(label: &str, tracks: &[PatternTrack]) -> Self {
+    Self {
+      label: label.to_owned(),
+      repeat: 1,
+      duration: None,
+      tracks: tracks.to_vec(),
+    }
+  }
+
+  pub fn with_repeat(mut self, repeat: u32) -> Self {
+    self.repeat = repeat;
+    self
+  }
+
+  /// Set an explicit section length. Without this, the section ends at its last keyframe.
+  pub fn with_duration(mut self, duration: u32) -> Self {
+    self.duration = Some(duration);
+    self
+  }
+
+  pub fn label(&self) -> &str {
+    &self.label
+  }
+
+  pub fn repeat(&self) -> u32 {
+    self.repeat
+  }
+
+  pub fn tracks(&self) -> &[PatternTrack] {
+    &self.tracks
+  }
+
+  /// Length of a single pass through the section, in milliseconds.
+  pub fn duration(&self) -> u32 {
+    self.duration.unwrap_or_else(|| {
+      self
+        .tracks
+        .iter()
+        .filter_map(|x| x.keyframes.last())
+        .map(|x| x.at)
+        .max()
+        .unwrap_or(0)
+    })
+  }
+}
+
+/// Parsed and validated pattern.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+  version: u32,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  name: Option<String>,
+  #[serde(default, rename = "loop")]
+  looping: bool,
+  sections: Vec<PatternSection>,
+}
+
+impl Pattern {
+  pub fn new(name: Option<&str>, looping: bool, sections: &[PatternSection]) -> Self {
+    Self {
+      version: PATTERN_FORMAT_VERSION,
+      name: name.map(|x| x.to_owned()),
+      looping,
+      sections: sections.to_vec(),
+    }
+  }
+
+  pub fn parse(json: &str) -> Result<Self, PatternError> {
+    let mut pattern: Pattern =
+      serde_json::from_str(json).map_err(|e| PatternError::ParseError(e.to_string()))?;
+    for track in pattern
+      .sections
+      .iter_mut()
+      .flat_map(|x| x.tracks.iter_mut())
+    {
+      track.keyframes.sort_by_key(|x| x.at);
+    }
+    pattern.validate()?;
+    Ok(pattern)
+  }
+
+  /// Check the pattern for problems that would make it unplayable.
+  pub fn validate(&self) -> Result<(), PatternError> {
+    if self.version > PATTERN_FORMAT_VERSION {
+      return Err(PatternError::UnsupportedVersion(self.version));
+    }
+    if self.sections.is_empty() {
+      return Err(PatternError::NoSections);
+    }
+    let mut labels = HashSet::new();
+    for section in &self.sections {
+      if !labels.insert(section.label.as_str()) {
+        return Err(PatternError::DuplicateSection(section.label.clone()));
+      }
+      // Keyframes past the end of the section are never played, so a section needs at least one
+      // within its duration.
+      let duration = section.duration();
+      if section.repeat == 0
+        || duration == 0
+        || !section
+          .tracks
+          .iter()
+          .flat_map(|x| x.keyframes.iter())
+          .any(|x| x.at <= duration)
+      {
+        return Err(PatternError::EmptySection(section.label.clone()));
+      }
+      for keyframe in section.tracks.iter().flat_map(|x| x.keyframes.iter()) {
+        if !(0.0..=1.0).contains(&keyframe.value) {
+          return Err(PatternError::InvalidValue(keyframe.value));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).expect("Infallible, pattern types always serialize.")
+  }
+
+  pub fn version(&self) -> u32 {
+    self.version
+  }
+
+  pub fn name(&self) -> &Option<String> {
+    &self.name
+  }
+
+  /// If true, playback restarts from the first section after the last section finishes.
+  pub fn looping(&self) -> bool {
+    self.looping
+  }
+
+  pub fn sections(&self) -> &[PatternSection] {
+    &self.sections
+  }
+
+  /// Index of the section with the given label.
+  pub fn section_index(&self, label: &str) -> Option<usize> {
+    self.sections.iter().position(|x| x.label == label)
+  }
+}
+
+impl FromStr for Pattern {
+  type Err = PatternError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Pattern::parse(s)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_pattern_parse() {
+    let pattern: Pattern = r#"{
+      "version": 1,
+      "name": "Heartbeat",
+      "loop": true,
+      "sections": [
+        { "label": "intro", "tracks": [
+          { "actuator": "Vibrate", "keyframes": [{ "at": 500, "value": 0.0 }, { "at": 0, "value": 0.5 }] }
+        ] },
+        { "label": "beat", "repeat": 3, "duration": 1000, "tracks": [
+          { "actuator": "Vibrate", "index": 1, "keyframes": [{ "at": 0, "value": 1.0 }] },
+          { "actuator": "Rotate", "keyframes": [{ "at": 200, "value": 0.25 }] }
+        ] }
+      ]
+    }"#
+      .parse()
+      .expect("Test, assuming infallible.");
+    assert!(pattern.looping());
+    assert_eq!(pattern.name(), &Some("Heartbeat".to_owned()));
+    assert_eq!(pattern.section_index("beat"), Some(1));
+    let intro = &pattern.sections()[0];
+    assert_eq!(intro.repeat(), 1);
+    assert_eq!(intro.duration(), 500);
+    assert_eq!(intro.tracks()[0].keyframes()[0].at, 0);
+    let beat = &pattern.sections()[1];
+    assert_eq!(beat.duration(), 1000);
+    assert_eq!(beat.tracks()[1].actuator(), ActuatorType::Rotate);
+    assert_eq!(beat.tracks()[0].index(), 1);
+    assert_eq!(Pattern::parse(&pattern.to_json()), Ok(pattern));
+  }
+
+  #[test]
+  fn test_pattern_validation() {
+    assert!(matches!(
+      Pattern::parse(r#"{"version": 2, "sections": []}"#),
+      Err(PatternError::UnsupportedVersion(2))
+    ));
+    assert_eq!(
+      Pattern::parse(r#"{"version": 1, "sections": []}"#),
+      Err(PatternError::NoSections)
+    );
+    let frames = [PatternKeyframe {
+      at: 100,
+      value: 1.0,
+    }];
+    let track = PatternTrack::new(ActuatorType::Vibrate, 0, &frames);
+    let section = PatternSection::new("a", &[track]);
+    assert_eq!(
+      Pattern::new(None, false, &[section.clone(), section.clone()]).validate(),
+      Err(PatternError::DuplicateSection("a".to_owned()))
+    );
+    assert_eq!(
+      Pattern::new(None, false, &[section.clone().with_repeat(0)]).validate(),
+      Err(PatternError::EmptySection("a".to_owned()))
+    );
+    // All keyframes are cut off by the section duration.
+    assert_eq!(
+      Pattern::new(None, true, &[section.with_duration(50)]).validate(),
+      Err(PatternError::EmptySection("a".to_owned()))
+    );
+    assert_eq!(
+      Pattern::new(
+        None,
+        true,
+        &[PatternSection::new("c", &[]).with_duration(50)]
+      )
+      .validate(),
+      Err(PatternError::EmptySection("c".to_owned()))
+    );
+    let bad_track = PatternTrack::new(
+      ActuatorType::Vibrate,
+      0,
+      &[PatternKeyframe {
+        at: 100,
+        value: 2.0,
+      }],
+    );
+    assert_eq!(
+      Pattern::new(None, false, &[PatternSection::new("b", &[bad_track])]).validate(),
+      Err(PatternError::InvalidValue(2.0))
+    );
+  }
+}
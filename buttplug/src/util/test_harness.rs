@@ -0,0 +1,162 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! End to end tests for apps and libraries built on Buttplug, without hardware.
+//!
+//! [ButtplugTestHarnessBuilder] builds a real [ButtplugServer] with [mock
+//! devices](crate::server::device::hardware::communication::mock), and connects a real
+//! [ButtplugClient] to it over the in-process connector. Tests can then go from scanning, to
+//! commanding devices through the client, to checking the bytes the hardware received:
+//!
+//! ```no_run
+//! # use buttplug::{client::ScalarValueCommand, util::test_harness::ButtplugTestHarnessBuilder};
+//! # async fn test() {
+//! let mut builder = ButtplugTestHarnessBuilder::default();
+//! let mut vivi = builder.add_device("Massage Demo");
+//! let harness = builder.finish().await.unwrap();
+//! let devices = harness.scan_for_devices(1).await.unwrap();
+//! devices[0]
+//!   .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+//!   .await
+//!   .unwrap();
+//! assert_eq!(*vivi.next_write().await.unwrap().data(), vec![0xF1, 64]);
+//! # }
+//! ```
+
+use crate::{
+  client::{ButtplugClient, ButtplugClientDevice, ButtplugClientError, ButtplugClientEvent},
+  core::connector::{ButtplugInProcessClientConnector, ButtplugInProcessClientConnectorBuilder},
+  server::{
+    device::hardware::communication::mock::{
+      mock_comm_manager::MockCommunicationManagerBuilder,
+      MockDeviceHandle,
+    },
+    ButtplugServer,
+    ButtplugServerBuilder,
+    ButtplugServerError,
+  },
+  util::sleep,
+};
+use futures::{pin_mut, FutureExt, StreamExt};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+
+/// How long [ButtplugTestHarness::scan_for_devices] waits for devices by default.
+pub const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Errors from setting up a [ButtplugTestHarness].
+#[derive(Error, Debug)]
+pub enum ButtplugTestHarnessError {
+  #[error(transparent)]
+  ServerError(#[from] ButtplugServerError),
+  #[error(transparent)]
+  ClientError(#[from] ButtplugClientError),
+}
+
+pub struct ButtplugTestHarnessBuilder {
+  server_builder: ButtplugServerBuilder,
+  devices: MockCommunicationManagerBuilder,
+  client_name: String,
+}
+
+impl Default for ButtplugTestHarnessBuilder {
+  fn default() -> Self {
+    Self {
+      server_builder: ButtplugServerBuilder::default(),
+      devices: MockCommunicationManagerBuilder::default(),
+      client_name: "Test Harness Client".to_owned(),
+    }
+  }
+}
+
+impl ButtplugTestHarnessBuilder {
+  /// Adds a mock device advertising the given Bluetooth LE name. The name picks the protocol the
+  /// device is handled by, through the server's device configuration.
+  pub fn add_device(&mut self, name: &str) -> MockDeviceHandle {
+    self.devices.add_device(name)
+  }
+
+  /// Builder for the server, for tests that need custom device configuration, raw messages, or
+  /// other server options. Other comm managers can be added too, but tests won't be hermetic.
+  pub fn server_builder(&mut self) -> &mut ButtplugServerBuilder {
+    &mut self.server_builder
+  }
+
+  pub fn client_name(&mut self, name: &str) -> &mut Self {
+    self.client_name = name.to_owned();
+    self
+  }
+
+  /// Builds the server, and connects the client to it.
+  pub async fn finish(&mut self) -> Result<ButtplugTestHarness, ButtplugTestHarnessError> {
+    let server = self
+      .server_builder
+      .comm_manager(std::mem::take(&mut self.devices))
+      .finish()?;
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+    let client = ButtplugClient::new(&self.client_name);
+    client.connect(connector.clone()).await?;
+    Ok(ButtplugTestHarness { client, connector })
+  }
+}
+
+/// Server and client connected in-process, built by [ButtplugTestHarnessBuilder].
+pub struct ButtplugTestHarness {
+  client: ButtplugClient,
+  connector: ButtplugInProcessClientConnector,
+}
+
+impl ButtplugTestHarness {
+  pub fn client(&self) -> &ButtplugClient {
+    &self.client
+  }
+
+  pub fn server(&self) -> &ButtplugServer {
+    self.connector.server_ref()
+  }
+
+  /// Scans until the client has the given number of devices, waiting at most
+  /// [DEFAULT_SCAN_TIMEOUT]. See [scan_for_devices_with_timeout](Self::scan_for_devices_with_timeout).
+  pub async fn scan_for_devices(
+    &self,
+    count: usize,
+  ) -> Result<Vec<Arc<ButtplugClientDevice>>, ButtplugClientError> {
+    self
+      .scan_for_devices_with_timeout(count, DEFAULT_SCAN_TIMEOUT)
+      .await
+  }
+
+  /// Scans until the client has the given number of devices, or the timeout runs out. Returns the
+  /// client's devices either way, so tests should check that all of them showed up. Devices whose
+  /// names don't match any protocol never show up.
+  pub async fn scan_for_devices_with_timeout(
+    &self,
+    count: usize,
+    timeout: Duration,
+  ) -> Result<Vec<Arc<ButtplugClientDevice>>, ButtplugClientError> {
+    let events = self.client.event_stream();
+    pin_mut!(events);
+    self.client.start_scanning().await?;
+    let wait_for_devices = async {
+      while self.client.devices().len() < count {
+        match events.next().await {
+          Some(ButtplugClientEvent::ServerDisconnect) | None => break,
+          _ => {}
+        }
+      }
+    };
+    select! {
+      _ = wait_for_devices.fuse() => {},
+      _ = sleep(timeout).fuse() => {
+        warn!("Only {} of {} devices found before the scan timed out.", self.client.devices().len(), count);
+      }
+    }
+    Ok(self.client.devices())
+  }
+}
@@ -0,0 +1,112 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "blocking-client")]
+mod test {
+  use buttplug::{
+    client::{
+      blocking::{ButtplugBlockingClient, ButtplugBlockingClientEvent},
+      ScalarValueCommand,
+    },
+    core::connector::{ButtplugInProcessClientConnector, ButtplugInProcessClientConnectorBuilder},
+    server::{
+      device::hardware::communication::mock::{
+        mock_comm_manager::MockCommunicationManagerBuilder,
+        MockDeviceHandle,
+      },
+      ButtplugServerBuilder,
+    },
+  };
+  use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+  };
+
+  /// Builds an in-process server with a mock Vivi (Massage Demo) device. Needs to run inside the
+  /// client's runtime, as the server spawns its tasks while building.
+  fn connector_with_device(
+    client: &ButtplugBlockingClient,
+  ) -> (ButtplugInProcessClientConnector, MockDeviceHandle) {
+    let _guard = client.handle().enter();
+    let mut devices = MockCommunicationManagerBuilder::default();
+    let vivi = devices.add_device("Massage Demo");
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder.comm_manager(devices);
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server_builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    (connector, vivi)
+  }
+
+  #[test]
+  fn test_blocking_client_device_command() {
+    let client = ButtplugBlockingClient::new("Test Client").expect("Test, assuming infallible.");
+    let (connector, mut vivi) = connector_with_device(&client);
+    let (sender, receiver) = mpsc::channel();
+    client.set_event_callback(move |event| {
+      // Callbacks can block on the client, as they don't run on the runtime.
+      if let ButtplugBlockingClientEvent::DeviceAdded(device) = &event {
+        device
+          .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+          .expect("Test, assuming infallible.");
+      }
+      let _ = sender.send(event);
+    });
+    client
+      .connect(connector)
+      .expect("Test, assuming infallible.");
+    assert!(client.connected());
+    client.start_scanning().expect("Test, assuming infallible.");
+    let device = loop {
+      match receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Test, assuming infallible.")
+      {
+        ButtplugBlockingClientEvent::DeviceAdded(device) => break device,
+        _ => continue,
+      }
+    };
+    assert_eq!(device.name(), "Aneros Vivi");
+    assert_eq!(client.devices().len(), 1);
+    assert_eq!(
+      *client
+        .block_on(vivi.next_write())
+        .expect("Test, assuming infallible.")
+        .data(),
+      vec![0xF1, 64]
+    );
+    device.stop().expect("Test, assuming infallible.");
+    client.disconnect().expect("Test, assuming infallible.");
+    assert!(!client.connected());
+  }
+
+  #[test]
+  fn test_blocking_client_clear_event_callback() {
+    let client = ButtplugBlockingClient::new("Test Client").expect("Test, assuming infallible.");
+    let (connector, _vivi) = connector_with_device(&client);
+    let (sender, receiver) = mpsc::channel();
+    client.set_event_callback(move |event| {
+      let _ = sender.send(event);
+    });
+    client
+      .connect(connector)
+      .expect("Test, assuming infallible.");
+    client.clear_event_callback();
+    client.disconnect().expect("Test, assuming infallible.");
+    // The callback thread exits once the forwarder is gone, which drops the sender.
+    loop {
+      match receiver.recv_timeout(Duration::from_secs(5)) {
+        Ok(ButtplugBlockingClientEvent::ServerDisconnect) => {
+          panic!("Events should not be delivered after the callback is cleared")
+        }
+        Ok(_) => continue,
+        Err(RecvTimeoutError::Disconnected) => break,
+        Err(RecvTimeoutError::Timeout) => panic!("Callback thread should have exited"),
+      }
+    }
+  }
+}
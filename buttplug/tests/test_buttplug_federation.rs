@@ -0,0 +1,121 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "buttplug-federation-manager")]
+mod test {
+  use crate::util::{test_server_with_device, ButtplugTestServer};
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent, ScalarValueCommand},
+    core::{
+      connector::{
+        ButtplugInProcessClientConnectorBuilder,
+        ButtplugRemoteServerConnector,
+        ButtplugWebsocketServerTransport,
+        ButtplugWebsocketServerTransportBuilder,
+      },
+      message::{serializer::ButtplugServerJSONSerializer, DeviceTransportType, Endpoint},
+    },
+    server::{
+      device::hardware::{
+        communication::buttplug_federation::ButtplugFederationCommunicationManagerBuilder,
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      ButtplugServerBuilder,
+    },
+    util::async_manager,
+  };
+  use futures::StreamExt;
+  use std::time::Duration;
+  use tokio::time::timeout;
+
+  #[tokio::test]
+  async fn test_federated_device_bridging() {
+    // Remote server, with a device attached, listening on a websocket.
+    let (remote_server, mut remote_device) = test_server_with_device("Massage Demo", false).await;
+    let remote_server = ButtplugTestServer::new(remote_server);
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugWebsocketServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugWebsocketServerTransportBuilder::default()
+          .port(12351)
+          .finish(),
+      );
+      remote_server
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+
+    // Local server, bridging the remote. The manager retries connecting while scanning, so it
+    // doesn't matter if the remote isn't listening yet.
+    let mut builder = ButtplugServerBuilder::default();
+    builder.comm_manager(ButtplugFederationCommunicationManagerBuilder::new(
+      "ws://127.0.0.1:12351",
+    ));
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    let client = ButtplugClient::new("Federation Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    let device = timeout(Duration::from_secs(10), async {
+      while let Some(event) = event_stream.next().await {
+        if let ButtplugClientEvent::DeviceAdded(device) = event {
+          return device;
+        }
+      }
+      panic!("Client event stream ended before device was added.");
+    })
+    .await
+    .expect("Federated device should be added.");
+    assert_eq!(device.name(), "Aneros Vivi");
+    assert_eq!(
+      device
+        .transport()
+        .as_ref()
+        .expect("Test, assuming infallible.")
+        .transport_type(),
+      DeviceTransportType::Network
+    );
+    // Features come across as the remote server describes them.
+    let actuators = device.actuators();
+    assert_eq!(actuators.len(), 2);
+    assert_eq!(actuators[1].feature_descriptor(), "Internal Vibrator");
+    assert_eq!(actuators[1].step_count(), 127);
+
+    // Commands to the local device end up at the remote hardware.
+    device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    for expected in [vec![0xF1, 64], vec![0xF2, 64]] {
+      let command = timeout(Duration::from_secs(5), remote_device.receiver.recv())
+        .await
+        .expect("Remote device should receive command.");
+      assert_eq!(
+        command,
+        Some(HardwareCommand::Write(HardwareWriteCmd::new(
+          Endpoint::Tx,
+          expected,
+          false
+        )))
+      );
+    }
+  }
+}
@@ -11,7 +11,7 @@ extern crate buttplug;
 extern crate tracing;
 
 use buttplug::{
-  client::{ButtplugClient, ButtplugClientError, ButtplugClientEvent},
+  client::{ButtplugClient, ButtplugClientError, ButtplugClientEvent, ScalarCommand},
   core::{
     connector::{
       ButtplugConnector,
@@ -20,14 +20,39 @@ use buttplug::{
       ButtplugInProcessClientConnectorBuilder,
     },
     errors::{ButtplugDeviceError, ButtplugError},
-    message::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage},
+    message::{
+      ActuatorType,
+      ButtplugCapability,
+      ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage,
+      ButtplugMessage,
+      ClientDeviceMessageAttributesBuilder,
+      ClientGenericDeviceMessageAttributes,
+      DeviceList,
+      DeviceMessageInfo,
+      Ok,
+      ServerInfo,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+      BUTTPLUG_DEFAULT_CAPABILITIES,
+    },
   },
   server::ButtplugServerBuilder,
+  util::unix_time_millis,
 };
 
-use futures::{future::BoxFuture, StreamExt};
-use std::time::Duration;
-use tokio::{sync::mpsc::Sender, time::sleep};
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
+};
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{
+  sync::mpsc::Sender,
+  time::{sleep, timeout},
+};
 
 #[derive(Default)]
 struct ButtplugFailingConnector {}
@@ -51,6 +76,84 @@ impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServ
   }
 }
 
+/// Answers every message right away, except StartScanning, which never finishes sending. This is
+/// what an in-process server waiting on slow hardware looks like to the client.
+/// Connector that never answers scans or device commands, and keeps everything the client sends.
+#[derive(Default)]
+struct ButtplugStallingConnector {
+  sender: Option<Sender<ButtplugCurrentSpecServerMessage>>,
+  sent: Arc<Mutex<Vec<ButtplugCurrentSpecClientMessage>>>,
+}
+
+fn stalling_device_info() -> DeviceMessageInfo {
+  let mut attributes = ClientDeviceMessageAttributesBuilder::default();
+  attributes.scalar_cmd(&[ClientGenericDeviceMessageAttributes::new(
+    "Motor",
+    20,
+    ActuatorType::Vibrate,
+  )]);
+  DeviceMessageInfo::new(
+    0,
+    "Stalling Device",
+    &None,
+    &None,
+    &None,
+    &None,
+    &None,
+    attributes.finish(),
+  )
+}
+
+impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage>
+  for ButtplugStallingConnector
+{
+  fn connect(
+    &mut self,
+    sender: Sender<ButtplugCurrentSpecServerMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    self.sender = Some(sender);
+    future::ready(Ok(())).boxed()
+  }
+
+  fn disconnect(&self) -> ButtplugConnectorResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn send(&self, msg: ButtplugCurrentSpecClientMessage) -> ButtplugConnectorResultFuture {
+    self
+      .sent
+      .lock()
+      .expect("Test, assuming infallible.")
+      .push(msg.clone());
+    let mut reply: ButtplugCurrentSpecServerMessage = match &msg {
+      ButtplugCurrentSpecClientMessage::RequestServerInfo(rsi) => {
+        let mut server_info =
+          ServerInfo::new("Stalling Server", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION, 0);
+        server_info.set_capabilities(rsi.capabilities().clone());
+        server_info.into()
+      }
+      ButtplugCurrentSpecClientMessage::RequestDeviceList(_) => {
+        DeviceList::new(vec![stalling_device_info()]).into()
+      }
+      ButtplugCurrentSpecClientMessage::StartScanning(_)
+      | ButtplugCurrentSpecClientMessage::ScalarCmd(_) => return future::pending().boxed(),
+      _ => Ok::default().into(),
+    };
+    reply.set_id(msg.id());
+    let sender = self
+      .sender
+      .clone()
+      .expect("Test, connected before sending.");
+    async move {
+      sender
+        .send(reply)
+        .await
+        .map_err(|_| ButtplugConnectorError::ConnectorNotConnected)
+    }
+    .boxed()
+  }
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_failing_connection() {
@@ -61,6 +164,111 @@ async fn test_failing_connection() {
     .is_err());
 }
 
+#[tokio::test]
+async fn test_slow_request_does_not_block_replies() {
+  let client = ButtplugClient::new("Test Client");
+  client
+    .connect(ButtplugStallingConnector::default())
+    .await
+    .expect("Test, assuming infallible.");
+  let scan = tokio::spawn(client.start_scanning());
+  sleep(Duration::from_millis(50)).await;
+  timeout(Duration::from_secs(1), client.stop_all_devices())
+    .await
+    .expect("Reply should not wait on the stalled request.")
+    .expect("Test, assuming infallible.");
+  assert!(!scan.is_finished());
+
+  // Requests still waiting on a reply fail once the connection is gone.
+  client
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    timeout(Duration::from_secs(1), scan)
+      .await
+      .expect("Stalled request should fail on disconnect.")
+      .expect("Test, assuming infallible."),
+    Err(ButtplugClientError::ButtplugConnectorError(
+      ButtplugConnectorError::ConnectorNotConnected
+    ))
+  ));
+}
+
+/// True if a CancelCmd was sent for the last ScalarCmd sent.
+fn scalar_cancelled(sent: &Mutex<Vec<ButtplugCurrentSpecClientMessage>>) -> bool {
+  let sent = sent.lock().expect("Test, assuming infallible.");
+  let scalar_id = sent
+    .iter()
+    .rev()
+    .find_map(|msg| match msg {
+      ButtplugCurrentSpecClientMessage::ScalarCmd(scalar) => Some(scalar.id()),
+      _ => None,
+    })
+    .expect("Test, scalar command was sent.");
+  sent.iter().any(|msg| {
+    matches!(msg, ButtplugCurrentSpecClientMessage::CancelCmd(cancel) if cancel.message_id() == scalar_id)
+  })
+}
+
+#[tokio::test]
+async fn test_dropped_command_is_cancelled() {
+  let connector = ButtplugStallingConnector::default();
+  let sent = connector.sent.clone();
+  let client = ButtplugClient::new("Test Client");
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  sleep(Duration::from_millis(50)).await;
+  let device = client.devices().pop().expect("Test, device list was sent.");
+
+  // Give up on the command once it's been sent.
+  assert!(timeout(
+    Duration::from_millis(50),
+    device.scalar(&ScalarCommand::Scalar((0.5, ActuatorType::Vibrate)))
+  )
+  .await
+  .is_err());
+  sleep(Duration::from_millis(50)).await;
+  assert!(scalar_cancelled(&sent));
+
+  // Commands can also be cancelled while something is still waiting on them.
+  let command = tokio::spawn(device.scalar(&ScalarCommand::Scalar((0.25, ActuatorType::Vibrate))));
+  sleep(Duration::from_millis(50)).await;
+  assert!(!scalar_cancelled(&sent));
+  device
+    .cancel_commands()
+    .await
+    .expect("Test, assuming infallible.");
+  sleep(Duration::from_millis(50)).await;
+  assert!(scalar_cancelled(&sent));
+  command.abort();
+}
+
+#[tokio::test]
+async fn test_cancel_requires_capability() {
+  let connector = ButtplugStallingConnector::default();
+  let sent = connector.sent.clone();
+  let mut client = ButtplugClient::new("Test Client");
+  client.request_capabilities(&[]);
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  sleep(Duration::from_millis(50)).await;
+  let device = client.devices().pop().expect("Test, device list was sent.");
+  assert!(device.cancel_commands().await.is_err());
+  assert!(timeout(
+    Duration::from_millis(50),
+    device.scalar(&ScalarCommand::Scalar((0.5, ActuatorType::Vibrate)))
+  )
+  .await
+  .is_err());
+  sleep(Duration::from_millis(50)).await;
+  assert!(!scalar_cancelled(&sent));
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_disconnect_status() {
@@ -84,6 +292,46 @@ async fn test_connect_init() {
   assert_eq!(client.server_name(), Some("Buttplug Server".to_owned()));
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_capabilities() {
+  let client = test_client().await;
+  assert_eq!(
+    client.capabilities(),
+    BUTTPLUG_DEFAULT_CAPABILITIES.to_vec()
+  );
+
+  let server = ButtplugServerBuilder::default()
+    .capabilities(&[ButtplugCapability::Sensors, ButtplugCapability::Compression])
+    .finish()
+    .expect("Test, assuming infallible.");
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server)
+    .finish();
+  let mut client = ButtplugClient::new("Test Client");
+  client.request_capabilities(&[
+    ButtplugCapability::PatternEngine,
+    ButtplugCapability::Compression,
+  ]);
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(client.capabilities(), vec![ButtplugCapability::Compression]);
+  assert!(!client.has_capability(ButtplugCapability::Sensors));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_server_time() {
+  let client = ButtplugClient::new("Test Client");
+  assert_eq!(client.server_time(), None);
+  let client = test_client().await;
+  // In process servers share our clock, so there shouldn't be any real offset.
+  let server_time = client.server_time().expect("Test, assuming infallible.");
+  assert!(server_time.abs_diff(unix_time_millis()) < 1000);
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_connected_status() {
@@ -167,6 +415,70 @@ async fn test_client_ping() {
   // TODO Watch for ping events
   assert!(client.ping().await.is_err());
 }
+
+#[cfg(feature = "simulation-manager")]
+#[tokio::test]
+async fn test_client_simulation() {
+  use buttplug::{
+    client::ScalarValueCommand,
+    server::device::hardware::communication::simulated::{
+      SimulatedDevice,
+      SimulatedDeviceBehavior,
+      SimulationSettings,
+    },
+  };
+
+  let mut draining = SimulatedDeviceBehavior::default();
+  draining
+    .set_initial_battery_level(1)
+    .set_battery_drain_per_minute(120.0);
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .simulation(SimulationSettings::new(vec![
+      SimulatedDevice::new("Massage Demo", SimulatedDeviceBehavior::default()),
+      SimulatedDevice::new("Massage Demo", draining),
+    ]))
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  let mut event_stream = client.event_stream();
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut added = 0;
+  while let Some(event) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(device) = event {
+      assert!(device
+        .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+        .await
+        .is_ok());
+      added += 1;
+      if added == 2 {
+        break;
+      }
+    }
+  }
+
+  // The draining device dies after half a second, and doesn't come back on the next scan.
+  let removed = tokio::time::timeout(Duration::from_secs(3), async {
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceRemoved(_) = event {
+        return;
+      }
+    }
+  })
+  .await;
+  assert!(removed.is_ok());
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  sleep(Duration::from_millis(200)).await;
+  assert_eq!(client.devices().len(), 1);
+}
 /*
 // Tests both the stop all devices functionality, as well as both ends of the
 // command range for is_in_command_range message validation.
@@ -8,21 +8,55 @@
 mod util;
 use buttplug::{
   client::{
+    ButtplugClient,
     ButtplugClientDeviceEvent,
     ButtplugClientError,
     ButtplugClientEvent,
+    DeviceGroup,
+    Easing,
+    FrameCommand,
+    FrameScheduler,
     ScalarValueCommand,
+    VibrationPatternBuilder,
   },
   core::{
+    connector::ButtplugInProcessClientConnectorBuilder,
     errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
-    message::{self, ButtplugClientMessage, ClientDeviceMessageAttributes},
+    message::{
+      self,
+      ButtplugClientMessage,
+      ButtplugDeviceMessage,
+      ClientDeviceMessageAttributes,
+      DeviceTransportType,
+      Endpoint,
+    },
+  },
+  server::device::hardware::{
+    HardwareCommand,
+    HardwareSubscribeCmd,
+    HardwareUnsubscribeCmd,
+    HardwareWriteCmd,
+  },
+  util::{
+    async_manager,
+    audio::{
+      run_audio_haptics,
+      AudioAnalyzer,
+      AudioBand,
+      AudioRoute,
+      AudioRouteSource,
+      AudioRouter,
+    },
   },
-  util::async_manager,
 };
 use futures::StreamExt;
 use std::{sync::Arc, time::Duration};
-use tokio::time::sleep;
-use util::{test_client_with_device, test_device_manager::TestHardwareEvent};
+use tokio::time::{sleep, timeout};
+use util::{
+  test_client_with_device,
+  test_device_manager::{TestHardwareEvent, TestHardwareNotification},
+  test_server_with_device,
+};
 
 #[cfg(feature = "server")]
 #[tokio::test]
@@ -62,6 +96,139 @@ async fn test_client_device_connected_status() {
   assert!(!client.connected());
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_transport() {
+  let (client, _device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let transport = test_device
+    .transport()
+    .clone()
+    .expect("Server should always send transport info.");
+  assert_eq!(transport.transport_type(), DeviceTransportType::BluetoothLE);
+  assert!(!transport.address().is_empty());
+  // Device list should carry the same info as DeviceAdded
+  assert_eq!(client.devices()[0].transport(), &Some(transport));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_set_display_name() {
+  let (client, _device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  test_device
+    .set_display_name(Some("Left Toy"))
+    .await
+    .expect("Test, assuming infallible.");
+  test_device
+    .set_display_name(None)
+    .await
+    .expect("Test, assuming infallible.");
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_self_test() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let report = test_device
+    .self_test()
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(report.passed());
+  assert_eq!(report.device_index(), test_device.index());
+  assert_eq!(report.actuators().len(), 2);
+  for (index, actuator) in report.actuators().iter().enumerate() {
+    assert_eq!(actuator.index(), index as u32);
+    assert_eq!(actuator.actuator_type(), message::ActuatorType::Vibrate);
+  }
+  assert!(report.sensors().is_empty());
+  // Each vibrator was pulsed on, and everything was off again at the end.
+  let mut writes = vec![];
+  while let Ok(HardwareCommand::Write(cmd)) = device.receiver.try_recv() {
+    writes.push(cmd.data().clone());
+  }
+  assert!(writes.iter().any(|data| data[0] == 0xF1 && data[1] > 0));
+  assert!(writes.iter().any(|data| data[0] == 0xF2 && data[1] > 0));
+  assert!(writes.contains(&vec![0xF1, 0]) && writes.contains(&vec![0xF2, 0]));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_lock() {
+  let (client, _device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  test_device
+    .lock()
+    .await
+    .expect("Test, assuming infallible.");
+  // Relocking from the owner is fine.
+  test_device
+    .lock()
+    .await
+    .expect("Test, assuming infallible.");
+  test_device
+    .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  test_device
+    .unlock()
+    .await
+    .expect("Test, assuming infallible.");
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_device_client_disconnected_status() {
@@ -171,6 +338,406 @@ async fn test_client_device_invalid_command() {
   ));
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_vibrate_ramp() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  test_device
+    .vibrate_ramp(0.0, 1.0, Duration::from_millis(200), Easing::EaseInOut)
+    .await
+    .expect("Test, assuming infallible.");
+  // We should have seen multiple intermediate updates, ending on full speed.
+  let mut writes = vec![];
+  while let Ok(cmd) = device.receiver.try_recv() {
+    writes.push(cmd);
+  }
+  assert!(writes.len() > 2);
+  assert!(
+    writes.contains(&HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0xF1, 0x7F],
+      false
+    )))
+  );
+  assert!(test_device
+    .oscillate_ramp(0.0, 1.0, Duration::from_millis(200), Easing::Linear)
+    .await
+    .is_err());
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_frame_scheduler() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let vibrate = |speed| FrameCommand::Vibrate(ScalarValueCommand::ScalarValue(speed));
+  let scheduler = FrameScheduler::new(Duration::from_secs(60));
+  let mut take_writes = || {
+    let mut writes = vec![];
+    while let Ok(cmd) = device.receiver.try_recv() {
+      writes.push(cmd);
+    }
+    writes
+  };
+
+  // Commands within a frame are coalesced.
+  assert!(scheduler.submit(1, &test_device, vibrate(0.5)));
+  assert!(scheduler.submit(1, &test_device, vibrate(1.0)));
+  scheduler.flush().await.expect("Test, assuming infallible.");
+  assert_eq!(
+    take_writes(),
+    vec![
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x7F], false)),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x7F], false)),
+    ]
+  );
+  // Repeats of the last sent command are dropped, new values wait out the rate limit.
+  assert!(scheduler.submit(2, &test_device, vibrate(1.0)));
+  scheduler.flush().await.expect("Test, assuming infallible.");
+  assert!(scheduler.submit(3, &test_device, vibrate(0.2)));
+  scheduler.flush().await.expect("Test, assuming infallible.");
+  assert!(take_writes().is_empty());
+  // Late commands from older frames are dropped.
+  assert!(!scheduler.submit(2, &test_device, vibrate(0.7)));
+  // Stops skip the rate limit.
+  assert!(scheduler.submit(4, &test_device, FrameCommand::Stop));
+  scheduler.flush().await.expect("Test, assuming infallible.");
+  assert_eq!(
+    take_writes(),
+    vec![
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x00], false)),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x00], false)),
+    ]
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_actuators() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let actuators = test_device.actuators();
+  assert_eq!(actuators.len(), 2);
+  assert!(actuators
+    .iter()
+    .all(|x| *x.actuator_type() == message::ActuatorType::Vibrate));
+  assert!(test_device.sensors().is_empty());
+
+  // Commanding a single feature only touches that motor.
+  actuators[1]
+    .command(0.5)
+    .await
+    .expect("Test, assuming infallible.");
+  actuators[1]
+    .stop()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut writes = vec![];
+  while let Ok(cmd) = device.receiver.try_recv() {
+    writes.push(cmd);
+  }
+  assert_eq!(
+    writes,
+    vec![
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x40], false)),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x00], false)),
+    ]
+  );
+  assert!(matches!(
+    actuators[0].rotate(0.5, true).await.unwrap_err(),
+    ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(
+      ButtplugDeviceError::MessageNotSupported(..)
+    ))
+  ));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_vibration_pattern() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let pattern = VibrationPatternBuilder::default()
+    .step(
+      Duration::from_millis(50),
+      &[
+        (message::ActuatorType::Vibrate, 0, 1.0),
+        (message::ActuatorType::Vibrate, 1, 0.5),
+      ],
+    )
+    .build()
+    .expect("Test, assuming infallible.");
+  let mut take_writes = || {
+    let mut writes = vec![];
+    while let Ok(cmd) = device.receiver.try_recv() {
+      writes.push(cmd);
+    }
+    writes
+  };
+
+  // Streaming sends the step, then zeroes the motors once the pattern ends.
+  pattern
+    .stream(&test_device)
+    .await
+    .expect("Test, assuming infallible.");
+  let writes = take_writes();
+  for expected in [
+    vec![0xF1, 0x7F],
+    vec![0xF2, 0x40],
+    vec![0xF1, 0x00],
+    vec![0xF2, 0x00],
+  ] {
+    assert!(
+      writes.contains(&HardwareCommand::Write(HardwareWriteCmd::new(
+        Endpoint::Tx,
+        expected,
+        false
+      )))
+    );
+  }
+
+  // Playing hands the pattern to the server's pattern engine.
+  pattern
+    .play(&test_device)
+    .await
+    .expect("Test, assuming infallible.");
+  sleep(Duration::from_millis(200)).await;
+  assert!(
+    take_writes().contains(&HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0xF1, 0x7F],
+      false
+    )))
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_group() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let group = DeviceGroup::default();
+  group.add(&test_device, 0.5, Easing::Linear);
+  assert_eq!(group.device_indexes(), vec![test_device.index()]);
+  let mut take_writes = || {
+    let mut writes = vec![];
+    while let Ok(cmd) = device.receiver.try_recv() {
+      writes.push(cmd);
+    }
+    writes
+  };
+
+  // Full intensity is scaled down by the device weight.
+  group
+    .set_intensity(1.0)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(group.intensity(), 1.0);
+  assert_eq!(
+    take_writes(),
+    vec![
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x40], false)),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x40], false)),
+    ]
+  );
+  // Re-adding updates the weight and curve.
+  group.add(&test_device, 2.0, Easing::EaseIn);
+  group
+    .set_intensity(0.5)
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(take_writes().is_empty());
+  group.stop().await.expect("Test, assuming infallible.");
+  assert_eq!(group.intensity(), 0.0);
+  assert_eq!(take_writes().len(), 2);
+  assert!(group.remove(test_device.index()));
+  assert!(!group.remove(test_device.index()));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_subscribe_endpoint() {
+  let (server, mut device) = test_server_with_device("Massage Demo", true).await;
+  let client = ButtplugClient::new("Test Client");
+  client
+    .connect(
+      ButtplugInProcessClientConnectorBuilder::default()
+        .server(server)
+        .finish(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let mut first = test_device
+    .subscribe_endpoint(Endpoint::Tx)
+    .await
+    .expect("Test, assuming infallible.");
+  let mut second = test_device
+    .subscribe_endpoint(Endpoint::Tx)
+    .await
+    .expect("Test, assuming infallible.");
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::Tx, &[1, 2, 3]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  for stream in [&mut first, &mut second] {
+    assert_eq!(
+      timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("Test, assuming infallible."),
+      Some(vec![1, 2, 3])
+    );
+  }
+
+  // The endpoint is only subscribed once, and unsubscribed when the last stream goes away.
+  drop(second);
+  drop(first);
+  sleep(Duration::from_millis(100)).await;
+  let mut commands = vec![];
+  while let Ok(cmd) = device.receiver.try_recv() {
+    commands.push(cmd);
+  }
+  assert_eq!(
+    commands,
+    vec![
+      HardwareCommand::Subscribe(HardwareSubscribeCmd::new(Endpoint::Tx)),
+      HardwareCommand::Unsubscribe(HardwareUnsubscribeCmd::new(Endpoint::Tx)),
+    ]
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_audio_haptics() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  // Half a second of a 60hz tone, in 10ms blocks.
+  let blocks: Vec<Vec<f32>> = (0..50)
+    .map(|block| {
+      (0..441)
+        .map(|i| {
+          let t = (block * 441 + i) as f32 / 44100.0;
+          (2.0 * std::f32::consts::PI * 60.0 * t).sin()
+        })
+        .collect()
+    })
+    .collect();
+  run_audio_haptics(
+    &test_device,
+    futures::stream::iter(blocks),
+    AudioAnalyzer::new(44100, 1, &[AudioBand::bass()]),
+    AudioRouter::new(&[AudioRoute::new(
+      AudioRouteSource::Band(0),
+      0,
+      message::ActuatorType::Vibrate,
+    )]),
+    Duration::from_millis(50),
+  )
+  .await
+  .expect("Test, assuming infallible.");
+  let mut writes = vec![];
+  while let Ok(HardwareCommand::Write(cmd)) = device.receiver.try_recv() {
+    writes.push(cmd.data().clone());
+  }
+  // Should have driven the motor, then stopped it once the stream ended.
+  assert!(writes.iter().any(|x| x[0] == 0xF1 && x[1] > 0x40));
+  assert_eq!(writes.last(), Some(&vec![0xF1, 0]));
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_repeated_deviceadded_message() {
@@ -191,6 +758,9 @@ async fn test_client_repeated_deviceadded_message() {
       "Test Device",
       &None,
       &None,
+      &None,
+      &None,
+      &None,
       &ClientDeviceMessageAttributes::default(),
     );
     helper_clone
@@ -239,6 +809,9 @@ async fn test_client_repeated_deviceremoved_message() {
       "Test Device",
       &None,
       &None,
+      &None,
+      &None,
+      &None,
       &ClientDeviceMessageAttributes::default(),
     );
     let device_removed = message::DeviceRemoved::new(1);
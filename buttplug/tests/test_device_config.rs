@@ -8,7 +8,20 @@
 mod util;
 extern crate buttplug;
 
-use buttplug::server::ButtplugServerBuilder;
+#[cfg(feature = "server")]
+use buttplug::util::device_configuration::{
+  migrate_protocol_config,
+  migrate_protocol_config_with_protocols,
+  set_user_config_display_name,
+};
+use buttplug::{
+  core::errors::{ButtplugDeviceError, DeviceConfigurationIssue},
+  server::{
+    device::{configuration::ProtocolAttributesType, ServerDeviceIdentifier},
+    ButtplugServerBuilder,
+    ButtplugServerError,
+  },
+};
 
 const BASE_CONFIG_JSON: &str = r#"
 {
@@ -102,6 +115,458 @@ async fn test_valid_null_user_config() {
     .unwrap();
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_disabled_protocols_user_config() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "disabled-protocols": ["xinput"]
+    }
+  }
+  "#;
+  let server = ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json.to_owned()))
+    .finish()
+    .unwrap();
+  assert!(!server.device_manager().protocol_enabled("xinput"));
+  assert!(server.device_manager().protocol_enabled("lovense"));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_effective_device_configuration() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "test-addr",
+            "protocol": "lovense",
+            "identifier": "P"
+          },
+          "config": {
+            "display-name": "My Edge",
+            "allow": true,
+            "index": 7
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let server = ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json.to_owned()))
+    .finish()
+    .unwrap();
+  let config = server
+    .device_manager()
+    .device_configuration(&ServerDeviceIdentifier::new(
+      "test-addr",
+      "lovense",
+      &ProtocolAttributesType::Identifier("P".to_owned()),
+    ))
+    .expect("Lovense Edge is in the base config");
+  assert!(config.user_configured());
+  assert!(config.allowed());
+  assert_eq!(config.reserved_index(), Some(7));
+  assert_eq!(
+    config.attributes().display_name(),
+    Some("My Edge".to_owned())
+  );
+  assert_eq!(config.attributes().name(), config.base_attributes().name());
+  assert!(server
+    .device_manager()
+    .device_configuration(&ServerDeviceIdentifier::new(
+      "test-addr",
+      "not-a-protocol",
+      &ProtocolAttributesType::Default,
+    ))
+    .is_none());
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_gpio_user_config() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "specifiers": {
+        "gpio-pwm": {
+          "gpio": [
+            {
+              "chip": "pwmchip0",
+              "period-ns": 50000,
+              "channels": {
+                "generic0": 0,
+                "generic1": 1
+              }
+            }
+          ]
+        }
+      },
+      "devices": [
+        {
+          "identifier": {
+            "address": "pwmchip0",
+            "protocol": "gpio-pwm"
+          },
+          "config": {
+            "display-name": "Pi Vibrators",
+            "messages": {
+              "ScalarCmd": [
+                {
+                  "ActuatorType": "Vibrate",
+                  "StepRange": [0, 100]
+                },
+                {
+                  "ActuatorType": "Vibrate",
+                  "StepRange": [0, 100]
+                }
+              ]
+            }
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let server = ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json.to_owned()))
+    .finish()
+    .unwrap();
+  let config = server
+    .device_manager()
+    .device_configuration(&ServerDeviceIdentifier::new(
+      "pwmchip0",
+      "gpio-pwm",
+      &ProtocolAttributesType::Default,
+    ))
+    .expect("GPIO PWM is in the base config");
+  assert!(config.user_configured());
+  assert_eq!(
+    config
+      .attributes()
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .map(|attrs| attrs.len()),
+    Some(2)
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_midi_user_config() {
+  let user_config_json = |output: &str| {
+    format!(
+      r#"
+      {{
+        "version": {{
+          "major": 2,
+          "minor": 999
+        }},
+        "user-configs": {{
+          "specifiers": {{
+            "midi": {{
+              "midi": [
+                {{
+                  "port": "midiC1D0",
+                  "outputs": {{
+                    "generic0": {}
+                  }}
+                }}
+              ]
+            }}
+          }}
+        }}
+      }}
+      "#,
+      output
+    )
+  };
+  assert!(ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json(r#"{ "channel": 1, "cc": 7 }"#)))
+    .finish()
+    .is_ok());
+  // Outputs are either a control change or a note, never both.
+  assert!(ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json(
+      r#"{ "channel": 1, "cc": 7, "note": 36 }"#
+    )))
+    .finish()
+    .is_err());
+}
+
+#[cfg(feature = "toml-config")]
+#[tokio::test]
+async fn test_toml_user_config() {
+  let user_config_toml = r#"
+[version]
+major = 2
+minor = 999
+
+[user-configs]
+disabled-protocols = ["xinput"]
+
+[[user-configs.devices]]
+identifier = { address = "test-addr", protocol = "lovense", identifier = "P" }
+config = { display-name = "My Edge", deny = false }
+"#;
+  let server = ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_toml.to_owned()))
+    .finish()
+    .unwrap();
+  assert!(!server.device_manager().protocol_enabled("xinput"));
+}
+
+#[cfg(feature = "toml-config")]
+#[tokio::test]
+async fn test_toml_user_config_keeps_format() {
+  let user_config_toml = r#"
+[version]
+major = 2
+minor = 999
+
+[user-configs]
+disabled-protocols = ["xinput"]
+"#;
+  let identifier = ServerDeviceIdentifier::new(
+    "test-addr",
+    "lovense",
+    &ProtocolAttributesType::Identifier("P".to_owned()),
+  );
+  let updated_config =
+    set_user_config_display_name(Some(user_config_toml), &identifier, Some("My Edge"))
+      .expect("Test, assuming infallible.");
+  // Settings changed at runtime are saved back in the format the user wrote the file in.
+  let updated_value: toml::Value =
+    toml::from_str(&updated_config).expect("Test, assuming infallible.");
+  assert_eq!(
+    updated_value["user-configs"]["devices"][0]["config"]["display-name"].as_str(),
+    Some("My Edge")
+  );
+  assert!(ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(updated_config))
+    .finish()
+    .is_ok());
+}
+
+#[cfg(feature = "toml-config")]
+#[tokio::test]
+async fn test_invalid_toml_user_config() {
+  // Valid TOML, but doesn't match the schema.
+  assert!(ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some("[version]\nmajor = \"two\"\n".to_owned()))
+    .finish()
+    .is_err());
+  // Not valid TOML at all.
+  assert!(ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some("version = ".to_owned()))
+    .finish()
+    .is_err());
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_user_config_validation_error_path() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "test-addr",
+            "protocol": "lovense"
+          },
+          "config": {
+            "index": "two"
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let err = ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json.to_owned()))
+    .finish()
+    .unwrap_err();
+  let ButtplugServerError::DeviceConfigurationManagerError(
+    ButtplugDeviceError::DeviceConfigurationValidationError(validation_error),
+  ) = err
+  else {
+    panic!("Should get a validation error, got {:?}", err);
+  };
+  assert_eq!(
+    validation_error.issues(),
+    &vec![DeviceConfigurationIssue::new(
+      "/user-configs/devices/0/config/index",
+      "\"two\"",
+      "integer"
+    )]
+  );
+}
+
+#[cfg(feature = "server")]
+const V1_USER_CONFIG_JSON: &str = r#"
+{
+  "version": 63,
+  "user-configs": {
+    "specifiers": {
+      "lovense": {
+        "btle": {
+          "names": ["LVS-Custom"],
+          "services": {
+            "5a300001-0023-4bd4-bbd5-a6920e4c5653": {
+              "tx": "5a300002-0023-4bd4-bbd5-a6920e4c5653",
+              "rx": "5a300003-0023-4bd4-bbd5-a6920e4c5653"
+            }
+          }
+        }
+      }
+    },
+    "devices": {
+      "lovense-addr": {
+        "display-name": "My Edge",
+        "index": 3,
+        "messages": {
+          "VibrateCmd": {
+            "StepRange": [[0, 10], [5, 15]]
+          }
+        }
+      },
+      "unknown-addr": {
+        "deny": true
+      }
+    }
+  }
+}
+"#;
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_migrate_v1_user_config() {
+  let migration = migrate_protocol_config_with_protocols(V1_USER_CONFIG_JSON, |address| {
+    (address == "lovense-addr").then(|| "lovense".to_owned())
+  })
+  .unwrap();
+  assert_eq!(migration.original_version().major(), 1);
+  assert_eq!(
+    migration.dropped_settings(),
+    &vec!["user-configs/devices/unknown-addr: protocol for device address is unknown".to_owned()]
+  );
+  let config: serde_json::Value = serde_json::from_str(migration.config()).unwrap();
+  assert_eq!(config["version"]["major"], 2);
+  assert_eq!(
+    config["user-configs"]["specifiers"]["lovense"]["btle"]["names"][0],
+    "LVS-Custom"
+  );
+  assert_eq!(
+    config["user-configs"]["devices"],
+    serde_json::json!([
+      {
+        "identifier": {
+          "address": "lovense-addr",
+          "protocol": "lovense"
+        },
+        "config": {
+          "display-name": "My Edge",
+          "index": 3,
+          "messages": {
+            "ScalarCmd": [
+              { "StepRange": [0, 10], "ActuatorType": "Vibrate" },
+              { "StepRange": [5, 15], "ActuatorType": "Vibrate" }
+            ]
+          }
+        }
+      }
+    ])
+  );
+  ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(migration.config().clone()))
+    .finish()
+    .unwrap();
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_migrate_v1_user_config_without_protocols() {
+  let migration = migrate_protocol_config(V1_USER_CONFIG_JSON).unwrap();
+  assert_eq!(migration.dropped_settings().len(), 2);
+  let config: serde_json::Value = serde_json::from_str(migration.config()).unwrap();
+  assert_eq!(config["user-configs"]["devices"], serde_json::json!([]));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_migrate_v2_user_config_keeps_overrides() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 0
+    },
+    "user-configs": {
+      "disabled-protocols": ["xinput"],
+      "devices": [
+        {
+          "identifier": {
+            "address": "test-addr",
+            "protocol": "lovense",
+            "identifier": "P"
+          },
+          "config": {
+            "display-name": "My Edge",
+            "deny": true
+          }
+        }
+      ]
+    }
+  }
+  "#;
+  let migration = migrate_protocol_config(user_config_json).unwrap();
+  assert!(migration.dropped_settings().is_empty());
+  let original: serde_json::Value = serde_json::from_str(user_config_json).unwrap();
+  let migrated: serde_json::Value = serde_json::from_str(migration.config()).unwrap();
+  assert_eq!(original["user-configs"], migrated["user-configs"]);
+  assert_ne!(migrated["version"]["minor"], 0);
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_migrate_newer_config_fails() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 999,
+      "minor": 0
+    },
+    "user-configs": {}
+  }
+  "#;
+  assert!(matches!(
+    migrate_protocol_config(user_config_json),
+    Err(ButtplugDeviceError::DeviceConfigurationError(_))
+  ));
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_invalid_null_version_config() {
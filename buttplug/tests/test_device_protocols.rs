@@ -84,6 +84,8 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_svakom_ella.yaml" ; "Svakom V1 Protocol - Ella")]
 #[test_case("test_svakom_vivianna.yaml" ; "Svakom V2 Protocol - Vivianna")]
 #[test_case("test_svakom_theodore.yaml" ; "Svakom V3 Protocol - Theodore")]
+#[test_case("test_svakom_alberta.yaml" ; "Svakom V3 Protocol - Alberta (Vibrate/Constrict)")]
+#[test_case("test_satisfyer_air_pulse.yaml" ; "Satisfyer Protocol - Air Pulse (Vibrate/Constrict)")]
 #[test_case("test_svakom_alex.yaml" ; "Svakom Alex Neo")]
 #[test_case("test_svakom_alex_v2.yaml" ; "Svakom Alex Neo 2")]
 #[test_case("test_svakom_iker.yaml" ; "Svakom Iker")]
@@ -97,6 +99,7 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_joyhub_protocol.yaml" ; "JoyHub Protocol")]
 #[test_case("test_itoys_protocol.yaml" ; "iToys Protocol")]
 #[test_case("test_leten_protocol.yaml" ; "Leten Protocol")]
+#[test_case("test_esp32_diy_protocol.yaml" ; "ESP32 DIY Protocol")]
 #[tokio::test]
 async fn test_device_protocols_embedded_v3(test_file: &str) {
   //tracing_subscriber::fmt::init();
@@ -171,6 +174,8 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_svakom_ella.yaml" ; "Svakom V1 Protocol - Ella")]
 #[test_case("test_svakom_vivianna.yaml" ; "Svakom V2 Protocol - Vivianna")]
 #[test_case("test_svakom_theodore.yaml" ; "Svakom V3 Protocol - Theodore")]
+#[test_case("test_svakom_alberta.yaml" ; "Svakom V3 Protocol - Alberta (Vibrate/Constrict)")]
+#[test_case("test_satisfyer_air_pulse.yaml" ; "Satisfyer Protocol - Air Pulse (Vibrate/Constrict)")]
 #[test_case("test_svakom_alex.yaml" ; "Svakom Alex Neo")]
 #[test_case("test_svakom_alex_v2.yaml" ; "Svakom Alex Neo 2")]
 #[test_case("test_svakom_barnard.yaml" ; "Svakom (Fantasy Cup) Barnard")]
@@ -183,6 +188,7 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_joyhub_protocol.yaml" ; "JoyHub Protocol")]
 #[test_case("test_itoys_protocol.yaml" ; "iToys Protocol")]
 #[test_case("test_leten_protocol.yaml" ; "Leten Protocol")]
+#[test_case("test_esp32_diy_protocol.yaml" ; "ESP32 DIY Protocol")]
 #[tokio::test]
 async fn test_device_protocols_json_v3(test_file: &str) {
   //tracing_subscriber::fmt::init();
@@ -195,6 +201,7 @@ async fn test_device_protocols_json_v3(test_file: &str) {
 #[test_case("test_cachito_protocol.yaml" ; "Cachito Protocol")]
 #[test_case("test_fredorch_protocol.yaml" ; "Fredorch Protocol")]
 #[test_case("test_hismith_wildolo.yaml" ; "Hismith Protocol - Wildolo")]
+#[test_case("test_hismith_thrusting_cup_vibrate.yaml" ; "Hismith Protocol - Thrusting Cup (VibrateCmd)")]
 #[test_case("test_lovense_single_vibrator.yaml" ; "Lovense Protocol - Single Vibrator Device")]
 #[test_case("test_lovense_nora.yaml" ; "Lovense Protocol - Lovense Nora (Vibrate/Rotate)")]
 #[test_case("test_lovense_battery.yaml" ; "Lovense Protocol - Lovense Battery (Default Devices)")]
@@ -260,6 +267,7 @@ async fn test_device_protocols_embedded_v2(test_file: &str) {
 #[test_case("test_cachito_protocol.yaml" ; "Cachito Protocol")]
 #[test_case("test_fredorch_protocol.yaml" ; "Fredorch Protocol")]
 #[test_case("test_hismith_wildolo.yaml" ; "Hismith Protocol - Wildolo")]
+#[test_case("test_hismith_thrusting_cup_vibrate.yaml" ; "Hismith Protocol - Thrusting Cup (VibrateCmd)")]
 #[test_case("test_lovense_single_vibrator.yaml" ; "Lovense Protocol - Single Vibrator Device")]
 #[test_case("test_lovense_nora.yaml" ; "Lovense Protocol - Lovense Nora (Vibrate/Rotate)")]
 #[test_case("test_lovense_battery.yaml" ; "Lovense Protocol - Lovense Battery (Default Devices)")]
@@ -0,0 +1,116 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "device-sharing")]
+mod test {
+  use crate::util::test_server_with_device;
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent, ScalarValueCommand},
+    core::{
+      connector::ButtplugInProcessClientConnectorBuilder,
+      message::{ButtplugServerMessage, Endpoint, StartScanning},
+    },
+    server::{
+      device::hardware::{
+        communication::websocket_server::websocket_server_comm_manager::WebsocketServerDeviceCommunicationManagerBuilder,
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      device_sharing::DeviceSharing,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::{pin_mut, StreamExt};
+  use std::time::Duration;
+  use tokio::time::timeout;
+
+  #[tokio::test]
+  async fn test_device_sharing() {
+    // Remote server, accepting websocket devices.
+    let mut builder = ButtplugServerBuilder::default();
+    builder
+      .comm_manager(WebsocketServerDeviceCommunicationManagerBuilder::default().server_port(51285));
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    let client = ButtplugClient::new("Device Sharing Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+
+    // Local server, with a device to share.
+    let (local_server, mut local_device) = test_server_with_device("Massage Demo", false).await;
+    let local_events = local_server.event_stream();
+    local_server
+      .device_manager()
+      .parse_message(StartScanning::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+    pin_mut!(local_events);
+    while let Some(msg) = local_events.next().await {
+      if let ButtplugServerMessage::DeviceAdded(_) = msg {
+        break;
+      }
+    }
+
+    let sharing = DeviceSharing::new(local_server.device_manager(), "ws://127.0.0.1:51285");
+    assert!(sharing.share_device(1).is_err());
+    sharing.share_device(0).expect("Test, assuming infallible.");
+    assert!(sharing.is_device_shared(0));
+    assert_eq!(sharing.shared_devices(), vec![0]);
+
+    let device = timeout(Duration::from_secs(10), async {
+      while let Some(event) = event_stream.next().await {
+        if let ButtplugClientEvent::DeviceAdded(device) = event {
+          return device;
+        }
+      }
+      panic!("Client event stream ended before device was added.");
+    })
+    .await
+    .expect("Shared device should be added.");
+    assert_eq!(device.name(), "Aneros Vivi");
+    assert_eq!(device.actuators().len(), 2);
+
+    // Commands from the remote end up at the local hardware.
+    device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    for expected in [vec![0xF1, 64], vec![0xF2, 64]] {
+      let command = timeout(Duration::from_secs(5), local_device.receiver.recv())
+        .await
+        .expect("Local device should receive command.");
+      assert_eq!(
+        command,
+        Some(HardwareCommand::Write(HardwareWriteCmd::new(
+          Endpoint::Tx,
+          expected,
+          false
+        )))
+      );
+    }
+
+    // Unsharing removes the device from the remote.
+    assert!(sharing.unshare_device(0));
+    assert!(!sharing.is_device_shared(0));
+    timeout(Duration::from_secs(10), async {
+      while let Some(event) = event_stream.next().await {
+        if let ButtplugClientEvent::DeviceRemoved(_) = event {
+          return;
+        }
+      }
+      panic!("Client event stream ended before device was removed.");
+    })
+    .await
+    .expect("Shared device should be removed.");
+  }
+}
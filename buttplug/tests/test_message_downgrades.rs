@@ -7,7 +7,11 @@
 
 extern crate buttplug;
 mod util;
-pub use util::test_device_manager::check_test_recv_value;
+pub use util::test_device_manager::{
+  check_test_recv_value,
+  TestDeviceCommunicationManagerBuilder,
+  TestDeviceIdentifier,
+};
 
 use buttplug::{
   core::message::{
@@ -17,11 +21,18 @@ use buttplug::{
       ButtplugSerializedMessage,
       ButtplugServerJSONSerializer,
     },
+    ActuatorType,
+    ButtplugDeviceMessageType,
+    ButtplugMessageSpecVersion,
+    ButtplugServerMessage,
     Endpoint,
+    LostDeviceFeature,
+    SensorType,
   },
   server::{
     device::hardware::{HardwareCommand, HardwareWriteCmd},
     ButtplugServer,
+    ButtplugServerBuilder,
   },
 };
 use futures::{pin_mut, StreamExt};
@@ -169,3 +180,112 @@ async fn test_version0_singlemotorvibratecmd() {
     HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
   );
 }
+
+#[tokio::test]
+async fn test_lost_device_features() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Downgrade Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            },
+            "0000180f-0000-1000-8000-00805f9b34fb": {
+              "rxblebattery": "00002a19-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Downgrade Test Device",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              },
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Oscillate"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new("Downgrade Test", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version1).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let device_index = loop {
+    if let ButtplugServerMessage::DeviceAdded(da) =
+      recv.next().await.expect("Test, assuming infallible.")
+    {
+      break da.device_index();
+    }
+  };
+  let oscillator = LostDeviceFeature::Actuator {
+    message_type: ButtplugDeviceMessageType::ScalarCmd,
+    index: 1,
+    actuator_type: ActuatorType::Oscillate,
+  };
+  let battery = LostDeviceFeature::Sensor {
+    message_type: ButtplugDeviceMessageType::SensorReadCmd,
+    index: 0,
+    sensor_type: SensorType::Battery,
+  };
+  assert_eq!(
+    server.lost_device_features(device_index),
+    Some(vec![oscillator.clone(), battery])
+  );
+  assert_eq!(server.lost_device_features(device_index + 1), None);
+
+  // v2 has battery readings, but still can't oscillate.
+  let v2_session = server.new_session();
+  assert_eq!(v2_session.lost_device_features(device_index), Some(vec![]));
+  v2_session
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version2).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    v2_session.lost_device_features(device_index),
+    Some(vec![oscillator])
+  );
+
+  let current_session = server.new_session();
+  current_session
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    current_session.lost_device_features(device_index),
+    Some(vec![])
+  );
+}
@@ -0,0 +1,121 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "osc-output")]
+mod test {
+  use crate::util::test_server_with_device;
+  use buttplug::{
+    core::message::{
+      ActuatorType,
+      ButtplugServerMessage,
+      ScalarCmd,
+      ScalarSubcommand,
+      StartScanning,
+      StopDeviceCmd,
+    },
+    server::osc_output::{encode_osc_message, OscArgument, OscOutput},
+  };
+  use futures::{pin_mut, StreamExt};
+  use std::time::Duration;
+  use tokio::{net::UdpSocket, time::timeout};
+
+  async fn assert_osc_message(socket: &UdpSocket, address: &str, arguments: &[OscArgument]) {
+    let mut buffer = [0u8; 1024];
+    let len = timeout(Duration::from_secs(5), socket.recv(&mut buffer))
+      .await
+      .expect("OSC message should arrive.")
+      .expect("Test, assuming infallible.");
+    assert_eq!(&buffer[..len], encode_osc_message(address, arguments));
+  }
+
+  #[tokio::test]
+  async fn test_osc_output() {
+    let (server, _device) = test_server_with_device("Massage Demo", false).await;
+    let device_manager = server.device_manager();
+    let events = device_manager.event_stream();
+    device_manager
+      .parse_message(StartScanning::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+    pin_mut!(events);
+    while let Some(msg) = events.next().await {
+      if let ButtplugServerMessage::DeviceAdded(_) = msg {
+        break;
+      }
+    }
+
+    let socket = UdpSocket::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let osc = OscOutput::new(
+      device_manager.clone(),
+      socket.local_addr().expect("Test, assuming infallible."),
+      Some("/test/"),
+    )
+    .expect("Test, assuming infallible.");
+
+    // Devices connected before output started are announced.
+    assert_osc_message(
+      &socket,
+      "/test/device/0/name",
+      &[OscArgument::String("Aneros Vivi".to_owned())],
+    )
+    .await;
+    assert_osc_message(&socket, "/test/device/0/connected", &[OscArgument::Int(1)]).await;
+
+    device_manager
+      .parse_message(
+        ScalarCmd::new(
+          0,
+          vec![ScalarSubcommand::new(1, 0.5, ActuatorType::Vibrate)],
+        )
+        .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    assert_osc_message(
+      &socket,
+      "/test/device/0/vibrate/1",
+      &[OscArgument::Float(0.5)],
+    )
+    .await;
+
+    // Stopping zeroes what was sent.
+    device_manager
+      .parse_message(StopDeviceCmd::new(0).into())
+      .await
+      .expect("Test, assuming infallible.");
+    assert_osc_message(
+      &socket,
+      "/test/device/0/vibrate/1",
+      &[OscArgument::Float(0.0)],
+    )
+    .await;
+
+    // Nothing is sent once output is dropped.
+    drop(osc);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    device_manager
+      .parse_message(
+        ScalarCmd::new(
+          0,
+          vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+        )
+        .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    let mut buffer = [0u8; 1024];
+    assert!(
+      timeout(Duration::from_millis(200), socket.recv(&mut buffer))
+        .await
+        .is_err()
+    );
+  }
+}
@@ -11,15 +11,18 @@ pub use util::{
     check_test_recv_value,
     TestDeviceCommunicationManagerBuilder,
     TestDeviceIdentifier,
+    TestHardwareEvent,
+    TestHardwareNotification,
   },
   test_server_with_device,
 };
 
 use buttplug::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     message::{
       self,
+      ButtplugCapability,
       ButtplugMessageSpecVersion,
       ButtplugServerMessage,
       Endpoint,
@@ -28,11 +31,13 @@ use buttplug::{
   },
   server::{
     device::hardware::{HardwareCommand, HardwareWriteCmd},
+    ButtplugConnectionScope,
     ButtplugServer,
     ButtplugServerBuilder,
+    ButtplugServerError,
   },
 };
-use futures::{pin_mut, Stream, StreamExt};
+use futures::{pin_mut, FutureExt, Stream, StreamExt};
 use std::time::Duration;
 use tokio::time::sleep;
 
@@ -47,10 +52,15 @@ async fn setup_test_server(
     .await
     .expect("Test, assuming infallible.")
   {
-    ButtplugServerMessage::ServerInfo(s) => assert_eq!(
-      s,
-      message::ServerInfo::new("Buttplug Server", ButtplugMessageSpecVersion::Version3, 0)
-    ),
+    ButtplugServerMessage::ServerInfo(mut s) => {
+      // Server time changes every run, so just check it's there.
+      assert!(s.server_time().is_some());
+      s.set_server_time(None);
+      assert_eq!(
+        s,
+        message::ServerInfo::new("Buttplug Server", ButtplugMessageSpecVersion::Version3, 0)
+      );
+    }
     _ => panic!("Should've received ok"),
   }
   (server, recv)
@@ -78,6 +88,62 @@ async fn test_server_handshake_not_done_first() {
   assert!(!server.connected());
 }
 
+#[tokio::test]
+async fn test_server_management_not_allowed_by_default() {
+  // Even with the admin scope, the server itself has to allow management.
+  let server = ButtplugServerBuilder::default()
+    .connection_scope(ButtplugConnectionScope::Admin)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  for (msg, name) in [
+    (
+      message::ServerShutdownCmd::default().into(),
+      "ServerShutdownCmd",
+    ),
+    (
+      message::ForceStopAllDevicesCmd::default().into(),
+      "ForceStopAllDevicesCmd",
+    ),
+    (
+      message::KickClientCmd::new(server.session_id()).into(),
+      "KickClientCmd",
+    ),
+    (
+      message::ScanningEnabledCmd::new(false).into(),
+      "ScanningEnabledCmd",
+    ),
+  ] {
+    let result = server.parse_message(msg).await;
+    assert!(matches!(
+      result.unwrap_err().original_error(),
+      ButtplugError::ButtplugMessageError(ButtplugMessageError::MessageNotAllowed(msg_name))
+        if msg_name == name
+    ));
+  }
+  // Lowering the intensity ceiling is fine, raising it again is management.
+  server
+    .parse_message(message::IntensityCeilingCmd::new(0.5).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let result = server
+    .parse_message(message::IntensityCeilingCmd::new(1.0).into())
+    .await;
+  assert!(matches!(
+    result.unwrap_err().original_error(),
+    ButtplugError::ButtplugMessageError(ButtplugMessageError::MessageNotAllowed(msg_name))
+      if msg_name == "IntensityCeilingCmd"
+  ));
+  assert_eq!(server.device_manager().intensity_ceiling(), 0.5);
+  assert!(server.connected());
+  assert!(server.device_manager().scanning_enabled());
+}
+
 #[tokio::test]
 async fn test_client_version_older_than_server() {
   let msg =
@@ -143,6 +209,93 @@ async fn test_ping_timeout() {
   }
 }
 
+#[tokio::test]
+async fn test_client_requested_ping_time() {
+  let server = ButtplugServerBuilder::default()
+    .max_ping_time(100)
+    .client_ping_time_limit(1000)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let long_session = server.new_session();
+  let unlimited_session = server.new_session();
+
+  let msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  let reply = server.parse_message(msg.into()).await;
+  assert!(
+    matches!(reply, Ok(ButtplugServerMessage::ServerInfo(ref info)) if info.max_ping_time() == 100),
+    "Should get default ping time: {:?}",
+    reply
+  );
+  let msg = message::RequestServerInfo::new_with_max_ping_time(
+    "Test Client",
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    800,
+  );
+  let reply = long_session.parse_message(msg.into()).await;
+  assert!(
+    matches!(reply, Ok(ButtplugServerMessage::ServerInfo(ref info)) if info.max_ping_time() == 800),
+    "Should get requested ping time: {:?}",
+    reply
+  );
+  let msg = message::RequestServerInfo::new_with_max_ping_time(
+    "Test Client",
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    0,
+  );
+  let reply = unlimited_session.parse_message(msg.into()).await;
+  assert!(
+    matches!(reply, Ok(ButtplugServerMessage::ServerInfo(ref info)) if info.max_ping_time() == 1000),
+    "Should get limited ping time: {:?}",
+    reply
+  );
+
+  sleep(Duration::from_millis(300)).await;
+  assert!(server
+    .parse_message(message::Ping::default().into())
+    .await
+    .is_err());
+  assert!(long_session
+    .parse_message(message::Ping::default().into())
+    .await
+    .is_ok());
+  assert!(long_session.connected());
+}
+
+#[tokio::test]
+async fn test_capability_negotiation() {
+  let server = ButtplugServerBuilder::default()
+    .capabilities(&[ButtplugCapability::Sensors, ButtplugCapability::Batching])
+    .finish()
+    .expect("Test, assuming infallible.");
+  let old_session = server.new_session();
+
+  let mut msg =
+    message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  msg.set_capabilities(vec![
+    ButtplugCapability::PatternEngine,
+    ButtplugCapability::Sensors,
+  ]);
+  let reply = server.parse_message(msg.into()).await;
+  assert!(
+    matches!(reply, Ok(ButtplugServerMessage::ServerInfo(ref info)) if *info.capabilities() == vec![ButtplugCapability::Sensors]),
+    "Should only agree to capabilities both sides have: {:?}",
+    reply
+  );
+  assert_eq!(server.capabilities(), vec![ButtplugCapability::Sensors]);
+  assert!(server.has_capability(ButtplugCapability::Sensors));
+  assert!(!server.has_capability(ButtplugCapability::PatternEngine));
+
+  // Clients that don't send capabilities don't get any.
+  let msg = message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version2);
+  let reply = old_session.parse_message(msg.into()).await;
+  assert!(
+    matches!(reply, Ok(ButtplugServerMessage::ServerInfo(ref info)) if info.capabilities().is_empty()),
+    "Should not agree to any capabilities: {:?}",
+    reply
+  );
+  assert!(old_session.capabilities().is_empty());
+}
+
 #[tokio::test]
 async fn test_device_stop_on_ping_timeout() {
   let mut builder = TestDeviceCommunicationManagerBuilder::default();
@@ -471,3 +624,166 @@ async fn test_server_builder_user_device_config_old_config_version() {
 // TODO Test scan with no comm managers
 // TODO Test message with no RequestServerInfo first
 // TODO Test sending device command for device that doesn't exist (in server)
+
+#[tokio::test]
+async fn test_server_runtime_comm_manager() {
+  let server = ButtplugServer::default();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  server
+    .add_comm_manager(builder)
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    server
+      .add_comm_manager(TestDeviceCommunicationManagerBuilder::default())
+      .await,
+    Err(ButtplugServerError::DeviceCommunicationManagerTypeAlreadyAdded(_))
+  ));
+
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+
+  // Removing the manager disconnects its devices.
+  server
+    .remove_comm_manager("TestDeviceCommunicationManager")
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceRemoved(dr) = msg {
+      assert_eq!(dr.device_index(), device_index);
+      break;
+    }
+  }
+  assert!(matches!(
+    server
+      .remove_comm_manager("TestDeviceCommunicationManager")
+      .await,
+    Err(ButtplugServerError::DeviceCommunicationManagerDoesNotExist(
+      _
+    ))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_remove_comm_manager_while_connecting() {
+  let server = ButtplugServer::default();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("LVS-Test", None));
+  server
+    .add_comm_manager(builder)
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Lovense devices are connecting until they answer the DeviceType query.
+  loop {
+    let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+      .await
+      .expect("Device should be queried while connecting.")
+      .expect("Test, assuming infallible.");
+    if matches!(cmd, HardwareCommand::Write(_)) {
+      break;
+    }
+  }
+  server
+    .remove_comm_manager("TestDeviceCommunicationManager")
+    .await
+    .expect("Test, assuming infallible.");
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::Rx, b"Z:11:0082059AD3BD;"),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  // The device finishes connecting without a manager, so it's released instead of added.
+  let released = tokio::time::timeout(Duration::from_secs(2), async {
+    while device.receiver.recv().await.is_some() {}
+  })
+  .await;
+  assert!(released.is_ok());
+  while let Some(Some(msg)) = recv.next().now_or_never() {
+    assert!(!matches!(msg, ButtplugServerMessage::DeviceAdded(_)));
+  }
+}
+
+#[tokio::test]
+async fn test_server_builder_environment_overrides() {
+  // Environment variables are process wide, so everything that touches them lives in this test.
+  let user_config_path = std::env::temp_dir().join(format!(
+    "buttplug-test-env-user-config-{}.json",
+    std::process::id()
+  ));
+  std::fs::write(
+    &user_config_path,
+    r#"{"version": {"major": 2, "minor": 0}, "user-configs": {"disabled-protocols": ["xinput"]}}"#,
+  )
+  .expect("Test, assuming infallible.");
+  std::env::set_var("BUTTPLUG_MAX_PING_TIME", "soon");
+  assert!(matches!(
+    ButtplugServerBuilder::default().environment_overrides(),
+    Err(ButtplugServerError::InvalidEnvironmentOverride(..))
+  ));
+  std::env::set_var("BUTTPLUG_MAX_PING_TIME", "500");
+  std::env::set_var("BUTTPLUG_USER_DEVICE_CONFIG_PATH", &user_config_path);
+  std::env::set_var("BUTTPLUG_COMM_MANAGERS", "DelayDeviceCommunicationManager");
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(TestDeviceCommunicationManagerBuilder::default())
+    .environment_overrides()
+    .expect("Test, assuming infallible.")
+    .finish()
+    .expect("Test, assuming infallible.");
+  std::env::remove_var("BUTTPLUG_MAX_PING_TIME");
+  std::env::remove_var("BUTTPLUG_USER_DEVICE_CONFIG_PATH");
+  std::env::remove_var("BUTTPLUG_COMM_MANAGERS");
+  let _ = std::fs::remove_file(&user_config_path);
+
+  let reply = server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await;
+  assert!(
+    matches!(reply, Ok(ButtplugServerMessage::ServerInfo(ref info)) if info.max_ping_time() == 500)
+  );
+  assert!(!server.device_manager().protocol_enabled("xinput"));
+  // The test comm manager isn't in the allowed list, so it never ran.
+  assert!(matches!(
+    server
+      .remove_comm_manager("TestDeviceCommunicationManager")
+      .await,
+    Err(ButtplugServerError::DeviceCommunicationManagerDoesNotExist(
+      _
+    ))
+  ));
+}
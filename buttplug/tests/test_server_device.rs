@@ -6,13 +6,45 @@
 // for full license information.
 
 mod util;
-use buttplug::core::{
-  errors::{ButtplugDeviceError, ButtplugError},
-  message::{self, ButtplugServerMessage, Endpoint, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION},
+use buttplug::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError, ButtplugUnknownError},
+    message::{
+      self,
+      ButtplugCapability,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  server::{
+    device::{
+      configuration::{ProtocolAttributesType, ProtocolCommunicationSpecifier},
+      hardware::{DeviceInformation, HardwareCommand, HardwareWriteCmd},
+      ServerDeviceIdentifier,
+      MAX_DEVICE_METADATA_SIZE,
+    },
+    ButtplugConnectionScope,
+    ButtplugServerBuilder,
+    ButtplugServerError,
+  },
+};
+use futures::{pin_mut, FutureExt, StreamExt};
+use std::{
+  collections::HashMap,
+  matches,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::sleep;
+pub use util::test_device_manager::{
+  TestDeviceChannelHost,
+  TestDeviceCommunicationManagerBuilder,
+  TestDeviceIdentifier,
+  TestHardwareEvent,
+  TestHardwareNotification,
 };
-use futures::{pin_mut, StreamExt};
-use std::matches;
-pub use util::test_device_manager::TestDeviceCommunicationManagerBuilder;
 use util::test_server_with_device;
 
 // Test devices that have protocols that support movements not all devices do.
@@ -178,67 +210,3770 @@ async fn test_reject_on_no_raw_message() {
   }
 }
 
-/*
-#[cfg(target_os = "windows")]
-#[ignore = "Has weird timeout issues"]
 #[tokio::test]
-async fn test_repeated_address_additions() {
-    let mut server_builder = ButtplugServerBuilder::default();
-    let builder = TestDeviceCommunicationManagerBuilder::default();
-    let helper = builder.helper();
-    server_builder.comm_manager(builder);
-    let server = server_builder.finish().unwrap();
-    let recv = server.event_stream();
-    pin_mut!(recv);
-    helper
-      .add_ble_device_with_address("Massage Demo", "SameAddress")
-      .await;
-    helper
-      .add_ble_device_with_address("Massage Demo", "SameAddress")
-      .await;
-    assert!(server
-      .parse_message(
-        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
-          .into()
-      )
-      .await
-      .is_ok());
-    assert!(server
-      .parse_message(messages::StartScanning::default().into())
-      .await
-      .is_ok());
-    let mut device_index = None;
-    let mut device_removed_called = true;
-    while let Some(msg) = recv.next().await {
-      match msg {
-        ButtplugServerMessage::ScanningFinished(_) => continue,
-        ButtplugServerMessage::DeviceAdded(da) => {
-          assert_eq!(da.device_name(), "Aneros Vivi");
-          if device_index.is_none() {
-            device_index = Some(da.device_index());
-          } else {
-            assert!(device_removed_called);
-            assert_eq!(
-              da.device_index(),
-              device_index.expect("Test, assuming infallible.")
-            );
-            return;
+async fn test_per_device_raw_messages() {
+  let user_config_json = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "devices": [
+        {
+          "identifier": {
+            "address": "raw-addr",
+            "protocol": "aneros"
+          },
+          "config": {
+            "allow-raw-messages": true
           }
         }
-        ButtplugServerMessage::DeviceRemoved(dr) => {
-          assert_eq!(
-            dr.device_index(),
-            device_index.expect("Test, assuming infallible.")
-          );
-          device_removed_called = true;
+      ]
+    }
+  }
+  "#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _raw_device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("raw-addr".to_owned()),
+  ));
+  let _other_device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("other-addr".to_owned()),
+  ));
+  let server = ButtplugServerBuilder::default()
+    .user_device_configuration_json(Some(user_config_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut found = 0;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let identifier = server
+        .device_manager()
+        .device_info(da.device_index())
+        .expect("Test, assuming infallible.")
+        .identifier()
+        .clone();
+      let raw_allowed = identifier
+        == ServerDeviceIdentifier::new("raw-addr", "aneros", &ProtocolAttributesType::Default);
+      assert_eq!(
+        server
+          .device_manager()
+          .device_configuration(&identifier)
+          .expect("Test, assuming infallible.")
+          .raw_messages_allowed(),
+        raw_allowed
+      );
+      let raw_write = server
+        .parse_message(
+          message::RawWriteCmd::new(da.device_index(), Endpoint::Tx, &[0x0], false).into(),
+        )
+        .await;
+      if raw_allowed {
+        assert_eq!(da.device_name(), "Aneros Vivi (Raw Messages Allowed)");
+        assert!(da.device_messages().raw_write_cmd().is_some());
+        assert!(raw_write.is_ok());
+      } else {
+        assert_eq!(da.device_name(), "Aneros Vivi");
+        assert!(da.device_messages().raw_write_cmd().is_none());
+        assert!(matches!(
+          raw_write.unwrap_err().original_error(),
+          ButtplugError::ButtplugDeviceError(ButtplugDeviceError::MessageNotSupported(_))
+        ));
+      }
+      found += 1;
+      if found == 2 {
+        return;
+      }
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_funscript_playback() {
+  let (server, mut device) = test_server_with_device("Onyx+", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let should_be_err = server
+        .parse_message(message::FunscriptPlaybackCmd::new(index, true, None, None).into())
+        .await;
+      assert!(matches!(
+        should_be_err.unwrap_err().original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::FunscriptNotLoaded(_))
+      ));
+      let script = r#"{"actions": [{"at": 0, "pos": 0}, {"at": 50, "pos": 100}, {"at": 100, "pos": 0}, {"at": 150, "pos": 100}]}"#;
+      server
+        .parse_message(message::FunscriptLoadCmd::new(index, script).into())
+        .await
+        .expect("Test, assuming infallible.");
+      // Clear out anything sent during device initialization.
+      while device.receiver.try_recv().is_ok() {}
+      server
+        .parse_message(message::FunscriptPlaybackCmd::new(index, true, Some(0), None).into())
+        .await
+        .expect("Test, assuming infallible.");
+      let mut writes = 0;
+      while writes < 3 {
+        let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+          .await
+          .expect("Playback should keep sending commands to the device.")
+          .expect("Test, assuming infallible.");
+        if matches!(cmd, HardwareCommand::Write(_)) {
+          writes += 1;
         }
-        _ => {
-          panic!(
-            "Returned message was not a DeviceAdded message or timed out: {:?}",
-            msg
-          );
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_funscript_playback_action_at_start() {
+  let (server, mut device) = test_server_with_device("Onyx+", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      // Playing from the start has to send the action at 0, even when it's the only one.
+      let script = r#"{"actions": [{"at": 0, "pos": 100}]}"#;
+      server
+        .parse_message(message::FunscriptLoadCmd::new(index, script).into())
+        .await
+        .expect("Test, assuming infallible.");
+      while device.receiver.try_recv().is_ok() {}
+      server
+        .parse_message(message::FunscriptPlaybackCmd::new(index, true, Some(0), None).into())
+        .await
+        .expect("Test, assuming infallible.");
+      loop {
+        let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+          .await
+          .expect("Playback should send the action at 0 to the device.")
+          .expect("Test, assuming infallible.");
+        if matches!(cmd, HardwareCommand::Write(_)) {
+          break;
         }
       }
+      return;
     }
+  }
+}
+
+#[tokio::test]
+async fn test_server_funscript_session_removed_with_device() {
+  let (server, device) = test_server_with_device("Onyx+", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let script = r#"{"actions": [{"at": 0, "pos": 0}, {"at": 50, "pos": 100}]}"#;
+      server
+        .parse_message(message::FunscriptLoadCmd::new(index, script).into())
+        .await
+        .expect("Test, assuming infallible.");
+      device
+        .sender
+        .send(TestHardwareEvent::Disconnect)
+        .await
+        .expect("Test, assuming infallible.");
+      while let Some(msg) = recv.next().await {
+        if let ButtplugServerMessage::DeviceRemoved(dr) = msg {
+          assert_eq!(dr.device_index(), index);
+          break;
+        }
+      }
+      // The session is dropped along with the device, so there's nothing left to play.
+      tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+          let reply = server
+            .parse_message(message::FunscriptPlaybackCmd::new(index, true, None, None).into())
+            .await;
+          if let Err(err) = reply {
+            if matches!(
+              err.original_error(),
+              ButtplugError::ButtplugDeviceError(ButtplugDeviceError::FunscriptNotLoaded(_))
+            ) {
+              return;
+            }
+          }
+          tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+      })
+      .await
+      .expect("Funscript session should be removed with its device.");
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_pattern_playback() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let should_be_err = server
+        .parse_message(message::PatternStopCmd::new(index).into())
+        .await;
+      assert!(matches!(
+        should_be_err.unwrap_err().original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::PatternNotLoaded(_))
+      ));
+      let pattern = r#"{
+        "version": 1,
+        "sections": [
+          { "label": "pulse", "repeat": 2, "duration": 100, "tracks": [
+            { "actuator": "Vibrate", "keyframes": [{ "at": 0, "value": 1.0 }] },
+            { "actuator": "Rotate", "keyframes": [{ "at": 0, "value": 1.0 }] }
+          ] }
+        ]
+      }"#;
+      server
+        .parse_message(message::PatternLoadCmd::new(index, pattern).into())
+        .await
+        .expect("Test, assuming infallible.");
+      let should_be_err = server
+        .parse_message(message::PatternPlayCmd::new(index, Some("missing")).into())
+        .await;
+      assert!(should_be_err.is_err());
+      server
+        .parse_message(message::PatternPlayCmd::new(index, Some("pulse")).into())
+        .await
+        .expect("Test, assuming infallible.");
+      // Full power at the start of the pattern, then zeroed once it finishes.
+      for expected in [vec![0xF1, 127], vec![0xF1, 0]] {
+        loop {
+          let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+            .await
+            .expect("Pattern should be sending commands to the device.")
+            .expect("Test, assuming infallible.");
+          if let HardwareCommand::Write(write_cmd) = cmd {
+            if *write_cmd.data() == expected {
+              break;
+            }
+          }
+        }
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_pattern_stop_error() {
+  let (server, device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let pattern = r#"{
+        "version": 1,
+        "sections": [
+          { "label": "pulse", "duration": 100, "tracks": [
+            { "actuator": "Vibrate", "keyframes": [{ "at": 0, "value": 1.0 }] }
+          ] }
+        ]
+      }"#;
+      server
+        .parse_message(message::PatternLoadCmd::new(index, pattern).into())
+        .await
+        .expect("Test, assuming infallible.");
+      device
+        .sender
+        .send(TestHardwareEvent::Disconnect)
+        .await
+        .expect("Test, assuming infallible.");
+      while let Some(msg) = recv.next().await {
+        if let ButtplugServerMessage::DeviceRemoved(_) = msg {
+          break;
+        }
+      }
+      // Stopping can't zero a device that's gone, and the client should hear about it.
+      let should_be_err = server
+        .parse_message(message::PatternStopCmd::new(index).into())
+        .await;
+      assert!(should_be_err.is_err());
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_timestamp_scheduled_command() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  let server_info = server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  // Timestamps are on the server clock, which the server sends during the handshake.
+  let handshake_time = Instant::now();
+  let server_time = if let ButtplugServerMessage::ServerInfo(server_info) = server_info {
+    server_info
+      .server_time()
+      .expect("Test, assuming infallible.")
+  } else {
+    panic!("Should've gotten ServerInfo back");
+  };
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let now = || server_time + handshake_time.elapsed().as_millis() as u64;
+      let scalar = |level| {
+        message::ScalarCmd::new(
+          index,
+          vec![message::ScalarSubcommand::new(
+            0,
+            level,
+            message::ActuatorType::Vibrate,
+          )],
+        )
+      };
+      while device.receiver.try_recv().is_ok() {}
+
+      // Command should not reach the device until its timestamp.
+      let sent_at = Instant::now();
+      server
+        .parse_message(scalar(1.0).with_timestamp(now() + 200).into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(sent_at.elapsed() >= Duration::from_millis(150));
+      assert!(matches!(
+        device.receiver.try_recv(),
+        Ok(HardwareCommand::Write(_))
+      ));
+
+      // Timestamps too far in the future are rejected.
+      assert!(server
+        .parse_message(scalar(0.5).with_timestamp(now() + 60000).into())
+        .await
+        .is_err());
+
+      // Stopping the device drops anything still waiting to run.
+      let scheduled = server.parse_message(scalar(0.5).with_timestamp(now() + 500).into());
+      server
+        .parse_message(message::StopDeviceCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(matches!(
+        scheduled.await.unwrap_err().original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::ScheduledCommandCancelled(_))
+      ));
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_cancel_device_command() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  let mut rsi =
+    message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  rsi.set_capabilities(vec![ButtplugCapability::Cancellation]);
+  server
+    .parse_message(rsi.into())
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      while device.receiver.try_recv().is_ok() {}
+      let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Test, assuming infallible.")
+        .as_millis() as u64
+        + 200;
+      let mut scalar = message::ScalarCmd::new(
+        index,
+        vec![message::ScalarSubcommand::new(
+          0,
+          1.0,
+          message::ActuatorType::Vibrate,
+        )],
+      )
+      .with_timestamp(timestamp);
+      scalar.set_id(5);
+      let command = server.parse_message(scalar.into());
+      let mut cancel = message::CancelCmd::new(5);
+      cancel.set_id(6);
+      let reply = server
+        .parse_message(cancel.into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(reply.id(), 6);
+      let err = command.await.unwrap_err();
+      assert_eq!(err.id(), 5);
+      assert!(matches!(
+        err.original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceCommandCancelled(5))
+      ));
+      sleep(Duration::from_millis(300)).await;
+      assert!(device.receiver.try_recv().is_err());
+
+      // Cancelling something that's already been answered is fine.
+      server
+        .parse_message(message::CancelCmd::new(5).into())
+        .await
+        .expect("Test, assuming infallible.");
+      return;
+    }
+  }
+  panic!("Device was never added.");
+}
+
+#[tokio::test]
+async fn test_server_cancel_requires_capability() {
+  let (server, _device) = test_server_with_device("Massage Demo", false).await;
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(server
+    .parse_message(message::CancelCmd::new(1).into())
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_server_scalar_loop() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let vibrate = |feature, level| {
+        message::ScalarSubcommand::new(feature, level, message::ActuatorType::Vibrate)
+      };
+      assert!(server
+        .parse_message(
+          message::ScalarLoopCmd::new(
+            index,
+            vec![message::ScalarLoopStep::new(50, vec![vibrate(5, 1.0)])]
+          )
+          .into()
+        )
+        .await
+        .is_err());
+      // Total loop length has to fit in a u32 of milliseconds.
+      assert!(server
+        .parse_message(
+          message::ScalarLoopCmd::new(
+            index,
+            vec![
+              message::ScalarLoopStep::new(u32::MAX, vec![vibrate(0, 1.0)]),
+              message::ScalarLoopStep::new(u32::MAX, vec![vibrate(0, 0.0)]),
+            ]
+          )
+          .into()
+        )
+        .await
+        .is_err());
+      server
+        .parse_message(
+          message::ScalarLoopCmd::new(
+            index,
+            vec![
+              message::ScalarLoopStep::new(50, vec![vibrate(0, 1.0)]),
+              message::ScalarLoopStep::new(50, vec![vibrate(0, 0.0)]),
+            ],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      // Loop should keep cycling without any further messages.
+      for expected in [
+        vec![0xF1, 127],
+        vec![0xF1, 0],
+        vec![0xF1, 127],
+        vec![0xF1, 0],
+      ] {
+        loop {
+          let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+            .await
+            .expect("Loop should be sending commands to the device.")
+            .expect("Test, assuming infallible.");
+          if let HardwareCommand::Write(write_cmd) = cmd {
+            if *write_cmd.data() == expected {
+              break;
+            }
+          }
+        }
+      }
+      // A new scalar command takes over from the loop.
+      server
+        .parse_message(message::ScalarCmd::new(index, vec![vibrate(0, 0.5)]).into())
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(200)).await;
+      while let Ok(cmd) = device.receiver.try_recv() {
+        if let HardwareCommand::Write(write_cmd) = cmd {
+          assert_ne!(*write_cmd.data(), vec![0xF1, 127]);
+        }
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_stroke_generator() {
+  let (server, mut device) = test_server_with_device("Onyx+", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      // 300 strokes per minute is 100ms per move for a triangle wave.
+      server
+        .parse_message(
+          message::StrokeCmd::new(index, 0.0, 1.0, 300.0, message::StrokeWaveform::Triangle).into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      // Strokes should keep going back and forth without any further messages.
+      for expected_position in [99, 0, 99, 0] {
+        loop {
+          let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+            .await
+            .expect("Strokes should be sending commands to the device.")
+            .expect("Test, assuming infallible.");
+          if let HardwareCommand::Write(write_cmd) = cmd {
+            if write_cmd.data()[3] == expected_position {
+              break;
+            }
+          }
+        }
+      }
+      // A new linear command takes over from the strokes.
+      server
+        .parse_message(
+          message::LinearCmd::new(index, vec![message::VectorSubcommand::new(0, 100, 0.5)]).into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      // Let any stroke move that was already in flight land before checking.
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+      sleep(Duration::from_millis(300)).await;
+      while let Ok(cmd) = device.receiver.try_recv() {
+        if let HardwareCommand::Write(write_cmd) = cmd {
+          assert_eq!(write_cmd.data()[3], 49);
+        }
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_lock() {
+  let (server, _device) = test_server_with_device("Massage Demo", false).await;
+  let other_session = server.new_session();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  for session in [&server, &other_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let vibrate = || {
+        message::ScalarCmd::new(
+          index,
+          vec![message::ScalarSubcommand::new(
+            0,
+            0.5,
+            message::ActuatorType::Vibrate,
+          )],
+        )
+        .into()
+      };
+      server
+        .parse_message(message::DeviceLockCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        server.device_manager().device_lock_owner(index),
+        Some(server.session_id())
+      );
+      // Owner can keep sending commands, other sessions can't, and can't take the lock.
+      server
+        .parse_message(vibrate())
+        .await
+        .expect("Test, assuming infallible.");
+      for msg in [
+        vibrate(),
+        message::DeviceLockCmd::new(index).into(),
+        message::DeviceUnlockCmd::new(index).into(),
+      ] {
+        assert!(matches!(
+          other_session
+            .parse_message(msg)
+            .await
+            .unwrap_err()
+            .original_error(),
+          ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceLocked(_))
+        ));
+      }
+      // Stops are always allowed.
+      other_session
+        .parse_message(message::StopDeviceCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      // Hand the device over.
+      server
+        .parse_message(message::DeviceUnlockCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      other_session
+        .parse_message(message::DeviceLockCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(server.parse_message(vibrate()).await.is_err());
+      // Disconnecting releases the lock.
+      other_session
+        .disconnect()
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(server.device_manager().device_lock_owner(index), None);
+      server
+        .parse_message(vibrate())
+        .await
+        .expect("Test, assuming infallible.");
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_lock_stops_other_session_playback() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let other_session = server.new_session();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  for session in [&server, &other_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let pattern = r#"{
+        "version": 1,
+        "loop": true,
+        "sections": [
+          { "label": "pulse", "duration": 100, "tracks": [
+            { "actuator": "Vibrate", "keyframes": [{ "at": 0, "value": 1.0 }, { "at": 50, "value": 0.2 }] }
+          ] }
+        ]
+      }"#;
+      server
+        .parse_message(message::PatternLoadCmd::new(index, pattern).into())
+        .await
+        .expect("Test, assuming infallible.");
+      server
+        .parse_message(message::PatternPlayCmd::new(index, None).into())
+        .await
+        .expect("Test, assuming infallible.");
+      tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+        .await
+        .expect("Pattern should be sending commands to the device.")
+        .expect("Test, assuming infallible.");
+      other_session
+        .parse_message(message::DeviceLockCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      // Let any pattern step that was already in flight land before checking.
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+      sleep(Duration::from_millis(300)).await;
+      assert!(
+        device.receiver.try_recv().is_err(),
+        "Pattern kept writing to a device locked by another session"
+      );
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_connection_scope() {
+  let (server, mut device) = test_server_with_device("Massage Demo", true).await;
+  let control_session = server.new_session_with_scope(ButtplugConnectionScope::Control);
+  let list_session = server.new_session_with_scope(ButtplugConnectionScope::DeviceList);
+  assert_eq!(server.connection_scope(), ButtplugConnectionScope::Admin);
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  for session in [&server, &control_session, &list_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  assert!(matches!(
+    list_session
+      .parse_message(message::StartScanning::default().into())
+      .await
+      .unwrap_err()
+      .original_error(),
+    ButtplugError::ButtplugMessageError(ButtplugMessageError::InsufficientScope(..))
+  ));
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let vibrate = || {
+        message::ScalarCmd::new(
+          index,
+          vec![message::ScalarSubcommand::new(
+            0,
+            0.5,
+            message::ActuatorType::Vibrate,
+          )],
+        )
+        .into()
+      };
+      // Control can move the device, but can't send raw commands or change settings.
+      control_session
+        .parse_message(vibrate())
+        .await
+        .expect("Test, assuming infallible.");
+      for msg in [
+        message::RawWriteCmd::new(index, Endpoint::Tx, &[0x0], false).into(),
+        message::DeviceDisplayNameCmd::new(index, Some("Toy")).into(),
+      ] {
+        assert!(matches!(
+          control_session
+            .parse_message(msg)
+            .await
+            .unwrap_err()
+            .original_error(),
+          ButtplugError::ButtplugMessageError(ButtplugMessageError::InsufficientScope(..))
+        ));
+      }
+      // Device list only can see devices, and stop them.
+      list_session
+        .parse_message(message::RequestDeviceList::default().into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(list_session.parse_message(vibrate()).await.is_err());
+      list_session
+        .parse_message(message::StopDeviceCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      // Admin can do everything.
+      server
+        .parse_message(message::RawWriteCmd::new(index, Endpoint::Tx, &[0x0], false).into())
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      let mut raw_written = false;
+      while let Ok(cmd) = device.receiver.try_recv() {
+        if let HardwareCommand::Write(write_cmd) = cmd {
+          raw_written |= *write_cmd.data() == vec![0x0];
+        }
+      }
+      assert!(raw_written);
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_disconnect_stops_session_devices() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let other_session = server.new_session();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  for session in [&server, &other_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      other_session
+        .parse_message(
+          message::ScalarCmd::new(
+            index,
+            vec![message::ScalarSubcommand::new(
+              0,
+              0.5,
+              message::ActuatorType::Vibrate,
+            )],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        server.device_manager().device_commander(index),
+        Some(other_session.session_id())
+      );
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+      // The session that disconnects wasn't commanding the device, so it should keep running.
+      server
+        .disconnect()
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      assert!(device.receiver.try_recv().is_err());
+      // Once the commanding session disconnects, the device is stopped.
+      other_session
+        .disconnect()
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      assert!(matches!(
+        device.receiver.try_recv(),
+        Ok(HardwareCommand::Write(_))
+      ));
+      assert_eq!(server.device_manager().device_commander(index), None);
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_session_resumption() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .session_resumption_window(Duration::from_secs(60))
+    .finish()
+    .expect("Test, assuming infallible.");
+  let handshake = |token: Option<String>| {
+    let mut rsi =
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    rsi.set_resumption_token(token);
+    let fut = server.parse_message(rsi.into());
+    async move {
+      match fut.await.expect("Test, assuming infallible.") {
+        ButtplugServerMessage::ServerInfo(info) => info.resumption_token().clone(),
+        msg => panic!("Expected ServerInfo, got {:?}", msg),
+      }
+    }
+  };
+  let token = handshake(None).await;
+  assert!(token.is_some());
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      server
+        .parse_message(message::DeviceLockCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      server
+        .parse_message(
+          message::ScalarCmd::new(
+            index,
+            vec![message::ScalarSubcommand::new(
+              0,
+              0.5,
+              message::ActuatorType::Vibrate,
+            )],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+
+      // Dropping the connection leaves the device running and locked while we wait for the client.
+      server
+        .disconnect()
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      assert!(device.receiver.try_recv().is_err());
+      assert!(!server.connected());
+
+      // Reconnecting with the token picks the session back up.
+      let new_token = handshake(token).await;
+      assert!(new_token.is_some());
+      sleep(Duration::from_millis(100)).await;
+      assert!(device.receiver.try_recv().is_err());
+      assert_eq!(
+        server.device_manager().device_lock_owner(index),
+        Some(server.session_id())
+      );
+
+      // A client without the token gets a clean session.
+      server
+        .disconnect()
+        .await
+        .expect("Test, assuming infallible.");
+      handshake(None).await;
+      sleep(Duration::from_millis(100)).await;
+      assert!(matches!(
+        device.receiver.try_recv(),
+        Ok(HardwareCommand::Write(_))
+      ));
+      assert_eq!(server.device_manager().device_lock_owner(index), None);
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_end_session_skips_resumption() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .session_resumption_window(Duration::from_secs(60))
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  match server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.")
+  {
+    ButtplugServerMessage::ServerInfo(info) => assert!(info.resumption_token().is_some()),
+    msg => panic!("Expected ServerInfo, got {:?}", msg),
+  }
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      server
+        .parse_message(message::DeviceLockCmd::new(index).into())
+        .await
+        .expect("Test, assuming infallible.");
+      server
+        .parse_message(
+          message::ScalarCmd::new(
+            index,
+            vec![message::ScalarSubcommand::new(
+              0,
+              0.5,
+              message::ActuatorType::Vibrate,
+            )],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+
+      // Ending the session means nobody is coming back for it, so it's cleaned up right away.
+      server
+        .parse_message(message::EndSessionCmd::default().into())
+        .await
+        .expect("Test, assuming infallible.");
+      server
+        .disconnect()
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      assert!(matches!(
+        device.receiver.try_recv(),
+        Ok(HardwareCommand::Write(_))
+      ));
+      assert_eq!(server.device_manager().device_lock_owner(index), None);
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_stops_devices_when_dropped_while_connected() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  // Keep the device manager alive, so only the session going away can stop the device.
+  let device_manager = server.device_manager();
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      server
+        .parse_message(
+          message::ScalarCmd::new(
+            da.device_index(),
+            vec![message::ScalarSubcommand::new(
+              0,
+              0.5,
+              message::ActuatorType::Vibrate,
+            )],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+
+      // No disconnect, as if the task holding the server panicked.
+      drop(server);
+      sleep(Duration::from_millis(100)).await;
+      assert!(matches!(
+        device.receiver.try_recv(),
+        Ok(HardwareCommand::Write(_))
+      ));
+      assert_eq!(device_manager.device_commander(da.device_index()), None);
+      return;
+    }
+  }
+}
+
+#[test]
+fn test_server_dropped_while_connected_outside_runtime() {
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()
+    .expect("Test, assuming infallible.");
+  let server = runtime.block_on(async {
+    let server = ButtplugServerBuilder::default()
+      .finish()
+      .expect("Test, assuming infallible.");
+    server
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    server
+  });
+  // There's no runtime to spawn cleanup on, which should be logged instead of panicking.
+  assert!(server.connected());
+  drop(server);
+}
+
+#[tokio::test]
+async fn test_server_probes_unknown_device_model() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Probe Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb",
+              "rxblemodel": "0000ff02-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Probe Test Default",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        },
+        "configurations": [
+          {
+            "identifier": [
+              "VIVI-2"
+            ],
+            "name": "Probe Test Two Motor",
+            "messages": {
+              "ScalarCmd": [
+                {
+                  "StepRange": [0, 127],
+                  "ActuatorType": "Vibrate"
+                },
+                {
+                  "StepRange": [0, 127],
+                  "ActuatorType": "Vibrate"
+                }
+              ]
+            }
+          }
+        ]
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new("Probe Test", None));
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      TestHardwareNotification::new(Endpoint::RxBLEModel, b"VIVI-2\0\0"),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(da.device_name(), "Probe Test Two Motor");
+      assert_eq!(
+        da.device_messages()
+          .scalar_cmd()
+          .as_ref()
+          .expect("Test, assuming infallible.")
+          .len(),
+        2
+      );
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_identifies_white_label_device_by_manufacturer() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Label Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Label Test Default",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        },
+        "configurations": [
+          {
+            "identifier": [
+              "Acme/X1"
+            ],
+            "name": "Acme X1"
+          },
+          {
+            "identifier": [
+              "X1"
+            ],
+            "name": "Generic X1"
+          }
+        ]
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  for (address, manufacturer) in [("acme-addr", "Acme"), ("other-addr", "Other")] {
+    let mut information = DeviceInformation::default();
+    information
+      .set_manufacturer_name(Some(manufacturer.to_owned()))
+      .set_model_number(Some("X1".to_owned()));
+    builder.add_test_device(
+      TestDeviceIdentifier::new("Label Test", Some(address.to_owned()))
+        .set_device_information(information),
+    );
+  }
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut names = vec![];
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      names.push(da.device_name().clone());
+      if names.len() == 2 {
+        break;
+      }
+    }
+  }
+  names.sort();
+  assert_eq!(names, vec!["Acme X1".to_owned(), "Generic X1".to_owned()]);
+}
+
+#[tokio::test]
+async fn test_server_identifies_rebranded_device() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Rebrand Base"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Rebrand Default",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        },
+        "configurations": [
+          {
+            "identifier": [
+              "Rebrand Base"
+            ],
+            "name": "Rebrand Two Motor",
+            "messages": {
+              "ScalarCmd": [
+                {
+                  "StepRange": [0, 127],
+                  "ActuatorType": "Vibrate"
+                },
+                {
+                  "StepRange": [0, 127],
+                  "ActuatorType": "Vibrate"
+                }
+              ]
+            }
+          }
+        ],
+        "rebrands": [
+          {
+            "names": [
+              "Acme Buzz"
+            ],
+            "base-identifier": "Rebrand Base",
+            "name": "Acme Buzz"
+          },
+          {
+            "names": [
+              "Other Buzz"
+            ],
+            "base-identifier": "Rebrand Base"
+          }
+        ]
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  builder.add_test_device(&TestDeviceIdentifier::new("Acme Buzz", None));
+  builder.add_test_device(&TestDeviceIdentifier::new("Other Buzz", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut names = vec![];
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(
+        da.device_messages()
+          .scalar_cmd()
+          .as_ref()
+          .expect("Test, assuming infallible.")
+          .len(),
+        2
+      );
+      names.push(da.device_name().clone());
+      if names.len() == 2 {
+        break;
+      }
+    }
+  }
+  names.sort();
+  assert_eq!(
+    names,
+    vec!["Acme Buzz".to_owned(), "Rebrand Two Motor".to_owned()]
+  );
+}
+
+#[tokio::test]
+async fn test_server_identifies_device_by_name_pattern() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Pattern Test"
+          ],
+          "name-patterns": [
+            "PT-(?<identifier>[A-Z]+)-[0-9]+"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Pattern Test Default",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        },
+        "configurations": [
+          {
+            "identifier": [
+              "AB"
+            ],
+            "name": "Pattern Test AB"
+          }
+        ]
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  for name in ["PT-AB-1234", "PT-CD-5678"] {
+    builder.add_test_device(&TestDeviceIdentifier::new(name, None));
+  }
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut names = vec![];
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      names.push(da.device_name().clone());
+      if names.len() == 2 {
+        break;
+      }
+    }
+  }
+  names.sort();
+  assert_eq!(
+    names,
+    vec![
+      "Pattern Test AB".to_owned(),
+      "Pattern Test Default".to_owned()
+    ]
+  );
+}
+
+#[tokio::test]
+async fn test_server_device_version_in_device_info() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Version Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            },
+            "0000180a-0000-1000-8000-00805f9b34fb": {
+              "rxblefirmwarerevision": "00002a26-0000-1000-8000-00805f9b34fb",
+              "rxblehardwarerevision": "00002a27-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Version Test Device",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new("Version Test", None));
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      TestHardwareNotification::new(Endpoint::RxBLEFirmwareRevision, b"1.2.3\0"),
+      TestHardwareNotification::new(Endpoint::RxBLEHardwareRevision, b" rev B "),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let expected = message::DeviceVersion::new(&Some("1.2.3".to_owned()), &Some("rev B".to_owned()));
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(da.device_version(), &Some(expected.clone()));
+      let list = server
+        .parse_message(message::RequestDeviceList::default().into())
+        .await
+        .expect("Test, assuming infallible.");
+      if let ButtplugServerMessage::DeviceList(list) = list {
+        assert_eq!(list.devices()[0].device_version(), &Some(expected));
+      } else {
+        panic!("Should've gotten a DeviceList");
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_battery_polling() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Battery Poll Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            },
+            "0000180f-0000-1000-8000-00805f9b34fb": {
+              "rxblebattery": "00002a19-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Battery Poll Test Device",
+          "battery-polling-interval": 1000,
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 127],
+                "ActuatorType": "Vibrate"
+              }
+            ],
+            "SensorReadCmd": [
+              {
+                "FeatureDescriptor": "Battery Level",
+                "SensorType": "Battery",
+                "SensorRange": [[0, 100]]
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new("Battery Poll Test", None));
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[75]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    match msg {
+      ButtplugServerMessage::DeviceAdded(da) => device_index = Some(da.device_index()),
+      ButtplugServerMessage::SensorReading(reading) => {
+        assert_eq!(Some(reading.device_index()), device_index);
+        assert_eq!(reading.sensor_type(), message::SensorType::Battery);
+        assert_eq!(reading.data(), &vec![75]);
+        return;
+      }
+      _ => {}
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_low_battery_warning() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "aneros": {
+        "btle": {
+          "names": [
+            "Low Battery Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            },
+            "0000180f-0000-1000-8000-00805f9b34fb": {
+              "rxblebattery": "00002a19-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Low Battery Test Device",
+          "messages": {
+            "SensorReadCmd": [
+              {
+                "FeatureDescriptor": "Battery Level",
+                "SensorType": "Battery",
+                "SensorRange": [[0, 100]]
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new("Low Battery Test", None));
+  device
+    .sender
+    .send(TestHardwareEvent::Reads(vec![
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[50]),
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[10]),
+      TestHardwareNotification::new(Endpoint::RxBLEBattery, &[5]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .low_battery_threshold(0.2)
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      for _ in 0..3 {
+        server
+          .parse_message(
+            message::SensorReadCmd::new(da.device_index(), 0, message::SensorType::Battery).into(),
+          )
+          .await
+          .expect("Test, assuming infallible.");
+      }
+      break;
+    }
+  }
+  // Only the first reading under the threshold warns.
+  let mut warnings = vec![];
+  while let Some(Some(msg)) = recv.next().now_or_never() {
+    if let ButtplugServerMessage::LowBatteryWarning(warning) = msg {
+      warnings.push(warning.battery_level());
+    }
+  }
+  assert_eq!(warnings, vec![0.1]);
+}
+
+const DFU_TEST_DEVICE_CONFIG: &str = r#"{
+  "version": {
+    "major": 2,
+    "minor": 25
+  },
+  "protocols": {
+    "aneros": {
+      "btle": {
+        "names": [
+          "DFU Test"
+        ],
+        "services": {
+          "0000ff00-0000-1000-8000-00805f9b34fb": {
+            "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+          },
+          "0000fe59-0000-1000-8000-00805f9b34fb": {
+            "dfucontrol": "8ec90001-f315-4f60-9fb8-838830daea50",
+            "dfupacket": "8ec90002-f315-4f60-9fb8-838830daea50"
+          }
+        }
+      },
+      "defaults": {
+        "name": "DFU Test Device",
+        "messages": {
+          "ScalarCmd": [
+            {
+              "StepRange": [0, 127],
+              "ActuatorType": "Vibrate"
+            }
+          ]
+        }
+      }
+    }
+  }
+}"#;
+
+fn test_crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 {
+        (crc >> 1) ^ 0xEDB8_8320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+/// Answers control point requests like a Nordic Secure DFU bootloader, until `firmware_len` bytes
+/// of firmware have been executed. Returns the init packet and firmware it received.
+async fn emulate_nordic_bootloader(
+  mut device: TestDeviceChannelHost,
+  firmware_len: usize,
+) -> (Vec<u8>, Vec<u8>) {
+  let (mut init_packet, mut firmware, mut object) = (vec![], vec![], vec![]);
+  let mut object_type = 0;
+  while let Some(command) = device.receiver.recv().await {
+    let HardwareCommand::Write(write) = command else {
+      continue;
+    };
+    if write.endpoint() == Endpoint::DfuPacket {
+      object.extend_from_slice(write.data());
+      continue;
+    }
+    if write.endpoint() != Endpoint::DfuControl {
+      continue;
+    }
+    let request = write.data();
+    let mut response = vec![0x60, request[0], 0x01];
+    match request[0] {
+      // Select, reply with the max object size, offset and CRC.
+      0x06 => {
+        object_type = request[1];
+        let max_size: u32 = if object_type == 0x01 { 256 } else { 4096 };
+        response.extend_from_slice(&max_size.to_le_bytes());
+        response.extend_from_slice(&[0; 8]);
+      }
+      // Create
+      0x01 => object.clear(),
+      // Calculate checksum, over everything received for the selected object type.
+      0x03 => {
+        let mut received = if object_type == 0x01 {
+          init_packet.clone()
+        } else {
+          firmware.clone()
+        };
+        received.extend_from_slice(&object);
+        response.extend_from_slice(&(received.len() as u32).to_le_bytes());
+        response.extend_from_slice(&test_crc32(&received).to_le_bytes());
+      }
+      // Execute
+      0x04 => {
+        if object_type == 0x01 {
+          init_packet.append(&mut object);
+        } else {
+          firmware.append(&mut object);
+        }
+      }
+      _ => {}
+    }
+    device
+      .sender
+      .send(TestHardwareEvent::Notifications(vec![
+        TestHardwareNotification::new(Endpoint::DfuControl, &response),
+      ]))
+      .await
+      .expect("Test, assuming infallible.");
+    if firmware.len() == firmware_len {
+      break;
+    }
+  }
+  (init_packet, firmware)
+}
+
+#[tokio::test]
+async fn test_server_device_firmware_update() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = Some(builder.add_test_device(&TestDeviceIdentifier::new("DFU Test", None)));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(DFU_TEST_DEVICE_CONFIG.to_owned()))
+    .allow_firmware_updates()
+    .connection_scope(ButtplugConnectionScope::Admin)
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let init_packet: Vec<u8> = (0..100).collect();
+      // More than one data object, with a partial last one.
+      let firmware: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+      let bootloader = tokio::spawn(emulate_nordic_bootloader(
+        device.take().expect("Test, assuming infallible."),
+        firmware.len(),
+      ));
+      let reply = server
+        .parse_message(
+          message::DeviceFirmwareUpdateCmd::new(da.device_index(), &init_packet, &firmware).into(),
+        )
+        .await;
+      assert!(matches!(reply, Ok(ButtplugServerMessage::Ok(_))));
+      let (received_init_packet, received_firmware) =
+        bootloader.await.expect("Test, assuming infallible.");
+      assert_eq!(received_init_packet, init_packet);
+      assert_eq!(received_firmware, firmware);
+      // The device restarts into its new firmware, so it's disconnected after the update.
+      while let Some(msg) = recv.next().await {
+        if let ButtplugServerMessage::DeviceRemoved(removed) = msg {
+          assert_eq!(removed.device_index(), da.device_index());
+          return;
+        }
+      }
+    }
+  }
+  panic!("Device was not removed after the firmware update.");
+}
+
+#[tokio::test]
+async fn test_server_device_firmware_update_not_allowed() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new("DFU Test", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(DFU_TEST_DEVICE_CONFIG.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert!(server
+        .parse_message(message::DeviceFirmwareUpdateCmd::new(da.device_index(), &[1], &[2]).into())
+        .await
+        .is_err());
+      // The device is untouched and still takes commands.
+      server
+        .parse_message(message::StopDeviceCmd::new(da.device_index()).into())
+        .await
+        .expect("Test, assuming infallible.");
+      return;
+    }
+  }
+}
+
+#[cfg(feature = "audit-log")]
+#[tokio::test]
+async fn test_server_audit_log() {
+  use buttplug::server::audit_log::read_audit_log;
+
+  let audit_log_path = std::env::temp_dir().join("buttplug-test-audit-log.jsonl");
+  let _ = std::fs::remove_file(&audit_log_path);
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .audit_log_path(&audit_log_path)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let list_session = server.new_session_with_scope(ButtplugConnectionScope::DeviceList);
+  for (session, name) in [(&server, "Audited Client"), (&list_session, "List Client")] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new(name, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let vibrate: message::ButtplugClientMessage = message::ScalarCmd::new(
+        da.device_index(),
+        vec![message::ScalarSubcommand::new(
+          0,
+          0.5,
+          message::ActuatorType::Vibrate,
+        )],
+      )
+      .into();
+      server
+        .parse_message(vibrate.clone())
+        .await
+        .expect("Test, assuming infallible.");
+      // Refused commands are logged too.
+      assert!(list_session.parse_message(vibrate).await.is_err());
+      break;
+    }
+  }
+
+  // Only device commands are recorded, not the handshake or scanning.
+  let entries = read_audit_log(&audit_log_path).expect("Test, assuming infallible.");
+  assert_eq!(entries.len(), 2);
+  assert_eq!(entries[0].session_id(), server.session_id());
+  assert_eq!(entries[0].client_name().as_deref(), Some("Audited Client"));
+  assert!(entries[0].message().get("ScalarCmd").is_some());
+  assert!(entries[0].error().is_none());
+  assert_eq!(entries[1].session_id(), list_session.session_id());
+  assert_eq!(entries[1].client_name().as_deref(), Some("List Client"));
+  assert!(entries[1].error().is_some());
+  let _ = std::fs::remove_file(&audit_log_path);
+}
+
+#[tokio::test]
+async fn test_server_intensity_ceiling() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let control_session = server.new_session_with_scope(ButtplugConnectionScope::Control);
+  for session in [&server, &control_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let vibrate = |speed| {
+        message::ScalarCmd::new(
+          index,
+          vec![message::ScalarSubcommand::new(
+            0,
+            speed,
+            message::ActuatorType::Vibrate,
+          )],
+        )
+        .into()
+      };
+      let mut sent = vec![];
+      for speed in [0.5, 1.0] {
+        control_session
+          .parse_message(vibrate(speed))
+          .await
+          .expect("Test, assuming infallible.");
+        sleep(Duration::from_millis(100)).await;
+        sent.push(
+          device
+            .receiver
+            .try_recv()
+            .expect("Test, assuming infallible."),
+        );
+        control_session
+          .parse_message(message::StopDeviceCmd::new(index).into())
+          .await
+          .expect("Test, assuming infallible.");
+        sleep(Duration::from_millis(100)).await;
+        while device.receiver.try_recv().is_ok() {}
+        if sent.len() == 1 {
+          // Only admins can change the ceiling.
+          assert!(control_session
+            .parse_message(message::IntensityCeilingCmd::new(1.0).into())
+            .await
+            .is_err());
+          server
+            .parse_message(message::IntensityCeilingCmd::new(0.5).into())
+            .await
+            .expect("Test, assuming infallible.");
+        }
+      }
+      // Full speed under a 50% ceiling should be sent the same as asking for 50%.
+      assert_eq!(sent[0], sent[1]);
+      assert_eq!(server.device_manager().intensity_ceiling(), 0.5);
+
+      // Setting a ceiling brings devices already running over it down to it.
+      server
+        .parse_message(message::IntensityCeilingCmd::new(1.0).into())
+        .await
+        .expect("Test, assuming infallible.");
+      control_session
+        .parse_message(vibrate(1.0))
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+      server
+        .parse_message(message::IntensityCeilingCmd::new(0.5).into())
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      assert_eq!(
+        device
+          .receiver
+          .try_recv()
+          .expect("Test, assuming infallible."),
+        sent[0]
+      );
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_kick_client() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let other_session = server.new_session_with_scope(ButtplugConnectionScope::Control);
+  for session in [&server, &other_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let recv = server.event_stream();
+  let other_recv = other_session.event_stream();
+  pin_mut!(recv, other_recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      other_session
+        .parse_message(
+          message::ScalarCmd::new(
+            index,
+            vec![message::ScalarSubcommand::new(
+              0,
+              0.5,
+              message::ActuatorType::Vibrate,
+            )],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+
+      // Only admins can kick, and only sessions that exist.
+      let kick = message::KickClientCmd::new(other_session.session_id());
+      assert!(other_session
+        .parse_message(message::KickClientCmd::new(server.session_id()).into())
+        .await
+        .is_err());
+      assert!(server
+        .parse_message(message::KickClientCmd::new(1000).into())
+        .await
+        .is_err());
+      assert_eq!(
+        server.device_manager().session_ids(),
+        vec![server.session_id(), other_session.session_id()]
+      );
+      server
+        .parse_message(kick.into())
+        .await
+        .expect("Test, assuming infallible.");
+
+      // The kicked client is told why, and the device it was commanding is stopped.
+      let error = tokio::time::timeout(Duration::from_secs(1), async {
+        while let Some(msg) = other_recv.next().await {
+          if let ButtplugServerMessage::Error(error) = msg {
+            return error;
+          }
+        }
+        panic!("Event stream ended before kick error.");
+      })
+      .await
+      .expect("Kicked session should get an error.");
+      assert!(matches!(
+        error.original_error(),
+        ButtplugError::ButtplugUnknownError(ButtplugUnknownError::ClientKicked)
+      ));
+      assert!(!other_session.connected());
+      assert!(server.connected());
+      sleep(Duration::from_millis(100)).await;
+      assert!(matches!(
+        device.receiver.try_recv(),
+        Ok(HardwareCommand::Write(_))
+      ));
+      assert_eq!(server.device_manager().device_commander(index), None);
+      drop(other_session);
+      assert_eq!(
+        server.device_manager().session_ids(),
+        vec![server.session_id()]
+      );
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_management_messages() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let other_session = server.new_session_with_scope(ButtplugConnectionScope::Control);
+  for session in [&server, &other_session] {
+    session
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  let recv = server.event_stream();
+  let other_recv = other_session.event_stream();
+  pin_mut!(recv, other_recv);
+
+  // Scanning can be turned off for everyone.
+  assert!(other_session
+    .parse_message(message::ScanningEnabledCmd::new(false).into())
+    .await
+    .is_err());
+  server
+    .parse_message(message::ScanningEnabledCmd::new(false).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(!server.device_manager().scanning_enabled());
+  assert!(other_session
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_err());
+  server
+    .parse_message(message::ScanningEnabledCmd::new(true).into())
+    .await
+    .expect("Test, assuming infallible.");
+  other_session
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let vibrate =
+        |level| message::ScalarSubcommand::new(0, level, message::ActuatorType::Vibrate);
+      other_session
+        .parse_message(
+          message::ScalarLoopCmd::new(
+            index,
+            vec![
+              message::ScalarLoopStep::new(50, vec![vibrate(1.0)]),
+              message::ScalarLoopStep::new(50, vec![vibrate(0.0)]),
+            ],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(150)).await;
+      assert!(device.receiver.try_recv().is_ok());
+
+      // Force stopping halts the other session's loop, where StopAllDevices wouldn't.
+      server
+        .parse_message(message::ForceStopAllDevicesCmd::default().into())
+        .await
+        .expect("Test, assuming infallible.");
+      sleep(Duration::from_millis(100)).await;
+      while device.receiver.try_recv().is_ok() {}
+      sleep(Duration::from_millis(200)).await;
+      assert!(device.receiver.try_recv().is_err());
+
+      // Shutdown disconnects everyone else, and tells whoever is hosting the server.
+      let shutdown_requested = server.shutdown_requested();
+      assert!(other_session
+        .parse_message(message::ServerShutdownCmd::default().into())
+        .await
+        .is_err());
+      server
+        .parse_message(message::ServerShutdownCmd::default().into())
+        .await
+        .expect("Test, assuming infallible.");
+      tokio::time::timeout(Duration::from_secs(1), shutdown_requested)
+        .await
+        .expect("Shutdown should be requested.");
+      let error = tokio::time::timeout(Duration::from_secs(1), async {
+        while let Some(msg) = other_recv.next().await {
+          if let ButtplugServerMessage::Error(error) = msg {
+            return error;
+          }
+        }
+        panic!("Event stream ended before shutdown error.");
+      })
+      .await
+      .expect("Other session should get an error.");
+      assert!(matches!(
+        error.original_error(),
+        ButtplugError::ButtplugUnknownError(ButtplugUnknownError::ServerShuttingDown)
+      ));
+      assert!(!other_session.connected());
+      assert!(server.connected());
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_disable_protocol() {
+  let (server, _device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  // Disabling the protocol disconnects devices using it.
+  server
+    .device_manager()
+    .set_protocol_enabled("aneros", false)
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(!server.device_manager().protocol_enabled("aneros"));
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceRemoved(dr) = msg {
+      assert_eq!(dr.device_index(), device_index);
+      break;
+    }
+  }
+  assert!(matches!(
+    server
+      .device_manager()
+      .set_protocol_enabled("not-a-protocol", false)
+      .await,
+    Err(ButtplugServerError::ProtocolDoesNotExist(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_builder_disabled_protocol() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .disabled_protocol("aneros")
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    match msg {
+      ButtplugServerMessage::DeviceAdded(_) => {
+        panic!("Disabled protocol device should not be added")
+      }
+      ButtplugServerMessage::ScanningFinished(_) => break,
+      _ => {}
+    }
+  }
+}
+
+async fn display_name_test_server(
+  user_config_path: &std::path::Path,
+) -> (
+  buttplug::server::ButtplugServer,
+  impl futures::Stream<Item = ButtplugServerMessage>,
+  util::test_device_manager::TestDeviceChannelHost,
+) {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("display-name-addr".to_owned()),
+  ));
+  let server = ButtplugServerBuilder::default()
+    .connection_scope(ButtplugConnectionScope::Admin)
+    .comm_manager(builder)
+    .user_device_configuration_path(user_config_path)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  (server, recv, device)
+}
+
+#[tokio::test]
+async fn test_server_device_display_name() {
+  let user_config_path = std::env::temp_dir().join("buttplug-test-display-name-user-config.json");
+  let _ = std::fs::remove_file(&user_config_path);
+
+  let (server, recv, _device) = display_name_test_server(&user_config_path).await;
+  pin_mut!(recv);
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(da.device_display_name(), &None);
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  server
+    .parse_message(message::DeviceDisplayNameCmd::new(device_index, Some("Left Toy")).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(server
+    .parse_message(message::DeviceDisplayNameCmd::new(device_index + 1, Some("Right Toy")).into())
+    .await
+    .is_err());
+  let Ok(ButtplugServerMessage::DeviceList(list)) = server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+  else {
+    panic!("Should get a device list");
+  };
+  assert_eq!(
+    list.devices()[0].device_display_name(),
+    &Some("Left Toy".to_owned())
+  );
+  assert!(server
+    .device_manager()
+    .user_device_configuration_json()
+    .expect("Test, assuming infallible.")
+    .contains("Left Toy"));
+  server.shutdown().await.expect("Test, assuming infallible.");
+  drop(server);
+
+  // The display name was saved to the user config file, so a new server picks it up.
+  let (server, recv, _device) = display_name_test_server(&user_config_path).await;
+  pin_mut!(recv);
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(da.device_display_name(), &Some("Left Toy".to_owned()));
+      server
+        .parse_message(message::DeviceDisplayNameCmd::new(da.device_index(), None).into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        server
+          .device_manager()
+          .device_info(da.device_index())
+          .expect("Test, assuming infallible.")
+          .display_name(),
+        &None
+      );
+      break;
+    }
+  }
+  let saved_config =
+    std::fs::read_to_string(&user_config_path).expect("Test, assuming infallible.");
+  assert!(!saved_config.contains("Left Toy"));
+  let _ = std::fs::remove_file(&user_config_path);
+}
+
+#[tokio::test]
+async fn test_server_device_display_name_save_failure() {
+  // Saving fails, since the directory for the user config doesn't exist.
+  let user_config_path = std::env::temp_dir()
+    .join(format!("buttplug-test-missing-{}", std::process::id()))
+    .join("user-config.json");
+  let (server, recv, _device) = display_name_test_server(&user_config_path).await;
+  pin_mut!(recv);
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert!(server
+        .parse_message(
+          message::DeviceDisplayNameCmd::new(da.device_index(), Some("Left Toy")).into()
+        )
+        .await
+        .is_err());
+      // Nothing changes unless the name was saved.
+      assert_eq!(
+        server
+          .device_manager()
+          .device_info(da.device_index())
+          .expect("Test, assuming infallible.")
+          .display_name(),
+        &None
+      );
+      assert!(server
+        .device_manager()
+        .user_device_configuration_json()
+        .is_none());
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_metadata() {
+  let user_config_path = std::env::temp_dir().join("buttplug-test-metadata-user-config.json");
+  let _ = std::fs::remove_file(&user_config_path);
+  let metadata: HashMap<String, String> = [
+    ("color".to_owned(), "#ff00ff".to_owned()),
+    ("notes".to_owned(), "Charge before use".to_owned()),
+  ]
+  .into_iter()
+  .collect();
+
+  let (server, recv, _device) = display_name_test_server(&user_config_path).await;
+  pin_mut!(recv);
+  let mut device_index = None;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(da.device_metadata(), &None);
+      device_index = Some(da.device_index());
+      break;
+    }
+  }
+  let device_index = device_index.expect("Test, assuming infallible.");
+  server
+    .device_manager()
+    .set_device_metadata(device_index, Some(metadata.clone()))
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(server
+    .device_manager()
+    .set_device_metadata(device_index + 1, Some(metadata.clone()))
+    .await
+    .is_err());
+  // Metadata is saved to the user config, so it can't be arbitrarily large.
+  let oversized_metadata: HashMap<String, String> =
+    [("notes".to_owned(), "a".repeat(MAX_DEVICE_METADATA_SIZE))]
+      .into_iter()
+      .collect();
+  assert!(server
+    .device_manager()
+    .set_device_metadata(device_index, Some(oversized_metadata))
+    .await
+    .is_err());
+  let Ok(ButtplugServerMessage::DeviceList(list)) = server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+  else {
+    panic!("Should get a device list");
+  };
+  assert_eq!(list.devices()[0].device_metadata(), &Some(metadata.clone()));
+  server.shutdown().await.expect("Test, assuming infallible.");
+  drop(server);
+
+  // The metadata was saved to the user config file, so a new server picks it up.
+  let (server, recv, _device) = display_name_test_server(&user_config_path).await;
+  pin_mut!(recv);
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert_eq!(da.device_metadata(), &Some(metadata.clone()));
+      server
+        .device_manager()
+        .set_device_metadata(da.device_index(), None)
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        server
+          .device_manager()
+          .device_info(da.device_index())
+          .expect("Test, assuming infallible.")
+          .metadata(),
+        &None
+      );
+      break;
+    }
+  }
+  let saved_config =
+    std::fs::read_to_string(&user_config_path).expect("Test, assuming infallible.");
+  assert!(!saved_config.contains("Charge before use"));
+  let _ = std::fs::remove_file(&user_config_path);
+}
+
+#[tokio::test]
+async fn test_server_device_statistics() {
+  let (server, _device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let index = da.device_index();
+      let statistics = server
+        .device_manager()
+        .device_statistics(index)
+        .expect("Test, assuming infallible.");
+      assert_eq!(statistics.commands_sent(), 0);
+      for speed in [0.5, 0.75] {
+        server
+          .parse_message(
+            message::ScalarCmd::new(
+              index,
+              vec![message::ScalarSubcommand::new(
+                0,
+                speed,
+                message::ActuatorType::Vibrate,
+              )],
+            )
+            .into(),
+          )
+          .await
+          .expect("Test, assuming infallible.");
+      }
+      let statistics = server
+        .device_manager()
+        .device_statistics(index)
+        .expect("Test, assuming infallible.");
+      assert_eq!(statistics.commands_sent(), 2);
+      assert_eq!(statistics.write_failures(), 0);
+      assert_eq!(statistics.commands_dropped(), 0);
+      assert!(statistics.max_latency() >= statistics.average_latency());
+      assert!(server
+        .device_manager()
+        .device_statistics(index + 1)
+        .is_none());
+      return;
+    }
+  }
+  panic!("Device was never added.");
+}
+
+#[tokio::test]
+async fn test_server_duplicate_device_arbitration() {
+  // The same device, found by two comm managers that format its address differently.
+  let mut bluetooth_builder = TestDeviceCommunicationManagerBuilder::default();
+  bluetooth_builder.manager_name("TestBluetoothManager");
+  let _bluetooth_device = bluetooth_builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("AA:BB:CC:DD:EE:FF".to_owned()),
+  ));
+  let mut connect_builder = TestDeviceCommunicationManagerBuilder::default();
+  connect_builder.manager_name("TestConnectManager");
+  let _connect_device = connect_builder.add_test_device(&TestDeviceIdentifier::new(
+    "Massage Demo",
+    Some("aabbccddeeff".to_owned()),
+  ));
+  let mut builder = ButtplugServerBuilder::default();
+  builder
+    .comm_manager(bluetooth_builder)
+    .comm_manager(connect_builder)
+    .preferred_comm_manager("TestConnectManager");
+  let server = builder.finish().expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Whichever order the managers find the device in, the preferred connection should win.
+  sleep(Duration::from_millis(500)).await;
+  let Ok(ButtplugServerMessage::DeviceList(list)) = server
+    .parse_message(message::RequestDeviceList::default().into())
+    .await
+  else {
+    panic!("Should get a device list");
+  };
+  assert_eq!(list.devices().len(), 1);
+  let transport = list.devices()[0]
+    .device_transport()
+    .clone()
+    .expect("Server should always send transport info.");
+  assert_eq!(transport.address(), "aabbccddeeff");
+}
+
+#[tokio::test]
+async fn test_server_scanning_timeout() {
+  let mut comm_builder = TestDeviceCommunicationManagerBuilder::default();
+  comm_builder.scan_until_stopped();
+  let mut builder = ButtplugServerBuilder::default();
+  builder.comm_manager(comm_builder);
+  let server = builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::new_with_timeout(100).into())
+    .await
+    .expect("Test, assuming infallible.");
+  // The comm manager would scan forever, the timeout should stop it.
+  let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+    .await
+    .expect("Scanning should stop before the test timeout.");
+  assert!(matches!(
+    msg,
+    Some(ButtplugServerMessage::ScanningFinished(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_default_scanning_timeout() {
+  let mut comm_builder = TestDeviceCommunicationManagerBuilder::default();
+  comm_builder.scan_until_stopped();
+  let mut builder = ButtplugServerBuilder::default();
+  builder
+    .comm_manager(comm_builder)
+    .scanning_timeout(Duration::from_millis(100));
+  let server = builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+    .await
+    .expect("Scanning should stop before the test timeout.");
+  assert!(matches!(
+    msg,
+    Some(ButtplugServerMessage::ScanningFinished(_))
+  ));
+  // A timeout of 0 overrides the default, so scanning shouldn't stop on its own.
+  server
+    .parse_message(message::StartScanning::new_with_timeout(0).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(
+    tokio::time::timeout(Duration::from_millis(300), recv.next())
+      .await
+      .is_err()
+  );
+  server
+    .parse_message(message::StopScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let msg = tokio::time::timeout(Duration::from_secs(5), recv.next())
+    .await
+    .expect("Scanning should stop before the test timeout.");
+  assert!(matches!(
+    msg,
+    Some(ButtplugServerMessage::ScanningFinished(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_background_scanning() {
+  let mut comm_builder = TestDeviceCommunicationManagerBuilder::default();
+  comm_builder.scan_until_stopped();
+  let _device = comm_builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let mut builder = ButtplugServerBuilder::default();
+  builder.comm_manager(comm_builder).background_scanning();
+  let server = builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  // The device should show up without the client ever asking to scan. It may connect before we've
+  // subscribed to events, so check the device list instead.
+  let mut device_found = false;
+  for _ in 0..50 {
+    if let Ok(ButtplugServerMessage::DeviceList(list)) = server
+      .parse_message(message::RequestDeviceList::default().into())
+      .await
+    {
+      if !list.devices().is_empty() {
+        device_found = true;
+        break;
+      }
+    }
+    sleep(Duration::from_millis(100)).await;
+  }
+  assert!(device_found);
+  // A client scan takes over the background scan, and ends like any other client scan.
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StopScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Skip the DeviceAdded event, if it came after we subscribed.
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::ScanningFinished(_) = msg {
+        return;
+      }
+    }
+    panic!("Event stream ended before scanning finished.");
+  })
+  .await
+  .expect("Scanning should stop before the test timeout.");
+}
+
+/*
+#[cfg(target_os = "windows")]
+#[ignore = "Has weird timeout issues"]
+#[tokio::test]
+async fn test_repeated_address_additions() {
+    let mut server_builder = ButtplugServerBuilder::default();
+    let builder = TestDeviceCommunicationManagerBuilder::default();
+    let helper = builder.helper();
+    server_builder.comm_manager(builder);
+    let server = server_builder.finish().unwrap();
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    helper
+      .add_ble_device_with_address("Massage Demo", "SameAddress")
+      .await;
+    helper
+      .add_ble_device_with_address("Massage Demo", "SameAddress")
+      .await;
+    assert!(server
+      .parse_message(
+        messages::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into()
+      )
+      .await
+      .is_ok());
+    assert!(server
+      .parse_message(messages::StartScanning::default().into())
+      .await
+      .is_ok());
+    let mut device_index = None;
+    let mut device_removed_called = true;
+    while let Some(msg) = recv.next().await {
+      match msg {
+        ButtplugServerMessage::ScanningFinished(_) => continue,
+        ButtplugServerMessage::DeviceAdded(da) => {
+          assert_eq!(da.device_name(), "Aneros Vivi");
+          if device_index.is_none() {
+            device_index = Some(da.device_index());
+          } else {
+            assert!(device_removed_called);
+            assert_eq!(
+              da.device_index(),
+              device_index.expect("Test, assuming infallible.")
+            );
+            return;
+          }
+        }
+        ButtplugServerMessage::DeviceRemoved(dr) => {
+          assert_eq!(
+            dr.device_index(),
+            device_index.expect("Test, assuming infallible.")
+          );
+          device_removed_called = true;
+        }
+        _ => {
+          panic!(
+            "Returned message was not a DeviceAdded message or timed out: {:?}",
+            msg
+          );
+        }
+      }
+    }
+}
+*/
+
+#[tokio::test]
+async fn test_server_device_position_feedback() {
+  let (server, device) = test_server_with_device("Onyx+", false).await;
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let attrs = da.device_messages();
+      let sensors = attrs
+        .sensor_subscribe_cmd()
+        .as_ref()
+        .expect("Test, assuming infallible.");
+      assert_eq!(*sensors[0].sensor_type(), message::SensorType::Position);
+      break;
+    }
+  }
+  server
+    .parse_message(message::SensorSubscribeCmd::new(0, 0, message::SensorType::Position).into())
+    .await
+    .expect("Test, assuming infallible.");
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::Rx, &[0x03, 42]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::SensorReading(reading) = msg {
+      assert_eq!(reading.device_index(), 0);
+      assert_eq!(reading.sensor_index(), 0);
+      assert_eq!(reading.sensor_type(), message::SensorType::Position);
+      assert_eq!(reading.data(), &vec![42]);
+      break;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_linear_motion_limits() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "tcode-v03": {
+        "btle": {
+          "names": [
+            "Motion Limit Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Motion Limit Test Device",
+          "linear-motion-limits": {
+            "max-velocity": 2.0
+          },
+          "messages": {
+            "LinearCmd": [
+              {
+                "StepRange": [0, 100],
+                "ActuatorType": "Position"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Motion Limit Test", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      // The first move is assumed to cross the whole range, so takes at least 500ms at 2 ranges per
+      // second. The second is half the range, and the third is slow enough already.
+      for (position, duration, expected) in [
+        (0.5, 100, "L049I500\n"),
+        (0.0, 100, "L000I250\n"),
+        (0.1, 1000, "L009I1000\n"),
+      ] {
+        server
+          .parse_message(
+            message::LinearCmd::new(
+              da.device_index(),
+              vec![message::VectorSubcommand::new(0, duration, position)],
+            )
+            .into(),
+          )
+          .await
+          .expect("Test, assuming infallible.");
+        let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+          .await
+          .expect("Test, assuming infallible.")
+          .expect("Test, assuming infallible.");
+        assert_eq!(
+          cmd,
+          HardwareCommand::Write(HardwareWriteCmd::new(
+            Endpoint::Tx,
+            expected.as_bytes().to_vec(),
+            false
+          ))
+        );
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_positional_rotation() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "tcode-v03": {
+        "btle": {
+          "names": [
+            "Twist Test"
+          ],
+          "services": {
+            "0000ff00-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000ff01-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Twist Test Device",
+          "messages": {
+            "LinearCmd": [
+              {
+                "StepRange": [0, 100],
+                "ActuatorType": "Position"
+              }
+            ],
+            "RotateCmd": [
+              {
+                "StepRange": [0, 100],
+                "ActuatorType": "Rotate",
+                "FeatureDescriptor": "Twist",
+                "RotationMode": "Positional"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Twist Test", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let rotate_attrs = da
+        .device_messages()
+        .rotate_cmd()
+        .as_ref()
+        .expect("Test, assuming infallible.");
+      assert!(rotate_attrs[0].is_positional_rotator());
+
+      server
+        .parse_message(
+          message::RotatePositionCmd::new(
+            da.device_index(),
+            vec![message::VectorSubcommand::new(0, 200, 0.5)],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+        .await
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        cmd,
+        HardwareCommand::Write(HardwareWriteCmd::new(
+          Endpoint::Tx,
+          b"R049I200\n".to_vec(),
+          false
+        ))
+      );
+
+      // Speeds make no sense for a servo, and stopping leaves it where it is.
+      assert!(server
+        .parse_message(
+          message::RotateCmd::new(
+            da.device_index(),
+            vec![message::RotationSubcommand::new(0, 0.5, true)],
+          )
+          .into(),
+        )
+        .await
+        .is_err());
+      server
+        .parse_message(message::StopDeviceCmd::new(da.device_index()).into())
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(device.receiver.try_recv().is_err());
+
+      // Under an intensity ceiling, moves are slowed down instead.
+      server
+        .device_manager()
+        .set_intensity_ceiling(0.5)
+        .await
+        .expect("Test, assuming infallible.");
+      server
+        .parse_message(
+          message::RotatePositionCmd::new(
+            da.device_index(),
+            vec![message::VectorSubcommand::new(0, 200, 0.25)],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+        .await
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        cmd,
+        HardwareCommand::Write(HardwareWriteCmd::new(
+          Endpoint::Tx,
+          b"R024I400\n".to_vec(),
+          false
+        ))
+      );
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_temperature_limit() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "kiiroo-v2-vibrator": {
+        "btle": {
+          "names": [
+            "Vibrator Test"
+          ],
+          "services": {
+            "88f82580-0000-01e6-aace-0002a5d5c51b": {
+              "tx": "88f82581-0000-01e6-aace-0002a5d5c51b"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Vibrator Test Device",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 100],
+                "ActuatorType": "Temperature"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  // The protocol has no idea how to drive a heater, so the configuration can't give it one.
+  assert!(ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .finish()
+    .is_err());
+
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "esp32-diy": {
+        "btle": {
+          "names": [
+            "ESP32-DIY*"
+          ],
+          "services": {
+            "6e400001-b5a3-f393-e0a9-e50e24dcca9e": {
+              "tx": "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+              "rx": "6e400003-b5a3-f393-e0a9-e50e24dcca9e"
+            }
+          }
+        },
+        "defaults": {
+          "name": "ESP32 DIY Device",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 255],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        },
+        "configurations": [
+          {
+            "identifier": [
+              "heater-test"
+            ],
+            "name": "Heater Test Device",
+            "temperature-limit": 0.5,
+            "messages": {
+              "ScalarCmd": [
+                {
+                  "StepRange": [0, 255],
+                  "ActuatorType": "Vibrate"
+                },
+                {
+                  "StepRange": [0, 100],
+                  "ActuatorType": "Temperature"
+                }
+              ]
+            }
+          }
+        ]
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("ESP32-DIY Heater", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Answer the HELLO with a model that's in the configuration, so the device won't be asked for
+  // its capabilities.
+  while !matches!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Write(_))
+  ) {}
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::Rx, b"HELLO heater-test 1.0.0\n"),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      // Vibration goes through untouched, while the heater is held at the configured limit.
+      server
+        .parse_message(
+          message::ScalarCmd::new(
+            da.device_index(),
+            vec![
+              message::ScalarSubcommand::new(0, 1.0, message::ActuatorType::Vibrate),
+              message::ScalarSubcommand::new(1, 1.0, message::ActuatorType::Temperature),
+            ],
+          )
+          .into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      for expected in [&b"PWM 0 255\n"[..], &b"PWM 1 50\n"[..]] {
+        let cmd = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+          .await
+          .expect("Test, assuming infallible.")
+          .expect("Test, assuming infallible.");
+        assert_eq!(
+          cmd,
+          HardwareCommand::Write(HardwareWriteCmd::new(
+            Endpoint::Tx,
+            expected.to_vec(),
+            false
+          ))
+        );
+      }
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_max_command_rate() {
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "kiiroo-v2-vibrator": {
+        "btle": {
+          "names": [
+            "Rate Test"
+          ],
+          "services": {
+            "88f82580-0000-01e6-aace-0002a5d5c51b": {
+              "tx": "88f82581-0000-01e6-aace-0002a5d5c51b"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Rate Test Device",
+          "max-command-rate": 5,
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 100],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Rate Test", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let mut write_times = vec![];
+      for speed in [0.5, 1.0] {
+        server
+          .parse_message(
+            message::ScalarCmd::new(
+              da.device_index(),
+              vec![message::ScalarSubcommand::new(
+                0,
+                speed,
+                message::ActuatorType::Vibrate,
+              )],
+            )
+            .into(),
+          )
+          .await
+          .expect("Test, assuming infallible.");
+        tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+          .await
+          .expect("Test, assuming infallible.")
+          .expect("Test, assuming infallible.");
+        write_times.push(Instant::now());
+      }
+      // The hardware completes writes immediately, so only the configured rate of 5 per second
+      // spaces these out.
+      assert!(write_times[1] - write_times[0] >= Duration::from_millis(150));
+      return;
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_protocol_initialization_timeout() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("LVS-Test", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .protocol_initialization_timeout(Duration::from_millis(200))
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // The device never answers the DeviceType query, so identification would retry for seconds
+  // before falling back to the BLE name. The timeout cuts that short and releases the hardware,
+  // which closes the test device's command channel.
+  let released = tokio::time::timeout(Duration::from_secs(2), async {
+    while device.receiver.recv().await.is_some() {}
+  })
+  .await;
+  assert!(released.is_ok());
+  while let Some(Some(msg)) = recv.next().now_or_never() {
+    assert!(!matches!(msg, ButtplugServerMessage::DeviceAdded(_)));
+  }
+}
+
+/// Scans for two Lovense devices that never answer their DeviceType query, and returns how long it
+/// takes for both to time out and be released.
+async fn time_unresponsive_device_initialization(concurrency: usize) -> Duration {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut devices = [
+    builder.add_test_device(&TestDeviceIdentifier::new("LVS-Test", None)),
+    builder.add_test_device(&TestDeviceIdentifier::new("LVS-Test", None)),
+  ];
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .protocol_initialization_timeout(Duration::from_millis(300))
+    .protocol_initialization_retries(0, Duration::ZERO)
+    .device_initialization_concurrency(concurrency)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let start = Instant::now();
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  for device in devices.iter_mut() {
+    tokio::time::timeout(Duration::from_secs(2), async {
+      while device.receiver.recv().await.is_some() {}
+    })
+    .await
+    .expect("Test, assuming infallible.");
+  }
+  start.elapsed()
+}
+
+#[tokio::test]
+async fn test_server_device_parallel_initialization() {
+  assert!(time_unresponsive_device_initialization(2).await < Duration::from_millis(550));
+}
+
+#[tokio::test]
+async fn test_server_device_initialization_concurrency_limit() {
+  assert!(time_unresponsive_device_initialization(1).await >= Duration::from_millis(600));
+}
+
+#[tokio::test]
+async fn test_server_device_initialization_retry() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut identifier = TestDeviceIdentifier::new("Massage Demo", None);
+  identifier.set_connect_failures(2);
+  let _device = builder.add_test_device(&identifier);
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .protocol_initialization_retries(2, Duration::from_millis(10))
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Both failed connections are retried, and the third attempt connects.
+  tokio::time::timeout(Duration::from_secs(2), async {
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::DeviceAdded(_) = msg {
+        return;
+      }
+    }
+    panic!("Event stream ended before the device was added.");
+  })
+  .await
+  .expect("Test, assuming infallible.");
+}
+
+#[tokio::test]
+async fn test_server_unmatched_device_stream() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "Unsupported Toy",
+    Some("unsupported-address".to_owned()),
+  ));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let unmatched = server.device_manager().unmatched_device_stream();
+  pin_mut!(unmatched);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let report = tokio::time::timeout(Duration::from_secs(1), unmatched.next())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  assert_eq!(report.name(), "Unsupported Toy");
+  assert_eq!(report.address(), "unsupported-address");
+  assert_eq!(report.comm_manager(), "TestDeviceCommunicationManager");
+  if let ProtocolCommunicationSpecifier::BluetoothLE(btle) = report.specifier() {
+    assert!(btle.names().contains("Unsupported Toy"));
+  } else {
+    panic!("Test devices are bluetooth devices.");
+  }
+}
+
+#[tokio::test]
+async fn test_server_device_ble_battery_fallback() {
+  // The protocol has no battery sensor configured, but the device exposes the standard BLE Battery
+  // Service, so we should get a battery sensor anyways.
+  let device_json = r#"{
+    "version": {
+      "major": 2,
+      "minor": 25
+    },
+    "protocols": {
+      "kiiroo-v2-vibrator": {
+        "btle": {
+          "names": [
+            "Battery Test"
+          ],
+          "services": {
+            "88f82580-0000-01e6-aace-0002a5d5c51b": {
+              "tx": "88f82581-0000-01e6-aace-0002a5d5c51b"
+            },
+            "0000180f-0000-1000-8000-00805f9b34fb": {
+              "rxblebattery": "00002a19-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Battery Test Device",
+          "messages": {
+            "ScalarCmd": [
+              {
+                "StepRange": [0, 100],
+                "ActuatorType": "Vibrate"
+              }
+            ]
+          }
+        }
+      }
+    }
+  }"#;
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let device = builder.add_test_device(&TestDeviceIdentifier::new("Battery Test", None));
+  let server = ButtplugServerBuilder::default()
+    .device_configuration_json(Some(device_json.to_owned()))
+    .comm_manager(builder)
+    .finish()
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      let sensors = da
+        .device_messages()
+        .sensor_read_cmd()
+        .as_ref()
+        .expect("Test, assuming infallible.");
+      assert_eq!(sensors.len(), 1);
+      assert_eq!(*sensors[0].sensor_type(), message::SensorType::Battery);
+
+      device
+        .sender
+        .send(TestHardwareEvent::Reads(vec![
+          TestHardwareNotification::new(Endpoint::RxBLEBattery, &[42]),
+        ]))
+        .await
+        .expect("Test, assuming infallible.");
+      let reading = server
+        .parse_message(
+          message::SensorReadCmd::new(da.device_index(), 0, message::SensorType::Battery).into(),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+      if let ButtplugServerMessage::SensorReading(reading) = reading {
+        assert_eq!(reading.data(), &vec![42]);
+      } else {
+        panic!("Expected sensor reading, got {:?}", reading);
+      }
+      return;
+    }
+  }
 }
-*/
@@ -0,0 +1,70 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "test-harness")]
+mod test {
+  use buttplug::{
+    client::{ButtplugClientEvent, ScalarValueCommand},
+    core::message::Endpoint,
+    server::device::hardware::{HardwareCommand, HardwareWriteCmd},
+    util::test_harness::ButtplugTestHarnessBuilder,
+  };
+  use futures::StreamExt;
+  use std::time::Duration;
+
+  #[tokio::test]
+  async fn test_harness_device_command() {
+    let mut builder = ButtplugTestHarnessBuilder::default();
+    let mut vivi = builder.add_device("Massage Demo");
+    let harness = builder.finish().await.expect("Test, assuming infallible.");
+    assert!(harness.client().connected());
+    let devices = harness
+      .scan_for_devices(1)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(devices.len(), 1);
+    devices[0]
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    // The Vivi has two motors, which both get set.
+    for expected in [vec![0xF1, 64], vec![0xF2, 64]] {
+      assert_eq!(
+        vivi.next_command().await,
+        Some(HardwareCommand::Write(HardwareWriteCmd::new(
+          Endpoint::Tx,
+          expected,
+          false
+        )))
+      );
+    }
+    assert_eq!(vivi.try_next_command(), None);
+  }
+
+  #[tokio::test]
+  async fn test_harness_unknown_device_and_disconnect() {
+    let mut builder = ButtplugTestHarnessBuilder::default();
+    let vivi = builder.add_device("Massage Demo");
+    let _unknown = builder.add_device("Not A Real Device");
+    let harness = builder.finish().await.expect("Test, assuming infallible.");
+    let devices = harness
+      .scan_for_devices_with_timeout(2, Duration::from_millis(500))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(devices.len(), 1);
+    let events = harness.client().event_stream();
+    futures::pin_mut!(events);
+    vivi.disconnect();
+    while let Some(event) = events.next().await {
+      if let ButtplugClientEvent::DeviceRemoved(device) = event {
+        assert_eq!(device.index(), devices[0].index());
+        break;
+      }
+    }
+    assert!(harness.client().devices().is_empty());
+  }
+}
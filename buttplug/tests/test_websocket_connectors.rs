@@ -113,6 +113,58 @@ mod websocket_connector_tests {
       .await
       .expect("Test, assuming infallible.");
   }
+
+  #[cfg(feature = "websocket-tls")]
+  #[tokio::test]
+  async fn test_client_ws_client_server_ws_server_secure() {
+    use buttplug::util::certificate::{SelfSignedCertificate, DEFAULT_CERTIFICATE_VALIDITY};
+
+    let certificate = SelfSignedCertificate::generate(
+      &["localhost".to_owned(), "127.0.0.1".to_owned()],
+      DEFAULT_CERTIFICATE_VALIDITY,
+    )
+    .expect("Test, assuming infallible.");
+    let test_server = ButtplugTestServer::default();
+    let server = Arc::new(test_server);
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugWebsocketServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugWebsocketServerTransportBuilder::default()
+          .port(12351)
+          .tls_certificate(certificate)
+          .finish(),
+      );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+    let mut connected = false;
+    for _ in 0..10u8 {
+      let connector = ButtplugRemoteClientConnector::<
+        ButtplugWebsocketClientTransport,
+        ButtplugClientJSONSerializer,
+      >::new(ButtplugWebsocketClientTransport::new_secure_connector(
+        "wss://127.0.0.1:12351",
+        true,
+      ));
+
+      let client = ButtplugClient::new("Test Client");
+      if client.connect(connector).await.is_ok() {
+        connected = true;
+        break;
+      }
+      sleep(Duration::from_secs(1)).await;
+    }
+    assert!(connected);
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+  }
 }
 
 // TODO Test disconnection event from server side
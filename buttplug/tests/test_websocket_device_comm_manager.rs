@@ -11,18 +11,22 @@ mod util;
 mod test {
 
   use buttplug::{
-    client::ButtplugClient,
+    client::{ButtplugClient, ButtplugClientEvent, ScalarValueCommand},
     core::connector::ButtplugInProcessClientConnectorBuilder,
     server::device::hardware::communication::websocket_server::websocket_server_comm_manager::WebsocketServerDeviceCommunicationManagerBuilder,
     server::ButtplugServerBuilder,
   };
+  use futures::{SinkExt, StreamExt};
+  use std::time::Duration;
+  use tokio::{net::UdpSocket, time::timeout};
+  use tokio_tungstenite::{connect_async, tungstenite::Message};
 
-  async fn setup_test_client() -> ButtplugClient {
+  async fn setup_test_client(port: u16) -> ButtplugClient {
     let mut builder = ButtplugServerBuilder::default();
 
     builder.name("Websocket DCM Test Server").comm_manager(
       WebsocketServerDeviceCommunicationManagerBuilder::default()
-        .server_port(51283)
+        .server_port(port)
         .listen_on_all_interfaces(true),
     );
     let server = builder.finish().expect("Test, assuming infallible.");
@@ -40,7 +44,62 @@ mod test {
 
   #[tokio::test]
   async fn test_websocket_server_dcm_bringup() {
-    let client = setup_test_client().await;
+    let client = setup_test_client(51283).await;
     assert!(client.connected());
   }
+
+  #[tokio::test]
+  async fn test_websocket_server_dcm_udp_output() {
+    let client = setup_test_client(51284).await;
+    let mut events = client.event_stream();
+    let udp_socket = UdpSocket::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let (mut ws_stream, _) = connect_async("ws://127.0.0.1:51284")
+      .await
+      .expect("Test, assuming infallible.");
+    let info = format!(
+      r#"{{
+        "identifier": "buttplug-passthru",
+        "address": "udp-test-device",
+        "version": 1,
+        "messages": {{
+          "ScalarCmd": [{{ "FeatureDescriptor": "Motor", "StepCount": 20, "ActuatorType": "Vibrate" }}]
+        }},
+        "udp-port": {}
+      }}"#,
+      udp_socket
+        .local_addr()
+        .expect("Test, assuming infallible.")
+        .port()
+    );
+    ws_stream
+      .send(Message::Text(info))
+      .await
+      .expect("Test, assuming infallible.");
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    let device = loop {
+      if let Some(ButtplugClientEvent::DeviceAdded(device)) = events.next().await {
+        break device;
+      }
+    };
+
+    let mut packet = [0u8; 1024];
+    for sequence in 0u32..2 {
+      device
+        .vibrate(&ScalarValueCommand::ScalarValue(0.5 * sequence as f64))
+        .await
+        .expect("Test, assuming infallible.");
+      let len = timeout(Duration::from_secs(5), udp_socket.recv(&mut packet))
+        .await
+        .expect("Device should get a UDP packet")
+        .expect("Test, assuming infallible.");
+      assert_eq!(packet[..4], sequence.to_le_bytes());
+      let command = std::str::from_utf8(&packet[4..len]).expect("Passthru sends JSON");
+      assert!(command.contains("ScalarCmd"));
+    }
+  }
 }
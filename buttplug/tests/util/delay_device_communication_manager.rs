@@ -24,6 +24,10 @@ use tokio::sync::mpsc::Sender;
 pub struct DelayDeviceCommunicationManagerBuilder {}
 
 impl HardwareCommunicationManagerBuilder for DelayDeviceCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    "DelayDeviceCommunicationManager"
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
@@ -16,7 +16,7 @@ mod channel_transport;
 use buttplug::{
   client::ButtplugClient,
   core::connector::ButtplugInProcessClientConnectorBuilder,
-  server::{ButtplugServer, ButtplugServerBuilder},
+  server::{ButtplugConnectionScope, ButtplugServer, ButtplugServerBuilder},
 };
 pub use channel_transport::*;
 pub use test_device_manager::{
@@ -56,7 +56,9 @@ pub async fn test_client_with_device() -> (ButtplugClient, TestDeviceChannelHost
   let device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
 
   let mut server_builder = ButtplugServerBuilder::default();
-  server_builder.comm_manager(builder);
+  server_builder
+    .comm_manager(builder)
+    .connection_scope(ButtplugConnectionScope::Admin);
   let connector = ButtplugInProcessClientConnectorBuilder::default()
     .server(server_builder.finish().unwrap())
     .finish();
@@ -103,7 +105,10 @@ pub async fn test_server_with_device(
   if allow_raw_message {
     server_builder.allow_raw_messages();
   }
-  server_builder.comm_manager(builder);
+  server_builder
+    .comm_manager(builder)
+    .allow_server_management()
+    .connection_scope(ButtplugConnectionScope::Admin);
   let server = server_builder.finish().unwrap();
   (server, device)
 }
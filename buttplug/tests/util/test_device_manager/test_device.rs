@@ -10,6 +10,7 @@ use buttplug::{
   server::device::{
     configuration::ProtocolCommunicationSpecifier,
     hardware::{
+      DeviceInformation,
       Hardware,
       HardwareCommand,
       HardwareConnector,
@@ -43,6 +44,16 @@ pub struct TestHardwareNotification {
   data: Vec<u8>,
 }
 
+impl TestHardwareNotification {
+  #[allow(dead_code)]
+  pub fn new(endpoint: Endpoint, data: &[u8]) -> Self {
+    Self {
+      endpoint,
+      data: data.to_vec(),
+    }
+  }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TestHardwareEvent {
   // Values to be emitted from subscriptions
@@ -55,6 +66,7 @@ pub enum TestHardwareEvent {
 pub struct TestHardwareConnector {
   specifier: ProtocolCommunicationSpecifier,
   hardware: Option<TestDevice>,
+  connect_failures: u32,
 }
 
 impl TestHardwareConnector {
@@ -63,8 +75,15 @@ impl TestHardwareConnector {
     Self {
       specifier,
       hardware: Some(hardware),
+      connect_failures: 0,
     }
   }
+
+  /// Fail this many connection attempts before connecting.
+  #[allow(dead_code)]
+  pub fn set_connect_failures(&mut self, failures: u32) {
+    self.connect_failures = failures;
+  }
 }
 
 impl Debug for TestHardwareConnector {
@@ -82,9 +101,17 @@ impl HardwareConnector for TestHardwareConnector {
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
-    Ok(Box::new(TestHardwareSpecializer::new(
-      self.hardware.take().expect("Test"),
-    )))
+    if self.connect_failures > 0 {
+      self.connect_failures -= 1;
+      return Err(ButtplugDeviceError::DeviceConnectionError(
+        "Test device connection failure".to_owned(),
+      ));
+    }
+    // Test devices can't be rebuilt once their hardware has been used.
+    let hardware = self.hardware.take().ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError("Test device already connected".to_owned())
+    })?;
+    Ok(Box::new(TestHardwareSpecializer::new(hardware)))
   }
 }
 
@@ -119,12 +146,14 @@ impl HardwareSpecializer for TestHardwareSpecializer {
         }
       }
     }
-    let hardware = Hardware::new(
+    let device_information = device.device_information.clone();
+    let mut hardware = Hardware::new(
       &device.name(),
       &device.address(),
       &endpoints,
       Box::new(device),
     );
+    hardware.set_device_information(device_information);
     Ok(hardware)
   }
 }
@@ -162,6 +191,7 @@ pub struct TestDevice {
   event_sender: broadcast::Sender<HardwareEvent>,
   subscribed_endpoints: Arc<DashSet<Endpoint>>,
   read_data: Arc<Mutex<VecDeque<HardwareReading>>>,
+  device_information: DeviceInformation,
 }
 
 impl TestDevice {
@@ -215,9 +245,14 @@ impl TestDevice {
       event_sender,
       subscribed_endpoints,
       read_data,
+      device_information: DeviceInformation::default(),
     }
   }
 
+  pub fn set_device_information(&mut self, information: &DeviceInformation) {
+    self.device_information = information.clone();
+  }
+
   pub fn add_endpoint(&mut self, endpoint: &Endpoint) {
     self.endpoints.insert(*endpoint);
   }
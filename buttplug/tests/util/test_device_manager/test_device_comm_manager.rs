@@ -22,6 +22,7 @@ use buttplug::{
     HardwareCommunicationManagerBuilder,
     HardwareCommunicationManagerEvent,
   },
+  server::device::hardware::DeviceInformation,
 };
 use futures::future::{self, FutureExt};
 use serde::{Deserialize, Serialize};
@@ -52,6 +53,12 @@ pub struct TestDeviceIdentifier {
   name: String,
   #[serde(default = "generate_address")]
   address: String,
+  /// What the device reports through the BLE Device Information Service, if anything.
+  #[serde(skip)]
+  device_information: DeviceInformation,
+  /// How many connection attempts fail before the device connects.
+  #[serde(skip)]
+  connect_failures: u32,
 }
 
 impl TestDeviceIdentifier {
@@ -62,23 +69,56 @@ impl TestDeviceIdentifier {
     Self {
       name: name.to_owned(),
       address,
+      device_information: DeviceInformation::default(),
+      connect_failures: 0,
     }
   }
+
+  #[allow(dead_code)]
+  pub fn set_device_information(&mut self, information: DeviceInformation) -> &mut Self {
+    self.device_information = information;
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn set_connect_failures(&mut self, failures: u32) -> &mut Self {
+    self.connect_failures = failures;
+    self
+  }
 }
 
 pub struct TestDeviceCommunicationManagerBuilder {
   devices: Option<Vec<(TestDeviceIdentifier, TestDeviceChannelDevice)>>,
+  name: &'static str,
+  scan_until_stopped: bool,
 }
 
 impl Default for TestDeviceCommunicationManagerBuilder {
   fn default() -> Self {
     Self {
       devices: Some(vec![]),
+      name: "TestDeviceCommunicationManager",
+      scan_until_stopped: false,
     }
   }
 }
 
 impl TestDeviceCommunicationManagerBuilder {
+  /// Name the manager, so that more than one can be added to a server.
+  #[allow(dead_code)]
+  pub fn manager_name(&mut self, name: &'static str) -> &mut Self {
+    self.name = name;
+    self
+  }
+
+  /// Keep scanning after all devices have been emitted, until scanning is stopped, like a real
+  /// radio would.
+  #[allow(dead_code)]
+  pub fn scan_until_stopped(&mut self) -> &mut Self {
+    self.scan_until_stopped = true;
+    self
+  }
+
   pub fn add_test_device(&mut self, device: &TestDeviceIdentifier) -> TestDeviceChannelHost {
     let (host_channel, device_channel) = new_device_channel();
     self
@@ -91,17 +131,24 @@ impl TestDeviceCommunicationManagerBuilder {
 }
 
 impl HardwareCommunicationManagerBuilder for TestDeviceCommunicationManagerBuilder {
+  fn name(&self) -> &'static str {
+    self.name
+  }
+
   fn finish(
     &mut self,
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
-    Box::new(TestDeviceCommunicationManager::new(
+    let mut manager = TestDeviceCommunicationManager::new(
+      self.name,
       sender,
       self
         .devices
         .take()
         .expect("Devices vec does not exist, is this running twice?"),
-    ))
+    );
+    manager.scan_until_stopped = self.scan_until_stopped;
+    Box::new(manager)
   }
 }
 
@@ -113,32 +160,40 @@ fn new_uninitialized_ble_test_device(
   let specifier = ProtocolCommunicationSpecifier::BluetoothLE(
     BluetoothLESpecifier::new_from_device(&identifier.name, &HashMap::new(), &[]),
   );
-  let hardware = TestDevice::new(&identifier.name, &address, device_channel);
-  TestHardwareConnector::new(specifier, hardware)
+  let mut hardware = TestDevice::new(&identifier.name, &address, device_channel);
+  hardware.set_device_information(&identifier.device_information);
+  let mut connector = TestHardwareConnector::new(specifier, hardware);
+  connector.set_connect_failures(identifier.connect_failures);
+  connector
 }
 
 pub struct TestDeviceCommunicationManager {
+  name: &'static str,
   device_sender: Sender<HardwareCommunicationManagerEvent>,
   devices: Vec<(TestDeviceIdentifier, TestDeviceChannelDevice)>,
   is_scanning: Arc<AtomicBool>,
+  scan_until_stopped: bool,
 }
 
 impl TestDeviceCommunicationManager {
   pub fn new(
+    name: &'static str,
     device_sender: Sender<HardwareCommunicationManagerEvent>,
     devices: Vec<(TestDeviceIdentifier, TestDeviceChannelDevice)>,
   ) -> Self {
     Self {
+      name,
       device_sender,
       devices,
       is_scanning: Arc::new(AtomicBool::new(false)),
+      scan_until_stopped: false,
     }
   }
 }
 
 impl HardwareCommunicationManager for TestDeviceCommunicationManager {
   fn name(&self) -> &'static str {
-    "TestDeviceCommunicationManager"
+    self.name
   }
 
   fn start_scanning(&mut self) -> ButtplugResultFuture {
@@ -159,6 +214,7 @@ impl HardwareCommunicationManager for TestDeviceCommunicationManager {
     }
     let device_sender = self.device_sender.clone();
     let is_scanning = self.is_scanning.clone();
+    let scan_until_stopped = self.scan_until_stopped;
     async move {
       is_scanning.store(true, Ordering::SeqCst);
       for event in events {
@@ -166,6 +222,9 @@ impl HardwareCommunicationManager for TestDeviceCommunicationManager {
           error!("Device channel no longer open.");
         }
       }
+      if scan_until_stopped {
+        return Ok(());
+      }
       // TODO Should should use
       is_scanning.store(false, Ordering::SeqCst);
       if device_sender
@@ -181,7 +240,22 @@ impl HardwareCommunicationManager for TestDeviceCommunicationManager {
   }
 
   fn stop_scanning(&mut self) -> ButtplugResultFuture {
-    future::ready(Ok(())).boxed()
+    // Scans that don't wait to be stopped finish on their own.
+    if !self.scan_until_stopped || !self.is_scanning.swap(false, Ordering::SeqCst) {
+      return future::ready(Ok(())).boxed();
+    }
+    let device_sender = self.device_sender.clone();
+    async move {
+      if device_sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
   }
 
   // Assume tests can scan for now, this would be a good place to instrument for device manager
@@ -264,9 +264,20 @@ fn impl_from_specific_buttplug_message_derive_macro(ast: &syn::DeriveInput) -> T
   let name = &ast.ident;
   if let syn::Data::Enum(ref e) = ast.data {
     let idents: Vec<_> = e.variants.iter().map(|x| x.ident.clone()).collect();
+    // Unlike try_from, where we expect all of our field identifiers to match, we may have different
+    // identifiers and field types when implementing from_specific. Therefore we need to parallel
+    // iterate our field identifiers and the identifier of the first member. This means we're locked
+    // to an enum style of field name([unnamed type]), but we're the only ones who use this macro,
+    // and on structs that almost never change, so hopefully leaving this comment will be enough.
+    let mut fields: Vec<_> = vec![];
+    for var in e.variants.iter() {
+      for field in var.fields.iter() {
+        fields.push(field.ty.clone());
+      }
+    }
     let gen = quote! {
-        #(impl From<#idents> for #name {
-            fn from(msg: #idents) -> #name {
+        #(impl From<#fields> for #name {
+            fn from(msg: #fields) -> #name {
                 #name::#idents(msg)
             }
         })*
@@ -312,3 +323,190 @@ fn impl_buttplug_server_message_type_macro(ast: &syn::DeriveInput) -> TokenStrea
   };
   gen.into()
 }
+
+#[proc_macro_derive(ButtplugJsonSchema)]
+pub fn buttplug_json_schema_derive(input: TokenStream) -> TokenStream {
+  // Construct a representation of Rust code as a syntax tree
+  // that we can manipulate
+  let ast = syn::parse(input).expect("Failure will cause compile failure.");
+
+  impl_buttplug_json_schema_macro(&ast)
+}
+
+// The parts of serde's attributes that change what a field or variant looks like on the wire.
+#[derive(Default)]
+struct SerdeAttributes {
+  rename: Option<String>,
+  skip_serializing: bool,
+  optional: bool,
+}
+
+fn serde_attributes(attrs: &[syn::Attribute]) -> SerdeAttributes {
+  let mut result = SerdeAttributes::default();
+  for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+    attr
+      .parse_nested_meta(|meta| {
+        if meta.path.is_ident("rename") {
+          let name: syn::LitStr = meta.value()?.parse()?;
+          result.rename = Some(name.value());
+          return Ok(());
+        }
+        if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+          result.skip_serializing = true;
+        } else if meta.path.is_ident("default")
+          || meta.path.is_ident("skip_serializing_if")
+          || meta.path.is_ident("skip_deserializing")
+        {
+          result.optional = true;
+        }
+        // Skip over the values of everything else.
+        if meta.input.peek(syn::Token![=]) {
+          meta.value()?.parse::<syn::Expr>()?;
+        } else if meta.input.peek(syn::token::Paren) {
+          meta.parse_nested_meta(|nested| {
+            if nested.input.peek(syn::Token![=]) {
+              nested.value()?.parse::<syn::Expr>()?;
+            }
+            Ok(())
+          })?;
+        }
+        Ok(())
+      })
+      .expect("Serde attributes are checked by serde, so they should parse.");
+  }
+  result
+}
+
+fn is_option(ty: &syn::Type) -> bool {
+  if let syn::Type::Path(path) = ty {
+    path
+      .path
+      .segments
+      .last()
+      .is_some_and(|segment| segment.ident == "Option")
+  } else {
+    false
+  }
+}
+
+fn impl_buttplug_json_schema_macro(ast: &syn::DeriveInput) -> TokenStream {
+  let name = &ast.ident;
+  let schema = match &ast.data {
+    syn::Data::Struct(s) => {
+      let mut names = vec![];
+      let mut types = vec![];
+      let mut required = vec![];
+      for field in s.fields.iter() {
+        let attrs = serde_attributes(&field.attrs);
+        if attrs.skip_serializing {
+          continue;
+        }
+        let field_name = attrs.rename.unwrap_or_else(|| {
+          field
+            .ident
+            .as_ref()
+            .expect("ButtplugJsonSchema only works on structs with named fields")
+            .to_string()
+        });
+        if !attrs.optional && !is_option(&field.ty) {
+          required.push(field_name.clone());
+        }
+        names.push(field_name);
+        types.push(field.ty.clone());
+      }
+      quote! {
+        let mut properties = ::serde_json::Map::new();
+        #( properties.insert(#names.to_owned(), <#types as ButtplugJsonSchema>::json_schema()); )*
+        ::serde_json::json!({
+          "type": "object",
+          "properties": properties,
+          "required": [#(#required),*],
+        })
+      }
+    }
+    syn::Data::Enum(e) => {
+      // Enums with a repr are serialized as their discriminant by serde_repr.
+      if ast.attrs.iter().any(|attr| attr.path().is_ident("repr")) {
+        let mut discriminant = 0u64;
+        let mut values = vec![];
+        for variant in e.variants.iter() {
+          if let Some((
+            _,
+            syn::Expr::Lit(syn::ExprLit {
+              lit: syn::Lit::Int(value),
+              ..
+            }),
+          )) = &variant.discriminant
+          {
+            discriminant = value
+              .base10_parse()
+              .expect("Discriminants are checked by the compiler.");
+          }
+          values.push(discriminant);
+          discriminant += 1;
+        }
+        quote! {
+          ::serde_json::json!({
+            "type": "integer",
+            "enum": [#(#values),*],
+          })
+        }
+      } else {
+        let mut unit_names = vec![];
+        let mut newtype_names = vec![];
+        let mut newtype_types = vec![];
+        for variant in e.variants.iter() {
+          let attrs = serde_attributes(&variant.attrs);
+          if attrs.skip_serializing {
+            continue;
+          }
+          let variant_name = attrs.rename.unwrap_or_else(|| variant.ident.to_string());
+          match &variant.fields {
+            syn::Fields::Unit => unit_names.push(variant_name),
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+              newtype_names.push(variant_name);
+              newtype_types.push(fields.unnamed[0].ty.clone());
+            }
+            _ => panic!("ButtplugJsonSchema only works on enums with unit or newtype variants"),
+          }
+        }
+        // Serde tags newtype variants with an object holding the variant name, and sends unit
+        // variants as just their name.
+        quote! {
+          let mut variants: Vec<::serde_json::Value> = vec![];
+          let unit_names: Vec<&str> = vec![#(#unit_names),*];
+          if !unit_names.is_empty() {
+            variants.push(::serde_json::json!({
+              "type": "string",
+              "enum": unit_names,
+            }));
+          }
+          #(
+            variants.push(::serde_json::json!({
+              "type": "object",
+              "properties": {
+                #newtype_names: <#newtype_types as ButtplugJsonSchema>::json_schema(),
+              },
+              "required": [#newtype_names],
+              "additionalProperties": false,
+            }));
+          )*
+          if variants.len() == 1 {
+            variants.remove(0)
+          } else {
+            ::serde_json::json!({ "anyOf": variants })
+          }
+        }
+      }
+    }
+    _ => panic!("Derivation only works on structs and enums"),
+  };
+  let gen = quote! {
+      impl ButtplugJsonSchema for #name {
+          fn json_schema() -> ::serde_json::Value {
+              #schema
+          }
+      }
+  };
+  gen.into()
+}